@@ -0,0 +1,188 @@
+use std::fs;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use serde_json::json;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::Endpoint;
+use tonic::{Request, Status};
+
+use offs::proto::filesystem::admin_client::AdminClient;
+use offs::proto::filesystem::{ClientInfo, ListClientsRequest, RevokeClientRequest};
+use offs::{now, AUTHORIZATION_HEADER_KEY, PROJ_AUTHORS, PROJ_NAME, PROJ_VERSION};
+
+/// Adds the admin token to the requests.
+struct Authorization(MetadataValue<Ascii>);
+
+impl Interceptor for Authorization {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert(AUTHORIZATION_HEADER_KEY, self.0.clone());
+        Ok(request)
+    }
+}
+
+/// Formats the time elapsed since the Unix time, such as `5m ago`.
+fn format_age(time: i64) -> String {
+    let seconds = (now().sec - time).max(0);
+
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn client_to_json(client: &ClientInfo) -> serde_json::Value {
+    json!({
+        "client_id": client.client_id,
+        "tenant": client.tenant,
+        "address": if client.address.is_empty() {
+            None
+        } else {
+            Some(&client.address)
+        },
+        "first_seen": client.first_seen,
+        "last_seen": client.last_seen,
+        "ops_per_minute": client.ops_per_minute,
+        "revoked": client.revoked,
+    })
+}
+
+fn print_clients(clients: &[ClientInfo], as_json: bool) {
+    if as_json {
+        let value: Vec<_> = clients.iter().map(client_to_json).collect();
+        println!("{}", json!(value));
+    } else if clients.is_empty() {
+        println!("No clients connected");
+    } else {
+        println!(
+            "{:<34} {:<16} {:<24} {:>10} {:>8}  STATUS",
+            "CLIENT", "TENANT", "ADDRESS", "LAST SEEN", "OPS/MIN"
+        );
+        for client in clients {
+            println!(
+                "{:<34} {:<16} {:<24} {:>10} {:>8}  {}",
+                client.client_id,
+                if client.tenant.is_empty() {
+                    "-"
+                } else {
+                    &client.tenant
+                },
+                if client.address.is_empty() {
+                    "-"
+                } else {
+                    &client.address
+                },
+                format_age(client.last_seen),
+                client.ops_per_minute,
+                if client.revoked { "revoked" } else { "active" }
+            );
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let matches = App::new(format!("{} admin", PROJ_NAME))
+        .version(PROJ_VERSION)
+        .author(PROJ_AUTHORS)
+        .about(format!("{} server administration", PROJ_NAME).as_ref())
+        .setting(AppSettings::VersionlessSubcommands)
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("server")
+                .global(true)
+                .short("s")
+                .long("server")
+                .value_name("ADDRESS")
+                .validator(offs::validators::check_address)
+                .default_value("127.0.0.1:10031")
+                .help("The address of the server to administer"),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .global(true)
+                .long("token-file")
+                .value_name("FILE")
+                .help("Authenticates with the admin token read from the given file"),
+        )
+        .subcommand(
+            SubCommand::with_name("clients")
+                .about("Lists the clients that have made requests within the last hour")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the clients as a JSON array"),
+                )
+                .subcommand(
+                    SubCommand::with_name("revoke")
+                        .about("Rejects the further requests of a client until the server restarts")
+                        .arg(
+                            Arg::with_name("tenant")
+                                .long("tenant")
+                                .value_name("NAME")
+                                .help("The tenant the client belongs to")
+                                .default_value(""),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints the revoked client as a JSON object"),
+                        )
+                        .arg(
+                            Arg::with_name("CLIENT_ID")
+                                .help("The ID of the client, as listed by `clients`")
+                                .required(true),
+                        ),
+                ),
+        )
+        .get_matches();
+
+    let token_file = matches
+        .value_of("token-file")
+        .expect("The admin token file has to be given with --token-file");
+    let token = fs::read_to_string(token_file).expect("Could not read the admin token");
+    let authorization: MetadataValue<Ascii> = format!("Bearer {}", token.trim())
+        .parse()
+        .expect("Invalid admin token");
+
+    let channel = Endpoint::from_shared(format!("http://{}", matches.value_of("server").unwrap()))
+        .unwrap()
+        .connect()
+        .await
+        .expect("Could not connect to the server");
+    let mut client = AdminClient::with_interceptor(channel, Authorization(authorization));
+
+    match matches.subcommand() {
+        ("clients", Some(sub_m)) => match sub_m.subcommand() {
+            ("revoke", Some(sub_m)) => {
+                let client = client
+                    .revoke_client(RevokeClientRequest {
+                        tenant: sub_m.value_of("tenant").unwrap().to_owned(),
+                        client_id: sub_m.value_of("CLIENT_ID").unwrap().to_owned(),
+                    })
+                    .await
+                    .expect("Could not revoke the client")
+                    .into_inner();
+                if sub_m.is_present("json") {
+                    println!("{}", client_to_json(&client));
+                } else {
+                    println!("Revoked client {}", client.client_id);
+                }
+            }
+            _ => {
+                let clients = client
+                    .list_clients(ListClientsRequest {})
+                    .await
+                    .expect("Could not list the clients")
+                    .into_inner()
+                    .clients;
+                print_clients(&clients, sub_m.is_present("json"));
+            }
+        },
+        _ => unreachable!(),
+    }
+}
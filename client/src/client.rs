@@ -1,67 +1,287 @@
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Duration;
 
+use fuser::MountOption;
+use sd_notify::NotifyState;
+use tokio::runtime::Runtime;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::RwLock;
+use tracing::{debug, error};
 
+use offs::errors::OperationResult;
 use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
 use offs::store::Store;
+use offs_client::events::SyncEvents;
+use offs_client::metrics::{run_metrics_server, Metrics};
+#[cfg(feature = "nfs")]
+use offs_client::remote_fs_client::NfsOffsFilesystem;
+use offs_client::remote_fs_client::{
+    FreeSpaceGuard, FuseOffsFilesystem, IgnorePatterns, OffsFilesystem, RemoteFsGrpcClient,
+};
+use offs_client::server_address::ServerAddress;
+use offs_client::transfers::Transfers;
 
-use crate::remote_fs_client::{FuseOffsFilesystem, OffsFilesystem};
-
+use super::config::Reloader;
+use super::control::InterfaceData;
+use super::control_socket;
+#[cfg(target_os = "linux")]
 use super::dbus_server;
+use super::hooks::hook_runner;
+#[cfg(all(target_os = "linux", feature = "notifications"))]
+use super::notifications::Notifier;
+
+pub struct MountConfig {
+    pub mount_point: PathBuf,
+    pub server: ServerAddress,
+    pub cache_path: PathBuf,
+    pub token: Option<String>,
+    /// Encrypts the cache with the given passphrase.
+    pub cache_passphrase: Option<String>,
+    pub maintenance: CacheMaintenance,
+    pub timeouts: Timeouts,
+    /// Keeps the files matching the patterns only in the cache.
+    pub ignore_patterns: IgnorePatterns,
+    /// Serves the filesystem over NFS on the given address instead of mounting it with FUSE.
+    #[cfg(feature = "nfs")]
+    pub nfs_address: Option<SocketAddr>,
+    pub fuse_options: FuseOptions,
+}
+
+/// Options of the FUSE mount, see mount.fuse(8).
+#[derive(Clone, Default)]
+pub struct FuseOptions {
+    /// Lets all the users access the files, not only the one who mounted the filesystem
+    pub allow_other: bool,
+    /// Lets root access the files, in addition to the user who mounted the filesystem
+    pub allow_root: bool,
+    /// Makes the kernel check the permissions by the file modes
+    pub default_permissions: bool,
+    /// Unmounts the filesystem once the client exits, even if it crashes
+    pub auto_unmount: bool,
+    /// Source shown in the mount table
+    pub fsname: Option<String>,
+    /// Type shown in the mount table as `fuse.SUBTYPE`
+    pub subtype: Option<String>,
+}
+
+impl FuseOptions {
+    fn mount_options(&self) -> Vec<MountOption> {
+        let mut options = Vec::new();
+        if self.allow_other {
+            options.push(MountOption::AllowOther);
+        }
+        if self.allow_root {
+            options.push(MountOption::AllowRoot);
+        }
+        if self.default_permissions {
+            options.push(MountOption::DefaultPermissions);
+        }
+        if self.auto_unmount {
+            options.push(MountOption::AutoUnmount);
+        }
+        options.extend(self.fsname.clone().map(MountOption::FSName));
+        options.extend(self.subtype.clone().map(MountOption::Subtype));
+
+        options
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Timeouts {
+    /// How long to wait for the server to respond to an RPC; `None` waits indefinitely.
+    pub rpc: Option<Duration>,
+    /// How long a filesystem request can take, including the RPCs made for it.
+    pub operation: Duration,
+}
+
+/// Periodic maintenance of the cache database.
+#[derive(Clone, Copy)]
+pub struct CacheMaintenance {
+    /// Seconds between the maintenance passes; 0 disables them.
+    pub interval: u64,
+    /// Frees the unused pages of the database on every pass.
+    pub incremental_vacuum: bool,
+}
+
+/// Opens the cache of the mount and prepares it, so that a cache that cannot be used is
+/// reported before the client detaches from the terminal. The store is closed again, as the
+/// SQLite connections must not be carried across `fork`.
+pub fn check_cache(mount: &MountConfig) -> OperationResult<()> {
+    let store = Store::new_client(&mount.cache_path, mount.cache_passphrase.as_deref())?;
+    if mount.maintenance.incremental_vacuum {
+        store.enable_incremental_vacuum()?;
+    }
+    store.get_replica_id()?;
 
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn run_client(
-    mount_point: &Path,
-    address: SocketAddr,
+    mounts: Vec<MountConfig>,
     offline_mode: bool,
-    store: Store<LocalTempIdGenerator>,
+    notify: bool,
+    dbus: bool,
+    metrics_address: Option<SocketAddr>,
+    reloader: Arc<Reloader>,
+    normalize_names: bool,
+    rt: Runtime,
 ) {
     let fs_mounted = Arc::new(AtomicBool::new(true));
-    let offline_mode_val = Arc::new(AtomicBool::new(offline_mode));
-    let should_flush_journal = Arc::new(AtomicBool::new(false));
+    // Blobs are content-addressed, so the cache can be safely shared between the mounts
+    let blob_cache = reloader.blob_cache();
+    let metrics = Arc::new(Metrics::new(blob_cache.clone()));
+    rt.spawn(reload_on_sighup(reloader.clone()));
 
-    {
-        let fs_mounted_cloned = fs_mounted.clone();
-        let mount_point_cloned = mount_point.to_owned();
-        let offline_mode_val_cloned = offline_mode_val.clone();
-        let should_flush_journal_cloned = should_flush_journal.clone();
+    if let Some(metrics_address) = metrics_address {
+        let metrics = metrics.clone();
 
-        thread::spawn(|| {
-            dbus_server::run_dbus_server(
-                fs_mounted_cloned,
-                mount_point_cloned,
-                offline_mode_val_cloned,
-                should_flush_journal_cloned,
-            )
-            .expect("Could not run D-Bus server");
+        rt.spawn(async move {
+            run_metrics_server(metrics, metrics_address)
+                .await
+                .expect("Could not run metrics server");
         });
     }
 
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()
-        .unwrap();
+    #[cfg(all(target_os = "linux", feature = "notifications"))]
+    let notifier = notify.then(Notifier::spawn);
+    #[cfg(not(all(target_os = "linux", feature = "notifications")))]
+    debug_assert!(!notify, "built without the notifications");
 
-    let fs = rt.block_on(async move {
-        OffsFilesystem::new(address, offline_mode_val, should_flush_journal, store)
+    let thread_lock = Arc::new((Mutex::new(false), Condvar::new()));
+    set_sigterm_handler(thread_lock.clone());
+
+    let mut interfaces = Vec::new();
+    let mut sessions = Vec::new();
+    for mount in mounts {
+        let store = Store::new_client(&mount.cache_path, mount.cache_passphrase.as_deref())
+            .expect("Could not open the cache");
+        if mount.maintenance.incremental_vacuum {
+            store
+                .enable_incremental_vacuum()
+                .expect("Could not enable the incremental vacuum of the cache");
+        }
+        if mount.maintenance.interval > 0 {
+            rt.spawn(run_maintenance(store.clone(), mount.maintenance));
+        }
+        let offline_mode_val = Arc::new(AtomicBool::new(offline_mode));
+        let should_flush_journal = Arc::new(AtomicBool::new(false));
+        let last_error = Arc::new(Mutex::new(String::new()));
+        let degraded = Arc::new(AtomicBool::new(false));
+        let transfers = Arc::new(Transfers::new());
+        let events = Arc::new(SyncEvents::new());
+        events.subscribe(hook_runner(
+            reloader.hooks(),
+            &mount.mount_point,
+            &mount.server,
+        ));
+        #[cfg(all(target_os = "linux", feature = "notifications"))]
+        if let Some(notifier) = &notifier {
+            events.subscribe(notifier.listener(&mount.mount_point));
+        }
+
+        let free_space_guard = FreeSpaceGuard::new(
+            mount.cache_path.clone(),
+            reloader.min_free_space(),
+            events.clone(),
+        );
+        let replica_id = store
+            .get_replica_id()
+            .expect("Could not read the replica ID");
+        let fs_store = StoreWrapper::with_blob_cache(store.clone(), blob_cache.clone());
+        let mut fs = rt.block_on(async {
+            let client = RemoteFsGrpcClient::new(
+                &mount.server,
+                mount.token.as_deref(),
+                replica_id,
+                mount.timeouts.rpc,
+            )
+            .await
+            .expect("Could not connect to the server");
+
+            OffsFilesystem::new(
+                client,
+                offline_mode_val.clone(),
+                should_flush_journal.clone(),
+                last_error.clone(),
+                fs_store,
+                metrics.clone(),
+                transfers.clone(),
+                events.clone(),
+            )
             .await
             .expect("Could not create Filesystem instance")
-    });
-    let fs = Arc::new(RwLock::new(fs));
+        });
+        fs.set_ignore_patterns(mount.ignore_patterns);
+        let fs = Arc::new(RwLock::new(fs));
 
-    let thread_lock = Arc::new((Mutex::new(false), Condvar::new()));
-    set_sigterm_handler(thread_lock.clone());
+        interfaces.push(InterfaceData {
+            mount_point: mount.mount_point.clone(),
+            server_address: mount.server.clone(),
+            offline_mode: offline_mode_val,
+            should_flush_journal,
+            last_error: last_error.clone(),
+            degraded: degraded.clone(),
+            store: StoreWrapper::new(store),
+            metrics: metrics.clone(),
+            transfers,
+            events,
+            fs: fs.clone(),
+            reloader: reloader.clone(),
+            runtime: rt.handle().clone(),
+        });
+
+        #[cfg(feature = "nfs")]
+        if let Some(nfs_address) = mount.nfs_address {
+            rt.spawn(run_nfs_server(
+                NfsOffsFilesystem::new(fs, free_space_guard, normalize_names),
+                nfs_address,
+            ));
+            continue;
+        }
 
-    let session = fuser::Session::new(
-        FuseOffsFilesystem::new(fs, rt),
-        &mount_point,
-        Default::default(),
-    )
-    .expect("Could not run FUSE session");
-    let _background_session = session.spawn().expect("Could not run FUSE session");
+        let mut fuse_fs = FuseOffsFilesystem::new(
+            fs,
+            rt.handle().clone(),
+            metrics.clone(),
+            degraded,
+            last_error,
+            free_space_guard,
+            normalize_names,
+        );
+        fuse_fs.set_operation_timeout(mount.timeouts.operation);
+        let mount_options = mount.fuse_options.mount_options();
+        let session = fuser::Session::new(fuse_fs, &mount.mount_point, &mount_options)
+            .expect("Could not run FUSE session");
+        sessions.push(session.spawn().expect("Could not run FUSE session"));
+    }
+
+    let socket_paths: Vec<_> = interfaces
+        .iter()
+        .map(|x| offs::control::socket_path(&x.mount_point))
+        .collect();
+    control_socket::run_control_socket_server(interfaces.clone())
+        .expect("Could not run control socket server");
+
+    #[cfg(target_os = "linux")]
+    if dbus {
+        let fs_mounted_cloned = fs_mounted.clone();
+
+        thread::spawn(|| {
+            dbus_server::run_dbus_server(fs_mounted_cloned, interfaces)
+                .expect("Could not run D-Bus server");
+        });
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = dbus;
+
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
 
     let (lock, cvar) = &*thread_lock;
     let mut interrupted = lock.lock().unwrap();
@@ -69,7 +289,71 @@ pub fn run_client(
         interrupted = cvar.wait(interrupted).unwrap();
     }
 
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+
+    // Unmount everything while the runtime is still alive, as the filesystems need it to close
+    // their open files
+    drop(sessions);
     fs_mounted.store(false, Ordering::Relaxed);
+    for path in socket_paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(feature = "nfs")]
+async fn run_nfs_server(fs: NfsOffsFilesystem, address: SocketAddr) {
+    use nfsserve::tcp::{NFSTcp, NFSTcpListener};
+
+    let listener = NFSTcpListener::bind(&address.to_string(), fs)
+        .await
+        .expect("Could not run NFS server");
+    listener
+        .handle_forever()
+        .await
+        .expect("Could not run NFS server");
+}
+
+/// Checkpoints the WAL of the cache periodically, freeing the unused pages of the database as
+/// well if configured to.
+async fn run_maintenance(store: Store<LocalTempIdGenerator>, maintenance: CacheMaintenance) {
+    let mut interval = tokio::time::interval(Duration::from_secs(maintenance.interval));
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let store = store.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            store.run_maintenance(maintenance.incremental_vacuum)
+        })
+        .await
+        .unwrap();
+        match result {
+            Ok(result) => debug!(
+                "Cache maintenance: checkpointed: {}, freed pages: {}",
+                result.checkpointed, result.freed_pages
+            ),
+            Err(e) => error!("Cache maintenance failed: {}", e),
+        }
+    }
+}
+
+/// Re-reads the configuration file on SIGHUP.
+async fn reload_on_sighup(reloader: Arc<Reloader>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Could not handle SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        if let Err(e) = reloader.reload() {
+            error!("Could not reload the configuration: {}", e);
+        }
+    }
 }
 
 fn set_sigterm_handler(pair2: Arc<(Mutex<bool>, Condvar)>) {
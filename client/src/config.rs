@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use serde::Deserialize;
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+
+use offs::store::blob_cache::BlobCache;
+
+use crate::hooks::Hooks;
+
+/// Client configuration, read from the TOML file given with `--config`. The settings override
+/// the command line options and are re-read on SIGHUP or `offs-clientctl reload`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// Log level ("off", "error", "warn", "info", "debug" or "trace"), instead of the one set
+    /// with `-v` and `-q`
+    pub log_level: Option<String>,
+    /// Size of the in-memory blob cache in MiB
+    pub blob_cache_size: Option<usize>,
+    /// Free space on the cache device below which the writes are refused, in MiB
+    pub min_free_space: Option<u64>,
+    pub hooks: Hooks,
+}
+
+impl ClientConfig {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&contents)?;
+
+        if let Some(log_level) = &config.log_level {
+            log_level
+                .parse::<LevelFilter>()
+                .map_err(|_| format!("Invalid log level: {:?}", log_level))?;
+        }
+
+        Ok(config)
+    }
+}
+
+/// The settings that can be changed without remounting.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub log_level: LevelFilter,
+    /// Size of the in-memory blob cache in bytes
+    pub blob_cache_size: usize,
+    /// In bytes
+    pub min_free_space: u64,
+}
+
+impl Settings {
+    /// Returns the settings with the ones given in the configuration file replacing these.
+    fn with_config(self, config: &ClientConfig) -> Self {
+        Self {
+            log_level: config
+                .log_level
+                .as_ref()
+                .map_or(self.log_level, |x| x.parse().unwrap()),
+            blob_cache_size: config
+                .blob_cache_size
+                .map_or(self.blob_cache_size, |x| x * 1024 * 1024),
+            min_free_space: config
+                .min_free_space
+                .map_or(self.min_free_space, |x| x * 1024 * 1024),
+        }
+    }
+}
+
+/// Applies the configuration file to the running client.
+pub struct Reloader {
+    config_path: Option<PathBuf>,
+    /// The settings given on the command line, used for whatever the file does not set.
+    defaults: Settings,
+    blob_cache: Arc<BlobCache>,
+    min_free_space: Arc<AtomicU64>,
+    hooks: Arc<RwLock<Hooks>>,
+}
+
+impl Reloader {
+    /// Applies the configuration read from `config_path` on start.
+    pub fn new(
+        config_path: Option<PathBuf>,
+        config: &ClientConfig,
+        defaults: Settings,
+    ) -> Result<Self, Box<dyn Error>> {
+        let settings = defaults.with_config(config);
+        offs::telemetry::set_log_level(settings.log_level)?;
+
+        Ok(Self {
+            config_path,
+            defaults,
+            blob_cache: Arc::new(BlobCache::new(settings.blob_cache_size)),
+            min_free_space: Arc::new(AtomicU64::new(settings.min_free_space)),
+            hooks: Arc::new(RwLock::new(config.hooks.clone())),
+        })
+    }
+
+    pub fn blob_cache(&self) -> Arc<BlobCache> {
+        self.blob_cache.clone()
+    }
+
+    pub fn min_free_space(&self) -> Arc<AtomicU64> {
+        self.min_free_space.clone()
+    }
+
+    pub fn hooks(&self) -> Arc<RwLock<Hooks>> {
+        self.hooks.clone()
+    }
+
+    /// Re-reads the configuration file, leaving the current settings in place if it is invalid.
+    pub fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let path = self
+            .config_path
+            .as_deref()
+            .ok_or("The client has been started without a configuration file")?;
+        info!("Reloading the configuration from {}", path.display());
+        let config = ClientConfig::load(path)?;
+        let settings = self.defaults.with_config(&config);
+
+        offs::telemetry::set_log_level(settings.log_level)?;
+        self.blob_cache.set_capacity(settings.blob_cache_size);
+        self.min_free_space
+            .store(settings.min_free_space, Ordering::Relaxed);
+        *self.hooks.write().unwrap() = config.hooks;
+        info!("Configuration reloaded");
+
+        Ok(())
+    }
+}
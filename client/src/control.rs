@@ -0,0 +1,182 @@
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use prost::Message as _;
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+
+use offs::errors::{JournalOperationStatus, OperationResult};
+use offs::modify_op::ModifyOperation;
+use offs::proto::filesystem as proto_types;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{Dehydration, DirUsage, SyncState};
+use offs_client::events::{SyncEvent, SyncEvents};
+use offs_client::metrics::Metrics;
+use offs_client::remote_fs_client::{Export, OffsFilesystem, ShareLink, SidecarEntry};
+use offs_client::server_address::ServerAddress;
+use offs_client::transfers::Transfers;
+
+use crate::config::Reloader;
+
+/// The state of a mount exposed over the control channels (D-Bus and the control socket).
+#[derive(Clone)]
+pub struct InterfaceData {
+    pub mount_point: PathBuf,
+    pub server_address: ServerAddress,
+    pub offline_mode: Arc<AtomicBool>,
+    pub should_flush_journal: Arc<AtomicBool>,
+    pub last_error: Arc<Mutex<String>>,
+    pub degraded: Arc<AtomicBool>,
+    pub store: StoreWrapper<LocalTempIdGenerator>,
+    pub metrics: Arc<Metrics>,
+    pub transfers: Arc<Transfers>,
+    pub events: Arc<SyncEvents>,
+    pub fs: Arc<RwLock<OffsFilesystem>>,
+    /// Shared by all the mounts, as the configuration applies to the whole client
+    pub reloader: Arc<Reloader>,
+    pub runtime: Handle,
+}
+
+impl InterfaceData {
+    /// Switches the offline mode, flushing the journal when going back online.
+    pub fn set_offline_mode(&self, enabled: bool) {
+        let was_enabled = self.offline_mode.swap(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.should_flush_journal.store(true, Ordering::Relaxed);
+        } else if !was_enabled {
+            self.events.emit(SyncEvent::GoneOffline);
+        }
+    }
+
+    /// Retrieves the entries of the whole subtree of the directory at the given path, relative
+    /// to the mount point, blocking until done. Returns the number of files retrieved.
+    pub fn prefetch(&self, path: &str) -> OperationResult<u64> {
+        let count = self
+            .runtime
+            .block_on(async { self.fs.write().await.prefetch(path).await })?;
+
+        Ok(count as u64)
+    }
+
+    /// Returns the total size and number of the files in the subtree of the directory at the
+    /// given path, relative to the mount point, as stored on the server.
+    pub fn dir_usage(&self, path: &str) -> OperationResult<DirUsage> {
+        self.runtime
+            .block_on(async { self.fs.write().await.dir_usage(path).await })
+    }
+
+    /// Creates a link giving read-only access to the file or directory at the given path,
+    /// relative to the mount point, for `expires_in` seconds.
+    pub fn create_share_link(&self, path: &str, expires_in: u64) -> OperationResult<ShareLink> {
+        self.runtime.block_on(async {
+            self.fs
+                .write()
+                .await
+                .create_share_link(path, expires_in)
+                .await
+        })
+    }
+
+    /// Removes the cached content of the file or directory tree at the given path, relative to
+    /// the mount point.
+    pub fn dehydrate(&self, path: &str) -> OperationResult<Dehydration> {
+        self.runtime
+            .block_on(async { self.fs.write().await.dehydrate(path).await })
+    }
+
+    /// Copies the file or directory tree at the given path, relative to the mount point, to
+    /// the absolute path `target` outside of the mount, blocking until done.
+    pub fn export(&self, path: &str, target: &str) -> OperationResult<Export> {
+        self.runtime
+            .block_on(async { self.fs.write().await.export(path, Path::new(target)).await })
+    }
+
+    /// Lists the data attached to the file at the given path, relative to the mount point.
+    pub fn list_sidecars(&self, path: &str) -> OperationResult<Vec<SidecarEntry>> {
+        self.runtime
+            .block_on(async { self.fs.write().await.list_sidecars(path).await })
+    }
+
+    /// Returns the data attached to the file at the given path, relative to the mount point,
+    /// under the name.
+    pub fn get_sidecar(&self, path: &str, name: &str) -> OperationResult<Vec<u8>> {
+        let data = self
+            .runtime
+            .block_on(async { self.fs.write().await.get_sidecar(path, name).await })?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Attaches the data to the file at the given path, relative to the mount point, under the
+    /// name, or removes it if `data` is empty.
+    pub fn set_sidecar(&self, path: &str, name: &str, data: Vec<u8>) -> OperationResult<()> {
+        self.runtime.block_on(async {
+            self.fs
+                .write()
+                .await
+                .set_sidecar(path, name, data.into())
+                .await
+        })
+    }
+
+    /// Excludes the directory at the given path, relative to the mount point, from the
+    /// synchronization, removing its contents from the cache.
+    pub fn exclude_from_sync(&self, path: &str) -> OperationResult<()> {
+        self.runtime
+            .block_on(async { self.fs.write().await.exclude_from_sync(path).await })
+    }
+
+    /// Includes the directory at the given path, relative to the mount point, in the
+    /// synchronization again.
+    pub fn include_in_sync(&self, path: &str) -> OperationResult<()> {
+        self.runtime
+            .block_on(async { self.fs.write().await.include_in_sync(path).await })
+    }
+
+    /// Returns whether the file at the given path, relative to the mount point, has been
+    /// synchronized with the server.
+    pub fn sync_state(&self, path: &str) -> OperationResult<SyncState> {
+        self.runtime
+            .block_on(async { self.fs.read().await.sync_state(path) })
+    }
+
+    /// Returns the file, the operation name and the reason of each quarantined journal entry.
+    pub fn quarantined_operations(&self) -> OperationResult<Vec<(String, String, String)>> {
+        let entries = self.store.get_quarantined_journal()?;
+
+        Ok(entries
+            .into_iter()
+            .map(|x| {
+                let operation = proto_types::ModifyOperation::decode(x.operation.as_slice())
+                    .ok()
+                    .and_then(|op| ModifyOperation::try_from(op).ok())
+                    .map_or("invalid", |op| op.operation.name());
+                (x.file, operation.to_owned(), x.reason)
+            })
+            .collect())
+    }
+
+    /// Checks the journal against the server without applying it. Returns the path, the
+    /// operation name, the status (`ok`, `conflicted` or `rejected`) and the reason of the
+    /// rejection for each journal entry.
+    pub fn review_journal(&self) -> OperationResult<Vec<(String, String, String, String)>> {
+        let entries = self
+            .runtime
+            .block_on(async { self.fs.write().await.review_journal().await })?;
+
+        Ok(entries
+            .into_iter()
+            .map(|x| {
+                let (status, reason) = match x.status {
+                    JournalOperationStatus::Applied => ("ok", String::new()),
+                    JournalOperationStatus::Conflicted => ("conflicted", String::new()),
+                    JournalOperationStatus::Rejected(reason) => ("rejected", reason),
+                };
+                (x.path, x.operation.to_owned(), status.to_owned(), reason)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::Ordering;
+use std::thread;
+
+use serde_json::{json, Value};
+use tracing::{error, info};
+
+use offs::control::{socket_dir, socket_path, CALL, ERROR, GET, SET, VALUE};
+use offs::dbus::{
+    BLOBS_FETCHED, BLOB_BYTES_FETCHED, CACHE_HITS, CACHE_MISSES, CACHE_SIZE, CREATE_SHARE_LINK,
+    DEDUP_STATS, DEGRADED, DEHYDRATE, DIR_USAGE, EXCLUDED_FROM_SYNC, EXCLUDE_FROM_SYNC, EXPORT,
+    GET_SIDECAR, INCLUDE_IN_SYNC, LAST_ERROR, LIST_SIDECARS, LOCATE, MOUNT_POINT, OFFLINE_MODE,
+    PENDING_JOURNAL_OPS, PREFETCH, QUARANTINED_JOURNAL_OPS, RELOAD, REVIEW_JOURNAL, SERVER_ADDRESS,
+    SET_SIDECAR, SYNC_STATE, TRANSFERS,
+};
+
+use crate::control::InterfaceData;
+
+/// Serves the properties of every mount on its own control socket (see [`offs::control`]).
+pub fn run_control_socket_server(mounts: Vec<InterfaceData>) -> io::Result<()> {
+    let dir = socket_dir();
+    if !dir.exists() {
+        DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+    }
+
+    for data in mounts {
+        let path = socket_path(&data.mount_point);
+        // A socket left behind by a client that did not exit cleanly
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        info!(
+            "Control socket of {:?} listening at {:?}",
+            data.mount_point, path
+        );
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let data = data.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(&data, stream) {
+                                error!("Control socket connection failed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("Could not accept control socket connection: {}", e),
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(data: &InterfaceData, stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let response = match handle_request(data, &line?) {
+            Ok(value) => json!({ VALUE: value }),
+            Err(message) => json!({ ERROR: message }),
+        };
+        writeln!(writer, "{}", response)?;
+    }
+
+    Ok(())
+}
+
+fn handle_request(data: &InterfaceData, request: &str) -> Result<Value, String> {
+    let mut parts = request.splitn(3, ' ');
+    let command = parts.next().unwrap_or_default();
+    let name = parts.next().ok_or("Missing property or method name")?;
+
+    match command {
+        GET => get_property(data, name),
+        SET => set_property(data, name, parts.next().ok_or("Missing value")?),
+        CALL => call_method(data, name, parts.next().ok_or("Missing arguments")?),
+        _ => Err(format!("Unknown command: {}", command)),
+    }
+}
+
+fn get_property(data: &InterfaceData, property: &str) -> Result<Value, String> {
+    let value = match property {
+        MOUNT_POINT => json!(data.mount_point.to_string_lossy()),
+        SERVER_ADDRESS => json!(data.server_address.to_string()),
+        OFFLINE_MODE => json!(data.offline_mode.load(Ordering::Relaxed)),
+        CACHE_SIZE => json!(data.store.get_db_size().map_err(|e| e.to_string())?),
+        PENDING_JOURNAL_OPS => json!(data.store.get_journal_size().map_err(|e| e.to_string())?),
+        LAST_ERROR => json!(*data.last_error.lock().unwrap()),
+        DEGRADED => json!(data.degraded.load(Ordering::Relaxed)),
+        CACHE_HITS => json!(data.metrics.cache_hits()),
+        CACHE_MISSES => json!(data.metrics.cache_misses()),
+        BLOBS_FETCHED => json!(data.metrics.blobs_fetched()),
+        BLOB_BYTES_FETCHED => json!(data.metrics.blob_bytes_fetched()),
+        TRANSFERS => json!(data
+            .transfers
+            .list()
+            .into_iter()
+            .map(|x| (x.direction.to_string(), x.name, x.bytes_done, x.bytes_total))
+            .collect::<Vec<_>>()),
+        QUARANTINED_JOURNAL_OPS => {
+            json!(data.quarantined_operations().map_err(|e| e.to_string())?)
+        }
+        EXCLUDED_FROM_SYNC => json!(data
+            .store
+            .get_excluded_from_sync()
+            .map_err(|e| e.to_string())?),
+        _ => return Err(format!("Unknown property: {}", property)),
+    };
+
+    Ok(value)
+}
+
+fn set_property(data: &InterfaceData, property: &str, value: &str) -> Result<Value, String> {
+    let value: Value = serde_json::from_str(value).map_err(|e| e.to_string())?;
+
+    match property {
+        OFFLINE_MODE => {
+            let enabled = value.as_bool().ok_or("OfflineMode has to be a boolean")?;
+            data.set_offline_mode(enabled);
+
+            Ok(json!(enabled))
+        }
+        _ => Err(format!("Property {} cannot be set", property)),
+    }
+}
+
+fn call_method(data: &InterfaceData, method: &str, arguments: &str) -> Result<Value, String> {
+    let arguments: Vec<Value> = serde_json::from_str(arguments).map_err(|e| e.to_string())?;
+    let string_arg = |i: usize| {
+        arguments.get(i).and_then(Value::as_str).ok_or(format!(
+            "Argument {} of {} has to be a string",
+            i + 1,
+            method
+        ))
+    };
+    let u32_arg = |i: usize| {
+        arguments
+            .get(i)
+            .and_then(Value::as_u64)
+            .map(|x| x as u32)
+            .ok_or(format!(
+                "Argument {} of {} has to be a number",
+                i + 1,
+                method
+            ))
+    };
+
+    match method {
+        PREFETCH => Ok(json!(data
+            .prefetch(string_arg(0)?)
+            .map_err(|e| e.to_string())?)),
+        LOCATE => Ok(json!(data
+            .store
+            .locate(string_arg(0)?, u32_arg(1)?)
+            .map_err(|e| e.to_string())?)),
+        DIR_USAGE => {
+            let usage = data.dir_usage(string_arg(0)?).map_err(|e| e.to_string())?;
+            Ok(json!({
+                "size": usage.size,
+                "files": usage.files,
+                "directories": usage.directories,
+            }))
+        }
+        DEDUP_STATS => {
+            let stats = data
+                .store
+                .dedup_stats(u32_arg(0)?)
+                .map_err(|e| e.to_string())?;
+            let files: Vec<_> = stats
+                .files
+                .iter()
+                .map(|x| {
+                    json!({
+                        "path": x.path,
+                        "logical_size": x.logical_size,
+                        "physical_size": x.physical_size,
+                    })
+                })
+                .collect();
+            Ok(json!({
+                "logical_size": stats.logical_size,
+                "physical_size": stats.physical_size,
+                "blobs": stats.blobs,
+                "files": files,
+            }))
+        }
+        CREATE_SHARE_LINK => {
+            let expires_in = arguments
+                .get(1)
+                .and_then(Value::as_u64)
+                .ok_or(format!("Argument 2 of {} has to be a number", method))?;
+            let link = data
+                .create_share_link(string_arg(0)?, expires_in)
+                .map_err(|e| e.to_string())?;
+            Ok(json!({
+                "url": link.url,
+                "expires_at": link.expires_at,
+            }))
+        }
+        RELOAD => {
+            data.reloader.reload().map_err(|e| e.to_string())?;
+            Ok(Value::Null)
+        }
+        DEHYDRATE => {
+            let dehydration = data.dehydrate(string_arg(0)?).map_err(|e| e.to_string())?;
+            Ok(json!({
+                "files": dehydration.files,
+                "size": dehydration.size,
+            }))
+        }
+        EXPORT => {
+            let export = data
+                .export(string_arg(0)?, string_arg(1)?)
+                .map_err(|e| e.to_string())?;
+            Ok(json!({
+                "files": export.files,
+                "size": export.size,
+            }))
+        }
+        LIST_SIDECARS => {
+            let sidecars: Vec<_> = data
+                .list_sidecars(string_arg(0)?)
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|x| {
+                    json!({
+                        "name": x.name,
+                        "size": x.size,
+                        "up_to_date": x.up_to_date,
+                    })
+                })
+                .collect();
+            Ok(json!(sidecars))
+        }
+        GET_SIDECAR => Ok(json!(data
+            .get_sidecar(string_arg(0)?, string_arg(1)?)
+            .map_err(|e| e.to_string())?)),
+        SET_SIDECAR => {
+            // The data is given as an array of the byte values
+            let sidecar: Vec<u8> = arguments
+                .get(2)
+                .and_then(|x| serde_json::from_value(x.clone()).ok())
+                .ok_or(format!(
+                    "Argument 3 of {} has to be an array of bytes",
+                    method
+                ))?;
+            data.set_sidecar(string_arg(0)?, string_arg(1)?, sidecar)
+                .map_err(|e| e.to_string())?;
+            Ok(Value::Null)
+        }
+        SYNC_STATE => Ok(json!(data
+            .sync_state(string_arg(0)?)
+            .map_err(|e| e.to_string())?
+            .as_str())),
+        REVIEW_JOURNAL => Ok(json!(data.review_journal().map_err(|e| e.to_string())?)),
+        EXCLUDE_FROM_SYNC => {
+            data.exclude_from_sync(string_arg(0)?)
+                .map_err(|e| e.to_string())?;
+            Ok(Value::Null)
+        }
+        INCLUDE_IN_SYNC => {
+            data.include_in_sync(string_arg(0)?)
+                .map_err(|e| e.to_string())?;
+            Ok(Value::Null)
+        }
+        _ => Err(format!("Unknown method: {}", method)),
+    }
+}
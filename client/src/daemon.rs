@@ -0,0 +1,118 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// File holding the ID of the client process, so that service managers and scripts can signal
+/// it. On Unix, the file is locked for as long as the client runs, so that two clients cannot
+/// use the same one.
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+    /// ID of the process that has written the file and removes it on exit.
+    pid: Option<u32>,
+}
+
+impl PidFile {
+    /// Opens the file and takes the lock on it, failing if a running client already holds it.
+    /// The ID is only written with [`PidFile::write_pid`], once the client has detached.
+    pub fn lock(path: &Path) -> io::Result<Self> {
+        // Not truncated yet, as the file may belong to a client which is still running
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Self::try_lock(&file).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the PID file is held by another running client",
+            )
+        })?;
+
+        Ok(Self {
+            file,
+            path: path.canonicalize()?,
+            pid: None,
+        })
+    }
+
+    #[cfg(unix)]
+    fn try_lock(file: &File) -> nix::Result<()> {
+        use nix::fcntl::{flock, FlockArg};
+        use std::os::unix::io::AsRawFd;
+
+        flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock)
+    }
+
+    #[cfg(not(unix))]
+    fn try_lock(_file: &File) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        let pid = process::id();
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", pid)?;
+        self.file.sync_all()?;
+        self.pid = Some(pid);
+
+        Ok(())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // The processes forked after the file has been written share it, but the file is only
+        // removed once the one it refers to exits
+        if self.pid == Some(process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Opens the file the output of the detached client is written to, discarding it if none is
+/// given.
+fn open_log_file(log_file: Option<&Path>) -> io::Result<File> {
+    match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path),
+        None => OpenOptions::new().write(true).open(null_device()),
+    }
+}
+
+#[cfg(unix)]
+fn null_device() -> &'static str {
+    "/dev/null"
+}
+
+/// Detaches the client from the terminal it was started from: the process forks twice, with a
+/// new session in between, so that it never gets a controlling terminal again. Its output is
+/// written to the log file, if given. Returns `true` in the process that should go on running
+/// the client, and `false` in the ones that should exit.
+#[cfg(unix)]
+pub fn detach(log_file: Option<&Path>) -> io::Result<bool> {
+    use nix::unistd::{chdir, dup2, fork, setsid, ForkResult};
+    use std::os::unix::io::AsRawFd;
+
+    // Opened before changing the working directory, as the path may be relative to it
+    let output = open_log_file(log_file)?;
+    let input = File::open(null_device())?;
+
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        return Ok(false);
+    }
+    setsid()?;
+    if let ForkResult::Parent { .. } = unsafe { fork() }? {
+        return Ok(false);
+    }
+
+    // Not to keep the file system the client was started from busy
+    chdir("/")?;
+    dup2(input.as_raw_fd(), libc::STDIN_FILENO)?;
+    dup2(output.as_raw_fd(), libc::STDOUT_FILENO)?;
+    dup2(output.as_raw_fd(), libc::STDERR_FILENO)?;
+
+    Ok(true)
+}
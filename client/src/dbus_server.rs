@@ -1,27 +1,37 @@
-use std::path::PathBuf;
 use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
 use dbus::blocking::Connection;
-use dbus::channel::MatchingReceiver;
-use dbus::Error;
-use dbus_crossroads::{Crossroads, IfaceBuilder};
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::{Error, Message};
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
 
-use offs::dbus::{ID_PREFIX, IFACE, MOUNT_POINT, OFFLINE_MODE, PATH};
+use offs::dbus::{
+    BLOBS_FETCHED, BLOB_BYTES_FETCHED, CACHE_HITS, CACHE_MISSES, CACHE_SIZE, CREATE_SHARE_LINK,
+    DEDUP_STATS, DEGRADED, DEHYDRATE, DIR_USAGE, EXCLUDED_FROM_SYNC, EXCLUDE_FROM_SYNC, EXPORT,
+    GET_SIDECAR, ID_PREFIX, IFACE, INCLUDE_IN_SYNC, JOURNAL_QUARANTINED, LAST_ERROR, LIST_SIDECARS,
+    LOCATE, MOUNT_POINT, OFFLINE_MODE, PATH, PENDING_JOURNAL_OPS, PREFETCH,
+    QUARANTINED_JOURNAL_OPS, RELOAD, REVIEW_JOURNAL, SERVER_ADDRESS, SET_SIDECAR, SYNC_STATE,
+    SYNC_STATE_CHANGED, TRANSFERS,
+};
 
-struct InterfaceData {
-    mount_point: PathBuf,
-    offline_mode: Arc<AtomicBool>,
-    should_flush_journal: Arc<AtomicBool>,
-}
+use offs::store::SyncState;
+
+use crate::control::InterfaceData;
 
+/// Serves the properties of every mount at `PATH/<index>`, with an object manager at `PATH` so
+/// that the mounts can be enumerated. `JOURNAL_QUARANTINED` is emitted with the number of
+/// quarantined journal operations whenever it grows, and `SYNC_STATE_CHANGED` with the path
+/// (relative to the mount point) and the new state of every file whose `SYNC_STATE` changes,
+/// so that the file managers can keep their emblems up to date.
+///
+/// The methods are handled on the same thread as everything else, so the properties are not
+/// available while a `PREFETCH` is in progress.
 pub fn run_dbus_server(
     fs_mounted: Arc<AtomicBool>,
-    mount_point: PathBuf,
-    offline_mode: Arc<AtomicBool>,
-    should_flush_journal: Arc<AtomicBool>,
+    mounts: Vec<InterfaceData>,
 ) -> Result<(), Error> {
     let c = Connection::new_session()?;
     let name = format!("{}{}", ID_PREFIX, process::id());
@@ -33,24 +43,224 @@ pub fn run_dbus_server(
         b.property(MOUNT_POINT)
             .get(|_, data| Ok(data.mount_point.to_str().unwrap().to_owned()));
 
+        b.property(SERVER_ADDRESS)
+            .get(|_, data| Ok(data.server_address.to_string()));
+
         b.property(OFFLINE_MODE)
             .get(|_, data| Ok(data.offline_mode.load(Ordering::Relaxed)))
             .set(|_, data, enabled| {
-                data.offline_mode.store(enabled, Ordering::Relaxed);
-                if !enabled {
-                    data.should_flush_journal.store(true, Ordering::Relaxed);
-                }
+                data.set_offline_mode(enabled);
 
                 Ok(Some(enabled))
             });
+
+        b.property(CACHE_SIZE)
+            .get(|_, data| data.store.get_db_size().map_err(|e| MethodErr::failed(&e)));
+
+        b.property(PENDING_JOURNAL_OPS).get(|_, data| {
+            data.store
+                .get_journal_size()
+                .map_err(|e| MethodErr::failed(&e))
+        });
+
+        b.property(LAST_ERROR)
+            .get(|_, data| Ok(data.last_error.lock().unwrap().clone()));
+
+        b.property(DEGRADED)
+            .get(|_, data| Ok(data.degraded.load(Ordering::Relaxed)));
+
+        b.property(CACHE_HITS)
+            .get(|_, data| Ok(data.metrics.cache_hits()));
+        b.property(CACHE_MISSES)
+            .get(|_, data| Ok(data.metrics.cache_misses()));
+        b.property(BLOBS_FETCHED)
+            .get(|_, data| Ok(data.metrics.blobs_fetched()));
+        b.property(BLOB_BYTES_FETCHED)
+            .get(|_, data| Ok(data.metrics.blob_bytes_fetched()));
+
+        b.property(TRANSFERS).get(|_, data| {
+            Ok(data
+                .transfers
+                .list()
+                .into_iter()
+                .map(|x| (x.direction.to_string(), x.name, x.bytes_done, x.bytes_total))
+                .collect::<Vec<_>>())
+        });
+
+        b.property(QUARANTINED_JOURNAL_OPS).get(|_, data| {
+            data.quarantined_operations()
+                .map_err(|e| MethodErr::failed(&e))
+        });
+        b.signal::<(u64,), _>(JOURNAL_QUARANTINED, ("count",));
+        b.signal::<(String, String), _>(SYNC_STATE_CHANGED, ("path", "state"));
+
+        b.property(EXCLUDED_FROM_SYNC).get(|_, data| {
+            data.store
+                .get_excluded_from_sync()
+                .map_err(|e| MethodErr::failed(&e))
+        });
+
+        b.method(
+            PREFETCH,
+            ("path",),
+            ("count",),
+            |_, data, (path,): (String,)| {
+                data.prefetch(&path)
+                    .map(|count| (count,))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            LOCATE,
+            ("pattern", "limit"),
+            ("paths",),
+            |_, data, (pattern, limit): (String, u32)| {
+                data.store
+                    .locate(&pattern, limit)
+                    .map(|paths| (paths,))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            DIR_USAGE,
+            ("path",),
+            ("size", "files", "directories"),
+            |_, data, (path,): (String,)| {
+                data.dir_usage(&path)
+                    .map(|usage| (usage.size, usage.files, usage.directories))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            DEDUP_STATS,
+            ("limit",),
+            ("logical_size", "physical_size", "blobs", "files"),
+            |_, data, (limit,): (u32,)| {
+                let stats = data
+                    .store
+                    .dedup_stats(limit)
+                    .map_err(|e| MethodErr::failed(&e))?;
+                let files: Vec<_> = stats
+                    .files
+                    .into_iter()
+                    .map(|x| (x.path, x.logical_size, x.physical_size))
+                    .collect();
+
+                Ok((stats.logical_size, stats.physical_size, stats.blobs, files))
+            },
+        );
+        b.method(
+            CREATE_SHARE_LINK,
+            ("path", "expires_in"),
+            ("url", "expires_at"),
+            |_, data, (path, expires_in): (String, u64)| {
+                data.create_share_link(&path, expires_in)
+                    .map(|link| (link.url, link.expires_at))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(RELOAD, (), (), |_, data, ()| {
+            data.reloader.reload().map_err(|e| MethodErr::failed(&e))
+        });
+        b.method(
+            DEHYDRATE,
+            ("path",),
+            ("files", "size"),
+            |_, data, (path,): (String,)| {
+                data.dehydrate(&path)
+                    .map(|x| (x.files, x.size))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            EXPORT,
+            ("path", "target"),
+            ("files", "size"),
+            |_, data, (path, target): (String, String)| {
+                data.export(&path, &target)
+                    .map(|x| (x.files, x.size))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            LIST_SIDECARS,
+            ("path",),
+            ("sidecars",),
+            |_, data, (path,): (String,)| {
+                let sidecars: Vec<_> = data
+                    .list_sidecars(&path)
+                    .map_err(|e| MethodErr::failed(&e))?
+                    .into_iter()
+                    .map(|x| (x.name, x.size, x.up_to_date))
+                    .collect();
+
+                Ok((sidecars,))
+            },
+        );
+        b.method(
+            GET_SIDECAR,
+            ("path", "name"),
+            ("data",),
+            |_, data, (path, name): (String, String)| {
+                data.get_sidecar(&path, &name)
+                    .map(|x| (x,))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            SET_SIDECAR,
+            ("path", "name", "data"),
+            (),
+            |_, data, (path, name, sidecar): (String, String, Vec<u8>)| {
+                data.set_sidecar(&path, &name, sidecar)
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            SYNC_STATE,
+            ("path",),
+            ("state",),
+            |_, data, (path,): (String,)| {
+                data.sync_state(&path)
+                    .map(|x| (x.as_str().to_owned(),))
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(REVIEW_JOURNAL, (), ("operations",), |_, data, ()| {
+            data.review_journal()
+                .map(|operations| (operations,))
+                .map_err(|e| MethodErr::failed(&e))
+        });
+        b.method(
+            EXCLUDE_FROM_SYNC,
+            ("path",),
+            (),
+            |_, data, (path,): (String,)| {
+                data.exclude_from_sync(&path)
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            INCLUDE_IN_SYNC,
+            ("path",),
+            (),
+            |_, data, (path,): (String,)| {
+                data.include_in_sync(&path)
+                    .map_err(|e| MethodErr::failed(&e))
+            },
+        );
     });
 
-    let data = InterfaceData {
-        mount_point,
-        offline_mode,
-        should_flush_journal,
-    };
-    cr.insert(PATH, &[iface_token], data);
+    let object_manager_token = cr.object_manager();
+    cr.insert(PATH, &[object_manager_token], ());
+    let mut watches = Vec::new();
+    for (index, data) in mounts.into_iter().enumerate() {
+        let path = format!("{}/{}", PATH, index);
+        let count = data.store.get_quarantined_journal_size().unwrap_or(0);
+        let states = data.store.get_sync_states().unwrap_or_default();
+        watches.push((path.clone(), data.store.clone(), count, states));
+        cr.insert(path, &[iface_token], data);
+    }
 
     c.start_receive(
         dbus::message::MatchRule::new_method_call(),
@@ -62,6 +272,37 @@ pub fn run_dbus_server(
 
     while fs_mounted.load(Ordering::Relaxed) {
         c.process(Duration::from_millis(1000))?;
+
+        for (path, store, last_count, last_states) in &mut watches {
+            let count = store.get_quarantined_journal_size().unwrap_or(*last_count);
+            if count > *last_count {
+                let signal = Message::new_signal(path.as_str(), IFACE, JOURNAL_QUARANTINED)
+                    .map_err(|e| Error::new_failed(&e))?
+                    .append1(count);
+                let _ = c.send(signal);
+            }
+            *last_count = count;
+
+            let states = match store.get_sync_states() {
+                Ok(states) => states,
+                Err(_) => continue,
+            };
+            let changed = states
+                .iter()
+                .filter(|(file, state)| last_states.get(*file) != Some(state))
+                .map(|(file, state)| (file.as_str(), *state));
+            let synced = last_states
+                .keys()
+                .filter(|file| !states.contains_key(*file))
+                .map(|file| (file.as_str(), SyncState::Synced));
+            for (file, state) in changed.chain(synced) {
+                let signal = Message::new_signal(path.as_str(), IFACE, SYNC_STATE_CHANGED)
+                    .map_err(|e| Error::new_failed(&e))?
+                    .append2(file, state.as_str());
+                let _ = c.send(signal);
+            }
+            *last_states = states;
+        }
     }
 
     Ok(())
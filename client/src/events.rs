@@ -0,0 +1,80 @@
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub enum ConflictResolution {
+    /// The file conflicted with a change made elsewhere, so the local version is sent to the
+    /// server as a new file
+    Recreated,
+    /// The server rejected the change for the given reason; it stays in the journal quarantine
+    Quarantined(String),
+}
+
+impl fmt::Display for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConflictResolution::Recreated => f.write_str("recreated"),
+            ConflictResolution::Quarantined(_) => f.write_str("quarantined"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SyncEvent {
+    /// A journal operation could not be applied as it was
+    Conflict {
+        file_id: String,
+        /// Relative to the root of the filesystem, if the file is still cached
+        path: Option<String>,
+        resolution: ConflictResolution,
+    },
+    /// The journal has been sent to the server
+    SyncComplete { operations: u64 },
+    /// Sending the journal to the server has failed
+    SyncFailed { error: String },
+    /// The offline mode has been switched on
+    GoneOffline,
+    /// The device the cache is stored on is running out of space; the writes are refused once
+    /// the free space drops below the minimum
+    CacheNearlyFull {
+        free_space: u64,
+        min_free_space: u64,
+    },
+}
+
+impl SyncEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncEvent::Conflict { .. } => "conflict",
+            SyncEvent::SyncComplete { .. } => "sync-complete",
+            SyncEvent::SyncFailed { .. } => "sync-failed",
+            SyncEvent::GoneOffline => "gone-offline",
+            SyncEvent::CacheNearlyFull { .. } => "cache-nearly-full",
+        }
+    }
+}
+
+type Listener = Box<dyn Fn(&SyncEvent) + Send>;
+
+/// The events of a single filesystem, passed to every listener subscribed, e.g. to run the
+/// hook scripts. The listeners are called synchronously, so they should not block.
+#[derive(Default)]
+pub struct SyncEvents {
+    listeners: Mutex<Vec<Listener>>,
+}
+
+impl SyncEvents {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn subscribe(&self, listener: impl Fn(&SyncEvent) + Send + 'static) {
+        self.listeners.lock().unwrap().push(Box::new(listener));
+    }
+
+    pub fn emit(&self, event: SyncEvent) {
+        for listener in self.listeners.lock().unwrap().iter() {
+            listener(&event);
+        }
+    }
+}
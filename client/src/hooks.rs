@@ -0,0 +1,113 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use offs_client::events::{ConflictResolution, SyncEvent};
+use offs_client::server_address::ServerAddress;
+
+/// Shell commands run on the synchronization events, set in the `[hooks]` table of the
+/// configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub on_conflict: Option<String>,
+    pub on_sync_complete: Option<String>,
+    pub on_sync_failed: Option<String>,
+    pub on_gone_offline: Option<String>,
+    pub on_cache_nearly_full: Option<String>,
+}
+
+impl Hooks {
+    fn command(&self, event: &SyncEvent) -> Option<&str> {
+        match event {
+            SyncEvent::Conflict { .. } => self.on_conflict.as_deref(),
+            SyncEvent::SyncComplete { .. } => self.on_sync_complete.as_deref(),
+            SyncEvent::SyncFailed { .. } => self.on_sync_failed.as_deref(),
+            SyncEvent::GoneOffline => self.on_gone_offline.as_deref(),
+            SyncEvent::CacheNearlyFull { .. } => self.on_cache_nearly_full.as_deref(),
+        }
+    }
+}
+
+/// Returns an event listener running the hooks of a mount, which are looked up on every event,
+/// so that the changes made on reload apply immediately.
+pub fn hook_runner(
+    hooks: Arc<RwLock<Hooks>>,
+    mount_point: &Path,
+    server: &ServerAddress,
+) -> impl Fn(&SyncEvent) + Send + 'static {
+    let mount_point = mount_point.to_string_lossy().into_owned();
+    let server = server.to_string();
+
+    move |event| {
+        let command = match hooks.read().unwrap().command(event) {
+            Some(command) => command.to_owned(),
+            None => return,
+        };
+        run_hook(&command, event, &mount_point, &server);
+    }
+}
+
+/// Runs the command in the background, describing the event in the environment variables.
+fn run_hook(command: &str, event: &SyncEvent, mount_point: &str, server: &str) {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command])
+        .stdin(Stdio::null())
+        .env("OFFS_EVENT", event.name())
+        .env("OFFS_MOUNT_POINT", mount_point)
+        .env("OFFS_SERVER", server);
+    match event {
+        SyncEvent::Conflict {
+            file_id,
+            path,
+            resolution,
+        } => {
+            cmd.env("OFFS_FILE_ID", file_id)
+                .env("OFFS_RESOLUTION", resolution.to_string());
+            if let Some(path) = path {
+                cmd.env("OFFS_PATH", path);
+            }
+            if let ConflictResolution::Quarantined(reason) = resolution {
+                cmd.env("OFFS_REASON", reason);
+            }
+        }
+        SyncEvent::SyncComplete { operations } => {
+            cmd.env("OFFS_OPERATIONS", operations.to_string());
+        }
+        SyncEvent::SyncFailed { error } => {
+            cmd.env("OFFS_ERROR", error);
+        }
+        SyncEvent::GoneOffline => {}
+        SyncEvent::CacheNearlyFull {
+            free_space,
+            min_free_space,
+        } => {
+            cmd.env("OFFS_FREE_SPACE", free_space.to_string())
+                .env("OFFS_MIN_FREE_SPACE", min_free_space.to_string());
+        }
+    }
+
+    debug!("Running the {} hook: {}", event.name(), command);
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Could not run the {} hook: {}", event.name(), e);
+            return;
+        }
+    };
+
+    // Reap the process, so that it does not linger as a zombie
+    let name = event.name();
+    let command = command.to_owned();
+    thread::spawn(move || match child.wait() {
+        Ok(status) if !status.success() => {
+            error!("The {} hook {:?} failed: {}", name, command, status)
+        }
+        Ok(_) => {}
+        Err(e) => error!("Could not wait for the {} hook: {}", name, e),
+    });
+}
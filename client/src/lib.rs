@@ -0,0 +1,32 @@
+//! Client side of offs: the synchronization engine keeping a local cache of the files in sync
+//! with the server, along with the frontends exposing it.
+//!
+//! Applications can embed the engine directly with [`OffsClient`], without mounting the
+//! filesystem:
+//!
+//! ```no_run
+//! use offs_client::OffsClient;
+//!
+//! # async fn example() -> offs::errors::OperationResult<()> {
+//! let server = "localhost:10031".parse().unwrap();
+//! let client = OffsClient::open(&server, "cache.db".as_ref(), None).await?;
+//!
+//! client.create_file("/notes.txt", 0o644).await?;
+//! client.write("/notes.txt", 0, b"Hello").await?;
+//! let data = client.read("/notes.txt", 0, 5).await?;
+//! client.sync().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The FUSE frontend is only built with the `fuse` feature (enabled by default).
+
+pub use remote_fs_client::OffsClient;
+
+pub mod events;
+pub mod metrics;
+// Some of the operations of the filesystem are only used by the FUSE frontend
+#[cfg_attr(not(feature = "fuse"), allow(dead_code))]
+pub mod remote_fs_client;
+pub mod server_address;
+pub mod transfers;
@@ -1,28 +1,339 @@
 use std::net::ToSocketAddrs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, io};
 
-use clap::{App, Arg};
-use nix::unistd::{fork, ForkResult};
+use clap::{App, Arg, ErrorKind};
 
-use offs::store::Store;
-use stderrlog::Timestamp;
+use offs::validators::check_is_dir;
+use offs_client::remote_fs_client::IgnorePatterns;
+use offs_client::server_address::ServerAddress;
+
+use crate::client::{CacheMaintenance, FuseOptions, MountConfig, Timeouts};
+use crate::config::{ClientConfig, Reloader, Settings};
+use crate::daemon::PidFile;
 
 mod client;
+mod config;
+mod control;
+mod control_socket;
+mod daemon;
+#[cfg(target_os = "linux")]
 mod dbus_server;
-mod remote_fs_client;
+mod hooks;
+#[cfg(all(target_os = "linux", feature = "notifications"))]
+mod notifications;
+mod supervisor;
+
+const DEFAULT_CACHE_PATH: &str = "cache.db";
+
+fn io_error(path: &str, error: io::Error) -> clap::Error {
+    clap::Error::with_description(&format!("{}: {}", path, error), ErrorKind::Io)
+}
+
+fn read_token(path: &str) -> clap::Result<String> {
+    let token = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    let token = token.trim();
+
+    if token.is_empty() || !token.bytes().all(|x| x.is_ascii_graphic()) {
+        return Err(clap::Error::with_description(
+            &format!("{}: the file does not contain a valid token", path),
+            ErrorKind::ValueValidation,
+        ));
+    }
+
+    Ok(token.to_owned())
+}
+
+fn run_passphrase_command(command: &str) -> clap::Result<String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .map_err(|e| {
+            clap::Error::with_description(&format!("{}: {}", command, e), ErrorKind::Io)
+        })?;
+    if !output.status.success() {
+        return Err(clap::Error::with_description(
+            &format!("{}: {}", command, output.status),
+            ErrorKind::Io,
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Reads the passphrase the cache is encrypted with from a file, or from the output of a command
+/// (e.g. one reading it from the OS keyring).
+fn read_cache_passphrase(file: Option<&str>, command: Option<&str>) -> clap::Result<String> {
+    let passphrase = match (file, command) {
+        (Some(path), _) => fs::read_to_string(path).map_err(|e| io_error(path, e))?,
+        (None, Some(command)) => run_passphrase_command(command)?,
+        (None, None) => {
+            return Err(clap::Error::with_description(
+                "A passphrase file or command is required to encrypt the cache",
+                ErrorKind::MissingRequiredArgument,
+            ))
+        }
+    };
+    let passphrase = passphrase.trim_end_matches(&['\r', '\n'][..]);
+
+    if passphrase.is_empty() {
+        return Err(clap::Error::with_description(
+            "The cache passphrase cannot be empty",
+            ErrorKind::ValueValidation,
+        ));
+    }
+
+    Ok(passphrase.to_owned())
+}
+
+fn parse_mounts(
+    mount_args: &[&str],
+    cache_paths: &[&str],
+    token: Option<&str>,
+    cache_passphrase: Option<&str>,
+    maintenance: CacheMaintenance,
+    timeouts: Timeouts,
+    fuse_options: FuseOptions,
+) -> clap::Result<Vec<MountConfig>> {
+    let pairs = mount_args.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(clap::Error::with_description(
+            "Each mount has to be given as an ADDRESS MOUNT_POINT pair",
+            ErrorKind::WrongNumberOfValues,
+        ));
+    }
+
+    let mount_count = mount_args.len() / 2;
+    let cache_paths = match (cache_paths.len(), mount_count) {
+        (0, 1) => vec![DEFAULT_CACHE_PATH],
+        (n, m) if n == m => cache_paths.to_vec(),
+        _ => {
+            return Err(clap::Error::with_description(
+                "A cache path has to be given for each mount when mounting more than one \
+                 filesystem",
+                ErrorKind::WrongNumberOfValues,
+            ))
+        }
+    };
+
+    let mut mounts = Vec::new();
+    for (pair, cache_path) in pairs.zip(cache_paths) {
+        let (address_str, mount_point) = (pair[0], pair[1]);
+        let server = address_str
+            .parse::<ServerAddress>()
+            .and_then(|server| check_is_dir(mount_point.to_owned()).map(|_| server))
+            .map_err(|e| clap::Error::with_description(&e, ErrorKind::ValueValidation))?;
+
+        mounts.push(MountConfig {
+            // Absolute, as the control socket is looked up by the mount point
+            mount_point: fs::canonicalize(mount_point)
+                .unwrap_or_else(|_| PathBuf::from(mount_point)),
+            server,
+            // Absolute, as the working directory changes when the client detaches
+            cache_path: env::current_dir()
+                .map(|x| x.join(cache_path))
+                .unwrap_or_else(|_| PathBuf::from(cache_path)),
+            token: token.map(|x| x.to_owned()),
+            cache_passphrase: cache_passphrase.map(|x| x.to_owned()),
+            maintenance,
+            timeouts,
+            ignore_patterns: IgnorePatterns::default(),
+            #[cfg(feature = "nfs")]
+            nfs_address: None,
+            fuse_options: fuse_options.clone(),
+        });
+    }
+
+    Ok(mounts)
+}
+
+/// Reads the patterns of the names of the files kept only in the cache, one file per mount.
+fn read_ignore_files(mounts: &mut [MountConfig], paths: &[&str]) -> clap::Result<()> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+    if paths.len() != mounts.len() {
+        return Err(clap::Error::with_description(
+            "An ignore file has to be given for each mount when mounting more than one \
+             filesystem",
+            ErrorKind::WrongNumberOfValues,
+        ));
+    }
+
+    for (mount, path) in mounts.iter_mut().zip(paths) {
+        mount.ignore_patterns =
+            IgnorePatterns::load(Path::new(path)).map_err(|e| io_error(path, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "nfs")]
+fn serve_over_nfs(mounts: &mut [MountConfig], address: &str) -> clap::Result<()> {
+    match mounts {
+        [mount] => {
+            mount.nfs_address = address.to_socket_addrs().unwrap().next();
+            Ok(())
+        }
+        _ => Err(clap::Error::with_description(
+            "Only a single filesystem can be served over NFS",
+            ErrorKind::WrongNumberOfValues,
+        )),
+    }
+}
+
+#[cfg(not(feature = "nfs"))]
+fn serve_over_nfs(_mounts: &mut [MountConfig], _address: &str) -> clap::Result<()> {
+    Err(clap::Error::with_description(
+        "The client has been built without the NFS support",
+        ErrorKind::InvalidValue,
+    ))
+}
+
+#[cfg(all(target_os = "linux", feature = "notifications"))]
+fn check_notifications_supported() -> clap::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "notifications")))]
+fn check_notifications_supported() -> clap::Result<()> {
+    Err(clap::Error::with_description(
+        "The client has been built without the desktop notifications support",
+        ErrorKind::InvalidValue,
+    ))
+}
 
 fn main() {
     let matches = App::new("offs client")
         .version("0.1")
         .author("Mateusz Maćkowski <m4tx@m4tx.pl>")
         .about("offs filesystem client module")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help(
+                    "Reads the settings that can be reloaded without remounting (such as the \
+                     log level) from a TOML file",
+                ),
+        )
         .arg(
             Arg::with_name("cache")
                 .short("c")
                 .long("cache")
                 .value_name("FILE")
-                .help("Sets a custom cache database path")
-                .default_value("cache.db"),
+                .help(
+                    "Sets a custom cache database path; has to be given once per mount when \
+                     mounting more than one filesystem",
+                )
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("ignore-file")
+                .long("ignore-file")
+                .value_name("FILE")
+                .help(
+                    "Keeps the files whose names match the patterns in the file (such as \
+                     `*.swp`) only in the cache, without sending them to the server; has to be \
+                     given once per mount when mounting more than one filesystem",
+                )
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("blob-cache-size")
+                .long("blob-cache-size")
+                .value_name("MIB")
+                .help("Sets the size of the in-memory blob cache in MiB")
+                .validator(offs::validators::check_number)
+                .default_value("64"),
+        )
+        .arg(
+            Arg::with_name("min-free-space")
+                .long("min-free-space")
+                .value_name("MIB")
+                .help(
+                    "Refuses the writes when there is less free space on the cache device, in \
+                     MiB",
+                )
+                .validator(offs::validators::check_number)
+                .default_value("64"),
+        )
+        .arg(
+            Arg::with_name("maintenance-interval")
+                .long("maintenance-interval")
+                .value_name("SECONDS")
+                .help("Checkpoints the WAL of the cache at the given interval; 0 disables it")
+                .validator(offs::validators::check_number)
+                .default_value("300"),
+        )
+        .arg(
+            Arg::with_name("incremental-vacuum")
+                .long("incremental-vacuum")
+                .help("Frees the unused pages of the cache database during the maintenance"),
+        )
+        .arg(
+            Arg::with_name("no-normalize-names")
+                .long("no-normalize-names")
+                .help("Passes the file names to the server as typed instead of converting them to NFC"),
+        )
+        .arg(
+            Arg::with_name("rpc-timeout")
+                .long("rpc-timeout")
+                .value_name("SECONDS")
+                .help(
+                    "Treats the server as unreachable if it does not respond to a request in \
+                     time; 0 waits indefinitely",
+                )
+                .validator(offs::validators::check_number)
+                .default_value("120"),
+        )
+        .arg(
+            Arg::with_name("operation-timeout")
+                .long("operation-timeout")
+                .value_name("SECONDS")
+                .help(
+                    "Fails the filesystem requests with ETIMEDOUT, cancelling the RPCs made for \
+                     them, if they take longer",
+                )
+                .validator(offs::validators::check_positive_number)
+                .default_value("300"),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("FILE")
+                .help("Authenticates to the server with the token read from the given file"),
+        )
+        .arg(
+            Arg::with_name("encrypt-cache")
+                .long("encrypt-cache")
+                .help(
+                    "Encrypts the blobs and the journal stored in the cache; has to be given \
+                     when the cache is created",
+                ),
+        )
+        .arg(
+            Arg::with_name("cache-passphrase-file")
+                .long("cache-passphrase-file")
+                .value_name("FILE")
+                .help("Reads the passphrase the cache is encrypted with from the given file")
+                .requires("encrypt-cache"),
+        )
+        .arg(
+            Arg::with_name("cache-passphrase-command")
+                .long("cache-passphrase-command")
+                .value_name("COMMAND")
+                .help(
+                    "Reads the passphrase the cache is encrypted with from the output of the \
+                     given shell command",
+                )
+                .requires("encrypt-cache")
+                .conflicts_with("cache-passphrase-file"),
         )
         .arg(
             Arg::with_name("offline")
@@ -30,6 +341,25 @@ fn main() {
                 .long("offline")
                 .help("Runs the client in the offline mode"),
         )
+        .arg(
+            Arg::with_name("notify")
+                .long("notify")
+                .help(
+                    "Shows desktop notifications of the conflicts, synchronization failures \
+                     and the cache running out of space",
+                )
+                .hidden(!cfg!(all(target_os = "linux", feature = "notifications"))),
+        )
+        .arg(
+            Arg::with_name("no-dbus")
+                .long("no-dbus")
+                .help(
+                    "Does not serve the D-Bus interface, leaving the control socket as the only \
+                     control channel; for the machines without a session bus",
+                )
+                .conflicts_with("notify")
+                .hidden(!cfg!(target_os = "linux")),
+        )
         .arg(
             Arg::with_name("foreground")
                 .short("f")
@@ -37,18 +367,97 @@ fn main() {
                 .help("Operate in foreground"),
         )
         .arg(
-            Arg::with_name("ADDRESS")
-                .help("The address of the server to connect to")
+            Arg::with_name("pidfile")
+                .long("pidfile")
+                .value_name("FILE")
+                .help("Writes the ID of the client process to the given file"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .help("Appends the output of the client to the given file when running in the background")
+        )
+        .arg(
+            Arg::with_name("supervise")
+                .long("supervise")
+                .help("Restarts the client and remounts the filesystems if it crashes"),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .value_name("ADDRESS")
+                .help("Exposes Prometheus metrics over HTTP on the given address")
+                .validator(offs::validators::check_address),
+        )
+        .arg(
+            Arg::with_name("nfs-addr")
+                .long("nfs-addr")
+                .value_name("ADDRESS")
+                .help(
+                    "Serves the filesystem over NFSv3 on the given address instead of mounting \
+                     it with FUSE",
+                )
                 .validator(offs::validators::check_address)
-                .required(true)
-                .index(1),
+                .hidden(!cfg!(feature = "nfs")),
+        )
+        .arg(
+            Arg::with_name("allow-other")
+                .long("allow-other")
+                .help(
+                    "Lets all the users access the mount, not only the one running the client; \
+                     needs `user_allow_other` in /etc/fuse.conf unless running as root",
+                )
+                .conflicts_with("nfs-addr"),
+        )
+        .arg(
+            Arg::with_name("allow-root")
+                .long("allow-root")
+                .help("Lets root access the mount, in addition to the user running the client")
+                .conflicts_with_all(&["allow-other", "nfs-addr"]),
         )
         .arg(
-            Arg::with_name("MOUNT_POINT")
-                .help("The path to mount the filesystem to")
-                .validator(offs::validators::check_is_dir)
-                .required(true)
-                .index(2),
+            Arg::with_name("default-permissions")
+                .long("default-permissions")
+                .help("Makes the kernel check the permissions by the file modes")
+                .conflicts_with("nfs-addr"),
+        )
+        .arg(
+            Arg::with_name("auto-unmount")
+                .long("auto-unmount")
+                .help("Unmounts the filesystems once the client exits, even if it crashes")
+                .conflicts_with("nfs-addr"),
+        )
+        .arg(
+            Arg::with_name("fsname")
+                .long("fsname")
+                .value_name("NAME")
+                .help("Sets the source of the mounts shown in the mount table")
+                .conflicts_with("nfs-addr"),
+        )
+        .arg(
+            Arg::with_name("subtype")
+                .long("subtype")
+                .value_name("NAME")
+                .help("Sets the type of the mounts shown in the mount table to fuse.NAME")
+                .conflicts_with("nfs-addr"),
+        )
+        .arg(
+            Arg::with_name("otlp-endpoint")
+                .long("otlp-endpoint")
+                .value_name("URL")
+                .help("Exports the traces to an OpenTelemetry collector"),
+        )
+        .arg(
+            Arg::with_name("MOUNTS")
+                .help(
+                    "The address of the server to connect to and the path to mount the \
+                     filesystem to; can be repeated to mount several filesystems",
+                )
+                .value_names(&["ADDRESS", "MOUNT_POINT"])
+                .use_delimiter(false)
+                .multiple(true)
+                .required(true),
         )
         .arg(
             Arg::with_name("verbosity")
@@ -63,34 +472,178 @@ fn main() {
         )
         .get_matches();
 
-    let verbose = matches.occurrences_of("verbosity") as usize;
-    let quiet = matches.is_present("quiet");
-    stderrlog::new()
-        .module(module_path!())
-        .quiet(quiet)
-        .verbosity(verbose)
-        .timestamp(Timestamp::Millisecond)
-        .init()
+    let mount_args: Vec<&str> = matches.values_of("MOUNTS").unwrap().collect();
+    let cache_paths: Vec<&str> = matches
+        .values_of("cache")
+        .map(|x| x.collect())
+        .unwrap_or_default();
+    let token = matches
+        .value_of("token-file")
+        .map(|x| read_token(x).unwrap_or_else(|e| e.exit()));
+    let cache_passphrase = if matches.is_present("encrypt-cache") {
+        Some(
+            read_cache_passphrase(
+                matches.value_of("cache-passphrase-file"),
+                matches.value_of("cache-passphrase-command"),
+            )
+            .unwrap_or_else(|e| e.exit()),
+        )
+    } else {
+        None
+    };
+    let mut mounts = parse_mounts(
+        &mount_args,
+        &cache_paths,
+        token.as_deref(),
+        cache_passphrase.as_deref(),
+        CacheMaintenance {
+            interval: matches
+                .value_of("maintenance-interval")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            incremental_vacuum: matches.is_present("incremental-vacuum"),
+        },
+        Timeouts {
+            rpc: match matches.value_of("rpc-timeout").unwrap().parse().unwrap() {
+                0 => None,
+                x => Some(Duration::from_secs(x)),
+            },
+            operation: Duration::from_secs(
+                matches
+                    .value_of("operation-timeout")
+                    .unwrap()
+                    .parse()
+                    .unwrap(),
+            ),
+        },
+        FuseOptions {
+            allow_other: matches.is_present("allow-other"),
+            allow_root: matches.is_present("allow-root"),
+            default_permissions: matches.is_present("default-permissions"),
+            auto_unmount: matches.is_present("auto-unmount"),
+            fsname: matches.value_of("fsname").map(|x| x.to_owned()),
+            subtype: matches.value_of("subtype").map(|x| x.to_owned()),
+        },
+    )
+    .unwrap_or_else(|e| e.exit());
+    let ignore_files: Vec<&str> = matches
+        .values_of("ignore-file")
+        .map(|x| x.collect())
+        .unwrap_or_default();
+    read_ignore_files(&mut mounts, &ignore_files).unwrap_or_else(|e| e.exit());
+    if let Some(nfs_address) = matches.value_of("nfs-addr") {
+        serve_over_nfs(&mut mounts, nfs_address).unwrap_or_else(|e| e.exit());
+    }
+
+    let offline = matches.is_present("offline");
+    let notify = matches.is_present("notify");
+    let dbus = !matches.is_present("no-dbus");
+    if notify {
+        check_notifications_supported().unwrap_or_else(|e| e.exit());
+    }
+    let blob_cache_size: usize = matches
+        .value_of("blob-cache-size")
+        .unwrap()
+        .parse()
         .unwrap();
+    let min_free_space: u64 = matches.value_of("min-free-space").unwrap().parse().unwrap();
+    // Read before detaching, so that an invalid file is reported on the terminal, and made
+    // absolute, as the working directory changes when the client detaches
+    let config_path = matches.value_of("config").map(|path| {
+        env::current_dir()
+            .map(|x| x.join(path))
+            .unwrap_or_else(|_| PathBuf::from(path))
+    });
+    let config = match &config_path {
+        Some(path) => ClientConfig::load(path).unwrap_or_else(|e| {
+            clap::Error::with_description(
+                &format!("{}: {}", path.display(), e),
+                ErrorKind::InvalidValue,
+            )
+            .exit()
+        }),
+        None => ClientConfig::default(),
+    };
+    let normalize_names = !matches.is_present("no-normalize-names");
 
-    let store = Store::new_client(matches.value_of("cache").unwrap()).unwrap();
+    let metrics_address = matches
+        .value_of("metrics-addr")
+        .map(|x| x.to_socket_addrs().unwrap().next().unwrap());
 
-    let address_str = matches.value_of("ADDRESS").unwrap();
-    let address = address_str.to_socket_addrs().unwrap().next().unwrap();
+    // Opened before detaching, so that a cache that cannot be used is reported on the terminal
+    for mount in &mounts {
+        client::check_cache(mount).unwrap_or_else(|e| {
+            clap::Error::with_description(
+                &format!("{}: {}", mount.cache_path.display(), e),
+                ErrorKind::Io,
+            )
+            .exit()
+        });
+    }
 
-    let offline = matches.is_present("offline");
+    // Locked before detaching, so that a client already running is reported on the terminal
+    let mut pid_file = matches
+        .value_of("pidfile")
+        .map(|path| PidFile::lock(path.as_ref()).unwrap_or_else(|e| io_error(path, e).exit()));
+    if !matches.is_present("foreground") {
+        let log_file = matches.value_of("log-file");
+        let detached = daemon::detach(log_file.map(Path::new))
+            .unwrap_or_else(|e| io_error(log_file.unwrap_or("detach"), e).exit());
+        if !detached {
+            return;
+        }
+    }
+    if let Some(pid_file) = &mut pid_file {
+        pid_file
+            .write_pid()
+            .unwrap_or_else(|e| io_error(matches.value_of("pidfile").unwrap(), e).exit());
+    }
 
-    let mount_point = Path::new(matches.value_of("MOUNT_POINT").unwrap());
+    if matches.is_present("supervise") {
+        let mount_points: Vec<_> = mounts.iter().map(|x| x.mount_point.clone()).collect();
+        supervisor::supervise(&mount_points, pid_file.take());
+    }
 
-    unsafe {
-        if !matches.is_present("foreground") {
-            match fork() {
-                Ok(ForkResult::Parent { .. }) => return,
-                Ok(ForkResult::Child) => {}
-                Err(_) => panic!("Fork failed"),
-            }
-        }
+    // The runtime and the tracing subscriber are created after forking, as neither of them
+    // survives it
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let verbose = matches.occurrences_of("verbosity") as usize;
+    let quiet = matches.is_present("quiet");
+    {
+        let _guard = rt.enter();
+        offs::telemetry::init_tracing(
+            "offs-client",
+            &[module_path!(), "offs"],
+            verbose,
+            quiet,
+            matches.value_of("otlp-endpoint"),
+        )
+        .expect("Could not initialize logging");
     }
 
-    client::run_client(mount_point, address, offline, store);
+    let settings = Settings {
+        log_level: offs::telemetry::verbosity_level(verbose, quiet),
+        blob_cache_size: blob_cache_size * 1024 * 1024,
+        min_free_space: min_free_space * 1024 * 1024,
+    };
+    let reloader =
+        Reloader::new(config_path, &config, settings).expect("Could not apply the configuration");
+
+    client::run_client(
+        mounts,
+        offline,
+        notify,
+        dbus,
+        metrics_address,
+        Arc::new(reloader),
+        normalize_names,
+        rt,
+    );
+
+    offs::telemetry::shutdown_tracing();
 }
@@ -0,0 +1,209 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntGauge, Registry,
+    TextEncoder,
+};
+
+use offs::store::blob_cache::BlobCache;
+
+const METRICS_PATH: &str = "/metrics";
+
+pub struct Metrics {
+    registry: Registry,
+
+    operation_duration: HistogramVec,
+    cache_hits: IntCounter,
+    cache_misses: IntCounter,
+    blobs_fetched: IntCounter,
+    blob_bytes_fetched: IntCounter,
+
+    blob_cache: Arc<BlobCache>,
+    blob_cache_sync: Mutex<()>,
+    blob_cache_hits: IntCounter,
+    blob_cache_misses: IntCounter,
+    blob_cache_size: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(blob_cache: Arc<BlobCache>) -> Self {
+        let registry = Registry::new_custom(Some("offs_client".to_owned()), None).unwrap();
+
+        let operation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "operation_duration_seconds",
+                "Time spent handling filesystem operations",
+            ),
+            &["operation"],
+        )
+        .unwrap();
+        let cache_hits = IntCounter::new(
+            "cache_hits_total",
+            "Number of chunks read that were already present in the cache",
+        )
+        .unwrap();
+        let cache_misses = IntCounter::new(
+            "cache_misses_total",
+            "Number of chunks read that had to be fetched from the server",
+        )
+        .unwrap();
+        let blobs_fetched = IntCounter::new(
+            "blobs_fetched_total",
+            "Number of blobs fetched from the server",
+        )
+        .unwrap();
+        let blob_bytes_fetched = IntCounter::new(
+            "blob_bytes_fetched_total",
+            "Number of blob content bytes fetched from the server",
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(operation_duration.clone()))
+            .unwrap();
+        let blob_cache_hits = IntCounter::new(
+            "blob_cache_hits_total",
+            "Number of blobs served from the in-memory blob cache",
+        )
+        .unwrap();
+        let blob_cache_misses = IntCounter::new(
+            "blob_cache_misses_total",
+            "Number of blobs that had to be read from the cache database",
+        )
+        .unwrap();
+        let blob_cache_size = IntGauge::new(
+            "blob_cache_size_bytes",
+            "Total size of the blobs held in the in-memory blob cache",
+        )
+        .unwrap();
+
+        registry.register(Box::new(cache_hits.clone())).unwrap();
+        registry.register(Box::new(cache_misses.clone())).unwrap();
+        registry.register(Box::new(blobs_fetched.clone())).unwrap();
+        registry
+            .register(Box::new(blob_bytes_fetched.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blob_cache_hits.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blob_cache_misses.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blob_cache_size.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+
+            operation_duration,
+            cache_hits,
+            cache_misses,
+            blobs_fetched,
+            blob_bytes_fetched,
+
+            blob_cache,
+            blob_cache_sync: Mutex::new(()),
+            blob_cache_hits,
+            blob_cache_misses,
+            blob_cache_size,
+        }
+    }
+
+    /// Returns a timer that records the operation latency when dropped
+    pub fn start_operation(&self, operation: &str) -> HistogramTimer {
+        self.operation_duration
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+
+    pub fn add_cache_lookups(&self, hits: u64, misses: u64) {
+        self.cache_hits.inc_by(hits);
+        self.cache_misses.inc_by(misses);
+    }
+
+    pub fn add_blobs_fetched(&self, count: u64, bytes: u64) {
+        self.blobs_fetched.inc_by(count);
+        self.blob_bytes_fetched.inc_by(bytes);
+    }
+
+    pub fn cache_hits(&self) -> u64 {
+        self.cache_hits.get()
+    }
+
+    pub fn cache_misses(&self) -> u64 {
+        self.cache_misses.get()
+    }
+
+    pub fn blobs_fetched(&self) -> u64 {
+        self.blobs_fetched.get()
+    }
+
+    pub fn blob_bytes_fetched(&self) -> u64 {
+        self.blob_bytes_fetched.get()
+    }
+
+    /// Copies the blob cache statistics, which are kept by the store itself, into the registry
+    fn sync_blob_cache_metrics(&self) {
+        let _guard = self.blob_cache_sync.lock().unwrap();
+
+        self.blob_cache_hits
+            .inc_by(self.blob_cache.hits() - self.blob_cache_hits.get());
+        self.blob_cache_misses
+            .inc_by(self.blob_cache.misses() - self.blob_cache_misses.get());
+        self.blob_cache_size.set(self.blob_cache.size() as i64);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.sync_blob_cache_metrics();
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+
+        buffer
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    metrics: Arc<Metrics>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    let response = Response::builder()
+        .header(
+            hyper::header::CONTENT_TYPE,
+            TextEncoder::new().format_type(),
+        )
+        .body(Body::from(metrics.encode()))
+        .unwrap();
+
+    Ok(response)
+}
+
+pub async fn run_metrics_server(
+    metrics: Arc<Metrics>,
+    address: SocketAddr,
+) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, metrics.clone())
+            }))
+        }
+    });
+
+    hyper::Server::bind(&address).serve(make_service).await
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use tracing::error;
+
+use offs::PROJ_NAME;
+use offs_client::events::{ConflictResolution, SyncEvent};
+
+const NOTIFICATIONS_SERVICE: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFY: &str = "Notify";
+const ICON: &str = "dialog-warning";
+/// The notifications of the events that come in bursts, such as the conflicts found in a
+/// single journal, are merged when they arrive within this time
+const BATCH_DELAY: Duration = Duration::from_millis(500);
+const MAX_LINES: usize = 5;
+
+struct Notification {
+    summary: String,
+    body: String,
+}
+
+/// Shows the problems the user should know about, such as conflicts, as freedesktop desktop
+/// notifications. They are sent from a separate thread, so that a slow notification daemon does
+/// not hold up the filesystem.
+#[derive(Clone)]
+pub struct Notifier {
+    sender: Sender<Notification>,
+}
+
+impl Notifier {
+    pub fn spawn() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(e) = run_notifier(receiver) {
+                error!("Could not show the desktop notifications: {}", e);
+            }
+        });
+
+        Self { sender }
+    }
+
+    /// Returns an event listener showing the notifications of a mount.
+    pub fn listener(&self, mount_point: &Path) -> impl Fn(&SyncEvent) + Send + 'static {
+        let sender = self.sender.clone();
+        let mount_point = mount_point.display().to_string();
+        let mount_point = mount_point.trim_end_matches('/').to_owned();
+
+        move |event| {
+            if let Some(notification) = make_notification(event, &mount_point) {
+                let _ = sender.send(notification);
+            }
+        }
+    }
+}
+
+fn make_notification(event: &SyncEvent, mount_point: &str) -> Option<Notification> {
+    let (summary, body) = match event {
+        SyncEvent::Conflict {
+            file_id,
+            path,
+            resolution,
+        } => {
+            let file = path.as_deref().unwrap_or(file_id);
+            match resolution {
+                ConflictResolution::Recreated => (
+                    "Conflicting changes".to_owned(),
+                    format!("{}{} has been saved as a new file", mount_point, file),
+                ),
+                ConflictResolution::Quarantined(reason) => (
+                    "Changes rejected by the server".to_owned(),
+                    format!("{}{}: {}", mount_point, file, reason),
+                ),
+            }
+        }
+        SyncEvent::SyncFailed { error } => (
+            "Synchronization failed".to_owned(),
+            format!("{}: {}", mount_point, error),
+        ),
+        SyncEvent::CacheNearlyFull { free_space, .. } => (
+            "Cache nearly full".to_owned(),
+            format!(
+                "Only {} MiB left on the device of the cache of {}",
+                free_space / 1024 / 1024,
+                mount_point
+            ),
+        ),
+        SyncEvent::SyncComplete { .. } | SyncEvent::GoneOffline => return None,
+    };
+
+    Some(Notification { summary, body })
+}
+
+fn run_notifier(receiver: Receiver<Notification>) -> Result<(), dbus::Error> {
+    let connection = Connection::new_session()?;
+    let proxy = connection.with_proxy(
+        NOTIFICATIONS_SERVICE,
+        NOTIFICATIONS_PATH,
+        Duration::from_millis(5000),
+    );
+
+    while let Ok(notification) = receiver.recv() {
+        thread::sleep(BATCH_DELAY);
+        let mut batches: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for notification in std::iter::once(notification).chain(receiver.try_iter()) {
+            batches
+                .entry(notification.summary)
+                .or_default()
+                .push(notification.body);
+        }
+
+        for (summary, lines) in batches {
+            let mut body = lines.iter().take(MAX_LINES).cloned().collect::<Vec<_>>();
+            if lines.len() > MAX_LINES {
+                body.push(format!("and {} more", lines.len() - MAX_LINES));
+            }
+
+            let result: Result<(u32,), _> = proxy.method_call(
+                NOTIFICATIONS_SERVICE,
+                NOTIFY,
+                (
+                    PROJ_NAME,
+                    0u32,
+                    ICON,
+                    format!("{}: {}", PROJ_NAME, summary),
+                    body.join("\n"),
+                    Vec::<String>::new(),
+                    PropMap::new(),
+                    -1i32,
+                ),
+            );
+            if let Err(e) = result {
+                error!("Could not show a desktop notification: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
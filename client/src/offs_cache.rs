@@ -0,0 +1,251 @@
+//! Lists and reads the files stored in a client cache without mounting the filesystem or
+//! connecting to the server, e.g. to recover the data when FUSE is not available.
+
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use clap::{App, AppSettings, Arg, SubCommand};
+use serde_json::json;
+
+use offs::errors::{OperationError, OperationResult};
+use offs::names::normalize_name;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{DirEntity, FileType, Store};
+use offs::{BLOB_SIZE, ROOT_ID};
+
+const DEFAULT_CACHE_PATH: &str = "cache.db";
+/// How much of a file is read from the cache at once
+const READ_SIZE: usize = 64 * BLOB_SIZE;
+
+type CacheStore = StoreWrapper<LocalTempIdGenerator>;
+
+fn resolve(store: &CacheStore, path: &str) -> OperationResult<DirEntity> {
+    let mut dirent = store.query_file(ROOT_ID)?;
+    for name in path.split('/').filter(|x| !x.is_empty()) {
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+        dirent = store
+            .try_query_file_by_name(&dirent.id, &normalize_name(name))?
+            .ok_or_else(|| OperationError::file_does_not_exist(path))?;
+    }
+
+    Ok(dirent)
+}
+
+/// Returns whether the whole content of the file is stored in the cache.
+fn is_cached(store: &CacheStore, dirent: &DirEntity) -> OperationResult<bool> {
+    match dirent.stat.file_type {
+        FileType::Directory => Ok(dirent.is_retrieved()),
+        FileType::RegularFile | FileType::Symlink => {
+            // The chunk lists of the files are only retrieved when they are first read
+            let chunks = store.get_chunks(&dirent.id)?;
+            let chunk_count = (dirent.stat.size as usize).div_ceil(BLOB_SIZE);
+            Ok(chunks.len() >= chunk_count && store.get_missing_blobs(&chunks)?.is_empty())
+        }
+        _ => Ok(true),
+    }
+}
+
+fn mode_string(dirent: &DirEntity) -> String {
+    let file_type = match dirent.stat.file_type {
+        FileType::NamedPipe => 'p',
+        FileType::CharDevice => 'c',
+        FileType::BlockDevice => 'b',
+        FileType::Directory => 'd',
+        FileType::RegularFile => '-',
+        FileType::Symlink => 'l',
+        FileType::Socket => 's',
+    };
+    let permissions = (0..9).rev().map(|bit| {
+        if dirent.stat.mode & (1 << bit) == 0 {
+            '-'
+        } else {
+            ['x', 'w', 'r'][bit % 3]
+        }
+    });
+
+    std::iter::once(file_type).chain(permissions).collect()
+}
+
+fn list(store: &CacheStore, path: &str, long: bool, as_json: bool) -> OperationResult<()> {
+    let dirent = resolve(store, path)?;
+    if dirent.stat.file_type != FileType::Directory {
+        return Err(OperationError::not_a_directory(path));
+    }
+    if !dirent.is_retrieved() {
+        eprintln!(
+            "warning: {} has never been listed by the client; the listing may be incomplete",
+            path
+        );
+    }
+
+    let mut files = store.list_files(&dirent.id)?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if as_json {
+        let mut value = Vec::new();
+        for file in &files {
+            value.push(json!({
+                "name": file.name,
+                "mode": file.stat.mode,
+                "directory": file.stat.file_type == FileType::Directory,
+                "size": file.stat.size,
+                "mtime": file.stat.mtim.sec,
+                "cached": is_cached(store, file)?,
+            }));
+        }
+        println!("{}", json!(value));
+    } else {
+        for file in &files {
+            let suffix = if file.stat.file_type == FileType::Directory {
+                "/"
+            } else {
+                ""
+            };
+            if long {
+                let cached = if is_cached(store, file)? {
+                    "cached"
+                } else {
+                    "missing"
+                };
+                println!(
+                    "{} {:>12} {:<8} {}{}",
+                    mode_string(file),
+                    file.stat.size,
+                    cached,
+                    file.name,
+                    suffix
+                );
+            } else {
+                println!("{}{}", file.name, suffix);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn cat(store: &mut CacheStore, path: &str) -> OperationResult<()> {
+    let dirent = resolve(store, path)?;
+    if dirent.stat.file_type == FileType::Directory {
+        return Err(OperationError::is_a_directory(path));
+    }
+    if !is_cached(store, &dirent)? {
+        return Err(OperationError::invalid_argument(&format!(
+            "the content of {} is not stored in the cache",
+            path
+        )));
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut offset = 0;
+    while offset < dirent.stat.size {
+        let size = (dirent.stat.size - offset).min(READ_SIZE as u64) as u32;
+        let data = store.read(&dirent.id, offset as i64, size)?;
+        if data.is_empty() {
+            break;
+        }
+        if let Err(e) = stdout.write_all(&data) {
+            // e.g. the output piped to `head`
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return Ok(());
+            }
+            return Err(OperationError::invalid_argument(&e.to_string()));
+        }
+        offset += data.len() as u64;
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("offs cache")
+        .version("0.1")
+        .author("Mateusz Maćkowski <m4tx@m4tx.pl>")
+        .about("Reads the files stored in an offs client cache, without mounting it")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("cache")
+                .short("c")
+                .long("cache")
+                .value_name("FILE")
+                .global(true)
+                .default_value(DEFAULT_CACHE_PATH)
+                .help("Sets the cache database path"),
+        )
+        .arg(
+            Arg::with_name("cache-passphrase-file")
+                .long("cache-passphrase-file")
+                .value_name("FILE")
+                .global(true)
+                .help("Reads the passphrase the cache is encrypted with from the given file"),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("Lists the files of a directory")
+                .arg(
+                    Arg::with_name("long")
+                        .short("l")
+                        .help("Shows the modes and the sizes, and whether the content is cached"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .conflicts_with("long")
+                        .help("Prints the files as a JSON array"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .default_value("/")
+                        .help("Directory, relative to the root of the filesystem"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Writes the content of a file to stdout")
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("File, relative to the root of the filesystem"),
+                ),
+        )
+        .get_matches();
+
+    let cache_path = matches.value_of("cache").unwrap();
+    if fs::metadata(cache_path).is_err() {
+        eprintln!("{}: the cache does not exist", cache_path);
+        process::exit(1);
+    }
+    let passphrase = matches.value_of("cache-passphrase-file").map(|path| {
+        let passphrase = fs::read_to_string(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        });
+        passphrase.trim_end_matches(&['\r', '\n'][..]).to_owned()
+    });
+
+    let store = Store::new_client(cache_path, passphrase.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Could not open the cache: {}", e.message);
+        process::exit(1);
+    });
+    let mut store = StoreWrapper::new(store);
+
+    let result = match matches.subcommand() {
+        ("ls", Some(sub_m)) => list(
+            &store,
+            sub_m.value_of("path").unwrap(),
+            sub_m.is_present("long"),
+            sub_m.is_present("json"),
+        ),
+        ("cat", Some(sub_m)) => cat(&mut store, sub_m.value_of("path").unwrap()),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e.message);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{HeaderMap, Request, Response};
+use tonic::codegen::{Body, BoxFuture, Service, StdError};
+use tonic::transport::Channel;
+use tonic::Status;
+
+/// Fault injected into a single call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// The request never reaches the server.
+    Drop,
+    /// The server handles the request, but its response is lost.
+    DropResponse,
+    /// The connection breaks after the given number of frames of the response have been
+    /// received.
+    Disconnect { after_frames: usize },
+}
+
+/// How often the faults happen when none are injected explicitly. Given the same seed and the
+/// same sequence of calls, the same faults are chosen.
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Delay added to every call
+    pub latency: Duration,
+    pub drop_rate: f64,
+    pub drop_response_rate: f64,
+    pub disconnect_rate: f64,
+}
+
+struct ChaosState {
+    config: ChaosConfig,
+    rng: u64,
+    /// Along with the name of the method of the call to fail, if it is not just the next one
+    injected: VecDeque<(Option<String>, Fault)>,
+    fault_count: u64,
+}
+
+/// Handle controlling the faults of a [`ChaosChannel`]; the clones control the same channel.
+#[derive(Clone)]
+pub struct Chaos {
+    state: Arc<Mutex<ChaosState>>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        let chaos = Self {
+            state: Arc::new(Mutex::new(ChaosState {
+                config: Default::default(),
+                rng: 0,
+                injected: VecDeque::new(),
+                fault_count: 0,
+            })),
+        };
+        chaos.set_config(config);
+
+        chaos
+    }
+
+    /// Replaces the configuration, restarting the sequence of the random faults.
+    pub fn set_config(&self, config: ChaosConfig) {
+        let mut state = self.state.lock().unwrap();
+        // xorshift gets stuck at 0
+        state.rng = config.seed | 1;
+        state.config = config;
+    }
+
+    /// Makes the next call fail with the fault, regardless of the configuration. Multiple
+    /// faults are used by the consecutive calls.
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().injected.push_back((None, fault));
+    }
+
+    /// Makes the next call of the gRPC method (e.g. `CommitJournalApply`) fail with the fault,
+    /// letting the calls of the other methods through.
+    pub fn inject_into(&self, method: &str, fault: Fault) {
+        let mut state = self.state.lock().unwrap();
+        state.injected.push_back((Some(method.to_owned()), fault));
+    }
+
+    /// Returns how many faults have happened so far.
+    pub fn fault_count(&self) -> u64 {
+        self.state.lock().unwrap().fault_count
+    }
+
+    /// Returns the latency and the fault of the call to the given path, which ends with the
+    /// name of the method.
+    fn next_call(&self, path: &str) -> (Duration, Option<Fault>) {
+        let mut state = self.state.lock().unwrap();
+
+        let injected = state.injected.iter().position(|(method, _)| match method {
+            Some(method) => path.rsplit('/').next() == Some(method.as_str()),
+            None => true,
+        });
+        let fault = match injected {
+            Some(i) => state.injected.remove(i).map(|(_, fault)| fault),
+            None => state.next_random_fault(),
+        };
+        if fault.is_some() {
+            state.fault_count += 1;
+        }
+
+        (state.config.latency, fault)
+    }
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl ChaosState {
+    fn next_random_fault(&mut self) -> Option<Fault> {
+        let roll = self.next_f64();
+        let config = &self.config;
+
+        if roll < config.drop_rate {
+            Some(Fault::Drop)
+        } else if roll < config.drop_rate + config.drop_response_rate {
+            Some(Fault::DropResponse)
+        } else if roll < config.drop_rate + config.drop_response_rate + config.disconnect_rate {
+            let after_frames = (self.next_u64() % 4) as usize;
+            Some(Fault::Disconnect { after_frames })
+        } else {
+            None
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Channel to the server that adds latency, drops requests and responses, and breaks the
+/// response streams, as controlled by a [`Chaos`] handle. Without any faults configured, it
+/// behaves just like the channel it wraps.
+#[derive(Clone)]
+pub struct ChaosChannel {
+    inner: Channel,
+    chaos: Chaos,
+}
+
+impl ChaosChannel {
+    pub fn new(inner: Channel, chaos: Chaos) -> Self {
+        Self { inner, chaos }
+    }
+}
+
+fn connection_error(message: &str) -> StdError {
+    Box::new(Status::unavailable(format!("chaos: {}", message)))
+}
+
+impl Service<Request<BoxBody>> for ChaosChannel {
+    type Response = Response<ChaosBody>;
+    type Error = StdError;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let (latency, fault) = self.chaos.next_call(request.uri().path());
+        // The channel that has been polled ready is the one that has to be called
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+            if fault == Some(Fault::Drop) {
+                return Err(connection_error("request dropped"));
+            }
+
+            let response = inner.call(request).await?;
+            let frames_left = match fault {
+                Some(Fault::DropResponse) => return Err(connection_error("response dropped")),
+                Some(Fault::Disconnect { after_frames }) => Some(after_frames),
+                _ => None,
+            };
+
+            let (parts, body) = response.into_parts();
+            Ok(Response::from_parts(
+                parts,
+                ChaosBody {
+                    inner: body,
+                    frames_left,
+                },
+            ))
+        })
+    }
+}
+
+/// Body of a response, failing after a number of frames if the connection is to break.
+pub struct ChaosBody {
+    inner: hyper::Body,
+    frames_left: Option<usize>,
+}
+
+impl Body for ChaosBody {
+    type Data = Bytes;
+    type Error = StdError;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.frames_left == Some(0) {
+            return Poll::Ready(Some(Err(connection_error("connection reset"))));
+        }
+
+        let result = Pin::new(&mut self.inner).poll_data(cx);
+        if let (Poll::Ready(Some(Ok(_))), Some(n)) = (&result, self.frames_left) {
+            self.frames_left = Some(n - 1);
+        }
+
+        result.map_err(Into::into)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        if self.frames_left == Some(0) {
+            return Poll::Ready(Err(connection_error("connection reset")));
+        }
+
+        Pin::new(&mut self.inner)
+            .poll_trailers(cx)
+            .map_err(Into::into)
+    }
+}
@@ -1,107 +1,634 @@
+use std::convert::TryInto;
+use std::future::Future;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
 use itertools::Itertools;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Status};
+use tracing::info;
 
-use offs::errors::{JournalApplyResult, OperationResult};
+use offs::errors::{
+    JournalApplyError, JournalApplyResult, JournalOperationStatus, OperationError, OperationResult,
+};
 use offs::modify_op::ModifyOperation;
 use offs::proto::filesystem as proto_types;
-use offs::proto::filesystem::remote_fs_client::RemoteFsClient;
+use offs::proto::filesystem::remote_fs_client::RemoteFsClient as ProtoRemoteFsClient;
 use offs::proto::filesystem::{
-    ApplyJournalRequest, GetBlobsRequest, GetMissingBlobsRequest, ListChunksRequest, ListRequest,
+    begin_journal_apply_response, ApplyJournalRequest, BeginJournalApplyResponse,
+    CommitJournalApplyRequest, CreateShareLinkRequest, GetBlobsRequest, GetDirEntitiesRequest,
+    GetDirUsageRequest, GetMissingBlobsRequest, GetServerInfoRequest, ListChunksRequest,
+    ListRequest, ListSidecarsRequest, ServerInfo, SetSidecarRequest,
+};
+use offs::protocol::{
+    check_compatibility, FEATURE_DIR_USAGE, FEATURE_LIST_TREE, FEATURE_SHARE_LINKS,
+    FEATURE_SIDECARS, FEATURE_STAGED_JOURNAL_APPLY, FEATURE_STREAMED_JOURNAL_APPLY,
+    FEATURE_VALIDATE_JOURNAL, PROTOCOL_VERSION,
 };
-use offs::store::DirEntity;
+use offs::store::{DirEntity, DirUsage, FileType, Sidecar};
+use offs::{AUTHORIZATION_HEADER_KEY, CLIENT_ID_HEADER_KEY, REQUEST_ID_HEADER_KEY};
+
+#[cfg(feature = "chaos")]
+use super::chaos::{Chaos, ChaosChannel};
+use super::remote_client::RemoteFsClient;
+use crate::server_address::ServerAddress;
+
+/// Link giving read-only access to a file or directory tree over HTTP.
+#[derive(Clone, Debug)]
+pub struct ShareLink {
+    pub url: String,
+    /// Unix time the link expires at
+    pub expires_at: i64,
+}
+
+/// How long the RPCs wait for the server by default, including connecting to it.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(120);
+/// Number of the blobs retrieved in a single request, even if the server allows more, so that
+/// each response (up to 16 MiB) can arrive well within the timeout.
+const MAX_BLOBS_PER_GET_REQUEST: usize = 256;
+
+tokio::task_local! {
+    /// ID of the FUSE request being handled, sent along with the RPCs to correlate the logs
+    pub static REQUEST_ID: u64;
+    /// Time by which the FUSE request being handled has to be answered. The RPCs made for it
+    /// are abandoned once it passes, and the server is told to give up on them as well.
+    pub static REQUEST_DEADLINE: Instant;
+}
+
+fn make_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+
+    if let Ok(request_id) = REQUEST_ID.try_with(|x| *x) {
+        request
+            .metadata_mut()
+            .insert(REQUEST_ID_HEADER_KEY, MetadataValue::from(request_id));
+    }
+    if let Ok(deadline) = REQUEST_DEADLINE.try_with(|x| *x) {
+        request.set_timeout(deadline.saturating_duration_since(Instant::now()));
+    }
+    offs::telemetry::inject_trace_context(request.metadata_mut());
+
+    request
+}
+
+/// Fails the call with `DeadlineExceeded` if the deadline of the request being handled passes
+/// before it completes.
+async fn with_deadline<T>(call: impl Future<Output = Result<T, Status>>) -> Result<T, Status> {
+    let deadline = match REQUEST_DEADLINE.try_with(|x| *x) {
+        Ok(deadline) => deadline,
+        Err(_) => return call.await,
+    };
+
+    match tokio::time::timeout_at(deadline, call).await {
+        Ok(result) => result,
+        Err(_) => Err(Status::deadline_exceeded("the request has timed out")),
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+type Transport = Channel;
+#[cfg(feature = "chaos")]
+type Transport = ChaosChannel;
 
 pub struct RemoteFsGrpcClient {
-    client: RemoteFsClient<tonic::transport::Channel>,
+    client: ProtoRemoteFsClient<Transport>,
+    share: String,
+    client_id: Option<MetadataValue<Ascii>>,
+    authorization: Option<MetadataValue<Ascii>>,
+    server_info: ServerInfo,
 }
 
 impl RemoteFsGrpcClient {
-    pub async fn new(address: &str) -> OperationResult<Self> {
-        let client = RemoteFsClient::connect(format!("http://{}", address))
+    /// Connects to the server. Unless `timeout` is `None`, the RPCs the server does not respond
+    /// to in time fail as if the client was offline.
+    pub async fn new(
+        server: &ServerAddress,
+        token: Option<&str>,
+        replica_id: String,
+        timeout: Option<Duration>,
+    ) -> OperationResult<Self> {
+        let channel = Self::connect(server, timeout).await?;
+        #[cfg(feature = "chaos")]
+        let channel = ChaosChannel::new(channel, Chaos::default());
+
+        Self::with_channel(channel, server, token, replica_id).await
+    }
+
+    /// Like [`RemoteFsGrpcClient::new`], but the connection suffers the faults controlled by
+    /// the `chaos` handle.
+    #[cfg(feature = "chaos")]
+    pub async fn with_chaos(
+        server: &ServerAddress,
+        token: Option<&str>,
+        replica_id: String,
+        timeout: Option<Duration>,
+        chaos: Chaos,
+    ) -> OperationResult<Self> {
+        let channel = ChaosChannel::new(Self::connect(server, timeout).await?, chaos);
+
+        Self::with_channel(channel, server, token, replica_id).await
+    }
+
+    async fn connect(
+        server: &ServerAddress,
+        timeout: Option<Duration>,
+    ) -> OperationResult<Channel> {
+        let mut endpoint = Endpoint::from_shared(format!("http://{}", server.address))
+            .map_err(|e| OperationError::invalid_argument(&e.to_string()))?;
+        if let Some(timeout) = timeout {
+            endpoint = endpoint.timeout(timeout).connect_timeout(timeout);
+        }
+
+        endpoint
+            .connect()
             .await
-            .unwrap();
+            .map_err(|e| OperationError::offline(&e.to_string()))
+    }
+
+    async fn with_channel(
+        channel: Transport,
+        server: &ServerAddress,
+        token: Option<&str>,
+        replica_id: String,
+    ) -> OperationResult<Self> {
+        let client = ProtoRemoteFsClient::new(channel);
+        let authorization = token.map(|x| format!("Bearer {}", x).parse().unwrap());
+        let client_id = replica_id.parse().ok();
+
+        let mut client = Self {
+            client,
+            share: server.share.clone(),
+            client_id,
+            authorization,
+            server_info: Default::default(),
+        };
+
+        let server_info = client.get_server_info().await?;
+        info!(
+            "Connected to server {} (protocol version {}, features: {:?})",
+            server_info.server_version, server_info.protocol_version, server_info.features
+        );
+        check_compatibility(
+            server_info.protocol_version,
+            server_info.min_protocol_version,
+        )?;
+        client.server_info = server_info;
+
+        Ok(client)
+    }
+
+    /// Asks the server about its protocol version and features. The servers predating the RPC
+    /// are described as speaking version 0, without any of the optional features.
+    async fn get_server_info(&mut self) -> OperationResult<ServerInfo> {
+        let req = GetServerInfoRequest {
+            protocol_version: PROTOCOL_VERSION,
+        };
+
+        match with_deadline(self.client.get_server_info(self.make_request(req))).await {
+            Ok(response) => Ok(response.into_inner()),
+            Err(status) if status.code() == Code::Unimplemented => Ok(Default::default()),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Returns how many blobs can be asked for in a single request.
+    fn max_blobs_per_request(&self) -> usize {
+        match self.server_info.limits.as_ref() {
+            Some(limits) if limits.max_blobs_per_request > 0 => {
+                limits.max_blobs_per_request as usize
+            }
+            _ => usize::MAX,
+        }
+    }
+
+    /// Returns how many files can be asked for in a single request.
+    fn max_files_per_request(&self) -> usize {
+        match self.server_info.limits.as_ref() {
+            Some(limits) if limits.max_files_per_request > 0 => {
+                limits.max_files_per_request as usize
+            }
+            _ => usize::MAX,
+        }
+    }
+
+    fn make_request<T>(&self, message: T) -> Request<T> {
+        let mut request = make_request(message);
+
+        if let Some(client_id) = &self.client_id {
+            request
+                .metadata_mut()
+                .insert(CLIENT_ID_HEADER_KEY, client_id.clone());
+        }
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZATION_HEADER_KEY, authorization.clone());
+        }
+
+        request
+    }
+
+    /// Stages the journal, sending each blob in a message of its own after the operations. A
+    /// blob is reported as sent once the transport has taken the message before it, as only one
+    /// message waits for being sent at a time.
+    async fn begin_streamed_journal_apply(
+        &mut self,
+        req: ApplyJournalRequest,
+        blobs: Vec<Bytes>,
+        on_blob_sent: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> Result<BeginJournalApplyResponse, Status> {
+        let (sender, receiver) = mpsc::channel(1);
+        let send = async move {
+            if sender.send(req).await.is_err() {
+                return;
+            }
+            for blob in blobs {
+                let message = ApplyJournalRequest {
+                    blobs: vec![blob.clone()],
+                    ..Default::default()
+                };
+                if sender.send(message).await.is_err() {
+                    return;
+                }
+                on_blob_sent(&blob);
+            }
+        };
+
+        let call = with_deadline(
+            self.client
+                .begin_streamed_journal_apply(self.make_request(ReceiverStream::new(receiver))),
+        );
+        tokio::pin!(call);
+        // The messages stop being sent if the server responds early, such as with an error
+        let response = tokio::select! {
+            response = &mut call => response?,
+            () = send => call.await?,
+        };
 
-        Ok(Self { client })
+        Ok(response.into_inner())
+    }
+}
+
+#[async_trait]
+impl RemoteFsClient for RemoteFsGrpcClient {
+    fn has_feature(&self, feature: &str) -> bool {
+        self.server_info.features.iter().any(|x| x == feature)
     }
 
     // Listing
-    pub async fn list_files(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>> {
+    async fn list_files(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>> {
+        self.list_files_page(dir_id, "", 0).await
+    }
+
+    async fn list_files_page(
+        &mut self,
+        dir_id: &str,
+        start_after: &str,
+        limit: u32,
+    ) -> OperationResult<Vec<DirEntity>> {
         let req = ListRequest {
             id: dir_id.to_owned(),
+            share: self.share.clone(),
+            start_after: start_after.to_owned(),
+            limit,
         };
 
-        let mut stream = self.client.list(req).await?.into_inner();
+        let mut stream = with_deadline(self.client.list(self.make_request(req)))
+            .await?
+            .into_inner();
         let mut res: Vec<DirEntity> = Vec::new();
 
-        while let Some(dir_entity) = stream.message().await? {
-            res.push(dir_entity.into());
+        while let Some(dir_entity) = with_deadline(stream.message()).await? {
+            res.push(dir_entity.try_into()?);
         }
 
         Ok(res)
     }
 
-    pub async fn get_chunks(&mut self, id: &str) -> OperationResult<Vec<String>> {
-        let req = ListChunksRequest { id: id.to_owned() };
+    /// Lists the whole subtree of the directory, parents before their children. The servers not
+    /// supporting `ListTree` are asked for every directory separately.
+    async fn list_tree(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>> {
+        if !self.has_feature(FEATURE_LIST_TREE) {
+            let mut res = self.list_files(dir_id).await?;
+
+            let mut i = 0;
+            while i < res.len() {
+                if res[i].stat.file_type == FileType::Directory {
+                    let id = res[i].id.clone();
+                    let children = self.list_files(&id).await?;
+                    res.extend(children);
+                }
+                i += 1;
+            }
+
+            return Ok(res);
+        }
+
+        let req = ListRequest {
+            id: dir_id.to_owned(),
+            share: self.share.clone(),
+            ..Default::default()
+        };
+
+        let mut stream = with_deadline(self.client.list_tree(self.make_request(req)))
+            .await?
+            .into_inner();
+        let mut res: Vec<DirEntity> = Vec::new();
+
+        while let Some(dir_entity) = with_deadline(stream.message()).await? {
+            res.push(dir_entity.try_into()?);
+        }
 
-        let resp = self.client.list_chunks(req).await?.into_inner();
+        Ok(res)
+    }
+
+    /// Returns the entries of the files which still exist, along with the IDs of the ones
+    /// which do not. The server has to support `GetDirEntities`.
+    async fn get_dir_entities(
+        &mut self,
+        ids: &[String],
+    ) -> OperationResult<(Vec<DirEntity>, Vec<String>)> {
+        let mut dir_entities = Vec::new();
+        let mut missing_ids = Vec::new();
+
+        for batch in ids.chunks(self.max_files_per_request()) {
+            let req = GetDirEntitiesRequest {
+                ids: batch.to_vec(),
+                share: self.share.clone(),
+            };
+
+            let resp = with_deadline(self.client.get_dir_entities(self.make_request(req)))
+                .await?
+                .into_inner();
+            for dir_entity in resp.dir_entities {
+                dir_entities.push(dir_entity.try_into()?);
+            }
+            missing_ids.extend(resp.missing_ids);
+        }
+
+        Ok((dir_entities, missing_ids))
+    }
+
+    async fn get_chunks(&mut self, id: &str) -> OperationResult<Vec<String>> {
+        let req = ListChunksRequest {
+            id: id.to_owned(),
+            share: self.share.clone(),
+        };
+
+        let resp = with_deadline(self.client.list_chunks(self.make_request(req)))
+            .await?
+            .into_inner();
         Ok(resp.blob_id)
     }
 
-    pub async fn get_blobs(&mut self, ids: Vec<String>) -> OperationResult<Vec<(String, Vec<u8>)>> {
-        let req = GetBlobsRequest { id: ids.into() };
+    /// Returns the total size and number of the files in the subtree of the directory, as
+    /// stored on the server.
+    async fn get_dir_usage(&mut self, dir_id: &str) -> OperationResult<DirUsage> {
+        if !self.has_feature(FEATURE_DIR_USAGE) {
+            return Err(OperationError::unsupported_protocol(
+                "the server does not track the usage of the directories; it has to be upgraded",
+            ));
+        }
+
+        let req = GetDirUsageRequest {
+            id: dir_id.to_owned(),
+            share: self.share.clone(),
+        };
 
-        let mut stream = self.client.get_blobs(req).await?.into_inner();
+        let resp = with_deadline(self.client.get_dir_usage(self.make_request(req)))
+            .await?
+            .into_inner();
+        Ok(DirUsage {
+            size: resp.size,
+            files: resp.files,
+            directories: resp.directories,
+        })
+    }
+
+    /// Creates a link giving read-only access to the file, or the directory tree, for the given
+    /// number of seconds.
+    async fn create_share_link(&mut self, id: &str, expires_in: u64) -> OperationResult<ShareLink> {
+        if !self.has_feature(FEATURE_SHARE_LINKS) {
+            return Err(OperationError::unsupported_protocol(
+                "the server does not serve share links",
+            ));
+        }
+
+        let req = CreateShareLinkRequest {
+            id: id.to_owned(),
+            share: self.share.clone(),
+            expires_in,
+        };
+
+        let resp = with_deadline(self.client.create_share_link(self.make_request(req)))
+            .await?
+            .into_inner();
+        Ok(ShareLink {
+            url: resp.url,
+            expires_at: resp.expires_at,
+        })
+    }
+
+    async fn list_sidecars(&mut self, id: &str) -> OperationResult<Vec<Sidecar>> {
+        if !self.has_feature(FEATURE_SIDECARS) {
+            // Nothing can be attached to the files on such a server
+            return Ok(Vec::new());
+        }
+
+        let req = ListSidecarsRequest {
+            id: id.to_owned(),
+            share: self.share.clone(),
+        };
+
+        let resp = with_deadline(self.client.list_sidecars(self.make_request(req)))
+            .await?
+            .into_inner();
+        Ok(resp.sidecars.into_iter().map(Into::into).collect())
+    }
+
+    async fn set_sidecar(
+        &mut self,
+        id: &str,
+        name: &str,
+        data: Bytes,
+    ) -> OperationResult<Vec<Sidecar>> {
+        if !self.has_feature(FEATURE_SIDECARS) {
+            return Err(OperationError::unsupported_protocol(
+                "the server does not store sidecar data; it has to be upgraded",
+            ));
+        }
+
+        let req = SetSidecarRequest {
+            id: id.to_owned(),
+            share: self.share.clone(),
+            name: name.to_owned(),
+            data: data.to_vec(),
+        };
+
+        let resp = with_deadline(self.client.set_sidecar(self.make_request(req)))
+            .await?
+            .into_inner();
+        Ok(resp.sidecars.into_iter().map(Into::into).collect())
+    }
+
+    async fn get_blobs(
+        &mut self,
+        ids: Vec<String>,
+        on_blob_received: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> OperationResult<Vec<(String, Bytes)>> {
         let mut res = Vec::new();
+        let batch_size = self.max_blobs_per_request().min(MAX_BLOBS_PER_GET_REQUEST);
 
-        while let Some(blob) = stream.message().await? {
-            res.push((blob.id, blob.content));
+        for batch in ids.chunks(batch_size) {
+            let req = GetBlobsRequest { id: batch.to_vec() };
+
+            let mut stream = with_deadline(self.client.get_blobs(self.make_request(req)))
+                .await?
+                .into_inner();
+
+            while let Some(blob) = with_deadline(stream.message()).await? {
+                on_blob_received(&blob.content);
+                let content = if blob.length as usize > blob.content.len() {
+                    // The trailing zeros are not sent
+                    let mut content = BytesMut::from(&blob.content[..]);
+                    content.resize(blob.length as usize, 0);
+                    content.freeze()
+                } else {
+                    blob.content
+                };
+                res.push((blob.id, content));
+            }
         }
 
         Ok(res)
     }
 
     // Modifications
-    pub async fn request_apply_operation(
+    async fn request_apply_operation(
         &mut self,
         modify_operation: ModifyOperation,
     ) -> OperationResult<DirEntity> {
-        let result = self
-            .client
-            .apply_operation(offs::proto::filesystem::ModifyOperation::from(
-                modify_operation,
-            ))
+        let req = proto_types::ModifyOperation {
+            share: self.share.clone(),
+            ..modify_operation.into()
+        };
+
+        let result = with_deadline(self.client.apply_operation(self.make_request(req)))
             .await?
             .into_inner();
 
-        Ok(result.into())
+        Ok(result.try_into()?)
     }
 
-    pub async fn apply_journal<'a>(
+    /// Applies the journal in two phases: the blobs are uploaded and the operations staged first,
+    /// and only then are the operations applied, so that losing the connection in the middle of
+    /// the upload leaves the files on the server untouched. The servers not supporting it get
+    /// the whole journal in a single request.
+    async fn apply_journal(
         &mut self,
         journal: Vec<ModifyOperation>,
         chunks: Vec<Vec<String>>,
-        blobs: Vec<Vec<u8>>,
+        blobs: Vec<Bytes>,
+        on_blob_sent: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
     ) -> OperationResult<JournalApplyResult> {
         let converted_journal: Vec<proto_types::ModifyOperation> =
             journal.into_iter().map(|x| x.into()).collect_vec();
         let converted_chunks: Vec<proto_types::FileChunks> =
             chunks.into_iter().map(|x| x.into()).collect_vec();
 
-        let req = ApplyJournalRequest {
+        let mut req = ApplyJournalRequest {
             operations: converted_journal.into(),
             chunks: converted_chunks.into(),
-            blobs: blobs.into(),
+            blobs: Vec::new(),
+            share: self.share.clone(),
+            validate_only: false,
         };
 
-        let result = self.client.apply_journal(req).await?.into_inner();
-        Ok(result.into())
+        let staged = if self.has_feature(FEATURE_STREAMED_JOURNAL_APPLY) {
+            self.begin_streamed_journal_apply(req, blobs, on_blob_sent)
+                .await?
+        } else {
+            // The blobs are sent in a single message, so they are all reported once it is done
+            req.blobs = blobs.clone();
+            let staged = if self.has_feature(FEATURE_STAGED_JOURNAL_APPLY) {
+                with_deadline(self.client.begin_journal_apply(self.make_request(req)))
+                    .await?
+                    .into_inner()
+            } else {
+                let result = with_deadline(self.client.apply_journal(self.make_request(req)))
+                    .await?
+                    .into_inner();
+                blobs.iter().for_each(|blob| on_blob_sent(blob));
+                return Ok(result.try_into()?);
+            };
+            blobs.iter().for_each(|blob| on_blob_sent(blob));
+
+            staged
+        };
+        if let Some(begin_journal_apply_response::Error::MissingBlobs(data)) = staged.error {
+            return Ok(Err(JournalApplyError::MissingBlobs(data.ids)));
+        }
+
+        let req = CommitJournalApplyRequest {
+            staging_id: staged.staging_id,
+            share: self.share.clone(),
+        };
+        let result = with_deadline(self.client.commit_journal_apply(self.make_request(req)))
+            .await?
+            .into_inner();
+        Ok(result.try_into()?)
     }
 
-    pub async fn get_server_missing_blobs(
+    async fn validate_journal(
         &mut self,
-        ids: Vec<String>,
-    ) -> OperationResult<Vec<String>> {
-        let req = GetMissingBlobsRequest { id: ids.into() };
+        journal: Vec<ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+    ) -> OperationResult<Vec<JournalOperationStatus>> {
+        // Otherwise the journal would be applied
+        if !self.has_feature(FEATURE_VALIDATE_JOURNAL) {
+            return Err(OperationError::unsupported_protocol(
+                "the server cannot validate journals; it has to be upgraded",
+            ));
+        }
+
+        let req = ApplyJournalRequest {
+            operations: journal.into_iter().map(|x| x.into()).collect(),
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            blobs: Vec::new(),
+            share: self.share.clone(),
+            validate_only: true,
+        };
 
-        let result = self.client.get_missing_blobs(req).await?.into_inner();
-        Ok(result.blob_id)
+        let result: JournalApplyResult =
+            with_deadline(self.client.apply_journal(self.make_request(req)))
+                .await?
+                .into_inner()
+                .try_into()?;
+        match result {
+            Ok(data) => Ok(data.statuses),
+            Err(JournalApplyError::MissingBlobs(_)) => Err(OperationError::invalid_argument(
+                "the server asked for the blobs of a journal being validated",
+            )),
+        }
+    }
+
+    async fn get_server_missing_blobs(&mut self, ids: Vec<String>) -> OperationResult<Vec<String>> {
+        let mut res = Vec::new();
+
+        for batch in ids.chunks(self.max_blobs_per_request()) {
+            let req = GetMissingBlobsRequest { id: batch.to_vec() };
+
+            let result = with_deadline(self.client.get_missing_blobs(self.make_request(req)))
+                .await?
+                .into_inner();
+            res.extend(result.blob_id);
+        }
+
+        Ok(res)
     }
 }
@@ -1,2 +1,5 @@
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod grpc_client;
 pub mod modify_op_builder;
+pub mod remote_client;
@@ -1,10 +1,12 @@
+use offs::acl::AclType;
 use offs::modify_op::{
-    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation, ModifyOperation,
-    ModifyOperationContent, RemoveDirectoryOperation, RemoveFileOperation, RenameOperation,
+    generate_op_id, CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation,
+    CreateSymlinkOperation, FallocateOperation, ModifyOperation, ModifyOperationContent,
+    RemoveDirectoryOperation, RemoveFileOperation, RenameOperation, SetAclOperation,
     SetAttributesOperation, WriteOperation,
 };
 use offs::now;
-use offs::store::{DirEntity, FileDev, FileMode, FileType};
+use offs::store::{DirEntity, FileDev, FileMode, FileOwner, FileType};
 use offs::timespec::Timespec;
 
 pub struct ModifyOpBuilder;
@@ -13,9 +15,13 @@ impl ModifyOpBuilder {
     fn create_modify_op(dirent: &DirEntity, content: ModifyOperationContent) -> ModifyOperation {
         ModifyOperation {
             id: dirent.id.clone(),
+            op_id: generate_op_id(),
             timestamp: now(),
             dirent_version: dirent.dirent_version,
             content_version: dirent.content_version,
+            content_vector: dirent.content_vector.clone(),
+            dirent_vector: dirent.dirent_vector.clone(),
+            replica_id: String::new(),
             operation: content,
         }
     }
@@ -26,12 +32,14 @@ impl ModifyOpBuilder {
         file_type: FileType,
         perm: FileMode,
         dev: FileDev,
+        owner: Option<FileOwner>,
     ) -> ModifyOperation {
         let operation = CreateFileOperation {
             name: name.to_owned(),
             file_type,
             perm,
             dev,
+            owner,
         };
         let content = ModifyOperationContent::CreateFileOperation(operation);
 
@@ -42,10 +50,12 @@ impl ModifyOpBuilder {
         parent_dirent: &DirEntity,
         name: &str,
         link: &str,
+        owner: Option<FileOwner>,
     ) -> ModifyOperation {
         let operation = CreateSymlinkOperation {
             name: name.to_owned(),
             link: link.to_owned(),
+            owner,
         };
         let content = ModifyOperationContent::CreateSymlinkOperation(operation);
 
@@ -56,10 +66,12 @@ impl ModifyOpBuilder {
         parent_dirent: &DirEntity,
         name: &str,
         perm: FileMode,
+        owner: Option<FileOwner>,
     ) -> ModifyOperation {
         let operation = CreateDirectoryOperation {
             name: name.to_owned(),
             perm,
+            owner,
         };
         let content = ModifyOperationContent::CreateDirectoryOperation(operation);
 
@@ -119,12 +131,56 @@ impl ModifyOpBuilder {
         Self::create_modify_op(dirent, content)
     }
 
+    pub fn make_fallocate_op(
+        dirent: &DirEntity,
+        offset: i64,
+        length: i64,
+        zero: bool,
+        keep_size: bool,
+    ) -> ModifyOperation {
+        let operation = FallocateOperation {
+            offset,
+            length,
+            zero,
+            keep_size,
+        };
+        let content = ModifyOperationContent::FallocateOperation(operation);
+
+        Self::create_modify_op(dirent, content)
+    }
+
+    pub fn make_copy_range_op(
+        dirent: &DirEntity,
+        source_id: &str,
+        source_offset: i64,
+        offset: i64,
+        length: i64,
+    ) -> ModifyOperation {
+        let operation = CopyRangeOperation {
+            source_id: source_id.to_owned(),
+            source_offset,
+            offset,
+            length,
+        };
+        let content = ModifyOperationContent::CopyRangeOperation(operation);
+
+        Self::create_modify_op(dirent, content)
+    }
+
+    pub fn make_set_acl_op(dirent: &DirEntity, acl_type: AclType, acl: Vec<u8>) -> ModifyOperation {
+        let operation = SetAclOperation { acl_type, acl };
+        let content = ModifyOperationContent::SetAclOperation(operation);
+
+        Self::create_modify_op(dirent, content)
+    }
+
     pub fn make_recreate_file_op(parent: &DirEntity, file: &DirEntity) -> ModifyOperation {
         let operation = CreateFileOperation {
             name: file.name.to_owned(),
             file_type: file.stat.file_type,
             perm: file.stat.mode,
             dev: file.stat.dev,
+            owner: Some(file.owner()),
         };
         let content = ModifyOperationContent::CreateFileOperation(operation);
 
@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use offs::errors::{JournalApplyResult, JournalOperationStatus, OperationResult};
+use offs::modify_op::ModifyOperation;
+use offs::store::{DirEntity, DirUsage, Sidecar};
+
+use super::grpc_client::ShareLink;
+
+/// Connection to the server, as used by [`OffsFilesystem`](crate::remote_fs_client::OffsFilesystem).
+/// Implemented by [`RemoteFsGrpcClient`](super::grpc_client::RemoteFsGrpcClient), but can be
+/// replaced with a test double to exercise the filesystem without a server.
+#[async_trait]
+pub trait RemoteFsClient: Send + Sync {
+    /// Returns whether the server supports the optional protocol feature.
+    fn has_feature(&self, feature: &str) -> bool;
+
+    async fn list_files(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>>;
+
+    /// Returns up to `limit` files of the directory whose names sort after `start_after`,
+    /// ordered by the name. The server has to support `list_pages`.
+    async fn list_files_page(
+        &mut self,
+        dir_id: &str,
+        start_after: &str,
+        limit: u32,
+    ) -> OperationResult<Vec<DirEntity>>;
+
+    /// Returns the entries of all the files in the subtree of the directory.
+    async fn list_tree(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>>;
+
+    /// Returns the entries of the files with the given IDs, along with the IDs of the files
+    /// that no longer exist.
+    async fn get_dir_entities(
+        &mut self,
+        ids: &[String],
+    ) -> OperationResult<(Vec<DirEntity>, Vec<String>)>;
+
+    async fn get_chunks(&mut self, id: &str) -> OperationResult<Vec<String>>;
+
+    async fn get_dir_usage(&mut self, dir_id: &str) -> OperationResult<DirUsage>;
+
+    async fn create_share_link(&mut self, id: &str, expires_in: u64) -> OperationResult<ShareLink>;
+
+    async fn list_sidecars(&mut self, id: &str) -> OperationResult<Vec<Sidecar>>;
+
+    /// Attaches the data to the file under the name, removing it if the data is empty. Returns
+    /// all the data attached to the file afterwards.
+    async fn set_sidecar(
+        &mut self,
+        id: &str,
+        name: &str,
+        data: Bytes,
+    ) -> OperationResult<Vec<Sidecar>>;
+
+    /// Retrieves the blobs, calling `on_blob_received` with the content of each one as it
+    /// arrives.
+    async fn get_blobs(
+        &mut self,
+        ids: Vec<String>,
+        on_blob_received: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> OperationResult<Vec<(String, Bytes)>>;
+
+    /// Applies a single operation, returning the resulting entry of the file.
+    async fn request_apply_operation(
+        &mut self,
+        modify_operation: ModifyOperation,
+    ) -> OperationResult<DirEntity>;
+
+    /// Applies the journal, uploading the blobs along with it and calling `on_blob_sent` with
+    /// the content of each one as it is sent.
+    async fn apply_journal(
+        &mut self,
+        journal: Vec<ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+        blobs: Vec<Bytes>,
+        on_blob_sent: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> OperationResult<JournalApplyResult>;
+
+    /// Returns the statuses the operations of the journal would get if it was applied now,
+    /// without applying it.
+    async fn validate_journal(
+        &mut self,
+        journal: Vec<ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+    ) -> OperationResult<Vec<JournalOperationStatus>>;
+
+    /// Returns the IDs of the blobs the server does not have.
+    async fn get_server_missing_blobs(&mut self, ids: Vec<String>) -> OperationResult<Vec<String>>;
+}
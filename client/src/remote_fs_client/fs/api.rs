@@ -0,0 +1,597 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tokio::sync::RwLock;
+
+use offs::errors::{OperationError, OperationResult};
+use offs::names::normalize_name;
+use offs::store::blob_cache::BlobCache;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{Dehydration, DirEntity, DirUsage, FileMode, FileType, Store, SyncState};
+use offs::timespec::Timespec;
+use offs::ROOT_ID;
+
+use super::super::client::grpc_client::{RemoteFsGrpcClient, ShareLink, DEFAULT_RPC_TIMEOUT};
+use super::super::client::remote_client::RemoteFsClient;
+use super::{Export, IgnorePatterns, JournalReviewEntry, OffsFilesystem, SidecarEntry};
+use crate::events::{SyncEvent, SyncEvents};
+use crate::metrics::Metrics;
+use crate::server_address::ServerAddress;
+use crate::transfers::Transfers;
+
+/// Handle to a filesystem synchronized with the server, for the applications that access it
+/// directly instead of through a mount.
+///
+/// The files are addressed by their `/`-separated paths, relative to the root of the
+/// filesystem (or the share, if one is selected in the server address). The names are
+/// normalized to NFC, the same way the mounts do by default.
+///
+/// Like with a mount, the changes made while offline are stored in the journal of the cache
+/// and sent to the server on [`OffsClient::sync`].
+pub struct OffsClient {
+    fs: RwLock<OffsFilesystem>,
+    offline_mode: Arc<AtomicBool>,
+    events: Arc<SyncEvents>,
+}
+
+impl OffsClient {
+    /// Opens the cache at `cache_path` (creating it if needed) and connects to the server.
+    pub async fn open(
+        server: &ServerAddress,
+        cache_path: &Path,
+        token: Option<&str>,
+    ) -> OperationResult<Self> {
+        Self::open_with_store(server, Store::new_client(cache_path, None)?, token).await
+    }
+
+    /// Like [`OffsClient::open`], but the blobs and the journal stored in the cache are
+    /// encrypted with the given passphrase. An existing cache can only be opened this way if it
+    /// has been created encrypted.
+    pub async fn open_encrypted(
+        server: &ServerAddress,
+        cache_path: &Path,
+        token: Option<&str>,
+        passphrase: &str,
+    ) -> OperationResult<Self> {
+        let store = Store::new_client(cache_path, Some(passphrase))?;
+
+        Self::open_with_store(server, store, token).await
+    }
+
+    async fn open_with_store(
+        server: &ServerAddress,
+        store: Store<LocalTempIdGenerator>,
+        token: Option<&str>,
+    ) -> OperationResult<Self> {
+        let replica_id = store.get_replica_id()?;
+        let client =
+            RemoteFsGrpcClient::new(server, token, replica_id, Some(DEFAULT_RPC_TIMEOUT)).await?;
+
+        Self::with_client(client, store).await
+    }
+
+    /// Creates a handle talking to the server through the given client instead of connecting
+    /// to one, e.g. to run against a test double with [`Store::new_client_in_memory`].
+    pub async fn with_client(
+        client: impl RemoteFsClient + 'static,
+        store: Store<LocalTempIdGenerator>,
+    ) -> OperationResult<Self> {
+        let offline_mode = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(SyncEvents::new());
+
+        let fs = OffsFilesystem::new(
+            client,
+            offline_mode.clone(),
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(Mutex::new(String::new())),
+            StoreWrapper::new(store),
+            Arc::new(Metrics::new(Arc::new(BlobCache::new(0)))),
+            Arc::new(Transfers::new()),
+            events.clone(),
+        )
+        .await?;
+
+        Ok(Self {
+            fs: RwLock::new(fs),
+            offline_mode,
+            events,
+        })
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline_mode.load(Ordering::Relaxed)
+    }
+
+    /// Switches the offline mode. While offline, only the files already in the cache are
+    /// available and the modifications are only written to the journal.
+    pub fn set_offline(&self, offline: bool) {
+        if offline && !self.offline_mode.swap(offline, Ordering::Relaxed) {
+            self.events.emit(SyncEvent::GoneOffline);
+        }
+        self.offline_mode.store(offline, Ordering::Relaxed);
+    }
+
+    /// Calls the listener on every synchronization event from now on, such as a conflict.
+    pub fn subscribe(&self, listener: impl Fn(&SyncEvent) + Send + 'static) {
+        self.events.subscribe(listener);
+    }
+
+    /// Returns the file at the given path.
+    pub async fn stat(&self, path: &str) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+
+        resolve(&mut fs, path).await
+    }
+
+    /// Lists the contents of the directory at the given path.
+    pub async fn list(&self, path: &str) -> OperationResult<Vec<DirEntity>> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+
+        fs.list_files(&dirent.id).await
+    }
+
+    /// Retrieves the entries of the whole subtree of the directory at the given path, so that
+    /// it can be browsed offline. Returns the number of files retrieved.
+    pub async fn prefetch(&self, path: &str) -> OperationResult<usize> {
+        self.fs.write().await.prefetch(path).await
+    }
+
+    /// Returns the total size and number of the files in the subtree of the directory at the
+    /// given path, as stored on the server.
+    pub async fn dir_usage(&self, path: &str) -> OperationResult<DirUsage> {
+        self.fs.write().await.dir_usage(path).await
+    }
+
+    /// Creates a link giving read-only access to the file, or the directory tree, at the given
+    /// path over HTTP for `expires_in` seconds. The link shows the content stored on the
+    /// server, without the changes still waiting in the journal.
+    pub async fn create_share_link(
+        &self,
+        path: &str,
+        expires_in: u64,
+    ) -> OperationResult<ShareLink> {
+        self.fs
+            .write()
+            .await
+            .create_share_link(path, expires_in)
+            .await
+    }
+
+    /// Lists the data derived from the file at the given path, such as thumbnails, attached to
+    /// it by the clients.
+    pub async fn list_sidecars(&self, path: &str) -> OperationResult<Vec<SidecarEntry>> {
+        self.fs.write().await.list_sidecars(path).await
+    }
+
+    /// Returns the data attached to the file at the given path under the name.
+    pub async fn get_sidecar(&self, path: &str, name: &str) -> OperationResult<Bytes> {
+        self.fs.write().await.get_sidecar(path, name).await
+    }
+
+    /// Attaches the data to the file at the given path under the name, or removes it if
+    /// `data` is empty. The data can be at most one blob long.
+    pub async fn set_sidecar(&self, path: &str, name: &str, data: Bytes) -> OperationResult<()> {
+        self.fs.write().await.set_sidecar(path, name, data).await
+    }
+
+    /// Returns the paths of up to `limit` cached files (all of them if 0) whose names match the
+    /// glob pattern, or contain the pattern if it has no wildcards. Only the cache is searched,
+    /// so it works offline, but finds only the files retrieved before.
+    pub async fn locate(&self, pattern: &str, limit: u32) -> OperationResult<Vec<String>> {
+        self.fs.read().await.store.locate(pattern, limit)
+    }
+
+    /// Reads up to `size` bytes of the file, starting at `offset`. Fewer bytes are returned if
+    /// the end of the file is reached.
+    pub async fn read(&self, path: &str, offset: u64, size: u32) -> OperationResult<Bytes> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        if dirent.stat.file_type == FileType::Directory {
+            return Err(OperationError::is_a_directory(path));
+        }
+        if !dirent.is_up_to_date() {
+            fs.update_chunks(&dirent.id).await?;
+        }
+
+        let size = dirent.stat.size.saturating_sub(offset).min(size as u64) as u32;
+        fs.read(&dirent.id, offset as i64, size).await
+    }
+
+    /// Writes the data to the regular file at the given offset, extending the file if needed.
+    pub async fn write(&self, path: &str, offset: u64, data: &[u8]) -> OperationResult<()> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        match dirent.stat.file_type {
+            FileType::RegularFile => {}
+            FileType::Directory => return Err(OperationError::is_a_directory(path)),
+            _ => return Err(not_a_regular_file(path)),
+        }
+
+        let fh = fs.open_file_handler.open_file(dirent.id);
+        let result = match fs.write(fh, offset as i64, data.to_vec()).await {
+            Ok(()) => fs.flush_write_buffer(fh).await,
+            Err(e) => Err(e),
+        };
+        fs.open_file_handler.close_file(fh);
+
+        result
+    }
+
+    /// Creates an empty regular file at the given path.
+    pub async fn create_file(&self, path: &str, mode: FileMode) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let (parent, name) = resolve_new(&mut fs, path).await?;
+
+        fs.create_file(&parent.id, &name, FileType::RegularFile, mode, 0, None)
+            .await
+    }
+
+    pub async fn create_directory(&self, path: &str, mode: FileMode) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let (parent, name) = resolve_new(&mut fs, path).await?;
+
+        fs.create_directory(&parent.id, &name, mode, None).await
+    }
+
+    /// Removes the file or the empty directory at the given path.
+    pub async fn remove(&self, path: &str) -> OperationResult<()> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+
+        if dirent.stat.file_type == FileType::Directory {
+            fs.remove_directory(&dirent.id).await
+        } else {
+            fs.remove_file(&dirent.id).await
+        }
+    }
+
+    /// Moves the file, or the directory tree, at the given path to `new_path`, which must not
+    /// exist.
+    pub async fn rename(&self, path: &str, new_path: &str) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        let (parent, name) = resolve_new(&mut fs, new_path).await?;
+
+        fs.rename_file(&dirent.id, &parent.id, &name).await
+    }
+
+    /// Creates a symbolic link at the given path, pointing to `target`. The links are not
+    /// followed by the other methods.
+    pub async fn create_symlink(&self, path: &str, target: &str) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let (parent, name) = resolve_new(&mut fs, path).await?;
+
+        fs.create_symlink(&parent.id, &name, target, None).await
+    }
+
+    /// Changes the permission bits of the file at the given path. Like on Linux, the modes of
+    /// the symbolic links cannot be changed.
+    pub async fn set_mode(&self, path: &str, mode: FileMode) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        if dirent.stat.file_type == FileType::Symlink {
+            return Err(OperationError::invalid_argument(
+                "the mode of a symbolic link cannot be changed",
+            ));
+        }
+
+        fs.set_attributes(&dirent.id, Some(mode), None, None, None, None, None)
+            .await
+    }
+
+    /// Sets the access and modification times of the file at the given path, leaving the ones
+    /// that are `None`.
+    pub async fn set_times(
+        &self,
+        path: &str,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+    ) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+
+        fs.set_attributes(&dirent.id, None, None, None, None, atime, mtime)
+            .await
+    }
+
+    /// Shrinks or extends the regular file at the given path to `size` bytes, the new part
+    /// reading as zeros.
+    pub async fn truncate(&self, path: &str, size: u64) -> OperationResult<DirEntity> {
+        let mut fs = self.fs.write().await;
+        let dirent = resolve(&mut fs, path).await?;
+        if dirent.stat.file_type != FileType::RegularFile {
+            return Err(not_a_regular_file(path));
+        }
+
+        fs.set_attributes(&dirent.id, None, None, None, Some(size), None, None)
+            .await
+    }
+
+    /// Keeps the files created from now on whose names match the patterns only in the cache,
+    /// without sending them to the server.
+    pub async fn set_ignore_patterns(&self, patterns: IgnorePatterns) {
+        self.fs.write().await.set_ignore_patterns(patterns);
+    }
+
+    /// Removes the cached content of the file, or the files in the directory tree, at the given
+    /// path, keeping their entries. The content is retrieved again when they are read.
+    pub async fn dehydrate(&self, path: &str) -> OperationResult<Dehydration> {
+        self.fs.write().await.dehydrate(path).await
+    }
+
+    /// Copies the file, or the directory tree, at the given path into `target` on the local
+    /// filesystem, which must not exist yet.
+    pub async fn export(&self, path: &str, target: &Path) -> OperationResult<Export> {
+        self.fs.write().await.export(path, target).await
+    }
+
+    /// Excludes the directory at the given path from the synchronization: its contents are
+    /// removed from the cache and it stays empty until included again.
+    pub async fn exclude_from_sync(&self, path: &str) -> OperationResult<()> {
+        self.fs.write().await.exclude_from_sync(path).await
+    }
+
+    /// Includes the directory at the given path, excluded before, in the synchronization again.
+    pub async fn include_in_sync(&self, path: &str) -> OperationResult<()> {
+        self.fs.write().await.include_in_sync(path).await
+    }
+
+    /// Returns the paths of the directories excluded from the synchronization.
+    pub async fn excluded_from_sync(&self) -> OperationResult<Vec<String>> {
+        self.fs.read().await.store.get_excluded_from_sync()
+    }
+
+    /// Returns whether the file at the given path has been synchronized with the server.
+    pub async fn sync_state(&self, path: &str) -> OperationResult<SyncState> {
+        self.fs.read().await.sync_state(path)
+    }
+
+    /// Checks the changes made while offline against the server, without sending them, and
+    /// returns what would happen to each of them.
+    pub async fn review_journal(&self) -> OperationResult<Vec<JournalReviewEntry>> {
+        self.fs.write().await.review_journal().await
+    }
+
+    /// Sends the changes made while offline to the server, then refreshes the cached entries
+    /// of the files changed by others in the meantime.
+    pub async fn sync(&self) -> OperationResult<()> {
+        if self.is_offline() {
+            return Err(OperationError::offline("The client is currently offline"));
+        }
+
+        let mut fs = self.fs.write().await;
+        fs.apply_journal().await?;
+        fs.refresh_dirents().await
+    }
+}
+
+impl OffsFilesystem {
+    /// Retrieves the entries of the whole subtree of the directory at the given path, relative
+    /// to the root of the filesystem. Returns the number of files retrieved.
+    pub async fn prefetch(&mut self, path: &str) -> OperationResult<usize> {
+        let dirent = resolve(self, path).await?;
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+        if LocalTempIdGenerator::is_local_only_id(&dirent.id) {
+            // Everything inside is already in the cache
+            return Ok(0);
+        }
+
+        self.prefetch_tree(&dirent.id).await
+    }
+
+    /// Returns the usage of the directory at the given path, relative to the root of the
+    /// filesystem. The changes not sent to the server yet are not included.
+    pub async fn dir_usage(&mut self, path: &str) -> OperationResult<DirUsage> {
+        if self.is_offline() {
+            return Err(OperationError::offline("The client is currently offline"));
+        }
+
+        let dirent = resolve(self, path).await?;
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+        if LocalTempIdGenerator::is_local_id(&dirent.id)
+            || LocalTempIdGenerator::is_local_only_id(&dirent.id)
+        {
+            // The directory has not been created on the server (yet)
+            return Ok(DirUsage::default());
+        }
+
+        self.client.get_dir_usage(&dirent.id).await
+    }
+
+    /// Creates a share link to the file or directory at the given path, relative to the root of
+    /// the filesystem.
+    pub async fn create_share_link(
+        &mut self,
+        path: &str,
+        expires_in: u64,
+    ) -> OperationResult<ShareLink> {
+        if self.is_offline() {
+            return Err(OperationError::offline("The client is currently offline"));
+        }
+
+        let dirent = resolve(self, path).await?;
+        if LocalTempIdGenerator::is_local_id(&dirent.id) {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} has not been sent to the server yet",
+                path
+            )));
+        }
+        if LocalTempIdGenerator::is_local_only_id(&dirent.id) {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} is ignored and only stored locally",
+                path
+            )));
+        }
+
+        self.client.create_share_link(&dirent.id, expires_in).await
+    }
+
+    /// Removes the cached content of the file or directory tree at the given path, relative to
+    /// the root of the filesystem. The opened files and the ones with changes not sent to the
+    /// server yet keep their content.
+    pub async fn dehydrate(&mut self, path: &str) -> OperationResult<Dehydration> {
+        let dirent = resolve(self, path).await?;
+        let open_ids: HashSet<String> = self
+            .open_file_handler
+            .get_file_handles()
+            .into_iter()
+            .map(|fh| self.open_file_handler.get_id(fh))
+            .collect();
+
+        self.store.dehydrate(&dirent.id, &open_ids)
+    }
+
+    /// Excludes the directory at the given path, relative to the root of the filesystem, from
+    /// the synchronization. Fails if any file inside is opened or has changes not sent to the
+    /// server yet.
+    pub async fn exclude_from_sync(&mut self, path: &str) -> OperationResult<()> {
+        let dirent = self.resolve_synced_dir(path).await?;
+
+        for fh in self.open_file_handler.get_file_handles() {
+            let mut id = self.open_file_handler.get_id(fh);
+            while id != ROOT_ID {
+                id = self.store.query_file(&id)?.parent;
+                if id == dirent.id {
+                    return Err(OperationError::invalid_argument(&format!(
+                        "{} contains opened files",
+                        path
+                    )));
+                }
+            }
+        }
+
+        self.store.exclude_from_sync(&dirent.id)
+    }
+
+    /// Includes the directory at the given path, relative to the root of the filesystem, in the
+    /// synchronization again. Its contents are retrieved when it is listed.
+    pub async fn include_in_sync(&mut self, path: &str) -> OperationResult<()> {
+        let dirent = self.resolve_synced_dir(path).await?;
+        if !self.store.is_excluded_from_sync(&dirent.id)? {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} is not excluded from the synchronization",
+                path
+            )));
+        }
+
+        self.store.include_in_sync(&dirent.id)
+    }
+
+    /// Returns whether the file at the given path, relative to the root of the filesystem, has
+    /// been synchronized with the server. Only the cache is looked at, so this does not block
+    /// when the server is slow to respond.
+    pub fn sync_state(&self, path: &str) -> OperationResult<SyncState> {
+        let mut dirent = self.store.query_file(ROOT_ID)?;
+        let mut canonical_path = String::new();
+        for name in split_path(path) {
+            let name = normalize_name(name);
+            dirent = self
+                .store
+                .try_query_file_by_name(&dirent.id, &name)?
+                .ok_or_else(|| OperationError::file_does_not_exist(path))?;
+            canonical_path.push('/');
+            canonical_path.push_str(&name);
+        }
+        if canonical_path.is_empty() {
+            canonical_path.push('/');
+        }
+
+        Ok(self
+            .store
+            .get_sync_states()?
+            .remove(&canonical_path)
+            .unwrap_or(SyncState::Synced))
+    }
+
+    async fn resolve_synced_dir(&mut self, path: &str) -> OperationResult<DirEntity> {
+        let dirent = resolve(self, path).await?;
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+        if dirent.id == ROOT_ID {
+            return Err(OperationError::invalid_argument(
+                "the root directory cannot be excluded from the synchronization",
+            ));
+        }
+        if LocalTempIdGenerator::is_local_id(&dirent.id)
+            || LocalTempIdGenerator::is_local_only_id(&dirent.id)
+        {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} is not stored on the server",
+                path
+            )));
+        }
+
+        Ok(dirent)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|x| !x.is_empty())
+}
+
+async fn lookup(
+    fs: &mut OffsFilesystem,
+    parent_id: &str,
+    name: &str,
+) -> OperationResult<Option<DirEntity>> {
+    // Make sure the file entries are up to date
+    fs.retrieve_dir(parent_id).await?;
+
+    fs.store
+        .try_query_file_by_name(parent_id, &normalize_name(name))
+}
+
+pub(super) async fn resolve(fs: &mut OffsFilesystem, path: &str) -> OperationResult<DirEntity> {
+    let mut dirent = fs.store.query_file(ROOT_ID)?;
+    for name in split_path(path) {
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(OperationError::not_a_directory(path));
+        }
+        dirent = lookup(fs, &dirent.id, name)
+            .await?
+            .ok_or_else(|| OperationError::file_does_not_exist(path))?;
+    }
+
+    Ok(dirent)
+}
+
+fn not_a_regular_file(path: &str) -> OperationError {
+    OperationError::invalid_argument(&format!("{} is not a regular file", path))
+}
+
+/// Returns the directory a file is to be created in, along with the name of the file.
+async fn resolve_new(fs: &mut OffsFilesystem, path: &str) -> OperationResult<(DirEntity, String)> {
+    let path = path.trim_end_matches('/');
+    let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(OperationError::invalid_argument(
+            "the path has no file name",
+        ));
+    }
+
+    let parent = resolve(fs, parent_path).await?;
+    if parent.stat.file_type != FileType::Directory {
+        return Err(OperationError::not_a_directory(parent_path));
+    }
+    if lookup(fs, &parent.id, name).await?.is_some() {
+        return Err(OperationError::invalid_argument(&format!(
+            "{} already exists",
+            path
+        )));
+    }
+
+    Ok((parent, normalize_name(name).into_owned()))
+}
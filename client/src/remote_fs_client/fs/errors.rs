@@ -1,16 +1,37 @@
-use libc::{c_int, EBADFD, EEXIST, EINVAL, ENOENT, ENOTEMPTY, ENOTRECOVERABLE, ETIMEDOUT};
+use libc::{
+    c_int, E2BIG, EACCES, EAGAIN, EDQUOT, EEXIST, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT,
+    ENOSPC, ENOTDIR, ENOTEMPTY, EPERM, EPROTONOSUPPORT, ESTALE, ETIMEDOUT, EXDEV,
+};
 
 use offs::errors::{OperationError, OperationErrorType};
 
+use super::platform::EBADFD;
+
 pub fn to_os_error(operation_error: &OperationError) -> c_int {
     match operation_error.error_type {
-        OperationErrorType::DatabaseError => ENOTRECOVERABLE,
+        OperationErrorType::DatabaseError => EIO,
         OperationErrorType::DirectoryNotEmpty => ENOTEMPTY,
         OperationErrorType::ConflictedFile => EEXIST,
         OperationErrorType::InvalidContentVersion => EBADFD,
-        OperationErrorType::BlobDoesNotExist => ENOENT,
+        OperationErrorType::BlobDoesNotExist => EIO,
         OperationErrorType::Offline => ETIMEDOUT,
         OperationErrorType::FileDoesNotExist => ENOENT,
         OperationErrorType::InvalidUnicode => EINVAL,
+        OperationErrorType::ShareDoesNotExist => ENOENT,
+        OperationErrorType::Unauthenticated => EACCES,
+        OperationErrorType::InvalidAcl => EINVAL,
+        OperationErrorType::NoSpace => ENOSPC,
+        OperationErrorType::RenameIntoDescendant => EINVAL,
+        OperationErrorType::InvalidArgument => EINVAL,
+        OperationErrorType::UnsupportedProtocol => EPROTONOSUPPORT,
+        OperationErrorType::RequestTooLarge => E2BIG,
+        OperationErrorType::RateLimited => EAGAIN,
+        OperationErrorType::QuotaExceeded => EDQUOT,
+        OperationErrorType::CrossDevice => EXDEV,
+        OperationErrorType::PermissionDenied => EPERM,
+        OperationErrorType::NameTooLong => ENAMETOOLONG,
+        OperationErrorType::NotADirectory => ENOTDIR,
+        OperationErrorType::IsADirectory => EISDIR,
+        OperationErrorType::StaleHandle => ESTALE,
     }
 }
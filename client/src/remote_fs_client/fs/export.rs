@@ -0,0 +1,155 @@
+use std::fs::{self, File, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+
+use nix::sys::stat::{utimensat, UtimensatFlags};
+use nix::sys::time::TimeSpec;
+use tracing::debug;
+
+use offs::errors::{OperationError, OperationResult};
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::{DirEntity, FileType};
+use offs::BLOB_SIZE;
+
+use super::api::resolve;
+use super::OffsFilesystem;
+
+/// How much of a file is read at once; the missing blobs of each piece are retrieved in a
+/// single request.
+const EXPORT_READ_SIZE: usize = 64 * BLOB_SIZE;
+
+/// Files copied out of the filesystem by [`OffsFilesystem::export`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Export {
+    pub files: u64,
+    /// Total size of the regular files
+    pub size: u64,
+}
+
+impl OffsFilesystem {
+    /// Copies the file or directory tree at the given path, relative to the root of the
+    /// filesystem, to `target`, which must not exist yet. The modes and modification times are
+    /// kept; the special files (pipes, sockets and devices) are skipped.
+    ///
+    /// The entries of the whole tree are retrieved at once, and the missing blobs of the files
+    /// in large batches, rather than one request per directory and read.
+    pub async fn export(&mut self, path: &str, target: &Path) -> OperationResult<Export> {
+        let dirent = resolve(self, path).await?;
+        if target.symlink_metadata().is_ok() {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} already exists",
+                target.display()
+            )));
+        }
+        let prefetched = dirent.stat.file_type == FileType::Directory
+            && !self.is_offline()
+            && !LocalTempIdGenerator::is_local_id(&dirent.id)
+            && !LocalTempIdGenerator::is_local_only_id(&dirent.id);
+        if prefetched {
+            self.prefetch_tree(&dirent.id).await?;
+        }
+
+        let mut export = Export::default();
+        let mut directories = Vec::new();
+        let mut queue = vec![(dirent, target.to_owned())];
+        while let Some((dirent, target)) = queue.pop() {
+            match dirent.stat.file_type {
+                FileType::Directory => {
+                    fs::create_dir(&target).map_err(|e| local_error(&target, e))?;
+                    if !prefetched {
+                        self.retrieve_dir(&dirent.id).await?;
+                    }
+                    for child in self.store.list_files(&dirent.id)? {
+                        let child_target = target.join(&child.name);
+                        queue.push((child, child_target));
+                    }
+                    directories.push((dirent, target));
+                }
+                FileType::RegularFile => {
+                    self.export_file(&dirent, &target).await?;
+                    set_times(&dirent, &target)?;
+                    export.size += dirent.stat.size;
+                }
+                FileType::Symlink => {
+                    self.export_symlink(&dirent, &target).await?;
+                }
+                _ => {
+                    debug!("Skipping the special file {}", target.display());
+                    continue;
+                }
+            }
+            export.files += 1;
+        }
+
+        // The times of the directories change when their contents are created
+        for (dirent, target) in directories.iter().rev() {
+            fs::set_permissions(target, Permissions::from_mode(dirent.stat.mode as u32))
+                .map_err(|e| local_error(target, e))?;
+            set_times(dirent, target)?;
+        }
+
+        Ok(export)
+    }
+
+    async fn export_file(&mut self, dirent: &DirEntity, target: &Path) -> OperationResult<()> {
+        if !dirent.is_up_to_date() {
+            self.update_chunks(&dirent.id).await?;
+        }
+
+        let mut file = File::create(target).map_err(|e| local_error(target, e))?;
+        let mut offset = 0;
+        while offset < dirent.stat.size {
+            let size = (dirent.stat.size - offset).min(EXPORT_READ_SIZE as u64) as u32;
+            let data = self.read(&dirent.id, offset as i64, size).await?;
+            if data.is_empty() {
+                break;
+            }
+            file.write_all(&data).map_err(|e| local_error(target, e))?;
+            offset += data.len() as u64;
+        }
+        file.set_permissions(Permissions::from_mode(dirent.stat.mode as u32))
+            .map_err(|e| local_error(target, e))?;
+
+        Ok(())
+    }
+
+    async fn export_symlink(&mut self, dirent: &DirEntity, target: &Path) -> OperationResult<()> {
+        if !dirent.is_up_to_date() {
+            self.update_chunks(&dirent.id).await?;
+        }
+
+        let data = self.read(&dirent.id, 0, dirent.stat.size as u32).await?;
+        let link =
+            String::from_utf8(data.to_vec()).map_err(|_| OperationError::invalid_unicode())?;
+        symlink(link, target).map_err(|e| local_error(target, e))?;
+        set_times(dirent, target)
+    }
+}
+
+fn set_times(dirent: &DirEntity, target: &Path) -> OperationResult<()> {
+    let to_timespec = |x: offs::timespec::Timespec| {
+        TimeSpec::from(libc::timespec {
+            tv_sec: x.sec,
+            tv_nsec: x.nsec as i64,
+        })
+    };
+
+    utimensat(
+        None,
+        target,
+        &to_timespec(dirent.stat.atim),
+        &to_timespec(dirent.stat.mtim),
+        UtimensatFlags::NoFollowSymlink,
+    )
+    .map_err(|e| local_error(target, e.into()))
+}
+
+fn local_error(path: &Path, error: io::Error) -> OperationError {
+    let message = format!("{}: {}", path.display(), error);
+
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => OperationError::permission_denied(&message),
+        _ => OperationError::invalid_argument(&message),
+    }
+}
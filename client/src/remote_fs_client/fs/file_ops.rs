@@ -1,17 +1,28 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::atomic::Ordering;
 
+use bytes::Bytes;
 use prost::Message;
+use tracing::{error, info};
 
-use offs::modify_op::ModifyOperation;
+use offs::acl::AclType;
+use offs::modify_op::{ModifyOperation, ModifyOperationContent};
 use offs::modify_op_handler::OperationApplier;
 use offs::proto::filesystem as proto_types;
-use offs::store::{DirEntity, FileDev, FileMode, FileType};
+use offs::protocol::FEATURE_LIST_PAGES;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::{DirEntity, FileDev, FileMode, FileOwner, FileType, WriteIntent};
 
 use super::super::client::modify_op_builder::ModifyOpBuilder;
 use super::write_buffer::WriteOperation;
 use super::OffsFilesystem;
-use offs::errors::{OperationError, OperationResult};
+use offs::errors::{OperationError, OperationErrorType, OperationResult};
 use offs::timespec::Timespec;
+use offs::BLOB_SIZE;
+
+/// Number of the files of a directory retrieved from the server at once.
+const LIST_PAGE_SIZE: u32 = 1000;
 
 impl OffsFilesystem {
     // File operations
@@ -26,13 +37,130 @@ impl OffsFilesystem {
     pub(super) async fn flush_write_buffer(&mut self, fh: u64) -> OperationResult<()> {
         let (id, operations) = self.open_file_handler.flush(fh);
         for op in operations.into_iter() {
-            self.do_single_write(&id, op).await?;
+            if let Err(e) = self.do_single_write(&id, op).await {
+                // The buffered data is lost at this point, so make sure the error reaches
+                // whoever closes the file, even if the flush was triggered by somebody else
+                self.open_file_handler.set_error(fh, e.clone());
+                self.store.remove_write_intents(fh)?;
+                return Err(e);
+            }
+        }
+        self.store.remove_write_intents(fh)?;
+
+        Ok(())
+    }
+
+    /// Writes the data left buffered when the client exited, as recorded in the write
+    /// intents, through the handles it has been written with.
+    pub(super) async fn replay_write_intents(&mut self) -> OperationResult<()> {
+        let mut handles: BTreeMap<u64, (String, Vec<WriteOperation>)> = BTreeMap::new();
+        for intent in self.store.get_write_intents()? {
+            let WriteIntent {
+                handle,
+                file,
+                offset,
+                data,
+            } = intent;
+            handles
+                .entry(handle)
+                .or_insert_with(|| (file, Vec::new()))
+                .1
+                .push(WriteOperation::new(offset as usize, data));
+        }
+
+        for (fh, (id, operations)) in handles {
+            info!(
+                "Writing {} buffered writes to {} left by the previous run",
+                operations.len(),
+                id
+            );
+            self.open_file_handler.reopen_file(fh, id.clone());
+            for op in operations {
+                let _ = self.open_file_handler.write(fh, op);
+            }
+            if let Err(e) = self.flush_write_buffer(fh).await {
+                error!("Could not write the data buffered for {}: {}", id, e);
+            }
+            self.open_file_handler.close_file(fh);
+        }
+
+        Ok(())
+    }
+
+    /// Pushes all the pending changes of the file to the server, reporting any error
+    /// encountered when writing the data buffered for the handle.
+    pub(super) async fn flush_file(&mut self, fh: u64) -> OperationResult<()> {
+        let id = self.open_file_handler.get_id(fh);
+        for other_fh in self.open_file_handler.get_file_handles_for(&id) {
+            // The errors are reported to the handles they were encountered for
+            let _ = self.flush_write_buffer(other_fh).await;
+        }
+        if let Some(e) = self.open_file_handler.take_error(fh) {
+            return Err(e);
+        }
+
+        // Operations which could not be sent to the server are left in the journal
+        if !self.is_offline() && self.store.is_file_in_journal(&id)? {
+            self.apply_journal().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes the changes of the file durable. While online, this waits for the server to
+    /// apply them, failing if it rejects any; offline, it is enough for them to be stored in
+    /// the journal.
+    pub(super) async fn sync_file(&mut self, fh: u64) -> OperationResult<()> {
+        let quarantined = self.store.get_quarantined_journal_size()?;
+        self.flush_file(fh).await?;
+
+        self.check_no_rejected_operations(quarantined)
+    }
+
+    /// Makes the changes of the entries of the directory durable, like [`Self::sync_file`].
+    pub(super) async fn sync_directory(&mut self) -> OperationResult<()> {
+        // The creations of the entries are journaled under the ID of the directory, but their
+        // removals and renames under their own IDs, so the whole journal is applied
+        if self.is_offline() || self.store.get_journal_size()? == 0 {
+            return Ok(());
+        }
+
+        let quarantined = self.store.get_quarantined_journal_size()?;
+        self.apply_journal().await?;
+
+        self.check_no_rejected_operations(quarantined)
+    }
+
+    fn check_no_rejected_operations(&self, quarantined_before: u64) -> OperationResult<()> {
+        let quarantined = self.store.get_quarantined_journal_size()?;
+        if quarantined > quarantined_before {
+            return Err(OperationError::new(
+                OperationErrorType::DatabaseError,
+                format!(
+                    "The server rejected {} pending operations, which have been quarantined",
+                    quarantined - quarantined_before
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the write buffers of all the opened instances of the file. The page cache writes
+    /// back the data of the memory mappings through any file handle, so flushing just the one
+    /// used by the caller is not enough to make sure the file is up to date.
+    pub(super) async fn flush_file_write_buffers(&mut self, id: &str) -> OperationResult<()> {
+        for fh in self.open_file_handler.get_file_handles_for(id) {
+            self.flush_write_buffer(fh).await?;
         }
 
         Ok(())
     }
 
     async fn do_single_write(&mut self, id: &str, op: WriteOperation) -> OperationResult<()> {
+        self.retrieve_blobs_for_write(id, op.offset as u64, op.data.len() as u64)
+            .await?;
+
         let dirent = self.store.query_file(id)?;
         let operation = ModifyOpBuilder::make_write_op(&dirent, op.offset as i64, op.data);
 
@@ -42,31 +170,176 @@ impl OffsFilesystem {
         Ok(())
     }
 
+    /// Retrieves the blobs needed to write `length` bytes at `offset`: the ones of the chunks
+    /// written partially and of the last chunk if the file grows.
+    async fn retrieve_blobs_for_write(
+        &mut self,
+        id: &str,
+        offset: u64,
+        length: u64,
+    ) -> OperationResult<()> {
+        let missing_blobs = self.store.get_missing_blobs_for_write(id, offset, length)?;
+        self.retrieve_missing_blobs(id, missing_blobs).await
+    }
+
     // Read
     pub(super) async fn list_files(&mut self, id: &str) -> OperationResult<Vec<DirEntity>> {
+        self.retrieve_dir(id).await?;
+
+        Ok(self.store.list_files(id)?)
+    }
+
+    /// Makes sure the cached entries of the directory are up to date. The servers supporting
+    /// it are asked for the entries in pages, so that the huge directories are never held in
+    /// memory as a whole.
+    pub(super) async fn retrieve_dir(&mut self, id: &str) -> OperationResult<()> {
+        // The directories not yet sent to the server only have the entries in the cache
+        if LocalTempIdGenerator::is_local_id(id)
+            || LocalTempIdGenerator::is_local_only_id(id)
+            || self.store.is_excluded_from_sync(id)?
+        {
+            return Ok(());
+        }
         if self.is_offline() {
             let dirent = self.store.query_file(id)?;
             if !dirent.is_retrieved() {
                 err_offline!();
             }
 
-            return Ok(self.store.list_files(id)?);
+            return Ok(());
+        }
+
+        if !self.client.has_feature(FEATURE_LIST_PAGES) {
+            let mut items = self.client.list_files(id).await?;
+
+            let transaction = self.store.transaction();
+            for dirent in &mut items {
+                self.add_dirent(dirent)?;
+            }
+            self.store.update_retrieved_version(id)?;
+
+            let children_ids = items.iter().map(|x| &x.id);
+            self.store.remove_remaining_files(id, children_ids)?;
+
+            transaction.commit()?;
+            return Ok(());
         }
 
-        let mut items = self.client.list_files(id).await?;
+        let mut start_after = String::new();
+        loop {
+            let mut items = self
+                .client
+                .list_files_page(id, &start_after, LIST_PAGE_SIZE)
+                .await?;
+            let is_last = items.len() < LIST_PAGE_SIZE as usize;
+
+            let transaction = self.store.transaction();
+            for dirent in &mut items {
+                self.add_dirent(dirent)?;
+            }
+
+            // The files between the previous page and the end of this one are the ones which
+            // the server did not return
+            let end = if is_last {
+                None
+            } else {
+                items.last().map(|x| x.name.clone())
+            };
+            let children_ids = items.iter().map(|x| &x.id);
+            self.store.remove_remaining_files_in_page(
+                id,
+                &start_after,
+                end.as_deref(),
+                children_ids,
+            )?;
+
+            if is_last {
+                self.store.update_retrieved_version(id)?;
+                transaction.commit()?;
+                return Ok(());
+            }
+            transaction.commit()?;
+
+            start_after = end.unwrap();
+        }
+    }
+
+    /// Retrieves the entries of all the directories in the subtree at once, so that browsing
+    /// it later does not need to contact the server. Returns the number of files retrieved.
+    pub(super) async fn prefetch_tree(&mut self, id: &str) -> OperationResult<usize> {
+        check_online!(self);
+        if self.store.is_excluded_from_sync(id)? {
+            return Ok(0);
+        }
+
+        let mut items = self.client.list_tree(id).await?;
+        let excluded = self.drop_excluded_from_sync(&mut items)?;
+
+        let mut children: HashMap<String, Vec<String>> = HashMap::new();
+        children.insert(id.to_owned(), Vec::new());
+        for dirent in &items {
+            if dirent.stat.file_type == FileType::Directory && !excluded.contains(&dirent.id) {
+                children.entry(dirent.id.clone()).or_default();
+            }
+            children
+                .entry(dirent.parent.clone())
+                .or_default()
+                .push(dirent.id.clone());
+        }
 
         let transaction = self.store.transaction();
         for dirent in &mut items {
             self.add_dirent(dirent)?;
         }
-        self.store.update_retrieved_version(id)?;
+        for (dir_id, children_ids) in &children {
+            self.store.update_retrieved_version(dir_id)?;
+            self.store.remove_remaining_files(dir_id, children_ids)?;
+        }
+        transaction.commit()?;
 
-        let children_ids = items.iter().map(|x| &x.id);
-        self.store.remove_remaining_files(id, children_ids)?;
+        Ok(items.len())
+    }
 
-        transaction.commit()?;
+    /// Drops the files inside the directories excluded from the synchronization from the
+    /// entries of a subtree. Returns the IDs of the excluded directories.
+    fn drop_excluded_from_sync(
+        &self,
+        items: &mut Vec<DirEntity>,
+    ) -> OperationResult<HashSet<String>> {
+        let parents: HashMap<String, String> = items
+            .iter()
+            .map(|x| (x.id.clone(), x.parent.clone()))
+            .collect();
+        let mut excluded = HashSet::new();
+        for dirent in items.iter() {
+            if dirent.stat.file_type == FileType::Directory
+                && self.store.is_excluded_from_sync(&dirent.id)?
+            {
+                excluded.insert(dirent.id.clone());
+            }
+        }
+        if excluded.is_empty() {
+            return Ok(excluded);
+        }
 
-        Ok(items)
+        let is_below_excluded = |id: &str| {
+            let mut parent = parents.get(id);
+            while let Some(id) = parent {
+                if excluded.contains(id) {
+                    return true;
+                }
+                parent = parents.get(id);
+            }
+            false
+        };
+        let dropped: HashSet<String> = items
+            .iter()
+            .filter(|x| is_below_excluded(&x.id))
+            .map(|x| x.id.clone())
+            .collect();
+        items.retain(|x| !dropped.contains(&x.id));
+
+        Ok(excluded)
     }
 
     pub(super) async fn read(
@@ -74,9 +347,14 @@ impl OffsFilesystem {
         id: &str,
         offset: i64,
         size: u32,
-    ) -> OperationResult<Vec<u8>> {
-        let missing_blobs = self.store.get_missing_blobs_for_read(id, offset, size)?;
-        self.retrieve_missing_blobs(missing_blobs).await?;
+    ) -> OperationResult<Bytes> {
+        let chunks = self.store.get_blobs_for_read(id, offset, size)?;
+        let missing_blobs = self.store.get_missing_blobs(&chunks)?;
+        self.metrics.add_cache_lookups(
+            (chunks.len() - missing_blobs.len()) as u64,
+            missing_blobs.len() as u64,
+        );
+        self.retrieve_missing_blobs(id, missing_blobs).await?;
 
         Ok(self.store.read(id, offset, size)?)
     }
@@ -91,12 +369,34 @@ impl OffsFilesystem {
         &mut self,
         operation: ModifyOperation,
     ) -> OperationResult<DirEntity> {
+        let result = self.try_perform_operation(operation).await;
+        if let Err(e) = &result {
+            self.set_last_error(e);
+        }
+
+        result
+    }
+
+    async fn try_perform_operation(
+        &mut self,
+        operation: ModifyOperation,
+    ) -> OperationResult<DirEntity> {
+        if self.is_local_only_operation(&operation) {
+            return self.perform_local_operation(operation);
+        }
         if self.should_flush_journal.load(Ordering::Relaxed) {
             self.apply_journal().await?;
         }
 
         let id = operation.id.clone();
+        // The server responds to the removals with the entry of the removed file
+        let removes_file = matches!(
+            operation.operation,
+            ModifyOperationContent::RemoveFileOperation(_)
+                | ModifyOperationContent::RemoveDirectoryOperation(_)
+        );
         let serialized_op = proto_types::ModifyOperation::from(operation.clone()).encode_to_vec();
+        let changed_dirs = self.get_changed_directories(&operation)?;
 
         let transaction = self.store.transaction();
 
@@ -109,12 +409,17 @@ impl OffsFilesystem {
             let mut dirent = self.client.request_apply_operation(operation).await?;
             self.store.remove_journal_item(journal_entry_id)?;
 
-            if !dirent.id.is_empty() {
+            if !dirent.id.is_empty() && !removes_file {
+                // Only the created files are assigned new IDs
                 if new_id != dirent.id {
-                    self.store.change_id(&new_id, &dirent.id)?;
+                    self.store.replace_temp_id(&new_id, &dirent.id)?;
+                    self.open_file_handler.change_id(&new_id, &dirent.id);
                 }
                 self.add_dirent(&mut dirent)?;
             }
+            for dir_id in &changed_dirs {
+                self.store.increment_directory_version(dir_id)?;
+            }
 
             dirent
         };
@@ -124,6 +429,129 @@ impl OffsFilesystem {
         Ok(dirent)
     }
 
+    /// Returns the directories whose entries the operation changes, which the server bumps the
+    /// versions of.
+    fn get_changed_directories(&self, operation: &ModifyOperation) -> OperationResult<Vec<String>> {
+        let dirs = match &operation.operation {
+            ModifyOperationContent::CreateFileOperation(_)
+            | ModifyOperationContent::CreateSymlinkOperation(_)
+            | ModifyOperationContent::CreateDirectoryOperation(_) => vec![operation.id.clone()],
+            ModifyOperationContent::RemoveFileOperation(_)
+            | ModifyOperationContent::RemoveDirectoryOperation(_) => {
+                vec![self.store.query_file(&operation.id)?.parent]
+            }
+            ModifyOperationContent::RenameOperation(op) => {
+                let parent = self.store.query_file(&operation.id)?.parent;
+                if parent == op.new_parent {
+                    vec![parent]
+                } else {
+                    vec![parent, op.new_parent.clone()]
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(dirs)
+    }
+
+    /// Returns whether the operation only concerns the files kept in the cache: the ones
+    /// matching the ignore patterns and everything inside the ignored directories.
+    fn is_local_only_operation(&self, operation: &ModifyOperation) -> bool {
+        if LocalTempIdGenerator::is_local_only_id(&operation.id) {
+            return true;
+        }
+
+        match &operation.operation {
+            ModifyOperationContent::CreateFileOperation(op) => self
+                .ignore_patterns
+                .is_ignored(&op.name, op.file_type == FileType::Directory),
+            ModifyOperationContent::CreateSymlinkOperation(op) => {
+                self.ignore_patterns.is_ignored(&op.name, false)
+            }
+            ModifyOperationContent::CreateDirectoryOperation(op) => {
+                self.ignore_patterns.is_ignored(&op.name, true)
+            }
+            _ => false,
+        }
+    }
+
+    /// Applies the operation to the cache only, without journaling it.
+    fn perform_local_operation(
+        &mut self,
+        operation: ModifyOperation,
+    ) -> OperationResult<DirEntity> {
+        let creates_file = matches!(
+            operation.operation,
+            ModifyOperationContent::CreateFileOperation(_)
+                | ModifyOperationContent::CreateSymlinkOperation(_)
+                | ModifyOperationContent::CreateDirectoryOperation(_)
+        );
+        let removes_file = matches!(
+            operation.operation,
+            ModifyOperationContent::RemoveFileOperation(_)
+                | ModifyOperationContent::RemoveDirectoryOperation(_)
+        );
+
+        let transaction = self.store.transaction();
+
+        // Like the server, respond to the removals with the entry of the removed file
+        let removed = if removes_file {
+            Some(self.store.query_file(&operation.id)?)
+        } else {
+            None
+        };
+        let mut new_id = self.apply_operation(&operation)?;
+        if creates_file {
+            new_id = self.store.make_local_only(&new_id)?;
+        }
+        let dirent = match removed {
+            Some(dirent) => dirent,
+            None => self.store.query_file(&new_id)?,
+        };
+
+        transaction.commit()?;
+
+        Ok(dirent)
+    }
+
+    /// Starts synchronizing the local-only file once it no longer matches the ignore patterns,
+    /// by journaling its creation, like for a file created while offline.
+    async fn promote_local_only_file(&mut self, id: &str) -> OperationResult<DirEntity> {
+        // Otherwise the parent directory could get its ID on the server in the meantime
+        if !self.is_offline() && self.store.get_journal_size()? > 0 {
+            self.apply_journal().await?;
+        }
+
+        let transaction = self.store.transaction();
+
+        let new_id = self.store.assign_temp_id(id)?;
+        self.open_file_handler.change_id(id, &new_id);
+        let dirent = self.store.query_file(&new_id)?;
+        let parent_dirent = self.store.query_file(&dirent.parent)?;
+
+        let create_op = ModifyOpBuilder::make_recreate_file_op(&parent_dirent, &dirent);
+        let create_op_proto: proto_types::ModifyOperation = create_op.into();
+        self.store
+            .add_journal_entry(&dirent.parent, &create_op_proto.encode_to_vec())?;
+
+        let attributes_op = ModifyOpBuilder::make_reset_attributes_op(&dirent);
+        let attributes_op_proto: proto_types::ModifyOperation = attributes_op.into();
+        self.store
+            .add_journal_entry(&new_id, &attributes_op_proto.encode_to_vec())?;
+
+        transaction.commit()?;
+
+        if self.is_offline() {
+            return Ok(dirent);
+        }
+        self.apply_journal().await?;
+
+        // The file has been assigned its ID on the server by now
+        Ok(self
+            .store
+            .query_file_by_name(&dirent.parent, &dirent.name)?)
+    }
+
     // Create
     pub(super) async fn create_file(
         &mut self,
@@ -132,10 +560,11 @@ impl OffsFilesystem {
         file_type: FileType,
         mode: FileMode,
         dev: FileDev,
+        owner: Option<FileOwner>,
     ) -> OperationResult<DirEntity> {
         let parent_dirent = self.store.query_file(parent_id)?;
         let operation =
-            ModifyOpBuilder::make_create_file_op(&parent_dirent, name, file_type, mode, dev);
+            ModifyOpBuilder::make_create_file_op(&parent_dirent, name, file_type, mode, dev, owner);
 
         let mut dirent = self.perform_operation(operation).await?;
         self.add_dirent(&mut dirent)?;
@@ -148,9 +577,10 @@ impl OffsFilesystem {
         parent_id: &str,
         name: &str,
         link: &str,
+        owner: Option<FileOwner>,
     ) -> OperationResult<DirEntity> {
         let parent_dirent = self.store.query_file(parent_id)?;
-        let operation = ModifyOpBuilder::make_create_symlink_op(&parent_dirent, name, link);
+        let operation = ModifyOpBuilder::make_create_symlink_op(&parent_dirent, name, link, owner);
 
         let mut dirent = self.perform_operation(operation).await?;
         self.add_dirent(&mut dirent)?;
@@ -163,9 +593,11 @@ impl OffsFilesystem {
         parent_id: &str,
         name: &str,
         mode: FileMode,
+        owner: Option<FileOwner>,
     ) -> OperationResult<DirEntity> {
         let parent_dirent = self.store.query_file(parent_id)?;
-        let operation = ModifyOpBuilder::make_create_directory_op(&parent_dirent, name, mode);
+        let operation =
+            ModifyOpBuilder::make_create_directory_op(&parent_dirent, name, mode, owner);
 
         let mut dirent = self.perform_operation(operation).await?;
         self.add_dirent(&mut dirent)?;
@@ -178,7 +610,52 @@ impl OffsFilesystem {
         let dirent = self.store.query_file(id)?;
         let operation = ModifyOpBuilder::make_remove_file_op(&dirent);
 
-        self.perform_operation(operation).await?;
+        if self.open_file_handler.get_file_handles_for(id).is_empty() {
+            self.perform_operation(operation).await?;
+            return Ok(());
+        }
+
+        // The opened file stays readable and writable until it is closed, through a copy kept
+        // only in the cache
+        let detached_id = self.detach_open_file(id).await?;
+        if let Err(e) = self.perform_operation(operation).await {
+            let transaction = self.store.transaction();
+            self.store.move_open_file(&detached_id, id)?;
+            self.store.forget_file(&detached_id)?;
+            transaction.commit()?;
+            self.open_file_handler.change_id(&detached_id, id);
+
+            return Err(e);
+        }
+        self.open_file_handler.mark_unlinked(&detached_id);
+
+        Ok(())
+    }
+
+    /// Moves the opened file over to a copy outside of the directory tree, which the file can
+    /// be removed without. Returns the ID of the copy.
+    async fn detach_open_file(&mut self, id: &str) -> OperationResult<String> {
+        // Nothing can be retrieved once the file is gone from the server
+        if !LocalTempIdGenerator::is_local_only_id(id) && !self.is_offline() {
+            let missing_blobs = self.store.get_missing_blobs(self.store.get_chunks(id)?)?;
+            self.retrieve_missing_blobs(id, missing_blobs).await?;
+        }
+
+        let transaction = self.store.transaction();
+        let detached_id = self.store.detach_file(id)?;
+        self.store.move_open_file(id, &detached_id)?;
+        transaction.commit()?;
+        self.open_file_handler.change_id(id, &detached_id);
+
+        Ok(detached_id)
+    }
+
+    /// Closes the handle, removing the file from the cache if it has been removed while opened
+    /// and the handle was the last one.
+    pub(super) fn close_file(&mut self, fh: u64) -> OperationResult<()> {
+        if let Some(id) = self.open_file_handler.close_file(fh) {
+            self.store.forget_file(&id)?;
+        }
 
         Ok(())
     }
@@ -199,10 +676,33 @@ impl OffsFilesystem {
         new_parent: &str,
         new_name: &str,
     ) -> OperationResult<DirEntity> {
+        if self.store.is_descendant(new_parent, id)? {
+            return Err(OperationError::rename_into_descendant(id));
+        }
+
         let dirent = self.store.query_file(id)?;
-        let operation = ModifyOpBuilder::make_rename_op(&dirent, new_parent, new_name);
+        let is_directory = dirent.stat.file_type == FileType::Directory;
+        let was_local_only = LocalTempIdGenerator::is_local_only_id(id);
+        let into_local_only = LocalTempIdGenerator::is_local_only_id(new_parent);
+        let stays_local_only =
+            into_local_only || self.ignore_patterns.is_ignored(new_name, is_directory);
+
+        // The synchronized files keep being synchronized when renamed to an ignored name, but
+        // the server would not know where to move them to inside an ignored directory
+        if (into_local_only && !was_local_only)
+            || (was_local_only && !stays_local_only && is_directory)
+        {
+            return Err(OperationError::cross_device(&format!(
+                "Cannot move {} between the synchronized and the ignored files",
+                dirent.name
+            )));
+        }
 
+        let operation = ModifyOpBuilder::make_rename_op(&dirent, new_parent, new_name);
         let mut dirent = self.perform_operation(operation).await?;
+        if was_local_only && !stays_local_only {
+            return self.promote_local_only_file(&dirent.id).await;
+        }
         self.add_dirent(&mut dirent)?;
 
         Ok(dirent)
@@ -218,6 +718,12 @@ impl OffsFilesystem {
         atime: Option<Timespec>,
         mtime: Option<Timespec>,
     ) -> OperationResult<DirEntity> {
+        // Otherwise the data buffered earlier would be written over the new size and times
+        self.flush_file_write_buffers(id).await?;
+        if let Some(size) = size {
+            self.retrieve_blobs_for_write(id, size, 0).await?;
+        }
+
         let dirent = self.store.query_file(id)?;
         let operation =
             ModifyOpBuilder::make_set_attributes_op(&dirent, mode, uid, gid, size, atime, mtime);
@@ -228,12 +734,107 @@ impl OffsFilesystem {
         Ok(dirent)
     }
 
+    pub(super) async fn fallocate(
+        &mut self,
+        id: &str,
+        offset: i64,
+        length: i64,
+        zero: bool,
+        keep_size: bool,
+    ) -> OperationResult<DirEntity> {
+        if zero {
+            // The chunks zeroed only partially, at the ends of the range, need their content
+            self.retrieve_blobs_for_write(id, offset as u64, length as u64)
+                .await?;
+        } else if !keep_size {
+            self.retrieve_blobs_for_write(id, (offset + length) as u64, 0)
+                .await?;
+        }
+
+        let dirent = self.store.query_file(id)?;
+        let operation =
+            ModifyOpBuilder::make_fallocate_op(&dirent, offset, length, zero, keep_size);
+
+        let mut dirent = self.perform_operation(operation).await?;
+        self.add_dirent(&mut dirent)?;
+
+        Ok(dirent)
+    }
+
+    /// Copies a range of `source_id` into `id`, returning the number of bytes copied.
+    pub(super) async fn copy_range(
+        &mut self,
+        source_id: &str,
+        source_offset: i64,
+        id: &str,
+        offset: i64,
+        length: u64,
+    ) -> OperationResult<(DirEntity, u64)> {
+        let source_size = self.store.query_file(source_id)?.stat.size;
+        let mut length = cmp::min(length, source_size.saturating_sub(source_offset as u64));
+        // The server does not have the local-only files, so their data is written instead
+        let writes_data = LocalTempIdGenerator::is_local_only_id(source_id)
+            && !LocalTempIdGenerator::is_local_only_id(id);
+        if writes_data {
+            length = cmp::min(length, u32::MAX as u64);
+        }
+        let length = length as i64;
+
+        // Only the chunks that are not copied whole need their content; if the offsets are
+        // not aligned the same way, that is all of them
+        let edges = if source_offset % BLOB_SIZE as i64 == offset % BLOB_SIZE as i64 {
+            vec![source_offset, source_offset + length - 1]
+        } else {
+            (source_offset..source_offset + length)
+                .step_by(BLOB_SIZE)
+                .collect()
+        };
+        for edge in edges.into_iter().filter(|_| length > 0) {
+            let chunks = self.store.get_blobs_for_read(source_id, edge, 1)?;
+            let missing_blobs = self.store.get_missing_blobs(&chunks)?;
+            self.retrieve_missing_blobs(source_id, missing_blobs)
+                .await?;
+        }
+        self.retrieve_blobs_for_write(id, offset as u64, length as u64)
+            .await?;
+
+        let dirent = self.store.query_file(id)?;
+        let operation = if writes_data {
+            let data = self.store.read(source_id, source_offset, length as u32)?;
+            ModifyOpBuilder::make_write_op(&dirent, offset, data.to_vec())
+        } else {
+            ModifyOpBuilder::make_copy_range_op(&dirent, source_id, source_offset, offset, length)
+        };
+
+        let mut dirent = self.perform_operation(operation).await?;
+        self.add_dirent(&mut dirent)?;
+
+        Ok((dirent, length as u64))
+    }
+
+    pub(super) async fn set_acl(
+        &mut self,
+        id: &str,
+        acl_type: AclType,
+        acl: Vec<u8>,
+    ) -> OperationResult<DirEntity> {
+        let dirent = self.store.query_file(id)?;
+        let operation = ModifyOpBuilder::make_set_acl_op(&dirent, acl_type, acl);
+
+        let mut dirent = self.perform_operation(operation).await?;
+        self.add_dirent(&mut dirent)?;
+
+        Ok(dirent)
+    }
+
     pub(super) async fn write(
         &mut self,
         fh: u64,
         offset: i64,
         data: Vec<u8>,
     ) -> OperationResult<()> {
+        let id = self.open_file_handler.get_id(fh);
+        self.store.add_write_intent(fh, &id, offset as u64, &data)?;
         let should_flush = self
             .open_file_handler
             .write(fh, WriteOperation::new(offset as usize, data));
@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use nix::sys::statvfs::statvfs;
+
+use offs::errors::{OperationError, OperationResult};
+
+use crate::events::{SyncEvent, SyncEvents};
+
+/// Refuses the writes when the device the cache is stored on is running out of space, so that
+/// the cache database does not end up filling it entirely. `CacheNearlyFull` is emitted once
+/// there is less than twice the minimum left, and again only after the space is freed.
+pub struct FreeSpaceGuard {
+    cache_path: PathBuf,
+    /// Shared between the mounts, so that it can be changed when the configuration is reloaded
+    min_free_space: Arc<AtomicU64>,
+    events: Arc<SyncEvents>,
+    nearly_full: AtomicBool,
+}
+
+impl FreeSpaceGuard {
+    pub fn new(
+        cache_path: PathBuf,
+        min_free_space: Arc<AtomicU64>,
+        events: Arc<SyncEvents>,
+    ) -> Self {
+        Self {
+            cache_path,
+            min_free_space,
+            events,
+            nearly_full: AtomicBool::new(false),
+        }
+    }
+
+    pub fn check(&self) -> OperationResult<()> {
+        let min_free_space = self.min_free_space.load(Ordering::Relaxed);
+        if min_free_space == 0 {
+            return Ok(());
+        }
+
+        // Failing to determine the free space should not make the filesystem read-only
+        if let Ok(stat) = statvfs(&self.cache_path) {
+            let free_space = stat.blocks_available() * stat.fragment_size();
+            let nearly_full = free_space < min_free_space.saturating_mul(2);
+            if nearly_full && !self.nearly_full.swap(nearly_full, Ordering::Relaxed) {
+                self.events.emit(SyncEvent::CacheNearlyFull {
+                    free_space,
+                    min_free_space,
+                });
+            }
+            self.nearly_full.store(nearly_full, Ordering::Relaxed);
+
+            if free_space < min_free_space {
+                return Err(OperationError::no_space(&format!(
+                    "only {} bytes free on the cache device",
+                    free_space
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,29 @@
-use std::net::SocketAddr;
+use std::cmp;
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
+use itertools::Itertools;
+use tracing::info;
+
+use offs::protocol::{FEATURE_BLAKE3_BLOB_IDS, FEATURE_GET_DIR_ENTITIES};
+use offs::store::dir_cache::DirCache;
 use offs::store::id_generator::LocalTempIdGenerator;
 use offs::store::wrapper::StoreWrapper;
-use offs::store::{DirEntity, Store};
-use offs::{now, ROOT_ID};
+use offs::store::{BlobHash, DirEntity};
+use offs::{now, BLOB_SIZE, ROOT_ID};
 
-use super::super::client::grpc_client::RemoteFsGrpcClient;
+use super::super::client::remote_client::RemoteFsClient;
+use crate::events::SyncEvents;
+use crate::metrics::Metrics;
+use crate::remote_fs_client::fs::ignore::IgnorePatterns;
 use crate::remote_fs_client::fs::open_file_handler::OpenFileHandler;
+use crate::transfers::{TransferDirection, Transfers};
 use offs::errors::{OperationError, OperationResult};
 
+/// Maximum number of the directory entries whose listings are kept in memory
+const DIR_CACHE_CAPACITY: usize = 65536;
+
 macro_rules! err_offline {
     () => {
         return Err(OperationError::offline("The client is currently offline"));
@@ -26,73 +39,216 @@ macro_rules! check_online {
 }
 
 pub struct OffsFilesystem {
-    pub(super) client: RemoteFsGrpcClient,
+    pub(super) client: Box<dyn RemoteFsClient>,
     offline_mode: Arc<AtomicBool>,
     pub(super) should_flush_journal: Arc<AtomicBool>,
+    last_error: Arc<Mutex<String>>,
 
     pub(super) store: StoreWrapper<LocalTempIdGenerator>,
     pub(super) open_file_handler: OpenFileHandler,
+    pub(super) ignore_patterns: IgnorePatterns,
+
+    pub(super) metrics: Arc<Metrics>,
+    pub(super) transfers: Arc<Transfers>,
+    pub(super) events: Arc<SyncEvents>,
 }
 
 impl OffsFilesystem {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        address: SocketAddr,
+        client: impl RemoteFsClient + 'static,
         offline_mode: Arc<AtomicBool>,
         should_flush_journal: Arc<AtomicBool>,
-        store: Store<LocalTempIdGenerator>,
+        last_error: Arc<Mutex<String>>,
+        mut store: StoreWrapper<LocalTempIdGenerator>,
+        metrics: Arc<Metrics>,
+        transfers: Arc<Transfers>,
+        events: Arc<SyncEvents>,
     ) -> OperationResult<Self> {
+        // The server has to derive the same IDs from the uploaded blobs
+        if client.has_feature(FEATURE_BLAKE3_BLOB_IDS) {
+            store.set_blob_hash(BlobHash::Blake3);
+        }
+        store.set_dir_cache(Arc::new(DirCache::new(DIR_CACHE_CAPACITY)));
+
         let mut fs = Self {
-            client: RemoteFsGrpcClient::new(&format!("{}", address)).await?,
+            client: Box::new(client),
             offline_mode,
             should_flush_journal,
+            last_error,
 
-            store: StoreWrapper::new(store),
+            store,
             open_file_handler: OpenFileHandler::new(),
+            ignore_patterns: IgnorePatterns::default(),
+
+            metrics,
+            transfers,
+            events,
         };
 
         // Request the root attributes
         fs.store.create_default_root_directory()?;
         fs.update_dirent(ROOT_ID, true).await?;
+        fs.replay_write_intents().await?;
 
         if !fs.is_offline() {
             fs.apply_journal().await?;
+            fs.refresh_dirents().await?;
         }
 
         Ok(fs)
     }
 
+    /// Keeps the files matching the patterns, created from now on, only in the cache.
+    pub fn set_ignore_patterns(&mut self, patterns: IgnorePatterns) {
+        self.ignore_patterns = patterns;
+    }
+
     pub(super) fn add_dirent(&mut self, dirent: &mut DirEntity) -> OperationResult<()> {
+        if !self.make_room_for_dirent(dirent)? {
+            return Ok(());
+        }
+
         self.store.add_or_replace_dirent(&dirent)?;
         Ok(())
     }
 
+    /// Forgets the cached file which has the name of the entry received from the server, as it
+    /// must have been renamed or removed on the server since. Returns false if that file has
+    /// changes which have not been sent to the server yet; the entry is then not cached until
+    /// the journal is applied, and the server resolves the conflict.
+    fn make_room_for_dirent(&mut self, dirent: &DirEntity) -> OperationResult<bool> {
+        let cached = match self
+            .store
+            .try_query_file_by_name(&dirent.parent, &dirent.name)?
+        {
+            Some(x) if x.id != dirent.id => x,
+            _ => return Ok(true),
+        };
+
+        if LocalTempIdGenerator::is_local_id(&cached.id)
+            || LocalTempIdGenerator::is_local_only_id(&cached.id)
+            || self.store.is_file_in_journal(&cached.id)?
+        {
+            return Ok(false);
+        }
+        self.store.forget_file(&cached.id)?;
+
+        Ok(true)
+    }
+
+    /// Returns the cached attributes of the file, without recording the access nor contacting
+    /// the server. The data still buffered is not accounted for; see
+    /// [`OffsFilesystem::has_buffered_writes`].
+    pub(super) fn get_attributes(&self, id: &str) -> OperationResult<DirEntity> {
+        self.store.query_file(id)
+    }
+
+    /// Returns whether the file has data written to it, but still buffered, so that its size
+    /// and modification time are not up to date until the buffers are flushed.
+    pub(super) fn has_buffered_writes(&self, id: &str) -> bool {
+        self.open_file_handler.has_buffered_writes(id)
+    }
+
+    /// Updates the access time of the file, refreshing its entry from the server. When offline,
+    /// the cached entry is served as it is instead, so that merely listing the files does not
+    /// fill the journal with access time updates.
     pub(super) async fn update_dirent(
         &mut self,
         id: &str,
         update_atime: bool,
     ) -> OperationResult<DirEntity> {
+        if self.is_offline() {
+            if let Some(dirent) = self.store.try_query_file(id)? {
+                return Ok(dirent);
+            }
+        }
+
         let atime = if update_atime { Some(now()) } else { None };
         self.set_attributes(id, None, None, None, None, atime, None)
             .await
     }
 
-    pub(super) async fn retrieve_missing_blobs(&mut self, ids: Vec<String>) -> OperationResult<()> {
+    /// Retrieves the current entries of all the cached files at once, forgetting the ones
+    /// removed in the meantime, as others might have changed them while we were offline. The
+    /// opened files are left alone, so that writing to them still detects the conflicts.
+    pub(super) async fn refresh_dirents(&mut self) -> OperationResult<()> {
+        if !self.client.has_feature(FEATURE_GET_DIR_ENTITIES) {
+            return Ok(());
+        }
+
+        let open_ids: HashSet<String> = self
+            .open_file_handler
+            .get_file_handles()
+            .into_iter()
+            .map(|fh| self.open_file_handler.get_id(fh))
+            .collect();
+        let ids = self
+            .store
+            .get_file_ids()?
+            .into_iter()
+            .filter(|x| {
+                !open_ids.contains(x)
+                    && !LocalTempIdGenerator::is_local_id(x)
+                    && !LocalTempIdGenerator::is_local_only_id(x)
+            })
+            .collect_vec();
+
+        let (mut dirents, missing_ids) = self.client.get_dir_entities(&ids).await?;
+
+        let transaction = self.store.transaction();
+        for dirent in &mut dirents {
+            self.add_dirent(dirent)?;
+        }
+        for id in &missing_ids {
+            self.store.forget_file(id)?;
+        }
+        transaction.commit()?;
+
+        info!(
+            "Refreshed {} cached files, {} of which no longer exist",
+            ids.len(),
+            missing_ids.len()
+        );
+
+        Ok(())
+    }
+
+    pub(super) async fn retrieve_missing_blobs(
+        &mut self,
+        file_id: &str,
+        ids: Vec<String>,
+    ) -> OperationResult<()> {
         if !ids.is_empty() {
             check_online!(self);
 
-            let blobs = self.client.get_blobs(ids).await?;
+            let dirent = self.store.query_file(file_id)?;
+            let estimated_size = cmp::min((ids.len() * BLOB_SIZE) as u64, dirent.stat.size);
+            let transfer =
+                self.transfers
+                    .start(TransferDirection::Download, &dirent.name, estimated_size);
 
-            let transaction = self.store.transaction();
-            for (_, blob) in &blobs {
-                self.store.add_blob(blob)?;
-            }
-            transaction.commit()?;
+            let blobs = self
+                .client
+                .get_blobs(ids, &mut |blob| transfer.add_progress(blob.len() as u64))
+                .await?;
+            self.metrics.add_blobs_fetched(
+                blobs.len() as u64,
+                blobs.iter().map(|(_, blob)| blob.len() as u64).sum(),
+            );
+
+            // Stored under the IDs they have been requested with, whichever hash those use
+            self.store
+                .add_blobs_batch(blobs.iter().map(|(id, blob)| (BlobHash::of_id(id), blob)))?;
         };
 
         Ok(())
     }
 
     pub(super) async fn update_chunks(&mut self, id: &str) -> OperationResult<()> {
+        if LocalTempIdGenerator::is_local_only_id(id) {
+            return Ok(());
+        }
         if self.is_offline() {
             let dirent = self.store.query_file(id)?;
             if dirent.stat.size != 0 && !dirent.is_up_to_date() {
@@ -109,7 +265,31 @@ impl OffsFilesystem {
         Ok(())
     }
 
+    /// Returns the path of the cached file, relative to the root of the filesystem.
+    pub(super) fn get_path(&self, id: &str) -> OperationResult<String> {
+        let mut names = Vec::new();
+        let mut id = id.to_owned();
+        while id != ROOT_ID {
+            let dirent = self.store.query_file(&id)?;
+            names.push(dirent.name);
+            id = dirent.parent;
+        }
+        if names.is_empty() {
+            return Ok("/".to_owned());
+        }
+
+        Ok(names
+            .iter()
+            .rev()
+            .map(|name| format!("/{}", name))
+            .collect())
+    }
+
     pub(super) fn is_offline(&self) -> bool {
         self.offline_mode.load(Ordering::Relaxed)
     }
+
+    pub(super) fn set_last_error(&self, error: &OperationError) {
+        *self.last_error.lock().unwrap() = error.to_string();
+    }
 }
@@ -1,28 +1,50 @@
+use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::future::Future;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use fuser::consts::{FOPEN_DIRECT_IO, FOPEN_KEEP_CACHE};
 use fuser::{
-    FileAttr, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen,
-    ReplyWrite, Request, TimeOrNow,
+    FileAttr, Filesystem, KernelConfig, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, ReplyXattr, Request, TimeOrNow,
 };
-use libc::{S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK};
-use log::debug;
-use tokio::runtime::Runtime;
+use libc::{
+    c_int, EACCES, EEXIST, EINVAL, ENODATA, ENOTSUP, EOPNOTSUPP, ERANGE, R_OK, W_OK, XATTR_CREATE,
+    XATTR_REPLACE, X_OK,
+};
+use tokio::runtime::Handle;
 use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error};
 
-use offs::store::{DirEntity, FileMode, FileType};
+use offs::acl::{Acl, AclType, ACL_EXECUTE, ACL_READ, ACL_WRITE};
+use offs::names::normalize_name;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{DirEntity, FileMode, FileOwner, FileType};
 use offs::timespec::Timespec;
 
 use super::errors::to_os_error;
+use super::free_space::FreeSpaceGuard;
+use super::platform::{
+    FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE, O_DIRECT, S_IFBLK, S_IFCHR,
+    S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK,
+};
+use super::request_supervisor::RequestSupervisor;
 use super::OffsFilesystem;
+use crate::metrics::Metrics;
 use offs::errors::{OperationError, OperationResult};
-use offs::ROOT_ID;
 use std::cell::RefCell;
 use std::collections::HashMap;
 
 const TTL: Duration = Duration::from_secs(1);
+/// Largest write request the kernel is asked to send, instead of splitting the writes into the
+/// default 128 KiB ones. The kernel does not allow more than 256 pages per request by default.
+const MAX_WRITE: u32 = 1024 * 1024;
+/// Number of the directory entries read from the cache at once when listing a directory
+const READDIR_PAGE_SIZE: u32 = 128;
 
 macro_rules! try_fs {
     ($e:expr, $reply:ident) => {
@@ -38,59 +60,99 @@ macro_rules! try_fs {
 }
 
 struct FuseHelper {
-    next_inode: RefCell<u64>,
-    inodes_to_ids: RefCell<HashMap<u64, String>>,
-    ids_to_inodes: RefCell<HashMap<String, u64>>,
+    /// Store keeping the inode numbers assigned to the files
+    store: StoreWrapper<LocalTempIdGenerator>,
+    /// Content versions of the files at the time their data could last get into the page cache
+    cached_content_versions: RefCell<HashMap<u64, i64>>,
+    next_dir_handle: RefCell<u64>,
+    /// Where the listing of each open directory has stopped
+    dir_cursors: RefCell<HashMap<u64, DirCursor>>,
+}
+
+/// Position in the listing of a directory: the offset and the name of the last entry returned.
+#[derive(Clone)]
+struct DirCursor {
+    offset: i64,
+    name: String,
+}
+
+impl DirCursor {
+    /// Cursor pointing right after `.` and `..`
+    fn start() -> Self {
+        Self {
+            offset: 2,
+            name: String::new(),
+        }
+    }
 }
 
 impl FuseHelper {
-    fn new() -> Self {
+    fn new(store: StoreWrapper<LocalTempIdGenerator>) -> Self {
         Self {
-            next_inode: RefCell::new(2),
-            inodes_to_ids: RefCell::new([(1, ROOT_ID.to_owned())].iter().cloned().collect()),
-            ids_to_inodes: RefCell::new([(ROOT_ID.to_owned(), 1)].iter().cloned().collect()),
+            store,
+            cached_content_versions: RefCell::new(HashMap::new()),
+            next_dir_handle: RefCell::new(1),
+            dir_cursors: RefCell::new(HashMap::new()),
         }
     }
 
-    fn get_inode_for_id(&self, id: &str) -> u64 {
-        if !self.ids_to_inodes.borrow().contains_key(id) {
-            let next_inode_val = *self.next_inode.borrow();
-            self.ids_to_inodes
-                .borrow_mut()
-                .insert(id.to_owned(), next_inode_val);
-            self.inodes_to_ids
-                .borrow_mut()
-                .insert(next_inode_val, id.to_owned());
+    fn open_dir(&self) -> u64 {
+        let fh = *self.next_dir_handle.borrow();
+        *self.next_dir_handle.borrow_mut() += 1;
+        self.dir_cursors.borrow_mut().insert(fh, DirCursor::start());
 
-            *self.next_inode.borrow_mut() += 1;
-        };
+        fh
+    }
+
+    fn close_dir(&self, fh: u64) {
+        self.dir_cursors.borrow_mut().remove(&fh);
+    }
+
+    fn get_dir_cursor(&self, fh: u64) -> Option<DirCursor> {
+        self.dir_cursors.borrow().get(&fh).cloned()
+    }
+
+    fn set_dir_cursor(&self, fh: u64, cursor: DirCursor) {
+        if let Some(x) = self.dir_cursors.borrow_mut().get_mut(&fh) {
+            *x = cursor;
+        }
+    }
 
-        self.ids_to_inodes.borrow()[id]
+    fn get_inode_for_id(&self, id: &str) -> OperationResult<u64> {
+        Ok(self.store.get_inode(id)?.number)
     }
 
     fn get_id_by_inode(&self, inode: u64) -> OperationResult<String> {
-        self.inodes_to_ids
-            .borrow()
-            .get(&inode)
-            .ok_or(OperationError::file_does_not_exist(&format!(
-                "inode={}",
-                inode
-            )))
-            .map(|x| x.to_owned())
-    }
-
-    fn get_fuse_stat(&self, dirent: &DirEntity) -> FileAttr {
-        let id = &dirent.id;
-        let inode = self.get_inode_for_id(id);
-
-        FileAttr {
-            ino: inode,
+        self.store.get_id_by_inode(inode)
+    }
+
+    /// Remembers the content version of the file, returning whether the page cache of the file
+    /// is still valid, i.e. the content has not been changed since the previous time.
+    fn update_cached_content_version(&self, inode: u64, content_version: i64) -> bool {
+        self.cached_content_versions
+            .borrow_mut()
+            .insert(inode, content_version)
+            == Some(content_version)
+    }
+
+    fn get_fuse_stat(&self, dirent: &DirEntity) -> OperationResult<FileAttr> {
+        Ok(self.get_fuse_entry(dirent)?.0)
+    }
+
+    /// Returns the attributes of the file along with the generation of its inode.
+    fn get_fuse_entry(&self, dirent: &DirEntity) -> OperationResult<(FileAttr, u64)> {
+        let inode = self.store.get_inode(&dirent.id)?;
+
+        let attr = FileAttr {
+            ino: inode.number,
             size: dirent.stat.size,
             blocks: dirent.stat.blocks,
             atime: dirent.stat.atim.into(),
             mtime: dirent.stat.mtim.into(),
             ctime: dirent.stat.ctim.into(),
-            crtime: SystemTime::UNIX_EPOCH,
+            // Only sent by fuser on macOS: no release of fuser answers FUSE_STATX, so Linux gets no
+            // birth time
+            crtime: dirent.stat.crtim.into(),
             kind: convert_file_type(dirent.stat.file_type),
             perm: dirent.stat.mode,
             nlink: dirent.stat.nlink as u32,
@@ -99,7 +161,9 @@ impl FuseHelper {
             rdev: dirent.stat.dev,
             blksize: 0,
             flags: 0,
-        }
+        };
+
+        Ok((attr, inode.generation))
     }
 }
 
@@ -117,25 +181,67 @@ fn convert_file_type(store_file_type: FileType) -> fuser::FileType {
 
 pub struct FuseOffsFilesystem {
     fs: Arc<RwLock<OffsFilesystem>>,
-    rt: Runtime,
+    rt: Handle,
     fuse_helper: Arc<Mutex<FuseHelper>>,
+    metrics: Arc<Metrics>,
+    supervisor: RequestSupervisor,
+    free_space_guard: Arc<FreeSpaceGuard>,
+    normalize_names: bool,
 }
 
 impl FuseOffsFilesystem {
-    pub fn new(fs: Arc<RwLock<OffsFilesystem>>, rt: Runtime) -> Self {
+    pub fn new(
+        fs: Arc<RwLock<OffsFilesystem>>,
+        rt: Handle,
+        metrics: Arc<Metrics>,
+        degraded: Arc<AtomicBool>,
+        last_error: Arc<std::sync::Mutex<String>>,
+        free_space_guard: FreeSpaceGuard,
+        normalize_names: bool,
+    ) -> Self {
+        let store = rt.block_on(fs.read()).store.clone();
+
         Self {
             fs,
+            fuse_helper: Arc::new(Mutex::new(FuseHelper::new(store))),
+            metrics,
+            supervisor: RequestSupervisor::new(rt.clone(), degraded, last_error),
+            free_space_guard: Arc::new(free_space_guard),
+            normalize_names,
             rt,
-            fuse_helper: Arc::new(Mutex::new(FuseHelper::new())),
         }
     }
+
+    /// Sets how long the requests are handled for before they fail with `ETIMEDOUT`, along with
+    /// the RPCs made for them.
+    pub fn set_operation_timeout(&mut self, timeout: Duration) {
+        self.supervisor.set_operation_timeout(timeout);
+    }
 }
 
 impl FuseOffsFilesystem {
+    /// Runs the request handler in the background, within a span identifying the request
+    fn spawn<F>(&self, req: &Request, operation: &'static str, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.supervisor.spawn(req.unique(), operation, future);
+    }
+
     fn check_os_str(string: &OsStr) -> OperationResult<&str> {
         string.to_str().ok_or(OperationError::invalid_unicode())
     }
 
+    fn check_name(name: &OsStr, normalize: bool) -> OperationResult<Cow<'_, str>> {
+        let name = Self::check_os_str(name)?;
+
+        Ok(if normalize {
+            normalize_name(name)
+        } else {
+            Cow::Borrowed(name)
+        })
+    }
+
     fn mode_to_file_type(mode: u32) -> FileType {
         if (mode & S_IFIFO) == S_IFIFO {
             FileType::NamedPipe
@@ -161,56 +267,165 @@ impl FuseOffsFilesystem {
             TimeOrNow::Now => SystemTime::now().into(),
         }
     }
+
+    /// Returns the user and group of the process making the request, which own the files it
+    /// creates.
+    fn request_owner(req: &Request) -> FileOwner {
+        FileOwner {
+            uid: req.uid(),
+            gid: req.gid(),
+        }
+    }
+
+    /// Checks the permissions the same way the kernel does with `default_permissions`, but
+    /// based on the cached file entry, so that it works in the offline mode as well.
+    fn is_access_allowed(
+        dirent: &DirEntity,
+        uid: u32,
+        gid: u32,
+        mask: i32,
+    ) -> OperationResult<bool> {
+        let mut requested = 0;
+        if mask & R_OK != 0 {
+            requested |= ACL_READ;
+        }
+        if mask & W_OK != 0 {
+            requested |= ACL_WRITE;
+        }
+        if mask & X_OK != 0 {
+            requested |= ACL_EXECUTE;
+        }
+
+        if uid == 0 {
+            // root can execute the file only if anyone can
+            let any_executable =
+                dirent.stat.file_type == FileType::Directory || dirent.stat.mode & 0o111 != 0;
+            return Ok(requested & ACL_EXECUTE == 0 || any_executable);
+        }
+
+        let acl = match &dirent.acl_access {
+            Some(acl) => Acl::parse(acl)?,
+            None => Acl::from_mode(dirent.stat.mode),
+        };
+
+        Ok(acl.check_access(dirent.stat.uid, dirent.stat.gid, uid, &[gid], requested))
+    }
+
+    /// Only the POSIX ACLs are supported as extended attributes
+    fn get_acl_type(name: &OsStr) -> Option<AclType> {
+        name.to_str().and_then(AclType::from_xattr_name)
+    }
+
+    /// Returns the cursor pointing at the entry with the given offset, by listing the entries
+    /// preceding it.
+    fn seek_dir(fs: &OffsFilesystem, dir_id: &str, offset: i64) -> OperationResult<DirCursor> {
+        let mut cursor = DirCursor::start();
+        while cursor.offset < offset {
+            let limit = (offset - cursor.offset).min(READDIR_PAGE_SIZE as i64) as u32;
+            let items = fs.store.list_files_page(dir_id, &cursor.name, limit)?;
+            match items.last() {
+                Some(last) => {
+                    cursor.offset += items.len() as i64;
+                    cursor.name = last.name.clone();
+                }
+                None => break,
+            }
+        }
+
+        Ok(cursor)
+    }
+
+    fn reply_xattr(data: &[u8], size: u32, reply: ReplyXattr) {
+        if size == 0 {
+            reply.size(data.len() as u32);
+        } else if data.len() > size as usize {
+            reply.error(ERANGE);
+        } else {
+            reply.data(data);
+        }
+    }
 }
 
 impl Filesystem for FuseOffsFilesystem {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+    fn init(&mut self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Within the size of the buffers of fuser
+        config.set_max_write(MAX_WRITE).unwrap();
+
+        Ok(())
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
         debug!("Request(lookup): parent={}, name={:?}", parent, name);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
+        let timer = self.metrics.start_operation("lookup");
+
+        self.spawn(req, "lookup", async move {
+            let _timer = timer;
 
-        self.rt.spawn(async move {
             let parent_id =
                 try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply).to_owned();
             let mut fs = fs.write().await;
 
             // Make sure the file entry is up to date
-            try_fs!(fs.list_files(&parent_id).await, reply);
+            try_fs!(fs.retrieve_dir(&parent_id).await, reply);
             let item = try_fs!(
-                fs.store
-                    .query_file_by_name(&parent_id, try_fs!(Self::check_os_str(&name), reply)),
+                fs.store.query_file_by_name(
+                    &parent_id,
+                    &try_fs!(Self::check_name(&name, normalize_names), reply)
+                ),
                 reply
             );
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&item);
-            debug!("Response: {:?}", rv);
-            reply.entry(&TTL, &rv, 1);
+            let (rv, generation) = try_fs!(fuse_helper.lock().await.get_fuse_entry(&item), reply);
+            debug!("Response: {:?}, generation={}", rv, generation);
+            reply.entry(&TTL, &rv, generation);
         });
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
         debug!("Request(getattr): ino={}", ino);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "getattr", async move {
             let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply);
+            if fs.read().await.has_buffered_writes(&id) {
+                try_fs!(fs.write().await.flush_file_write_buffers(&id).await, reply);
+            }
             let fs = fs.read().await;
 
-            let item = try_fs!(fs.store.query_file(&id), reply);
+            let item = try_fs!(fs.get_attributes(&id), reply);
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&item);
+            let rv = try_fs!(fuse_helper.lock().await.get_fuse_stat(&item), reply);
             debug!("Response: {:?}", rv);
             reply.attr(&TTL, &rv);
         });
     }
 
+    /// Reports the creation times on macOS; the backup times are not kept.
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&mut self, req: &Request, ino: u64, reply: fuser::ReplyXTimes) {
+        debug!("Request(getxtimes): ino={}", ino);
+
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "getxtimes", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply);
+            let item = try_fs!(fs.read().await.get_attributes(&id), reply);
+
+            reply.xtimes(SystemTime::UNIX_EPOCH, item.stat.crtim.into());
+        });
+    }
+
     fn setattr(
         &mut self,
-        _req: &Request<'_>,
+        req: &Request<'_>,
         ino: u64,
         mode: Option<u32>,
         uid: Option<u32>,
@@ -231,7 +446,7 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "setattr", async move {
             let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).to_owned();
             let mut fs = fs.write().await;
 
@@ -250,19 +465,19 @@ impl Filesystem for FuseOffsFilesystem {
                 reply
             );
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&dirent);
+            let rv = try_fs!(fuse_helper.lock().await.get_fuse_stat(&dirent), reply);
             debug!("Response: {:?}", rv);
             reply.attr(&TTL, &rv);
         });
     }
 
-    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
         debug!("Request(readlink): ino={}", ino);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "readlink", async move {
             let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).to_owned();
             let mut fs = fs.write().await;
 
@@ -276,7 +491,7 @@ impl Filesystem for FuseOffsFilesystem {
 
     fn mknod(
         &mut self,
-        _req: &Request,
+        req: &Request,
         parent: u64,
         name: &OsStr,
         mode: u32,
@@ -292,8 +507,11 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
+
+        let owner = Self::request_owner(req);
 
-        self.rt.spawn(async move {
+        self.spawn(req, "mknod", async move {
             let parent_id =
                 try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply).clone();
             let mut fs = fs.write().await;
@@ -301,24 +519,25 @@ impl Filesystem for FuseOffsFilesystem {
             let dirent = try_fs!(
                 fs.create_file(
                     &parent_id,
-                    try_fs!(Self::check_os_str(&name), reply),
+                    &try_fs!(Self::check_name(&name, normalize_names), reply),
                     Self::mode_to_file_type(mode),
                     mode as FileMode,
                     rdev,
+                    Some(owner),
                 )
                 .await,
                 reply
             );
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&dirent);
-            debug!("Response: {:?}", rv);
-            reply.entry(&TTL, &rv, 1);
+            let (rv, generation) = try_fs!(fuse_helper.lock().await.get_fuse_entry(&dirent), reply);
+            debug!("Response: {:?}, generation={}", rv, generation);
+            reply.entry(&TTL, &rv, generation);
         });
     }
 
     fn mkdir(
         &mut self,
-        _req: &Request,
+        req: &Request,
         parent: u64,
         name: &OsStr,
         mode: u32,
@@ -333,8 +552,11 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
+
+        let owner = Self::request_owner(req);
 
-        self.rt.spawn(async move {
+        self.spawn(req, "mkdir", async move {
             let parent_id =
                 try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply).clone();
             let mut fs = fs.write().await;
@@ -342,33 +564,37 @@ impl Filesystem for FuseOffsFilesystem {
             let dirent = try_fs!(
                 fs.create_directory(
                     &parent_id,
-                    try_fs!(Self::check_os_str(&name), reply),
+                    &try_fs!(Self::check_name(&name, normalize_names), reply),
                     mode as FileMode,
+                    Some(owner),
                 )
                 .await,
                 reply
             );
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&dirent);
-            debug!("Response: {:?}", rv);
-            reply.entry(&TTL, &rv, 1);
+            let (rv, generation) = try_fs!(fuse_helper.lock().await.get_fuse_entry(&dirent), reply);
+            debug!("Response: {:?}, generation={}", rv, generation);
+            reply.entry(&TTL, &rv, generation);
         });
     }
 
-    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
         debug!("Request(unlink): parent={}, name={:?}", parent, name);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
 
-        self.rt.spawn(async move {
+        self.spawn(req, "unlink", async move {
             let parent_id = try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply);
             let mut fs = fs.write().await;
 
             let item = try_fs!(
-                fs.store
-                    .query_file_by_name(&parent_id, try_fs!(Self::check_os_str(&name), reply)),
+                fs.store.query_file_by_name(
+                    &parent_id,
+                    &try_fs!(Self::check_name(&name, normalize_names), reply)
+                ),
                 reply
             );
 
@@ -379,20 +605,23 @@ impl Filesystem for FuseOffsFilesystem {
         });
     }
 
-    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
         debug!("Request(rmdir): parent={}, name={:?}", parent, name);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
 
-        self.rt.spawn(async move {
+        self.spawn(req, "rmdir", async move {
             let parent_id = try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply);
             let mut fs = fs.write().await;
 
             let item = try_fs!(
-                fs.store
-                    .query_file_by_name(&parent_id, try_fs!(Self::check_os_str(&name), reply)),
+                fs.store.query_file_by_name(
+                    &parent_id,
+                    &try_fs!(Self::check_name(&name, normalize_names), reply)
+                ),
                 reply
             );
 
@@ -405,7 +634,7 @@ impl Filesystem for FuseOffsFilesystem {
 
     fn symlink(
         &mut self,
-        _req: &Request,
+        req: &Request,
         parent: u64,
         name: &OsStr,
         link: &Path,
@@ -419,9 +648,12 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
         let link = link.to_owned();
 
-        self.rt.spawn(async move {
+        let owner = Self::request_owner(req);
+
+        self.spawn(req, "symlink", async move {
             let parent_id =
                 try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply).clone();
             let mut fs = fs.write().await;
@@ -429,22 +661,23 @@ impl Filesystem for FuseOffsFilesystem {
             let dirent = try_fs!(
                 fs.create_symlink(
                     &parent_id,
-                    try_fs!(Self::check_os_str(&name), reply),
+                    &try_fs!(Self::check_name(&name, normalize_names), reply),
                     try_fs!(Self::check_os_str(link.as_os_str()), reply),
+                    Some(owner),
                 )
                 .await,
                 reply
             );
 
-            let rv = fuse_helper.lock().await.get_fuse_stat(&dirent);
-            debug!("Response: {:?}", rv);
-            reply.entry(&TTL, &rv, 1);
+            let (rv, generation) = try_fs!(fuse_helper.lock().await.get_fuse_entry(&dirent), reply);
+            debug!("Response: {:?}, generation={}", rv, generation);
+            reply.entry(&TTL, &rv, generation);
         });
     }
 
     fn rename(
         &mut self,
-        _req: &Request,
+        req: &Request,
         parent: u64,
         name: &OsStr,
         newparent: u64,
@@ -460,17 +693,20 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
         let name = name.to_owned();
+        let normalize_names = self.normalize_names;
         let newname = newname.to_owned();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "rename", async move {
             let old_parent_id = try_fs!(fuse_helper.lock().await.get_id_by_inode(parent), reply);
             let new_parent_id =
                 try_fs!(fuse_helper.lock().await.get_id_by_inode(newparent), reply).clone();
             let mut fs = fs.write().await;
 
             let item = try_fs!(
-                fs.store
-                    .query_file_by_name(&old_parent_id, try_fs!(Self::check_os_str(&name), reply)),
+                fs.store.query_file_by_name(
+                    &old_parent_id,
+                    &try_fs!(Self::check_name(&name, normalize_names), reply)
+                ),
                 reply
             );
 
@@ -478,7 +714,7 @@ impl Filesystem for FuseOffsFilesystem {
                 fs.rename_file(
                     &item.id,
                     &new_parent_id,
-                    try_fs!(Self::check_os_str(&newname), reply),
+                    &try_fs!(Self::check_name(&newname, normalize_names), reply),
                 )
                 .await,
                 reply
@@ -489,22 +725,35 @@ impl Filesystem for FuseOffsFilesystem {
         });
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
-        debug!("Request(open): ino={}", ino);
+    fn open(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        debug!("Request(open): ino={}, flags={}", ino, flags);
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "open", async move {
             let mut fs = fs.write().await;
 
             let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
 
-            try_fs!(fs.update_dirent(&id, true).await, reply);
+            let dirent = try_fs!(fs.update_dirent(&id, true).await, reply);
             try_fs!(fs.update_chunks(&id).await, reply);
 
+            // Keeping the page cache lets the shared memory mappings of the file opened
+            // elsewhere stay coherent with this instance, as long as nobody else changed it
+            let keep_cache = fuse_helper
+                .lock()
+                .await
+                .update_cached_content_version(ino, dirent.content_version);
+            let flags = if flags & O_DIRECT != 0 {
+                FOPEN_DIRECT_IO
+            } else if keep_cache {
+                FOPEN_KEEP_CACHE
+            } else {
+                0
+            };
+
             let fh = fs.open_file_handler.open_file(id);
-            let flags: u32 = 0;
             debug!("Response: fh={}, flags={}", fh, flags);
             reply.opened(fh, flags);
         });
@@ -512,9 +761,9 @@ impl Filesystem for FuseOffsFilesystem {
 
     fn read(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        fh: u64,
+        _fh: u64,
         offset: i64,
         size: u32,
         _flags: i32,
@@ -528,17 +777,15 @@ impl Filesystem for FuseOffsFilesystem {
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
+        let timer = self.metrics.start_operation("read");
+
+        self.spawn(req, "read", async move {
+            let _timer = timer;
 
-        self.rt.spawn(async move {
             let mut fs = fs.write().await;
 
-            try_fs!(fs.flush_write_buffer(fh).await, reply);
-            let id = fuse_helper
-                .lock()
-                .await
-                .get_id_by_inode(ino)
-                .unwrap()
-                .clone();
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
+            try_fs!(fs.flush_file_write_buffers(&id).await, reply);
 
             let data = try_fs!(fs.read(&id, offset, size).await, reply);
             debug!("Response: {:?}", data);
@@ -548,7 +795,7 @@ impl Filesystem for FuseOffsFilesystem {
 
     fn write(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -565,8 +812,13 @@ impl Filesystem for FuseOffsFilesystem {
 
         let fs = self.fs.clone();
         let data = data.to_vec();
+        let free_space_guard = self.free_space_guard.clone();
+        let timer = self.metrics.start_operation("write");
 
-        self.rt.spawn(async move {
+        self.spawn(req, "write", async move {
+            let _timer = timer;
+
+            try_fs!(free_space_guard.check(), reply);
             let mut fs = fs.write().await;
 
             let rv = data.len() as u32;
@@ -578,7 +830,7 @@ impl Filesystem for FuseOffsFilesystem {
 
     fn release(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         fh: u64,
         _flags: i32,
@@ -591,39 +843,190 @@ impl Filesystem for FuseOffsFilesystem {
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "release", async move {
             let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
             let mut fs = fs.write().await;
 
             try_fs!(fs.flush_write_buffer(fh).await, reply);
-            try_fs!(fs.update_dirent(&id, true).await, reply);
-            fs.open_file_handler.close_file(fh);
+            let dirent = try_fs!(fs.update_dirent(&id, true).await, reply);
+            try_fs!(fs.close_file(fh), reply);
+            fuse_helper
+                .lock()
+                .await
+                .update_cached_content_version(ino, dirent.content_version);
+
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn flush(&mut self, req: &Request<'_>, ino: u64, fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        debug!("Request(flush): ino={}", ino);
+
+        let fs = self.fs.clone();
+
+        self.spawn(req, "flush", async move {
+            let mut fs = fs.write().await;
+
+            try_fs!(fs.flush_file(fh).await, reply);
+
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        // The data and the attributes are sent to the server in the same operations, so they
+        // are synced together either way
+        debug!("Request(fsync): ino={}, datasync={}", ino, datasync);
+
+        let fs = self.fs.clone();
+
+        self.spawn(req, "fsync", async move {
+            let mut fs = fs.write().await;
+
+            try_fs!(fs.sync_file(fh).await, reply);
+
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn fsyncdir(&mut self, req: &Request, ino: u64, _fh: u64, datasync: bool, reply: ReplyEmpty) {
+        debug!("Request(fsyncdir): ino={}, datasync={}", ino, datasync);
+
+        let fs = self.fs.clone();
+
+        self.spawn(req, "fsyncdir", async move {
+            let mut fs = fs.write().await;
+
+            try_fs!(fs.sync_directory().await, reply);
 
             debug!("Response: ok");
             reply.ok();
         });
     }
 
-    fn fsync(&mut self, _req: &Request, _ino: u64, fh: u64, _datasync: bool, reply: ReplyEmpty) {
-        debug!("Request(fsync)");
+    fn fallocate(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        debug!(
+            "Request(fallocate): ino={}, offset={}, length={}, mode={}",
+            ino, offset, length, mode
+        );
+
+        // Punching holes and zeroing ranges are the same thing here, as the holes read as
+        // zeros and take no space anyway
+        let zero_flags = FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE;
+        let zero = mode & zero_flags != 0;
+        let keep_size = mode & FALLOC_FL_KEEP_SIZE != 0;
+        if mode & !(zero_flags | FALLOC_FL_KEEP_SIZE) != 0
+            || mode & zero_flags == zero_flags
+            || (mode & FALLOC_FL_PUNCH_HOLE != 0 && !keep_size)
+        {
+            reply.error(EOPNOTSUPP);
+            return;
+        }
 
         let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
 
-        self.rt.spawn(async move {
+        self.spawn(req, "fallocate", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
             let mut fs = fs.write().await;
 
             try_fs!(fs.flush_write_buffer(fh).await, reply);
+            try_fs!(
+                fs.fallocate(&id, offset, length, zero, keep_size).await,
+                reply
+            );
 
             debug!("Response: ok");
             reply.ok();
         });
     }
 
+    fn copy_file_range(
+        &mut self,
+        req: &Request<'_>,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        debug!(
+            "Request(copy_file_range): ino_in={}, offset_in={}, ino_out={}, offset_out={}, len={}",
+            ino_in, offset_in, ino_out, offset_out, len
+        );
+
+        let overlapping = ino_in == ino_out
+            && offset_in < offset_out.saturating_add(len as i64)
+            && offset_out < offset_in.saturating_add(len as i64);
+        if flags != 0 || overlapping {
+            reply.error(EINVAL);
+            return;
+        }
+
+        // The number of bytes copied has to fit in the reply
+        let len = len.min(u32::MAX as u64);
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "copy_file_range", async move {
+            let (source_id, id) = {
+                let fuse_helper = fuse_helper.lock().await;
+                (
+                    try_fs!(fuse_helper.get_id_by_inode(ino_in), reply).clone(),
+                    try_fs!(fuse_helper.get_id_by_inode(ino_out), reply).clone(),
+                )
+            };
+            let mut fs = fs.write().await;
+
+            try_fs!(fs.flush_write_buffer(fh_in).await, reply);
+            try_fs!(fs.flush_write_buffer(fh_out).await, reply);
+            let (_, copied) = try_fs!(
+                fs.copy_range(&source_id, offset_in, &id, offset_out, len)
+                    .await,
+                reply
+            );
+
+            debug!("Response: {:?}", copied);
+            reply.written(copied as u32);
+        });
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        debug!("Request(opendir): ino={}, flags={}", ino, flags);
+
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "opendir", async move {
+            let fuse_helper = fuse_helper.lock().await;
+            try_fs!(fuse_helper.get_id_by_inode(ino), reply);
+
+            let fh = fuse_helper.open_dir();
+            debug!("Response: fh={}", fh);
+            reply.opened(fh, 0);
+        });
+    }
+
     fn readdir(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
-        _fh: u64,
+        fh: u64,
         offset: i64,
         mut reply: ReplyDirectory,
     ) {
@@ -631,33 +1034,242 @@ impl Filesystem for FuseOffsFilesystem {
 
         let fs = self.fs.clone();
         let fuse_helper = self.fuse_helper.clone();
+        let timer = self.metrics.start_operation("readdir");
+
+        self.spawn(req, "readdir", async move {
+            let _timer = timer;
 
-        self.rt.spawn(async move {
             let dir_id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
             let mut fs = fs.write().await;
 
-            try_fs!(fs.update_dirent(&dir_id, true).await, reply);
-            let items = try_fs!(fs.list_files(&dir_id).await, reply);
+            // The entries are retrieved from the server once, when the listing starts, and
+            // then served from the cache page by page
+            if offset == 0 {
+                try_fs!(fs.update_dirent(&dir_id, true).await, reply);
+                try_fs!(fs.retrieve_dir(&dir_id).await, reply);
+            }
+
+            // `.` and `..` have the offsets 1 and 2, and the files follow them
+            for (entry_offset, name) in [(1, "."), (2, "..")].iter() {
+                if offset < *entry_offset
+                    && reply.add(1, *entry_offset, fuser::FileType::Directory, name)
+                {
+                    debug!("Response: ok");
+                    reply.ok();
+                    return;
+                }
+            }
+
+            let cursor = fuse_helper.lock().await.get_dir_cursor(fh);
+            let mut cursor = match cursor {
+                _ if offset <= 2 => DirCursor::start(),
+                Some(cursor) if cursor.offset == offset => cursor,
+                // Seeking to an arbitrary position, which is rare, needs the preceding
+                // entries to be skipped
+                _ => try_fs!(Self::seek_dir(&fs, &dir_id, offset), reply),
+            };
+
+            'pages: loop {
+                let items = try_fs!(
+                    fs.store
+                        .list_files_page(&dir_id, &cursor.name, READDIR_PAGE_SIZE),
+                    reply
+                );
+                let is_last = items.len() < READDIR_PAGE_SIZE as usize;
 
-            let mut entries = vec![
-                (1, fuser::FileType::Directory, ".".to_owned()),
-                (1, fuser::FileType::Directory, "..".to_owned()),
-            ];
-            {
                 let fuse_helper_locked = fuse_helper.lock().await;
                 for dirent in items {
-                    entries.push((
-                        fuse_helper_locked.get_inode_for_id(&dirent.id),
-                        convert_file_type(dirent.stat.file_type),
-                        dirent.name,
-                    ));
+                    let inode = try_fs!(fuse_helper_locked.get_inode_for_id(&dirent.id), reply);
+                    let file_type = convert_file_type(dirent.stat.file_type);
+                    if reply.add(inode, cursor.offset + 1, file_type, &dirent.name) {
+                        // The buffer is full
+                        break 'pages;
+                    }
+
+                    cursor.offset += 1;
+                    cursor.name = dirent.name;
+                }
+                if is_last {
+                    break;
+                }
+            }
+
+            fuse_helper.lock().await.set_dir_cursor(fh, cursor);
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn releasedir(&mut self, req: &Request, ino: u64, fh: u64, _flags: i32, reply: ReplyEmpty) {
+        debug!("Request(releasedir): ino={}, fh={}", ino, fh);
+
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "releasedir", async move {
+            fuse_helper.lock().await.close_dir(fh);
+
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn access(&mut self, req: &Request<'_>, ino: u64, mask: i32, reply: ReplyEmpty) {
+        debug!("Request(access): ino={}, mask={}", ino, mask);
+
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+        let uid = req.uid();
+        let gid = req.gid();
+
+        self.spawn(req, "access", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply);
+            let fs = fs.read().await;
+
+            let dirent = try_fs!(fs.store.query_file(&id), reply);
+            if try_fs!(Self::is_access_allowed(&dirent, uid, gid, mask), reply) {
+                debug!("Response: ok");
+                reply.ok();
+            } else {
+                debug!("Response: access denied");
+                reply.error(EACCES);
+            }
+        });
+    }
+
+    fn setxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        debug!(
+            "Request(setxattr): ino={}, name={:?}, flags={}",
+            ino, name, flags
+        );
+
+        let acl_type = match Self::get_acl_type(name) {
+            Some(x) => x,
+            None => {
+                reply.error(ENOTSUP);
+                return;
+            }
+        };
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+        let value = value.to_vec();
+
+        self.spawn(req, "setxattr", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
+            let mut fs = fs.write().await;
+
+            let exists = try_fs!(fs.store.query_file(&id), reply)
+                .acl(acl_type)
+                .is_some();
+            if flags & XATTR_CREATE != 0 && exists {
+                reply.error(EEXIST);
+                return;
+            }
+            if flags & XATTR_REPLACE != 0 && !exists {
+                reply.error(ENODATA);
+                return;
+            }
+
+            try_fs!(fs.set_acl(&id, acl_type, value).await, reply);
+
+            debug!("Response: ok");
+            reply.ok();
+        });
+    }
+
+    fn getxattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        debug!(
+            "Request(getxattr): ino={}, name={:?}, size={}",
+            ino, name, size
+        );
+
+        let acl_type = match Self::get_acl_type(name) {
+            Some(x) => x,
+            None => {
+                reply.error(ENODATA);
+                return;
+            }
+        };
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "getxattr", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply);
+            let fs = fs.read().await;
+
+            let dirent = try_fs!(fs.store.query_file(&id), reply);
+            match dirent.acl(acl_type) {
+                Some(acl) => Self::reply_xattr(acl, size, reply),
+                None => reply.error(ENODATA),
+            }
+        });
+    }
+
+    fn listxattr(&mut self, req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        debug!("Request(listxattr): ino={}, size={}", ino, size);
+
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "listxattr", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply);
+            let fs = fs.read().await;
+
+            let dirent = try_fs!(fs.store.query_file(&id), reply);
+            let mut names = Vec::new();
+            for acl_type in [AclType::Access, AclType::Default] {
+                if dirent.acl(acl_type).is_some() {
+                    names.extend_from_slice(acl_type.xattr_name().as_bytes());
+                    names.push(0);
                 }
             }
 
-            let to_skip = if offset == 0 { offset } else { offset + 1 } as usize;
-            for (i, entry) in entries.into_iter().enumerate().skip(to_skip) {
-                reply.add(entry.0, i as i64, entry.1, &entry.2);
+            Self::reply_xattr(&names, size, reply);
+        });
+    }
+
+    fn removexattr(&mut self, req: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        debug!("Request(removexattr): ino={}, name={:?}", ino, name);
+
+        let acl_type = match Self::get_acl_type(name) {
+            Some(x) => x,
+            None => {
+                reply.error(ENODATA);
+                return;
+            }
+        };
+        let fs = self.fs.clone();
+        let fuse_helper = self.fuse_helper.clone();
+
+        self.spawn(req, "removexattr", async move {
+            let id = try_fs!(fuse_helper.lock().await.get_id_by_inode(ino), reply).clone();
+            let mut fs = fs.write().await;
+
+            if try_fs!(fs.store.query_file(&id), reply)
+                .acl(acl_type)
+                .is_none()
+            {
+                reply.error(ENODATA);
+                return;
             }
+
+            try_fs!(fs.set_acl(&id, acl_type, Vec::new()).await, reply);
+
             debug!("Response: ok");
             reply.ok();
         });
@@ -668,7 +1280,9 @@ impl Drop for FuseOffsFilesystem {
     fn drop(&mut self) {
         let fs = self.fs.clone();
         self.rt.block_on(async move {
-            fs.write().await.close_all_files().await.unwrap();
+            if let Err(e) = fs.write().await.close_all_files().await {
+                error!("Could not flush the open files: {}", e);
+            }
         });
     }
 }
@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use itertools::Itertools;
+
+/// Patterns of the names of the files which are kept only in the cache, and never journaled or
+/// sent to the server, along with everything inside the directories they match.
+///
+/// The patterns are given one per line, like in `.gitignore`, but they are matched against the
+/// names of the files rather than their paths. `*`, `?` and `[...]` are wildcards, `\` escapes
+/// the next character, a trailing `/` restricts the pattern to directories and a leading `!`
+/// negates it. The last pattern matching a name decides. Empty lines and the ones starting
+/// with `#` are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct IgnorePatterns {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug)]
+struct Pattern {
+    tokens: Vec<Token>,
+    negated: bool,
+    directories_only: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyString,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl IgnorePatterns {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let patterns = text
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(Pattern::parse)
+            .collect();
+
+        Self { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_ignored(&self, name: &str, is_directory: bool) -> bool {
+        let name = name.chars().collect_vec();
+
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| (is_directory || !pattern.directories_only) && pattern.matches(&name))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Self> {
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (directories_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        let chars = line.chars().collect_vec();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' => Token::AnyString,
+                '?' => Token::AnyChar,
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Char(chars[i])
+                }
+                '[' => match Self::parse_class(&chars[i + 1..]) {
+                    Some((token, length)) => {
+                        i += length;
+                        token
+                    }
+                    None => Token::Char('['),
+                },
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        if tokens.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            tokens,
+            negated,
+            directories_only,
+        })
+    }
+
+    /// Parses the character class following a `[`, returning it along with the number of
+    /// characters it spans, including the closing `]`. An unterminated class is not one.
+    fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+        let negated = matches!(chars.first(), Some('!') | Some('^'));
+        let mut i = if negated { 1 } else { 0 };
+        let mut ranges = Vec::new();
+
+        // A `]` right at the start is a part of the class
+        while i < chars.len() && (chars[i] != ']' || ranges.is_empty()) {
+            let start = chars[i];
+            if i + 2 < chars.len() && chars[i + 1] == '-' && chars[i + 2] != ']' {
+                ranges.push((start, chars[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((start, start));
+                i += 1;
+            }
+        }
+
+        if i == chars.len() {
+            return None;
+        }
+
+        Some((Token::Class { ranges, negated }, i + 1))
+    }
+
+    fn matches(&self, name: &[char]) -> bool {
+        let (mut t, mut n) = (0, 0);
+        // Where to resume from if the rest does not match: right after the last `*`, which
+        // then takes one more character
+        let mut backtrack = None;
+
+        while n < name.len() {
+            match self.tokens.get(t) {
+                Some(Token::AnyString) => {
+                    backtrack = Some((t + 1, n));
+                    t += 1;
+                    continue;
+                }
+                Some(token) if token.matches(name[n]) => {
+                    t += 1;
+                    n += 1;
+                    continue;
+                }
+                _ => {}
+            }
+
+            match backtrack {
+                Some((after_star, start)) => {
+                    backtrack = Some((after_star, start + 1));
+                    t = after_star;
+                    n = start + 1;
+                }
+                None => return false,
+            }
+        }
+
+        self.tokens[t..]
+            .iter()
+            .all(|token| matches!(token, Token::AnyString))
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(x) => *x == c,
+            Token::AnyChar => true,
+            Token::AnyString => true,
+            Token::Class { ranges, negated } => {
+                ranges
+                    .iter()
+                    .any(|(start, end)| (*start..=*end).contains(&c))
+                    != *negated
+            }
+        }
+    }
+}
@@ -1,56 +1,62 @@
+use std::convert::TryInto;
 use std::sync::atomic::Ordering;
 
+use bytes::Bytes;
 use itertools::Itertools;
-use log::info;
 use prost::Message;
+use tracing::{error, info};
 
-use offs::errors::{JournalApplyData, JournalApplyError, OperationResult};
-use offs::modify_op::ModifyOperation;
+use offs::errors::{JournalApplyData, JournalApplyError, JournalOperationStatus, OperationResult};
+use offs::modify_op::{ModifyOperation, ModifyOperationContent};
+use offs::now;
 use offs::proto::filesystem as proto_types;
 use offs::store::id_generator::LocalTempIdGenerator;
 
 use super::super::client::modify_op_builder::ModifyOpBuilder;
 use super::OffsFilesystem;
+use crate::events::{ConflictResolution, SyncEvent};
+use crate::transfers::TransferDirection;
 
 const JOURNAL_MAX_RETRIES: u32 = 10;
+const JOURNAL_TRANSFER_NAME: &str = "<journal>";
+
+/// An operation of the journal, along with what would happen to it if the journal was sent now.
+pub struct JournalReviewEntry {
+    /// Path of the file, relative to the root of the filesystem, or its ID if it is no longer
+    /// cached (e.g. it has been removed)
+    pub path: String,
+    pub operation: &'static str,
+    pub status: JournalOperationStatus,
+}
 
 impl OffsFilesystem {
     pub(super) async fn apply_journal(&mut self) -> OperationResult<()> {
         info!("Applying journal");
+        let reconnected = self.should_flush_journal.load(Ordering::Relaxed);
+        let operations = self.store.get_journal_size()?;
 
-        let JournalApplyData {
-            assigned_ids,
-            dir_entities,
-        } = self.prepare_and_send_journal().await?;
-        self.should_flush_journal.store(false, Ordering::Relaxed);
-
-        if assigned_ids.is_empty() && dir_entities.is_empty() {
-            return Ok(());
-        }
-
-        let transaction = self.store.transaction();
-
-        for (i, id) in assigned_ids.iter().enumerate() {
-            self.store
-                .change_id(&LocalTempIdGenerator::get_nth_id(i), id)?;
-        }
-        for mut dirent in dir_entities {
-            self.add_dirent(&mut dirent)?;
-        }
-        self.store.clear_journal()?;
-
-        transaction.commit().unwrap();
-
-        info!("Done applying journal");
+        for _ in 0..JOURNAL_MAX_RETRIES {
+            let applied = match self.try_apply_journal().await {
+                Ok(applied) => applied,
+                Err(e) => {
+                    self.events.emit(SyncEvent::SyncFailed {
+                        error: e.to_string(),
+                    });
+                    return Err(e);
+                }
+            };
+            if applied {
+                self.should_flush_journal.store(false, Ordering::Relaxed);
+                info!("Done applying journal");
+                if operations > 0 {
+                    self.events.emit(SyncEvent::SyncComplete { operations });
+                }
 
-        Ok(())
-    }
+                if reconnected {
+                    self.refresh_dirents().await?;
+                }
 
-    async fn prepare_and_send_journal(&mut self) -> OperationResult<JournalApplyData> {
-        for _ in 0..JOURNAL_MAX_RETRIES {
-            let result = self.try_prepare_and_send_journal().await?;
-            if let Some(journal_apply_data) = result {
-                return Ok(journal_apply_data);
+                return Ok(());
             }
         }
 
@@ -60,46 +66,155 @@ impl OffsFilesystem {
         );
     }
 
-    async fn try_prepare_and_send_journal(&mut self) -> OperationResult<Option<JournalApplyData>> {
-        let ops = self.prepare_ops_to_send()?;
+    /// Checks the journal against the current state of the files on the server, without
+    /// applying it. The server is contacted even in the offline mode, so that the changes can be
+    /// reviewed before going online.
+    pub async fn review_journal(&mut self) -> OperationResult<Vec<JournalReviewEntry>> {
+        let (_, ops) = self.prepare_ops_to_send()?;
         if ops.is_empty() {
-            info!("No journal entries, skipping");
-            return Ok(Some(Default::default()));
+            return Ok(Vec::new());
         }
         let chunks = self.prepare_chunks_to_send()?;
-        let blobs = self.prepare_blobs_to_send().await?;
 
-        let result = self.client.apply_journal(ops, chunks, blobs).await?;
+        let paths = ops
+            .iter()
+            .map(|op| {
+                let path = match self.get_path(&op.id) {
+                    Ok(path) => path,
+                    Err(_) => return op.id.clone(),
+                };
+                // The creations are made on the parent directory
+                let name = match &op.operation {
+                    ModifyOperationContent::CreateFileOperation(x) => &x.name,
+                    ModifyOperationContent::CreateSymlinkOperation(x) => &x.name,
+                    ModifyOperationContent::CreateDirectoryOperation(x) => &x.name,
+                    _ => return path,
+                };
+                format!("{}/{}", path.trim_end_matches('/'), name)
+            })
+            .collect_vec();
+        let operations = ops.iter().map(|op| op.operation.name()).collect_vec();
+        let statuses = self.client.validate_journal(ops, chunks).await?;
 
-        if let Ok(result_ok) = result {
-            return Ok(Some(result_ok));
+        Ok(paths
+            .into_iter()
+            .zip(operations)
+            .zip(statuses)
+            .map(|((path, operation), status)| JournalReviewEntry {
+                path,
+                operation,
+                status,
+            })
+            .collect())
+    }
+
+    /// Sends the journal to the server and removes the operations it has applied. Returns
+    /// whether the whole journal has been applied.
+    async fn try_apply_journal(&mut self) -> OperationResult<bool> {
+        let (entry_ids, ops) = self.prepare_ops_to_send()?;
+        if ops.is_empty() {
+            info!("No journal entries, skipping");
+            return Ok(true);
         }
+        let file_ids = ops.iter().map(|x| x.id.clone()).collect_vec();
+        let chunks = self.prepare_chunks_to_send()?;
+        let blobs = self.prepare_blobs_to_send().await?;
 
-        match result.err().unwrap() {
-            JournalApplyError::InvalidJournal => {
-                panic!("The file operation journal is corrupted");
+        let blobs_size = blobs.iter().map(|x| x.len() as u64).sum();
+        let result = {
+            let transfer =
+                self.transfers
+                    .start(TransferDirection::Upload, JOURNAL_TRANSFER_NAME, blobs_size);
+            self.client
+                .apply_journal(ops, chunks, blobs, &mut |blob| {
+                    transfer.add_progress(blob.len() as u64)
+                })
+                .await?
+        };
+
+        let JournalApplyData {
+            assigned_ids,
+            dir_entities,
+            statuses,
+        } = match result {
+            Ok(data) => data,
+            Err(JournalApplyError::MissingBlobs(_)) => {
+                // Do nothing, we will re-query the missing blobs in the next iteration
+                return Ok(false);
             }
-            JournalApplyError::ConflictingFiles(ids) => {
-                self.recreate_conflicting_files(ids)?;
+        };
+
+        let mut conflicting_ids = Vec::new();
+        let mut quarantined = Vec::new();
+        let mut changed_ids = Vec::new();
+        let transaction = self.store.transaction();
+
+        // Iterate backwards, so that the files are removed before their parent directories
+        for (i, id) in assigned_ids.iter().enumerate().rev() {
+            let temp_id = LocalTempIdGenerator::get_nth_id(i);
+            if !id.is_empty() {
+                self.store.change_id(&temp_id, id)?;
+                changed_ids.push((temp_id, id));
+            } else if self.store.try_query_file(&temp_id)?.is_some() {
+                self.store.remove_file(&temp_id, now())?;
             }
-            JournalApplyError::MissingBlobs(_) => {
-                // Do nothing, we will re-query the missing blobs in the next iteration
+        }
+        for mut dirent in dir_entities {
+            self.add_dirent(&mut dirent)?;
+        }
+        for ((entry_id, file_id), status) in entry_ids.into_iter().zip(file_ids).zip(statuses) {
+            match status {
+                JournalOperationStatus::Applied => self.store.remove_journal_item(entry_id)?,
+                JournalOperationStatus::Conflicted => conflicting_ids.push(file_id),
+                JournalOperationStatus::Rejected(reason) => {
+                    // Keep syncing the rest of the journal, the entry can be reviewed later
+                    error!(
+                        "Server rejected journal operation on {}: {}",
+                        file_id, reason
+                    );
+                    self.store.quarantine_journal_item(entry_id, &reason)?;
+                    quarantined.push((file_id, reason));
+                }
             }
         }
+        // All the temporary files have either been assigned an ID or removed by now
+        self.store.reset_id_generator();
 
-        Ok(None)
+        transaction.commit().unwrap();
+
+        // The temporary IDs are reused from now on, so the opened files must not refer to them
+        for (temp_id, id) in &changed_ids {
+            self.open_file_handler.change_id(temp_id, id);
+        }
+
+        for (file_id, reason) in quarantined {
+            let file_id = changed_ids
+                .iter()
+                .find(|(temp_id, _)| *temp_id == file_id)
+                .map_or(file_id, |(_, id)| (*id).clone());
+            self.emit_conflict(file_id, ConflictResolution::Quarantined(reason));
+        }
+
+        if conflicting_ids.is_empty() {
+            return Ok(true);
+        }
+        self.recreate_conflicting_files(conflicting_ids.into_iter().unique().collect())?;
+
+        Ok(false)
     }
 
-    fn prepare_ops_to_send(&mut self) -> OperationResult<Vec<ModifyOperation>> {
-        Ok(self
+    fn prepare_ops_to_send(&mut self) -> OperationResult<(Vec<i64>, Vec<ModifyOperation>)> {
+        let ops: Vec<(i64, ModifyOperation)> = self
             .store
             .get_journal()?
             .into_iter()
-            .map(|x| {
+            .map(|(id, x)| {
                 let parsed = proto_types::ModifyOperation::decode(x.as_slice()).unwrap();
-                parsed.into()
+                Ok((id, parsed.try_into()?))
             })
-            .collect_vec())
+            .collect::<OperationResult<_>>()?;
+
+        Ok(ops.into_iter().unzip())
     }
 
     fn prepare_chunks_to_send(&mut self) -> OperationResult<Vec<Vec<String>>> {
@@ -110,7 +225,7 @@ impl OffsFilesystem {
             .collect())
     }
 
-    async fn prepare_blobs_to_send(&mut self) -> OperationResult<Vec<Vec<u8>>> {
+    async fn prepare_blobs_to_send(&mut self) -> OperationResult<Vec<Bytes>> {
         let blobs_used = self.store.get_temp_chunks()?;
         let blob_ids_to_send = self.client.get_server_missing_blobs(blobs_used).await?;
         let blobs_to_send = self.store.get_blobs(&blob_ids_to_send)?;
@@ -120,20 +235,42 @@ impl OffsFilesystem {
     fn recreate_conflicting_files(&mut self, ids: Vec<String>) -> OperationResult<()> {
         let transaction = self.store.transaction();
 
+        let mut new_ids = Vec::new();
         for id in ids {
-            self.recreate_conflicting_file(&id)?;
+            new_ids.push(self.recreate_conflicting_file(&id)?);
         }
 
         transaction.commit().unwrap();
 
+        for id in new_ids {
+            self.emit_conflict(id, ConflictResolution::Recreated);
+        }
+
         Ok(())
     }
 
-    fn recreate_conflicting_file(&mut self, id: &String) -> OperationResult<()> {
+    fn emit_conflict(&self, file_id: String, resolution: ConflictResolution) {
+        let path = self.get_path(&file_id).ok();
+        self.events.emit(SyncEvent::Conflict {
+            file_id,
+            path,
+            resolution,
+        });
+    }
+
+    /// Returns the temporary ID assigned to the file.
+    fn recreate_conflicting_file(&mut self, id: &String) -> OperationResult<String> {
         self.store.remove_file_from_journal(&id)?;
         let new_id = self.store.assign_temp_id(&id)?;
+        self.open_file_handler.change_id(id, &new_id);
 
-        let dirent = self.store.query_file(&new_id)?;
+        // The file is created anew on the server, so its versions start over
+        let mut dirent = self.store.query_file(&new_id)?;
+        dirent.dirent_version = 1;
+        dirent.content_version = 1;
+        dirent.content_vector = Default::default();
+        dirent.dirent_vector = Default::default();
+        self.store.add_or_replace_dirent(&dirent)?;
         let parent_dirent = self.store.query_file(&dirent.parent)?;
 
         let recreate_file_op = ModifyOpBuilder::make_recreate_file_op(&parent_dirent, &dirent);
@@ -146,6 +283,6 @@ impl OffsFilesystem {
         self.store
             .add_journal_entry(&new_id, &reset_attributes_op_proto.encode_to_vec())?;
 
-        Ok(())
+        Ok(new_id)
     }
 }
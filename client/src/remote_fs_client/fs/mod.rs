@@ -1,12 +1,34 @@
+pub use api::OffsClient;
+pub use export::Export;
+pub use free_space::FreeSpaceGuard;
 pub use fs::OffsFilesystem;
+#[cfg(feature = "fuse")]
 pub use fuse_fs::FuseOffsFilesystem;
+pub use ignore::IgnorePatterns;
+pub use journal::JournalReviewEntry;
+#[cfg(feature = "nfs")]
+pub use nfs_fs::NfsOffsFilesystem;
+pub use request_supervisor::RequestSupervisor;
+pub use sidecar::SidecarEntry;
 
+mod api;
+#[cfg(feature = "fuse")]
 mod errors;
+mod export;
 #[macro_use]
 mod fs;
 mod file_ops;
+mod free_space;
+#[cfg(feature = "fuse")]
 mod fuse_fs;
+mod ignore;
 mod journal;
+#[cfg(feature = "nfs")]
+mod nfs_fs;
 mod open_file_handler;
 mod operation_handler;
+#[cfg(feature = "fuse")]
+mod platform;
+mod request_supervisor;
+mod sidecar;
 mod write_buffer;
@@ -0,0 +1,532 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use nfsserve::nfs::{
+    fattr3, fileid3, filename3, ftype3, nfspath3, nfsstat3, nfsstring, nfstime3, sattr3, set_atime,
+    set_gid3, set_mode3, set_mtime, set_size3, set_uid3, specdata3,
+};
+use nfsserve::vfs::{DirEntry, NFSFileSystem, ReadDirResult, VFSCapabilities};
+use tokio::sync::{Mutex, RwLock};
+use tracing::debug;
+
+use offs::errors::{OperationError, OperationErrorType, OperationResult};
+use offs::names::normalize_name;
+use offs::store::{DirEntity, FileMode, FileType};
+use offs::timespec::Timespec;
+use offs::{now, ROOT_ID};
+
+use super::free_space::FreeSpaceGuard;
+use super::OffsFilesystem;
+
+const ROOT_FILEID: fileid3 = 1;
+const DEFAULT_FILE_MODE: FileMode = 0o644;
+const DEFAULT_DIRECTORY_MODE: FileMode = 0o755;
+
+macro_rules! try_nfs {
+    ($e:expr) => {
+        match $e {
+            Ok(val) => val,
+            Err(e) => {
+                debug!("Response: {:?}", e);
+                return Err(to_nfs_error(&e));
+            }
+        }
+    };
+}
+
+fn to_nfs_error(operation_error: &OperationError) -> nfsstat3 {
+    match operation_error.error_type {
+        OperationErrorType::DatabaseError => nfsstat3::NFS3ERR_SERVERFAULT,
+        OperationErrorType::DirectoryNotEmpty => nfsstat3::NFS3ERR_NOTEMPTY,
+        OperationErrorType::ConflictedFile => nfsstat3::NFS3ERR_EXIST,
+        OperationErrorType::InvalidContentVersion => nfsstat3::NFS3ERR_STALE,
+        OperationErrorType::BlobDoesNotExist => nfsstat3::NFS3ERR_IO,
+        OperationErrorType::Offline => nfsstat3::NFS3ERR_JUKEBOX,
+        OperationErrorType::FileDoesNotExist => nfsstat3::NFS3ERR_NOENT,
+        OperationErrorType::InvalidUnicode => nfsstat3::NFS3ERR_INVAL,
+        OperationErrorType::ShareDoesNotExist => nfsstat3::NFS3ERR_NOENT,
+        OperationErrorType::Unauthenticated => nfsstat3::NFS3ERR_ACCES,
+        OperationErrorType::InvalidAcl => nfsstat3::NFS3ERR_INVAL,
+        OperationErrorType::NoSpace => nfsstat3::NFS3ERR_NOSPC,
+        OperationErrorType::RenameIntoDescendant => nfsstat3::NFS3ERR_INVAL,
+        OperationErrorType::InvalidArgument => nfsstat3::NFS3ERR_INVAL,
+        OperationErrorType::UnsupportedProtocol => nfsstat3::NFS3ERR_SERVERFAULT,
+        OperationErrorType::RequestTooLarge => nfsstat3::NFS3ERR_FBIG,
+        OperationErrorType::RateLimited => nfsstat3::NFS3ERR_JUKEBOX,
+        OperationErrorType::QuotaExceeded => nfsstat3::NFS3ERR_DQUOT,
+        OperationErrorType::CrossDevice => nfsstat3::NFS3ERR_XDEV,
+        OperationErrorType::PermissionDenied => nfsstat3::NFS3ERR_PERM,
+        OperationErrorType::NameTooLong => nfsstat3::NFS3ERR_NAMETOOLONG,
+        OperationErrorType::NotADirectory => nfsstat3::NFS3ERR_NOTDIR,
+        OperationErrorType::IsADirectory => nfsstat3::NFS3ERR_ISDIR,
+        OperationErrorType::StaleHandle => nfsstat3::NFS3ERR_STALE,
+    }
+}
+
+fn convert_file_type(file_type: FileType) -> ftype3 {
+    match file_type {
+        FileType::NamedPipe => ftype3::NF3FIFO,
+        FileType::CharDevice => ftype3::NF3CHR,
+        FileType::BlockDevice => ftype3::NF3BLK,
+        FileType::Directory => ftype3::NF3DIR,
+        FileType::RegularFile => ftype3::NF3REG,
+        FileType::Symlink => ftype3::NF3LNK,
+        FileType::Socket => ftype3::NF3SOCK,
+    }
+}
+
+fn convert_time(time: Timespec) -> nfstime3 {
+    nfstime3 {
+        seconds: time.sec as u32,
+        nseconds: time.nsec,
+    }
+}
+
+fn convert_set_time(time: nfstime3) -> Timespec {
+    Timespec::new(time.seconds as i64, time.nseconds)
+}
+
+/// Assigns the NFS file IDs to the file IDs of the store. Unlike the inode numbers of the FUSE
+/// frontend, they are only kept in memory, so they change when the server restarts.
+struct FileIds {
+    next_fileid: fileid3,
+    ids: HashMap<fileid3, String>,
+    fileids: HashMap<String, fileid3>,
+}
+
+impl FileIds {
+    fn new() -> Self {
+        Self {
+            next_fileid: ROOT_FILEID + 1,
+            ids: [(ROOT_FILEID, ROOT_ID.to_owned())]
+                .iter()
+                .cloned()
+                .collect(),
+            fileids: [(ROOT_ID.to_owned(), ROOT_FILEID)]
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn get_fileid(&mut self, id: &str) -> fileid3 {
+        if let Some(fileid) = self.fileids.get(id) {
+            return *fileid;
+        }
+
+        let fileid = self.next_fileid;
+        self.next_fileid += 1;
+        self.ids.insert(fileid, id.to_owned());
+        self.fileids.insert(id.to_owned(), fileid);
+
+        fileid
+    }
+
+    fn get_id(&self, fileid: fileid3) -> Result<String, nfsstat3> {
+        self.ids
+            .get(&fileid)
+            .cloned()
+            .ok_or(nfsstat3::NFS3ERR_STALE)
+    }
+}
+
+/// Serves the filesystem over NFSv3, for the machines that cannot use FUSE.
+pub struct NfsOffsFilesystem {
+    fs: Arc<RwLock<OffsFilesystem>>,
+    file_ids: Mutex<FileIds>,
+    free_space_guard: FreeSpaceGuard,
+    normalize_names: bool,
+}
+
+impl NfsOffsFilesystem {
+    pub fn new(
+        fs: Arc<RwLock<OffsFilesystem>>,
+        free_space_guard: FreeSpaceGuard,
+        normalize_names: bool,
+    ) -> Self {
+        Self {
+            fs,
+            file_ids: Mutex::new(FileIds::new()),
+            free_space_guard,
+            normalize_names,
+        }
+    }
+
+    async fn get_id(&self, fileid: fileid3) -> Result<String, nfsstat3> {
+        self.file_ids.lock().await.get_id(fileid)
+    }
+
+    fn check_name(&self, name: &nfsstring) -> OperationResult<String> {
+        let name = std::str::from_utf8(&name.0).map_err(|_| OperationError::invalid_unicode())?;
+
+        Ok(if self.normalize_names {
+            normalize_name(name).into_owned()
+        } else {
+            name.to_owned()
+        })
+    }
+
+    async fn get_attr(&self, dirent: &DirEntity) -> fattr3 {
+        let fileid = self.file_ids.lock().await.get_fileid(&dirent.id);
+
+        fattr3 {
+            ftype: convert_file_type(dirent.stat.file_type),
+            mode: dirent.stat.mode as u32,
+            nlink: dirent.stat.nlink as u32,
+            uid: dirent.stat.uid,
+            gid: dirent.stat.gid,
+            size: dirent.stat.size,
+            used: dirent.stat.size,
+            rdev: specdata3::default(),
+            fsid: 0,
+            fileid,
+            atime: convert_time(dirent.stat.atim),
+            mtime: convert_time(dirent.stat.mtim),
+            ctime: convert_time(dirent.stat.ctim),
+        }
+    }
+
+    async fn query_by_name(
+        &self,
+        fs: &mut OffsFilesystem,
+        parent_id: &str,
+        name: &filename3,
+    ) -> Result<DirEntity, nfsstat3> {
+        // Make sure the file entry is up to date
+        try_nfs!(fs.retrieve_dir(parent_id).await);
+
+        Ok(try_nfs!(fs.store.query_file_by_name(
+            parent_id,
+            &try_nfs!(self.check_name(name))
+        )))
+    }
+}
+
+#[async_trait]
+impl NFSFileSystem for NfsOffsFilesystem {
+    fn capabilities(&self) -> VFSCapabilities {
+        VFSCapabilities::ReadWrite
+    }
+
+    fn root_dir(&self) -> fileid3 {
+        ROOT_FILEID
+    }
+
+    async fn lookup(&self, dirid: fileid3, filename: &filename3) -> Result<fileid3, nfsstat3> {
+        debug!("Request(lookup): dirid={}, filename={:?}", dirid, filename);
+
+        let parent_id = self.get_id(dirid).await?;
+        let mut fs = self.fs.write().await;
+        let dirent = self.query_by_name(&mut fs, &parent_id, filename).await?;
+
+        Ok(self.file_ids.lock().await.get_fileid(&dirent.id))
+    }
+
+    async fn getattr(&self, id: fileid3) -> Result<fattr3, nfsstat3> {
+        debug!("Request(getattr): id={}", id);
+
+        let file_id = self.get_id(id).await?;
+        if self.fs.read().await.has_buffered_writes(&file_id) {
+            try_nfs!(
+                self.fs
+                    .write()
+                    .await
+                    .flush_file_write_buffers(&file_id)
+                    .await
+            );
+        }
+        let dirent = try_nfs!(self.fs.read().await.get_attributes(&file_id));
+
+        Ok(self.get_attr(&dirent).await)
+    }
+
+    async fn setattr(&self, id: fileid3, setattr: sattr3) -> Result<fattr3, nfsstat3> {
+        debug!("Request(setattr): id={}, setattr={:?}", id, setattr);
+
+        let file_id = self.get_id(id).await?;
+        let mode = match setattr.mode {
+            set_mode3::mode(mode) => Some(mode as FileMode),
+            set_mode3::Void => None,
+        };
+        let uid = match setattr.uid {
+            set_uid3::uid(uid) => Some(uid),
+            set_uid3::Void => None,
+        };
+        let gid = match setattr.gid {
+            set_gid3::gid(gid) => Some(gid),
+            set_gid3::Void => None,
+        };
+        let size = match setattr.size {
+            set_size3::size(size) => Some(size),
+            set_size3::Void => None,
+        };
+        let atime = match setattr.atime {
+            set_atime::SET_TO_CLIENT_TIME(time) => Some(convert_set_time(time)),
+            set_atime::SET_TO_SERVER_TIME => Some(now()),
+            set_atime::DONT_CHANGE => None,
+        };
+        let mtime = match setattr.mtime {
+            set_mtime::SET_TO_CLIENT_TIME(time) => Some(convert_set_time(time)),
+            set_mtime::SET_TO_SERVER_TIME => Some(now()),
+            set_mtime::DONT_CHANGE => None,
+        };
+
+        let mut fs = self.fs.write().await;
+        let dirent = try_nfs!(
+            fs.set_attributes(&file_id, mode, uid, gid, size, atime, mtime)
+                .await
+        );
+
+        Ok(self.get_attr(&dirent).await)
+    }
+
+    async fn read(
+        &self,
+        id: fileid3,
+        offset: u64,
+        count: u32,
+    ) -> Result<(Vec<u8>, bool), nfsstat3> {
+        debug!(
+            "Request(read): id={}, offset={}, count={}",
+            id, offset, count
+        );
+
+        let file_id = self.get_id(id).await?;
+        let mut fs = self.fs.write().await;
+
+        // NFS has no notion of opening a file, so the content is refreshed whenever it is read
+        // from the beginning
+        if offset == 0 {
+            try_nfs!(fs.update_chunks(&file_id).await);
+        }
+        try_nfs!(fs.flush_file_write_buffers(&file_id).await);
+
+        let data = try_nfs!(fs.read(&file_id, offset as i64, count).await);
+        let size = try_nfs!(fs.store.query_file(&file_id)).stat.size;
+
+        Ok((data.to_vec(), offset + data.len() as u64 >= size))
+    }
+
+    async fn write(&self, id: fileid3, offset: u64, data: &[u8]) -> Result<fattr3, nfsstat3> {
+        debug!(
+            "Request(write): id={}, offset={}, len={}",
+            id,
+            offset,
+            data.len()
+        );
+
+        let file_id = self.get_id(id).await?;
+        try_nfs!(self.free_space_guard.check());
+        let mut fs = self.fs.write().await;
+
+        let fh = fs.open_file_handler.open_file(file_id.clone());
+        let result = match fs.write(fh, offset as i64, data.to_vec()).await {
+            Ok(()) => fs.flush_write_buffer(fh).await,
+            Err(e) => Err(e),
+        };
+        fs.open_file_handler.close_file(fh);
+        try_nfs!(result);
+
+        let dirent = try_nfs!(fs.store.query_file(&file_id));
+        Ok(self.get_attr(&dirent).await)
+    }
+
+    async fn create(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+        attr: sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        debug!("Request(create): dirid={}, filename={:?}", dirid, filename);
+
+        let parent_id = self.get_id(dirid).await?;
+        let mode = match attr.mode {
+            set_mode3::mode(mode) => mode as FileMode,
+            set_mode3::Void => DEFAULT_FILE_MODE,
+        };
+        let name = try_nfs!(self.check_name(filename));
+
+        let mut fs = self.fs.write().await;
+        let dirent = try_nfs!(
+            fs.create_file(&parent_id, &name, FileType::RegularFile, mode, 0, None)
+                .await
+        );
+
+        let attr = self.get_attr(&dirent).await;
+        Ok((attr.fileid, attr))
+    }
+
+    async fn create_exclusive(
+        &self,
+        dirid: fileid3,
+        filename: &filename3,
+    ) -> Result<fileid3, nfsstat3> {
+        debug!(
+            "Request(create_exclusive): dirid={}, filename={:?}",
+            dirid, filename
+        );
+
+        let parent_id = self.get_id(dirid).await?;
+        let name = try_nfs!(self.check_name(filename));
+
+        let mut fs = self.fs.write().await;
+        try_nfs!(fs.retrieve_dir(&parent_id).await);
+        if try_nfs!(fs.store.file_exists_by_name(&parent_id, &name)) {
+            return Err(nfsstat3::NFS3ERR_EXIST);
+        }
+        let dirent = try_nfs!(
+            fs.create_file(
+                &parent_id,
+                &name,
+                FileType::RegularFile,
+                DEFAULT_FILE_MODE,
+                0,
+                None
+            )
+            .await
+        );
+
+        Ok(self.file_ids.lock().await.get_fileid(&dirent.id))
+    }
+
+    async fn mkdir(
+        &self,
+        dirid: fileid3,
+        dirname: &filename3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        debug!("Request(mkdir): dirid={}, dirname={:?}", dirid, dirname);
+
+        let parent_id = self.get_id(dirid).await?;
+        let name = try_nfs!(self.check_name(dirname));
+
+        let mut fs = self.fs.write().await;
+        let dirent = try_nfs!(
+            fs.create_directory(&parent_id, &name, DEFAULT_DIRECTORY_MODE, None)
+                .await
+        );
+
+        let attr = self.get_attr(&dirent).await;
+        Ok((attr.fileid, attr))
+    }
+
+    async fn remove(&self, dirid: fileid3, filename: &filename3) -> Result<(), nfsstat3> {
+        debug!("Request(remove): dirid={}, filename={:?}", dirid, filename);
+
+        let parent_id = self.get_id(dirid).await?;
+        let mut fs = self.fs.write().await;
+        let dirent = self.query_by_name(&mut fs, &parent_id, filename).await?;
+
+        if dirent.stat.file_type == FileType::Directory {
+            try_nfs!(fs.remove_directory(&dirent.id).await);
+        } else {
+            try_nfs!(fs.remove_file(&dirent.id).await);
+        }
+
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        from_dirid: fileid3,
+        from_filename: &filename3,
+        to_dirid: fileid3,
+        to_filename: &filename3,
+    ) -> Result<(), nfsstat3> {
+        debug!(
+            "Request(rename): from_dirid={}, from_filename={:?}, to_dirid={}, to_filename={:?}",
+            from_dirid, from_filename, to_dirid, to_filename
+        );
+
+        let old_parent_id = self.get_id(from_dirid).await?;
+        let new_parent_id = self.get_id(to_dirid).await?;
+        let new_name = try_nfs!(self.check_name(to_filename));
+
+        let mut fs = self.fs.write().await;
+        let dirent = self
+            .query_by_name(&mut fs, &old_parent_id, from_filename)
+            .await?;
+        try_nfs!(fs.rename_file(&dirent.id, &new_parent_id, &new_name).await);
+
+        Ok(())
+    }
+
+    async fn readdir(
+        &self,
+        dirid: fileid3,
+        start_after: fileid3,
+        max_entries: usize,
+    ) -> Result<ReadDirResult, nfsstat3> {
+        debug!(
+            "Request(readdir): dirid={}, start_after={}, max_entries={}",
+            dirid, start_after, max_entries
+        );
+
+        let dir_id = self.get_id(dirid).await?;
+        let mut items = try_nfs!(self.fs.write().await.list_files(&dir_id).await);
+        // The listing has to be deterministic for the pagination to work
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut entries = Vec::new();
+        for item in &items {
+            let attr = self.get_attr(item).await;
+            entries.push(DirEntry {
+                fileid: attr.fileid,
+                name: item.name.as_bytes().into(),
+                attr,
+            });
+        }
+
+        let start = if start_after == 0 {
+            0
+        } else {
+            entries
+                .iter()
+                .position(|x| x.fileid == start_after)
+                .map(|x| x + 1)
+                .ok_or(nfsstat3::NFS3ERR_BAD_COOKIE)?
+        };
+        let end = start + max_entries >= entries.len();
+        let entries = entries.into_iter().skip(start).take(max_entries).collect();
+
+        Ok(ReadDirResult { entries, end })
+    }
+
+    async fn symlink(
+        &self,
+        dirid: fileid3,
+        linkname: &filename3,
+        symlink: &nfspath3,
+        _attr: &sattr3,
+    ) -> Result<(fileid3, fattr3), nfsstat3> {
+        debug!(
+            "Request(symlink): dirid={}, linkname={:?}, symlink={:?}",
+            dirid, linkname, symlink
+        );
+
+        let parent_id = self.get_id(dirid).await?;
+        let name = try_nfs!(self.check_name(linkname));
+        let link = try_nfs!(
+            std::str::from_utf8(&symlink.0).map_err(|_| OperationError::invalid_unicode())
+        );
+
+        let mut fs = self.fs.write().await;
+        let dirent = try_nfs!(fs.create_symlink(&parent_id, &name, link, None).await);
+
+        let attr = self.get_attr(&dirent).await;
+        Ok((attr.fileid, attr))
+    }
+
+    async fn readlink(&self, id: fileid3) -> Result<nfspath3, nfsstat3> {
+        debug!("Request(readlink): id={}", id);
+
+        let file_id = self.get_id(id).await?;
+        let mut fs = self.fs.write().await;
+
+        try_nfs!(fs.update_chunks(&file_id).await);
+        let dirent = try_nfs!(fs.store.query_file(&file_id));
+        let data = try_nfs!(fs.read(&file_id, 0, dirent.stat.size as u32).await);
+
+        Ok(data.to_vec().into())
+    }
+}
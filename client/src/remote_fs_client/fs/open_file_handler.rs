@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::remote_fs_client::fs::write_buffer::{WriteBuffer, WriteOperation};
 use itertools::Itertools;
+use offs::errors::OperationError;
 
 struct OpenFile {
     id: String,
     write_buffer: WriteBuffer,
+    /// Error encountered when writing the buffered data, not yet reported to the user
+    error: Option<OperationError>,
 }
 
 impl OpenFile {
@@ -13,6 +16,7 @@ impl OpenFile {
         Self {
             id,
             write_buffer: WriteBuffer::new(),
+            error: None,
         }
     }
 }
@@ -20,6 +24,8 @@ impl OpenFile {
 pub struct OpenFileHandler {
     files: HashMap<u64, OpenFile>,
     next_fh: u64,
+    /// Files removed while opened, to be removed from the cache once their last handle is closed
+    unlinked: HashSet<String>,
 }
 
 impl OpenFileHandler {
@@ -27,6 +33,7 @@ impl OpenFileHandler {
         Self {
             files: Default::default(),
             next_fh: 1,
+            unlinked: HashSet::new(),
         }
     }
 
@@ -39,14 +46,72 @@ impl OpenFileHandler {
         fh
     }
 
-    pub fn close_file(&mut self, fh: u64) {
-        self.files.remove(&fh);
+    /// Opens the file with the handle it had before the client exited, to write the data
+    /// buffered for the handle.
+    pub fn reopen_file(&mut self, fh: u64, id: String) {
+        self.files.insert(fh, OpenFile::new(id));
+        self.next_fh = self.next_fh.max(fh + 1);
+    }
+
+    /// Closes the handle. Returns the ID of the file if it has been removed while opened and the
+    /// handle was the last one, so that the file can be removed from the cache now.
+    pub fn close_file(&mut self, fh: u64) -> Option<String> {
+        let id = self.files.remove(&fh)?.id;
+        if self.unlinked.contains(&id) && self.get_file_handles_for(&id).is_empty() {
+            self.unlinked.remove(&id);
+            return Some(id);
+        }
+
+        None
+    }
+
+    /// Marks the file as removed while opened.
+    pub fn mark_unlinked(&mut self, id: &str) {
+        self.unlinked.insert(id.to_owned());
     }
 
     pub fn get_file_handles(&self) -> Vec<u64> {
         self.files.keys().map(|x| *x).collect_vec()
     }
 
+    pub fn get_id(&self, fh: u64) -> String {
+        self.files[&fh].id.clone()
+    }
+
+    pub fn set_error(&mut self, fh: u64, error: OperationError) {
+        if let Some(file) = self.files.get_mut(&fh) {
+            file.error = Some(error);
+        }
+    }
+
+    pub fn take_error(&mut self, fh: u64) -> Option<OperationError> {
+        self.files.get_mut(&fh).and_then(|file| file.error.take())
+    }
+
+    /// Makes the opened instances of the file follow it when its ID changes, e.g. when the
+    /// server assigns one to a file created offline.
+    pub fn change_id(&mut self, old_id: &str, new_id: &str) {
+        for file in self.files.values_mut().filter(|file| file.id == old_id) {
+            file.id = new_id.to_owned();
+        }
+    }
+
+    /// Returns the handles of all the opened instances of the file.
+    pub fn get_file_handles_for(&self, id: &str) -> Vec<u64> {
+        self.files
+            .iter()
+            .filter(|(_, file)| file.id == id)
+            .map(|(fh, _)| *fh)
+            .collect_vec()
+    }
+
+    /// Returns whether any opened instance of the file has data buffered.
+    pub fn has_buffered_writes(&self, id: &str) -> bool {
+        self.files
+            .values()
+            .any(|file| file.id == id && !file.write_buffer.is_empty())
+    }
+
     #[must_use]
     pub fn write(&mut self, fh: u64, operation: WriteOperation) -> bool {
         self.files
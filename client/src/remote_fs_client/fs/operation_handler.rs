@@ -1,7 +1,7 @@
 use offs::modify_op::{
-    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
-    RemoveDirectoryOperation, RemoveFileOperation, RenameOperation, SetAttributesOperation,
-    WriteOperation as ModifyOpWriteOperation,
+    CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
+    FallocateOperation, RemoveDirectoryOperation, RemoveFileOperation, RenameOperation,
+    SetAclOperation, SetAttributesOperation, WriteOperation as ModifyOpWriteOperation,
 };
 use offs::modify_op_handler::OperationHandler;
 
@@ -23,6 +23,7 @@ impl OperationHandler for OffsFilesystem {
             operation.file_type,
             operation.perm,
             operation.dev,
+            operation.owner,
         )?)
     }
 
@@ -32,9 +33,13 @@ impl OperationHandler for OffsFilesystem {
         timestamp: Timespec,
         operation: &CreateSymlinkOperation,
     ) -> OperationResult<String> {
-        Ok(self
-            .store
-            .create_symlink(parent_id, timestamp, &operation.name, &operation.link)?)
+        Ok(self.store.create_symlink(
+            parent_id,
+            timestamp,
+            &operation.name,
+            &operation.link,
+            operation.owner,
+        )?)
     }
 
     fn perform_create_directory(
@@ -43,9 +48,13 @@ impl OperationHandler for OffsFilesystem {
         timestamp: Timespec,
         operation: &CreateDirectoryOperation,
     ) -> OperationResult<String> {
-        Ok(self
-            .store
-            .create_directory(parent_id, timestamp, &operation.name, operation.perm)?)
+        Ok(self.store.create_directory(
+            parent_id,
+            timestamp,
+            &operation.name,
+            operation.perm,
+            operation.owner,
+        )?)
     }
 
     fn perform_remove_file(
@@ -108,4 +117,49 @@ impl OperationHandler for OffsFilesystem {
             .write(id, timestamp, operation.offset as usize, &operation.data)?;
         Ok(())
     }
+
+    fn perform_set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        self.store
+            .set_acl(id, timestamp, operation.acl_type, &operation.acl)?;
+        Ok(())
+    }
+
+    fn perform_fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        self.store.fallocate(
+            id,
+            timestamp,
+            operation.offset as u64,
+            operation.length as u64,
+            operation.zero,
+            operation.keep_size,
+        )?;
+        Ok(())
+    }
+
+    fn perform_copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        self.store.copy_range(
+            id,
+            timestamp,
+            &operation.source_id,
+            operation.source_offset as u64,
+            operation.offset as u64,
+            operation.length as u64,
+        )?;
+        Ok(())
+    }
 }
@@ -0,0 +1,34 @@
+//! The constants that are either missing or have a different type outside of Linux.
+
+#[cfg(target_os = "linux")]
+pub use libc::{
+    EBADFD, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, FALLOC_FL_ZERO_RANGE, O_DIRECT, S_IFBLK,
+    S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK,
+};
+
+#[cfg(not(target_os = "linux"))]
+pub use self::fallback::*;
+
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use libc::c_int;
+
+    pub const EBADFD: c_int = libc::EBADF;
+
+    // fallocate() is never called on macFUSE; the values are the ones of Linux
+    pub const FALLOC_FL_KEEP_SIZE: c_int = 0x01;
+    pub const FALLOC_FL_PUNCH_HOLE: c_int = 0x02;
+    pub const FALLOC_FL_ZERO_RANGE: c_int = 0x10;
+
+    // There is no O_DIRECT on macOS, so the files are never opened in direct I/O mode
+    pub const O_DIRECT: c_int = 0;
+
+    // mode_t is 16 bits wide on macOS, while FUSE always passes the mode as 32 bits
+    pub const S_IFBLK: u32 = libc::S_IFBLK as u32;
+    pub const S_IFCHR: u32 = libc::S_IFCHR as u32;
+    pub const S_IFDIR: u32 = libc::S_IFDIR as u32;
+    pub const S_IFIFO: u32 = libc::S_IFIFO as u32;
+    pub const S_IFLNK: u32 = libc::S_IFLNK as u32;
+    pub const S_IFREG: u32 = libc::S_IFREG as u32;
+    pub const S_IFSOCK: u32 = libc::S_IFSOCK as u32;
+}
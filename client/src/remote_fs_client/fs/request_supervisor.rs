@@ -0,0 +1,94 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, Instrument};
+
+use super::super::client::grpc_client::{REQUEST_DEADLINE, REQUEST_ID};
+
+/// Time after which a request is abandoned by default, so that e.g. a hung server connection
+/// does not leave it unanswered forever
+pub const DEFAULT_OPERATION_TIMEOUT: Duration = Duration::from_secs(300);
+/// Time the request handlers are given to reply after the RPCs made for them have timed out,
+/// before they are abandoned
+const REPLY_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Runs the handlers of the filesystem requests in the background, marking the filesystem as
+/// degraded while they time out or panic. The flag is cleared as soon as a handler completes
+/// again, so that a single slow moment of the server does not leave it set for good.
+pub struct RequestSupervisor {
+    rt: Handle,
+    degraded: Arc<AtomicBool>,
+    last_error: Arc<Mutex<String>>,
+    operation_timeout: Duration,
+}
+
+impl RequestSupervisor {
+    pub fn new(rt: Handle, degraded: Arc<AtomicBool>, last_error: Arc<Mutex<String>>) -> Self {
+        Self {
+            rt,
+            degraded,
+            last_error,
+            operation_timeout: DEFAULT_OPERATION_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the requests are handled for before they are abandoned, along with the
+    /// RPCs made for them.
+    pub fn set_operation_timeout(&mut self, timeout: Duration) {
+        self.operation_timeout = timeout;
+    }
+
+    /// Runs the request handler in the background, within a span identifying the request.
+    /// Returns the task watching the handler, which completes once it has been accounted for.
+    pub fn spawn<F>(&self, request_id: u64, operation: &'static str, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let span = info_span!("fuse_request", operation, request_id);
+        let deadline = tokio::time::Instant::now() + self.operation_timeout;
+
+        let handle = self.rt.spawn(
+            REQUEST_ID
+                .scope(
+                    request_id,
+                    REQUEST_DEADLINE.scope(
+                        deadline,
+                        tokio::time::timeout_at(deadline + REPLY_GRACE_PERIOD, future),
+                    ),
+                )
+                .instrument(span.clone()),
+        );
+
+        // A failed request handler drops the reply, which makes fuser respond with EIO
+        let degraded = self.degraded.clone();
+        let last_error = self.last_error.clone();
+        self.rt.spawn(
+            async move {
+                let message = match handle.await {
+                    Ok(Ok(())) => {
+                        if degraded.swap(false, Ordering::Relaxed) {
+                            info!("The filesystem is no longer degraded");
+                        }
+                        return;
+                    }
+                    Ok(Err(_)) => format!("Request {} ({}) timed out", request_id, operation),
+                    Err(e) if e.is_panic() => {
+                        format!("Request {} ({}) panicked", request_id, operation)
+                    }
+                    Err(_) => return,
+                };
+
+                error!("{}; the filesystem is now degraded", message);
+                degraded.store(true, Ordering::Relaxed);
+                if let Ok(mut last_error) = last_error.lock() {
+                    *last_error = message;
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,113 @@
+use bytes::Bytes;
+
+use offs::errors::{OperationError, OperationResult};
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::Sidecar;
+
+use super::api::resolve;
+use super::OffsFilesystem;
+
+/// Data derived from a file, such as a thumbnail, attached to it under a name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SidecarEntry {
+    pub name: String,
+    pub size: u64,
+    /// Whether the data was derived from the current content of the file
+    pub up_to_date: bool,
+}
+
+impl OffsFilesystem {
+    /// Lists the data attached to the file at the given path, relative to the root of the
+    /// filesystem. The list is cached, so that it is still available in the offline mode.
+    pub async fn list_sidecars(&mut self, path: &str) -> OperationResult<Vec<SidecarEntry>> {
+        let dirent = resolve(self, path).await?;
+        let sidecars = self.retrieve_sidecars(&dirent.id).await?;
+
+        Ok(sidecars
+            .into_iter()
+            .map(|x| SidecarEntry {
+                name: x.name,
+                size: x.size,
+                up_to_date: x.content_version == dirent.content_version,
+            })
+            .collect())
+    }
+
+    /// Returns the data attached to the file at the given path under the name. The data is
+    /// only retrieved from the server when it is not cached yet.
+    pub async fn get_sidecar(&mut self, path: &str, name: &str) -> OperationResult<Bytes> {
+        let dirent = resolve(self, path).await?;
+        let sidecar = self
+            .retrieve_sidecars(&dirent.id)
+            .await?
+            .into_iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| {
+                OperationError::file_does_not_exist(&format!("{:?} sidecar of {}", name, path))
+            })?;
+
+        let missing_blobs = self.store.get_missing_blobs([&sidecar.blob].iter())?;
+        self.retrieve_missing_blobs(&dirent.id, missing_blobs)
+            .await?;
+
+        self.store
+            .get_blobs([&sidecar.blob].iter())?
+            .remove(&sidecar.blob)
+            .ok_or_else(|| OperationError::blob_does_not_exist(&sidecar.blob))
+    }
+
+    /// Attaches the data to the file at the given path under the name, replacing the previous
+    /// data, or removes it if `data` is empty. The data is sent to the server right away, so
+    /// this does not work in the offline mode.
+    pub async fn set_sidecar(
+        &mut self,
+        path: &str,
+        name: &str,
+        data: Bytes,
+    ) -> OperationResult<()> {
+        check_online!(self);
+
+        let dirent = resolve(self, path).await?;
+        if LocalTempIdGenerator::is_local_id(&dirent.id) {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} has not been sent to the server yet",
+                path
+            )));
+        }
+        if LocalTempIdGenerator::is_local_only_id(&dirent.id) {
+            return Err(OperationError::invalid_argument(&format!(
+                "{} is ignored and only stored locally",
+                path
+            )));
+        }
+
+        let sidecars = self
+            .client
+            .set_sidecar(&dirent.id, name, data.clone())
+            .await?;
+
+        let transaction = self.store.transaction();
+        if !data.is_empty() {
+            self.store.add_blob(&data)?;
+        }
+        self.store.replace_sidecars(&dirent.id, &sidecars)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// Returns the data attached to the file, refreshing the cached list when online.
+    async fn retrieve_sidecars(&mut self, id: &str) -> OperationResult<Vec<Sidecar>> {
+        if self.is_offline()
+            || LocalTempIdGenerator::is_local_id(id)
+            || LocalTempIdGenerator::is_local_only_id(id)
+        {
+            return self.store.get_sidecars(id);
+        }
+
+        let sidecars = self.client.list_sidecars(id).await?;
+        self.store.replace_sidecars(id, &sidecars)?;
+
+        Ok(sidecars)
+    }
+}
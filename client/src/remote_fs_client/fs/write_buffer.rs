@@ -1,8 +1,7 @@
-use std::collections::BTreeSet;
+use std::collections::BTreeMap;
 
 const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct WriteOperation {
     pub offset: usize,
     pub data: Vec<u8>,
@@ -12,55 +11,95 @@ impl WriteOperation {
     pub fn new(offset: usize, data: Vec<u8>) -> Self {
         Self { offset, data }
     }
+
+    fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
 }
 
 pub struct WriteBuffer {
     size: usize,
-    operations: BTreeSet<WriteOperation>,
+    /// Buffered data by offset. The ranges never overlap nor touch each other, as the writes
+    /// are merged into them as they come, with the later writes replacing the overlapped parts
+    /// of the earlier ones.
+    ranges: BTreeMap<usize, Vec<u8>>,
 }
 
 impl WriteBuffer {
     pub fn new() -> Self {
         Self {
             size: 0,
-            operations: BTreeSet::new(),
+            ranges: BTreeMap::new(),
         }
     }
 
     #[must_use]
     pub fn write(&mut self, write_operation: WriteOperation) -> bool {
-        self.size += write_operation.data.len();
-        self.operations.insert(write_operation);
+        if !write_operation.data.is_empty() {
+            self.merge(write_operation);
+        }
 
         self.is_full()
     }
 
-    fn is_full(&self) -> bool {
-        self.size >= BUFFER_SIZE
-    }
-
-    pub fn flush(&mut self) -> Vec<WriteOperation> {
-        let mut result = Vec::new();
-
-        let mut old_operations = BTreeSet::new();
-        std::mem::swap(&mut self.operations, &mut old_operations);
+    fn merge(&mut self, operation: WriteOperation) {
+        let end = operation.end();
 
-        for mut op in old_operations.into_iter() {
-            if result.len() == 0 {
-                result.push(op);
-                continue;
+        // The range starting before the write is extended in place if it reaches it
+        let preceding = self
+            .ranges
+            .range(..=operation.offset)
+            .next_back()
+            .filter(|(&offset, data)| offset + data.len() >= operation.offset)
+            .map(|(&offset, _)| offset);
+        let mut merged = match preceding {
+            Some(offset) => {
+                let mut range = WriteOperation::new(offset, self.ranges.remove(&offset).unwrap());
+                self.size -= range.data.len();
+                if range.end() < end {
+                    range.data.resize(end - offset, 0);
+                }
+                range.data[operation.offset - offset..end - offset]
+                    .copy_from_slice(&operation.data);
+                range
             }
+            None => operation,
+        };
 
-            let last = result.last_mut().unwrap();
-            if last.offset + last.data.len() == op.offset {
-                last.data.append(&mut op.data);
-            } else {
-                result.push(op);
+        // Only the parts of the following ranges past the end of the write survive
+        let following: Vec<usize> = self
+            .ranges
+            .range(merged.offset..=end)
+            .map(|(&offset, _)| offset)
+            .collect();
+        for offset in following {
+            let data = self.ranges.remove(&offset).unwrap();
+            self.size -= data.len();
+            if offset + data.len() > merged.end() {
+                let tail_start = merged.end() - offset;
+                merged.data.extend_from_slice(&data[tail_start..]);
             }
         }
 
+        self.size += merged.data.len();
+        self.ranges.insert(merged.offset, merged.data);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.size >= BUFFER_SIZE
+    }
+
+    /// Returns the buffered data as the minimal set of operations.
+    pub fn flush(&mut self) -> Vec<WriteOperation> {
         self.size = 0;
 
-        result
+        std::mem::take(&mut self.ranges)
+            .into_iter()
+            .map(|(offset, data)| WriteOperation::new(offset, data))
+            .collect()
     }
 }
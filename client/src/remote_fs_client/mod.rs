@@ -1,5 +1,20 @@
+pub use fs::Export;
+pub use fs::FreeSpaceGuard;
+#[cfg(feature = "fuse")]
 pub use fs::FuseOffsFilesystem;
+pub use fs::IgnorePatterns;
+pub use fs::JournalReviewEntry;
+#[cfg(feature = "nfs")]
+pub use fs::NfsOffsFilesystem;
+pub use fs::OffsClient;
 pub use fs::OffsFilesystem;
+pub use fs::RequestSupervisor;
+pub use fs::SidecarEntry;
+
+#[cfg(feature = "chaos")]
+pub use client::chaos::{Chaos, ChaosChannel, ChaosConfig, Fault};
+pub use client::grpc_client::{RemoteFsGrpcClient, ShareLink};
+pub use client::remote_client::RemoteFsClient;
 
 mod client;
 mod fs;
@@ -0,0 +1,51 @@
+use std::fmt::{Display, Formatter};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+
+const URL_SCHEME: &str = "offs://";
+
+/// The server to connect to along with the share to mount, given either as `host:port` (the
+/// whole store) or `offs://host:port/share`.
+#[derive(Clone, Debug)]
+pub struct ServerAddress {
+    pub address: SocketAddr,
+    pub share: String,
+}
+
+impl FromStr for ServerAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, share) = match s.strip_prefix(URL_SCHEME) {
+            Some(rest) => {
+                let (host, share) = rest.split_once('/').unwrap_or((rest, ""));
+                (host, share.trim_end_matches('/'))
+            }
+            None => (s, ""),
+        };
+
+        if share.contains('/') {
+            return Err(format!("{}: invalid share name", s));
+        }
+        let address = host
+            .to_socket_addrs()
+            .map_err(|e| format!("{}: {}", host, e))?
+            .next()
+            .ok_or_else(|| format!("{}: could not resolve the address", host))?;
+
+        Ok(Self {
+            address,
+            share: share.to_owned(),
+        })
+    }
+}
+
+impl Display for ServerAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.share.is_empty() {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{}{}/{}", URL_SCHEME, self.address, self.share)
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult};
+
+use crate::daemon::PidFile;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const FORWARDED_SIGNALS: [Signal; 3] = [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP];
+
+static CHILD_PID: AtomicI32 = AtomicI32::new(0);
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    // SIGHUP only makes the client reload its configuration
+    if signal != libc::SIGHUP {
+        SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    }
+
+    let pid = CHILD_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+fn set_signal_handler(handler: SigHandler) {
+    let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+
+    for signal in &FORWARDED_SIGNALS {
+        unsafe { sigaction(*signal, &action) }.expect("Could not set signal handler");
+    }
+}
+
+/// Lazily unmounts a mount point left behind by a crashed client, so that it can be mounted
+/// again. Fails silently if there is nothing to unmount.
+#[cfg(target_os = "linux")]
+fn unmount_stale(mount_point: &Path) {
+    let _ = Command::new("fusermount")
+        .arg("-u")
+        .arg("-z")
+        .arg(mount_point)
+        .status();
+}
+
+/// Forcibly unmounts a mount point left behind by a crashed client, so that it can be mounted
+/// again. macFUSE comes without fusermount, so the system umount is used instead.
+#[cfg(not(target_os = "linux"))]
+fn unmount_stale(mount_point: &Path) {
+    let _ = Command::new("umount").arg("-f").arg(mount_point).status();
+}
+
+/// Keeps the client running by forking a new client process whenever the previous one dies
+/// abnormally. Returns only in the forked client process; the supervisor exits once the client
+/// exits cleanly or the supervisor is asked to terminate. The PID file, if given, refers to the
+/// supervisor, which forwards the termination signals and SIGHUP to the client.
+pub fn supervise(mount_points: &[PathBuf], pid_file: Option<PidFile>) {
+    set_signal_handler(SigHandler::Handler(forward_signal));
+
+    loop {
+        match unsafe { fork() }.expect("Fork failed") {
+            ForkResult::Child => {
+                set_signal_handler(SigHandler::SigDfl);
+                return;
+            }
+            ForkResult::Parent { child } => {
+                CHILD_PID.store(child.as_raw(), Ordering::SeqCst);
+                let status = loop {
+                    match waitpid(child, None) {
+                        Err(Errno::EINTR) => continue,
+                        result => break result.expect("Could not wait for the client process"),
+                    }
+                };
+                CHILD_PID.store(0, Ordering::SeqCst);
+
+                match status {
+                    WaitStatus::Exited(_, code)
+                        if code == 0 || SHUTTING_DOWN.load(Ordering::SeqCst) =>
+                    {
+                        drop(pid_file);
+                        process::exit(code)
+                    }
+                    _ if SHUTTING_DOWN.load(Ordering::SeqCst) => {
+                        drop(pid_file);
+                        process::exit(1)
+                    }
+                    _ => eprintln!("Client process died ({:?}), remounting", status),
+                }
+
+                for mount_point in mount_points {
+                    unmount_stale(mount_point);
+                }
+                thread::sleep(RESTART_DELAY);
+            }
+        }
+    }
+}
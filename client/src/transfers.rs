@@ -0,0 +1,91 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug)]
+pub enum TransferDirection {
+    Download,
+    Upload,
+}
+
+impl fmt::Display for TransferDirection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransferDirection::Download => f.write_str("download"),
+            TransferDirection::Upload => f.write_str("upload"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Transfer {
+    pub direction: TransferDirection,
+    pub name: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+/// Blob transfers currently in flight, exposed over D-Bus so that long transfers can be told
+/// apart from hangs.
+#[derive(Default)]
+pub struct Transfers {
+    next_id: AtomicU64,
+    in_flight: Mutex<BTreeMap<u64, Transfer>>,
+}
+
+impl Transfers {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a new transfer; it is removed once the returned guard is dropped.
+    pub fn start(
+        self: &Arc<Self>,
+        direction: TransferDirection,
+        name: &str,
+        bytes_total: u64,
+    ) -> TransferGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.lock().unwrap().insert(
+            id,
+            Transfer {
+                direction,
+                name: name.to_owned(),
+                bytes_done: 0,
+                bytes_total,
+            },
+        );
+
+        TransferGuard {
+            transfers: self.clone(),
+            id,
+        }
+    }
+
+    pub fn list(&self) -> Vec<Transfer> {
+        self.in_flight.lock().unwrap().values().cloned().collect()
+    }
+}
+
+pub struct TransferGuard {
+    transfers: Arc<Transfers>,
+    id: u64,
+}
+
+impl TransferGuard {
+    pub fn add_progress(&self, bytes: u64) {
+        if let Some(transfer) = self.transfers.in_flight.lock().unwrap().get_mut(&self.id) {
+            transfer.bytes_done += bytes;
+            // The total might be just an estimate
+            transfer.bytes_total = cmp::max(transfer.bytes_total, transfer.bytes_done);
+        }
+    }
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.transfers.in_flight.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,175 @@
+//! Tests of the recovery from the network faults, injected by a `ChaosChannel` between the
+//! client and the server. The random faults are picked from fixed seeds, so the runs are
+//! reproducible.
+
+mod common;
+
+use bytes::Bytes;
+
+use offs::store::Store;
+use offs_client::remote_fs_client::{Chaos, ChaosConfig, Fault, RemoteFsGrpcClient};
+use offs_client::server_address::ServerAddress;
+use offs_client::OffsClient;
+
+const SEEDS: u64 = 4;
+const FILES_PER_SEED: usize = 8;
+const MAX_SYNC_ATTEMPTS: usize = 50;
+
+async fn connect_chaos_client(server: &ServerAddress, chaos: Chaos) -> OffsClient {
+    let store = Store::new_client_in_memory().unwrap();
+    let replica_id = store.get_replica_id().unwrap();
+    let client = RemoteFsGrpcClient::with_chaos(server, None, replica_id, None, chaos)
+        .await
+        .unwrap();
+
+    OffsClient::with_client(client, store).await.unwrap()
+}
+
+/// Returns the names of the files in the directory along with their contents, as seen by a
+/// client connected without any faults.
+async fn server_contents(server: &ServerAddress, path: &str) -> Vec<(String, Bytes)> {
+    let client = common::connect_client(server).await;
+
+    let mut contents = Vec::new();
+    for dirent in client.list(path).await.unwrap() {
+        let file_path = format!("{}/{}", path.trim_end_matches('/'), dirent.name);
+        let data = match client.read(&file_path, 0, 1024).await {
+            Ok(data) => data,
+            // A directory
+            Err(_) => Bytes::new(),
+        };
+        contents.push((dirent.name, data));
+    }
+    contents.sort();
+
+    contents
+}
+
+fn entry(name: &str, data: &'static [u8]) -> (String, Bytes) {
+    (name.to_owned(), Bytes::from_static(data))
+}
+
+#[tokio::test]
+async fn journal_resent_after_lost_response_is_applied_once() {
+    let (server, _) = common::start_server().await;
+    let chaos = Chaos::default();
+    let client = connect_chaos_client(&server, chaos.clone()).await;
+    client.create_file("/b.txt", 0o644).await.unwrap();
+    client.write("/b.txt", 0, b"base").await.unwrap();
+
+    client.set_offline(true);
+    client.create_file("/a.txt", 0o644).await.unwrap();
+    client.write("/a.txt", 0, b"hello").await.unwrap();
+    client.create_directory("/d", 0o755).await.unwrap();
+    client.write("/b.txt", 0, b"mine").await.unwrap();
+    client.set_offline(false);
+
+    // The server applies the journal, but the client never learns about it
+    chaos.inject_into("CommitJournalApply", Fault::DropResponse);
+    assert!(client.sync().await.is_err());
+    client.sync().await.unwrap();
+
+    assert_eq!(chaos.fault_count(), 1);
+    assert!(client.review_journal().await.unwrap().is_empty());
+    assert_eq!(
+        server_contents(&server, "/").await,
+        vec![
+            entry("a.txt", b"hello"),
+            entry("b.txt", b"mine"),
+            entry("d", b"")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn operation_with_lost_response_is_applied_once() {
+    let (server, _) = common::start_server().await;
+    let chaos = Chaos::default();
+    let client = connect_chaos_client(&server, chaos.clone()).await;
+
+    chaos.inject_into("ApplyOperation", Fault::DropResponse);
+    assert!(client.create_file("/a.txt", 0o644).await.is_err());
+    // The file created on the server is found instead of being created again
+    assert!(client.create_file("/a.txt", 0o644).await.is_err());
+    client.write("/a.txt", 0, b"hello").await.unwrap();
+
+    assert_eq!(chaos.fault_count(), 1);
+    assert_eq!(
+        server_contents(&server, "/").await,
+        vec![entry("a.txt", b"hello")]
+    );
+}
+
+#[tokio::test]
+async fn calls_succeed_again_after_connection_faults() {
+    let (server, _) = common::start_server().await;
+    let chaos = Chaos::default();
+    let client = connect_chaos_client(&server, chaos.clone()).await;
+    let other_client = common::connect_client(&server).await;
+    other_client.create_file("/a.txt", 0o644).await.unwrap();
+    other_client.write("/a.txt", 0, b"hello").await.unwrap();
+
+    chaos.inject_into("List", Fault::Drop);
+    assert!(client.list("/").await.is_err());
+    assert_eq!(client.list("/").await.unwrap().len(), 1);
+
+    chaos.inject_into("GetBlobs", Fault::Disconnect { after_frames: 0 });
+    assert!(client.read("/a.txt", 0, 100).await.is_err());
+    let data = client.read("/a.txt", 0, 100).await.unwrap();
+    assert_eq!(data, Bytes::from_static(b"hello"));
+
+    assert_eq!(chaos.fault_count(), 2);
+}
+
+#[tokio::test]
+async fn journal_synced_through_random_faults_is_applied_once() {
+    let (server, _) = common::start_server().await;
+
+    for seed in 0..SEEDS {
+        let chaos = Chaos::default();
+        let client = connect_chaos_client(&server, chaos.clone()).await;
+        let root = format!("/seed{}", seed);
+        client.create_directory(&root, 0o755).await.unwrap();
+        client.list(&root).await.unwrap();
+
+        client.set_offline(true);
+        let mut expected = Vec::new();
+        for i in 0..FILES_PER_SEED {
+            let name = format!("{}.txt", i);
+            let path = format!("{}/{}", root, name);
+            let data = Bytes::from(format!("data of {}", i));
+            client.create_file(&path, 0o644).await.unwrap();
+            client.write(&path, 0, &data).await.unwrap();
+            expected.push((name, data));
+        }
+        expected.sort();
+        client.set_offline(false);
+
+        chaos.set_config(ChaosConfig {
+            seed,
+            drop_rate: 0.1,
+            drop_response_rate: 0.2,
+            disconnect_rate: 0.1,
+            ..Default::default()
+        });
+        let mut attempts = 0;
+        while client.sync().await.is_err() {
+            attempts += 1;
+            assert!(attempts < MAX_SYNC_ATTEMPTS, "seed {}", seed);
+        }
+        chaos.set_config(Default::default());
+        assert!(chaos.fault_count() > 0, "seed {}", seed);
+
+        assert!(
+            client.review_journal().await.unwrap().is_empty(),
+            "seed {}",
+            seed
+        );
+        assert_eq!(
+            server_contents(&server, &root).await,
+            expected,
+            "seed {}",
+            seed
+        );
+    }
+}
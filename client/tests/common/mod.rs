@@ -0,0 +1,60 @@
+//! Server the tests of the client run against: the gRPC service of `offs-server` serving a store
+//! kept in memory, listening on a port of its own.
+
+// Each test uses only some of the helpers
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+use offs::proto::filesystem::remote_fs_server::RemoteFsServer;
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::Store;
+use offs_client::remote_fs_client::RemoteFsGrpcClient;
+use offs_client::server_address::ServerAddress;
+use offs_client::OffsClient;
+use offs_server::remote_fs::{RemoteFs, RemoteFsServerImpl};
+
+/// Starts a server with an empty store, running until the runtime of the test shuts down.
+/// Returns the server filesystem as well, to inspect what the clients have done.
+pub async fn start_server() -> (ServerAddress, RemoteFs) {
+    let fs = RemoteFs::new(Store::new_server_in_memory().unwrap(), true).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address: SocketAddr = listener.local_addr().unwrap();
+
+    let service = RemoteFsServer::new(RemoteFsServerImpl::with_defaults(fs.session()));
+    tokio::spawn(
+        Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let server = ServerAddress {
+        address,
+        share: String::new(),
+    };
+
+    (server, fs)
+}
+
+/// Connects to the server with a cache kept in memory.
+pub async fn connect_client(server: &ServerAddress) -> OffsClient {
+    let (client, store) = connect(server).await;
+
+    OffsClient::with_client(client, store).await.unwrap()
+}
+
+/// Connects to the server, returning the connection along with the cache kept in memory the
+/// client is to use, for the tests that wrap the connection.
+pub async fn connect(server: &ServerAddress) -> (RemoteFsGrpcClient, Store<LocalTempIdGenerator>) {
+    let store = Store::new_client_in_memory().unwrap();
+    let replica_id = store.get_replica_id().unwrap();
+    let client = RemoteFsGrpcClient::new(server, None, replica_id, None)
+        .await
+        .unwrap();
+
+    (client, store)
+}
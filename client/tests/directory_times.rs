@@ -0,0 +1,399 @@
+//! Property tests of the times of the files: random sequences of operations are applied both
+//! through the client and to a local directory (on tmpfs, where `/tmp` is one), and the files
+//! whose modification and change times move have to be the same on both sides. The files whose
+//! times move have to get a new dirent version as well, so that the other clients see them.
+//!
+//! The sequences are generated from fixed seeds, so a failure is reproduced by running the
+//! test again; the seed and the operations applied so far are printed when it fails.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{self as unix_fs, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tempfile::TempDir;
+
+use offs::store::{FileMode, FileType};
+use offs::timespec::Timespec;
+use offs_client::OffsClient;
+
+const SEEDS: u64 = 4;
+const OPERATIONS_PER_SEED: usize = 40;
+/// Time waited after each operation, so that the times set by the next one differ even with
+/// the coarse clock of tmpfs.
+const CLOCK_STEP: Duration = Duration::from_millis(10);
+const DIRECTORIES: &[&str] = &["", "/d0", "/d1", "/d0/d2"];
+const NAMES: &[&str] = &["a", "b", "d0", "d1", "d2"];
+const MODES: &[FileMode] = &[0o700, 0o755];
+/// Target of the symbolic links, never created
+const SYMLINK_TARGET: &str = "missing";
+const EXISTING_FILE_PROBABILITY: f64 = 0.8;
+
+#[derive(Debug)]
+enum Operation {
+    CreateFile(String),
+    CreateDirectory(String),
+    CreateSymlink(String),
+    Remove(String),
+    Rename(String, String),
+    Write(String, u64, usize),
+    Truncate(String, u64),
+    SetMode(String, FileMode),
+    SetMtime(String, i64),
+}
+
+fn random_path(rng: &mut StdRng) -> String {
+    format!(
+        "{}/{}",
+        DIRECTORIES.choose(rng).unwrap(),
+        NAMES.choose(rng).unwrap()
+    )
+}
+
+impl Operation {
+    /// Returns a random operation; the ones modifying a file mostly pick one of the `existing`
+    /// files, so that they do not just fail.
+    fn random(rng: &mut StdRng, existing: &[&String]) -> Self {
+        let new_path = random_path(rng);
+        let path = match existing.choose(rng) {
+            Some(path) if rng.gen_bool(EXISTING_FILE_PROBABILITY) => (*path).clone(),
+            _ => random_path(rng),
+        };
+
+        match rng.gen_range(0..9) {
+            0 => Operation::CreateFile(new_path),
+            1 => Operation::CreateDirectory(new_path),
+            2 => Operation::CreateSymlink(new_path),
+            3 => Operation::Remove(path),
+            4 => Operation::Rename(path, new_path),
+            5 => Operation::Truncate(path, rng.gen_range(0..8192)),
+            6 => Operation::SetMode(path, *MODES.choose(rng).unwrap()),
+            7 => Operation::SetMtime(path, rng.gen_range(1..1_000_000)),
+            _ => Operation::Write(path, rng.gen_range(0..8192), rng.gen_range(1..4096)),
+        }
+    }
+
+    async fn apply(&self, client: &OffsClient, root: &str) -> bool {
+        let client_path = |path: &str| format!("{}{}", root, path);
+
+        match self {
+            Operation::CreateFile(path) => {
+                client.create_file(&client_path(path), 0o644).await.is_ok()
+            }
+            Operation::CreateDirectory(path) => client
+                .create_directory(&client_path(path), 0o755)
+                .await
+                .is_ok(),
+            Operation::CreateSymlink(path) => client
+                .create_symlink(&client_path(path), SYMLINK_TARGET)
+                .await
+                .is_ok(),
+            Operation::Remove(path) => client.remove(&client_path(path)).await.is_ok(),
+            Operation::Rename(path, new_path) => client
+                .rename(&client_path(path), &client_path(new_path))
+                .await
+                .is_ok(),
+            Operation::Write(path, offset, size) => client
+                .write(&client_path(path), *offset, &vec![1; *size])
+                .await
+                .is_ok(),
+            Operation::Truncate(path, size) => {
+                client.truncate(&client_path(path), *size).await.is_ok()
+            }
+            Operation::SetMode(path, mode) => {
+                client.set_mode(&client_path(path), *mode).await.is_ok()
+            }
+            Operation::SetMtime(path, sec) => {
+                let mtime = Timespec { sec: *sec, nsec: 0 };
+                client
+                    .set_times(&client_path(path), None, Some(mtime))
+                    .await
+                    .is_ok()
+            }
+        }
+    }
+
+    fn apply_reference(&self, root: &Path) -> bool {
+        let local_path = |path: &str| root.join(path.trim_start_matches('/'));
+
+        match self {
+            Operation::CreateFile(path) => OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(local_path(path))
+                .is_ok(),
+            Operation::CreateDirectory(path) => fs::create_dir(local_path(path)).is_ok(),
+            Operation::CreateSymlink(path) => {
+                unix_fs::symlink(SYMLINK_TARGET, local_path(path)).is_ok()
+            }
+            Operation::Remove(path) => {
+                let path = local_path(path);
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => fs::remove_dir(path).is_ok(),
+                    Ok(_) => fs::remove_file(path).is_ok(),
+                    Err(_) => false,
+                }
+            }
+            // The client only renames to the paths that do not exist
+            Operation::Rename(path, new_path) => {
+                fs::symlink_metadata(local_path(new_path)).is_err()
+                    && fs::rename(local_path(path), local_path(new_path)).is_ok()
+            }
+            // The client does not follow the symbolic links
+            Operation::Write(path, offset, size) => {
+                let result = OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NOFOLLOW)
+                    .open(local_path(path))
+                    .and_then(|mut file| {
+                        file.seek(SeekFrom::Start(*offset))?;
+                        file.write_all(&vec![1; *size])
+                    });
+                result.is_ok()
+            }
+            Operation::Truncate(path, size) => {
+                let result = OpenOptions::new()
+                    .write(true)
+                    .custom_flags(libc::O_NOFOLLOW)
+                    .open(local_path(path))
+                    .and_then(|file| file.set_len(*size));
+                result.is_ok()
+            }
+            Operation::SetMode(path, mode) => {
+                let path = local_path(path);
+                match fs::symlink_metadata(&path) {
+                    Ok(metadata) if !metadata.file_type().is_symlink() => {
+                        fs::set_permissions(path, fs::Permissions::from_mode(*mode as u32)).is_ok()
+                    }
+                    _ => false,
+                }
+            }
+            Operation::SetMtime(path, sec) => set_reference_mtime(&local_path(path), *sec),
+        }
+    }
+}
+
+/// Sets the modification time of the file, without following the symbolic links.
+fn set_reference_mtime(path: &Path, sec: i64) -> bool {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: sec,
+            tv_nsec: 0,
+        },
+    ];
+
+    // SAFETY: the path is NUL-terminated and the times are an array of two timespecs
+    let result = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    result == 0
+}
+
+/// Observable state of a file: its type, size, and modification and change times, along with
+/// its dirent version on the client side.
+#[derive(Clone, Debug, PartialEq)]
+struct FileState {
+    is_directory: bool,
+    size: Option<u64>,
+    mtime: (i64, u32),
+    ctime: (i64, u32),
+    dirent_version: i64,
+}
+
+async fn snapshot(client: &OffsClient, root: &str) -> BTreeMap<String, FileState> {
+    let mut files = BTreeMap::new();
+    let mut directories = vec![String::new()];
+
+    while let Some(directory) = directories.pop() {
+        let path = format!("{}{}", root, directory);
+        let dirent = client.stat(&path).await.unwrap();
+        files.insert(
+            directory.clone(),
+            FileState {
+                is_directory: true,
+                size: None,
+                mtime: (dirent.stat.mtim.sec, dirent.stat.mtim.nsec),
+                ctime: (dirent.stat.ctim.sec, dirent.stat.ctim.nsec),
+                dirent_version: dirent.dirent_version,
+            },
+        );
+
+        for child in client.list(&path).await.unwrap() {
+            let child_path = format!("{}/{}", directory, child.name);
+            if child.stat.file_type == FileType::Directory {
+                directories.push(child_path);
+                continue;
+            }
+
+            let dirent = client
+                .stat(&format!("{}{}", root, child_path))
+                .await
+                .unwrap();
+            files.insert(
+                child_path,
+                FileState {
+                    is_directory: false,
+                    size: Some(dirent.stat.size),
+                    mtime: (dirent.stat.mtim.sec, dirent.stat.mtim.nsec),
+                    ctime: (dirent.stat.ctim.sec, dirent.stat.ctim.nsec),
+                    dirent_version: dirent.dirent_version,
+                },
+            );
+        }
+    }
+
+    files
+}
+
+fn snapshot_reference(root: &Path) -> BTreeMap<String, FileState> {
+    let mut files = BTreeMap::new();
+    let mut directories = vec![String::new()];
+
+    while let Some(directory) = directories.pop() {
+        let local_path = root.join(directory.trim_start_matches('/'));
+        let metadata = fs::metadata(&local_path).unwrap();
+        files.insert(directory.clone(), reference_state(&metadata));
+
+        for entry in fs::read_dir(&local_path).unwrap() {
+            let entry = entry.unwrap();
+            let child_path = format!("{}/{}", directory, entry.file_name().to_str().unwrap());
+            let metadata = entry.metadata().unwrap();
+            if metadata.is_dir() {
+                directories.push(child_path);
+            } else {
+                files.insert(child_path, reference_state(&metadata));
+            }
+        }
+    }
+
+    files
+}
+
+fn reference_state(metadata: &fs::Metadata) -> FileState {
+    FileState {
+        is_directory: metadata.is_dir(),
+        size: Some(metadata.len()).filter(|_| !metadata.is_dir()),
+        mtime: (metadata.mtime(), metadata.mtime_nsec() as u32),
+        ctime: (metadata.ctime(), metadata.ctime_nsec() as u32),
+        // The local files have no versions
+        dirent_version: 0,
+    }
+}
+
+/// Returns the paths of the files whose times have changed between the snapshots, along with
+/// which of the times did.
+fn changed_times(
+    before: &BTreeMap<String, FileState>,
+    after: &BTreeMap<String, FileState>,
+) -> BTreeMap<String, (bool, bool)> {
+    after
+        .iter()
+        .filter_map(|(path, state)| {
+            let old_state = before.get(path)?;
+            let changed = (
+                state.mtime != old_state.mtime,
+                state.ctime != old_state.ctime,
+            );
+            Some((path.clone(), changed)).filter(|_| changed != (false, false))
+        })
+        .collect()
+}
+
+/// Returns the paths of the files whose times have changed between the snapshots without
+/// their dirent version growing.
+fn unversioned_changes(
+    before: &BTreeMap<String, FileState>,
+    after: &BTreeMap<String, FileState>,
+) -> Vec<String> {
+    changed_times(before, after)
+        .into_keys()
+        .filter(|path| after[path].dirent_version <= before[path].dirent_version)
+        .collect()
+}
+
+/// Drops the times, which naturally differ between the two sides.
+fn without_times(files: &BTreeMap<String, FileState>) -> BTreeMap<String, (bool, Option<u64>)> {
+    files
+        .iter()
+        .map(|(path, state)| (path.clone(), (state.is_directory, state.size)))
+        .collect()
+}
+
+#[tokio::test]
+async fn times_change_like_on_a_local_filesystem() {
+    let (server, _) = common::start_server().await;
+
+    for seed in 0..SEEDS {
+        let client = common::connect_client(&server).await;
+        let reference = TempDir::new().unwrap();
+        // The clients share the server, so each one works in a directory of its own
+        let root = format!("/seed{}", seed);
+        client.create_directory(&root, 0o755).await.unwrap();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut applied = Vec::new();
+        let mut state = snapshot(&client, &root).await;
+        let mut reference_state = snapshot_reference(reference.path());
+
+        for _ in 0..OPERATIONS_PER_SEED {
+            let existing: Vec<_> = reference_state.keys().filter(|x| !x.is_empty()).collect();
+            let operation = Operation::random(&mut rng, &existing);
+            tokio::time::sleep(CLOCK_STEP).await;
+
+            let succeeded = operation.apply(&client, &root).await;
+            let reference_succeeded = operation.apply_reference(reference.path());
+            applied.push(operation);
+            assert_eq!(
+                succeeded, reference_succeeded,
+                "seed {}, operations: {:?}",
+                seed, applied
+            );
+
+            let new_state = snapshot(&client, &root).await;
+            let new_reference_state = snapshot_reference(reference.path());
+            assert_eq!(
+                without_times(&new_state),
+                without_times(&new_reference_state),
+                "seed {}, operations: {:?}",
+                seed,
+                applied
+            );
+            assert_eq!(
+                changed_times(&state, &new_state),
+                changed_times(&reference_state, &new_reference_state),
+                "seed {}, operations: {:?}",
+                seed,
+                applied
+            );
+            assert_eq!(
+                unversioned_changes(&state, &new_state),
+                Vec::<String>::new(),
+                "seed {}, operations: {:?}",
+                seed,
+                applied
+            );
+
+            state = new_state;
+            reference_state = new_reference_state;
+        }
+    }
+}
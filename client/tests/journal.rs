@@ -0,0 +1,489 @@
+//! Tests of the journal of the offline changes, run through a test double of the connection
+//! that calls the gRPC service of a server running in the same process directly, without any
+//! network, recording the calls and failing them on demand.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use tokio_stream::StreamExt;
+use tonic::Request;
+
+use offs::errors::{
+    JournalApplyError, JournalApplyResult, JournalOperationStatus, OperationError, OperationResult,
+};
+use offs::modify_op::ModifyOperation;
+use offs::proto::filesystem as proto_types;
+use offs::proto::filesystem::remote_fs_server::RemoteFs as RemoteFsService;
+use offs::proto::filesystem::{
+    begin_journal_apply_response, ApplyJournalRequest, CommitJournalApplyRequest,
+    CreateShareLinkRequest, GetBlobsRequest, GetDirEntitiesRequest, GetDirUsageRequest,
+    GetMissingBlobsRequest, GetServerInfoRequest, ListChunksRequest, ListRequest,
+    ListSidecarsRequest, SetSidecarRequest,
+};
+use offs::protocol::{FEATURE_STREAMED_JOURNAL_APPLY, PROTOCOL_VERSION};
+use offs::store::{DirEntity, DirUsage, Sidecar, Store};
+use offs::CLIENT_ID_HEADER_KEY;
+use offs_client::events::{ConflictResolution, SyncEvent};
+use offs_client::remote_fs_client::{RemoteFsClient, ShareLink};
+use offs_client::OffsClient;
+use offs_server::remote_fs::{RemoteFs, RemoteFsServerImpl};
+
+#[derive(Default)]
+struct Calls {
+    made: Vec<&'static str>,
+    failures: HashMap<&'static str, OperationError>,
+}
+
+/// Handle to the calls made through an [`InMemoryClient`], shared with the client.
+#[derive(Clone, Default)]
+struct CallLog {
+    calls: Arc<Mutex<Calls>>,
+}
+
+impl CallLog {
+    /// Makes the next call of the method fail with the error, without reaching the server.
+    fn fail_next(&self, method: &'static str, error: OperationError) {
+        self.calls.lock().unwrap().failures.insert(method, error);
+    }
+
+    /// Returns how many times the method has been called, including the failed calls.
+    fn count(&self, method: &str) -> usize {
+        let calls = self.calls.lock().unwrap();
+        calls.made.iter().filter(|x| **x == method).count()
+    }
+
+    fn record(&self, method: &'static str) -> OperationResult<()> {
+        let mut calls = self.calls.lock().unwrap();
+        calls.made.push(method);
+        match calls.failures.remove(method) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Connection to a server in the same process, calling its gRPC service directly. The journals
+/// are staged in a single request, as the blobs cannot be streamed without a transport.
+struct InMemoryClient {
+    service: RemoteFsServerImpl,
+    replica_id: String,
+    features: Vec<String>,
+    log: CallLog,
+}
+
+impl InMemoryClient {
+    async fn new(fs: &RemoteFs, replica_id: String, log: CallLog) -> Self {
+        let service = RemoteFsServerImpl::with_defaults(fs.session());
+        let req = GetServerInfoRequest {
+            protocol_version: PROTOCOL_VERSION,
+        };
+        let server_info = service
+            .get_server_info(Request::new(req))
+            .await
+            .unwrap()
+            .into_inner();
+        let features = server_info
+            .features
+            .into_iter()
+            .filter(|x| x != FEATURE_STREAMED_JOURNAL_APPLY)
+            .collect();
+
+        Self {
+            service,
+            replica_id,
+            features,
+            log,
+        }
+    }
+
+    /// Identifies the client like the gRPC one does, so that its operations are attributed to
+    /// its replica.
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(CLIENT_ID_HEADER_KEY, self.replica_id.parse().unwrap());
+
+        request
+    }
+
+    async fn list(&self, req: ListRequest, tree: bool) -> OperationResult<Vec<DirEntity>> {
+        let request = self.request(req);
+        let mut stream = if tree {
+            self.service.list_tree(request).await?.into_inner()
+        } else {
+            self.service.list(request).await?.into_inner()
+        };
+
+        let mut res = Vec::new();
+        while let Some(dir_entity) = stream.next().await {
+            res.push(dir_entity?.try_into()?);
+        }
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl RemoteFsClient for InMemoryClient {
+    fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|x| x == feature)
+    }
+
+    async fn list_files(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>> {
+        self.log.record("list_files")?;
+        let req = ListRequest {
+            id: dir_id.to_owned(),
+            ..Default::default()
+        };
+        self.list(req, false).await
+    }
+
+    async fn list_files_page(
+        &mut self,
+        dir_id: &str,
+        start_after: &str,
+        limit: u32,
+    ) -> OperationResult<Vec<DirEntity>> {
+        self.log.record("list_files_page")?;
+        let req = ListRequest {
+            id: dir_id.to_owned(),
+            start_after: start_after.to_owned(),
+            limit,
+            ..Default::default()
+        };
+        self.list(req, false).await
+    }
+
+    async fn list_tree(&mut self, dir_id: &str) -> OperationResult<Vec<DirEntity>> {
+        self.log.record("list_tree")?;
+        let req = ListRequest {
+            id: dir_id.to_owned(),
+            ..Default::default()
+        };
+        self.list(req, true).await
+    }
+
+    async fn get_dir_entities(
+        &mut self,
+        ids: &[String],
+    ) -> OperationResult<(Vec<DirEntity>, Vec<String>)> {
+        self.log.record("get_dir_entities")?;
+        let req = GetDirEntitiesRequest {
+            ids: ids.to_vec(),
+            ..Default::default()
+        };
+        let resp = self
+            .service
+            .get_dir_entities(self.request(req))
+            .await?
+            .into_inner();
+
+        let dir_entities = resp
+            .dir_entities
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<_, _>>()?;
+        Ok((dir_entities, resp.missing_ids))
+    }
+
+    async fn get_chunks(&mut self, id: &str) -> OperationResult<Vec<String>> {
+        self.log.record("get_chunks")?;
+        let req = ListChunksRequest {
+            id: id.to_owned(),
+            ..Default::default()
+        };
+        let resp = self.service.list_chunks(self.request(req)).await?;
+        Ok(resp.into_inner().blob_id)
+    }
+
+    async fn get_dir_usage(&mut self, dir_id: &str) -> OperationResult<DirUsage> {
+        self.log.record("get_dir_usage")?;
+        let req = GetDirUsageRequest {
+            id: dir_id.to_owned(),
+            ..Default::default()
+        };
+        let resp = self
+            .service
+            .get_dir_usage(self.request(req))
+            .await?
+            .into_inner();
+        Ok(DirUsage {
+            size: resp.size,
+            files: resp.files,
+            directories: resp.directories,
+        })
+    }
+
+    async fn create_share_link(&mut self, id: &str, expires_in: u64) -> OperationResult<ShareLink> {
+        self.log.record("create_share_link")?;
+        let req = CreateShareLinkRequest {
+            id: id.to_owned(),
+            expires_in,
+            ..Default::default()
+        };
+        let resp = self
+            .service
+            .create_share_link(self.request(req))
+            .await?
+            .into_inner();
+        Ok(ShareLink {
+            url: resp.url,
+            expires_at: resp.expires_at,
+        })
+    }
+
+    async fn list_sidecars(&mut self, id: &str) -> OperationResult<Vec<Sidecar>> {
+        self.log.record("list_sidecars")?;
+        let req = ListSidecarsRequest {
+            id: id.to_owned(),
+            ..Default::default()
+        };
+        let resp = self.service.list_sidecars(self.request(req)).await?;
+        Ok(resp
+            .into_inner()
+            .sidecars
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn set_sidecar(
+        &mut self,
+        id: &str,
+        name: &str,
+        data: Bytes,
+    ) -> OperationResult<Vec<Sidecar>> {
+        self.log.record("set_sidecar")?;
+        let req = SetSidecarRequest {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            data: data.to_vec(),
+            ..Default::default()
+        };
+        let resp = self.service.set_sidecar(self.request(req)).await?;
+        Ok(resp
+            .into_inner()
+            .sidecars
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    async fn get_blobs(
+        &mut self,
+        ids: Vec<String>,
+        on_blob_received: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> OperationResult<Vec<(String, Bytes)>> {
+        self.log.record("get_blobs")?;
+        let req = GetBlobsRequest { id: ids };
+        let mut stream = self
+            .service
+            .get_blobs(self.request(req))
+            .await?
+            .into_inner();
+
+        let mut res = Vec::new();
+        while let Some(blob) = stream.next().await {
+            let blob = blob?;
+            on_blob_received(&blob.content);
+            // The trailing zeros are not sent
+            let mut content = BytesMut::from(&blob.content[..]);
+            content.resize(blob.length as usize, 0);
+            res.push((blob.id, content.freeze()));
+        }
+
+        Ok(res)
+    }
+
+    async fn request_apply_operation(
+        &mut self,
+        modify_operation: ModifyOperation,
+    ) -> OperationResult<DirEntity> {
+        self.log.record("request_apply_operation")?;
+        let req: proto_types::ModifyOperation = modify_operation.into();
+        let resp = self.service.apply_operation(self.request(req)).await?;
+        Ok(resp.into_inner().try_into()?)
+    }
+
+    async fn apply_journal(
+        &mut self,
+        journal: Vec<ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+        blobs: Vec<Bytes>,
+        on_blob_sent: &mut (dyn for<'b> FnMut(&'b [u8]) + Send),
+    ) -> OperationResult<JournalApplyResult> {
+        self.log.record("apply_journal")?;
+        let req = ApplyJournalRequest {
+            operations: journal.into_iter().map(Into::into).collect(),
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            blobs: blobs.clone(),
+            ..Default::default()
+        };
+        let staged = self
+            .service
+            .begin_journal_apply(self.request(req))
+            .await?
+            .into_inner();
+        blobs.iter().for_each(|blob| on_blob_sent(blob));
+        if let Some(begin_journal_apply_response::Error::MissingBlobs(data)) = staged.error {
+            return Ok(Err(JournalApplyError::MissingBlobs(data.ids)));
+        }
+
+        let req = CommitJournalApplyRequest {
+            staging_id: staged.staging_id,
+            ..Default::default()
+        };
+        let resp = self.service.commit_journal_apply(self.request(req)).await?;
+        Ok(resp.into_inner().try_into()?)
+    }
+
+    async fn validate_journal(
+        &mut self,
+        journal: Vec<ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+    ) -> OperationResult<Vec<JournalOperationStatus>> {
+        self.log.record("validate_journal")?;
+        let req = ApplyJournalRequest {
+            operations: journal.into_iter().map(Into::into).collect(),
+            chunks: chunks.into_iter().map(Into::into).collect(),
+            validate_only: true,
+            ..Default::default()
+        };
+        let resp = self.service.apply_journal(self.request(req)).await?;
+        let result: JournalApplyResult = resp.into_inner().try_into()?;
+
+        match result {
+            Ok(data) => Ok(data.statuses),
+            Err(JournalApplyError::MissingBlobs(_)) => Err(OperationError::invalid_argument(
+                "the server asked for the blobs of a journal being validated",
+            )),
+        }
+    }
+
+    async fn get_server_missing_blobs(&mut self, ids: Vec<String>) -> OperationResult<Vec<String>> {
+        self.log.record("get_server_missing_blobs")?;
+        let req = GetMissingBlobsRequest { id: ids };
+        let resp = self.service.get_missing_blobs(self.request(req)).await?;
+        Ok(resp.into_inner().blob_id)
+    }
+}
+
+fn start_server() -> RemoteFs {
+    RemoteFs::new(Store::new_server_in_memory().unwrap(), true).unwrap()
+}
+
+async fn connect_client(fs: &RemoteFs) -> (OffsClient, CallLog) {
+    let store = Store::new_client_in_memory().unwrap();
+    let log = CallLog::default();
+    let client = InMemoryClient::new(fs, store.get_replica_id().unwrap(), log.clone()).await;
+
+    (OffsClient::with_client(client, store).await.unwrap(), log)
+}
+
+fn collect_events(client: &OffsClient) -> Arc<Mutex<Vec<SyncEvent>>> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    client.subscribe(move |event| events_clone.lock().unwrap().push(event.clone()));
+
+    events
+}
+
+#[tokio::test]
+async fn offline_changes_are_sent_in_one_journal() {
+    let server = start_server();
+    let (client, log) = connect_client(&server).await;
+
+    // The root directory has to be cached to work on it offline
+    client.list("/").await.unwrap();
+    let operations_applied = log.count("request_apply_operation");
+    client.set_offline(true);
+    client.create_directory("/docs", 0o755).await.unwrap();
+    client.create_file("/docs/a.txt", 0o644).await.unwrap();
+    client.write("/docs/a.txt", 0, b"hello").await.unwrap();
+    assert_eq!(log.count("request_apply_operation"), operations_applied);
+    assert_eq!(log.count("apply_journal"), 0);
+
+    client.set_offline(false);
+    client.sync().await.unwrap();
+    assert_eq!(log.count("request_apply_operation"), operations_applied);
+    assert_eq!(log.count("apply_journal"), 1);
+    assert!(client.review_journal().await.unwrap().is_empty());
+
+    let (other_client, _) = connect_client(&server).await;
+    let data = other_client.read("/docs/a.txt", 0, 100).await.unwrap();
+    assert_eq!(data, Bytes::from_static(b"hello"));
+}
+
+#[tokio::test]
+async fn failed_sync_keeps_the_journal() {
+    let server = start_server();
+    let (client, log) = connect_client(&server).await;
+    let events = collect_events(&client);
+
+    client.list("/").await.unwrap();
+    client.set_offline(true);
+    client.create_file("/a.txt", 0o644).await.unwrap();
+    client.write("/a.txt", 0, b"hello").await.unwrap();
+    client.set_offline(false);
+
+    log.fail_next("apply_journal", OperationError::offline("Connection lost"));
+    assert!(client.sync().await.is_err());
+    assert!(events
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|event| matches!(event, SyncEvent::SyncFailed { .. })));
+    let (other_client, _) = connect_client(&server).await;
+    assert!(other_client.stat("/a.txt").await.is_err());
+
+    let journal = client.review_journal().await.unwrap();
+    assert!(!journal.is_empty());
+    assert!(journal
+        .iter()
+        .all(|entry| entry.status == JournalOperationStatus::Applied));
+
+    client.sync().await.unwrap();
+    assert_eq!(log.count("apply_journal"), 2);
+    assert!(client.review_journal().await.unwrap().is_empty());
+    let data = other_client.read("/a.txt", 0, 100).await.unwrap();
+    assert_eq!(data, Bytes::from_static(b"hello"));
+}
+
+#[tokio::test]
+async fn conflicting_change_is_sent_as_a_new_file() {
+    let server = start_server();
+    let (client, _) = connect_client(&server).await;
+    let events = collect_events(&client);
+    client.create_file("/a.txt", 0o644).await.unwrap();
+    client.write("/a.txt", 0, b"base").await.unwrap();
+
+    let (other_client, _) = connect_client(&server).await;
+    client.set_offline(true);
+    client.write("/a.txt", 0, b"mine").await.unwrap();
+    other_client.write("/a.txt", 0, b"them").await.unwrap();
+    client.set_offline(false);
+    client.sync().await.unwrap();
+
+    assert!(events.lock().unwrap().iter().any(|event| matches!(
+        event,
+        SyncEvent::Conflict {
+            resolution: ConflictResolution::Recreated,
+            ..
+        }
+    )));
+
+    let mut contents = Vec::new();
+    for dirent in other_client.list("/").await.unwrap() {
+        let path = format!("/{}", dirent.name);
+        contents.push(other_client.read(&path, 0, 100).await.unwrap());
+    }
+    contents.sort();
+    assert_eq!(
+        contents,
+        vec![Bytes::from_static(b"mine"), Bytes::from_static(b"them")]
+    );
+}
@@ -0,0 +1,236 @@
+//! POSIX conformance of the FUSE mount: runs a subset of pjdfstest on a filesystem mounted within
+//! the test, against a server running within the test as well, to catch regressions in the
+//! semantics of the mount.
+//!
+//! Only built with the `posix-tests` feature. Has to be run as root, as pjdfstest changes the
+//! owners of the files, with `PJDFSTEST_DIR` pointing to a checkout of pjdfstest with the
+//! `pjdfstest` binary built. `POSIX_TESTS` can list the directories of pjdfstest/tests to run
+//! (`chmod,rename`, ...) instead of the default subset.
+//!
+//! The test files listed in `posix/known-failures.txt` are expected to fail: the test fails if
+//! any other test file fails, or if a known failure passes, so that the list gets updated (with
+//! `UPDATE_KNOWN_FAILURES=1`).
+
+mod common;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::{Arc, Mutex};
+
+use fuser::{BackgroundSession, MountOption};
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+
+use offs::store::blob_cache::BlobCache;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::Store;
+use offs_client::events::SyncEvents;
+use offs_client::metrics::Metrics;
+use offs_client::remote_fs_client::{
+    FreeSpaceGuard, FuseOffsFilesystem, OffsFilesystem, RemoteFsGrpcClient,
+};
+use offs_client::server_address::ServerAddress;
+use offs_client::transfers::Transfers;
+
+/// Hard links are not supported, so the link tests are left out
+const DEFAULT_TESTS: &[&str] = &[
+    "chmod",
+    "chown",
+    "ftruncate",
+    "mkdir",
+    "open",
+    "rename",
+    "rmdir",
+    "symlink",
+    "truncate",
+    "unlink",
+    "utimensat",
+];
+const KNOWN_FAILURES_HEADER: &str = "\
+# Test files of pjdfstest (relative to its tests directory) that are expected to fail on an offs
+# mount, one per line. Regenerate with
+# `UPDATE_KNOWN_FAILURES=1 cargo test -p offs-client --features posix-tests --test posix`.
+";
+
+fn known_failures_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/posix/known-failures.txt")
+}
+
+fn read_known_failures() -> BTreeSet<String> {
+    fs::read_to_string(known_failures_path())
+        .unwrap()
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Mounts a client with a cache in `work_dir`, letting all the users access the files with the
+/// permissions checked by the kernel, as pjdfstest switches between them.
+fn mount(
+    rt: &Runtime,
+    server: &ServerAddress,
+    work_dir: &Path,
+    mount_point: &Path,
+) -> BackgroundSession {
+    let cache_path = work_dir.join("cache.db");
+    let store = Store::new_client(&cache_path, None).unwrap();
+    let replica_id = store.get_replica_id().unwrap();
+    let last_error = Arc::new(Mutex::new(String::new()));
+    let events = Arc::new(SyncEvents::new());
+    let metrics = Arc::new(Metrics::new(Arc::new(BlobCache::new(0))));
+
+    let fs = rt.block_on(async {
+        let client = RemoteFsGrpcClient::new(server, None, replica_id, None)
+            .await
+            .unwrap();
+
+        OffsFilesystem::new(
+            client,
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+            last_error.clone(),
+            StoreWrapper::new(store),
+            metrics.clone(),
+            Arc::new(Transfers::new()),
+            events.clone(),
+        )
+        .await
+        .unwrap()
+    });
+    let fuse_fs = FuseOffsFilesystem::new(
+        Arc::new(RwLock::new(fs)),
+        rt.handle().clone(),
+        metrics,
+        Arc::new(AtomicBool::new(false)),
+        last_error,
+        FreeSpaceGuard::new(cache_path, Arc::new(AtomicU64::new(0)), events),
+        true,
+    );
+
+    let options = [MountOption::AllowOther, MountOption::DefaultPermissions];
+    fuser::Session::new(fuse_fs, mount_point, &options)
+        .unwrap()
+        .spawn()
+        .unwrap()
+}
+
+/// Runs the test files of the directories on the mount, returning the ones that have failed.
+fn run_pjdfstest(
+    pjdfstest_dir: &Path,
+    tests: &[String],
+    mount_point: &Path,
+    log_path: &Path,
+) -> BTreeSet<String> {
+    let log = File::create(log_path).unwrap();
+    let tests_dir = pjdfstest_dir.join("tests");
+
+    let mut failed = BTreeSet::new();
+    for test_dir in tests {
+        let mut test_files: Vec<_> = fs::read_dir(tests_dir.join(test_dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|x| x == "t"))
+            .collect();
+        test_files.sort();
+
+        for test_file in test_files {
+            let status = Command::new("prove")
+                .arg("-v")
+                .arg(&test_file)
+                .current_dir(mount_point)
+                .stdout(log.try_clone().unwrap())
+                .stderr(log.try_clone().unwrap())
+                .stdin(Stdio::null())
+                .status()
+                .unwrap();
+            if !status.success() {
+                let name = test_file.strip_prefix(&tests_dir).unwrap();
+                failed.insert(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    failed
+}
+
+/// Replaces the known failures of the test directories that have been run with the failures of
+/// the run, keeping the ones of the other directories.
+fn update_known_failures(
+    known_failures: &BTreeSet<String>,
+    expected: &BTreeSet<String>,
+    failed: &BTreeSet<String>,
+) {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(known_failures_path())
+        .unwrap();
+    let lines: BTreeSet<_> = known_failures
+        .difference(expected)
+        .chain(failed)
+        .map(|x| format!("{}\n", x))
+        .collect();
+
+    file.write_all(KNOWN_FAILURES_HEADER.as_bytes()).unwrap();
+    for line in lines {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn pjdfstest() {
+    let pjdfstest_dir = PathBuf::from(
+        env::var_os("PJDFSTEST_DIR").expect("PJDFSTEST_DIR has to point to a pjdfstest checkout"),
+    );
+    assert!(
+        pjdfstest_dir.join("pjdfstest").is_file(),
+        "pjdfstest has not been built in {}",
+        pjdfstest_dir.display()
+    );
+    let tests: Vec<String> = match env::var("POSIX_TESTS") {
+        Ok(tests) => tests.split(',').map(str::to_owned).collect(),
+        Err(_) => DEFAULT_TESTS.iter().map(|&x| x.to_owned()).collect(),
+    };
+
+    let rt = Runtime::new().unwrap();
+    let (server, _) = rt.block_on(common::start_server());
+    let work_dir = tempfile::tempdir().unwrap();
+    let mount_point = work_dir.path().join("mnt");
+    fs::create_dir(&mount_point).unwrap();
+    let log_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("posix-tests.log");
+
+    let session = mount(&rt, &server, work_dir.path(), &mount_point);
+    let failed = run_pjdfstest(&pjdfstest_dir, &tests, &mount_point, &log_path);
+    // Unmounts the filesystem while the runtime is still alive
+    drop(session);
+
+    // Only the known failures of the test directories that have been run are compared
+    let known_failures = read_known_failures();
+    let expected: BTreeSet<_> = known_failures
+        .iter()
+        .filter(|name| tests.iter().any(|x| name.starts_with(&format!("{}/", x))))
+        .cloned()
+        .collect();
+
+    if env::var_os("UPDATE_KNOWN_FAILURES").is_some() {
+        update_known_failures(&known_failures, &expected, &failed);
+        return;
+    }
+
+    let new_failures: Vec<_> = failed.difference(&expected).collect();
+    let fixed: Vec<_> = expected.difference(&failed).collect();
+    assert!(
+        new_failures.is_empty() && fixed.is_empty(),
+        "New failures: {:?}\nKnown failures that now pass: {:?}\nSee {} for the output of the tests",
+        new_failures,
+        fixed,
+        log_path.display()
+    );
+}
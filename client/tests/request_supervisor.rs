@@ -0,0 +1,73 @@
+//! Tests of the degraded state of the filesystem, entered when the request handlers time out
+//! or panic, and left once they complete again. The time is paused, so the timeouts elapse
+//! as soon as the handlers are waiting for nothing else.
+
+use std::future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+use offs_client::remote_fs_client::RequestSupervisor;
+
+fn supervisor() -> (RequestSupervisor, Arc<AtomicBool>, Arc<Mutex<String>>) {
+    let degraded = Arc::new(AtomicBool::new(false));
+    let last_error = Arc::new(Mutex::new(String::new()));
+    let mut supervisor =
+        RequestSupervisor::new(Handle::current(), degraded.clone(), last_error.clone());
+    supervisor.set_operation_timeout(Duration::from_secs(1));
+
+    (supervisor, degraded, last_error)
+}
+
+#[tokio::test(start_paused = true)]
+async fn degraded_after_timeout_until_request_completes() {
+    let (supervisor, degraded, last_error) = supervisor();
+
+    supervisor
+        .spawn(1, "read", future::ready(()))
+        .await
+        .unwrap();
+    assert!(!degraded.load(Ordering::Relaxed));
+
+    supervisor
+        .spawn(2, "read", future::pending())
+        .await
+        .unwrap();
+    assert!(degraded.load(Ordering::Relaxed));
+    assert_eq!(*last_error.lock().unwrap(), "Request 2 (read) timed out");
+
+    supervisor
+        .spawn(3, "write", tokio::time::sleep(Duration::from_millis(500)))
+        .await
+        .unwrap();
+    assert!(!degraded.load(Ordering::Relaxed));
+    // The error is still reported as the last one encountered
+    assert_eq!(*last_error.lock().unwrap(), "Request 2 (read) timed out");
+}
+
+#[tokio::test(start_paused = true)]
+async fn degraded_after_panic_until_request_completes() {
+    let (supervisor, degraded, last_error) = supervisor();
+
+    supervisor
+        .spawn(1, "lookup", async { panic!("handler failure") })
+        .await
+        .unwrap();
+    assert!(degraded.load(Ordering::Relaxed));
+    assert_eq!(*last_error.lock().unwrap(), "Request 1 (lookup) panicked");
+
+    // Still degraded while the other requests keep timing out
+    supervisor
+        .spawn(2, "lookup", future::pending())
+        .await
+        .unwrap();
+    assert!(degraded.load(Ordering::Relaxed));
+
+    supervisor
+        .spawn(3, "lookup", future::ready(()))
+        .await
+        .unwrap();
+    assert!(!degraded.load(Ordering::Relaxed));
+}
@@ -1,159 +1,335 @@
-use std::fmt::{Debug, Error, Formatter};
 use std::time::Duration;
 
-use dbus::arg::{Array, Variant};
-use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::arg::{prop_cast, Array, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{ObjectManager, Properties};
 use dbus::blocking::{BlockingSender, Connection};
-use dbus::Message;
+use dbus::{Message, Path};
 
-use offs::dbus::{ID_PREFIX, IFACE, MOUNT_POINT, OFFLINE_MODE, PATH};
-use offs::PROJ_NAME;
+use offs::dbus::{
+    CACHE_SIZE, CREATE_SHARE_LINK, DEDUP_STATS, DEGRADED, DEHYDRATE, DIR_USAGE, EXCLUDED_FROM_SYNC,
+    EXCLUDE_FROM_SYNC, EXPORT, GET_SIDECAR, ID_PREFIX, IFACE, INCLUDE_IN_SYNC, LAST_ERROR,
+    LIST_SIDECARS, LOCATE, MOUNT_POINT, OFFLINE_MODE, PATH, PENDING_JOURNAL_OPS, PREFETCH,
+    QUARANTINED_JOURNAL_OPS, RELOAD, REVIEW_JOURNAL, SERVER_ADDRESS, SET_SIDECAR, SYNC_STATE,
+    TRANSFERS,
+};
 
-pub struct DBusClientError {
-    pub message: String,
-    pub mount_points: Vec<String>,
+use crate::mount::{
+    ClientError, ClientResult, ClientStatus, DedupStats, DirUsage, FileDedupStats,
+    QuarantinedOperation, ReviewedOperation, Sidecar, TransferStatus,
+};
+
+pub fn get_connection() -> ClientResult<Connection> {
+    Ok(Connection::new_session()?)
 }
 
-type DBusClientResult<T> = Result<T, DBusClientError>;
+fn get_services(connection: &Connection) -> ClientResult<Vec<String>> {
+    let m = Message::new_method_call(
+        "org.freedesktop.DBus",
+        "/",
+        "org.freedesktop.DBus",
+        "ListNames",
+    )?;
+    let r = connection.send_with_reply_and_block(m, Duration::from_millis(1000))?;
+    let arr: Array<&str, _> = r.get1().ok_or(ClientError::none_error())?;
 
-impl DBusClientError {
-    fn with_message(message: String) -> Self {
-        Self {
-            message,
-            mount_points: Default::default(),
-        }
-    }
+    Ok(arr
+        .filter(|x| x.starts_with(ID_PREFIX))
+        .map(|x| x.to_owned())
+        .collect())
+}
+
+/// A single mount served by an offs client process over D-Bus.
+pub struct MountId {
+    pub service: String,
+    pub path: Path<'static>,
+    pub mount_point: String,
+}
 
-    fn with_message_and_mp_list(message: String, mount_points: Vec<String>) -> Self {
-        Self {
-            message,
-            mount_points,
+pub fn get_mounts(connection: &Connection) -> ClientResult<Vec<MountId>> {
+    let mut mounts = Vec::new();
+
+    for service in get_services(connection)? {
+        let p = connection.with_proxy(&service, PATH, Duration::from_millis(1000));
+
+        for (path, interfaces) in p.get_managed_objects()? {
+            let mount_point = interfaces
+                .get(IFACE)
+                .and_then(|props| prop_cast::<String>(props, MOUNT_POINT))
+                .ok_or_else(|| format!("{} does not expose a mount point", path))?;
+
+            mounts.push(MountId {
+                service: service.clone(),
+                path,
+                mount_point: mount_point.clone(),
+            });
         }
     }
 
-    fn none_error() -> Self {
-        DBusClientError::with_message(format!("Could not obtain running {} services", PROJ_NAME))
-    }
+    Ok(mounts)
 }
 
-impl Debug for DBusClientError {
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        f.write_str(&self.message)?;
+pub fn set_offline_mode(
+    connection: &Connection,
+    mount: &MountId,
+    enabled: bool,
+) -> ClientResult<()> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(2000));
+    p.set(IFACE, OFFLINE_MODE, Variant(enabled))?;
 
-        if !self.mount_points.is_empty() {
-            f.write_str("\n\nAvailable mount points:")?;
+    Ok(())
+}
 
-            for mount_point in &self.mount_points {
-                f.write_str(&format!("* {}", mount_point))?;
-            }
-        }
+pub fn get_status(connection: &Connection, mount: &MountId) -> ClientResult<ClientStatus> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(2000));
 
-        Ok(())
-    }
+    Ok(ClientStatus {
+        mount_point: p.get(IFACE, MOUNT_POINT)?,
+        server_address: p.get(IFACE, SERVER_ADDRESS)?,
+        offline_mode: p.get(IFACE, OFFLINE_MODE)?,
+        cache_size: p.get(IFACE, CACHE_SIZE)?,
+        pending_journal_ops: p.get(IFACE, PENDING_JOURNAL_OPS)?,
+        last_error: p.get(IFACE, LAST_ERROR)?,
+        degraded: p.get(IFACE, DEGRADED)?,
+    })
 }
 
-impl From<dbus::Error> for DBusClientError {
-    fn from(error: dbus::Error) -> Self {
-        DBusClientError::with_message(error.to_string())
-    }
-}
+pub fn get_transfers(
+    connection: &Connection,
+    mount: &MountId,
+) -> ClientResult<Vec<TransferStatus>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(2000));
+    let transfers: Vec<(String, String, u64, u64)> = p.get(IFACE, TRANSFERS)?;
 
-impl From<String> for DBusClientError {
-    fn from(message: String) -> Self {
-        DBusClientError::with_message(message)
-    }
+    Ok(transfers.into_iter().map(TransferStatus::from).collect())
 }
 
-pub fn get_connection() -> DBusClientResult<Connection> {
-    Ok(Connection::new_session()?)
+pub fn get_quarantined_operations(
+    connection: &Connection,
+    mount: &MountId,
+) -> ClientResult<Vec<QuarantinedOperation>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(2000));
+    let operations: Vec<(String, String, String)> = p.get(IFACE, QUARANTINED_JOURNAL_OPS)?;
+
+    Ok(operations
+        .into_iter()
+        .map(QuarantinedOperation::from)
+        .collect())
 }
 
-fn get_services(connection: &Connection) -> Result<Vec<String>, DBusClientError> {
-    let m = Message::new_method_call(
-        "org.freedesktop.DBus",
-        "/",
-        "org.freedesktop.DBus",
-        "ListNames",
-    )?;
-    let r = connection.send_with_reply_and_block(m, Duration::from_millis(1000))?;
-    let arr: Array<&str, _> = r.get1().ok_or(DBusClientError::none_error())?;
+/// Checks the journal of the client against the server without applying it.
+pub fn review_journal(
+    connection: &Connection,
+    mount: &MountId,
+) -> ClientResult<Vec<ReviewedOperation>> {
+    // The whole journal, along with its chunk lists, is sent to the server
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_secs(5 * 60));
+    let (operations,): (Vec<(String, String, String, String)>,) =
+        p.method_call(IFACE, REVIEW_JOURNAL, ())?;
 
-    Ok(arr
-        .filter(|x| x.starts_with(ID_PREFIX))
-        .map(|x| x.to_owned())
+    Ok(operations
+        .into_iter()
+        .map(ReviewedOperation::from)
         .collect())
 }
 
-fn get_mount_points(
+/// Makes the client retrieve the whole subtree of the directory at `path`, relative to the mount
+/// point. Returns the number of files retrieved.
+pub fn prefetch(connection: &Connection, mount: &MountId, path: &str) -> ClientResult<u64> {
+    // Listing a large tree can take a while
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_secs(60 * 60));
+    let (count,): (u64,) = p.method_call(IFACE, PREFETCH, (path,))?;
+
+    Ok(count)
+}
+
+/// Returns the paths of up to `limit` cached files (all of them if 0) whose names match the
+/// pattern.
+pub fn locate(
     connection: &Connection,
-    services: Vec<String>,
-) -> Result<Vec<String>, DBusClientError> {
-    let mut vec = Vec::new();
+    mount: &MountId,
+    pattern: &str,
+    limit: u32,
+) -> ClientResult<Vec<String>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (paths,): (Vec<String>,) = p.method_call(IFACE, LOCATE, (pattern, limit))?;
 
-    for id in services {
-        vec.push(get_mount_point(connection, &id)?);
-    }
+    Ok(paths)
+}
 
-    Ok(vec)
+/// Returns the total size and number of the files in the subtree of the directory at `path`,
+/// relative to the mount point.
+pub fn dir_usage(connection: &Connection, mount: &MountId, path: &str) -> ClientResult<DirUsage> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (size, files, directories): (u64, u64, u64) = p.method_call(IFACE, DIR_USAGE, (path,))?;
+
+    Ok(DirUsage {
+        size,
+        files,
+        directories,
+    })
 }
 
-fn get_mount_point(connection: &Connection, service_id: &str) -> Result<String, DBusClientError> {
-    let p = connection.with_proxy(service_id, PATH, Duration::from_millis(1000));
-    Ok(p.get(IFACE, MOUNT_POINT)?)
+/// Returns the deduplication statistics of the cache, along with up to `limit` files (all of
+/// them if 0) that save the most space by sharing their blobs.
+pub fn dedup_stats(
+    connection: &Connection,
+    mount: &MountId,
+    limit: u32,
+) -> ClientResult<DedupStats> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (logical_size, physical_size, blobs, files): (u64, u64, u64, Vec<(String, u64, u64)>) =
+        p.method_call(IFACE, DEDUP_STATS, (limit,))?;
+
+    Ok(DedupStats {
+        logical_size,
+        physical_size,
+        blobs,
+        files: files
+            .into_iter()
+            .map(|(path, logical_size, physical_size)| FileDedupStats {
+                path,
+                logical_size,
+                physical_size,
+            })
+            .collect(),
+    })
 }
 
-pub fn get_id_by_mountpoint(
+/// Creates a link giving read-only access to the file or directory at `path`, relative to the
+/// mount point, for `expires_in` seconds. Returns the URL and the Unix time it expires at.
+pub fn create_share_link(
     connection: &Connection,
-    mount_point: &str,
-) -> Result<String, DBusClientError> {
-    let services = get_services(&connection)?;
+    mount: &MountId,
+    path: &str,
+    expires_in: u64,
+) -> ClientResult<(String, i64)> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (url, expires_at): (String, i64) =
+        p.method_call(IFACE, CREATE_SHARE_LINK, (path, expires_in))?;
 
-    let mut mount_points = Vec::new();
+    Ok((url, expires_at))
+}
 
-    for service in services {
-        let mp = get_mount_point(&connection, &service)?;
+/// Makes the client re-read its configuration file.
+pub fn reload(connection: &Connection, mount: &MountId) -> ClientResult<()> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (): () = p.method_call(IFACE, RELOAD, ())?;
 
-        if mp == mount_point {
-            return Ok(service);
-        }
+    Ok(())
+}
 
-        mount_points.push(mp);
-    }
+/// Removes the cached content of the file or directory tree at `path`, relative to the mount
+/// point. Returns the number of files and the size of the content removed.
+pub fn dehydrate(connection: &Connection, mount: &MountId, path: &str) -> ClientResult<(u64, u64)> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_secs(60));
+    let (files, size): (u64, u64) = p.method_call(IFACE, DEHYDRATE, (path,))?;
 
-    Err(DBusClientError::with_message_and_mp_list(
-        format!(
-            "{} client running for the mount point specified was not found",
-            PROJ_NAME
-        ),
-        mount_points,
-    ))
-}
-
-pub fn get_only_service(connection: &Connection) -> Result<String, DBusClientError> {
-    let mut services = get_services(&connection)?;
-
-    if services.len() == 1 {
-        Ok(services.pop().ok_or(DBusClientError::none_error())?)
-    } else if services.is_empty() {
-        Err(DBusClientError::with_message(format!(
-            "No {} clients found",
-            PROJ_NAME
-        )))
-    } else {
-        Err(DBusClientError::with_message_and_mp_list(
-            format!("More than one {} client found", PROJ_NAME),
-            get_mount_points(&connection, services)?,
-        ))
-    }
+    Ok((files, size))
 }
 
-pub fn set_offline_mode(
+/// Copies the file or directory tree at `path`, relative to the mount point, to the absolute
+/// path `target`. Returns the number of files and their total size.
+pub fn export(
     connection: &Connection,
-    service_id: &str,
-    enabled: bool,
-) -> Result<(), DBusClientError> {
-    let p = connection.with_proxy(service_id, PATH, Duration::from_millis(2000));
-    p.set(IFACE, OFFLINE_MODE, Variant(enabled))?;
+    mount: &MountId,
+    path: &str,
+    target: &str,
+) -> ClientResult<(u64, u64)> {
+    // Retrieving the content of a large tree can take a while
+    let p = connection.with_proxy(
+        &mount.service,
+        &mount.path,
+        Duration::from_secs(24 * 60 * 60),
+    );
+    let (files, size): (u64, u64) = p.method_call(IFACE, EXPORT, (path, target))?;
+
+    Ok((files, size))
+}
+
+/// Lists the data attached to the file at `path`, relative to the mount point.
+pub fn list_sidecars(
+    connection: &Connection,
+    mount: &MountId,
+    path: &str,
+) -> ClientResult<Vec<Sidecar>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (sidecars,): (Vec<(String, u64, bool)>,) = p.method_call(IFACE, LIST_SIDECARS, (path,))?;
+
+    Ok(sidecars
+        .into_iter()
+        .map(|(name, size, up_to_date)| Sidecar {
+            name,
+            size,
+            up_to_date,
+        })
+        .collect())
+}
+
+/// Returns the data attached to the file at `path`, relative to the mount point, under the
+/// name.
+pub fn get_sidecar(
+    connection: &Connection,
+    mount: &MountId,
+    path: &str,
+    name: &str,
+) -> ClientResult<Vec<u8>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (data,): (Vec<u8>,) = p.method_call(IFACE, GET_SIDECAR, (path, name))?;
+
+    Ok(data)
+}
+
+/// Attaches the data to the file at `path`, relative to the mount point, under the name, or
+/// removes it if `data` is empty.
+pub fn set_sidecar(
+    connection: &Connection,
+    mount: &MountId,
+    path: &str,
+    name: &str,
+    data: &[u8],
+) -> ClientResult<()> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (): () = p.method_call(IFACE, SET_SIDECAR, (path, name, data))?;
+
+    Ok(())
+}
+
+/// Excludes the directory at `path`, relative to the mount point, from the synchronization.
+pub fn exclude_from_sync(connection: &Connection, mount: &MountId, path: &str) -> ClientResult<()> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (): () = p.method_call(IFACE, EXCLUDE_FROM_SYNC, (path,))?;
 
     Ok(())
 }
+
+/// Includes the directory at `path`, relative to the mount point, in the synchronization again.
+pub fn include_in_sync(connection: &Connection, mount: &MountId, path: &str) -> ClientResult<()> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (): () = p.method_call(IFACE, INCLUDE_IN_SYNC, (path,))?;
+
+    Ok(())
+}
+
+/// Returns whether the file at `path`, relative to the mount point, has been synchronized with
+/// the server: `synced`, `pending`, `conflicted` or `offline-only`.
+pub fn get_sync_state(
+    connection: &Connection,
+    mount: &MountId,
+    path: &str,
+) -> ClientResult<String> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(10000));
+    let (state,): (String,) = p.method_call(IFACE, SYNC_STATE, (path,))?;
+
+    Ok(state)
+}
+
+/// Returns the paths of the directories excluded from the synchronization, relative to the
+/// mount point.
+pub fn get_excluded_from_sync(
+    connection: &Connection,
+    mount: &MountId,
+) -> ClientResult<Vec<String>> {
+    let p = connection.with_proxy(&mount.service, &mount.path, Duration::from_millis(2000));
+
+    Ok(p.get(IFACE, EXCLUDED_FROM_SYNC)?)
+}
@@ -1,8 +1,337 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, PathBuf};
+
 use clap::{App, AppSettings, Arg, SubCommand};
+use serde_json::json;
 
 use offs::{PROJ_AUTHORS, PROJ_NAME, PROJ_VERSION};
 
+use crate::mount::{
+    ClientStatus, DedupStats, DirUsage, Mount, QuarantinedOperation, ReviewedOperation, Sidecar,
+    TransferStatus,
+};
+
 mod dbus_client;
+mod mount;
+mod socket_client;
+
+fn print_status(status: &ClientStatus, as_json: bool) {
+    if as_json {
+        let value = json!({
+            "mount_point": status.mount_point,
+            "server_address": status.server_address,
+            "offline_mode": status.offline_mode,
+            "cache_size": status.cache_size,
+            "pending_journal_ops": status.pending_journal_ops,
+            "last_error": if status.last_error.is_empty() {
+                None
+            } else {
+                Some(&status.last_error)
+            },
+            "degraded": status.degraded,
+        });
+        println!("{}", value);
+    } else {
+        println!("Mount point:         {}", status.mount_point);
+        println!("Server address:      {}", status.server_address);
+        println!(
+            "Offline mode:        {}",
+            if status.offline_mode { "on" } else { "off" }
+        );
+        println!("Cache size:          {} bytes", status.cache_size);
+        println!("Pending journal ops: {}", status.pending_journal_ops);
+        println!(
+            "Last error:          {}",
+            if status.last_error.is_empty() {
+                "none"
+            } else {
+                &status.last_error
+            }
+        );
+        println!(
+            "Degraded:            {}",
+            if status.degraded { "yes" } else { "no" }
+        );
+    }
+}
+
+fn print_transfers(transfers: &[TransferStatus], as_json: bool) {
+    if as_json {
+        let value: Vec<_> = transfers
+            .iter()
+            .map(|x| {
+                json!({
+                    "direction": x.direction,
+                    "name": x.name,
+                    "bytes_done": x.bytes_done,
+                    "bytes_total": x.bytes_total,
+                })
+            })
+            .collect();
+        println!("{}", json!(value));
+    } else if transfers.is_empty() {
+        println!("No transfers in progress");
+    } else {
+        for transfer in transfers {
+            let percent = if transfer.bytes_total == 0 {
+                100.0
+            } else {
+                transfer.bytes_done as f64 * 100.0 / transfer.bytes_total as f64
+            };
+            println!(
+                "{:<8} {:>5.1}% {:>12} / {:<12} {}",
+                transfer.direction,
+                percent,
+                transfer.bytes_done,
+                transfer.bytes_total,
+                transfer.name
+            );
+        }
+    }
+}
+
+fn print_quarantined_operations(operations: &[QuarantinedOperation], as_json: bool) {
+    if as_json {
+        let value: Vec<_> = operations
+            .iter()
+            .map(|x| {
+                json!({
+                    "file": x.file,
+                    "operation": x.operation,
+                    "reason": x.reason,
+                })
+            })
+            .collect();
+        println!("{}", json!(value));
+    } else if operations.is_empty() {
+        println!("No quarantined journal operations");
+    } else {
+        for operation in operations {
+            println!(
+                "{:<16} {:<24} {}",
+                operation.operation, operation.file, operation.reason
+            );
+        }
+    }
+}
+
+fn print_reviewed_operations(operations: &[ReviewedOperation], mount: &Mount, as_json: bool) {
+    if as_json {
+        let value: Vec<_> = operations
+            .iter()
+            .map(|x| {
+                json!({
+                    "path": x.path,
+                    "operation": x.operation,
+                    "status": x.status,
+                    "reason": x.reason,
+                })
+            })
+            .collect();
+        println!("{}", json!(value));
+    } else if operations.is_empty() {
+        println!("No pending journal operations");
+    } else {
+        for operation in operations {
+            println!(
+                "{:<12} {:<16} {}{} {}",
+                operation.status,
+                operation.operation,
+                mount.mount_point.trim_end_matches('/'),
+                operation.path,
+                operation.reason
+            );
+        }
+    }
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().expect("Could not write to stdout");
+
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .expect("Could not read the answer");
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn print_sidecars(sidecars: &[Sidecar], as_json: bool) {
+    if as_json {
+        let value: Vec<_> = sidecars
+            .iter()
+            .map(|x| {
+                json!({
+                    "name": x.name,
+                    "size": x.size,
+                    "up_to_date": x.up_to_date,
+                })
+            })
+            .collect();
+        println!("{}", json!(value));
+    } else {
+        for sidecar in sidecars {
+            println!(
+                "{:>8} {:<8} {}",
+                sidecar.size,
+                if sidecar.up_to_date {
+                    "current"
+                } else {
+                    "stale"
+                },
+                sidecar.name
+            );
+        }
+    }
+}
+
+fn print_dir_usage(usage: &DirUsage, as_json: bool) {
+    if as_json {
+        let value = json!({
+            "size": usage.size,
+            "files": usage.files,
+            "directories": usage.directories,
+        });
+        println!("{}", value);
+    } else {
+        println!("Size:        {} bytes", usage.size);
+        println!("Files:       {}", usage.files);
+        println!("Directories: {}", usage.directories);
+    }
+}
+
+fn dedup_ratio(logical_size: u64, physical_size: u64) -> f64 {
+    if physical_size == 0 {
+        1.0
+    } else {
+        logical_size as f64 / physical_size as f64
+    }
+}
+
+fn print_dedup_stats(stats: &DedupStats, mount: &Mount, as_json: bool) {
+    let mount_point = mount.mount_point.trim_end_matches('/');
+
+    if as_json {
+        let files: Vec<_> = stats
+            .files
+            .iter()
+            .map(|x| {
+                json!({
+                    "path": format!("{}{}", mount_point, x.path),
+                    "logical_size": x.logical_size,
+                    "physical_size": x.physical_size,
+                })
+            })
+            .collect();
+        let value = json!({
+            "logical_size": stats.logical_size,
+            "physical_size": stats.physical_size,
+            "blobs": stats.blobs,
+            "files": files,
+        });
+        println!("{}", value);
+    } else {
+        println!("Logical size:  {} bytes", stats.logical_size);
+        println!("Physical size: {} bytes", stats.physical_size);
+        println!("Blobs:         {}", stats.blobs);
+        println!(
+            "Dedup ratio:   {:.2}",
+            dedup_ratio(stats.logical_size, stats.physical_size)
+        );
+        if !stats.files.is_empty() {
+            println!();
+            println!("{:>7} {:>12} {:>12} Path", "Ratio", "Logical", "Physical");
+        }
+        for file in &stats.files {
+            println!(
+                "{:>7.2} {:>12} {:>12} {}{}",
+                dedup_ratio(file.logical_size, file.physical_size),
+                file.logical_size,
+                file.physical_size,
+                mount_point,
+                file.path
+            );
+        }
+    }
+}
+
+/// Parses a duration given as a number of seconds, or followed by one of the `m`, `h`, `d`
+/// and `w` units, into seconds.
+fn parse_duration(duration: &str) -> Result<u64, String> {
+    let (number, unit) = match duration.find(|x: char| !x.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration unit: {}", unit)),
+    };
+    let number: u64 = number.parse().map_err(|_| "Invalid duration".to_owned())?;
+    if number == 0 {
+        return Err("The duration has to be positive".to_owned());
+    }
+
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| "The duration is too long".to_owned())
+}
+
+/// Resolves the path against the current directory.
+fn absolute_path(path: &str) -> Result<PathBuf, String> {
+    let current_dir = env::current_dir().map_err(|e| e.to_string())?;
+    let mut absolute = PathBuf::new();
+    // The path is not canonicalized, as that would need to look it up through the mount
+    for component in current_dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            _ => absolute.push(component),
+        }
+    }
+
+    Ok(absolute)
+}
+
+/// Returns the path relative to the mount point, resolving the relative paths against the
+/// current directory.
+fn path_in_mount(mount: &Mount, path: &str) -> Result<String, String> {
+    let absolute = absolute_path(path)?;
+    let relative = absolute
+        .strip_prefix(&mount.mount_point)
+        .map_err(|_| format!("{} is not within {}", absolute.display(), mount.mount_point))?;
+
+    relative
+        .to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| format!("{} is not valid Unicode", absolute.display()))
+}
+
+/// Returns the absolute path to export the files to, which cannot be within the mount, as the
+/// client would then wait for itself.
+fn export_target(mount: &Mount, path: &str) -> Result<String, String> {
+    let absolute = absolute_path(path)?;
+    if absolute.starts_with(&mount.mount_point) {
+        return Err(format!(
+            "{} is within {}",
+            absolute.display(),
+            mount.mount_point
+        ));
+    }
+
+    absolute
+        .to_str()
+        .map(str::to_owned)
+        .ok_or_else(|| format!("{} is not valid Unicode", absolute.display()))
+}
 
 fn main() {
     let matches = App::new(format!("{} client controller", PROJ_NAME))
@@ -28,27 +357,475 @@ fn main() {
                     .help("Whether to enable or disable offline mode"),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("status").arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Prints the status as a JSON object"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("transfers").arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Prints the transfers as a JSON array"),
+            ),
+        )
+        .subcommand(
+            SubCommand::with_name("prefetch")
+                .about("Retrieves the entries of a whole directory tree into the cache")
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("Directory within the mount point to retrieve"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("locate")
+                .about("Searches the cache for the files whose names match a pattern")
+                .arg(
+                    Arg::with_name("limit")
+                        .short("l")
+                        .long("limit")
+                        .value_name("LIMIT")
+                        .validator(|x| x.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                        .help("Prints at most LIMIT paths"),
+                )
+                .arg(
+                    Arg::with_name("pattern")
+                        .required(true)
+                        .help("Glob pattern, or a part of the name if it has no wildcards"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("du")
+                .about("Shows the total size and number of the files in a directory tree")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the usage as a JSON object"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("Directory within the mount point to summarize"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Shows how much space the deduplication of the cached blobs saves")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the statistics as a JSON object"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .short("l")
+                        .long("limit")
+                        .value_name("LIMIT")
+                        .default_value("10")
+                        .validator(|x| x.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                        .help("Lists at most LIMIT files sharing their content (all if 0)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("share")
+                .about("Creates a link giving read-only access to a file or directory over HTTP")
+                .arg(
+                    Arg::with_name("expires")
+                        .short("e")
+                        .long("expires")
+                        .value_name("DURATION")
+                        .default_value("7d")
+                        .validator(|x| parse_duration(&x).map(|_| ()))
+                        .help("How long the link stays valid, e.g. 30m, 12h, 7d or 2w"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the link as a JSON object"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("File or directory within the mount point to share"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reload")
+                .about("Makes the client re-read its configuration file without remounting"),
+        )
+        .subcommand(
+            SubCommand::with_name("dehydrate")
+                .about("Removes the cached content of files, keeping them listed")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the space reclaimed as a JSON object"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("File or directory within the mount point to dehydrate"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Copies a file or directory tree out of the filesystem")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the number and size of the files copied as a JSON object"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("File or directory within the mount point to copy"),
+                )
+                .arg(
+                    Arg::with_name("target")
+                        .required(true)
+                        .help("Path outside of the mount point to create the copy at"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sidecar")
+                .about("Manages the data derived from files, such as thumbnails, attached to them")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists the data attached to a file")
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints the data as a JSON array"),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("File within the mount point"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Writes the data attached to a file under a name to stdout")
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("File within the mount point"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("Name of the data"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Attaches the contents of a local file to a file under a name")
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("File within the mount point"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("Name of the data"),
+                        )
+                        .arg(
+                            Arg::with_name("source")
+                                .required(true)
+                                .help("Local file to read the data from"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Removes the data attached to a file under a name")
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("File within the mount point"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .help("Name of the data"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("selective-sync")
+                .about("Excludes directories from the synchronization to save the disk space")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Removes a directory from the cache and stops retrieving it")
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("Directory within the mount point to exclude"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Makes an excluded directory synchronized again")
+                        .arg(
+                            Arg::with_name("path")
+                                .required(true)
+                                .help("Directory within the mount point to include"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists the directories excluded from the synchronization")
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints the directories as a JSON array"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sync-state")
+                .about("Shows whether a file has been synchronized with the server")
+                .arg(
+                    Arg::with_name("path")
+                        .required(true)
+                        .help("File or directory within the mount point"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("journal")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("review")
+                        .about(
+                            "Checks the pending journal operations against the server without \
+                             sending them; if offline, offers to synchronize afterwards",
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints the operations as a JSON array"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("quarantined")
+                        .about("Lists the journal operations rejected by the server")
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints the operations as a JSON array"),
+                        ),
+                ),
+        )
         .get_matches();
 
     let mount_point = matches.value_of("mountpoint").unwrap_or("");
 
-    let connection = dbus_client::get_connection().expect("Could not obtain DBus connection");
-    let service_id = if mount_point.is_empty() {
-        dbus_client::get_only_service(&connection)
+    let mount = if mount_point.is_empty() {
+        mount::get_only_mount()
     } else {
-        dbus_client::get_id_by_mountpoint(&connection, mount_point)
+        mount::get_by_mountpoint(mount_point)
     }
     .expect(&format!("Could not get {} service", PROJ_NAME));
 
     match matches.subcommand() {
         ("offline-mode", Some(sub_m)) => {
-            dbus_client::set_offline_mode(
-                &connection,
-                &service_id,
-                sub_m.value_of("enable").unwrap() == "on",
-            )
-            .expect("Could not set offline mode");
+            mount
+                .set_offline_mode(sub_m.value_of("enable").unwrap() == "on")
+                .expect("Could not set offline mode");
         }
+        ("status", Some(sub_m)) => {
+            let status = mount.get_status().expect("Could not get client status");
+            print_status(&status, sub_m.is_present("json"));
+        }
+        ("transfers", Some(sub_m)) => {
+            let transfers = mount
+                .get_transfers()
+                .expect("Could not get the transfers in progress");
+            print_transfers(&transfers, sub_m.is_present("json"));
+        }
+        ("prefetch", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not prefetch the directory");
+            let count = mount
+                .prefetch(&path)
+                .expect("Could not prefetch the directory");
+            println!("Retrieved {} files", count);
+        }
+        ("locate", Some(sub_m)) => {
+            let limit = sub_m.value_of("limit").map_or(0, |x| x.parse().unwrap());
+            let paths = mount
+                .locate(sub_m.value_of("pattern").unwrap(), limit)
+                .expect("Could not search the cache");
+            for path in paths {
+                println!("{}{}", mount.mount_point.trim_end_matches('/'), path);
+            }
+        }
+        ("du", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not get the directory usage");
+            let usage = mount
+                .dir_usage(&path)
+                .expect("Could not get the directory usage");
+            print_dir_usage(&usage, sub_m.is_present("json"));
+        }
+        ("stats", Some(sub_m)) => {
+            let limit = sub_m.value_of("limit").unwrap().parse().unwrap();
+            let stats = mount
+                .dedup_stats(limit)
+                .expect("Could not get the deduplication statistics");
+            print_dedup_stats(&stats, &mount, sub_m.is_present("json"));
+        }
+        ("share", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not create the share link");
+            let expires_in = parse_duration(sub_m.value_of("expires").unwrap()).unwrap();
+            let (url, expires_at) = mount
+                .create_share_link(&path, expires_in)
+                .expect("Could not create the share link");
+            if sub_m.is_present("json") {
+                println!("{}", json!({ "url": url, "expires_at": expires_at }));
+            } else {
+                println!("{}", url);
+            }
+        }
+        ("reload", Some(_)) => {
+            mount.reload().expect("Could not reload the configuration");
+        }
+        ("dehydrate", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not dehydrate the files");
+            let (files, size) = mount
+                .dehydrate(&path)
+                .expect("Could not dehydrate the files");
+            if sub_m.is_present("json") {
+                println!("{}", json!({ "files": files, "size": size }));
+            } else {
+                println!("Dehydrated {} files, reclaimed {} bytes", files, size);
+            }
+        }
+        ("export", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not export the files");
+            let target = export_target(&mount, sub_m.value_of("target").unwrap())
+                .expect("Could not export the files");
+            let (files, size) = mount
+                .export(&path, &target)
+                .expect("Could not export the files");
+            if sub_m.is_present("json") {
+                println!("{}", json!({ "files": files, "size": size }));
+            } else {
+                println!("Exported {} files, {} bytes", files, size);
+            }
+        }
+        ("sidecar", Some(sub_m)) => match sub_m.subcommand() {
+            ("list", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not list the sidecar data");
+                let sidecars = mount
+                    .list_sidecars(&path)
+                    .expect("Could not list the sidecar data");
+                print_sidecars(&sidecars, sub_m.is_present("json"));
+            }
+            ("get", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not get the sidecar data");
+                let data = mount
+                    .get_sidecar(&path, sub_m.value_of("name").unwrap())
+                    .expect("Could not get the sidecar data");
+                io::stdout()
+                    .write_all(&data)
+                    .expect("Could not write the sidecar data");
+            }
+            ("set", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not set the sidecar data");
+                let data =
+                    fs::read(sub_m.value_of("source").unwrap()).expect("Could not read the data");
+                if data.is_empty() {
+                    panic!("The data cannot be empty");
+                }
+                mount
+                    .set_sidecar(&path, sub_m.value_of("name").unwrap(), &data)
+                    .expect("Could not set the sidecar data");
+            }
+            ("remove", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not remove the sidecar data");
+                mount
+                    .set_sidecar(&path, sub_m.value_of("name").unwrap(), &[])
+                    .expect("Could not remove the sidecar data");
+            }
+            _ => unreachable!(),
+        },
+        ("selective-sync", Some(sub_m)) => match sub_m.subcommand() {
+            ("add", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not exclude the directory");
+                mount
+                    .exclude_from_sync(&path)
+                    .expect("Could not exclude the directory");
+            }
+            ("remove", Some(sub_m)) => {
+                let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                    .expect("Could not include the directory");
+                mount
+                    .include_in_sync(&path)
+                    .expect("Could not include the directory");
+            }
+            ("list", Some(sub_m)) => {
+                let paths = mount
+                    .get_excluded_from_sync()
+                    .expect("Could not get the excluded directories");
+                if sub_m.is_present("json") {
+                    println!("{}", json!(paths));
+                } else {
+                    for path in paths {
+                        println!("{}{}", mount.mount_point.trim_end_matches('/'), path);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        },
+        ("sync-state", Some(sub_m)) => {
+            let path = path_in_mount(&mount, sub_m.value_of("path").unwrap())
+                .expect("Could not get the synchronization state");
+            let state = mount
+                .get_sync_state(&path)
+                .expect("Could not get the synchronization state");
+            println!("{}", state);
+        }
+        ("journal", Some(sub_m)) => match sub_m.subcommand() {
+            ("review", Some(sub_m)) => {
+                let as_json = sub_m.is_present("json");
+                let operations = mount
+                    .review_journal()
+                    .expect("Could not review the journal");
+                print_reviewed_operations(&operations, &mount, as_json);
+
+                let status = mount.get_status().expect("Could not get client status");
+                if !as_json
+                    && !operations.is_empty()
+                    && status.offline_mode
+                    && confirm("Send the changes to the server?")
+                {
+                    mount
+                        .set_offline_mode(false)
+                        .expect("Could not set offline mode");
+                }
+            }
+            ("quarantined", Some(sub_m)) => {
+                let operations = mount
+                    .get_quarantined_operations()
+                    .expect("Could not get the quarantined journal operations");
+                print_quarantined_operations(&operations, sub_m.is_present("json"));
+            }
+            _ => unreachable!(),
+        },
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,521 @@
+use std::fmt::{Debug, Error, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use serde::Deserialize;
+use serde_json::json;
+
+use offs::dbus::{
+    DEDUP_STATS, DIR_USAGE, EXCLUDED_FROM_SYNC, EXCLUDE_FROM_SYNC, GET_SIDECAR, INCLUDE_IN_SYNC,
+    LIST_SIDECARS, LOCATE, PREFETCH, QUARANTINED_JOURNAL_OPS, RELOAD, REVIEW_JOURNAL, SET_SIDECAR,
+    SYNC_STATE, TRANSFERS,
+};
+use offs::PROJ_NAME;
+
+use crate::dbus_client::{self, MountId};
+use crate::socket_client;
+
+pub struct ClientStatus {
+    pub mount_point: String,
+    pub server_address: String,
+    pub offline_mode: bool,
+    pub cache_size: u64,
+    pub pending_journal_ops: u64,
+    pub last_error: String,
+    pub degraded: bool,
+}
+
+pub struct TransferStatus {
+    pub direction: String,
+    pub name: String,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+impl From<(String, String, u64, u64)> for TransferStatus {
+    fn from((direction, name, bytes_done, bytes_total): (String, String, u64, u64)) -> Self {
+        Self {
+            direction,
+            name,
+            bytes_done,
+            bytes_total,
+        }
+    }
+}
+
+pub struct QuarantinedOperation {
+    pub file: String,
+    pub operation: String,
+    pub reason: String,
+}
+
+impl From<(String, String, String)> for QuarantinedOperation {
+    fn from((file, operation, reason): (String, String, String)) -> Self {
+        Self {
+            file,
+            operation,
+            reason,
+        }
+    }
+}
+
+pub struct ReviewedOperation {
+    pub path: String,
+    pub operation: String,
+    /// `ok`, `conflicted` or `rejected`
+    pub status: String,
+    pub reason: String,
+}
+
+impl From<(String, String, String, String)> for ReviewedOperation {
+    fn from((path, operation, status, reason): (String, String, String, String)) -> Self {
+        Self {
+            path,
+            operation,
+            status,
+            reason,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DirUsage {
+    pub size: u64,
+    pub files: u64,
+    pub directories: u64,
+}
+
+#[derive(Deserialize)]
+pub struct FileDedupStats {
+    pub path: String,
+    pub logical_size: u64,
+    pub physical_size: u64,
+}
+
+#[derive(Deserialize)]
+pub struct DedupStats {
+    pub logical_size: u64,
+    pub physical_size: u64,
+    pub blobs: u64,
+    pub files: Vec<FileDedupStats>,
+}
+
+#[derive(Deserialize)]
+pub struct Sidecar {
+    pub name: String,
+    pub size: u64,
+    pub up_to_date: bool,
+}
+
+pub struct ClientError {
+    pub message: String,
+    pub mount_points: Vec<String>,
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+impl ClientError {
+    pub fn with_message(message: String) -> Self {
+        Self {
+            message,
+            mount_points: Default::default(),
+        }
+    }
+
+    fn with_message_and_mp_list(message: String, mount_points: Vec<String>) -> Self {
+        Self {
+            message,
+            mount_points,
+        }
+    }
+
+    pub fn none_error() -> Self {
+        ClientError::with_message(format!("Could not obtain running {} services", PROJ_NAME))
+    }
+}
+
+impl Debug for ClientError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str(&self.message)?;
+
+        if !self.mount_points.is_empty() {
+            f.write_str("\n\nAvailable mount points:")?;
+
+            for mount_point in &self.mount_points {
+                f.write_str(&format!("* {}", mount_point))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<dbus::Error> for ClientError {
+    fn from(error: dbus::Error) -> Self {
+        ClientError::with_message(error.to_string())
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(error: io::Error) -> Self {
+        ClientError::with_message(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(error: serde_json::Error) -> Self {
+        ClientError::with_message(error.to_string())
+    }
+}
+
+impl From<String> for ClientError {
+    fn from(message: String) -> Self {
+        ClientError::with_message(message)
+    }
+}
+
+enum Channel {
+    DBus(Rc<Connection>, MountId),
+    /// The control socket, for the clients not on the session bus
+    Socket(PathBuf),
+}
+
+/// A single mount served by an offs client process, controlled over D-Bus or its control
+/// socket.
+pub struct Mount {
+    pub mount_point: String,
+    channel: Channel,
+}
+
+/// Returns the mounts available over D-Bus, along with the ones only available over their
+/// control sockets, either because there is no session bus or because the client does not use
+/// it (`offs-client --no-dbus`).
+fn get_mounts() -> ClientResult<Vec<Mount>> {
+    let mut mounts = Vec::new();
+
+    let dbus_error = match dbus_client::get_connection()
+        .and_then(|c| dbus_client::get_mounts(&c).map(|ids| (c, ids)))
+    {
+        Ok((connection, ids)) => {
+            let connection = Rc::new(connection);
+            for id in ids {
+                mounts.push(Mount {
+                    mount_point: id.mount_point.clone(),
+                    channel: Channel::DBus(connection.clone(), id),
+                });
+            }
+
+            None
+        }
+        Err(e) => Some(e),
+    };
+
+    for (mount_point, socket) in socket_client::get_mounts()? {
+        if !mounts.iter().any(|x| x.mount_point == mount_point) {
+            mounts.push(Mount {
+                mount_point,
+                channel: Channel::Socket(socket),
+            });
+        }
+    }
+
+    match dbus_error {
+        Some(e) if mounts.is_empty() => Err(ClientError::with_message(format!(
+            "No {} clients found on the control sockets, and D-Bus is unavailable: {}",
+            PROJ_NAME, e.message
+        ))),
+        _ => Ok(mounts),
+    }
+}
+
+pub fn get_by_mountpoint(mount_point: &str) -> ClientResult<Mount> {
+    let mut mounts = get_mounts()?;
+
+    if let Some(pos) = mounts.iter().position(|x| x.mount_point == mount_point) {
+        return Ok(mounts.swap_remove(pos));
+    }
+
+    Err(ClientError::with_message_and_mp_list(
+        format!(
+            "{} client running for the mount point specified was not found",
+            PROJ_NAME
+        ),
+        mounts.into_iter().map(|x| x.mount_point).collect(),
+    ))
+}
+
+pub fn get_only_mount() -> ClientResult<Mount> {
+    let mut mounts = get_mounts()?;
+
+    if mounts.len() == 1 {
+        Ok(mounts.pop().ok_or(ClientError::none_error())?)
+    } else if mounts.is_empty() {
+        Err(ClientError::with_message(format!(
+            "No {} clients found",
+            PROJ_NAME
+        )))
+    } else {
+        Err(ClientError::with_message_and_mp_list(
+            format!("More than one {} mount found", PROJ_NAME),
+            mounts.into_iter().map(|x| x.mount_point).collect(),
+        ))
+    }
+}
+
+/// The operations on the mount, with the same timeouts on both channels.
+impl Mount {
+    pub fn set_offline_mode(&self, enabled: bool) -> ClientResult<()> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::set_offline_mode(connection, id, enabled),
+            Channel::Socket(socket) => socket_client::set_offline_mode(socket, enabled),
+        }
+    }
+
+    pub fn get_status(&self) -> ClientResult<ClientStatus> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::get_status(connection, id),
+            Channel::Socket(socket) => socket_client::get_status(socket),
+        }
+    }
+
+    pub fn get_transfers(&self) -> ClientResult<Vec<TransferStatus>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::get_transfers(connection, id),
+            Channel::Socket(socket) => {
+                let transfers: Vec<(String, String, u64, u64)> =
+                    socket_client::get(socket, TRANSFERS)?;
+
+                Ok(transfers.into_iter().map(TransferStatus::from).collect())
+            }
+        }
+    }
+
+    pub fn get_quarantined_operations(&self) -> ClientResult<Vec<QuarantinedOperation>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => {
+                dbus_client::get_quarantined_operations(connection, id)
+            }
+            Channel::Socket(socket) => {
+                let operations: Vec<(String, String, String)> =
+                    socket_client::get(socket, QUARANTINED_JOURNAL_OPS)?;
+
+                Ok(operations
+                    .into_iter()
+                    .map(QuarantinedOperation::from)
+                    .collect())
+            }
+        }
+    }
+
+    /// Checks the journal of the client against the server without applying it.
+    pub fn review_journal(&self) -> ClientResult<Vec<ReviewedOperation>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::review_journal(connection, id),
+            Channel::Socket(socket) => {
+                let operations: Vec<(String, String, String, String)> = socket_client::call(
+                    socket,
+                    REVIEW_JOURNAL,
+                    json!([]),
+                    Duration::from_secs(5 * 60),
+                )?;
+
+                Ok(operations
+                    .into_iter()
+                    .map(ReviewedOperation::from)
+                    .collect())
+            }
+        }
+    }
+
+    /// Makes the client retrieve the whole subtree of the directory at `path`, relative to the
+    /// mount point. Returns the number of files retrieved.
+    pub fn prefetch(&self, path: &str) -> ClientResult<u64> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::prefetch(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                PREFETCH,
+                json!([path]),
+                Duration::from_secs(60 * 60),
+            ),
+        }
+    }
+
+    /// Returns the paths of up to `limit` cached files (all of them if 0) whose names match the
+    /// pattern.
+    pub fn locate(&self, pattern: &str, limit: u32) -> ClientResult<Vec<String>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::locate(connection, id, pattern, limit),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                LOCATE,
+                json!([pattern, limit]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Returns the total size and number of the files in the subtree of the directory at
+    /// `path`, relative to the mount point.
+    pub fn dir_usage(&self, path: &str) -> ClientResult<DirUsage> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::dir_usage(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                DIR_USAGE,
+                json!([path]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Returns the deduplication statistics of the cache, along with up to `limit` files (all
+    /// of them if 0) that save the most space by sharing their blobs.
+    pub fn dedup_stats(&self, limit: u32) -> ClientResult<DedupStats> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::dedup_stats(connection, id, limit),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                DEDUP_STATS,
+                json!([limit]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Creates a link giving read-only access to the file or directory at `path`, relative to
+    /// the mount point, for `expires_in` seconds. Returns the URL and the Unix time it expires
+    /// at.
+    pub fn create_share_link(&self, path: &str, expires_in: u64) -> ClientResult<(String, i64)> {
+        match &self.channel {
+            Channel::DBus(connection, id) => {
+                dbus_client::create_share_link(connection, id, path, expires_in)
+            }
+            Channel::Socket(socket) => socket_client::create_share_link(socket, path, expires_in),
+        }
+    }
+
+    /// Makes the client re-read its configuration file.
+    pub fn reload(&self) -> ClientResult<()> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::reload(connection, id),
+            Channel::Socket(socket) => {
+                socket_client::call(socket, RELOAD, json!([]), Duration::from_millis(10000))
+            }
+        }
+    }
+
+    /// Removes the cached content of the file or directory tree at `path`, relative to the
+    /// mount point. Returns the number of files and the size of the content removed.
+    pub fn dehydrate(&self, path: &str) -> ClientResult<(u64, u64)> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::dehydrate(connection, id, path),
+            Channel::Socket(socket) => socket_client::dehydrate(socket, path),
+        }
+    }
+
+    /// Copies the file or directory tree at `path`, relative to the mount point, to the
+    /// absolute path `target`. Returns the number of files and their total size.
+    pub fn export(&self, path: &str, target: &str) -> ClientResult<(u64, u64)> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::export(connection, id, path, target),
+            Channel::Socket(socket) => socket_client::export(socket, path, target),
+        }
+    }
+
+    /// Lists the data attached to the file at `path`, relative to the mount point.
+    pub fn list_sidecars(&self, path: &str) -> ClientResult<Vec<Sidecar>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::list_sidecars(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                LIST_SIDECARS,
+                json!([path]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Returns the data attached to the file at `path`, relative to the mount point, under the
+    /// name.
+    pub fn get_sidecar(&self, path: &str, name: &str) -> ClientResult<Vec<u8>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::get_sidecar(connection, id, path, name),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                GET_SIDECAR,
+                json!([path, name]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Attaches the data to the file at `path`, relative to the mount point, under the name, or
+    /// removes it if `data` is empty.
+    pub fn set_sidecar(&self, path: &str, name: &str, data: &[u8]) -> ClientResult<()> {
+        match &self.channel {
+            Channel::DBus(connection, id) => {
+                dbus_client::set_sidecar(connection, id, path, name, data)
+            }
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                SET_SIDECAR,
+                json!([path, name, data]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Excludes the directory at `path`, relative to the mount point, from the synchronization.
+    pub fn exclude_from_sync(&self, path: &str) -> ClientResult<()> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::exclude_from_sync(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                EXCLUDE_FROM_SYNC,
+                json!([path]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Includes the directory at `path`, relative to the mount point, in the synchronization
+    /// again.
+    pub fn include_in_sync(&self, path: &str) -> ClientResult<()> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::include_in_sync(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                INCLUDE_IN_SYNC,
+                json!([path]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Returns whether the file at `path`, relative to the mount point, has been synchronized
+    /// with the server: `synced`, `pending`, `conflicted` or `offline-only`.
+    pub fn get_sync_state(&self, path: &str) -> ClientResult<String> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::get_sync_state(connection, id, path),
+            Channel::Socket(socket) => socket_client::call(
+                socket,
+                SYNC_STATE,
+                json!([path]),
+                Duration::from_millis(10000),
+            ),
+        }
+    }
+
+    /// Returns the paths of the directories excluded from the synchronization, relative to the
+    /// mount point.
+    pub fn get_excluded_from_sync(&self) -> ClientResult<Vec<String>> {
+        match &self.channel {
+            Channel::DBus(connection, id) => dbus_client::get_excluded_from_sync(connection, id),
+            Channel::Socket(socket) => socket_client::get(socket, EXCLUDED_FROM_SYNC),
+        }
+    }
+}
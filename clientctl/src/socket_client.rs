@@ -0,0 +1,165 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use offs::control::{socket_dir, CALL, ERROR, GET, SET, VALUE};
+use offs::dbus::{
+    CACHE_SIZE, CREATE_SHARE_LINK, DEGRADED, DEHYDRATE, EXPORT, LAST_ERROR, MOUNT_POINT,
+    OFFLINE_MODE, PENDING_JOURNAL_OPS, SERVER_ADDRESS,
+};
+
+use crate::mount::{ClientError, ClientResult, ClientStatus};
+
+const PROPERTY_TIMEOUT: Duration = Duration::from_millis(2000);
+
+#[derive(Deserialize)]
+struct ShareLink {
+    url: String,
+    expires_at: i64,
+}
+
+#[derive(Deserialize)]
+struct FileTotals {
+    files: u64,
+    size: u64,
+}
+
+/// Returns the mount points and the control sockets of the running clients. The sockets left
+/// behind by the clients that did not exit cleanly are skipped.
+pub fn get_mounts() -> ClientResult<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(socket_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut mounts = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some("sock".as_ref()) {
+            continue;
+        }
+
+        let stream = match UnixStream::connect(&path) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let mount_point = request_on(
+            stream,
+            PROPERTY_TIMEOUT,
+            &format!("{} {}", GET, MOUNT_POINT),
+        )?;
+        mounts.push((serde_json::from_value(mount_point)?, path));
+    }
+
+    Ok(mounts)
+}
+
+fn request(socket: &Path, timeout: Duration, request: &str) -> ClientResult<Value> {
+    request_on(UnixStream::connect(socket)?, timeout, request)
+}
+
+/// Sends a single request (see [`offs::control`]) and returns the value of the response.
+fn request_on(mut stream: UnixStream, timeout: Duration, request: &str) -> ClientResult<Value> {
+    stream.set_read_timeout(Some(timeout))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let mut response: Value = serde_json::from_str(&line)?;
+    if let Some(message) = response.get(ERROR).and_then(Value::as_str) {
+        return Err(ClientError::with_message(message.to_owned()));
+    }
+
+    response
+        .get_mut(VALUE)
+        .map(Value::take)
+        .ok_or_else(|| ClientError::with_message("Invalid control socket response".to_owned()))
+}
+
+pub fn get<T: DeserializeOwned>(socket: &Path, property: &str) -> ClientResult<T> {
+    let value = request(socket, PROPERTY_TIMEOUT, &format!("{} {}", GET, property))?;
+
+    Ok(serde_json::from_value(value)?)
+}
+
+fn set(socket: &Path, property: &str, value: Value) -> ClientResult<()> {
+    request(
+        socket,
+        PROPERTY_TIMEOUT,
+        &format!("{} {} {}", SET, property, value),
+    )?;
+
+    Ok(())
+}
+
+/// Calls the method, waiting for up to `timeout` for it to finish.
+pub fn call<T: DeserializeOwned>(
+    socket: &Path,
+    method: &str,
+    arguments: Value,
+    timeout: Duration,
+) -> ClientResult<T> {
+    let value = request(
+        socket,
+        timeout,
+        &format!("{} {} {}", CALL, method, arguments),
+    )?;
+
+    Ok(serde_json::from_value(value)?)
+}
+
+pub fn set_offline_mode(socket: &Path, enabled: bool) -> ClientResult<()> {
+    set(socket, OFFLINE_MODE, json!(enabled))
+}
+
+pub fn get_status(socket: &Path) -> ClientResult<ClientStatus> {
+    Ok(ClientStatus {
+        mount_point: get(socket, MOUNT_POINT)?,
+        server_address: get(socket, SERVER_ADDRESS)?,
+        offline_mode: get(socket, OFFLINE_MODE)?,
+        cache_size: get(socket, CACHE_SIZE)?,
+        pending_journal_ops: get(socket, PENDING_JOURNAL_OPS)?,
+        last_error: get(socket, LAST_ERROR)?,
+        degraded: get(socket, DEGRADED)?,
+    })
+}
+
+pub fn create_share_link(
+    socket: &Path,
+    path: &str,
+    expires_in: u64,
+) -> ClientResult<(String, i64)> {
+    let link: ShareLink = call(
+        socket,
+        CREATE_SHARE_LINK,
+        json!([path, expires_in]),
+        Duration::from_millis(10000),
+    )?;
+
+    Ok((link.url, link.expires_at))
+}
+
+pub fn dehydrate(socket: &Path, path: &str) -> ClientResult<(u64, u64)> {
+    let totals: FileTotals = call(socket, DEHYDRATE, json!([path]), Duration::from_secs(60))?;
+
+    Ok((totals.files, totals.size))
+}
+
+pub fn export(socket: &Path, path: &str, target: &str) -> ClientResult<(u64, u64)> {
+    // Retrieving the content of a large tree can take a while
+    let totals: FileTotals = call(
+        socket,
+        EXPORT,
+        json!([path, target]),
+        Duration::from_secs(24 * 60 * 60),
+    )?;
+
+    Ok((totals.files, totals.size))
+}
@@ -0,0 +1,9 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
+
+    cbindgen::generate(&crate_dir)?.write_to_file(crate_dir.join("include/offs.h"));
+    Ok(())
+}
@@ -0,0 +1,198 @@
+#ifndef OFFS_H
+#define OFFS_H
+
+/* Generated by cbindgen from the sources of offs-ffi; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum OffsFileType {
+  OFFS_FILE_TYPE_NAMED_PIPE = 0,
+  OFFS_FILE_TYPE_CHAR_DEVICE,
+  OFFS_FILE_TYPE_BLOCK_DEVICE,
+  OFFS_FILE_TYPE_DIRECTORY,
+  OFFS_FILE_TYPE_REGULAR_FILE,
+  OFFS_FILE_TYPE_SYMLINK,
+  OFFS_FILE_TYPE_SOCKET,
+} OffsFileType;
+
+typedef enum OffsStatus {
+  OFFS_STATUS_OK = 0,
+  OFFS_STATUS_DATABASE_ERROR,
+  OFFS_STATUS_DIRECTORY_NOT_EMPTY,
+  OFFS_STATUS_CONFLICTED_FILE,
+  OFFS_STATUS_INVALID_CONTENT_VERSION,
+  OFFS_STATUS_BLOB_DOES_NOT_EXIST,
+  OFFS_STATUS_OFFLINE,
+  OFFS_STATUS_FILE_DOES_NOT_EXIST,
+  OFFS_STATUS_INVALID_UNICODE,
+  OFFS_STATUS_SHARE_DOES_NOT_EXIST,
+  OFFS_STATUS_UNAUTHENTICATED,
+  OFFS_STATUS_INVALID_ACL,
+  OFFS_STATUS_NO_SPACE,
+  OFFS_STATUS_RENAME_INTO_DESCENDANT,
+  OFFS_STATUS_INVALID_ARGUMENT,
+  OFFS_STATUS_UNSUPPORTED_PROTOCOL,
+  OFFS_STATUS_REQUEST_TOO_LARGE,
+  OFFS_STATUS_RATE_LIMITED,
+  OFFS_STATUS_QUOTA_EXCEEDED,
+  OFFS_STATUS_CROSS_DEVICE,
+  OFFS_STATUS_PERMISSION_DENIED,
+  OFFS_STATUS_NAME_TOO_LONG,
+  OFFS_STATUS_NOT_A_DIRECTORY,
+  OFFS_STATUS_IS_A_DIRECTORY,
+  OFFS_STATUS_STALE_HANDLE,
+  // The library has panicked; the handle may be in an inconsistent state
+  OFFS_STATUS_PANIC,
+} OffsStatus;
+
+// Opaque handle to a filesystem synchronized with the server.
+typedef struct OffsClient OffsClient;
+
+typedef struct OffsStat {
+  enum OffsFileType file_type;
+  // Permission bits, without the file type
+  uint32_t mode;
+  uint32_t uid;
+  uint32_t gid;
+  uint64_t size;
+  int64_t atime_sec;
+  uint32_t atime_nsec;
+  int64_t mtime_sec;
+  uint32_t mtime_nsec;
+  int64_t ctime_sec;
+  uint32_t ctime_nsec;
+  // Time of creation
+  int64_t crtime_sec;
+  uint32_t crtime_nsec;
+} OffsStat;
+
+typedef struct OffsDirEntry {
+  // Name of the file, as a NUL-terminated UTF-8 string
+  char *name;
+  struct OffsStat stat;
+} OffsDirEntry;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Opens the cache at `cache_path` (creating it if needed) and connects to the server at
+// `server` (`HOST:PORT`, optionally followed by `/SHARE`). `token` may be NULL if the server
+// does not require authentication.
+//
+// Returns NULL on failure. The handle has to be released with `offs_close`.
+//
+// # Safety
+//
+// The strings have to be valid NUL-terminated strings.
+struct OffsClient *offs_open(const char *server, const char *cache_path, const char *token);
+
+// Releases the handle. Passing NULL is a no-op.
+//
+// # Safety
+//
+// `client` has to be NULL or returned by `offs_open`, and not used afterwards.
+void offs_close(struct OffsClient *client);
+
+// Returns the message of the last error encountered on the calling thread, or NULL if there
+// was none. The string is valid until the next call failing on the same thread.
+const char *offs_last_error_message(void);
+
+// Switches the offline mode. While offline, only the files already in the cache are available
+// and the modifications are only written to the journal.
+//
+// # Safety
+//
+// `client` has to be a valid handle.
+void offs_set_offline(const struct OffsClient *client, bool offline);
+
+// Fills `stat` with the attributes of the file at `path`.
+//
+// # Safety
+//
+// `client` has to be a valid handle, `path` a valid string and `stat` a valid pointer.
+enum OffsStatus offs_stat(const struct OffsClient *client, const char *path, struct OffsStat *stat);
+
+// Lists the directory at `path`, storing an array of `count` entries in `entries`. The array
+// has to be released with `offs_free_entries`.
+//
+// # Safety
+//
+// `client` has to be a valid handle, `path` a valid string and `entries` and `count` valid
+// pointers.
+enum OffsStatus offs_list(const struct OffsClient *client,
+                          const char *path,
+                          struct OffsDirEntry **entries,
+                          size_t *count);
+
+// Releases the entries returned by `offs_list`.
+//
+// # Safety
+//
+// `entries` and `count` have to be the ones returned by `offs_list`.
+void offs_free_entries(struct OffsDirEntry *entries, size_t count);
+
+// Reads up to `size` bytes of the file at `path` into `buf`, starting at `offset`. The number
+// of bytes read, which is smaller than `size` at the end of the file, is stored in
+// `bytes_read`.
+//
+// # Safety
+//
+// `client` has to be a valid handle, `path` a valid string, `buf` has to point to at least
+// `size` bytes and `bytes_read` has to be a valid pointer.
+enum OffsStatus offs_read(const struct OffsClient *client,
+                          const char *path,
+                          uint64_t offset,
+                          uint8_t *buf,
+                          uint32_t size,
+                          uint32_t *bytes_read);
+
+// Writes `size` bytes of `data` to the file at `path`, starting at `offset`.
+//
+// # Safety
+//
+// `client` has to be a valid handle, `path` a valid string and `data` has to point to at
+// least `size` bytes.
+enum OffsStatus offs_write(const struct OffsClient *client,
+                           const char *path,
+                           uint64_t offset,
+                           const uint8_t *data,
+                           size_t size);
+
+// Creates an empty regular file at `path`.
+//
+// # Safety
+//
+// `client` has to be a valid handle and `path` a valid string.
+enum OffsStatus offs_create_file(const struct OffsClient *client, const char *path, uint16_t mode);
+
+// # Safety
+//
+// `client` has to be a valid handle and `path` a valid string.
+enum OffsStatus offs_create_directory(const struct OffsClient *client,
+                                      const char *path,
+                                      uint16_t mode);
+
+// Removes the file or the empty directory at `path`.
+//
+// # Safety
+//
+// `client` has to be a valid handle and `path` a valid string.
+enum OffsStatus offs_remove(const struct OffsClient *client, const char *path);
+
+// Sends the changes made while offline to the server.
+//
+// # Safety
+//
+// `client` has to be a valid handle.
+enum OffsStatus offs_sync(const struct OffsClient *client);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OFFS_H */
@@ -0,0 +1,514 @@
+//! C bindings of the offs client, letting the non-Rust applications use the synchronization
+//! engine without mounting the filesystem. The header is generated into `include/offs.h`.
+//!
+//! All the functions taking an `OffsClient` are blocking and can be called from multiple
+//! threads. The functions returning `OffsStatus` report the details of a failure through
+//! `offs_last_error_message`. A panic never unwinds into the caller: it is reported as
+//! `OFFS_STATUS_PANIC` (or NULL by `offs_open`), and the handle should not be used afterwards.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use tokio::runtime::Runtime;
+
+use offs::errors::{OperationError, OperationErrorType, OperationResult};
+use offs::store::{DirEntity, FileType};
+use offs_client::server_address::ServerAddress;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OffsStatus {
+    Ok = 0,
+    DatabaseError,
+    DirectoryNotEmpty,
+    ConflictedFile,
+    InvalidContentVersion,
+    BlobDoesNotExist,
+    Offline,
+    FileDoesNotExist,
+    InvalidUnicode,
+    ShareDoesNotExist,
+    Unauthenticated,
+    InvalidAcl,
+    NoSpace,
+    RenameIntoDescendant,
+    InvalidArgument,
+    UnsupportedProtocol,
+    RequestTooLarge,
+    RateLimited,
+    QuotaExceeded,
+    CrossDevice,
+    PermissionDenied,
+    NameTooLong,
+    NotADirectory,
+    IsADirectory,
+    StaleHandle,
+    /// The library has panicked; the handle may be in an inconsistent state
+    Panic,
+}
+
+impl From<OperationErrorType> for OffsStatus {
+    fn from(error_type: OperationErrorType) -> Self {
+        match error_type {
+            OperationErrorType::DatabaseError => OffsStatus::DatabaseError,
+            OperationErrorType::DirectoryNotEmpty => OffsStatus::DirectoryNotEmpty,
+            OperationErrorType::ConflictedFile => OffsStatus::ConflictedFile,
+            OperationErrorType::InvalidContentVersion => OffsStatus::InvalidContentVersion,
+            OperationErrorType::BlobDoesNotExist => OffsStatus::BlobDoesNotExist,
+            OperationErrorType::Offline => OffsStatus::Offline,
+            OperationErrorType::FileDoesNotExist => OffsStatus::FileDoesNotExist,
+            OperationErrorType::InvalidUnicode => OffsStatus::InvalidUnicode,
+            OperationErrorType::ShareDoesNotExist => OffsStatus::ShareDoesNotExist,
+            OperationErrorType::Unauthenticated => OffsStatus::Unauthenticated,
+            OperationErrorType::InvalidAcl => OffsStatus::InvalidAcl,
+            OperationErrorType::NoSpace => OffsStatus::NoSpace,
+            OperationErrorType::RenameIntoDescendant => OffsStatus::RenameIntoDescendant,
+            OperationErrorType::InvalidArgument => OffsStatus::InvalidArgument,
+            OperationErrorType::UnsupportedProtocol => OffsStatus::UnsupportedProtocol,
+            OperationErrorType::RequestTooLarge => OffsStatus::RequestTooLarge,
+            OperationErrorType::RateLimited => OffsStatus::RateLimited,
+            OperationErrorType::QuotaExceeded => OffsStatus::QuotaExceeded,
+            OperationErrorType::CrossDevice => OffsStatus::CrossDevice,
+            OperationErrorType::PermissionDenied => OffsStatus::PermissionDenied,
+            OperationErrorType::NameTooLong => OffsStatus::NameTooLong,
+            OperationErrorType::NotADirectory => OffsStatus::NotADirectory,
+            OperationErrorType::IsADirectory => OffsStatus::IsADirectory,
+            OperationErrorType::StaleHandle => OffsStatus::StaleHandle,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OffsFileType {
+    NamedPipe = 0,
+    CharDevice,
+    BlockDevice,
+    Directory,
+    RegularFile,
+    Symlink,
+    Socket,
+}
+
+impl From<FileType> for OffsFileType {
+    fn from(file_type: FileType) -> Self {
+        match file_type {
+            FileType::NamedPipe => OffsFileType::NamedPipe,
+            FileType::CharDevice => OffsFileType::CharDevice,
+            FileType::BlockDevice => OffsFileType::BlockDevice,
+            FileType::Directory => OffsFileType::Directory,
+            FileType::RegularFile => OffsFileType::RegularFile,
+            FileType::Symlink => OffsFileType::Symlink,
+            FileType::Socket => OffsFileType::Socket,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct OffsStat {
+    pub file_type: OffsFileType,
+    /// Permission bits, without the file type
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime_sec: i64,
+    pub atime_nsec: u32,
+    pub mtime_sec: i64,
+    pub mtime_nsec: u32,
+    pub ctime_sec: i64,
+    pub ctime_nsec: u32,
+    /// Time of creation
+    pub crtime_sec: i64,
+    pub crtime_nsec: u32,
+}
+
+impl From<&DirEntity> for OffsStat {
+    fn from(dirent: &DirEntity) -> Self {
+        let stat = &dirent.stat;
+
+        Self {
+            file_type: stat.file_type.into(),
+            mode: stat.mode as u32,
+            uid: stat.uid,
+            gid: stat.gid,
+            size: stat.size,
+            atime_sec: stat.atim.sec,
+            atime_nsec: stat.atim.nsec,
+            mtime_sec: stat.mtim.sec,
+            mtime_nsec: stat.mtim.nsec,
+            ctime_sec: stat.ctim.sec,
+            ctime_nsec: stat.ctim.nsec,
+            crtime_sec: stat.crtim.sec,
+            crtime_nsec: stat.crtim.nsec,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct OffsDirEntry {
+    /// Name of the file, as a NUL-terminated UTF-8 string
+    pub name: *mut c_char,
+    pub stat: OffsStat,
+}
+
+/// Opaque handle to a filesystem synchronized with the server.
+pub struct OffsClient {
+    runtime: Runtime,
+    client: offs_client::OffsClient,
+}
+
+fn set_last_error(message: &str) {
+    // The messages cannot contain NUL bytes, as they are passed as C strings
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(message));
+}
+
+/// Runs the body of an entry point, returning `on_panic` if it panics, as unwinding into the
+/// C code is undefined behavior.
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        set_last_error(&format!("panicked: {}", message));
+
+        on_panic
+    })
+}
+
+fn to_status<T>(result: OperationResult<T>) -> Result<T, OffsStatus> {
+    result.map_err(|e| {
+        set_last_error(&e.message);
+        e.error_type.into()
+    })
+}
+
+/// Converts the result of an operation without any value to return.
+fn into_status(result: OperationResult<()>) -> OffsStatus {
+    match to_status(result) {
+        Ok(()) => OffsStatus::Ok,
+        Err(status) => status,
+    }
+}
+
+unsafe fn to_str<'a>(string: *const c_char) -> OperationResult<&'a str> {
+    if string.is_null() {
+        return Err(OperationError::invalid_argument("null string"));
+    }
+
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| OperationError::invalid_unicode())
+}
+
+macro_rules! try_ffi {
+    ($e:expr) => {
+        match to_status($e) {
+            Ok(val) => val,
+            Err(status) => return status,
+        }
+    };
+}
+
+/// Opens the cache at `cache_path` (creating it if needed) and connects to the server at
+/// `server` (`HOST:PORT`, optionally followed by `/SHARE`). `token` may be NULL if the server
+/// does not require authentication.
+///
+/// Returns NULL on failure. The handle has to be released with `offs_close`.
+///
+/// # Safety
+///
+/// The strings have to be valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn offs_open(
+    server: *const c_char,
+    cache_path: *const c_char,
+    token: *const c_char,
+) -> *mut OffsClient {
+    catch_panic(ptr::null_mut(), || {
+        let runtime = match Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                set_last_error(&format!("Could not create the runtime: {}", e));
+                return ptr::null_mut();
+            }
+        };
+        let result = (|| {
+            let server: ServerAddress = to_str(server)?
+                .parse()
+                .map_err(|e: String| OperationError::invalid_argument(&e))?;
+            let cache_path = to_str(cache_path)?;
+            let token = if token.is_null() {
+                None
+            } else {
+                Some(to_str(token)?)
+            };
+
+            runtime.block_on(offs_client::OffsClient::open(
+                &server,
+                cache_path.as_ref(),
+                token,
+            ))
+        })();
+
+        match to_status(result) {
+            Ok(client) => Box::into_raw(Box::new(OffsClient { runtime, client })),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Releases the handle. Passing NULL is a no-op.
+///
+/// # Safety
+///
+/// `client` has to be NULL or returned by `offs_open`, and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn offs_close(client: *mut OffsClient) {
+    if !client.is_null() {
+        catch_panic((), || drop(Box::from_raw(client)));
+    }
+}
+
+/// Returns the message of the last error encountered on the calling thread, or NULL if there
+/// was none. The string is valid until the next call failing on the same thread.
+#[no_mangle]
+pub extern "C" fn offs_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|x| x.borrow().as_ref().map_or(ptr::null(), |x| x.as_ptr()))
+}
+
+/// Switches the offline mode. While offline, only the files already in the cache are available
+/// and the modifications are only written to the journal.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn offs_set_offline(client: *const OffsClient, offline: bool) {
+    catch_panic((), || (*client).client.set_offline(offline));
+}
+
+/// Fills `stat` with the attributes of the file at `path`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle, `path` a valid string and `stat` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn offs_stat(
+    client: *const OffsClient,
+    path: *const c_char,
+    stat: *mut OffsStat,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+        let dirent = try_ffi!(client.runtime.block_on(client.client.stat(path)));
+
+        *stat = (&dirent).into();
+        OffsStatus::Ok
+    })
+}
+
+/// Lists the directory at `path`, storing an array of `count` entries in `entries`. The array
+/// has to be released with `offs_free_entries`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle, `path` a valid string and `entries` and `count` valid
+/// pointers.
+#[no_mangle]
+pub unsafe extern "C" fn offs_list(
+    client: *const OffsClient,
+    path: *const c_char,
+    entries: *mut *mut OffsDirEntry,
+    count: *mut usize,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+        let dirents = try_ffi!(client.runtime.block_on(client.client.list(path)));
+
+        let names: Vec<CString> = try_ffi!(dirents
+            .iter()
+            .map(|x| {
+                CString::new(x.name.as_str())
+                    .map_err(|_| OperationError::invalid_argument("file name containing NUL"))
+            })
+            .collect());
+        let list: Box<[OffsDirEntry]> = names
+            .into_iter()
+            .zip(&dirents)
+            .map(|(name, x)| OffsDirEntry {
+                name: name.into_raw(),
+                stat: x.into(),
+            })
+            .collect();
+        *count = list.len();
+        *entries = Box::into_raw(list) as *mut OffsDirEntry;
+
+        OffsStatus::Ok
+    })
+}
+
+/// Releases the entries returned by `offs_list`.
+///
+/// # Safety
+///
+/// `entries` and `count` have to be the ones returned by `offs_list`.
+#[no_mangle]
+pub unsafe extern "C" fn offs_free_entries(entries: *mut OffsDirEntry, count: usize) {
+    if entries.is_null() {
+        return;
+    }
+
+    catch_panic((), || {
+        let list = Box::from_raw(ptr::slice_from_raw_parts_mut(entries, count));
+        for entry in list.iter() {
+            drop(CString::from_raw(entry.name));
+        }
+    })
+}
+
+/// Reads up to `size` bytes of the file at `path` into `buf`, starting at `offset`. The number
+/// of bytes read, which is smaller than `size` at the end of the file, is stored in
+/// `bytes_read`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle, `path` a valid string, `buf` has to point to at least
+/// `size` bytes and `bytes_read` has to be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn offs_read(
+    client: *const OffsClient,
+    path: *const c_char,
+    offset: u64,
+    buf: *mut u8,
+    size: u32,
+    bytes_read: *mut u32,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+        let data = try_ffi!(client
+            .runtime
+            .block_on(client.client.read(path, offset, size)));
+
+        ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+        *bytes_read = data.len() as u32;
+
+        OffsStatus::Ok
+    })
+}
+
+/// Writes `size` bytes of `data` to the file at `path`, starting at `offset`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle, `path` a valid string and `data` has to point to at
+/// least `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn offs_write(
+    client: *const OffsClient,
+    path: *const c_char,
+    offset: u64,
+    data: *const u8,
+    size: usize,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+        let data = slice::from_raw_parts(data, size);
+
+        into_status(
+            client
+                .runtime
+                .block_on(client.client.write(path, offset, data)),
+        )
+    })
+}
+
+/// Creates an empty regular file at `path`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle and `path` a valid string.
+#[no_mangle]
+pub unsafe extern "C" fn offs_create_file(
+    client: *const OffsClient,
+    path: *const c_char,
+    mode: u16,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+
+        into_status(
+            client
+                .runtime
+                .block_on(client.client.create_file(path, mode))
+                .map(|_| ()),
+        )
+    })
+}
+
+/// # Safety
+///
+/// `client` has to be a valid handle and `path` a valid string.
+#[no_mangle]
+pub unsafe extern "C" fn offs_create_directory(
+    client: *const OffsClient,
+    path: *const c_char,
+    mode: u16,
+) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+
+        into_status(
+            client
+                .runtime
+                .block_on(client.client.create_directory(path, mode))
+                .map(|_| ()),
+        )
+    })
+}
+
+/// Removes the file or the empty directory at `path`.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle and `path` a valid string.
+#[no_mangle]
+pub unsafe extern "C" fn offs_remove(client: *const OffsClient, path: *const c_char) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+        let path = try_ffi!(to_str(path));
+
+        into_status(client.runtime.block_on(client.client.remove(path)))
+    })
+}
+
+/// Sends the changes made while offline to the server.
+///
+/// # Safety
+///
+/// `client` has to be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn offs_sync(client: *const OffsClient) -> OffsStatus {
+    catch_panic(OffsStatus::Panic, || {
+        let client = &*client;
+
+        into_status(client.runtime.block_on(client.client.sync()))
+    })
+}
@@ -0,0 +1,364 @@
+//! Applies arbitrary journals, as sent by a client of one tenant, to a server holding the
+//! files of two tenants. Besides not panicking, the server has to leave the files of the other
+//! tenant alone and keep the tree of the client's share a tree.
+
+#![no_main]
+
+use std::collections::{BTreeMap, HashSet};
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use num_traits::FromPrimitive;
+
+use offs::acl::AclType;
+use offs::modify_op::{
+    CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
+    FallocateOperation, ModifyOperation, ModifyOperationContent, RemoveDirectoryOperation,
+    RemoveFileOperation, RenameOperation, SetAclOperation, SetAttributesOperation, WriteOperation,
+};
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::{DirEntity, FileType, Store};
+use offs::timespec::Timespec;
+use offs::version_vector::VersionVector;
+use offs::ROOT_ID;
+use offs_server::remote_fs::{RemoteFs, Share};
+
+const REPLICAS: [&str; 2] = ["replica-a", "replica-b"];
+
+/// The file an operation refers to. Picking the existing files by their index gives the
+/// fuzzer a chance to hit them, as their IDs are random.
+#[derive(Arbitrary, Debug)]
+enum Target {
+    Root,
+    Existing(u8),
+    Created(u8),
+    Raw(String),
+}
+
+/// The offsets and sizes are limited to 32 bits: every chunk of a file is a row of the store,
+/// holes included, so the larger ones make the operations too slow to fuzz.
+#[derive(Arbitrary, Debug)]
+enum Operation {
+    CreateFile {
+        name: String,
+        file_type: u8,
+        perm: u16,
+        dev: u32,
+    },
+    CreateSymlink {
+        name: String,
+        link: String,
+    },
+    CreateDirectory {
+        name: String,
+        perm: u16,
+    },
+    RemoveFile,
+    RemoveDirectory,
+    Rename {
+        new_parent: Target,
+        new_name: String,
+    },
+    SetAttributes {
+        perm: Option<u16>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u32>,
+        atim: Option<(i64, u32)>,
+        mtim: Option<(i64, u32)>,
+    },
+    Write {
+        offset: i32,
+        data: Vec<u8>,
+    },
+    SetAcl {
+        default: bool,
+        acl: Vec<u8>,
+    },
+    Fallocate {
+        offset: i32,
+        length: i32,
+        zero: bool,
+        keep_size: bool,
+    },
+    CopyRange {
+        source: Target,
+        source_offset: i32,
+        offset: i32,
+        length: i32,
+    },
+}
+
+#[derive(Arbitrary, Debug)]
+struct JournalEntry {
+    target: Target,
+    operation: Operation,
+    op_id: Option<u8>,
+    timestamp: (i64, u32),
+    dirent_version: i64,
+    content_version: u8,
+    replica: Option<bool>,
+    content_vector: Vec<(bool, u8)>,
+    dirent_vector: Vec<(bool, u8)>,
+}
+
+struct Setup {
+    fs: RemoteFs,
+    share: Share,
+    /// IDs of the files existing before the journal, including the ones of the other tenant
+    existing: Vec<String>,
+    other_root: String,
+}
+
+fuzz_target!(|journal: Vec<JournalEntry>| {
+    let Setup {
+        mut fs,
+        share,
+        existing,
+        other_root,
+    } = setup();
+    let other_files = snapshot(&fs.list_tree(&other_root).unwrap());
+
+    let operations = journal
+        .into_iter()
+        .map(|entry| to_operation(entry, &existing))
+        .collect::<Vec<_>>();
+    let count = operations.len();
+    let (assigned_ids, _, statuses) = fs.apply_journal(&share, operations);
+
+    assert_eq!(statuses.len(), count);
+    assert!(assigned_ids.len() <= count);
+    assert_eq!(
+        snapshot(&fs.list_tree(&other_root).unwrap()),
+        other_files,
+        "the journal has modified the files of another tenant"
+    );
+
+    // A directory moved into its own subtree would make this loop forever
+    let files = fs.list_tree(share.root_id()).unwrap();
+    let mut ids = HashSet::new();
+    ids.insert(share.root_id().to_owned());
+    for file in &files {
+        assert!(ids.contains(&file.parent), "{} is detached", file.id);
+        assert!(ids.insert(file.id.clone()), "{} is listed twice", file.id);
+    }
+});
+
+fn setup() -> Setup {
+    let mut fs = RemoteFs::new(Store::new_server_in_memory().unwrap(), true).unwrap();
+
+    let other = fs.create_share("other", "").unwrap();
+    let secret = create(&mut fs, &other, ROOT_ID, "secret", FileType::RegularFile);
+
+    let share = fs.create_share("tenant", "share").unwrap();
+    let dir = create(&mut fs, &share, ROOT_ID, "dir", FileType::Directory);
+    let file = create(&mut fs, &share, ROOT_ID, "file", FileType::RegularFile);
+    let nested = create(&mut fs, &share, &dir, "nested", FileType::RegularFile);
+
+    Setup {
+        existing: vec![
+            dir,
+            file,
+            nested,
+            secret,
+            other.root_id().to_owned(),
+            share.root_id().to_owned(),
+        ],
+        other_root: other.root_id().to_owned(),
+        fs,
+        share,
+    }
+}
+
+fn create(
+    fs: &mut RemoteFs,
+    share: &Share,
+    parent: &str,
+    name: &str,
+    file_type: FileType,
+) -> String {
+    let name = name.to_owned();
+    let content = match file_type {
+        FileType::Directory => {
+            ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                name,
+                perm: 0o755,
+                owner: None,
+            })
+        }
+        _ => ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+            name,
+            file_type,
+            perm: 0o644,
+            dev: 0,
+            owner: None,
+        }),
+    };
+    let operation = ModifyOperation {
+        id: parent.to_owned(),
+        op_id: String::new(),
+        timestamp: Timespec::new(0, 0),
+        dirent_version: 0,
+        content_version: 0,
+        content_vector: VersionVector::default(),
+        dirent_vector: VersionVector::default(),
+        replica_id: String::new(),
+        operation: content,
+    };
+
+    fs.apply_operation(share, operation).unwrap().id
+}
+
+fn resolve(target: Target, existing: &[String]) -> String {
+    match target {
+        Target::Root => ROOT_ID.to_owned(),
+        Target::Existing(n) => existing[n as usize % existing.len()].clone(),
+        Target::Created(n) => LocalTempIdGenerator::get_nth_id(n as usize),
+        Target::Raw(id) => id,
+    }
+}
+
+fn to_timespec((sec, nsec): (i64, u32)) -> Timespec {
+    // The conversion from the proto message rejects the larger ones
+    Timespec::new(sec, nsec % 1_000_000_000)
+}
+
+fn to_operation(entry: JournalEntry, existing: &[String]) -> ModifyOperation {
+    let operation = match entry.operation {
+        Operation::CreateFile {
+            name,
+            file_type,
+            perm,
+            dev,
+        } => ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+            name,
+            file_type: FileType::from_u8(file_type % 7).unwrap(),
+            perm,
+            dev,
+            owner: None,
+        }),
+        Operation::CreateSymlink { name, link } => {
+            ModifyOperationContent::CreateSymlinkOperation(CreateSymlinkOperation {
+                name,
+                link,
+                owner: None,
+            })
+        }
+        Operation::CreateDirectory { name, perm } => {
+            ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                name,
+                perm,
+                owner: None,
+            })
+        }
+        Operation::RemoveFile => {
+            ModifyOperationContent::RemoveFileOperation(RemoveFileOperation {})
+        }
+        Operation::RemoveDirectory => {
+            ModifyOperationContent::RemoveDirectoryOperation(RemoveDirectoryOperation {})
+        }
+        Operation::Rename {
+            new_parent,
+            new_name,
+        } => ModifyOperationContent::RenameOperation(RenameOperation {
+            new_parent: resolve(new_parent, existing),
+            new_name,
+        }),
+        Operation::SetAttributes {
+            perm,
+            uid,
+            gid,
+            size,
+            atim,
+            mtim,
+        } => ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+            perm,
+            uid,
+            gid,
+            size: size.map(Into::into),
+            atim: atim.map(to_timespec),
+            mtim: mtim.map(to_timespec),
+        }),
+        Operation::Write { offset, data } => {
+            ModifyOperationContent::WriteOperation(WriteOperation {
+                offset: offset.into(),
+                data,
+            })
+        }
+        Operation::SetAcl { default, acl } => {
+            ModifyOperationContent::SetAclOperation(SetAclOperation {
+                acl_type: if default {
+                    AclType::Default
+                } else {
+                    AclType::Access
+                },
+                acl,
+            })
+        }
+        Operation::Fallocate {
+            offset,
+            length,
+            zero,
+            keep_size,
+        } => ModifyOperationContent::FallocateOperation(FallocateOperation {
+            offset: offset.into(),
+            length: length.into(),
+            zero,
+            keep_size,
+        }),
+        Operation::CopyRange {
+            source,
+            source_offset,
+            offset,
+            length,
+        } => ModifyOperationContent::CopyRangeOperation(CopyRangeOperation {
+            source_id: resolve(source, existing),
+            source_offset: source_offset.into(),
+            offset: offset.into(),
+            length: length.into(),
+        }),
+    };
+
+    let to_vector = |entries: Vec<(bool, u8)>| {
+        let mut versions = BTreeMap::new();
+        for (replica, version) in entries {
+            versions.insert(REPLICAS[replica as usize].to_owned(), version as i64);
+        }
+        VersionVector::new(versions)
+    };
+
+    ModifyOperation {
+        id: resolve(entry.target, existing),
+        op_id: entry.op_id.map(|x| x.to_string()).unwrap_or_default(),
+        timestamp: to_timespec(entry.timestamp),
+        dirent_version: entry.dirent_version,
+        content_version: entry.content_version as i64,
+        content_vector: to_vector(entry.content_vector),
+        dirent_vector: to_vector(entry.dirent_vector),
+        replica_id: entry
+            .replica
+            .map(|x| REPLICAS[x as usize].to_owned())
+            .unwrap_or_default(),
+        operation,
+    }
+}
+
+/// The state of the files that any modification would change.
+fn snapshot(files: &[DirEntity]) -> Vec<(String, String, String, i64, i64, u64)> {
+    let mut state = files
+        .iter()
+        .map(|x| {
+            (
+                x.id.clone(),
+                x.parent.clone(),
+                x.name.clone(),
+                x.dirent_version,
+                x.content_version,
+                x.stat.size,
+            )
+        })
+        .collect::<Vec<_>>();
+    state.sort();
+
+    state
+}
@@ -0,0 +1,50 @@
+//! Feeds arbitrary bytes to the decoding of the operations the clients send, the way the
+//! server decodes them, and checks that the ones it accepts survive a round trip.
+
+#![no_main]
+
+use std::convert::{TryFrom, TryInto};
+
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+
+use offs::modify_op::ModifyOperation;
+use offs::proto::filesystem as proto_types;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = proto_types::ModifyOperation::decode(data) {
+        check_round_trip(message);
+    }
+
+    if let Ok(request) = proto_types::ApplyJournalRequest::decode(data) {
+        let operations: Result<Vec<ModifyOperation>, _> = request
+            .operations
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect();
+        let _chunks: Vec<Vec<String>> = request.chunks.into_iter().map(Into::into).collect();
+
+        if let Ok(operations) = operations {
+            for operation in operations {
+                check_round_trip(operation.into());
+            }
+        }
+    }
+});
+
+/// The operations are compared as proto messages, as neither the encoding of the version
+/// vectors (which are maps) nor the converted operations can be compared directly.
+fn check_round_trip(message: proto_types::ModifyOperation) {
+    let operation = match ModifyOperation::try_from(message) {
+        Ok(operation) => operation,
+        Err(_) => return,
+    };
+    let converted = proto_types::ModifyOperation::from(operation);
+
+    let decoded = proto_types::ModifyOperation::decode(&converted.encode_to_vec()[..])
+        .expect("could not decode an encoded operation");
+    let reconverted = ModifyOperation::try_from(decoded)
+        .expect("could not convert an operation converted before");
+
+    assert_eq!(proto_types::ModifyOperation::from(reconverted), converted);
+}
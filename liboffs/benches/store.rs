@@ -0,0 +1,139 @@
+//! Benchmarks of the store operations on the hot paths of the filesystem, run against a cache
+//! on disk so that they include the cost of SQLite itself.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tempfile::TempDir;
+
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{FileType, Store};
+use offs::timespec::Timespec;
+use offs::{BLOB_SIZE, ROOT_ID};
+
+const FILE_SIZE: usize = 16 * 1024 * 1024;
+const DIRECTORY_SIZE: usize = 10_000;
+
+fn open_store(dir: &TempDir) -> StoreWrapper<LocalTempIdGenerator> {
+    let mut store =
+        StoreWrapper::new(Store::new_client(dir.path().join("cache.db"), None).unwrap());
+    store.create_default_root_directory().unwrap();
+
+    store
+}
+
+fn create_file(store: &mut StoreWrapper<LocalTempIdGenerator>, parent: &str, name: &str) -> String {
+    store
+        .create_file(
+            parent,
+            Timespec::now(),
+            name,
+            FileType::RegularFile,
+            0o644,
+            0,
+            None,
+        )
+        .unwrap()
+}
+
+/// Writes whole chunks and parts of them, both rewriting the existing content and appending.
+fn bench_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+
+    for &size in &[4096, BLOB_SIZE, 1024 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_function(format!("overwrite {} B", size), |b| {
+            let dir = TempDir::new().unwrap();
+            let mut store = open_store(&dir);
+            let id = create_file(&mut store, ROOT_ID, "file");
+            store
+                .write(&id, Timespec::now(), 0, &vec![1; FILE_SIZE])
+                .unwrap();
+            let data = vec![2; size];
+            let mut offset = 0;
+
+            b.iter(|| {
+                store.write(&id, Timespec::now(), offset, &data).unwrap();
+                offset = (offset + size + 4096) % (FILE_SIZE - size);
+            });
+        });
+
+        group.bench_function(format!("append {} B", size), |b| {
+            let dir = TempDir::new().unwrap();
+            let mut store = open_store(&dir);
+            let data = vec![2; size];
+            let mut file = None;
+            let mut file_count = 0;
+            let mut offset = 0;
+
+            b.iter(|| {
+                // Start over before the file gets large enough to skew the results
+                if file.is_none() || offset + size > FILE_SIZE {
+                    file_count += 1;
+                    file = Some(create_file(
+                        &mut store,
+                        ROOT_ID,
+                        &format!("file{}", file_count),
+                    ));
+                    offset = 0;
+                }
+                let id = file.as_ref().unwrap();
+                store.write(id, Timespec::now(), offset, &data).unwrap();
+                offset += size;
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    let dir = TempDir::new().unwrap();
+    let mut store = open_store(&dir);
+    let id = create_file(&mut store, ROOT_ID, "file");
+    let content = (0..FILE_SIZE).map(|x| x as u8).collect::<Vec<_>>();
+    store.write(&id, Timespec::now(), 0, &content).unwrap();
+
+    for &size in &[4096, BLOB_SIZE, 1024 * 1024] {
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_function(format!("{} B", size), |b| {
+            let mut offset = 0;
+
+            b.iter(|| {
+                let data = store.read(&id, offset as i64, size as u32).unwrap();
+                assert_eq!(data.len(), size);
+                offset = (offset + size + 4096) % (FILE_SIZE - size);
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_list_files(c: &mut Criterion) {
+    let mut group = c.benchmark_group("list_files");
+    let dir = TempDir::new().unwrap();
+    let mut store = open_store(&dir);
+    let parent = store
+        .create_directory(ROOT_ID, Timespec::now(), "dir", 0o755, None)
+        .unwrap();
+    {
+        let transaction = store.transaction();
+        for i in 0..DIRECTORY_SIZE {
+            create_file(&mut store, &parent, &format!("file{}", i));
+        }
+        transaction.commit().unwrap();
+    }
+
+    group.throughput(Throughput::Elements(DIRECTORY_SIZE as u64));
+    group.bench_function(format!("{} entries", DIRECTORY_SIZE), |b| {
+        b.iter(|| assert_eq!(store.list_files(&parent).unwrap().len(), DIRECTORY_SIZE));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_write, bench_read, bench_list_files);
+criterion_main!(benches);
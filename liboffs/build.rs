@@ -1,4 +1,15 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("src/proto/filesystem.proto")?;
+    let mut config = prost_build::Config::new();
+    // Blob contents are passed around without copying
+    config.bytes([
+        ".filesystem.Blob.content",
+        ".filesystem.ApplyJournalRequest.blobs",
+    ]);
+
+    tonic_build::configure().compile_with_config(
+        config,
+        &["src/proto/filesystem.proto"],
+        &["src/proto"],
+    )?;
     Ok(())
 }
@@ -0,0 +1,258 @@
+use std::convert::TryInto;
+
+use crate::errors::{OperationError, OperationResult};
+use crate::store::FileMode;
+
+pub const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+pub const ACL_DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const ACL_VERSION: u32 = 2;
+const ACL_HEADER_SIZE: usize = 4;
+const ACL_ENTRY_SIZE: usize = 8;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+pub const ACL_USER_OBJ: u16 = 0x01;
+pub const ACL_USER: u16 = 0x02;
+pub const ACL_GROUP_OBJ: u16 = 0x04;
+pub const ACL_GROUP: u16 = 0x08;
+pub const ACL_MASK: u16 = 0x10;
+pub const ACL_OTHER: u16 = 0x20;
+
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AclType {
+    Access = 0,
+    Default,
+}
+
+impl AclType {
+    pub fn from_xattr_name(name: &str) -> Option<Self> {
+        match name {
+            ACL_ACCESS_XATTR => Some(AclType::Access),
+            ACL_DEFAULT_XATTR => Some(AclType::Default),
+            _ => None,
+        }
+    }
+
+    pub fn xattr_name(&self) -> &'static str {
+        match self {
+            AclType::Access => ACL_ACCESS_XATTR,
+            AclType::Default => ACL_DEFAULT_XATTR,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AclEntry {
+    pub tag: u16,
+    pub perm: u16,
+    pub id: u32,
+}
+
+/// POSIX ACL, stored in the same binary format as the `system.posix_acl_*` extended attributes
+/// on Linux.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Creates the minimal ACL equivalent to the permission bits of the file mode.
+    pub fn from_mode(mode: FileMode) -> Self {
+        let entry = |tag, perm| AclEntry {
+            tag,
+            perm,
+            id: ACL_UNDEFINED_ID,
+        };
+
+        Self {
+            entries: vec![
+                entry(ACL_USER_OBJ, (mode >> 6) & 0o7),
+                entry(ACL_GROUP_OBJ, (mode >> 3) & 0o7),
+                entry(ACL_OTHER, mode & 0o7),
+            ],
+        }
+    }
+
+    /// Parses and validates the extended attribute value.
+    pub fn parse(data: &[u8]) -> OperationResult<Self> {
+        if data.len() < ACL_HEADER_SIZE {
+            return Err(OperationError::invalid_acl("invalid size"));
+        }
+        if u32::from_le_bytes(data[..ACL_HEADER_SIZE].try_into().unwrap()) != ACL_VERSION {
+            return Err(OperationError::invalid_acl("unsupported version"));
+        }
+
+        let chunks = data[ACL_HEADER_SIZE..].chunks_exact(ACL_ENTRY_SIZE);
+        if !chunks.remainder().is_empty() {
+            return Err(OperationError::invalid_acl("invalid size"));
+        }
+        let entries = chunks
+            .map(|x| AclEntry {
+                tag: u16::from_le_bytes([x[0], x[1]]),
+                perm: u16::from_le_bytes([x[2], x[3]]),
+                id: u32::from_le_bytes([x[4], x[5], x[6], x[7]]),
+            })
+            .collect();
+        let acl = Self { entries };
+        acl.validate()?;
+
+        Ok(acl)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(ACL_HEADER_SIZE + self.entries.len() * ACL_ENTRY_SIZE);
+        data.extend_from_slice(&ACL_VERSION.to_le_bytes());
+
+        for entry in &self.entries {
+            data.extend_from_slice(&entry.tag.to_le_bytes());
+            data.extend_from_slice(&entry.perm.to_le_bytes());
+            data.extend_from_slice(&entry.id.to_le_bytes());
+        }
+
+        data
+    }
+
+    /// Checks the rules `setfacl` follows: the entries are sorted by tag and qualifier, the
+    /// owner, owning group and other entries are present exactly once, and there is a mask
+    /// entry whenever named user or group entries exist.
+    fn validate(&self) -> OperationResult<()> {
+        let mut has_named = false;
+        let mut mask_count = 0;
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.perm & !(ACL_READ | ACL_WRITE | ACL_EXECUTE) != 0 {
+                return Err(OperationError::invalid_acl("invalid permissions"));
+            }
+
+            match entry.tag {
+                ACL_USER | ACL_GROUP => has_named = true,
+                ACL_MASK => mask_count += 1,
+                ACL_USER_OBJ | ACL_GROUP_OBJ | ACL_OTHER => {}
+                _ => return Err(OperationError::invalid_acl("invalid tag")),
+            }
+            if (entry.tag == ACL_USER || entry.tag == ACL_GROUP) == (entry.id == ACL_UNDEFINED_ID) {
+                return Err(OperationError::invalid_acl("invalid qualifier"));
+            }
+
+            if i > 0 {
+                let prev = &self.entries[i - 1];
+                if (prev.tag, prev.id) >= (entry.tag, entry.id) {
+                    return Err(OperationError::invalid_acl("unsorted or duplicate entries"));
+                }
+            }
+        }
+
+        for tag in [ACL_USER_OBJ, ACL_GROUP_OBJ, ACL_OTHER] {
+            if !self.entries.iter().any(|x| x.tag == tag) {
+                return Err(OperationError::invalid_acl("missing required entry"));
+            }
+        }
+        if has_named && mask_count == 0 {
+            return Err(OperationError::invalid_acl("missing mask entry"));
+        }
+
+        Ok(())
+    }
+
+    fn get_perm(&self, tag: u16) -> Option<u16> {
+        self.entries.iter().find(|x| x.tag == tag).map(|x| x.perm)
+    }
+
+    fn set_perm(&mut self, tag: u16, perm: u16) {
+        if let Some(entry) = self.entries.iter_mut().find(|x| x.tag == tag) {
+            entry.perm = perm;
+        }
+    }
+
+    /// The entry the group permission bits of the file mode correspond to.
+    fn group_class_tag(&self) -> u16 {
+        if self.get_perm(ACL_MASK).is_some() {
+            ACL_MASK
+        } else {
+            ACL_GROUP_OBJ
+        }
+    }
+
+    /// Whether the ACL can be fully represented by the permission bits of the file mode.
+    pub fn is_minimal(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    /// Returns the permission bits of the file mode reflecting the ACL.
+    pub fn to_mode(&self) -> FileMode {
+        let perm = |tag| self.get_perm(tag).unwrap_or(0);
+
+        (perm(ACL_USER_OBJ) << 6) | (perm(self.group_class_tag()) << 3) | perm(ACL_OTHER)
+    }
+
+    /// Updates the ACL after the permission bits of the file mode have been changed.
+    pub fn set_mode(&mut self, mode: FileMode) {
+        self.set_perm(ACL_USER_OBJ, (mode >> 6) & 0o7);
+        self.set_perm(self.group_class_tag(), (mode >> 3) & 0o7);
+        self.set_perm(ACL_OTHER, mode & 0o7);
+    }
+
+    /// Restricts an ACL inherited from the default ACL of the parent directory to the mode the
+    /// file is created with.
+    pub fn restrict_to_mode(&mut self, mode: FileMode) {
+        let group_class_tag = self.group_class_tag();
+
+        for entry in &mut self.entries {
+            if entry.tag == ACL_USER_OBJ {
+                entry.perm &= (mode >> 6) & 0o7;
+            } else if entry.tag == group_class_tag {
+                entry.perm &= (mode >> 3) & 0o7;
+            } else if entry.tag == ACL_OTHER {
+                entry.perm &= mode & 0o7;
+            }
+        }
+    }
+
+    /// Checks whether the user may access the file with all the requested `ACL_*` permissions,
+    /// following the POSIX.1e access check algorithm.
+    pub fn check_access(
+        &self,
+        owner_uid: u32,
+        owner_gid: u32,
+        uid: u32,
+        gids: &[u32],
+        requested: u16,
+    ) -> bool {
+        let mask = self
+            .get_perm(ACL_MASK)
+            .unwrap_or(ACL_READ | ACL_WRITE | ACL_EXECUTE);
+
+        if uid == owner_uid {
+            return self.get_perm(ACL_USER_OBJ).unwrap_or(0) & requested == requested;
+        }
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .find(|x| x.tag == ACL_USER && x.id == uid)
+        {
+            return entry.perm & mask & requested == requested;
+        }
+
+        let group_entries = self.entries.iter().filter(|x| match x.tag {
+            ACL_GROUP_OBJ => gids.contains(&owner_gid),
+            ACL_GROUP => gids.contains(&x.id),
+            _ => false,
+        });
+        let mut group_matched = false;
+        for entry in group_entries {
+            group_matched = true;
+            if entry.perm & mask & requested == requested {
+                return true;
+            }
+        }
+        if group_matched {
+            return false;
+        }
+
+        self.get_perm(ACL_OTHER).unwrap_or(0) & requested == requested
+    }
+}
@@ -0,0 +1,35 @@
+//! The control socket, offering the properties of the D-Bus interface (see [`crate::dbus`]) on
+//! the platforms without D-Bus. Each request is a single line, either `get PROPERTY`,
+//! `set PROPERTY VALUE` or `call METHOD ARGUMENTS`, with the value and the array of arguments
+//! encoded as JSON; the response is a single JSON object line with either a `value` or an
+//! `error` field.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use digest::Digest;
+use sha2::Sha256;
+
+pub const GET: &str = "get";
+pub const SET: &str = "set";
+pub const CALL: &str = "call";
+
+pub const VALUE: &str = "value";
+pub const ERROR: &str = "error";
+
+/// Returns the directory the control sockets are created in: `$XDG_RUNTIME_DIR/offs`, or a
+/// per-user directory in the temporary directory if it is not set.
+pub fn socket_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("offs"),
+        None => env::temp_dir().join(format!("offs-{}", env::var("USER").unwrap_or_default())),
+    }
+}
+
+/// Returns the path of the control socket of the given (absolute) mount point. The mount point
+/// is hashed, as the socket paths are limited to about a hundred bytes.
+pub fn socket_path(mount_point: &Path) -> PathBuf {
+    let hash = Sha256::digest(mount_point.to_string_lossy().as_bytes());
+
+    socket_dir().join(format!("{}.sock", &hex::encode(hash)[..16]))
+}
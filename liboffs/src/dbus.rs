@@ -4,3 +4,34 @@ pub const IFACE: &str = "pl.m4tx.offs.clientctl";
 
 pub const MOUNT_POINT: &str = "MountPoint";
 pub const OFFLINE_MODE: &str = "OfflineMode";
+pub const SERVER_ADDRESS: &str = "ServerAddress";
+pub const CACHE_SIZE: &str = "CacheSize";
+pub const PENDING_JOURNAL_OPS: &str = "PendingJournalOps";
+pub const LAST_ERROR: &str = "LastError";
+pub const DEGRADED: &str = "Degraded";
+pub const CACHE_HITS: &str = "CacheHits";
+pub const CACHE_MISSES: &str = "CacheMisses";
+pub const BLOBS_FETCHED: &str = "BlobsFetched";
+pub const BLOB_BYTES_FETCHED: &str = "BlobBytesFetched";
+pub const TRANSFERS: &str = "Transfers";
+pub const QUARANTINED_JOURNAL_OPS: &str = "QuarantinedJournalOps";
+pub const EXCLUDED_FROM_SYNC: &str = "ExcludedFromSync";
+
+pub const PREFETCH: &str = "Prefetch";
+pub const LOCATE: &str = "Locate";
+pub const DIR_USAGE: &str = "DirUsage";
+pub const DEDUP_STATS: &str = "DedupStats";
+pub const CREATE_SHARE_LINK: &str = "CreateShareLink";
+pub const RELOAD: &str = "Reload";
+pub const EXCLUDE_FROM_SYNC: &str = "ExcludeFromSync";
+pub const INCLUDE_IN_SYNC: &str = "IncludeInSync";
+pub const DEHYDRATE: &str = "Dehydrate";
+pub const EXPORT: &str = "Export";
+pub const LIST_SIDECARS: &str = "ListSidecars";
+pub const GET_SIDECAR: &str = "GetSidecar";
+pub const SET_SIDECAR: &str = "SetSidecar";
+pub const SYNC_STATE: &str = "SyncState";
+pub const REVIEW_JOURNAL: &str = "ReviewJournal";
+
+pub const JOURNAL_QUARANTINED: &str = "JournalQuarantined";
+pub const SYNC_STATE_CHANGED: &str = "SyncStateChanged";
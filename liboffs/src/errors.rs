@@ -5,7 +5,7 @@ use std::str::FromStr;
 use bytes::Bytes;
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
-use rusqlite::Error;
+use rusqlite::{Error, ErrorCode};
 use tonic::{Code, Status};
 
 use crate::store::DirEntity;
@@ -21,6 +21,22 @@ pub enum OperationErrorType {
     Offline,
     FileDoesNotExist,
     InvalidUnicode,
+    ShareDoesNotExist,
+    Unauthenticated,
+    InvalidAcl,
+    NoSpace,
+    RenameIntoDescendant,
+    InvalidArgument,
+    UnsupportedProtocol,
+    RequestTooLarge,
+    RateLimited,
+    QuotaExceeded,
+    CrossDevice,
+    PermissionDenied,
+    NameTooLong,
+    NotADirectory,
+    IsADirectory,
+    StaleHandle,
 }
 
 impl Into<Code> for OperationErrorType {
@@ -34,6 +50,22 @@ impl Into<Code> for OperationErrorType {
             OperationErrorType::Offline => Code::Unavailable,
             OperationErrorType::FileDoesNotExist => Code::InvalidArgument,
             OperationErrorType::InvalidUnicode => Code::InvalidArgument,
+            OperationErrorType::ShareDoesNotExist => Code::NotFound,
+            OperationErrorType::Unauthenticated => Code::Unauthenticated,
+            OperationErrorType::InvalidAcl => Code::InvalidArgument,
+            OperationErrorType::NoSpace => Code::ResourceExhausted,
+            OperationErrorType::RenameIntoDescendant => Code::InvalidArgument,
+            OperationErrorType::InvalidArgument => Code::InvalidArgument,
+            OperationErrorType::UnsupportedProtocol => Code::FailedPrecondition,
+            OperationErrorType::RequestTooLarge => Code::ResourceExhausted,
+            OperationErrorType::RateLimited => Code::ResourceExhausted,
+            OperationErrorType::QuotaExceeded => Code::ResourceExhausted,
+            OperationErrorType::CrossDevice => Code::FailedPrecondition,
+            OperationErrorType::PermissionDenied => Code::PermissionDenied,
+            OperationErrorType::NameTooLong => Code::InvalidArgument,
+            OperationErrorType::NotADirectory => Code::FailedPrecondition,
+            OperationErrorType::IsADirectory => Code::FailedPrecondition,
+            OperationErrorType::StaleHandle => Code::NotFound,
         }
     }
 }
@@ -114,6 +146,114 @@ impl OperationError {
             "Invalid unicode string".to_owned(),
         )
     }
+
+    pub fn share_does_not_exist(name: &str) -> Self {
+        Self::new(
+            OperationErrorType::ShareDoesNotExist,
+            format!("Share {} does not exist", name),
+        )
+    }
+
+    pub fn unauthenticated(message: &str) -> Self {
+        Self::new(OperationErrorType::Unauthenticated, message.to_owned())
+    }
+
+    pub fn invalid_acl(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::InvalidAcl,
+            format!("Invalid ACL: {}", message),
+        )
+    }
+
+    pub fn no_space(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::NoSpace,
+            format!("No space left: {}", message),
+        )
+    }
+
+    pub fn rename_into_descendant(id: &str) -> Self {
+        Self::new(
+            OperationErrorType::RenameIntoDescendant,
+            format!("Cannot move directory {} into its own descendant", id),
+        )
+    }
+
+    pub fn invalid_argument(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::InvalidArgument,
+            format!("Invalid argument: {}", message),
+        )
+    }
+
+    pub fn unsupported_protocol(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::UnsupportedProtocol,
+            format!("Unsupported protocol version: {}", message),
+        )
+    }
+
+    pub fn request_too_large(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::RequestTooLarge,
+            format!("Request too large: {}", message),
+        )
+    }
+
+    pub fn rate_limited() -> Self {
+        Self::new(
+            OperationErrorType::RateLimited,
+            "Too many requests, try again later".to_owned(),
+        )
+    }
+
+    pub fn quota_exceeded(id: &str) -> Self {
+        Self::new(
+            OperationErrorType::QuotaExceeded,
+            format!("Quota of directory {} exceeded", id),
+        )
+    }
+
+    pub fn cross_device(message: &str) -> Self {
+        Self::new(OperationErrorType::CrossDevice, message.to_owned())
+    }
+
+    pub fn permission_denied(message: &str) -> Self {
+        Self::new(
+            OperationErrorType::PermissionDenied,
+            format!("Permission denied: {}", message),
+        )
+    }
+
+    pub fn name_too_long(name: &str) -> Self {
+        Self::new(
+            OperationErrorType::NameTooLong,
+            format!("File name too long: {}", name),
+        )
+    }
+
+    pub fn not_a_directory(id: &str) -> Self {
+        Self::new(
+            OperationErrorType::NotADirectory,
+            format!("File {} is not a directory", id),
+        )
+    }
+
+    pub fn is_a_directory(id: &str) -> Self {
+        Self::new(
+            OperationErrorType::IsADirectory,
+            format!("File {} is a directory", id),
+        )
+    }
+
+    /// The handle refers to a file which is no longer known, e.g. an inode or an NFS file
+    /// handle of a file removed in the meantime.
+    pub fn stale_handle(handle: &str) -> Self {
+        Self::new(
+            OperationErrorType::StaleHandle,
+            format!("Stale file handle: {}", handle),
+        )
+    }
 }
 
 impl Display for OperationError {
@@ -130,7 +270,19 @@ impl std::error::Error for OperationError {}
 
 impl From<rusqlite::Error> for OperationError {
     fn from(error: Error) -> Self {
-        Self::new(OperationErrorType::DatabaseError, error.to_string())
+        match &error {
+            Error::SqliteFailure(e, _) if e.code == ErrorCode::DiskFull => {
+                Self::no_space(&error.to_string())
+            }
+            // The names of the files are unique within their directories
+            Error::SqliteFailure(e, Some(message))
+                if e.code == ErrorCode::ConstraintViolation
+                    && message.contains("UNIQUE constraint failed: file.parent, file.name") =>
+            {
+                Self::new(OperationErrorType::ConflictedFile, error.to_string())
+            }
+            _ => Self::new(OperationErrorType::DatabaseError, error.to_string()),
+        }
     }
 }
 
@@ -153,22 +305,48 @@ impl From<OperationError> for tonic::Status {
 
 impl From<tonic::Status> for OperationError {
     fn from(status: Status) -> Self {
-        Self::with_details(
-            FromPrimitive::from_u64(
-                u64::from_str(
-                    status
-                        .metadata()
-                        .get(ERROR_STATUS_CODE_HEADER_KEY)
-                        .unwrap()
-                        .to_str()
-                        .unwrap(),
-                )
-                .unwrap(),
-            )
-            .unwrap(),
-            status.message().to_owned(),
-            Bytes::copy_from_slice(status.details()),
-        )
+        // The statuses generated by tonic itself, e.g. on transport errors or expired
+        // deadlines, do not carry the error type
+        let error_type = status
+            .metadata()
+            .get(ERROR_STATUS_CODE_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| u64::from_str(x).ok())
+            .and_then(FromPrimitive::from_u64);
+
+        match error_type {
+            Some(error_type) => Self::with_details(
+                error_type,
+                status.message().to_owned(),
+                Bytes::copy_from_slice(status.details()),
+            ),
+            None => Self::from_status_code(&status),
+        }
+    }
+}
+
+impl OperationError {
+    fn from_status_code(status: &Status) -> Self {
+        let error_type = match status.code() {
+            Code::Unavailable
+            | Code::DeadlineExceeded
+            | Code::Cancelled
+            | Code::Aborted
+            | Code::Unknown
+            | Code::Internal => return Self::offline(status.message()),
+            Code::NotFound => OperationErrorType::FileDoesNotExist,
+            Code::AlreadyExists => OperationErrorType::ConflictedFile,
+            Code::InvalidArgument | Code::OutOfRange => OperationErrorType::InvalidArgument,
+            Code::Unauthenticated => OperationErrorType::Unauthenticated,
+            Code::PermissionDenied => OperationErrorType::PermissionDenied,
+            Code::ResourceExhausted => OperationErrorType::RequestTooLarge,
+            Code::Unimplemented => OperationErrorType::UnsupportedProtocol,
+            Code::Ok | Code::FailedPrecondition | Code::DataLoss => {
+                OperationErrorType::DatabaseError
+            }
+        };
+
+        Self::new(error_type, status.message().to_owned())
     }
 }
 
@@ -182,15 +360,24 @@ pub type OperationResult<T> = Result<T, OperationError>;
 
 #[derive(PartialEq)]
 pub enum JournalApplyError {
-    InvalidJournal,
-    ConflictingFiles(Vec<String>),
     MissingBlobs(Vec<String>),
 }
 
+/// The outcome of applying a single journal operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JournalOperationStatus {
+    Applied,
+    /// The file has been modified on the server since it was retrieved.
+    Conflicted,
+    Rejected(String),
+}
+
 #[derive(Default)]
 pub struct JournalApplyData {
+    /// The IDs of the files created by the journal, in order; empty if the creation failed.
     pub assigned_ids: Vec<String>,
     pub dir_entities: Vec<DirEntity>,
+    pub statuses: Vec<JournalOperationStatus>,
 }
 
 pub type JournalApplyResult = Result<JournalApplyData, JournalApplyError>;
@@ -1,13 +1,21 @@
+use std::time::Duration;
+
 use crate::timespec::Timespec;
 
+pub mod acl;
+pub mod control;
 pub mod dbus;
 pub mod errors;
 pub mod modify_op;
 pub mod modify_op_handler;
+pub mod names;
 pub mod proto;
+pub mod protocol;
 pub mod store;
+pub mod telemetry;
 pub mod timespec;
 pub mod validators;
+pub mod version_vector;
 
 pub const PROJ_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PROJ_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -18,8 +26,14 @@ pub const ROOT_ID: &str = "root";
 
 pub const SQLITE_PAGE_SIZE: i64 = 8192;
 pub const SQLITE_CACHE_SIZE: i64 = -32000; // 32MiB
+pub const SQLITE_READ_CONNECTIONS: usize = 4;
+/// How long a write waits for the ones made through the other connections to finish
+pub const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub const ERROR_STATUS_CODE_HEADER_KEY: &str = "offs-status-code";
+pub const REQUEST_ID_HEADER_KEY: &str = "offs-request-id";
+pub const AUTHORIZATION_HEADER_KEY: &str = "authorization";
+pub const CLIENT_ID_HEADER_KEY: &str = "offs-client-id";
 
 pub fn now() -> Timespec {
     Timespec::now()
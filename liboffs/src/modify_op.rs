@@ -1,5 +1,8 @@
-use crate::store::{FileDev, FileMode, FileType};
+use crate::acl::AclType;
+use crate::store::id_generator::{IdGenerator, RandomHexIdGenerator};
+use crate::store::{FileDev, FileMode, FileOwner, FileType};
 use crate::timespec::Timespec;
+use crate::version_vector::VersionVector;
 
 #[derive(Clone)]
 pub struct CreateFileOperation {
@@ -7,18 +10,22 @@ pub struct CreateFileOperation {
     pub file_type: FileType,
     pub perm: FileMode,
     pub dev: FileDev,
+    /// The files get the default owner of the store if not set
+    pub owner: Option<FileOwner>,
 }
 
 #[derive(Clone)]
 pub struct CreateSymlinkOperation {
     pub name: String,
     pub link: String,
+    pub owner: Option<FileOwner>,
 }
 
 #[derive(Clone)]
 pub struct CreateDirectoryOperation {
     pub name: String,
     pub perm: FileMode,
+    pub owner: Option<FileOwner>,
 }
 
 #[derive(Clone)]
@@ -49,6 +56,31 @@ pub struct WriteOperation {
     pub data: Vec<u8>,
 }
 
+#[derive(Clone)]
+pub struct SetAclOperation {
+    pub acl_type: AclType,
+    /// ACL in the `system.posix_acl_*` extended attribute format; empty to remove the ACL
+    pub acl: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct FallocateOperation {
+    pub offset: i64,
+    pub length: i64,
+    /// Whether the range should be zeroed (punching a hole in the file)
+    pub zero: bool,
+    /// Whether the file size should be left intact even if the range exceeds it
+    pub keep_size: bool,
+}
+
+#[derive(Clone)]
+pub struct CopyRangeOperation {
+    pub source_id: String,
+    pub source_offset: i64,
+    pub offset: i64,
+    pub length: i64,
+}
+
 #[derive(Clone)]
 pub enum ModifyOperationContent {
     CreateFileOperation(CreateFileOperation),
@@ -61,15 +93,47 @@ pub enum ModifyOperationContent {
     RenameOperation(RenameOperation),
     SetAttributesOperation(SetAttributesOperation),
     WriteOperation(WriteOperation),
+    SetAclOperation(SetAclOperation),
+    FallocateOperation(FallocateOperation),
+    CopyRangeOperation(CopyRangeOperation),
+}
+
+impl ModifyOperationContent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModifyOperationContent::CreateFileOperation(_) => "create_file",
+            ModifyOperationContent::CreateSymlinkOperation(_) => "create_symlink",
+            ModifyOperationContent::CreateDirectoryOperation(_) => "create_directory",
+            ModifyOperationContent::RemoveFileOperation(_) => "remove_file",
+            ModifyOperationContent::RemoveDirectoryOperation(_) => "remove_directory",
+            ModifyOperationContent::RenameOperation(_) => "rename",
+            ModifyOperationContent::SetAttributesOperation(_) => "set_attributes",
+            ModifyOperationContent::WriteOperation(_) => "write",
+            ModifyOperationContent::SetAclOperation(_) => "set_acl",
+            ModifyOperationContent::FallocateOperation(_) => "fallocate",
+            ModifyOperationContent::CopyRangeOperation(_) => "copy_range",
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ModifyOperation {
     pub id: String,
+    /// Unique ID of the operation, letting the server recognize the operations already applied.
+    pub op_id: String,
     pub timestamp: Timespec,
 
     pub dirent_version: i64,
     pub content_version: i64,
+    pub content_vector: VersionVector,
+    pub dirent_vector: VersionVector,
+    /// ID of the replica that made the operation; set by the server from the session of the
+    /// client that sent it.
+    pub replica_id: String,
 
     pub operation: ModifyOperationContent,
 }
+
+pub fn generate_op_id() -> String {
+    RandomHexIdGenerator::new().generate_id()
+}
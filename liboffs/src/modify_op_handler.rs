@@ -1,8 +1,8 @@
 use crate::errors::OperationResult;
 use crate::modify_op::{
-    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation, ModifyOperation,
-    ModifyOperationContent, RemoveDirectoryOperation, RemoveFileOperation, RenameOperation,
-    SetAttributesOperation, WriteOperation,
+    CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
+    FallocateOperation, ModifyOperation, ModifyOperationContent, RemoveDirectoryOperation,
+    RemoveFileOperation, RenameOperation, SetAclOperation, SetAttributesOperation, WriteOperation,
 };
 use crate::timespec::Timespec;
 
@@ -63,6 +63,27 @@ pub trait OperationHandler {
         operation: &WriteOperation,
     ) -> OperationResult<()>;
 
+    fn perform_set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()>;
+
+    fn perform_fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()>;
+
+    fn perform_copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()>;
+
     fn deferred_create_file(
         &mut self,
         _parent_id: &str,
@@ -150,6 +171,39 @@ pub trait OperationHandler {
     ) -> OperationResult<()> {
         unimplemented!()
     }
+
+    fn deferred_set_acl(
+        &mut self,
+        _id: &str,
+        _timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        _operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        unimplemented!()
+    }
+
+    fn deferred_fallocate(
+        &mut self,
+        _id: &str,
+        _timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        _operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        unimplemented!()
+    }
+
+    fn deferred_copy_range(
+        &mut self,
+        _id: &str,
+        _timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        _operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        unimplemented!()
+    }
 }
 
 pub struct OperationApplier;
@@ -260,6 +314,33 @@ impl OperationApplier {
                 content_version,
                 op,
             )?,
+            ModifyOperationContent::SetAclOperation(op) => Self::set_acl_op(
+                handler,
+                deferred,
+                id,
+                timestamp,
+                dirent_version,
+                content_version,
+                op,
+            )?,
+            ModifyOperationContent::FallocateOperation(op) => Self::fallocate_op(
+                handler,
+                deferred,
+                id,
+                timestamp,
+                dirent_version,
+                content_version,
+                op,
+            )?,
+            ModifyOperationContent::CopyRangeOperation(op) => Self::copy_range_op(
+                handler,
+                deferred,
+                id,
+                timestamp,
+                dirent_version,
+                content_version,
+                op,
+            )?,
         }
 
         Ok(new_id)
@@ -416,4 +497,52 @@ impl OperationApplier {
             Ok(handler.perform_write(id, timestamp, operation)?)
         }
     }
+
+    fn set_acl_op<T: OperationHandler>(
+        handler: &mut T,
+        deferred: bool,
+        id: &str,
+        timestamp: Timespec,
+        dirent_version: i64,
+        content_version: i64,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        if deferred {
+            handler.deferred_set_acl(id, timestamp, dirent_version, content_version, operation)
+        } else {
+            Ok(handler.perform_set_acl(id, timestamp, operation)?)
+        }
+    }
+
+    fn fallocate_op<T: OperationHandler>(
+        handler: &mut T,
+        deferred: bool,
+        id: &str,
+        timestamp: Timespec,
+        dirent_version: i64,
+        content_version: i64,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        if deferred {
+            handler.deferred_fallocate(id, timestamp, dirent_version, content_version, operation)
+        } else {
+            Ok(handler.perform_fallocate(id, timestamp, operation)?)
+        }
+    }
+
+    fn copy_range_op<T: OperationHandler>(
+        handler: &mut T,
+        deferred: bool,
+        id: &str,
+        timestamp: Timespec,
+        dirent_version: i64,
+        content_version: i64,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        if deferred {
+            handler.deferred_copy_range(id, timestamp, dirent_version, content_version, operation)
+        } else {
+            Ok(handler.perform_copy_range(id, timestamp, operation)?)
+        }
+    }
 }
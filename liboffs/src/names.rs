@@ -0,0 +1,13 @@
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+/// Converts the file name to the Unicode Normalization Form C, so that the same name typed on
+/// a system preferring the decomposed form (such as macOS) and on Linux refers to the same file.
+pub fn normalize_name(name: &str) -> Cow<'_, str> {
+    if is_nfc_quick(name.chars()) == IsNormalized::Yes {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(name.nfc().collect())
+    }
+}
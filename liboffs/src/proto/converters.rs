@@ -1,17 +1,82 @@
+use std::convert::{TryFrom, TryInto};
+use std::fmt::{Display, Formatter};
+
 use itertools::Itertools;
 use num_traits::cast::FromPrimitive;
 
-use crate::errors::{JournalApplyData, JournalApplyError, JournalApplyResult};
+use crate::acl::AclType;
+use crate::errors::{
+    JournalApplyData, JournalApplyError, JournalApplyResult, JournalOperationStatus, OperationError,
+};
 use crate::modify_op;
 use crate::modify_op::ModifyOperationContent;
 use crate::proto::filesystem::apply_journal_response::Error;
+use crate::proto::filesystem::journal_operation_status::Status;
 use crate::proto::filesystem::modify_operation::Operation;
 use crate::proto::filesystem::FileChunks;
 use crate::store as crate_types;
-use crate::store::{FileMode, FileType};
+use crate::store::{FileMode, FileOwner, FileType};
+use crate::version_vector::VersionVector;
 
 use super::filesystem as proto_types;
 
+const NANOS_PER_SEC: i32 = 1_000_000_000;
+
+/// Error of converting a malformed proto message, such as one lacking a required field or
+/// containing an unknown enum value.
+#[derive(Clone, Debug)]
+pub struct ProtoConversionError {
+    message: String,
+}
+
+impl ProtoConversionError {
+    fn missing_field(name: &str) -> Self {
+        Self {
+            message: format!("{} missing in the proto message", name),
+        }
+    }
+
+    fn invalid_value(name: &str, value: impl Display) -> Self {
+        Self {
+            message: format!("invalid {} in the proto message: {}", name, value),
+        }
+    }
+}
+
+impl Display for ProtoConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ProtoConversionError {}
+
+impl From<ProtoConversionError> for OperationError {
+    fn from(error: ProtoConversionError) -> Self {
+        OperationError::invalid_argument(&error.message)
+    }
+}
+
+impl From<ProtoConversionError> for tonic::Status {
+    fn from(error: ProtoConversionError) -> Self {
+        OperationError::from(error).into()
+    }
+}
+
+/// Converts the value of a required message field.
+fn convert_required<T, U>(value: Option<T>, name: &str) -> Result<U, ProtoConversionError>
+where
+    T: TryInto<U, Error = ProtoConversionError>,
+{
+    value
+        .ok_or_else(|| ProtoConversionError::missing_field(name))?
+        .try_into()
+}
+
+fn convert_file_type(value: i32) -> Result<FileType, ProtoConversionError> {
+    FileType::from_i32(value).ok_or_else(|| ProtoConversionError::invalid_value("file type", value))
+}
+
 // Timespec
 impl Into<proto_types::Timespec> for crate::timespec::Timespec {
     fn into(self) -> proto_types::Timespec {
@@ -22,9 +87,33 @@ impl Into<proto_types::Timespec> for crate::timespec::Timespec {
     }
 }
 
-impl Into<crate::timespec::Timespec> for proto_types::Timespec {
-    fn into(self) -> crate::timespec::Timespec {
-        crate::timespec::Timespec::new(self.sec, self.nsec as u32)
+impl TryFrom<proto_types::Timespec> for crate::timespec::Timespec {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::Timespec) -> Result<Self, Self::Error> {
+        if !(0..NANOS_PER_SEC).contains(&value.nsec) {
+            return Err(ProtoConversionError::invalid_value(
+                "nanoseconds",
+                value.nsec,
+            ));
+        }
+
+        Ok(crate::timespec::Timespec::new(value.sec, value.nsec as u32))
+    }
+}
+
+// VersionVector
+impl From<VersionVector> for proto_types::VersionVector {
+    fn from(value: VersionVector) -> Self {
+        proto_types::VersionVector {
+            versions: value.into_versions().into_iter().collect(),
+        }
+    }
+}
+
+impl From<proto_types::VersionVector> for VersionVector {
+    fn from(value: proto_types::VersionVector) -> Self {
+        VersionVector::new(value.versions.into_iter().collect())
     }
 }
 
@@ -97,14 +186,19 @@ impl From<crate_types::FileStat> for proto_types::Stat {
             atim: Some(value.atim.into()),
             mtim: Some(value.mtim.into()),
             ctim: Some(value.ctim.into()),
+            crtim: Some(value.crtim.into()),
         }
     }
 }
 
-impl From<proto_types::Stat> for crate_types::FileStat {
-    fn from(value: proto_types::Stat) -> Self {
-        crate_types::FileStat {
-            file_type: FileType::from_i32(value.file_type).unwrap(),
+impl TryFrom<proto_types::Stat> for crate_types::FileStat {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::Stat) -> Result<Self, Self::Error> {
+        let mtim = convert_required(value.mtim, "mtim")?;
+
+        Ok(crate_types::FileStat {
+            file_type: convert_file_type(value.file_type)?,
             mode: value.perm as u16,
             dev: 0,
             nlink: value.nlink,
@@ -112,19 +206,15 @@ impl From<proto_types::Stat> for crate_types::FileStat {
             gid: value.gid,
             size: value.size,
             blocks: value.blocks,
-            atim: value
-                .atim
-                .expect("atim missing in the proto message")
-                .into(),
-            mtim: value
-                .mtim
-                .expect("mtim missing in the proto message")
-                .into(),
-            ctim: value
-                .ctim
-                .expect("ctim missing in the proto message")
-                .into(),
-        }
+            atim: convert_required(value.atim, "atim")?,
+            mtim,
+            ctim: convert_required(value.ctim, "ctim")?,
+            crtim: value
+                .crtim
+                .map(TryInto::try_into)
+                .transpose()?
+                .unwrap_or(mtim),
+        })
     }
 }
 
@@ -138,13 +228,19 @@ impl From<crate_types::DirEntity> for proto_types::DirEntity {
             dirent_version: value.dirent_version,
             content_version: value.content_version,
             stat: Some(value.stat.into()),
+            acl_access: value.acl_access.unwrap_or_default(),
+            acl_default: value.acl_default.unwrap_or_default(),
+            content_vector: Some(value.content_vector.into()),
+            dirent_vector: Some(value.dirent_vector.into()),
         }
     }
 }
 
-impl From<proto_types::DirEntity> for crate_types::DirEntity {
-    fn from(value: proto_types::DirEntity) -> Self {
-        crate_types::DirEntity {
+impl TryFrom<proto_types::DirEntity> for crate_types::DirEntity {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::DirEntity) -> Result<Self, Self::Error> {
+        Ok(crate_types::DirEntity {
             id: value.id,
             parent: value.parent,
             name: value.name,
@@ -152,11 +248,54 @@ impl From<proto_types::DirEntity> for crate_types::DirEntity {
             dirent_version: value.dirent_version,
             content_version: value.content_version,
             retrieved_version: 0,
+            content_vector: value.content_vector.map(Into::into).unwrap_or_default(),
+            dirent_vector: value.dirent_vector.map(Into::into).unwrap_or_default(),
 
-            stat: value
-                .stat
-                .expect("stat missing in the proto message")
-                .into(),
+            stat: convert_required(value.stat, "stat")?,
+
+            acl_access: Some(value.acl_access).filter(|x| !x.is_empty()),
+            acl_default: Some(value.acl_default).filter(|x| !x.is_empty()),
+        })
+    }
+}
+
+impl From<crate_types::Sidecar> for proto_types::Sidecar {
+    fn from(value: crate_types::Sidecar) -> Self {
+        Self {
+            name: value.name,
+            blob_id: value.blob,
+            size: value.size,
+            content_version: value.content_version,
+        }
+    }
+}
+
+impl From<proto_types::Sidecar> for crate_types::Sidecar {
+    fn from(value: proto_types::Sidecar) -> Self {
+        Self {
+            name: value.name,
+            blob: value.blob_id,
+            size: value.size,
+            content_version: value.content_version,
+        }
+    }
+}
+
+// FileOwner
+impl From<FileOwner> for proto_types::FileOwner {
+    fn from(value: FileOwner) -> Self {
+        proto_types::FileOwner {
+            uid: value.uid,
+            gid: value.gid,
+        }
+    }
+}
+
+impl From<proto_types::FileOwner> for FileOwner {
+    fn from(value: proto_types::FileOwner) -> Self {
+        FileOwner {
+            uid: value.uid,
+            gid: value.gid,
         }
     }
 }
@@ -169,18 +308,22 @@ impl From<modify_op::CreateFileOperation> for proto_types::CreateFileOperation {
             file_type: value.file_type as i32,
             perm: value.perm as u32,
             dev: value.dev,
+            owner: value.owner.map(Into::into),
         }
     }
 }
 
-impl From<proto_types::CreateFileOperation> for modify_op::CreateFileOperation {
-    fn from(value: proto_types::CreateFileOperation) -> Self {
-        modify_op::CreateFileOperation {
+impl TryFrom<proto_types::CreateFileOperation> for modify_op::CreateFileOperation {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::CreateFileOperation) -> Result<Self, Self::Error> {
+        Ok(modify_op::CreateFileOperation {
             name: value.name,
-            file_type: FileType::from_i32(value.file_type).unwrap(),
+            file_type: convert_file_type(value.file_type)?,
             perm: value.perm as u16,
             dev: value.dev,
-        }
+            owner: value.owner.map(Into::into),
+        })
     }
 }
 
@@ -190,6 +333,7 @@ impl From<modify_op::CreateSymlinkOperation> for proto_types::CreateSymlinkOpera
         proto_types::CreateSymlinkOperation {
             name: value.name,
             link: value.link,
+            owner: value.owner.map(Into::into),
         }
     }
 }
@@ -199,6 +343,7 @@ impl From<proto_types::CreateSymlinkOperation> for modify_op::CreateSymlinkOpera
         modify_op::CreateSymlinkOperation {
             name: value.name,
             link: value.link,
+            owner: value.owner.map(Into::into),
         }
     }
 }
@@ -209,6 +354,7 @@ impl From<modify_op::CreateDirectoryOperation> for proto_types::CreateDirectoryO
         proto_types::CreateDirectoryOperation {
             name: value.name,
             perm: value.perm as u32,
+            owner: value.owner.map(Into::into),
         }
     }
 }
@@ -218,6 +364,7 @@ impl From<proto_types::CreateDirectoryOperation> for modify_op::CreateDirectoryO
         modify_op::CreateDirectoryOperation {
             name: value.name,
             perm: value.perm as u16,
+            owner: value.owner.map(Into::into),
         }
     }
 }
@@ -281,16 +428,18 @@ impl From<modify_op::SetAttributesOperation> for proto_types::SetAttributesOpera
     }
 }
 
-impl From<proto_types::SetAttributesOperation> for modify_op::SetAttributesOperation {
-    fn from(value: proto_types::SetAttributesOperation) -> Self {
-        Self {
+impl TryFrom<proto_types::SetAttributesOperation> for modify_op::SetAttributesOperation {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::SetAttributesOperation) -> Result<Self, Self::Error> {
+        Ok(Self {
             perm: value.perm.map(|x| Into::<u32>::into(x) as FileMode),
             uid: value.uid.map(|x| x.into()),
             gid: value.gid.map(|x| x.into()),
             size: value.size.map(|x| x.into()),
-            atim: value.atim.map(|x| x.into()),
-            mtim: value.mtim.map(|x| x.into()),
-        }
+            atim: value.atim.map(TryInto::try_into).transpose()?,
+            mtim: value.mtim.map(TryInto::try_into).transpose()?,
+        })
     }
 }
 
@@ -313,6 +462,94 @@ impl From<proto_types::WriteOperation> for modify_op::WriteOperation {
     }
 }
 
+// AclType
+impl From<AclType> for proto_types::AclType {
+    fn from(value: AclType) -> Self {
+        match value {
+            AclType::Access => proto_types::AclType::Access,
+            AclType::Default => proto_types::AclType::Default,
+        }
+    }
+}
+
+impl From<proto_types::AclType> for AclType {
+    fn from(value: proto_types::AclType) -> Self {
+        match value {
+            proto_types::AclType::Access => AclType::Access,
+            proto_types::AclType::Default => AclType::Default,
+        }
+    }
+}
+
+// SetAclOperation
+impl From<modify_op::SetAclOperation> for proto_types::SetAclOperation {
+    fn from(value: modify_op::SetAclOperation) -> Self {
+        proto_types::SetAclOperation {
+            acl_type: proto_types::AclType::from(value.acl_type) as i32,
+            acl: value.acl,
+        }
+    }
+}
+
+impl TryFrom<proto_types::SetAclOperation> for modify_op::SetAclOperation {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::SetAclOperation) -> Result<Self, Self::Error> {
+        Ok(modify_op::SetAclOperation {
+            acl_type: proto_types::AclType::from_i32(value.acl_type)
+                .ok_or_else(|| ProtoConversionError::invalid_value("ACL type", value.acl_type))?
+                .into(),
+            acl: value.acl,
+        })
+    }
+}
+
+// FallocateOperation
+impl From<modify_op::FallocateOperation> for proto_types::FallocateOperation {
+    fn from(value: modify_op::FallocateOperation) -> Self {
+        proto_types::FallocateOperation {
+            offset: value.offset,
+            length: value.length,
+            zero: value.zero,
+            keep_size: value.keep_size,
+        }
+    }
+}
+
+impl From<proto_types::FallocateOperation> for modify_op::FallocateOperation {
+    fn from(value: proto_types::FallocateOperation) -> Self {
+        modify_op::FallocateOperation {
+            offset: value.offset,
+            length: value.length,
+            zero: value.zero,
+            keep_size: value.keep_size,
+        }
+    }
+}
+
+// CopyRangeOperation
+impl From<modify_op::CopyRangeOperation> for proto_types::CopyRangeOperation {
+    fn from(value: modify_op::CopyRangeOperation) -> Self {
+        proto_types::CopyRangeOperation {
+            source_id: value.source_id,
+            source_offset: value.source_offset,
+            offset: value.offset,
+            length: value.length,
+        }
+    }
+}
+
+impl From<proto_types::CopyRangeOperation> for modify_op::CopyRangeOperation {
+    fn from(value: proto_types::CopyRangeOperation) -> Self {
+        modify_op::CopyRangeOperation {
+            source_id: value.source_id,
+            source_offset: value.source_offset,
+            offset: value.offset,
+            length: value.length,
+        }
+    }
+}
+
 // ModifyOperation
 impl From<modify_op::ModifyOperation> for proto_types::ModifyOperation {
     fn from(value: modify_op::ModifyOperation) -> Self {
@@ -322,6 +559,9 @@ impl From<modify_op::ModifyOperation> for proto_types::ModifyOperation {
 
             dirent_version: value.dirent_version,
             content_version: value.content_version,
+            content_vector: Some(value.content_vector.into()),
+            dirent_vector: Some(value.dirent_vector.into()),
+            replica_id: value.replica_id,
 
             operation: Some(match value.operation {
                 ModifyOperationContent::CreateFileOperation(op) => {
@@ -348,22 +588,46 @@ impl From<modify_op::ModifyOperation> for proto_types::ModifyOperation {
                 ModifyOperationContent::WriteOperation(op) => {
                     proto_types::modify_operation::Operation::Write(op.into())
                 }
+                ModifyOperationContent::SetAclOperation(op) => {
+                    proto_types::modify_operation::Operation::SetAcl(op.into())
+                }
+                ModifyOperationContent::FallocateOperation(op) => {
+                    proto_types::modify_operation::Operation::Fallocate(op.into())
+                }
+                ModifyOperationContent::CopyRangeOperation(op) => {
+                    proto_types::modify_operation::Operation::CopyRange(op.into())
+                }
             }),
+
+            share: Default::default(),
+            op_id: value.op_id,
         }
     }
 }
 
-impl From<proto_types::ModifyOperation> for modify_op::ModifyOperation {
-    fn from(value: proto_types::ModifyOperation) -> Self {
-        modify_op::ModifyOperation {
+impl TryFrom<proto_types::ModifyOperation> for modify_op::ModifyOperation {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::ModifyOperation) -> Result<Self, Self::Error> {
+        let operation = value
+            .operation
+            .ok_or_else(|| ProtoConversionError::missing_field("operation"))?;
+
+        Ok(modify_op::ModifyOperation {
             id: value.id,
-            timestamp: value.timestamp.unwrap().into(),
+            op_id: value.op_id,
+            timestamp: convert_required(value.timestamp, "timestamp")?,
 
             dirent_version: value.dirent_version,
             content_version: value.content_version,
+            content_vector: value.content_vector.map(Into::into).unwrap_or_default(),
+            dirent_vector: value.dirent_vector.map(Into::into).unwrap_or_default(),
+            replica_id: value.replica_id,
 
-            operation: match value.operation.unwrap() {
-                Operation::CreateFile(op) => ModifyOperationContent::CreateFileOperation(op.into()),
+            operation: match operation {
+                Operation::CreateFile(op) => {
+                    ModifyOperationContent::CreateFileOperation(op.try_into()?)
+                }
                 Operation::CreateSymlink(op) => {
                     ModifyOperationContent::CreateSymlinkOperation(op.into())
                 }
@@ -376,11 +640,14 @@ impl From<proto_types::ModifyOperation> for modify_op::ModifyOperation {
                 }
                 Operation::Rename(op) => ModifyOperationContent::RenameOperation(op.into()),
                 Operation::SetAttributes(op) => {
-                    ModifyOperationContent::SetAttributesOperation(op.into())
+                    ModifyOperationContent::SetAttributesOperation(op.try_into()?)
                 }
                 Operation::Write(op) => ModifyOperationContent::WriteOperation(op.into()),
+                Operation::SetAcl(op) => ModifyOperationContent::SetAclOperation(op.try_into()?),
+                Operation::Fallocate(op) => ModifyOperationContent::FallocateOperation(op.into()),
+                Operation::CopyRange(op) => ModifyOperationContent::CopyRangeOperation(op.into()),
             },
-        }
+        })
     }
 }
 
@@ -399,6 +666,38 @@ impl Into<Vec<String>> for FileChunks {
     }
 }
 
+// JournalOperationStatus
+impl From<JournalOperationStatus> for proto_types::JournalOperationStatus {
+    fn from(value: JournalOperationStatus) -> Self {
+        let (status, reason) = match value {
+            JournalOperationStatus::Applied => (Status::Applied, String::new()),
+            JournalOperationStatus::Conflicted => (Status::Conflicted, String::new()),
+            JournalOperationStatus::Rejected(reason) => (Status::Rejected, reason),
+        };
+
+        proto_types::JournalOperationStatus {
+            status: status as i32,
+            reason,
+        }
+    }
+}
+
+impl TryFrom<proto_types::JournalOperationStatus> for JournalOperationStatus {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::JournalOperationStatus) -> Result<Self, Self::Error> {
+        let status = Status::from_i32(value.status).ok_or_else(|| {
+            ProtoConversionError::invalid_value("journal operation status", value.status)
+        })?;
+
+        Ok(match status {
+            Status::Applied => JournalOperationStatus::Applied,
+            Status::Conflicted => JournalOperationStatus::Conflicted,
+            Status::Rejected => JournalOperationStatus::Rejected(value.reason),
+        })
+    }
+}
+
 // OperationApplyError
 impl From<JournalApplyResult> for proto_types::ApplyJournalResponse {
     fn from(value: JournalApplyResult) -> Self {
@@ -413,21 +712,12 @@ impl From<JournalApplyResult> for proto_types::ApplyJournalResponse {
                 proto_types::ApplyJournalResponse {
                     assigned_ids: data.assigned_ids.into(),
                     dir_entities: converted_dir_entities.into(),
+                    statuses: data.statuses.into_iter().map(|x| x.into()).collect_vec(),
                     error: None,
                 }
             }
             Err(err) => {
                 let error = match err {
-                    JournalApplyError::InvalidJournal => {
-                        let data = proto_types::InvalidJournalError {};
-
-                        proto_types::apply_journal_response::Error::InvalidJournal(data)
-                    }
-                    JournalApplyError::ConflictingFiles(ids) => {
-                        let data = proto_types::ConflictingFilesError { ids };
-
-                        proto_types::apply_journal_response::Error::ConflictingFiles(data)
-                    }
                     JournalApplyError::MissingBlobs(ids) => {
                         let data = proto_types::MissingBlobsError { ids };
 
@@ -438,6 +728,7 @@ impl From<JournalApplyResult> for proto_types::ApplyJournalResponse {
                 proto_types::ApplyJournalResponse {
                     assigned_ids: Default::default(),
                     dir_entities: Default::default(),
+                    statuses: Default::default(),
                     error: Some(error),
                 }
             }
@@ -445,27 +736,30 @@ impl From<JournalApplyResult> for proto_types::ApplyJournalResponse {
     }
 }
 
-impl Into<JournalApplyResult> for proto_types::ApplyJournalResponse {
-    fn into(self) -> JournalApplyResult {
-        if let Some(err) = self.error {
+impl TryFrom<proto_types::ApplyJournalResponse> for JournalApplyResult {
+    type Error = ProtoConversionError;
+
+    fn try_from(value: proto_types::ApplyJournalResponse) -> Result<Self, Self::Error> {
+        if let Some(err) = value.error {
             let converted_error = match err {
-                Error::InvalidJournal(_) => JournalApplyError::InvalidJournal,
-                Error::ConflictingFiles(data) => {
-                    JournalApplyError::ConflictingFiles(data.ids.into())
-                }
-                Error::MissingBlobs(data) => JournalApplyError::MissingBlobs(data.ids.into()),
+                Error::MissingBlobs(data) => JournalApplyError::MissingBlobs(data.ids),
             };
 
-            Err(converted_error)
+            Ok(Err(converted_error))
         } else {
-            Ok(JournalApplyData {
-                assigned_ids: self.assigned_ids.into(),
-                dir_entities: self
+            Ok(Ok(JournalApplyData {
+                assigned_ids: value.assigned_ids,
+                dir_entities: value
                     .dir_entities
                     .into_iter()
-                    .map(|x| x.into())
-                    .collect_vec(),
-            })
+                    .map(TryInto::try_into)
+                    .try_collect()?,
+                statuses: value
+                    .statuses
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .try_collect()?,
+            }))
         }
     }
 }
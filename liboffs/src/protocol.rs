@@ -0,0 +1,54 @@
+//! Versioning of the protocol spoken between the clients and the server.
+
+use crate::errors::{OperationError, OperationResult};
+
+/// Version of the protocol, increased whenever a change requires both sides to be aware of it.
+/// The servers predating `GetServerInfo` are treated as version 0.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Oldest version of the protocol of the other side that is still supported. Version 2 includes
+/// the trailing zeros of the blobs in their IDs.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Journals can be applied in two phases with `BeginJournalApply` and `CommitJournalApply`.
+pub const FEATURE_STAGED_JOURNAL_APPLY: &str = "staged_journal_apply";
+/// The blobs of a staged journal can be streamed with `BeginStreamedJournalApply`.
+pub const FEATURE_STREAMED_JOURNAL_APPLY: &str = "streamed_journal_apply";
+/// Whole directory subtrees can be listed at once with `ListTree`.
+pub const FEATURE_LIST_TREE: &str = "list_tree";
+/// The entries of several files can be retrieved at once with `GetDirEntities`.
+pub const FEATURE_GET_DIR_ENTITIES: &str = "get_dir_entities";
+/// The total usage of directory subtrees can be retrieved with `GetDirUsage`.
+pub const FEATURE_DIR_USAGE: &str = "dir_usage";
+/// Links to the files can be created with `CreateShareLink`, as the server serves them.
+pub const FEATURE_SHARE_LINKS: &str = "share_links";
+/// Directories can be listed in pages, ordered by the name, with `start_after` and `limit` of
+/// `List`.
+pub const FEATURE_LIST_PAGES: &str = "list_pages";
+/// Data derived from the files can be attached to them with `SetSidecar` and listed with
+/// `ListSidecars`.
+pub const FEATURE_SIDECARS: &str = "sidecars";
+/// Journals can be checked against the current state of the files with `validate_only` of
+/// `ApplyJournal`. The servers not supporting it would apply the journal instead.
+pub const FEATURE_VALIDATE_JOURNAL: &str = "validate_journal";
+/// The server hashes the blobs with BLAKE3, so the clients should use it for the new blobs.
+/// The blobs hashed with SHA-256 are still accepted either way.
+pub const FEATURE_BLAKE3_BLOB_IDS: &str = "blake3_blob_ids";
+
+/// Checks whether a peer speaking protocol `version`, which supports our version down to
+/// `min_version`, can be talked to.
+pub fn check_compatibility(version: u32, min_version: u32) -> OperationResult<()> {
+    if version < MIN_PROTOCOL_VERSION {
+        Err(OperationError::unsupported_protocol(&format!(
+            "the other side uses version {}, while at least {} is required; it has to be upgraded",
+            version, MIN_PROTOCOL_VERSION
+        )))
+    } else if min_version > PROTOCOL_VERSION {
+        Err(OperationError::unsupported_protocol(&format!(
+            "the other side requires at least version {}, while {} is used; this side has to be \
+             upgraded",
+            min_version, PROTOCOL_VERSION
+        )))
+    } else {
+        Ok(())
+    }
+}
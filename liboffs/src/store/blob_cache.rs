@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use lru::LruCache;
+
+struct BlobCacheInner {
+    blobs: LruCache<String, Bytes>,
+    size: usize,
+}
+
+/// In-memory LRU cache of blob contents, bounded by the total size of the blobs stored.
+///
+/// Since the blobs are content-addressed, a cached blob never gets stale; entries are only
+/// evicted to keep the memory usage down.
+pub struct BlobCache {
+    capacity: AtomicUsize,
+    inner: Mutex<BlobCacheInner>,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl BlobCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: AtomicUsize::new(capacity),
+            inner: Mutex::new(BlobCacheInner {
+                blobs: LruCache::unbounded(),
+                size: 0,
+            }),
+
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, id: &String) -> Option<Bytes> {
+        let result = self.inner.lock().unwrap().blobs.get(id).cloned();
+
+        let counter = if result.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        result
+    }
+
+    pub fn insert(&self, id: String, blob: Bytes) {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if blob.len() > capacity {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.size += blob.len();
+        if let Some(old_blob) = inner.blobs.put(id, blob) {
+            inner.size -= old_blob.len();
+        }
+        Self::evict(&mut inner, capacity);
+    }
+
+    /// Changes the total size of the blobs the cache can hold, evicting the least recently used
+    /// ones if they no longer fit.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        self.capacity.store(capacity, Ordering::Relaxed);
+        Self::evict(&mut inner, capacity);
+    }
+
+    fn evict(inner: &mut BlobCacheInner, capacity: usize) {
+        while inner.size > capacity {
+            let (_, evicted) = inner.blobs.pop_lru().unwrap();
+            inner.size -= evicted.len();
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.blobs.clear();
+        inner.size = 0;
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.lock().unwrap().size
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+use std::str::FromStr;
+
+use digest::Digest;
+use sha2::Sha256;
+
+/// Prefix of the IDs of the blobs hashed with BLAKE3. The IDs without a prefix are SHA-256
+/// hashes, as they were before the algorithm was tagged.
+const BLAKE3_PREFIX: &str = "blake3:";
+
+/// Hash function the blob IDs are derived from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlobHash {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl BlobHash {
+    /// Returns the hash function the blob with given ID has been hashed with.
+    pub fn of_id(id: &str) -> Self {
+        if id.starts_with(BLAKE3_PREFIX) {
+            Self::Blake3
+        } else {
+            Self::Sha256
+        }
+    }
+
+    pub fn blob_id(self, data: &[u8]) -> String {
+        match self {
+            Self::Sha256 => hex::encode(Sha256::digest(data)),
+            Self::Blake3 => format!("{}{}", BLAKE3_PREFIX, blake3::hash(data).to_hex()),
+        }
+    }
+}
+
+impl fmt::Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "sha256"),
+            Self::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+impl FromStr for BlobHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => Err(format!("unknown blob hash: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use rusqlite::params;
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::IdGenerator;
+use crate::store::Store;
+
+/// How much space the content addressing of the blobs saves.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DedupStats {
+    /// Size of the content of all the chunks, as if every chunk had its own blob.
+    pub logical_size: u64,
+    /// Total size of the distinct blobs.
+    pub physical_size: u64,
+    pub blobs: u64,
+    /// The files sharing the most content, with the most bytes saved first.
+    pub files: Vec<FileDedupStats>,
+}
+
+impl DedupStats {
+    pub fn ratio(&self) -> f64 {
+        dedup_ratio(self.logical_size, self.physical_size)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileDedupStats {
+    pub id: String,
+    pub tenant: String,
+    /// Path of the file within the root directory of the tenant.
+    pub path: String,
+    pub logical_size: u64,
+    /// The share of the blobs of the file, with each blob split evenly between all the chunks
+    /// referencing it.
+    pub physical_size: u64,
+}
+
+impl FileDedupStats {
+    pub fn ratio(&self) -> f64 {
+        dedup_ratio(self.logical_size, self.physical_size)
+    }
+}
+
+fn dedup_ratio(logical_size: u64, physical_size: u64) -> f64 {
+    if physical_size == 0 {
+        1.0
+    } else {
+        logical_size as f64 / physical_size as f64
+    }
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    /// Returns the deduplication statistics of the store, along with up to `limit` files (all of
+    /// them if 0) that save the most space by sharing their blobs.
+    pub fn dedup_stats(&self, limit: u32) -> OperationResult<DedupStats> {
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+        let connection = self.pool.reader();
+
+        let (physical_size, blobs): (i64, i64) = connection.query_row(
+            "SELECT COALESCE(SUM(COALESCE(length, LENGTH(content))), 0), COUNT(*) FROM blob",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let logical_size: i64 = connection.query_row(
+            r#"
+                SELECT COALESCE(SUM(COALESCE(blob.length, LENGTH(blob.content))), 0)
+                FROM chunk
+                         JOIN blob ON blob.id = chunk.blob"#,
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = connection.prepare(
+            r#"
+                WITH RECURSIVE
+                    blob_ref(id, size, refs) AS (
+                        SELECT blob.id, COALESCE(blob.length, LENGTH(blob.content)), COUNT(*)
+                        FROM chunk
+                                 JOIN blob ON blob.id = chunk.blob
+                        GROUP BY blob.id
+                    ),
+                    file_dedup(id, logical_size, physical_size) AS (
+                        SELECT chunk.file, SUM(blob_ref.size), SUM(CAST(blob_ref.size AS REAL) / blob_ref.refs)
+                        FROM chunk
+                                 JOIN blob_ref ON blob_ref.id = chunk.blob
+                        GROUP BY chunk.file
+                        HAVING SUM(blob_ref.refs) > COUNT(*)
+                        ORDER BY SUM(blob_ref.size) - SUM(CAST(blob_ref.size AS REAL) / blob_ref.refs) DESC
+                        LIMIT ?
+                    ),
+                    path(file, parent, path) AS (
+                        SELECT file.id, file.parent, file.name
+                        FROM file_dedup
+                                 JOIN file ON file.id = file_dedup.id
+                        UNION ALL
+                        SELECT path.file, file.parent, file.name || '/' || path.path
+                        FROM path
+                                 JOIN file ON file.id = path.parent
+                        WHERE file.parent IS NOT NULL
+                    )
+                SELECT file.id, file.tenant, '/' || path.path, file_dedup.logical_size,
+                       ROUND(file_dedup.physical_size)
+                FROM path
+                         JOIN file root ON root.id = path.parent
+                         JOIN file ON file.id = path.file
+                         JOIN file_dedup ON file_dedup.id = path.file
+                WHERE root.parent IS NULL
+                ORDER BY file_dedup.logical_size - file_dedup.physical_size DESC"#,
+        )?;
+        let files = stmt.query_map(params![limit], |row| {
+            Ok(FileDedupStats {
+                id: row.get(0)?,
+                tenant: row.get(1)?,
+                path: row.get(2)?,
+                logical_size: row.get::<_, i64>(3)? as u64,
+                physical_size: row.get::<_, f64>(4)? as u64,
+            })
+        })?;
+
+        Ok(DedupStats {
+            logical_size: logical_size as u64,
+            physical_size: physical_size as u64,
+            blobs: blobs as u64,
+            files: files.collect::<Result<_, _>>()?,
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+use rusqlite::params;
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::{FileType, Store};
+
+/// Cached content removed by [`Store::dehydrate`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Dehydration {
+    pub files: u64,
+    /// Size of the blobs removed from the cache, as stored.
+    pub size: u64,
+    pub blobs: Vec<String>,
+}
+
+impl Store<LocalTempIdGenerator> {
+    /// Removes the cached content of the file, or of all the files in the subtree of the
+    /// directory, leaving their entries, so that they can still be listed. The content is
+    /// retrieved again when the files are opened. The files in `keep` and the ones with changes
+    /// not sent to the server yet are left alone.
+    pub fn dehydrate(&self, id: &str, keep: &HashSet<String>) -> OperationResult<Dehydration> {
+        let transaction = self.transaction();
+        let mut result = Dehydration::default();
+        {
+            let connection = self.pool.writer();
+
+            let files = connection
+                .prepare(
+                    r#"
+                        WITH RECURSIVE subtree(id) AS (
+                            SELECT ?
+                            UNION
+                            SELECT file.id
+                            FROM file
+                                     JOIN subtree ON file.parent = subtree.id
+                        )
+                        SELECT file.id
+                        FROM subtree
+                                 JOIN file ON file.id = subtree.id
+                        WHERE file.file_type = ?
+                          AND file.id IN (SELECT file FROM chunk)
+                          AND file.id NOT LIKE 'temp-%'
+                          AND file.id NOT LIKE 'local-%'
+                          AND file.id NOT IN (SELECT file FROM journal)
+                          AND file.id NOT IN (SELECT file FROM write_intent)"#,
+                )?
+                .query_map(params![id, FileType::RegularFile as i64], |row| {
+                    row.get::<_, String>(0)
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for file in files.iter().filter(|x| !keep.contains(*x)) {
+                connection.execute("DELETE FROM chunk WHERE file = ?", params![file])?;
+                connection.execute(
+                    "UPDATE file SET retrieved_version = 0 WHERE id = ?",
+                    params![file],
+                )?;
+                result.files += 1;
+            }
+
+            let mut stmt = connection.prepare(
+                r#"
+                    SELECT blob.id, LENGTH(blob.content)
+                    FROM blob
+                             LEFT JOIN chunk ON blob.id = chunk.blob
+                    WHERE chunk.blob IS NULL
+                      AND blob.id NOT IN (SELECT blob FROM sidecar)"#,
+            )?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                result.blobs.push(row.get(0)?);
+                result.size += row.get::<_, i64>(1)? as u64;
+            }
+        }
+        self.run_gc()?;
+        transaction.commit()?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lru::LruCache;
+
+use crate::store::DirEntity;
+
+struct CachedDir {
+    content_version: i64,
+    files: Arc<Vec<DirEntity>>,
+}
+
+struct DirCacheInner {
+    dirs: LruCache<String, CachedDir>,
+    /// Maps the files of the cached listings to the directories listing them
+    parents: HashMap<String, String>,
+    size: usize,
+}
+
+/// In-memory LRU cache of the directory listings, bounded by the total number of the entries,
+/// counting every directory as one on top of its files.
+///
+/// A listing is only returned for the content version of the directory it has been made at, and
+/// is dropped whenever the directory or any of the files listed are modified.
+pub struct DirCache {
+    capacity: usize,
+    inner: Mutex<DirCacheInner>,
+    /// Increased on every invalidation, so that the listings read before are not cached
+    generation: AtomicU64,
+}
+
+impl DirCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(DirCacheInner {
+                dirs: LruCache::unbounded(),
+                parents: HashMap::new(),
+                size: 0,
+            }),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the files of the directory, ordered by the name.
+    pub fn get(&self, id: &str, content_version: i64) -> Option<Arc<Vec<DirEntity>>> {
+        match self.inner.lock().unwrap().dirs.get(&id.to_owned()) {
+            Some(dir) if dir.content_version == content_version => Some(dir.files.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the current generation, to be passed to [`DirCache::insert`] along with the
+    /// listing read afterwards.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Caches the listing of the directory, unless anything has been invalidated since the
+    /// given generation, as the listing might predate the modification then.
+    pub fn insert(
+        &self,
+        id: String,
+        content_version: i64,
+        generation: u64,
+        mut files: Vec<DirEntity>,
+    ) {
+        if files.len() + 1 > self.capacity {
+            return;
+        }
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut inner = self.inner.lock().unwrap();
+        if self.generation() != generation {
+            return;
+        }
+        Self::remove_dir(&mut inner, &id);
+        for file in &files {
+            inner.parents.insert(file.id.clone(), id.clone());
+        }
+        inner.size += files.len() + 1;
+        let files = Arc::new(files);
+        inner.dirs.put(
+            id,
+            CachedDir {
+                content_version,
+                files,
+            },
+        );
+
+        while inner.size > self.capacity {
+            let (evicted_id, _) = inner.dirs.peek_lru().unwrap();
+            let evicted_id = evicted_id.clone();
+            Self::remove_dir(&mut inner, &evicted_id);
+        }
+    }
+
+    /// Drops the listing of the file, if it is a directory, and the listing of its parent.
+    pub fn invalidate(&self, id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        if let Some(parent) = inner.parents.get(id).cloned() {
+            Self::remove_dir(&mut inner, &parent);
+        }
+        Self::remove_dir(&mut inner, id);
+    }
+
+    fn remove_dir(inner: &mut DirCacheInner, id: &str) {
+        if let Some(dir) = inner.dirs.pop(&id.to_owned()) {
+            inner.size -= dir.files.len() + 1;
+            for file in dir.files.iter() {
+                if inner.parents.get(&file.id).map(String::as_str) == Some(id) {
+                    inner.parents.remove(&file.id);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        inner.dirs.clear();
+        inner.parents.clear();
+        inner.size = 0;
+    }
+}
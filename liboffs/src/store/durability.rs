@@ -0,0 +1,48 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How hard SQLite tries to keep the committed transactions on the disk (`PRAGMA synchronous`).
+/// None of the levels lose data when just the process crashes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    /// Syncs the WAL on every commit, so that the committed transactions survive a power loss.
+    Full,
+    /// Syncs the WAL only when checkpointing, so the last transactions may be rolled back after
+    /// a power loss, but the database is never corrupted.
+    Normal,
+    /// Leaves syncing to the OS, so a power loss may corrupt the database.
+    Off,
+}
+
+impl Durability {
+    pub(super) fn pragma_value(self) -> &'static str {
+        match self {
+            Self::Full => "FULL",
+            Self::Normal => "NORMAL",
+            Self::Off => "OFF",
+        }
+    }
+}
+
+impl fmt::Display for Durability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full => write!(f, "full"),
+            Self::Normal => write!(f, "normal"),
+            Self::Off => write!(f, "off"),
+        }
+    }
+}
+
+impl FromStr for Durability {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "normal" => Ok(Self::Normal),
+            "off" => Ok(Self::Off),
+            _ => Err(format!("unknown durability: {}", s)),
+        }
+    }
+}
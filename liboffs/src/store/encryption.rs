@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use aes_gcm::aead::{Aead, KeyInit, Nonce, Payload};
+use aes_gcm::Aes256Gcm;
+use argon2::Argon2;
+use rand::RngCore;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::errors::{OperationError, OperationErrorType, OperationResult};
+use crate::store::id_generator::IdGenerator;
+use crate::store::Store;
+
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+/// Known value encrypted with the key, to tell a wrong passphrase from a corrupted cache.
+const CHECK_VALUE: &[u8] = b"offs cache key";
+const CHECK_AAD: &[u8] = b"check";
+/// Associated data of the journal operations; the blobs use their IDs instead.
+pub(super) const JOURNAL_AAD: &[u8] = b"journal";
+pub(super) const WRITE_INTENT_AAD: &[u8] = b"write_intent";
+
+/// Encrypts the content stored in a client cache with AES-256-GCM, using a key derived from a
+/// passphrase with Argon2. Every value gets a random nonce, stored in front of the ciphertext.
+pub(super) struct CacheCipher {
+    cipher: Aes256Gcm,
+}
+
+impl CacheCipher {
+    fn derive(passphrase: &str, salt: &[u8]) -> OperationResult<Self> {
+        let mut key = [0u8; KEY_SIZE];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| OperationError::invalid_argument(&e.to_string()))?;
+
+        Ok(Self {
+            cipher: Aes256Gcm::new_from_slice(&key).unwrap(),
+        })
+    }
+
+    /// Sets up the encryption of a new cache or unlocks an existing one. Returns `None` if the
+    /// cache is not encrypted.
+    pub(super) fn open(
+        connection: &Connection,
+        passphrase: Option<&str>,
+    ) -> OperationResult<Option<Self>> {
+        let key_row: Option<(Vec<u8>, Vec<u8>)> = connection
+            .query_row("SELECT salt, check_value FROM cache_key", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+
+        match (key_row, passphrase) {
+            (None, None) => Ok(None),
+            (Some(_), None) => Err(OperationError::unauthenticated(
+                "The cache is encrypted and no passphrase was given",
+            )),
+            (Some((salt, check_value)), Some(passphrase)) => {
+                let cipher = Self::derive(passphrase, &salt)?;
+                match cipher.decrypt(&check_value, CHECK_AAD) {
+                    Ok(value) if value == CHECK_VALUE => Ok(Some(cipher)),
+                    _ => Err(OperationError::unauthenticated(
+                        "Invalid passphrase for the cache",
+                    )),
+                }
+            }
+            (None, Some(passphrase)) => {
+                // The content stored so far would stay readable
+                let has_content = connection
+                    .prepare("SELECT 1 FROM blob UNION ALL SELECT 1 FROM journal UNION ALL SELECT 1 FROM write_intent")?
+                    .exists([])?;
+                if has_content {
+                    return Err(OperationError::invalid_argument(
+                        "the cache has been created without encryption",
+                    ));
+                }
+
+                let mut salt = [0u8; SALT_SIZE];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let cipher = Self::derive(passphrase, &salt)?;
+                connection.execute(
+                    "INSERT INTO cache_key (salt, check_value) VALUES (?, ?)",
+                    params![&salt[..], cipher.encrypt(CHECK_VALUE, CHECK_AAD)],
+                )?;
+
+                Ok(Some(cipher))
+            }
+        }
+    }
+
+    /// Encrypts the data. The associated data is not stored, but has to match on decryption,
+    /// which prevents swapping the values between the rows.
+    pub(super) fn encrypt(&self, data: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut nonce = Nonce::<Aes256Gcm>::default();
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .unwrap();
+
+        let mut result = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&ciphertext);
+        result
+    }
+
+    pub(super) fn decrypt(&self, data: &[u8], aad: &[u8]) -> OperationResult<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return Err(Self::decryption_error());
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(
+                &Nonce::<Aes256Gcm>::try_from(nonce).unwrap(),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| Self::decryption_error())
+    }
+
+    fn decryption_error() -> OperationError {
+        OperationError::new(
+            OperationErrorType::DatabaseError,
+            "Could not decrypt the cache content".to_owned(),
+        )
+    }
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    /// Encrypts the data to be stored if the cache is encrypted.
+    pub(super) fn encrypt<'a>(&self, data: &'a [u8], aad: &[u8]) -> Cow<'a, [u8]> {
+        match &self.cipher {
+            Some(cipher) => Cow::Owned(cipher.encrypt(data, aad)),
+            None => Cow::Borrowed(data),
+        }
+    }
+
+    /// Decrypts the data read from the cache if it is encrypted.
+    pub(super) fn decrypt(&self, data: Vec<u8>, aad: &[u8]) -> OperationResult<Vec<u8>> {
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(&data, aad),
+            None => Ok(data),
+        }
+    }
+}
@@ -37,6 +37,7 @@ pub struct LocalTempIdGenerator {
 }
 
 const LOCAL_PREFIX: &str = "temp-";
+const LOCAL_ONLY_PREFIX: &str = "local-";
 
 impl LocalTempIdGenerator {
     pub fn new() -> Self {
@@ -60,6 +61,28 @@ impl LocalTempIdGenerator {
     pub fn is_local_id(id: &str) -> bool {
         id.starts_with(LOCAL_PREFIX)
     }
+
+    /// Returns a new ID for a file which is kept only in the cache and never sent to the
+    /// server. Unlike the temporary IDs, these are never replaced.
+    pub fn generate_local_only_id() -> String {
+        format!(
+            "{}{}",
+            LOCAL_ONLY_PREFIX,
+            RandomHexIdGenerator::new().generate_id()
+        )
+    }
+
+    pub fn is_local_only_id(id: &str) -> bool {
+        id.starts_with(LOCAL_ONLY_PREFIX)
+    }
+
+    /// Hands back the last ID generated, so that it is generated again next time.
+    pub fn release_id(&mut self, id: &str) {
+        let n = Self::get_n(id);
+        debug_assert_eq!(n + 1, self.next_id.load(Ordering::Relaxed));
+
+        self.next_id.store(n, Ordering::Relaxed);
+    }
 }
 
 impl IdGenerator for LocalTempIdGenerator {
@@ -0,0 +1,77 @@
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::errors::{OperationError, OperationResult};
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::Store;
+use crate::ROOT_ID;
+
+/// Inode number of the root directory.
+pub const ROOT_INODE: u64 = 1;
+
+/// Inode number assigned to a file, along with its generation. The pair never identifies two
+/// different files, even if the cache is recreated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inode {
+    pub number: u64,
+    pub generation: u64,
+}
+
+impl Store<LocalTempIdGenerator> {
+    /// Returns the inode number of the file, assigning a new one if it has none yet. The numbers
+    /// stay the same across the mounts, and are not reused once the files are gone.
+    pub fn get_inode(&self, id: &str) -> OperationResult<Inode> {
+        if id == ROOT_ID {
+            return Ok(Inode {
+                number: ROOT_INODE,
+                generation: 0,
+            });
+        }
+
+        let inode = self
+            .pool
+            .reader()
+            .query_row(
+                "SELECT inode, generation FROM inode WHERE file = ?",
+                params![id],
+                Self::convert_inode,
+            )
+            .optional()?;
+        if let Some(inode) = inode {
+            return Ok(inode);
+        }
+
+        let connection = self.pool.writer();
+        connection.execute("INSERT OR IGNORE INTO inode (file) VALUES (?)", params![id])?;
+        let inode = connection.query_row(
+            "SELECT inode, generation FROM inode WHERE file = ?",
+            params![id],
+            Self::convert_inode,
+        )?;
+
+        Ok(inode)
+    }
+
+    /// Returns the ID of the file the inode number has been assigned to.
+    pub fn get_id_by_inode(&self, inode: u64) -> OperationResult<String> {
+        if inode == ROOT_INODE {
+            return Ok(ROOT_ID.to_owned());
+        }
+
+        self.pool
+            .reader()
+            .query_row(
+                "SELECT file FROM inode WHERE inode = ?",
+                params![inode as i64],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| OperationError::stale_handle(&format!("inode={}", inode)))
+    }
+
+    fn convert_inode(row: &Row) -> rusqlite::Result<Inode> {
+        Ok(Inode {
+            number: row.get::<_, i64>(0)? as u64,
+            generation: row.get::<_, i64>(1)? as u64,
+        })
+    }
+}
@@ -0,0 +1,105 @@
+use rusqlite::Connection;
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::IdGenerator;
+use crate::store::Store;
+
+/// `PRAGMA auto_vacuum` value of the incremental mode.
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// What a maintenance pass did, for the logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaintenanceResult {
+    /// Whether the whole WAL has been copied into the database and truncated; the checkpoint
+    /// does not complete while the readers use the older content.
+    pub checkpointed: bool,
+    pub freed_pages: u64,
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    /// Frees the unused pages of the database, if the incremental vacuum is enabled, and
+    /// checkpoints the WAL, so that neither of them grows indefinitely.
+    pub fn run_maintenance(&self, incremental_vacuum: bool) -> OperationResult<MaintenanceResult> {
+        let freed_pages = if incremental_vacuum {
+            self.incremental_vacuum()?
+        } else {
+            0
+        };
+
+        Ok(MaintenanceResult {
+            checkpointed: self.checkpoint()?,
+            freed_pages,
+        })
+    }
+
+    /// Copies the content of the WAL into the database and truncates it. Returns whether the
+    /// checkpoint has completed.
+    pub fn checkpoint(&self) -> OperationResult<bool> {
+        let busy: i64 =
+            self.pool
+                .writer()
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+
+        Ok(busy == 0)
+    }
+
+    /// Makes the database keep track of its unused pages, so that they can be freed with
+    /// [`Store::incremental_vacuum`]. The existing databases have to be rebuilt with VACUUM
+    /// for this, which can take a while.
+    pub fn enable_incremental_vacuum(&self) -> OperationResult<()> {
+        let connection = self.pool.writer();
+        let auto_vacuum: i64 = connection.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        if auto_vacuum == AUTO_VACUUM_INCREMENTAL {
+            return Ok(());
+        }
+
+        connection.pragma_update(None, "auto_vacuum", &"INCREMENTAL")?;
+        connection.execute_batch("VACUUM")?;
+        Self::rebuild_name_index(&connection)?;
+
+        Ok(())
+    }
+
+    /// Frees all the unused pages of the database, returning their number. Does nothing
+    /// unless [`Store::enable_incremental_vacuum`] has been called on the database.
+    pub fn incremental_vacuum(&self) -> OperationResult<u64> {
+        let connection = self.pool.writer();
+        let free_pages = |connection: &Connection| -> rusqlite::Result<i64> {
+            connection.query_row("PRAGMA freelist_count", [], |row| row.get(0))
+        };
+
+        let before = free_pages(&connection)?;
+        connection.execute_batch("PRAGMA incremental_vacuum")?;
+        let after = free_pages(&connection)?;
+
+        Ok(before.saturating_sub(after) as u64)
+    }
+
+    /// Commits a write to the database, failing if it cannot be modified, e.g. because the file
+    /// is read-only or the disk is full. Only available on the server stores.
+    pub fn check_writable(&self) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "INSERT OR REPLACE INTO health_check (id, checked_at) \
+             VALUES (0, strftime('%s', 'now'))",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    /// VACUUM may renumber the rows of the files, which the name index of the client caches
+    /// refers to.
+    fn rebuild_name_index(connection: &Connection) -> OperationResult<()> {
+        let has_name_index = connection
+            .prepare("SELECT 1 FROM sqlite_master WHERE name = 'file_name_index'")?
+            .exists([])?;
+        if has_name_index {
+            connection.execute(
+                "INSERT INTO file_name_index (file_name_index) VALUES ('rebuild')",
+                [],
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,30 +1,88 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::atomic::Ordering;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use digest::Digest;
+use bytes::Bytes;
+use rayon::prelude::*;
 use rusqlite::types::Null;
-use rusqlite::{params, params_from_iter, Connection, Row, ToSql};
-use sha2::Sha256;
+use rusqlite::{params, params_from_iter, Connection, DatabaseName, Row, ToSql};
 
+use crate::acl::AclType;
 use crate::store::id_generator::{LocalTempIdGenerator, RandomHexIdGenerator};
-use crate::{ROOT_ID, SQLITE_CACHE_SIZE, SQLITE_PAGE_SIZE};
-
+use crate::{
+    ROOT_ID, SQLITE_BUSY_TIMEOUT, SQLITE_CACHE_SIZE, SQLITE_PAGE_SIZE, SQLITE_READ_CONNECTIONS,
+};
+
+pub use self::blob_hash::BlobHash;
+pub use self::dedup::{DedupStats, FileDedupStats};
+pub use self::dehydration::Dehydration;
+pub use self::durability::Durability;
+use self::encryption::{CacheCipher, JOURNAL_AAD};
 use self::id_generator::IdGenerator;
-pub use self::types::{DirEntity, FileDev, FileMode, FileStat, FileType};
+pub use self::inode::{Inode, ROOT_INODE};
+pub use self::maintenance::MaintenanceResult;
+use self::pool::ConnectionPool;
+pub use self::quota::Quota;
+pub use self::recovery::QuarantinedJournalEntry;
+pub use self::sidecar::Sidecar;
+pub use self::sync_state::SyncState;
+pub use self::types::{DirEntity, FileDev, FileMode, FileOwner, FileStat, FileType};
+pub use self::usage::DirUsage;
+pub use self::write_intent::WriteIntent;
+
 use crate::errors::{OperationError, OperationResult};
 use crate::timespec::Timespec;
-
+use crate::version_vector::VersionVector;
+
+pub mod blob_cache;
+mod blob_hash;
+mod dedup;
+mod dehydration;
+pub mod dir_cache;
+mod durability;
+mod encryption;
 pub mod id_generator;
+mod inode;
+mod maintenance;
+mod pool;
+mod quota;
+mod recovery;
+mod selective_sync;
+mod sidecar;
+mod sync_state;
 mod types;
+mod unlinked;
+mod usage;
 pub mod wrapper;
+mod write_intent;
+
+/// Blob ID of the chunks that are holes in sparse files. Such chunks consist of zeros only and
+/// have no blob stored.
+pub const HOLE_BLOB_ID: &str = "";
+
+/// Returns the data without the zeros at its end, which are not stored nor sent along with the
+/// blobs, but only counted in their lengths.
+pub fn trim_trailing_zeros(data: &[u8]) -> &[u8] {
+    let length = data.iter().rposition(|&x| x != 0).map_or(0, |x| x + 1);
+
+    &data[..length]
+}
 
 pub struct Store<T: IdGenerator> {
-    connection: Arc<Mutex<Connection>>,
+    pool: Arc<ConnectionPool>,
     db_path: PathBuf,
 
     id_generator: T,
+    /// Whether the usage of the directories is kept up to date on every modification.
+    track_usage: bool,
+    /// Encrypts the blobs, the journal and the write intents of an encrypted client cache.
+    cipher: Option<Arc<CacheCipher>>,
+    /// Hash function the IDs of the newly added blobs are derived from.
+    blob_hash: BlobHash,
+    durability: Durability,
 }
 
 impl Store<RandomHexIdGenerator> {
@@ -35,11 +93,79 @@ impl Store<RandomHexIdGenerator> {
     }
 
     pub fn new_server(db_path: impl AsRef<std::path::Path>) -> OperationResult<Self> {
-        Ok(Self::new_with_random_id_generator(db_path)?)
+        let store = Self::new_with_random_id_generator(db_path)?;
+
+        Self::init_server(store)
+    }
+
+    /// Creates an empty server store kept in memory, which is gone once the store and all its
+    /// clones are dropped.
+    pub fn new_server_in_memory() -> OperationResult<Self> {
+        let store = Self::new(Self::in_memory_db_path(), RandomHexIdGenerator::new())?;
+
+        Self::init_server(store)
+    }
+
+    fn init_server(mut store: Self) -> OperationResult<Self> {
+        {
+            let connection = store.pool.writer();
+            let has_usage = connection
+                .prepare("SELECT 1 FROM sqlite_master WHERE name = 'dir_usage'")?
+                .exists([])?;
+            connection.execute_batch(include_str!("sql/init_server.sql"))?;
+            // Compute the usage of the files stored before it was tracked
+            if !has_usage {
+                Self::initialize_usage(&connection)?;
+            }
+        }
+        store.track_usage = true;
+
+        Ok(store)
+    }
+
+    /// Returns the result of the journal operation with given ID, if it has already been applied.
+    pub fn get_applied_operation(
+        &self,
+        tenant: &str,
+        op_id: &str,
+    ) -> OperationResult<Option<String>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection
+            .prepare("SELECT result FROM applied_operation WHERE tenant = ? AND id = ?")?;
+        let mut rows = stmt.query(params![tenant, op_id])?;
+
+        Ok(match rows.next()? {
+            Some(row) => Some(row.get(0)?),
+            None => None,
+        })
+    }
+
+    pub fn add_applied_operation(
+        &self,
+        tenant: &str,
+        op_id: &str,
+        result: &str,
+        timestamp: i64,
+    ) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "INSERT INTO applied_operation (tenant, id, result, timestamp) VALUES (?, ?, ?, ?)",
+            params![tenant, op_id, result, timestamp],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn remove_applied_operations_before(&self, timestamp: i64) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "DELETE FROM applied_operation WHERE timestamp < ?",
+            params![timestamp],
+        )?;
+
+        Ok(())
     }
 
     pub fn increment_dirent_version(&self, id: &str) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             "UPDATE file SET dirent_version = dirent_version + 1 WHERE id = ?",
             params![id],
         )?;
@@ -47,8 +173,43 @@ impl Store<RandomHexIdGenerator> {
         Ok(())
     }
 
+    /// Records a modification of the content of the file made by the given replica.
+    pub fn increment_content_vector(&self, id: &str, replica: &str) -> OperationResult<()> {
+        self.increment_version_vector("content_vector", id, replica)
+    }
+
+    /// Records a modification of the attributes or ACLs of the file made by the given replica.
+    pub fn increment_dirent_vector(&self, id: &str, replica: &str) -> OperationResult<()> {
+        self.increment_version_vector("dirent_vector", id, replica)
+    }
+
+    fn increment_version_vector(
+        &self,
+        column: &str,
+        id: &str,
+        replica: &str,
+    ) -> OperationResult<()> {
+        let connection = self.pool.writer();
+        let mut version_vector: VersionVector = connection
+            .query_row(
+                &format!("SELECT {} FROM file WHERE id = ?", column),
+                params![id],
+                |row| row.get::<_, String>(0),
+            )?
+            .parse()
+            .unwrap_or_default();
+        version_vector.increment(replica);
+
+        connection.execute(
+            &format!("UPDATE file SET {} = ? WHERE id = ?", column),
+            params![version_vector.to_string(), id],
+        )?;
+
+        Ok(())
+    }
+
     pub fn increment_content_version(&self, id: &str) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             r#"
                 UPDATE file
                 SET dirent_version  = dirent_version + 1,
@@ -68,22 +229,69 @@ impl Store<LocalTempIdGenerator> {
         Ok(Self::new(db_path, LocalTempIdGenerator::new())?)
     }
 
-    pub fn new_client(db_path: impl AsRef<std::path::Path>) -> OperationResult<Self> {
+    /// Opens the cache of a client. If a passphrase is given, the content stored in the cache is
+    /// encrypted with it; it has to be given when the cache is created.
+    pub fn new_client(
+        db_path: impl AsRef<std::path::Path>,
+        passphrase: Option<&str>,
+    ) -> OperationResult<Self> {
         let store = Self::new_with_local_temp_id_generator(db_path)?;
 
-        store
-            .connection
-            .lock()
-            .unwrap()
-            .execute_batch(include_str!("sql/init_client.sql"))?;
-        let next_id = store.get_next_temp_id()?;
+        Self::init_client(store, passphrase)
+    }
+
+    /// Creates an empty, unencrypted client cache kept in memory, which is gone once the store
+    /// and all its clones are dropped.
+    pub fn new_client_in_memory() -> OperationResult<Self> {
+        let store = Self::new(Self::in_memory_db_path(), LocalTempIdGenerator::new())?;
+
+        Self::init_client(store, None)
+    }
+
+    fn init_client(mut store: Self, passphrase: Option<&str>) -> OperationResult<Self> {
+        // A power loss can only roll back the last few changes of the cache, which is not worth
+        // syncing on every write
+        store.set_durability(Durability::Normal)?;
+        {
+            let connection = store.pool.writer();
+            let has_name_index = connection
+                .prepare("SELECT 1 FROM sqlite_master WHERE name = 'file_name_index'")?
+                .exists([])?;
+            connection.execute_batch(include_str!("sql/init_client.sql"))?;
+            // Index the files cached before the index was introduced
+            if !has_name_index {
+                connection.execute(
+                    "INSERT INTO file_name_index (file_name_index) VALUES ('rebuild')",
+                    [],
+                )?;
+            }
+            store.cipher = CacheCipher::open(&connection, passphrase)?.map(Arc::new);
+        }
+        store.remove_detached_files()?;
+        // The IDs of the removed files cannot be reused, as the journal refers to them by number
+        let created_count = store.recover_journal()?;
+        let next_id = max(store.get_next_temp_id()?, created_count);
         store.id_generator.next_id.store(next_id, Ordering::Relaxed);
 
         Ok(store)
     }
 
+    /// Returns the ID identifying this cache in the version vectors, generating it on first use.
+    pub fn get_replica_id(&self) -> OperationResult<String> {
+        let connection = self.pool.writer();
+        let mut stmt = connection.prepare("SELECT id FROM replica LIMIT 1")?;
+        if let Some(row) = stmt.query([])?.next()? {
+            return Ok(row.get(0)?);
+        }
+
+        let id = RandomHexIdGenerator::new().generate_id();
+        connection.execute("INSERT INTO replica (id) VALUES (?)", params![id])?;
+
+        Ok(id)
+    }
+
     fn get_next_temp_id(&self) -> OperationResult<usize> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection
             .prepare("SELECT id FROM file WHERE id LIKE 'temp-%' ORDER BY id DESC LIMIT 1")?;
         let mut rows = stmt.query([])?;
@@ -98,16 +306,16 @@ impl Store<LocalTempIdGenerator> {
     }
 
     pub fn get_temp_chunks(&self) -> OperationResult<Vec<String>> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare(
             r#"
                 SELECT DISTINCT blob
                 FROM file
                          JOIN chunk fb on file.id = fb.file
-                WHERE id LIKE "temp-%""#,
+                WHERE id LIKE "temp-%" AND blob != ?"#,
         )?;
 
-        let iter = stmt.query_map([], |row| Ok(row.get(0)?))?;
+        let iter = stmt.query_map(params![HOLE_BLOB_ID], |row| Ok(row.get(0)?))?;
 
         Ok(iter.map(|x| x.unwrap()).collect())
     }
@@ -119,48 +327,83 @@ impl Store<LocalTempIdGenerator> {
     }
 
     pub fn add_journal_entry(&self, id: &str, operation: &[u8]) -> OperationResult<i64> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.writer();
         let mut stmt = connection.prepare("INSERT INTO journal (file, operation) VALUES (?, ?)")?;
 
-        Ok(stmt.insert(params![id, operation])?)
+        Ok(stmt.insert(params![id, self.encrypt(operation, JOURNAL_AAD)])?)
     }
 
-    pub fn get_journal(&self) -> OperationResult<Vec<Vec<u8>>> {
-        let connection = self.connection.lock().unwrap();
-        let mut stmt = connection.prepare("SELECT operation FROM journal")?;
-        let iter = stmt.query_map([], |row| Ok(row.get(0).unwrap()))?;
+    pub fn get_journal(&self) -> OperationResult<Vec<(i64, Vec<u8>)>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT id, operation FROM journal ORDER BY id")?;
+        let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
 
-        Ok(iter.map(|x| x.unwrap()).collect())
+        iter.map(|x| {
+            let (id, operation) = x?;
+            Ok((id, self.decrypt(operation, JOURNAL_AAD)?))
+        })
+        .collect()
+    }
+
+    pub fn get_journal_size(&self) -> OperationResult<u64> {
+        let connection = self.pool.reader();
+        let size: i64 =
+            connection.query_row("SELECT COUNT(*) FROM journal", [], |row| row.get(0))?;
+
+        Ok(size as u64)
+    }
+
+    pub fn is_file_in_journal(&self, id: &str) -> OperationResult<bool> {
+        let connection = self.pool.reader();
+        let count: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM journal WHERE file = ?",
+            params![id],
+            |row| row.get(0),
+        )?;
+
+        Ok(count > 0)
     }
 
     pub fn clear_journal(&mut self) -> OperationResult<()> {
-        self.connection
-            .lock()
-            .unwrap()
-            .execute("DELETE FROM journal", [])?;
+        self.pool.writer().execute("DELETE FROM journal", [])?;
         self.id_generator.reset_generator();
 
         Ok(())
     }
 
     pub fn remove_file_from_journal(&self, id: &str) -> OperationResult<()> {
-        self.connection
-            .lock()
-            .unwrap()
+        self.pool
+            .writer()
             .execute("DELETE FROM journal WHERE file = ?", params![id])?;
 
         Ok(())
     }
 
     pub fn remove_journal_item(&self, id: i64) -> OperationResult<()> {
-        self.connection
-            .lock()
-            .unwrap()
+        self.pool
+            .writer()
             .execute("DELETE FROM journal WHERE id = ?", params![id])?;
 
         Ok(())
     }
 
+    /// Gives the file which has just been created its final ID, handing its temporary ID back,
+    /// so that the temporary IDs of the files created by the journal stay consecutive.
+    pub fn replace_temp_id(&mut self, temp_id: &str, new_id: &str) -> OperationResult<()> {
+        self.change_id(temp_id, new_id)?;
+        self.id_generator.release_id(temp_id);
+
+        Ok(())
+    }
+
+    /// Turns the file which has just been created into one kept only in the cache.
+    pub fn make_local_only(&mut self, id: &str) -> OperationResult<String> {
+        let new_id = LocalTempIdGenerator::generate_local_only_id();
+        self.replace_temp_id(id, &new_id)?;
+
+        Ok(new_id)
+    }
+
     pub fn assign_temp_id(&mut self, id: &str) -> OperationResult<String> {
         let new_id = self.id_generator.generate_id();
         self.change_id(id, &new_id)?;
@@ -169,7 +412,7 @@ impl Store<LocalTempIdGenerator> {
     }
 
     pub fn update_retrieved_version(&self, id: &str) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             "UPDATE file SET retrieved_version = content_version WHERE id = ?",
             params![id],
         )?;
@@ -177,6 +420,26 @@ impl Store<LocalTempIdGenerator> {
         Ok(())
     }
 
+    /// Mirrors the change of the entries of the directory made on the server, so that the
+    /// cached entries stay up to date if they were before.
+    pub fn increment_directory_version(&self, id: &str) -> OperationResult<()> {
+        self.pool.writer().execute(
+            r#"
+                UPDATE file
+                SET dirent_version    = dirent_version + 1,
+                    content_version   = content_version + 1,
+                    retrieved_version = CASE
+                                            WHEN retrieved_version = content_version
+                                                THEN content_version + 1
+                                            ELSE retrieved_version
+                        END
+                WHERE id = ?"#,
+            params![id],
+        )?;
+
+        Ok(())
+    }
+
     pub fn remove_remaining_files<T: IntoIterator>(
         &self,
         parent_id: &str,
@@ -186,22 +449,41 @@ impl Store<LocalTempIdGenerator> {
         T::Item: AsRef<str>,
         T::IntoIter: ExactSizeIterator,
     {
-        let iter = to_keep.into_iter();
+        self.remove_remaining_files_in_page(parent_id, "", None, to_keep)
+    }
 
-        if iter.len() == 0 {
-            return Ok(());
-        }
+    /// Like `remove_remaining_files`, but only for the files whose names sort after
+    /// `start_after` and, if given, not after `end`, i.e. the ones which could be on a page of
+    /// the listing.
+    pub fn remove_remaining_files_in_page<T: IntoIterator>(
+        &self,
+        parent_id: &str,
+        start_after: &str,
+        end: Option<&str>,
+        to_keep: T,
+    ) -> OperationResult<()>
+    where
+        T::Item: AsRef<str>,
+        T::IntoIter: ExactSizeIterator,
+    {
+        let iter = to_keep.into_iter();
 
         let args_str = itertools::join((0..iter.len()).into_iter().map(|_x| "?"), ", ");
+        let end_str = if end.is_some() { "AND name <= ?" } else { "" };
+        // `NOT IN ()` is valid in SQLite and matches every row, which empties the directory.
+        // The server does not know about the local-only files, so they are always kept.
         let query = format!(
-            "DELETE FROM file WHERE parent = ? AND id NOT IN ({})",
-            args_str
+            "DELETE FROM file WHERE parent = ? AND name > ? {} AND id NOT LIKE 'local-%' AND id \
+             NOT IN ({})",
+            end_str, args_str
         );
 
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.writer();
         let mut stmt = connection.prepare(&query)?;
-        let params =
-            std::iter::once(parent_id.to_owned()).chain(iter.map(|x| x.as_ref().to_owned()));
+        let params = std::iter::once(parent_id.to_owned())
+            .chain(std::iter::once(start_after.to_owned()))
+            .chain(end.map(|x| x.to_owned()))
+            .chain(iter.map(|x| x.as_ref().to_owned()));
 
         stmt.execute(params_from_iter(params))?;
 
@@ -212,15 +494,20 @@ impl Store<LocalTempIdGenerator> {
 impl<IdT: IdGenerator> Store<IdT> {
     pub fn new(db_path: impl AsRef<std::path::Path>, id_generator: IdT) -> OperationResult<Self> {
         let cloned_db_path = db_path.as_ref().to_owned();
-        let connection = Self::create_connection(db_path);
-
+        let durability = Durability::Full;
+        let connection = Self::create_connection(&db_path, durability);
         connection.execute_batch(include_str!("sql/init.sql"))?;
+        Self::migrate(&connection)?;
 
         let store = Self {
-            connection: Arc::new(Mutex::new(connection)),
+            pool: Arc::new(Self::create_pool(connection, &db_path, durability)),
             db_path: cloned_db_path,
 
             id_generator,
+            track_usage: false,
+            cipher: None,
+            blob_hash: BlobHash::default(),
+            durability,
         };
 
         store.run_gc()?;
@@ -228,8 +515,90 @@ impl<IdT: IdGenerator> Store<IdT> {
         Ok(store)
     }
 
-    fn create_connection(db_path: impl AsRef<std::path::Path>) -> Connection {
-        let connection = Connection::open(db_path).unwrap();
+    /// Brings the databases created by the older versions up to date.
+    fn migrate(connection: &Connection) -> OperationResult<()> {
+        const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+            ("file", "tenant", "VARCHAR(64) NOT NULL DEFAULT ''"),
+            ("file", "acl_access", "BLOB"),
+            ("file", "acl_default", "BLOB"),
+            ("file", "content_vector", "TEXT NOT NULL DEFAULT ''"),
+            ("file", "dirent_vector", "TEXT NOT NULL DEFAULT ''"),
+            ("blob", "length", "INTEGER"),
+            ("file", "crtim", "INTEGER NOT NULL DEFAULT 0"),
+            ("file", "crtimns", "INTEGER NOT NULL DEFAULT 0"),
+            ("file", "uid", "INTEGER NOT NULL DEFAULT 1000"),
+            ("file", "gid", "INTEGER NOT NULL DEFAULT 1000"),
+        ];
+
+        for (table, name, definition) in ADDED_COLUMNS {
+            let exists = connection
+                .prepare("SELECT 1 FROM pragma_table_info(?) WHERE name = ?")?
+                .exists(params![table, name])?;
+            if !exists {
+                connection.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table, name, definition
+                ))?;
+                if *name == "crtimns" {
+                    // The creation times were not kept, so the earliest of the known times is used
+                    connection.execute_batch(
+                        "UPDATE file SET crtim = mtim, crtimns = mtimns \
+                         WHERE mtim < ctim OR (mtim = ctim AND mtimns <= ctimns); \
+                         UPDATE file SET crtim = ctim, crtimns = ctimns \
+                         WHERE crtim = 0 AND crtimns = 0",
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the URI of a new in-memory database. It is shared between the connections opened
+    /// with it, so that the clones and the sessions of the store see the same files. Unlike
+    /// with a file, they see each other's uncommitted changes, and only one of them can have a
+    /// transaction open at a time.
+    fn in_memory_db_path() -> String {
+        static NEXT_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+        format!(
+            "file:offs-memory-{}-{}?mode=memory&cache=shared",
+            std::process::id(),
+            NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    fn is_in_memory(db_path: impl AsRef<std::path::Path>) -> bool {
+        db_path
+            .as_ref()
+            .to_str()
+            .is_some_and(|path| path.starts_with("file:") && path.contains("mode=memory"))
+    }
+
+    fn create_pool(
+        writer: Connection,
+        db_path: impl AsRef<std::path::Path>,
+        durability: Durability,
+    ) -> ConnectionPool {
+        // The connections to a shared in-memory database lock whole tables instead of reading
+        // from a snapshot, so all the reads go through the writer
+        let reader_count = if Self::is_in_memory(&db_path) {
+            0
+        } else {
+            SQLITE_READ_CONNECTIONS
+        };
+        let readers = (0..reader_count)
+            .map(|_| Self::create_connection(&db_path, durability))
+            .collect();
+
+        ConnectionPool::new(writer, readers)
+    }
+
+    fn create_connection(
+        db_path: impl AsRef<std::path::Path>,
+        durability: Durability,
+    ) -> Connection {
+        let connection = Connection::open(&db_path).unwrap();
 
         connection
             .pragma_update(None, "foreign_keys", &true)
@@ -243,10 +612,28 @@ impl<IdT: IdGenerator> Store<IdT> {
         connection
             .pragma_update(None, "journal_mode", &"WAL")
             .unwrap();
+        connection
+            .pragma_update(None, "synchronous", &durability.pragma_value())
+            .unwrap();
+        connection.busy_timeout(SQLITE_BUSY_TIMEOUT).unwrap();
+        // Otherwise reading a table fails right away while another connection writes to it
+        if Self::is_in_memory(&db_path) {
+            connection
+                .pragma_update(None, "read_uncommitted", &true)
+                .unwrap();
+        }
 
         connection
     }
 
+    pub fn get_db_size(&self) -> OperationResult<u64> {
+        let connection = self.pool.reader();
+        let page_count: i64 = connection.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+        let page_size: i64 = connection.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+
+        Ok((page_count * page_size) as u64)
+    }
+
     pub fn reset_id_generator(&mut self) {
         self.id_generator.reset_generator();
     }
@@ -262,52 +649,191 @@ impl<IdT: IdGenerator> Store<IdT> {
             dirent_version: row.get("dirent_version")?,
             content_version: row.get("content_version")?,
             retrieved_version: row.get("retrieved_version")?,
+            content_vector: row
+                .get::<_, String>("content_vector")?
+                .parse()
+                .unwrap_or_default(),
+            dirent_vector: row
+                .get::<_, String>("dirent_vector")?
+                .parse()
+                .unwrap_or_default(),
 
             stat: FileStat {
                 file_type: num_traits::FromPrimitive::from_i64(row.get("file_type")?).unwrap(),
                 mode: row.get("mode")?,
                 dev: row.get("dev")?,
                 nlink: 2,
-                uid: 1000,
-                gid: 1000,
+                uid: row.get("uid")?,
+                gid: row.get("gid")?,
                 size: row.get::<_, i64>("size")? as u64,
                 blocks: 1,
                 atim: Timespec::new(row.get("atim")?, row.get("atimns")?),
                 mtim: Timespec::new(row.get("mtim")?, row.get("mtimns")?),
                 ctim: Timespec::new(row.get("ctim")?, row.get("ctimns")?),
+                crtim: Timespec::new(row.get("crtim")?, row.get("crtimns")?),
             },
+
+            acl_access: row.get("acl_access")?,
+            acl_default: row.get("acl_default")?,
         })
     }
 
     pub fn list_files(&self, parent_id: &str) -> OperationResult<Vec<DirEntity>> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT * FROM file WHERE parent = ?")?;
         let iter = stmt.query_map(params![parent_id], Self::convert_file_data)?;
 
         Ok(iter.map(|x| x.unwrap()).collect())
     }
 
+    /// Returns up to `limit` files of the directory whose names sort after `start_after`,
+    /// ordered by the name, so that huge directories can be listed in pages. The limit of 0
+    /// means no limit.
+    pub fn list_files_page(
+        &self,
+        parent_id: &str,
+        start_after: &str,
+        limit: u32,
+    ) -> OperationResult<Vec<DirEntity>> {
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+
+        let connection = self.pool.reader();
+        let mut stmt = connection
+            .prepare("SELECT * FROM file WHERE parent = ? AND name > ? ORDER BY name LIMIT ?")?;
+        let iter = stmt.query_map(
+            params![parent_id, start_after, limit],
+            Self::convert_file_data,
+        )?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+
+    pub fn get_file_ids(&self) -> OperationResult<Vec<String>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT id FROM file")?;
+        let iter = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the paths of up to `limit` files whose names match the glob pattern, or contain
+    /// the pattern if it has no wildcards. Only the client caches index the names.
+    pub fn locate(&self, pattern: &str, limit: u32) -> OperationResult<Vec<String>> {
+        let pattern = if pattern.contains(&['*', '?', '['][..]) {
+            pattern.to_owned()
+        } else {
+            format!("*{}*", pattern)
+        };
+        let limit = if limit == 0 { -1 } else { limit as i64 };
+
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(
+            r#"
+                WITH RECURSIVE
+                    matched(id) AS (
+                        SELECT file.id
+                        FROM file_name_index
+                                 JOIN file ON file.rowid = file_name_index.rowid
+                        WHERE file_name_index.name GLOB ?
+                        LIMIT ?
+                    ),
+                    path(file, parent, path) AS (
+                        SELECT file.id, file.parent, file.name
+                        FROM matched
+                                 JOIN file ON file.id = matched.id
+                        UNION ALL
+                        SELECT path.file, file.parent, file.name || '/' || path.path
+                        FROM path
+                                 JOIN file ON file.id = path.parent
+                        WHERE file.parent IS NOT NULL
+                    )
+                SELECT '/' || path FROM path WHERE parent = ? ORDER BY 1"#,
+        )?;
+        let iter = stmt.query_map(params![pattern, limit, ROOT_ID], |row| row.get(0))?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+
     pub fn file_exists(&self, id: &str) -> OperationResult<bool> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT 1 FROM file WHERE id = ?")?;
 
         Ok(stmt.exists(params![id])?)
     }
 
     pub fn any_child_exists(&self, id: &str) -> OperationResult<bool> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT 1 FROM file WHERE parent = ?")?;
         Ok(stmt.exists(params![id])?)
     }
 
+    /// Returns whether the file is the given directory or is contained in it, at any depth.
+    pub fn is_descendant(&self, id: &str, ancestor_id: &str) -> OperationResult<bool> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(
+            r#"
+                WITH RECURSIVE ancestor(id) AS (
+                    SELECT ?
+                    UNION
+                    SELECT file.parent
+                    FROM file
+                             JOIN ancestor ON file.id = ancestor.id
+                    WHERE file.parent IS NOT NULL
+                )
+                SELECT 1 FROM ancestor WHERE id = ?"#,
+        )?;
+
+        Ok(stmt.exists(params![id, ancestor_id])?)
+    }
+
+    /// Returns the number of directories the file is contained in; 0 for the root directory.
+    pub fn get_depth(&self, id: &str) -> OperationResult<u64> {
+        let connection = self.pool.reader();
+        let depth: i64 = connection.query_row(
+            r#"
+                WITH RECURSIVE ancestor(id, depth) AS (
+                    SELECT parent, 0 FROM file WHERE id = ?
+                    UNION
+                    SELECT file.parent, ancestor.depth + 1
+                    FROM file
+                             JOIN ancestor ON file.id = ancestor.id
+                )
+                SELECT MAX(depth) FROM ancestor WHERE id IS NOT NULL"#,
+            params![id],
+            |row| Ok(row.get::<_, Option<i64>>(0)?.map_or(0, |x| x + 1)),
+        )?;
+
+        Ok(depth as u64)
+    }
+
+    /// Returns the number of levels of the directory tree below the file; 0 if it has no children.
+    pub fn get_subtree_height(&self, id: &str) -> OperationResult<u64> {
+        let connection = self.pool.reader();
+        let height: i64 = connection.query_row(
+            r#"
+                WITH RECURSIVE descendant(id, height) AS (
+                    SELECT ?, 0
+                    UNION
+                    SELECT file.id, descendant.height + 1
+                    FROM file
+                             JOIN descendant ON file.parent = descendant.id
+                )
+                SELECT MAX(height) FROM descendant"#,
+            params![id],
+            |row| row.get(0),
+        )?;
+
+        Ok(height as u64)
+    }
+
     pub fn file_exists_by_name(&self, parent_id: &str, name: &str) -> OperationResult<bool> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT 1 FROM file WHERE parent = ? AND name = ?")?;
         Ok(stmt.exists(params![parent_id, name])?)
     }
 
     pub fn query_file(&self, id: &str) -> OperationResult<Option<DirEntity>> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT * FROM file WHERE id = ?")?;
         let mut rows = stmt.query(params![id])?;
 
@@ -325,7 +851,7 @@ impl<IdT: IdGenerator> Store<IdT> {
         parent_id: &str,
         name: &str,
     ) -> OperationResult<Option<DirEntity>> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare("SELECT * FROM file WHERE parent = ? AND name = ?")?;
         let mut rows = stmt.query(params![parent_id, name])?;
 
@@ -339,10 +865,12 @@ impl<IdT: IdGenerator> Store<IdT> {
     }
 
     pub fn resize_file(&self, id: &str, size: u64) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        let old_usage = self.get_file_usage(id)?;
+        self.pool.writer().execute(
             "UPDATE file SET size = ? WHERE id = ?",
             params![size as i64, id],
         )?;
+        self.move_file_usage(id, old_usage)?;
 
         Ok(())
     }
@@ -377,18 +905,24 @@ impl<IdT: IdGenerator> Store<IdT> {
         )?)
     }
 
+    /// Stores the entry, or updates the one with the same ID. Fails with `ConflictedFile` if
+    /// another file in the directory has the same name.
     pub fn add_or_replace_dirent(&self, dirent: &DirEntity) -> OperationResult<()> {
-        let parent = if dirent.id == ROOT_ID {
+        // The root directories have no parent, including the ones of the tenants
+        let parent = if dirent.id == ROOT_ID || dirent.parent.is_empty() {
             &Null as &dyn ToSql
         } else {
             &dirent.parent as &dyn ToSql
         };
 
-        self.connection.lock().unwrap().execute(
-            r#"INSERT OR IGNORE INTO file (
+        let old_usage = self.get_file_usage(&dirent.id)?;
+        self.pool.writer().execute(
+            r#"INSERT INTO file (
                  id, parent, name, dirent_version, content_version,
-                 file_type, mode, dev, size, atim, atimns, mtim, mtimns, ctim, ctimns
-                 ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+                 file_type, mode, dev, size, atim, atimns, mtim, mtimns, ctim, ctimns,
+                 crtim, crtimns, acl_access, acl_default, content_vector, dirent_vector, uid, gid
+                 ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                 ON CONFLICT (id) DO NOTHING"#,
             params![
                 dirent.id,
                 parent,
@@ -405,9 +939,17 @@ impl<IdT: IdGenerator> Store<IdT> {
                 dirent.stat.mtim.nsec,
                 dirent.stat.ctim.sec,
                 dirent.stat.ctim.nsec,
+                dirent.stat.crtim.sec,
+                dirent.stat.crtim.nsec,
+                dirent.acl_access,
+                dirent.acl_default,
+                dirent.content_vector.to_string(),
+                dirent.dirent_vector.to_string(),
+                dirent.stat.uid,
+                dirent.stat.gid,
             ],
         )?;
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             r#"
                 UPDATE file
                 SET parent          = ?,
@@ -423,7 +965,15 @@ impl<IdT: IdGenerator> Store<IdT> {
                     mtim            = ?,
                     mtimns          = ?,
                     ctim            = ?,
-                    ctimns          = ?
+                    ctimns          = ?,
+                    crtim           = ?,
+                    crtimns         = ?,
+                    acl_access      = ?,
+                    acl_default     = ?,
+                    content_vector  = ?,
+                    dirent_vector   = ?,
+                    uid             = ?,
+                    gid             = ?
                 WHERE id = ?"#,
             params![
                 parent,
@@ -440,9 +990,18 @@ impl<IdT: IdGenerator> Store<IdT> {
                 dirent.stat.mtim.nsec,
                 dirent.stat.ctim.sec,
                 dirent.stat.ctim.nsec,
+                dirent.stat.crtim.sec,
+                dirent.stat.crtim.nsec,
+                dirent.acl_access,
+                dirent.acl_default,
+                dirent.content_vector.to_string(),
+                dirent.dirent_vector.to_string(),
+                dirent.stat.uid,
+                dirent.stat.gid,
                 dirent.id,
             ],
         )?;
+        self.move_file_usage(&dirent.id, old_usage)?;
 
         Ok(())
     }
@@ -488,14 +1047,17 @@ impl<IdT: IdGenerator> Store<IdT> {
             None => self.id_generator.generate_id(),
         };
 
-        self.connection.lock().unwrap().execute(
+        // The files inherit the tenant of their parent directory
+        self.pool.writer().execute(
             "INSERT INTO file (\
                  id, parent, name, dirent_version, content_version,\
-                 file_type, mode, dev, size, atim, atimns, mtim, mtimns, ctim, ctimns\
-                 ) VALUES (?, ?, ?, 1, 1, ?, ?, ?, 0, ?, ?, ?, ?, ?, ?)",
+                 file_type, mode, dev, size, atim, atimns, mtim, mtimns, ctim, ctimns, crtim,\
+                 crtimns, tenant\
+                 ) VALUES (?, ?, ?, 1, 1, ?, ?, ?, 0, ?, ?, ?, ?, ?, ?, ?, ?,\
+                 COALESCE((SELECT tenant FROM file WHERE id = ?), ''))",
             params![
                 id,
-                if id == ROOT_ID {
+                if parent_id.is_empty() {
                     &Null as &dyn ToSql
                 } else {
                     &parent_id as &dyn ToSql
@@ -510,32 +1072,132 @@ impl<IdT: IdGenerator> Store<IdT> {
                 creation_time.nsec,
                 creation_time.sec,
                 creation_time.nsec,
+                creation_time.sec,
+                creation_time.nsec,
+                parent_id,
             ],
         )?;
+        self.move_file_usage(&id, None)?;
 
         Ok(id)
     }
 
+    pub fn get_tenant(&self, id: &str) -> OperationResult<String> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT tenant FROM file WHERE id = ?")?;
+        let mut rows = stmt.query(params![id])?;
+
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Err(OperationError::file_does_not_exist(id)),
+        }
+    }
+
+    pub fn get_tenant_root(&self, tenant: &str) -> OperationResult<Option<String>> {
+        let connection = self.pool.reader();
+        let mut stmt =
+            connection.prepare("SELECT id FROM file WHERE parent IS NULL AND tenant = ?")?;
+        let mut rows = stmt.query(params![tenant])?;
+
+        let result = if let Some(row) = rows.next()? {
+            Some(row.get(0)?)
+        } else {
+            None
+        };
+
+        Ok(result)
+    }
+
+    pub fn create_tenant_root(
+        &mut self,
+        tenant: &str,
+        mode: FileMode,
+        creation_time: Timespec,
+    ) -> OperationResult<String> {
+        let id =
+            self.create_file_entity("", None, "", FileType::Directory, mode, 0, creation_time)?;
+        self.set_tenant(&id, tenant)?;
+
+        Ok(id)
+    }
+
+    /// Assigns the file to the tenant. The files created in a directory get its tenant, so only
+    /// the files added with `add_or_replace_dirent` need it set.
+    pub fn set_tenant(&self, id: &str, tenant: &str) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "UPDATE file SET tenant = ? WHERE id = ?",
+            params![tenant, id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the IDs of the directories with no parent: the root directory and the root
+    /// directories of the tenants.
+    pub fn get_root_ids(&self) -> OperationResult<Vec<String>> {
+        let connection = self.pool.reader();
+        let mut stmt =
+            connection.prepare("SELECT id FROM file WHERE parent IS NULL ORDER BY id")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the subset of given blob IDs that are referenced by the files of the tenant.
+    pub fn get_tenant_blobs<T: IntoIterator>(
+        &self,
+        tenant: &str,
+        ids: T,
+    ) -> OperationResult<HashSet<String>>
+    where
+        T::Item: AsRef<str>,
+        T::IntoIter: ExactSizeIterator,
+    {
+        let iter = ids.into_iter();
+
+        if iter.len() == 0 {
+            return Ok(HashSet::new());
+        }
+
+        // Numbered, as the IDs are used twice
+        let args_str = itertools::join((0..iter.len()).map(|x| format!("?{}", x + 2)), ", ");
+        let query = format!(
+            "SELECT chunk.blob FROM chunk JOIN file ON chunk.file = file.id \
+             WHERE file.tenant = ?1 AND chunk.blob IN ({0}) \
+             UNION SELECT sidecar.blob FROM sidecar JOIN file ON sidecar.file = file.id \
+             WHERE file.tenant = ?1 AND sidecar.blob IN ({0})",
+            args_str
+        );
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(&query)?;
+        let params = std::iter::once(tenant.to_owned()).chain(iter.map(|x| x.as_ref().to_owned()));
+        let rows = stmt.query_map(params_from_iter(params), |row| Ok(row.get(0)?))?;
+
+        Ok(rows.map(|x| x.unwrap()).collect())
+    }
+
     pub fn remove_file(&self, id: &str) -> OperationResult<()> {
-        self.connection
-            .lock()
-            .unwrap()
+        let old_usage = self.get_file_usage(id)?;
+        self.pool
+            .writer()
             .execute("DELETE FROM file WHERE id = ?", params![id])?;
+        self.move_file_usage(id, old_usage)?;
 
         Ok(())
     }
 
     pub fn remove_directory(&self, id: &str) -> OperationResult<()> {
-        self.connection
-            .lock()
-            .unwrap()
+        let old_usage = self.get_file_usage(id)?;
+        self.pool
+            .writer()
             .execute("DELETE FROM file WHERE id = ?", params![id])?;
+        self.move_file_usage(id, old_usage)?;
 
         Ok(())
     }
 
     pub fn get_chunks(&self, id: &str) -> OperationResult<Vec<String>> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt =
             connection.prepare(r#"SELECT blob FROM chunk WHERE file = ? ORDER BY "index""#)?;
 
@@ -544,7 +1206,7 @@ impl<IdT: IdGenerator> Store<IdT> {
         Ok(iter.map(|x| x.unwrap()).collect())
     }
 
-    pub fn get_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<HashMap<String, Vec<u8>>>
+    pub fn get_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<HashMap<String, Bytes>>
     where
         T::Item: AsRef<str>,
         T::IntoIter: ExactSizeIterator,
@@ -557,20 +1219,44 @@ impl<IdT: IdGenerator> Store<IdT> {
         }
 
         let args_str = itertools::join((0..iter.len()).into_iter().map(|_x| "?"), ", ");
-        let query = "SELECT * FROM blob WHERE id IN (".to_owned() + &args_str + ")";
-        let connection = self.connection.lock().unwrap();
+        let query = "SELECT id, rowid, length FROM blob WHERE id IN (".to_owned() + &args_str + ")";
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare(&query)?;
         let params = iter.map(|x| x.as_ref().to_owned());
         let mut rows = stmt.query(params_from_iter(params))?;
 
         while let Some(row) = rows.next()? {
-            map.insert(row.get(0)?, row.get(1)?);
+            let id: String = row.get(0)?;
+            let content = self.read_blob(&connection, row.get(1)?, &id, row.get(2)?)?;
+            map.insert(id, content);
         }
 
         Ok(map)
     }
 
-    pub fn get_blob(&self, id: impl AsRef<str>) -> OperationResult<Vec<u8>> {
+    /// Reads the blob content straight into its final buffer using SQLite incremental blob IO,
+    /// restoring the trailing zeros. The blobs stored without the length consist of the stored
+    /// content only.
+    fn read_blob(
+        &self,
+        connection: &Connection,
+        row_id: i64,
+        id: &str,
+        length: Option<i64>,
+    ) -> OperationResult<Bytes> {
+        let blob = connection.blob_open(DatabaseName::Main, "blob", "content", row_id, true)?;
+        let mut content = vec![0u8; blob.len()];
+        blob.read_at_exact(&mut content, 0)?;
+
+        let mut content = self.decrypt(content, id.as_bytes())?;
+        if let Some(length) = length {
+            content.resize(length as usize, 0);
+        }
+
+        Ok(Bytes::from(content))
+    }
+
+    pub fn get_blob(&self, id: impl AsRef<str>) -> OperationResult<Bytes> {
         let result = self
             .get_blobs([id.as_ref()].iter())?
             .remove(id.as_ref())
@@ -578,13 +1264,26 @@ impl<IdT: IdGenerator> Store<IdT> {
         Ok(result)
     }
 
+    pub fn get_blob_ids(&self) -> OperationResult<Vec<String>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT id FROM blob")?;
+        let iter = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+
     pub fn get_missing_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<Vec<String>>
     where
         T::Item: AsRef<str>,
         T::IntoIter: ExactSizeIterator,
     {
-        let ids_iter = ids.into_iter();
-        let ids_len = ids_iter.len();
+        // Holes have no blobs, so they can never be missing
+        let ids: Vec<String> = ids
+            .into_iter()
+            .map(|x| x.as_ref().to_owned())
+            .filter(|x| x != HOLE_BLOB_ID)
+            .collect();
+        let ids_len = ids.len();
 
         if ids_len == 0 {
             return Ok(Vec::new());
@@ -598,47 +1297,89 @@ impl<IdT: IdGenerator> Store<IdT> {
             r#"SELECT t.id FROM ({}) AS t LEFT JOIN blob ON t.id = blob.id WHERE blob.id IS NULL;"#,
             args_str
         );
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.reader();
         let mut stmt = connection.prepare(&query)?;
 
-        let params = ids_iter.map(|x| x.as_ref().to_owned());
-        let rows = stmt.query_map(params_from_iter(params), |row| Ok(row.get(0)?))?;
+        let rows = stmt.query_map(params_from_iter(ids), |row| Ok(row.get(0)?))?;
 
         Ok(rows.map(|x| x.unwrap()).collect())
     }
 
-    fn get_blob_id(data: &[u8]) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        hex::encode(hasher.finalize())
+    pub fn blob_hash(&self) -> BlobHash {
+        self.blob_hash
     }
 
-    pub fn add_blob(&self, data: &[u8]) -> OperationResult<String> {
-        let mut length = data.len();
-        while length >= 1 && data[length - 1] == 0u8 {
-            length -= 1;
-        }
-        let data = &data[..length];
+    /// Sets the hash function of the IDs of the blobs added from now on. The blobs already
+    /// stored keep their IDs.
+    pub fn set_blob_hash(&mut self, blob_hash: BlobHash) {
+        self.blob_hash = blob_hash;
+    }
 
-        let id = Self::get_blob_id(data);
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
 
-        self.connection.lock().unwrap().execute(
-            "INSERT OR IGNORE INTO blob (id, content) VALUES (?, ?)",
-            params![id, data],
-        )?;
+    /// Sets the durability of the transactions committed from now on, by this store and the
+    /// clones and sessions made of it afterwards. The read connections never write, so they
+    /// are left as they are.
+    pub fn set_durability(&mut self, durability: Durability) -> OperationResult<()> {
+        self.pool
+            .writer()
+            .pragma_update(None, "synchronous", &durability.pragma_value())?;
+        self.durability = durability;
 
-        Ok(id)
+        Ok(())
     }
 
+    pub fn add_blob(&self, data: &[u8]) -> OperationResult<String> {
+        Ok(self.add_blobs(std::iter::once(data))?.remove(0))
+    }
+
+    /// Stores the blobs, returning their IDs in order. See [`Store::add_blobs_batch`].
     pub fn add_blobs(
         &self,
-        blobs: impl IntoIterator<Item = impl AsRef<[u8]>>,
-    ) -> OperationResult<()> {
-        for blob in blobs {
-            self.add_blob(blob.as_ref())?;
-        }
+        blobs: impl IntoIterator<Item = impl AsRef<[u8]> + Sync>,
+    ) -> OperationResult<Vec<String>> {
+        let blob_hash = self.blob_hash;
+        self.add_blobs_batch(blobs.into_iter().map(|data| (blob_hash, data)))
+    }
 
-        Ok(())
+    /// Stores the blobs, each hashed with the given function (e.g. the one of the ID it has
+    /// been received under), returning their IDs in order. The blobs are hashed (and
+    /// encrypted) on all the cores before the database is locked, and then inserted with a
+    /// single statement in one transaction.
+    pub fn add_blobs_batch(
+        &self,
+        blobs: impl IntoIterator<Item = (BlobHash, impl AsRef<[u8]> + Sync)>,
+    ) -> OperationResult<Vec<String>> {
+        let blobs: Vec<_> = blobs.into_iter().collect();
+        let cipher = self.cipher.as_deref();
+        let rows: Vec<(String, Cow<[u8]>, i64)> = blobs
+            .par_iter()
+            .map(|(blob_hash, data)| {
+                let data = data.as_ref();
+                let id = blob_hash.blob_id(data);
+                let content = trim_trailing_zeros(data);
+                let content = match cipher {
+                    Some(cipher) => Cow::Owned(cipher.encrypt(content, id.as_bytes())),
+                    None => Cow::Borrowed(content),
+                };
+                (id, content, data.len() as i64)
+            })
+            .collect();
+
+        self.pool.write_batch(|connection| -> OperationResult<()> {
+            let mut stmt = connection.prepare_cached(
+                "INSERT OR IGNORE INTO blob (id, content, length) VALUES (?, ?, ?)",
+            )?;
+            for (id, content, length) in &rows {
+                stmt.execute(params![id, content, length])?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(rows.into_iter().map(|(id, _, _)| id).collect())
     }
 
     pub fn replace_chunks<T: AsRef<str>>(
@@ -646,15 +1387,33 @@ impl<IdT: IdGenerator> Store<IdT> {
         id: &str,
         chunks: impl IntoIterator<Item = (usize, T)>,
     ) -> OperationResult<()> {
-        for (index, blob_id) in chunks.into_iter() {
-            self.replace_chunk(id, index, blob_id.as_ref())?;
-        }
+        self.replace_chunks_batch(std::iter::once((id, chunks)))
+    }
 
-        Ok(())
+    /// Replaces the chunks of several files with a single statement in one transaction.
+    pub fn replace_chunks_batch<S: AsRef<str>, T: AsRef<str>, C>(
+        &self,
+        files: impl IntoIterator<Item = (S, C)>,
+    ) -> OperationResult<()>
+    where
+        C: IntoIterator<Item = (usize, T)>,
+    {
+        self.pool.write_batch(|connection| {
+            let mut stmt = connection.prepare_cached(
+                r#"INSERT OR REPLACE INTO chunk (file, blob, "index") VALUES (?, ?, ?)"#,
+            )?;
+            for (id, chunks) in files {
+                for (index, blob_id) in chunks {
+                    stmt.execute(params![id.as_ref(), blob_id.as_ref(), index as i64])?;
+                }
+            }
+
+            Ok(())
+        })
     }
 
     pub fn replace_chunk(&self, id: &str, index: usize, blob_id: &str) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             r#"INSERT OR REPLACE INTO chunk (file, blob, "index") VALUES (?, ?, ?)"#,
             params![id, blob_id, index as i64],
         )?;
@@ -663,7 +1422,7 @@ impl<IdT: IdGenerator> Store<IdT> {
     }
 
     pub fn truncate_chunks(&self, id: &str, remove_since_id: usize) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             r#"DELETE FROM chunk WHERE file = ? AND "index" >= ?"#,
             params![id, remove_since_id as i64],
         )?;
@@ -672,15 +1431,50 @@ impl<IdT: IdGenerator> Store<IdT> {
     }
 
     pub fn transaction(&self) -> Transaction {
-        Transaction::new(self.connection.clone())
+        self.try_transaction().expect("Cannot start transaction")
+    }
+
+    /// Starts a transaction, failing instead of panicking if the database stays locked by the
+    /// other connections for longer than the busy timeout.
+    pub fn try_transaction(&self) -> Result<Transaction, rusqlite::Error> {
+        Transaction::new(self.pool.clone())
+    }
+
+    /// Starts a transaction in which all the reads see the same state of the store.
+    pub fn read_transaction(&self) -> Transaction {
+        Transaction::new_read(self.pool.clone()).expect("Cannot start transaction")
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.pool.in_transaction()
+    }
+
+    /// Returns a handle to the store with a write connection of its own, so that its
+    /// transactions do not mix with the ones made through the other handles. The read
+    /// connections are shared. SQLite still applies the transactions one at a time, so the
+    /// handles wait for each other to commit.
+    pub fn session(&self) -> Self {
+        Self {
+            pool: Arc::new(
+                self.pool
+                    .with_writer(Self::create_connection(&self.db_path, self.durability)),
+            ),
+            db_path: self.db_path.clone(),
+
+            id_generator: self.id_generator.clone(),
+            track_usage: self.track_usage,
+            cipher: self.cipher.clone(),
+            blob_hash: self.blob_hash,
+            durability: self.durability,
+        }
     }
 
     pub fn set_attributes(
         &self,
         id: &str,
         mode: Option<FileMode>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         size: Option<u64>,
         atim: Option<Timespec>,
         mtim: Option<Timespec>,
@@ -695,6 +1489,15 @@ impl<IdT: IdGenerator> Store<IdT> {
             values.push(&mode_val);
         }
 
+        if let Some(uid) = &uid {
+            columns.push("uid");
+            values.push(uid);
+        }
+        if let Some(gid) = &gid {
+            columns.push("gid");
+            values.push(gid);
+        }
+
         let size_val = size.unwrap_or(Default::default()) as i64;
         if size.is_some() {
             columns.push("size");
@@ -734,25 +1537,51 @@ impl<IdT: IdGenerator> Store<IdT> {
         let args_str = itertools::join(columns.iter().map(|x| format!("{} = ?", x)), ", ");
         let query = format!("UPDATE file SET {} WHERE id = ?", args_str);
 
-        self.connection
-            .lock()
-            .unwrap()
+        let old_usage = self.get_file_usage(id)?;
+        self.pool
+            .writer()
             .execute(&query, params_from_iter(values))?;
+        self.move_file_usage(id, old_usage)?;
+
+        Ok(())
+    }
+
+    pub fn set_acl(
+        &self,
+        id: &str,
+        acl_type: AclType,
+        acl: Option<&[u8]>,
+        ctim: Timespec,
+    ) -> OperationResult<()> {
+        let column = match acl_type {
+            AclType::Access => "acl_access",
+            AclType::Default => "acl_default",
+        };
+
+        self.pool.writer().execute(
+            &format!(
+                "UPDATE file SET {} = ?, ctim = ?, ctimns = ? WHERE id = ?",
+                column
+            ),
+            params![acl, ctim.sec, ctim.nsec, id],
+        )?;
 
         Ok(())
     }
 
     pub fn rename(&self, id: &str, new_parent: &str, new_name: &str) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        let old_usage = self.get_file_usage(id)?;
+        self.pool.writer().execute(
             "UPDATE file SET parent = ?, name = ? WHERE id = ?",
             params![new_parent, new_name, id],
         )?;
+        self.move_file_usage(id, old_usage)?;
 
         Ok(())
     }
 
     pub fn change_id(&self, old_id: &str, new_id: &str) -> OperationResult<()> {
-        let connection = self.connection.lock().unwrap();
+        let connection = self.pool.writer();
         connection.execute(
             "UPDATE file SET id = ? WHERE id = ?",
             params![new_id, old_id],
@@ -766,7 +1595,7 @@ impl<IdT: IdGenerator> Store<IdT> {
     }
 
     pub fn run_gc(&self) -> OperationResult<()> {
-        self.connection.lock().unwrap().execute(
+        self.pool.writer().execute(
             r#"
                 DELETE
                 FROM blob
@@ -775,6 +1604,7 @@ impl<IdT: IdGenerator> Store<IdT> {
                     FROM blob
                              LEFT JOIN chunk ON blob.id = chunk.blob
                     WHERE chunk.blob IS NULL
+                      AND blob.id NOT IN (SELECT blob FROM sidecar)
                 )"#,
             [],
         )?;
@@ -786,46 +1616,60 @@ impl<IdT: IdGenerator> Store<IdT> {
 impl<IdT: IdGenerator> Clone for Store<IdT> {
     fn clone(&self) -> Self {
         return Self {
-            connection: Arc::new(Mutex::new(Self::create_connection(&self.db_path))),
+            pool: Arc::new(Self::create_pool(
+                Self::create_connection(&self.db_path, self.durability),
+                &self.db_path,
+                self.durability,
+            )),
             db_path: self.db_path.clone(),
 
             id_generator: self.id_generator.clone(),
+            track_usage: self.track_usage,
+            cipher: self.cipher.clone(),
+            blob_hash: self.blob_hash,
+            durability: self.durability,
         };
     }
 }
 
 pub struct Transaction {
-    connection: Arc<Mutex<Connection>>,
+    pool: Arc<ConnectionPool>,
     committed: bool,
 }
 
 impl Transaction {
-    fn new(connection: Arc<Mutex<Connection>>) -> Self {
-        connection
-            .lock()
-            .unwrap()
-            .execute("BEGIN", [])
-            .expect("Cannot start transaction");
+    fn new(pool: Arc<ConnectionPool>) -> Result<Self, rusqlite::Error> {
+        // Take the write lock right away, as the other pools' writers might otherwise commit
+        // in between, making the transaction unable to write anything
+        Self::with_statement(pool, "BEGIN IMMEDIATE")
+    }
 
-        Self {
-            connection,
+    /// Starts a transaction that only reads. It sees a single snapshot of the store without
+    /// blocking the writers of the other pools, as the store uses WAL.
+    fn new_read(pool: Arc<ConnectionPool>) -> Result<Self, rusqlite::Error> {
+        Self::with_statement(pool, "BEGIN")
+    }
+
+    fn with_statement(pool: Arc<ConnectionPool>, statement: &str) -> Result<Self, rusqlite::Error> {
+        pool.begin(statement)?;
+
+        Ok(Self {
+            pool,
             committed: false,
-        }
+        })
     }
 
     pub fn commit(mut self) -> Result<usize, rusqlite::Error> {
         self.committed = true;
-        self.connection.lock().unwrap().execute("COMMIT", [])
+        self.pool.finish("COMMIT")
     }
 }
 
 impl Drop for Transaction {
     fn drop(&mut self) {
         if !self.committed {
-            self.connection
-                .lock()
-                .unwrap()
-                .execute("ROLLBACK", [])
+            self.pool
+                .finish("ROLLBACK")
                 .expect("Could not rollback transaction");
         }
     }
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+
+/// Connections to a single database: one used for writing and several used only for reading,
+/// which, thanks to WAL, don't block each other or the writer.
+pub(super) struct ConnectionPool {
+    writer: Mutex<Connection>,
+    readers: Arc<Vec<Mutex<Connection>>>,
+    next_reader: Arc<AtomicUsize>,
+    in_transaction: AtomicBool,
+}
+
+impl ConnectionPool {
+    pub(super) fn new(writer: Connection, readers: Vec<Connection>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            readers: Arc::new(readers.into_iter().map(Mutex::new).collect()),
+            next_reader: Default::default(),
+            in_transaction: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a pool sharing the read connections with this one, but with a write connection
+    /// of its own.
+    pub(super) fn with_writer(&self, writer: Connection) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            readers: self.readers.clone(),
+            next_reader: self.next_reader.clone(),
+            in_transaction: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn writer(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap()
+    }
+
+    /// Returns the first idle read connection, or waits for one if all of them are busy. While a
+    /// transaction is open, the reads go to the writer instead so that they see its changes.
+    pub(super) fn reader(&self) -> MutexGuard<'_, Connection> {
+        if self.readers.is_empty() || self.in_transaction.load(Ordering::SeqCst) {
+            return self.writer();
+        }
+
+        let start = self.next_reader.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.readers.len() {
+            if let Ok(connection) = self.readers[(start + i) % self.readers.len()].try_lock() {
+                return connection;
+            }
+        }
+
+        self.readers[start % self.readers.len()].lock().unwrap()
+    }
+
+    pub(super) fn in_transaction(&self) -> bool {
+        self.in_transaction.load(Ordering::SeqCst)
+    }
+
+    /// Starts a transaction with the given statement: `BEGIN IMMEDIATE`, or `BEGIN` for one
+    /// that only reads.
+    pub(super) fn begin(&self, statement: &str) -> Result<(), rusqlite::Error> {
+        let writer = self.writer();
+        writer.execute(statement, [])?;
+        self.in_transaction.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Runs `f` on the write connection within a transaction of its own, so that all its
+    /// statements are committed at once, unless a transaction is already open.
+    pub(super) fn write_batch<R, E: From<rusqlite::Error>>(
+        &self,
+        f: impl FnOnce(&Connection) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let writer = self.writer();
+        if self.in_transaction() {
+            return f(&writer);
+        }
+
+        let transaction = Transaction::new_unchecked(&writer, TransactionBehavior::Immediate)?;
+        let result = f(&transaction)?;
+        transaction.commit()?;
+
+        Ok(result)
+    }
+
+    pub(super) fn finish(&self, statement: &str) -> Result<usize, rusqlite::Error> {
+        let writer = self.writer();
+        self.in_transaction.store(false, Ordering::SeqCst);
+        writer.execute(statement, [])
+    }
+}
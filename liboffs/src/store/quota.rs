@@ -0,0 +1,126 @@
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::errors::{OperationError, OperationResult};
+use crate::store::id_generator::IdGenerator;
+use crate::store::Store;
+
+/// Limits of the total size and number of the files in a directory subtree. The number of the
+/// files includes the directories. 0 means no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quota {
+    pub max_size: u64,
+    pub max_files: u64,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_size == 0 && self.max_files == 0
+    }
+
+    fn from_row(row: &Row, first_column: usize) -> rusqlite::Result<Self> {
+        Ok(Self {
+            max_size: row.get::<_, i64>(first_column)? as u64,
+            max_files: row.get::<_, i64>(first_column + 1)? as u64,
+        })
+    }
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    pub fn get_quota(&self, id: &str) -> OperationResult<Quota> {
+        let quota = self
+            .pool
+            .reader()
+            .query_row(
+                "SELECT max_size, max_files FROM quota WHERE id = ?",
+                params![id],
+                |row| Quota::from_row(row, 0),
+            )
+            .optional()?;
+
+        Ok(quota.unwrap_or_default())
+    }
+
+    /// Returns the IDs of the directories with a quota, along with the quotas.
+    pub fn get_quotas(&self) -> OperationResult<Vec<(String, Quota)>> {
+        let connection = self.pool.reader();
+        let mut stmt =
+            connection.prepare("SELECT id, max_size, max_files FROM quota ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, Quota::from_row(row, 1)?)))?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Sets the quota of the directory, removing it if unlimited.
+    pub fn set_quota(&self, id: &str, quota: Quota) -> OperationResult<()> {
+        if quota.is_unlimited() {
+            self.pool
+                .writer()
+                .execute("DELETE FROM quota WHERE id = ?", params![id])?;
+        } else {
+            self.pool.writer().execute(
+                "INSERT OR REPLACE INTO quota (id, max_size, max_files) VALUES (?, ?, ?)",
+                params![id, quota.max_size as i64, quota.max_files as i64],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if adding `size` bytes and `files` files to the directory would exceed
+    /// the quota of the directory or of any directory containing it. The quotas of the
+    /// directories containing `source_dir` as well are not checked, as moving the files from
+    /// there does not change their usage.
+    pub fn check_quota(
+        &self,
+        dir_id: &str,
+        size: u64,
+        files: u64,
+        source_dir: Option<&str>,
+    ) -> OperationResult<()> {
+        if size == 0 && files == 0 {
+            return Ok(());
+        }
+
+        let exceeded: Option<String> = self
+            .pool
+            .reader()
+            .query_row(
+                r#"
+                    WITH RECURSIVE ancestor(id) AS (
+                        SELECT ?1
+                        UNION ALL
+                        SELECT file.parent
+                        FROM file
+                                 JOIN ancestor ON file.id = ancestor.id
+                        WHERE file.parent IS NOT NULL
+                    ),
+                    source_ancestor(id) AS (
+                        SELECT ?2
+                        WHERE ?2 IS NOT NULL
+                        UNION ALL
+                        SELECT file.parent
+                        FROM file
+                                 JOIN source_ancestor ON file.id = source_ancestor.id
+                        WHERE file.parent IS NOT NULL
+                    )
+                    SELECT quota.id
+                    FROM quota
+                             JOIN dir_usage ON dir_usage.id = quota.id
+                    WHERE quota.id IN (SELECT id FROM ancestor)
+                      AND quota.id NOT IN (SELECT id FROM source_ancestor)
+                      AND ((?3 > 0 AND quota.max_size > 0
+                        AND dir_usage.size + ?3 > quota.max_size)
+                        OR (?4 > 0 AND quota.max_files > 0
+                            AND dir_usage.files + dir_usage.directories + ?4 > quota.max_files))
+                    LIMIT 1"#,
+                params![dir_id, source_dir, size as i64, files as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match exceeded {
+            Some(id) => Err(OperationError::quota_exceeded(&id)),
+            None => Ok(()),
+        }
+    }
+}
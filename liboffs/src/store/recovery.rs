@@ -0,0 +1,431 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+
+use prost::Message;
+use rusqlite::{params, Connection};
+use tracing::warn;
+
+use crate::errors::OperationResult;
+use crate::modify_op::{
+    generate_op_id, CreateFileOperation, ModifyOperation, ModifyOperationContent,
+    SetAttributesOperation,
+};
+use crate::proto::filesystem as proto_types;
+use crate::store::encryption::JOURNAL_AAD;
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::{DirEntity, Store};
+use crate::{now, ROOT_ID};
+
+pub struct QuarantinedJournalEntry {
+    pub file: String,
+    pub operation: Vec<u8>,
+    pub reason: String,
+}
+
+struct JournalEntry {
+    row_id: i64,
+    file: String,
+    raw: Vec<u8>,
+    operation: Option<ModifyOperation>,
+}
+
+fn is_create(operation: &ModifyOperation) -> bool {
+    matches!(
+        operation.operation,
+        ModifyOperationContent::CreateFileOperation(_)
+            | ModifyOperationContent::CreateSymlinkOperation(_)
+            | ModifyOperationContent::CreateDirectoryOperation(_)
+    )
+}
+
+fn is_remove(operation: &ModifyOperation) -> bool {
+    matches!(
+        operation.operation,
+        ModifyOperationContent::RemoveFileOperation(_)
+            | ModifyOperationContent::RemoveDirectoryOperation(_)
+    )
+}
+
+/// Returns the IDs of all the files the operation refers to.
+fn referenced_ids(operation: &ModifyOperation) -> Vec<&str> {
+    let mut ids = vec![operation.id.as_str()];
+    match &operation.operation {
+        ModifyOperationContent::RenameOperation(op) => ids.push(&op.new_parent),
+        ModifyOperationContent::CopyRangeOperation(op) => ids.push(&op.source_id),
+        _ => {}
+    }
+
+    ids
+}
+
+fn map_ids(operation: &mut ModifyOperation, map: &impl Fn(&str) -> String) {
+    operation.id = map(&operation.id);
+    match &mut operation.operation {
+        ModifyOperationContent::RenameOperation(op) => op.new_parent = map(&op.new_parent),
+        ModifyOperationContent::CopyRangeOperation(op) => op.source_id = map(&op.source_id),
+        _ => {}
+    }
+}
+
+fn recovered_id(n: usize) -> String {
+    format!("recovered-{:020}", n)
+}
+
+fn temp_number(id: &str) -> Option<usize> {
+    if LocalTempIdGenerator::is_local_id(id) {
+        Some(LocalTempIdGenerator::get_n(id))
+    } else {
+        None
+    }
+}
+
+/// Returns a function translating the temporary IDs according to the given renumbering.
+fn renumbering(map: &HashMap<usize, usize>) -> impl Fn(&str) -> String + '_ {
+    move |id| match temp_number(id).and_then(|n| map.get(&n)) {
+        Some(n) => LocalTempIdGenerator::get_nth_id(*n),
+        None => id.to_owned(),
+    }
+}
+
+impl Store<LocalTempIdGenerator> {
+    /// Checks the journal against the temporary files and repairs what would otherwise make
+    /// applying it fail: the temporary IDs not matching the file creation operations are
+    /// renumbered, the files whose creation is missing from the journal are recreated, and the
+    /// entries referencing files that do not exist are moved to the quarantine. Returns the
+    /// number of the files created by the journal.
+    pub(super) fn recover_journal(&self) -> OperationResult<usize> {
+        let mut entries = self.load_journal_entries()?;
+        let mut temp_files = self.load_temp_files()?;
+        let file_ids = self.load_file_ids()?;
+
+        let mut quarantined = Vec::new();
+        entries.retain(|entry| {
+            if entry.operation.is_some() {
+                return true;
+            }
+            quarantined.push((entry.row_id, "the operation cannot be decoded".to_owned()));
+            false
+        });
+
+        // The server assigns the IDs to the created files in the order of the creation
+        // operations, so the n-th one has to create the n-th temporary ID. If the IDs are not
+        // consecutive, but their number matches, they can just be renumbered.
+        let mut temp_numbers: BTreeSet<usize> = temp_files
+            .values()
+            .flat_map(|x| vec![x.id.as_str(), x.parent.as_str()])
+            .chain(
+                entries
+                    .iter()
+                    .flat_map(|x| referenced_ids(x.operation.as_ref().unwrap())),
+            )
+            .filter_map(temp_number)
+            .collect();
+        let create_count = entries
+            .iter()
+            .filter(|x| is_create(x.operation.as_ref().unwrap()))
+            .count();
+        let temp_numbers_before_alignment = temp_numbers.clone();
+        let alignment: HashMap<usize, usize> = if temp_numbers.len() == create_count {
+            temp_numbers.iter().cloned().zip(0..).collect()
+        } else {
+            HashMap::new()
+        };
+        Self::renumber(&alignment, &mut entries, &mut temp_files);
+        temp_numbers = temp_numbers
+            .into_iter()
+            .map(|x| *alignment.get(&x).unwrap_or(&x))
+            .collect();
+
+        // Temporary files can only be referenced after they have been created, and the other
+        // files have to exist, unless they are removed by the journal itself
+        let removed_ids: HashSet<String> = entries
+            .iter()
+            .map(|x| x.operation.as_ref().unwrap())
+            .filter(|x| is_remove(x))
+            .map(|x| x.id.clone())
+            .collect();
+        let mut created = Vec::new();
+        let mut created_set = HashSet::new();
+        let mut next_created = 0;
+        entries.retain(|entry| {
+            let operation = entry.operation.as_ref().unwrap();
+            let missing = referenced_ids(operation)
+                .into_iter()
+                .find(|id| match temp_number(id) {
+                    Some(n) => !created_set.contains(&n),
+                    None => *id != ROOT_ID && !file_ids.contains(*id) && !removed_ids.contains(*id),
+                });
+
+            let created_number = next_created;
+            if is_create(operation) {
+                next_created += 1;
+            }
+            if let Some(id) = missing {
+                quarantined.push((entry.row_id, format!("references missing file {}", id)));
+                return false;
+            }
+            if is_create(operation) {
+                created.push(created_number);
+                created_set.insert(created_number);
+            }
+            true
+        });
+
+        // The files left without their creation operation are recreated at the end
+        let orphans: Vec<usize> = temp_numbers
+            .iter()
+            .filter(|x| {
+                !created_set.contains(*x)
+                    && temp_files.contains_key(&LocalTempIdGenerator::get_nth_id(**x))
+            })
+            .cloned()
+            .collect();
+        let final_numbering: HashMap<usize, usize> = created
+            .iter()
+            .chain(orphans.iter())
+            .cloned()
+            .zip(0..)
+            .collect();
+        Self::renumber(&final_numbering, &mut entries, &mut temp_files);
+
+        let mut new_operations = Vec::new();
+        for n in &orphans {
+            let file = &temp_files[&LocalTempIdGenerator::get_nth_id(final_numbering[n])];
+            let parent_exists = temp_files.values().any(|x| x.id == file.parent)
+                || (temp_number(&file.parent).is_none() && file_ids.contains(&file.parent));
+            let parent = if parent_exists {
+                file.parent.clone()
+            } else {
+                ROOT_ID.to_owned()
+            };
+            warn!("Recreating file {} missing from the journal", file.id);
+
+            new_operations.push(ModifyOperation {
+                id: parent,
+                op_id: generate_op_id(),
+                timestamp: now(),
+                dirent_version: 0,
+                content_version: 0,
+                content_vector: Default::default(),
+                dirent_vector: Default::default(),
+                replica_id: String::new(),
+                operation: ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+                    name: file.name.clone(),
+                    file_type: file.stat.file_type,
+                    perm: file.stat.mode,
+                    dev: file.stat.dev,
+                    owner: Some(file.owner()),
+                }),
+            });
+            new_operations.push(ModifyOperation {
+                id: file.id.clone(),
+                op_id: generate_op_id(),
+                timestamp: now(),
+                dirent_version: file.dirent_version,
+                content_version: file.content_version,
+                content_vector: file.content_vector.clone(),
+                dirent_vector: file.dirent_vector.clone(),
+                replica_id: String::new(),
+                operation: ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+                    perm: Some(file.stat.mode),
+                    uid: Some(file.stat.uid),
+                    gid: Some(file.stat.gid),
+                    size: Some(file.stat.size),
+                    atim: Some(file.stat.atim),
+                    mtim: Some(file.stat.mtim),
+                }),
+            });
+        }
+
+        let renamed: Vec<(usize, usize)> = temp_numbers_before_alignment
+            .iter()
+            .map(|x| {
+                let aligned = *alignment.get(x).unwrap_or(x);
+                (*x, *final_numbering.get(&aligned).unwrap_or(&aligned))
+            })
+            .filter(|(old, new)| old != new)
+            .collect();
+        let created_count = final_numbering.len();
+        if quarantined.is_empty() && new_operations.is_empty() && renamed.is_empty() {
+            return Ok(created_count);
+        }
+
+        let transaction = self.transaction();
+        {
+            let connection = self.pool.writer();
+
+            for (row_id, reason) in &quarantined {
+                warn!("Quarantining journal entry {}: {}", row_id, reason);
+                Self::move_to_quarantine(&connection, *row_id, reason)?;
+            }
+
+            // Rename through intermediate IDs, so that the renumbered files do not collide
+            let renames =
+                renamed
+                    .iter()
+                    .map(|(old, new)| (LocalTempIdGenerator::get_nth_id(*old), recovered_id(*new)))
+                    .chain(renamed.iter().map(|(_, new)| {
+                        (recovered_id(*new), LocalTempIdGenerator::get_nth_id(*new))
+                    }));
+            for (old_id, new_id) in renames {
+                connection.execute(
+                    "UPDATE file SET id = ? WHERE id = ?",
+                    params![new_id, old_id],
+                )?;
+                connection.execute(
+                    "UPDATE file SET parent = ? WHERE parent = ?",
+                    params![new_id, old_id],
+                )?;
+                connection.execute(
+                    "UPDATE chunk SET file = ? WHERE file = ?",
+                    params![new_id, old_id],
+                )?;
+            }
+
+            for entry in &entries {
+                let operation = entry.operation.clone().unwrap();
+                let raw = proto_types::ModifyOperation::from(operation.clone()).encode_to_vec();
+                if raw != entry.raw || operation.id != entry.file {
+                    connection.execute(
+                        "UPDATE journal SET file = ?, operation = ? WHERE id = ?",
+                        params![operation.id, self.encrypt(&raw, JOURNAL_AAD), entry.row_id],
+                    )?;
+                }
+            }
+            for operation in new_operations {
+                let raw = proto_types::ModifyOperation::from(operation.clone()).encode_to_vec();
+                connection.execute(
+                    "INSERT INTO journal (file, operation) VALUES (?, ?)",
+                    params![operation.id, self.encrypt(&raw, JOURNAL_AAD)],
+                )?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(created_count)
+    }
+
+    /// Moves the journal entry with the given ID to the quarantine, so that it can be reviewed
+    /// instead of being applied.
+    pub fn quarantine_journal_item(&self, id: i64, reason: &str) -> OperationResult<()> {
+        warn!("Quarantining journal entry {}: {}", id, reason);
+
+        Self::move_to_quarantine(&self.pool.writer(), id, reason)
+    }
+
+    pub fn get_quarantined_journal_size(&self) -> OperationResult<u64> {
+        let connection = self.pool.reader();
+        let size: i64 =
+            connection.query_row("SELECT COUNT(*) FROM journal_quarantine", [], |row| {
+                row.get(0)
+            })?;
+
+        Ok(size as u64)
+    }
+
+    pub fn get_quarantined_journal(&self) -> OperationResult<Vec<QuarantinedJournalEntry>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection
+            .prepare("SELECT file, operation, reason FROM journal_quarantine ORDER BY id")?;
+        let iter = stmt.query_map([], |row| {
+            Ok(QuarantinedJournalEntry {
+                file: row.get(0)?,
+                operation: row.get(1)?,
+                reason: row.get(2)?,
+            })
+        })?;
+
+        iter.map(|entry| {
+            let entry = entry?;
+            Ok(QuarantinedJournalEntry {
+                operation: self.decrypt(entry.operation, JOURNAL_AAD)?,
+                ..entry
+            })
+        })
+        .collect()
+    }
+
+    fn move_to_quarantine(
+        connection: &Connection,
+        row_id: i64,
+        reason: &str,
+    ) -> OperationResult<()> {
+        connection.execute(
+            r#"
+                INSERT INTO journal_quarantine (file, operation, reason)
+                SELECT file, operation, ? FROM journal WHERE id = ?"#,
+            params![reason, row_id],
+        )?;
+        connection.execute("DELETE FROM journal WHERE id = ?", params![row_id])?;
+
+        Ok(())
+    }
+
+    fn renumber(
+        map: &HashMap<usize, usize>,
+        entries: &mut [JournalEntry],
+        temp_files: &mut HashMap<String, DirEntity>,
+    ) {
+        let map_id = renumbering(map);
+        for entry in entries {
+            map_ids(entry.operation.as_mut().unwrap(), &map_id);
+        }
+        *temp_files = temp_files
+            .drain()
+            .map(|(_, mut file)| {
+                file.id = map_id(&file.id);
+                file.parent = map_id(&file.parent);
+                (file.id.clone(), file)
+            })
+            .collect();
+    }
+
+    fn load_journal_entries(&self) -> OperationResult<Vec<JournalEntry>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT id, file, operation FROM journal ORDER BY id")?;
+        let iter = stmt.query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in iter {
+            let (row_id, file, raw) = row?;
+            let (raw, operation) = match self.decrypt(raw, JOURNAL_AAD) {
+                Ok(raw) => {
+                    let operation = proto_types::ModifyOperation::decode(raw.as_slice())
+                        .ok()
+                        .and_then(|x| x.try_into().ok());
+                    (raw, operation)
+                }
+                Err(_) => (Vec::new(), None),
+            };
+
+            entries.push(JournalEntry {
+                row_id,
+                file,
+                raw,
+                operation,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn load_temp_files(&self) -> OperationResult<HashMap<String, DirEntity>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT * FROM file WHERE id LIKE 'temp-%'")?;
+        let iter = stmt.query_map([], Self::convert_file_data)?;
+
+        Ok(iter
+            .map(|x| x.map(|file| (file.id.clone(), file)))
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn load_file_ids(&self) -> OperationResult<HashSet<String>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT id FROM file")?;
+        let iter = stmt.query_map([], |row| row.get(0))?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+}
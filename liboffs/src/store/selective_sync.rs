@@ -0,0 +1,88 @@
+use rusqlite::params;
+
+use crate::errors::{OperationError, OperationResult};
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::Store;
+use crate::ROOT_ID;
+
+impl Store<LocalTempIdGenerator> {
+    /// Excludes the directory from the synchronization, forgetting everything cached inside it,
+    /// so that it looks empty. Fails if anything inside has changes not sent to the server.
+    pub fn exclude_from_sync(&self, id: &str) -> OperationResult<()> {
+        let connection = self.pool.writer();
+        let has_changes = connection
+            .prepare(
+                r#"
+                    WITH RECURSIVE descendant(id) AS (
+                        SELECT id FROM file WHERE parent = ?
+                        UNION
+                        SELECT file.id
+                        FROM file
+                                 JOIN descendant ON file.parent = descendant.id
+                    )
+                    SELECT 1
+                    FROM descendant
+                    WHERE id LIKE 'temp-%'
+                       OR id LIKE 'local-%'
+                       OR id IN (SELECT file FROM journal)
+                       OR id IN (SELECT file FROM write_intent)"#,
+            )?
+            .exists(params![id])?;
+        if has_changes {
+            return Err(OperationError::invalid_argument(
+                "the directory contains changes not sent to the server yet",
+            ));
+        }
+
+        connection.execute(
+            "INSERT OR IGNORE INTO excluded_dir (id) VALUES (?)",
+            params![id],
+        )?;
+        connection.execute("DELETE FROM file WHERE parent = ?", params![id])?;
+        // The directory has to be listed again once included
+        connection.execute(
+            "UPDATE file SET retrieved_version = 0 WHERE id = ?",
+            params![id],
+        )?;
+        drop(connection);
+
+        self.run_gc()
+    }
+
+    pub fn include_in_sync(&self, id: &str) -> OperationResult<()> {
+        self.pool
+            .writer()
+            .execute("DELETE FROM excluded_dir WHERE id = ?", params![id])?;
+
+        Ok(())
+    }
+
+    pub fn is_excluded_from_sync(&self, id: &str) -> OperationResult<bool> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare("SELECT 1 FROM excluded_dir WHERE id = ?")?;
+
+        Ok(stmt.exists(params![id])?)
+    }
+
+    /// Returns the paths of the directories excluded from the synchronization.
+    pub fn get_excluded_from_sync(&self) -> OperationResult<Vec<String>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(
+            r#"
+                WITH RECURSIVE path(file, parent, path) AS (
+                    SELECT file.id, file.parent, file.name
+                    FROM excluded_dir
+                             JOIN file ON file.id = excluded_dir.id
+                    UNION ALL
+                    SELECT path.file, file.parent, file.name || '/' || path.path
+                    FROM path
+                             JOIN file ON file.id = path.parent
+                    WHERE file.parent IS NOT NULL
+                )
+                SELECT '/' || path FROM path WHERE parent = ? ORDER BY 1"#,
+        )?;
+        let iter = stmt.query_map(params![ROOT_ID], |row| row.get(0))?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+}
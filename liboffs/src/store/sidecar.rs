@@ -0,0 +1,92 @@
+use rusqlite::{params, OptionalExtension, Row};
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::IdGenerator;
+use crate::store::Store;
+
+/// Data derived from a file, such as a thumbnail, attached to it under a name. The data itself
+/// is stored as a single blob, retrieved only when it is needed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sidecar {
+    pub name: String,
+    pub blob: String,
+    pub size: u64,
+    /// Version of the content of the file the data was derived from
+    pub content_version: i64,
+}
+
+impl Sidecar {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            name: row.get(0)?,
+            blob: row.get(1)?,
+            size: row.get::<_, i64>(2)? as u64,
+            content_version: row.get(3)?,
+        })
+    }
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    pub fn get_sidecars(&self, id: &str) -> OperationResult<Vec<Sidecar>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(
+            "SELECT name, blob, size, content_version FROM sidecar WHERE file = ? ORDER BY name",
+        )?;
+        let rows = stmt.query_map(params![id], Sidecar::from_row)?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn get_sidecar(&self, id: &str, name: &str) -> OperationResult<Option<Sidecar>> {
+        Ok(self
+            .pool
+            .reader()
+            .query_row(
+                "SELECT name, blob, size, content_version FROM sidecar WHERE file = ? AND name = ?",
+                params![id, name],
+                Sidecar::from_row,
+            )
+            .optional()?)
+    }
+
+    /// Attaches the data to the file, replacing the one with the same name. Its blob has to be
+    /// added separately.
+    pub fn set_sidecar(&self, id: &str, sidecar: &Sidecar) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "INSERT OR REPLACE INTO sidecar (file, name, blob, size, content_version) \
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                id,
+                sidecar.name,
+                sidecar.blob,
+                sidecar.size as i64,
+                sidecar.content_version
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes the data attached to the file under the name, returning whether there was any.
+    /// The blob is left for the garbage collection.
+    pub fn remove_sidecar(&self, id: &str, name: &str) -> OperationResult<bool> {
+        let removed = self.pool.writer().execute(
+            "DELETE FROM sidecar WHERE file = ? AND name = ?",
+            params![id, name],
+        )?;
+
+        Ok(removed > 0)
+    }
+
+    /// Replaces all the data attached to the file, e.g. with the list retrieved from the server.
+    pub fn replace_sidecars(&self, id: &str, sidecars: &[Sidecar]) -> OperationResult<()> {
+        self.pool
+            .writer()
+            .execute("DELETE FROM sidecar WHERE file = ?", params![id])?;
+        for sidecar in sidecars {
+            self.set_sidecar(id, sidecar)?;
+        }
+
+        Ok(())
+    }
+}
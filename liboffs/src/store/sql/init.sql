@@ -15,6 +15,10 @@ CREATE TABLE IF NOT EXISTS file
     mode              INTEGER                 NOT NULL,
     dev               INTEGER                 NOT NULL,
     size              INTEGER                 NOT NULL,
+    -- The files created before the owners were stored, or through the frontends without
+    -- users, are owned by 1000, which all the files used to be reported as owned by
+    uid               INTEGER                 NOT NULL DEFAULT 1000,
+    gid               INTEGER                 NOT NULL DEFAULT 1000,
 
     -- Timestamps
     atim              INTEGER                 NOT NULL,
@@ -23,6 +27,20 @@ CREATE TABLE IF NOT EXISTS file
     mtimns            INTEGER                 NOT NULL,
     ctim              INTEGER                 NOT NULL,
     ctimns            INTEGER                 NOT NULL,
+    crtim             INTEGER                 NOT NULL DEFAULT 0,
+    crtimns           INTEGER                 NOT NULL DEFAULT 0,
+
+    -- Owner of the file on a multi-tenant server
+    tenant            VARCHAR(64)             NOT NULL DEFAULT '',
+
+    -- POSIX ACLs, in the format of the system.posix_acl_* extended attributes
+    acl_access        BLOB,
+    acl_default       BLOB,
+
+    -- Number of content modifications made by each replica, as "replica:count,..."
+    content_vector    TEXT                    NOT NULL DEFAULT '',
+    -- Number of modifications of the attributes and ACLs made by each replica, in the same format
+    dirent_vector     TEXT                    NOT NULL DEFAULT '',
 
     FOREIGN KEY (parent) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE
 );
@@ -32,7 +50,10 @@ CREATE UNIQUE INDEX IF NOT EXISTS idx_file_path ON file (parent, name);
 CREATE TABLE IF NOT EXISTS blob
 (
     id      VARCHAR(64) PRIMARY KEY NOT NULL,
-    content BLOB                    NOT NULL
+    -- The data without the zeros at its end, which are only counted in the length
+    content BLOB                    NOT NULL,
+    -- NULL for the blobs stored before the lengths were, which consist of the content only
+    length  INTEGER
 );
 
 CREATE TABLE IF NOT EXISTS chunk
@@ -44,3 +65,21 @@ CREATE TABLE IF NOT EXISTS chunk
     FOREIGN KEY (file) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE,
     PRIMARY KEY (file, "index")
 );
+
+CREATE INDEX IF NOT EXISTS idx_chunk_blob ON chunk (blob);
+
+-- Data derived from the files, such as thumbnails, attached to them under a name
+CREATE TABLE IF NOT EXISTS sidecar
+(
+    file            VARCHAR(64)  NOT NULL,
+    name            VARCHAR(512) NOT NULL,
+    blob            VARCHAR(64)  NOT NULL,
+    size            INTEGER      NOT NULL,
+    -- Version of the content of the file the data was derived from
+    content_version INTEGER      NOT NULL,
+
+    FOREIGN KEY (file) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY (file, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sidecar_blob ON sidecar (blob);
@@ -5,3 +5,99 @@ CREATE TABLE IF NOT EXISTS journal
 
     operation BLOB        NOT NULL
 );
+
+CREATE INDEX IF NOT EXISTS idx_journal_file ON journal (file);
+
+CREATE TABLE IF NOT EXISTS journal_quarantine
+(
+    id        INTEGER PRIMARY KEY,
+    file      VARCHAR(64) NOT NULL,
+
+    operation BLOB        NOT NULL,
+    reason    TEXT        NOT NULL
+);
+
+-- Data written to the opened files, but still buffered in the memory of the client, so that
+-- it can be written to the files if the client exits before flushing the buffers
+CREATE TABLE IF NOT EXISTS write_intent
+(
+    id       INTEGER PRIMARY KEY,
+    -- Handle of the opened file the data has been written through
+    handle   INTEGER     NOT NULL,
+    file     VARCHAR(64) NOT NULL,
+    "offset" INTEGER     NOT NULL,
+    data     BLOB        NOT NULL,
+
+    FOREIGN KEY (file) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS replica
+(
+    id VARCHAR(64) NOT NULL
+);
+
+-- Index of the file names, for searching the cache by substrings or glob patterns
+CREATE VIRTUAL TABLE IF NOT EXISTS file_name_index USING fts5
+(
+    name,
+    content = 'file',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER IF NOT EXISTS file_name_index_insert
+    AFTER INSERT
+    ON file
+BEGIN
+    INSERT INTO file_name_index (rowid, name) VALUES (new.rowid, new.name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_name_index_delete
+    AFTER DELETE
+    ON file
+BEGIN
+    INSERT INTO file_name_index (file_name_index, rowid, name) VALUES ('delete', old.rowid, old.name);
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_name_index_update
+    AFTER UPDATE OF name
+    ON file
+BEGIN
+    INSERT INTO file_name_index (file_name_index, rowid, name) VALUES ('delete', old.rowid, old.name);
+    INSERT INTO file_name_index (rowid, name) VALUES (new.rowid, new.name);
+END;
+
+-- Salt of the key the cache content is encrypted with, and a known value encrypted with the key
+-- to verify the passphrase. Empty if the cache is not encrypted.
+CREATE TABLE IF NOT EXISTS cache_key
+(
+    salt        BLOB NOT NULL,
+    check_value BLOB NOT NULL
+);
+
+-- Directories excluded from the synchronization, whose contents are neither listed nor retrieved
+-- from the server
+CREATE TABLE IF NOT EXISTS excluded_dir
+(
+    id VARCHAR(64) NOT NULL PRIMARY KEY,
+
+    FOREIGN KEY (id) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Inode numbers of the files, so that they stay the same across the mounts. The inode follows
+-- the file when its ID changes.
+CREATE TABLE IF NOT EXISTS inode
+(
+    inode      INTEGER PRIMARY KEY AUTOINCREMENT,
+    file       VARCHAR(64) NOT NULL UNIQUE,
+    -- Random, so that the inode number and generation pair of a file in a recreated cache
+    -- differs from the one of the file which used to have the number
+    generation INTEGER     NOT NULL DEFAULT (random() & 0xffffffff),
+
+    FOREIGN KEY (file) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Inode number 1 belongs to the root directory, so the assigned ones start from 2
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'inode', 1
+WHERE NOT EXISTS(SELECT 1 FROM sqlite_sequence WHERE name = 'inode');
@@ -0,0 +1,41 @@
+-- Operations applied from the journals, so that they are not applied twice if a journal is sent
+-- again after the connection has been lost
+CREATE TABLE IF NOT EXISTS applied_operation
+(
+    tenant    VARCHAR(64) NOT NULL,
+    id        VARCHAR(64) NOT NULL,
+    result    VARCHAR(64) NOT NULL,
+    timestamp INTEGER     NOT NULL,
+
+    PRIMARY KEY (tenant, id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_applied_operation_timestamp ON applied_operation (timestamp);
+
+-- Total size and number of the files in the subtree of each directory, kept up to date on every
+-- modification
+CREATE TABLE IF NOT EXISTS dir_usage
+(
+    id          VARCHAR(64) NOT NULL PRIMARY KEY REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    size        INTEGER     NOT NULL DEFAULT 0,
+    files       INTEGER     NOT NULL DEFAULT 0,
+    directories INTEGER     NOT NULL DEFAULT 0
+);
+
+-- Limits of the total size and number of the files (including the directories) in the subtree of
+-- a directory; 0 means no limit
+CREATE TABLE IF NOT EXISTS quota
+(
+    id        VARCHAR(64) NOT NULL PRIMARY KEY,
+    max_size  INTEGER     NOT NULL,
+    max_files INTEGER     NOT NULL,
+
+    FOREIGN KEY (id) REFERENCES file (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Overwritten by the readiness probe of the server, to check that the store accepts writes
+CREATE TABLE IF NOT EXISTS health_check
+(
+    id         INTEGER NOT NULL PRIMARY KEY CHECK (id = 0),
+    checked_at INTEGER NOT NULL
+);
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use rusqlite::params;
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::Store;
+use crate::ROOT_ID;
+
+/// Whether the changes of a file, or of anything inside a directory, have reached the server.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncState {
+    Synced,
+    /// There are changes not sent to the server yet
+    Pending,
+    /// The server has rejected some changes, which are quarantined
+    Conflicted,
+    /// The file is ignored and only stored locally
+    OfflineOnly,
+}
+
+impl SyncState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncState::Synced => "synced",
+            SyncState::Pending => "pending",
+            SyncState::Conflicted => "conflicted",
+            SyncState::OfflineOnly => "offline-only",
+        }
+    }
+}
+
+impl Display for SyncState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Store<LocalTempIdGenerator> {
+    /// Returns the states of the files which are not synced, by their paths (`/` being the root
+    /// directory). The directories take the state of the files inside them, the conflicts
+    /// taking precedence over the pending changes.
+    pub fn get_sync_states(&self) -> OperationResult<HashMap<String, SyncState>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection.prepare(
+            r#"
+                WITH RECURSIVE
+                    unsynced(id, state) AS (
+                        SELECT file, 2 FROM journal_quarantine
+                        UNION ALL
+                        SELECT file, 1 FROM journal
+                        UNION ALL
+                        SELECT file, 1 FROM write_intent
+                        UNION ALL
+                        SELECT id, 1 FROM file WHERE id LIKE 'temp-%'
+                    ),
+                    ancestor(id, state) AS (
+                        SELECT id, state FROM unsynced
+                        UNION
+                        SELECT file.parent, ancestor.state
+                        FROM ancestor
+                                 JOIN file ON file.id = ancestor.id
+                        WHERE file.parent IS NOT NULL
+                    ),
+                    file_state(id, state) AS (
+                        SELECT id, MAX(state) FROM ancestor GROUP BY id
+                        UNION ALL
+                        SELECT id, 3 FROM file WHERE id LIKE 'local-%'
+                    ),
+                    path(file, parent, path, state) AS (
+                        SELECT file.id, file.parent, file.name, file_state.state
+                        FROM file_state
+                                 JOIN file ON file.id = file_state.id
+                        UNION ALL
+                        SELECT path.file, file.parent, file.name || '/' || path.path, path.state
+                        FROM path
+                                 JOIN file ON file.id = path.parent
+                        WHERE file.parent IS NOT NULL
+                    )
+                SELECT CASE WHEN file = ?1 THEN '/' ELSE '/' || path END, state
+                FROM path
+                WHERE parent = ?1 OR file = ?1"#,
+        )?;
+        let iter = stmt.query_map(params![ROOT_ID], |row| {
+            let state = match row.get::<_, i64>(1)? {
+                1 => SyncState::Pending,
+                2 => SyncState::Conflicted,
+                _ => SyncState::OfflineOnly,
+            };
+            Ok((row.get(0)?, state))
+        })?;
+
+        Ok(iter.collect::<Result<_, _>>()?)
+    }
+}
@@ -1,4 +1,6 @@
+use crate::acl::AclType;
 use crate::timespec::Timespec;
+use crate::version_vector::VersionVector;
 use num_derive::{FromPrimitive, ToPrimitive};
 
 pub type FileMode = u16;
@@ -28,6 +30,8 @@ pub struct FileStat {
     pub atim: Timespec,
     pub mtim: Timespec,
     pub ctim: Timespec,
+    /// Time of creation
+    pub crtim: Timespec,
 }
 
 impl FileStat {
@@ -36,6 +40,13 @@ impl FileStat {
     }
 }
 
+/// User and group owning a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileOwner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
 #[derive(Clone, Debug)]
 pub struct DirEntity {
     pub id: String,
@@ -45,8 +56,13 @@ pub struct DirEntity {
     pub dirent_version: i64,
     pub content_version: i64,
     pub retrieved_version: i64,
+    pub content_vector: VersionVector,
+    pub dirent_vector: VersionVector,
 
     pub stat: FileStat,
+
+    pub acl_access: Option<Vec<u8>>,
+    pub acl_default: Option<Vec<u8>>,
 }
 
 impl DirEntity {
@@ -57,4 +73,18 @@ impl DirEntity {
     pub fn is_up_to_date(&self) -> bool {
         self.retrieved_version == self.content_version
     }
+
+    pub fn owner(&self) -> FileOwner {
+        FileOwner {
+            uid: self.stat.uid,
+            gid: self.stat.gid,
+        }
+    }
+
+    pub fn acl(&self, acl_type: AclType) -> Option<&[u8]> {
+        match acl_type {
+            AclType::Access => self.acl_access.as_deref(),
+            AclType::Default => self.acl_default.as_deref(),
+        }
+    }
 }
@@ -0,0 +1,66 @@
+use rusqlite::params;
+
+use crate::errors::OperationResult;
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::Store;
+use crate::ROOT_ID;
+
+impl Store<LocalTempIdGenerator> {
+    /// Copies the file which is about to be removed while opened into a new local-only file
+    /// outside of the directory tree, which keeps the content until the file is closed. Returns
+    /// the ID of the copy.
+    pub fn detach_file(&self, id: &str) -> OperationResult<String> {
+        let new_id = LocalTempIdGenerator::generate_local_only_id();
+
+        let connection = self.pool.writer();
+        connection.execute(
+            r#"
+                INSERT INTO file (id, parent, name, dirent_version, content_version,
+                                  retrieved_version, file_type, mode, dev, size, atim, atimns,
+                                  mtim, mtimns, ctim, ctimns, crtim, crtimns, tenant, acl_access,
+                                  acl_default, content_vector, dirent_vector, uid, gid)
+                SELECT ?, NULL, name, dirent_version, content_version, retrieved_version,
+                       file_type, mode, dev, size, atim, atimns, mtim, mtimns, ctim, ctimns,
+                       crtim, crtimns, tenant, acl_access, acl_default, content_vector,
+                       dirent_vector, uid, gid
+                FROM file
+                WHERE id = ?"#,
+            params![new_id, id],
+        )?;
+        connection.execute(
+            r#"INSERT INTO chunk (file, blob, "index") SELECT ?, blob, "index" FROM chunk WHERE file = ?"#,
+            params![new_id, id],
+        )?;
+
+        Ok(new_id)
+    }
+
+    /// Moves the inode number and the buffered writes of the opened file over to another file.
+    pub fn move_open_file(&self, from_id: &str, to_id: &str) -> OperationResult<()> {
+        let connection = self.pool.writer();
+        connection.execute(
+            "UPDATE inode SET file = ? WHERE file = ?",
+            params![to_id, from_id],
+        )?;
+        connection.execute(
+            "UPDATE write_intent SET file = ? WHERE file = ?",
+            params![to_id, from_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Removes the copies of the files removed while opened, left behind if the client exited
+    /// before closing them.
+    pub(super) fn remove_detached_files(&self) -> OperationResult<()> {
+        let removed = self.pool.writer().execute(
+            "DELETE FROM file WHERE parent IS NULL AND id != ?",
+            params![ROOT_ID],
+        )?;
+        if removed > 0 {
+            self.run_gc()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::errors::{OperationError, OperationResult};
+use crate::store::id_generator::IdGenerator;
+use crate::store::{FileType, Store};
+
+/// Total size and number of the files in a directory subtree, not counting the directory
+/// itself. The sizes of the directories are not included.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DirUsage {
+    pub size: u64,
+    pub files: u64,
+    pub directories: u64,
+}
+
+/// What a file adds to the usage of each directory containing it: its own size and count, plus
+/// the usage of its subtree if it is a directory.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(super) struct UsageDelta {
+    size: i64,
+    files: i64,
+    directories: i64,
+}
+
+impl UsageDelta {
+    fn minus(self, other: Self) -> Self {
+        Self {
+            size: self.size - other.size,
+            files: self.files - other.files,
+            directories: self.directories - other.directories,
+        }
+    }
+
+    fn negated(self) -> Self {
+        Self::default().minus(self)
+    }
+}
+
+impl<IdT: IdGenerator> Store<IdT> {
+    /// Computes the usage of every directory from scratch, for the databases created before it
+    /// was tracked.
+    pub(super) fn initialize_usage(connection: &Connection) -> OperationResult<()> {
+        connection.execute(
+            r#"
+                INSERT OR REPLACE INTO dir_usage (id, size, files, directories)
+                WITH RECURSIVE ancestor(id, file_type, size) AS (
+                    SELECT parent, file_type, size FROM file WHERE parent IS NOT NULL
+                    UNION ALL
+                    SELECT file.parent, ancestor.file_type, ancestor.size
+                    FROM ancestor
+                             JOIN file ON file.id = ancestor.id
+                    WHERE file.parent IS NOT NULL
+                )
+                SELECT dir.id,
+                       COALESCE(SUM(CASE WHEN ancestor.file_type = ?1 THEN 0 ELSE ancestor.size END), 0),
+                       COUNT(CASE WHEN ancestor.file_type != ?1 THEN 1 END),
+                       COUNT(CASE WHEN ancestor.file_type = ?1 THEN 1 END)
+                FROM file dir
+                         LEFT JOIN ancestor ON ancestor.id = dir.id
+                WHERE dir.file_type = ?1
+                GROUP BY dir.id"#,
+            params![FileType::Directory as i64],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_dir_usage(&self, id: &str) -> OperationResult<DirUsage> {
+        let connection = self.pool.reader();
+        let usage = connection
+            .query_row(
+                "SELECT size, files, directories FROM dir_usage WHERE id = ?",
+                params![id],
+                |row| {
+                    Ok(DirUsage {
+                        size: row.get::<_, i64>(0)? as u64,
+                        files: row.get::<_, i64>(1)? as u64,
+                        directories: row.get::<_, i64>(2)? as u64,
+                    })
+                },
+            )
+            .optional()?;
+
+        match usage {
+            Some(usage) => Ok(usage),
+            None if self.file_exists(id)? => Err(OperationError::invalid_argument(&format!(
+                "{} is not a directory",
+                id
+            ))),
+            None => Err(OperationError::file_does_not_exist(id)),
+        }
+    }
+
+    /// Returns the parent of the file and what the file adds to its usage, to be passed to
+    /// [`Store::move_file_usage`] after the file is modified. Returns `None` if the store does
+    /// not track the usage.
+    pub(super) fn get_file_usage(&self, id: &str) -> OperationResult<Option<(String, UsageDelta)>> {
+        if !self.track_usage {
+            return Ok(None);
+        }
+
+        self.get_usage_delta(id)
+    }
+
+    /// Moves what the file adds to the usage of its ancestors from the ones before the
+    /// modification to the current ones.
+    pub(super) fn move_file_usage(
+        &self,
+        id: &str,
+        old_usage: Option<(String, UsageDelta)>,
+    ) -> OperationResult<()> {
+        if !self.track_usage {
+            return Ok(());
+        }
+
+        match (old_usage, self.get_usage_delta(id)?) {
+            (Some((old_parent, old)), Some((new_parent, new))) if old_parent == new_parent => {
+                self.add_usage(&new_parent, new.minus(old))?;
+            }
+            (old, new) => {
+                if let Some((parent, old)) = old {
+                    self.add_usage(&parent, old.negated())?;
+                }
+                if let Some((parent, new)) = new {
+                    self.add_usage(&parent, new)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the parent of the file and what the file adds to its usage, or `None` if the
+    /// file does not exist or is a root directory.
+    fn get_usage_delta(&self, id: &str) -> OperationResult<Option<(String, UsageDelta)>> {
+        let connection = self.pool.writer();
+        let is_directory = FileType::Directory as i64;
+
+        let (parent, file_type, size): (Option<String>, i64, i64) = match connection
+            .query_row(
+                "SELECT parent, file_type, size FROM file WHERE id = ?",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+        {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        let delta = if file_type == is_directory {
+            connection.execute(
+                "INSERT OR IGNORE INTO dir_usage (id) VALUES (?)",
+                params![id],
+            )?;
+            let mut delta = connection.query_row(
+                "SELECT size, files, directories FROM dir_usage WHERE id = ?",
+                params![id],
+                |row| {
+                    Ok(UsageDelta {
+                        size: row.get(0)?,
+                        files: row.get(1)?,
+                        directories: row.get(2)?,
+                    })
+                },
+            )?;
+            delta.directories += 1;
+            delta
+        } else {
+            UsageDelta {
+                size,
+                files: 1,
+                directories: 0,
+            }
+        };
+
+        Ok(parent.map(|parent| (parent, delta)))
+    }
+
+    /// Adds the delta to the usage of the directory and all the directories containing it.
+    fn add_usage(&self, dir_id: &str, delta: UsageDelta) -> OperationResult<()> {
+        if delta == UsageDelta::default() {
+            return Ok(());
+        }
+
+        self.pool.writer().execute(
+            r#"
+                WITH RECURSIVE ancestor(id) AS (
+                    SELECT ?
+                    UNION ALL
+                    SELECT file.parent
+                    FROM file
+                             JOIN ancestor ON file.id = ancestor.id
+                    WHERE file.parent IS NOT NULL
+                )
+                UPDATE dir_usage
+                SET size        = size + ?,
+                    files       = files + ?,
+                    directories = directories + ?
+                WHERE id IN (SELECT id FROM ancestor)"#,
+            params![dir_id, delta.size, delta.files, delta.directories],
+        )?;
+
+        Ok(())
+    }
+}
@@ -1,10 +1,22 @@
+use std::borrow::Cow;
 use std::cmp::{max, min};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::iter;
+use std::sync::Arc;
 
+use bytes::Bytes;
+use tracing::instrument;
+
+use crate::acl::{Acl, AclType};
 use crate::errors::{OperationError, OperationResult};
+use crate::store::blob_cache::BlobCache;
+use crate::store::dir_cache::DirCache;
 use crate::store::id_generator::{IdGenerator, LocalTempIdGenerator, RandomHexIdGenerator};
-use crate::store::{DirEntity, FileDev, FileMode, FileType, Store, Transaction};
+use crate::store::{
+    BlobHash, DedupStats, Dehydration, DirEntity, DirUsage, FileDev, FileMode, FileOwner, FileType,
+    Inode, QuarantinedJournalEntry, Quota, Sidecar, Store, SyncState, Transaction, WriteIntent,
+    HOLE_BLOB_ID,
+};
 use crate::timespec::Timespec;
 use crate::BLOB_SIZE;
 
@@ -12,11 +24,31 @@ use crate::BLOB_SIZE;
 #[derive(Clone)]
 pub struct StoreWrapper<T: IdGenerator> {
     inner: Store<T>,
+    blob_cache: Option<Arc<BlobCache>>,
+    dir_cache: Option<Arc<DirCache>>,
 }
 
 impl<IdT: IdGenerator> StoreWrapper<IdT> {
     pub fn new(store: Store<IdT>) -> Self {
-        Self { inner: store }
+        Self {
+            inner: store,
+            blob_cache: None,
+            dir_cache: None,
+        }
+    }
+
+    pub fn with_blob_cache(store: Store<IdT>, blob_cache: Arc<BlobCache>) -> Self {
+        Self {
+            inner: store,
+            blob_cache: Some(blob_cache),
+            dir_cache: None,
+        }
+    }
+
+    /// Makes [`StoreWrapper::list_files`] serve the listings of the unchanged directories from
+    /// memory. All the modifications have to be made through this wrapper (or its clones) then.
+    pub fn set_dir_cache(&mut self, dir_cache: Arc<DirCache>) {
+        self.dir_cache = Some(dir_cache);
     }
 
     // Read
@@ -47,6 +79,18 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
             )))
     }
 
+    pub fn is_descendant(&self, id: &str, ancestor_id: &str) -> OperationResult<bool> {
+        Ok(self.inner.is_descendant(id, ancestor_id)?)
+    }
+
+    pub fn get_depth(&self, id: &str) -> OperationResult<u64> {
+        Ok(self.inner.get_depth(id)?)
+    }
+
+    pub fn get_subtree_height(&self, id: &str) -> OperationResult<u64> {
+        Ok(self.inner.get_subtree_height(id)?)
+    }
+
     pub fn file_exists_by_name(&self, parent_id: &str, name: &str) -> OperationResult<bool> {
         Ok(self.inner.file_exists_by_name(parent_id, name)?)
     }
@@ -55,8 +99,100 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(self.inner.any_child_exists(id)?)
     }
 
+    #[instrument(level = "trace", skip(self))]
     pub fn list_files(&self, parent_id: &str) -> OperationResult<Vec<DirEntity>> {
-        Ok(self.inner.list_files(parent_id)?)
+        let (dir_cache, content_version, generation) = match self.dir_cache_key(parent_id)? {
+            Some(key) => key,
+            None => return Ok(self.inner.list_files(parent_id)?),
+        };
+        if let Some(files) = dir_cache.get(parent_id, content_version) {
+            return Ok(files.to_vec());
+        }
+
+        let files = self.inner.list_files(parent_id)?;
+        dir_cache.insert(
+            parent_id.to_owned(),
+            content_version,
+            generation,
+            files.clone(),
+        );
+
+        Ok(files)
+    }
+
+    pub fn list_files_page(
+        &self,
+        parent_id: &str,
+        start_after: &str,
+        limit: u32,
+    ) -> OperationResult<Vec<DirEntity>> {
+        let key = self.dir_cache_key(parent_id)?;
+        if let Some((dir_cache, content_version, _)) = key {
+            if let Some(files) = dir_cache.get(parent_id, content_version) {
+                let start = files.partition_point(|x| x.name.as_str() <= start_after);
+                let end = match limit {
+                    0 => files.len(),
+                    _ => min(files.len(), start + limit as usize),
+                };
+                return Ok(files[start..end].to_vec());
+            }
+        }
+
+        let files = self.inner.list_files_page(parent_id, start_after, limit)?;
+        // The first page is the whole directory if it is shorter than the limit, while the
+        // huge directories are never held in memory as a whole
+        if let Some((dir_cache, content_version, generation)) = key {
+            if start_after.is_empty() && (limit == 0 || files.len() < limit as usize) {
+                dir_cache.insert(
+                    parent_id.to_owned(),
+                    content_version,
+                    generation,
+                    files.clone(),
+                );
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Returns the directory cache along with the content version of the directory and the
+    /// generation of the cache, if the listing of the directory can be cached. Nothing is
+    /// cached within the transactions, as they might still be rolled back.
+    fn dir_cache_key(&self, id: &str) -> OperationResult<Option<(&DirCache, i64, u64)>> {
+        let dir_cache = match &self.dir_cache {
+            Some(dir_cache) if !self.inner.in_transaction() => dir_cache,
+            _ => return Ok(None),
+        };
+        let generation = dir_cache.generation();
+
+        Ok(self
+            .inner
+            .query_file(id)?
+            .map(|dirent| (dir_cache.as_ref(), dirent.content_version, generation)))
+    }
+
+    fn invalidate_listing(&self, id: &str) {
+        if let Some(dir_cache) = &self.dir_cache {
+            dir_cache.invalidate(id);
+        }
+    }
+
+    fn clear_listings(&self) {
+        if let Some(dir_cache) = &self.dir_cache {
+            dir_cache.clear();
+        }
+    }
+
+    pub fn get_file_ids(&self) -> OperationResult<Vec<String>> {
+        Ok(self.inner.get_file_ids()?)
+    }
+
+    pub fn locate(&self, pattern: &str, limit: u32) -> OperationResult<Vec<String>> {
+        Ok(self.inner.locate(pattern, limit)?)
+    }
+
+    pub fn dedup_stats(&self, limit: u32) -> OperationResult<DedupStats> {
+        Ok(self.inner.dedup_stats(limit)?)
     }
 
     fn get_start_end_chunks(offset: i64, size: u32, chunk_num: usize) -> (usize, usize) {
@@ -73,45 +209,76 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
 
     fn get_data(
         &self,
-        chunks: &Vec<String>,
-        blobs: &HashMap<String, Vec<u8>>,
+        chunks: &[String],
+        blobs: &HashMap<String, Bytes>,
         offset: i64,
         size: u32,
-    ) -> Vec<u8> {
+    ) -> Bytes {
         if chunks.is_empty() {
-            return Vec::new();
+            return Bytes::new();
         }
 
-        let mut vec = Vec::with_capacity(size as usize);
         let start_index = (offset % BLOB_SIZE as i64) as usize;
+        let end_index = min(start_index + size as usize, BLOB_SIZE);
+        if chunks.len() == 1 && chunks[0] != HOLE_BLOB_ID && blobs[&chunks[0]].len() >= end_index {
+            // The read fits in a single blob, so it can be served without copying
+            return blobs[&chunks[0]].slice(start_index..end_index);
+        }
 
-        let first_chunk = blobs[&chunks[0]]
-            .iter()
-            .skip(start_index)
-            .take(size as usize);
-        vec.extend(first_chunk);
-
-        for i in 1..chunks.len() {
-            let chunk_length = size as usize - vec.len();
-            let chunk = &blobs[&chunks[i]];
-            let chunk_iter = chunk.iter().take(chunk_length);
-            vec.extend(chunk_iter);
-
-            let real_chunk_length = min(chunk_length, BLOB_SIZE);
-            if chunk.len() < real_chunk_length {
-                vec.extend(iter::repeat(0u8).take(real_chunk_length - chunk.len()));
-            }
+        // Holes and the parts of the chunks past the ends of their blobs have to be filled in
+        let mut vec = Vec::with_capacity(size as usize);
+        let mut chunk_offset = start_index;
+        for chunk in chunks {
+            let chunk_end = min(chunk_offset + size as usize - vec.len(), BLOB_SIZE);
+            let blob: &[u8] = if chunk == HOLE_BLOB_ID {
+                &[]
+            } else {
+                &blobs[chunk]
+            };
+
+            let data = &blob[min(chunk_offset, blob.len())..min(chunk_end, blob.len())];
+            vec.extend_from_slice(data);
+            vec.resize(vec.len() + (chunk_end - chunk_offset - data.len()), 0);
+
+            chunk_offset = 0;
         }
 
-        vec
+        Bytes::from(vec)
     }
 
-    pub fn get_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<HashMap<String, Vec<u8>>>
+    #[instrument(level = "trace", skip(self, ids))]
+    pub fn get_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<HashMap<String, Bytes>>
     where
         T::Item: AsRef<str>,
         T::IntoIter: ExactSizeIterator,
     {
-        Ok(self.inner.get_blobs(ids)?)
+        let blob_cache = match &self.blob_cache {
+            Some(blob_cache) => blob_cache,
+            None => return Ok(self.inner.get_blobs(ids)?),
+        };
+
+        let mut blobs = HashMap::new();
+        let mut missing_ids = Vec::new();
+        for id in ids {
+            let id = id.as_ref().to_owned();
+            match blob_cache.get(&id) {
+                Some(blob) => {
+                    blobs.insert(id, blob);
+                }
+                None => missing_ids.push(id),
+            }
+        }
+
+        for (id, blob) in self.inner.get_blobs(&missing_ids)? {
+            blob_cache.insert(id.clone(), blob.clone());
+            blobs.insert(id, blob);
+        }
+
+        Ok(blobs)
+    }
+
+    pub fn get_blob_ids(&self) -> OperationResult<Vec<String>> {
+        Ok(self.inner.get_blob_ids()?)
     }
 
     pub fn get_missing_blobs<T: IntoIterator>(&self, ids: T) -> OperationResult<Vec<String>>
@@ -126,14 +293,58 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(self.inner.get_chunks(id)?)
     }
 
+    pub fn get_tenant(&self, id: &str) -> OperationResult<String> {
+        Ok(self.inner.get_tenant(id)?)
+    }
+
+    pub fn get_tenant_root(&self, tenant: &str) -> OperationResult<Option<String>> {
+        Ok(self.inner.get_tenant_root(tenant)?)
+    }
+
+    pub fn set_tenant(&self, id: &str, tenant: &str) -> OperationResult<()> {
+        Ok(self.inner.set_tenant(id, tenant)?)
+    }
+
+    pub fn get_root_ids(&self) -> OperationResult<Vec<String>> {
+        Ok(self.inner.get_root_ids()?)
+    }
+
+    pub fn get_tenant_blobs<T: IntoIterator>(
+        &self,
+        tenant: &str,
+        ids: T,
+    ) -> OperationResult<HashSet<String>>
+    where
+        T::Item: AsRef<str>,
+        T::IntoIter: ExactSizeIterator,
+    {
+        Ok(self.inner.get_tenant_blobs(tenant, ids)?)
+    }
+
+    #[instrument(level = "trace", skip(self, blobs))]
     pub fn add_blobs(
         &self,
-        blobs: impl IntoIterator<Item = impl AsRef<[u8]>>,
-    ) -> OperationResult<()> {
+        blobs: impl IntoIterator<Item = impl AsRef<[u8]> + Sync>,
+    ) -> OperationResult<Vec<String>> {
         Ok(self.inner.add_blobs(blobs)?)
     }
 
-    fn get_blobs_for_read(
+    pub fn add_blobs_batch(
+        &self,
+        blobs: impl IntoIterator<Item = (BlobHash, impl AsRef<[u8]> + Sync)>,
+    ) -> OperationResult<Vec<String>> {
+        Ok(self.inner.add_blobs_batch(blobs)?)
+    }
+
+    pub fn blob_hash(&self) -> BlobHash {
+        self.inner.blob_hash()
+    }
+
+    pub fn set_blob_hash(&mut self, blob_hash: BlobHash) {
+        self.inner.set_blob_hash(blob_hash)
+    }
+
+    pub fn get_blobs_for_read(
         &mut self,
         id: &str,
         offset: i64,
@@ -161,9 +372,14 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(self.inner.get_missing_blobs(&chunks)?)
     }
 
-    pub fn read(&mut self, id: &str, offset: i64, size: u32) -> OperationResult<Vec<u8>> {
+    #[instrument(level = "trace", skip(self))]
+    pub fn read(&mut self, id: &str, offset: i64, size: u32) -> OperationResult<Bytes> {
+        // The last chunk may still hold the data past the end of a truncated file
+        let file_size = self.query_file(id)?.stat.size;
+        let size = min(size as u64, file_size.saturating_sub(offset as u64)) as u32;
+
         let chunks = self.get_blobs_for_read(id, offset, size)?;
-        let blobs = self.inner.get_blobs(chunks.iter())?;
+        let blobs = self.get_blobs(chunks.iter())?;
 
         Ok(self.get_data(&chunks, &blobs, offset, size))
     }
@@ -182,6 +398,7 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
 
         self.inner
             .set_attributes(id, None, None, None, None, atime, mtime, ctime)?;
+        self.invalidate_listing(id);
 
         Ok(())
     }
@@ -191,10 +408,26 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(self.inner.create_default_root_directory()?)
     }
 
+    pub fn create_tenant_root(
+        &mut self,
+        tenant: &str,
+        mode: FileMode,
+        creation_time: Timespec,
+    ) -> OperationResult<String> {
+        Ok(self.inner.create_tenant_root(tenant, mode, creation_time)?)
+    }
+
     pub fn add_or_replace_dirent(&self, dirent: &DirEntity) -> OperationResult<()> {
-        Ok(self.inner.add_or_replace_dirent(dirent)?)
+        self.inner.add_or_replace_dirent(dirent)?;
+        // The file might have been moved from another directory as well
+        self.invalidate_listing(&dirent.id);
+        self.invalidate_listing(&dirent.parent);
+
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(level = "trace", skip(self))]
     pub fn create_file(
         &mut self,
         parent_id: &str,
@@ -203,40 +436,109 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         file_type: FileType,
         mode: FileMode,
         dev: FileDev,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
         let id = self
             .inner
             .create_file(parent_id, name, file_type, mode, dev, timestamp)?;
+        self.set_owner(&id, owner)?;
+        if file_type != FileType::Symlink {
+            self.inherit_acl(parent_id, &id, file_type, mode, timestamp)?;
+        }
 
         self.update_time(parent_id, timestamp, false, true, true)?;
 
         Ok(id)
     }
 
+    #[instrument(level = "trace", skip(self))]
     pub fn create_directory(
         &mut self,
         parent_id: &str,
         timestamp: Timespec,
         name: &str,
         mode: FileMode,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
         let id = self
             .inner
             .create_directory(parent_id, name, mode, timestamp)?;
+        self.set_owner(&id, owner)?;
+        self.inherit_acl(parent_id, &id, FileType::Directory, mode, timestamp)?;
 
         self.update_time(parent_id, timestamp, false, true, true)?;
 
         Ok(id)
     }
 
+    /// Sets the owner of a newly created file, which otherwise keeps the default one.
+    fn set_owner(&self, id: &str, owner: Option<FileOwner>) -> OperationResult<()> {
+        if let Some(owner) = owner {
+            self.inner.set_attributes(
+                id,
+                None,
+                Some(owner.uid),
+                Some(owner.gid),
+                None,
+                None,
+                None,
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the default ACL of the parent directory to a newly created file.
+    fn inherit_acl(
+        &mut self,
+        parent_id: &str,
+        id: &str,
+        file_type: FileType,
+        mode: FileMode,
+        timestamp: Timespec,
+    ) -> OperationResult<()> {
+        let default_acl = match self.query_file(parent_id)?.acl_default {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        let mut acl = Acl::parse(&default_acl)?;
+        acl.restrict_to_mode(mode);
+        let new_mode = (mode & !0o777) | acl.to_mode();
+        self.inner
+            .set_attributes(id, Some(new_mode), None, None, None, None, None, None)?;
+
+        if !acl.is_minimal() {
+            self.inner
+                .set_acl(id, AclType::Access, Some(&acl.to_bytes()), timestamp)?;
+        }
+        if file_type == FileType::Directory {
+            self.inner
+                .set_acl(id, AclType::Default, Some(&default_acl), timestamp)?;
+        }
+
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
     pub fn create_symlink(
         &mut self,
         parent_id: &str,
         timestamp: Timespec,
         name: &str,
         link: &str,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
-        let id = self.create_file(parent_id, timestamp, name, FileType::Symlink, 0o777, 0)?;
+        let id = self.create_file(
+            parent_id,
+            timestamp,
+            name,
+            FileType::Symlink,
+            0o777,
+            0,
+            owner,
+        )?;
 
         self.write(&id, timestamp, 0, link.as_bytes())?;
 
@@ -244,6 +546,7 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
     }
 
     // Remove
+    #[instrument(level = "trace", skip(self))]
     pub fn remove_file(&mut self, id: &str, timestamp: Timespec) -> OperationResult<()> {
         let dirent = self.query_file(id)?;
 
@@ -254,6 +557,7 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(())
     }
 
+    #[instrument(level = "trace", skip(self))]
     pub fn remove_directory(&mut self, id: &str, timestamp: Timespec) -> OperationResult<()> {
         let dirent = self.query_file(id)?;
 
@@ -266,9 +570,13 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
 
     // Modify
     pub fn change_id(&self, old_id: &str, new_id: &str) -> OperationResult<()> {
-        Ok(self.inner.change_id(old_id, new_id)?)
+        self.inner.change_id(old_id, new_id)?;
+        self.invalidate_listing(old_id);
+
+        Ok(())
     }
 
+    #[instrument(level = "trace", skip(self))]
     pub fn rename(
         &mut self,
         id: &str,
@@ -287,18 +595,21 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(())
     }
 
+    /// Adjusts the chunks of the file to its new size. Truncating the file only updates the
+    /// metadata, leaving the rest of the new last chunk in its blob; see
+    /// [`StoreWrapper::clear_past_end`].
+    #[instrument(level = "trace", skip(self))]
     pub fn resize_file(&mut self, id: &str, new_size: u64) -> OperationResult<()> {
-        let dirent = self.query_file(id)?;
+        let old_size = self.query_file(id)?.stat.size;
+        if new_size > old_size {
+            self.clear_past_end(id, old_size)?;
+        }
 
-        let old_size = dirent.stat.size;
         let old_chunk_count = (old_size as usize + BLOB_SIZE - 1) / BLOB_SIZE;
         let new_chunk_count = (new_size as usize + BLOB_SIZE - 1) / BLOB_SIZE;
-        let chunks = self.inner.get_chunks(id)?;
 
-        // Adjust the chunks
         if new_chunk_count > old_chunk_count {
-            let zero_blob = self.inner.add_blob(&[])?;
-            let iter = std::iter::repeat(&zero_blob)
+            let iter = std::iter::repeat(HOLE_BLOB_ID)
                 .enumerate()
                 .skip(old_chunk_count)
                 .take(new_chunk_count - old_chunk_count);
@@ -307,25 +618,35 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
             self.inner.truncate_chunks(id, new_chunk_count)?;
         }
 
-        // Adjust the last chunk
-        let last_chunk_size = (new_size % BLOB_SIZE as u64) as usize;
-
-        if last_chunk_size != 0 && new_size < old_size {
-            let last_chunk_index = new_chunk_count - 1;
+        Ok(())
+    }
 
-            let mut last_chunk = self.inner.get_blob(&chunks[last_chunk_index])?;
-            if last_chunk_size < last_chunk.len() {
-                last_chunk.resize(last_chunk_size, 0);
+    /// Cuts the blob of the last chunk of the file at the end of the file, as the truncation
+    /// leaves the data past it in place. Has to be called before the file grows, so that the
+    /// data does not show up again.
+    fn clear_past_end(&mut self, id: &str, size: u64) -> OperationResult<()> {
+        let end = (size % BLOB_SIZE as u64) as usize;
+        let index = (size / BLOB_SIZE as u64) as usize;
+        if end == 0 {
+            return Ok(());
+        }
 
-                let last_chunk_blob = self.inner.add_blob(&last_chunk)?;
-                self.inner
-                    .replace_chunk(id, last_chunk_index, &last_chunk_blob)?;
+        let chunks = self.inner.get_chunks(id)?;
+        match chunks.get(index) {
+            Some(blob_id) if blob_id != HOLE_BLOB_ID => {
+                let blob = self.inner.get_blob(blob_id)?;
+                if blob.len() > end {
+                    let new_blob_id = self.add_chunk_blob(&blob[..end])?;
+                    self.inner.replace_chunk(id, index, &new_blob_id)?;
+                }
             }
+            _ => {}
         }
 
         Ok(())
     }
 
+    #[instrument(level = "trace", skip(self))]
     pub fn set_attributes(
         &mut self,
         id: &str,
@@ -337,23 +658,132 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         atim: Option<Timespec>,
         mut mtim: Option<Timespec>,
     ) -> OperationResult<()> {
+        // Setting the modification time explicitly is a change of the metadata as well, unlike
+        // the access time, which is also updated on reads
+        let changes_metadata =
+            mode.is_some() || uid.is_some() || gid.is_some() || size.is_some() || mtim.is_some();
+
         if let Some(size_val) = size {
             self.resize_file(id, size_val)?;
             mtim = Some(timestamp);
         }
 
-        let ctim = if mode.is_some() || uid.is_some() || gid.is_some() || size.is_some() {
+        let ctim = if changes_metadata {
             Some(timestamp)
         } else {
             None
         };
 
+        // chmod changes the corresponding entries of the access ACL
+        if let Some(mode_val) = mode {
+            if let Some(acl_access) = self.query_file(id)?.acl_access {
+                let mut acl = Acl::parse(&acl_access)?;
+                acl.set_mode(mode_val);
+                self.inner
+                    .set_acl(id, AclType::Access, Some(&acl.to_bytes()), timestamp)?;
+            }
+        }
+
         self.inner
             .set_attributes(id, mode, uid, gid, size, atim, mtim, ctim)?;
+        self.invalidate_listing(id);
 
         Ok(())
     }
 
+    /// Sets or, if `acl` is empty, removes the ACL of the file. Setting the access ACL updates
+    /// the permission bits of the file mode accordingly.
+    #[instrument(level = "trace", skip(self, acl))]
+    pub fn set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        acl_type: AclType,
+        acl: &[u8],
+    ) -> OperationResult<()> {
+        if acl.is_empty() {
+            self.inner.set_acl(id, acl_type, None, timestamp)?;
+            self.invalidate_listing(id);
+            return Ok(());
+        }
+
+        let dirent = self.query_file(id)?;
+        let acl = Acl::parse(acl)?;
+
+        match acl_type {
+            AclType::Access => {
+                let mode = (dirent.stat.mode & !0o777) | acl.to_mode();
+                self.inner
+                    .set_attributes(id, Some(mode), None, None, None, None, None, None)?;
+
+                // ACLs equivalent to the file mode are not stored, just like on other file systems
+                let value = if acl.is_minimal() {
+                    None
+                } else {
+                    Some(acl.to_bytes())
+                };
+                self.inner
+                    .set_acl(id, acl_type, value.as_deref(), timestamp)?;
+            }
+            AclType::Default => {
+                if dirent.stat.file_type != FileType::Directory {
+                    return Err(OperationError::invalid_acl(
+                        "default ACL can only be set on a directory",
+                    ));
+                }
+                self.inner
+                    .set_acl(id, acl_type, Some(&acl.to_bytes()), timestamp)?;
+            }
+        }
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    /// Returns the blobs of the chunks written only partially by a write of `length` bytes at
+    /// `offset`, which are needed to write them, along with the last one if the write extends
+    /// the file of the given size. The fully covered chunks are replaced without reading their
+    /// content.
+    fn get_partially_written_blobs(
+        chunks: &[String],
+        size: u64,
+        offset: u64,
+        length: u64,
+    ) -> Vec<&str> {
+        let end = offset + length;
+        let edges = if end > size {
+            vec![offset, end, size]
+        } else {
+            vec![offset, end]
+        };
+
+        edges
+            .into_iter()
+            .filter(|&x| x % BLOB_SIZE as u64 != 0)
+            .filter_map(|x| chunks.get((x / BLOB_SIZE as u64) as usize))
+            .map(String::as_str)
+            .filter(|&x| x != HOLE_BLOB_ID)
+            .collect()
+    }
+
+    /// Returns the blobs missing in the store that are needed to write `length` bytes at
+    /// `offset`. Resizing the file is the same as writing nothing at its new end.
+    pub fn get_missing_blobs_for_write(
+        &mut self,
+        id: &str,
+        offset: u64,
+        length: u64,
+    ) -> OperationResult<Vec<String>> {
+        let size = self.query_file(id)?.stat.size;
+        let chunks = self.inner.get_chunks(id)?;
+        Ok(self
+            .inner
+            .get_missing_blobs(Self::get_partially_written_blobs(
+                &chunks, size, offset, length,
+            ))?)
+    }
+
+    #[instrument(level = "trace", skip(self, data), fields(size = data.len()))]
     pub fn write(
         &mut self,
         id: &str,
@@ -361,35 +791,66 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         offset: usize,
         data: &[u8],
     ) -> OperationResult<()> {
+        let size = self.query_file(id)?.stat.size;
+        let end = (offset + data.len()) as u64;
+        if end > size {
+            self.clear_past_end(id, size)?;
+        }
+
         let chunks = self.inner.get_chunks(id)?;
-        let mut blobs = self.inner.get_blobs(chunks.iter())?;
-        let mut new_chunks = Vec::new();
+        let blobs = self.get_blobs(Self::get_partially_written_blobs(
+            &chunks,
+            size,
+            offset as u64,
+            data.len() as u64,
+        ))?;
+        let previous_content = |index: usize| -> OperationResult<Vec<u8>> {
+            match chunks.get(index).map(String::as_str) {
+                None | Some(HOLE_BLOB_ID) => Ok(Vec::new()),
+                Some(blob_id) => blobs
+                    .get(blob_id)
+                    .map(|x| x.to_vec())
+                    .ok_or_else(|| OperationError::blob_does_not_exist(blob_id)),
+            }
+        };
+        // The contents of the chunks are stored at once, so that they are hashed in parallel
+        let mut new_contents: Vec<Cow<[u8]>> = Vec::new();
         let mut data_offset: usize = 0;
         let first_chunk_id = offset / BLOB_SIZE;
 
+        // Writing past the end of the file leaves a hole in between
+        if first_chunk_id > chunks.len() {
+            let holes = iter::repeat(HOLE_BLOB_ID)
+                .enumerate()
+                .skip(chunks.len())
+                .take(first_chunk_id - chunks.len());
+            self.inner.replace_chunks(id, holes)?;
+        }
+
         {
             // The first chunk
             let chunk_offset = offset % BLOB_SIZE;
             let first_chunk_size = min(data.len(), BLOB_SIZE - chunk_offset);
-            let mut chunk = blobs
-                .remove(chunks.get(first_chunk_id).unwrap_or(&"".to_owned()))
-                .unwrap_or(Vec::new());
-
-            chunk.resize(BLOB_SIZE, 0);
-            chunk.as_mut_slice()[chunk_offset..chunk_offset + first_chunk_size]
+            let mut chunk = if first_chunk_size == BLOB_SIZE {
+                Vec::new()
+            } else {
+                previous_content(first_chunk_id)?
+            };
+
+            // The blob only covers the chunk up to the end of the data written to it
+            let chunk_end = chunk_offset + first_chunk_size;
+            chunk.resize(max(chunk.len(), chunk_end), 0);
+            chunk.as_mut_slice()[chunk_offset..chunk_end]
                 .copy_from_slice(&data[..first_chunk_size]);
 
-            new_chunks.push(self.inner.add_blob(&chunk)?);
+            new_contents.push(Cow::Owned(chunk));
 
             data_offset += first_chunk_size;
         }
 
         // Middle chunks
         while data_offset + BLOB_SIZE <= data.len() {
-            new_chunks.push(
-                self.inner
-                    .add_blob(&data[data_offset..data_offset + BLOB_SIZE])?,
-            );
+            new_contents.push(Cow::Borrowed(&data[data_offset..data_offset + BLOB_SIZE]));
 
             data_offset += BLOB_SIZE;
         }
@@ -398,17 +859,16 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         if data_offset < data.len() {
             let last_chunk_size = data.len() - data_offset;
 
-            let mut chunk = blobs
-                .remove(&chunks[(offset + data_offset) / BLOB_SIZE])
-                .unwrap_or(Vec::new());
-            chunk.resize(BLOB_SIZE, 0);
+            let mut chunk = previous_content((offset + data_offset) / BLOB_SIZE)?;
+            chunk.resize(max(chunk.len(), last_chunk_size), 0);
 
             chunk.as_mut_slice()[..last_chunk_size].copy_from_slice(&data[data_offset..]);
 
-            new_chunks.push(self.inner.add_blob(&chunk)?);
+            new_contents.push(Cow::Owned(chunk));
         }
 
         // Update the store
+        let new_chunks = self.add_chunk_blobs(&new_contents)?;
         self.inner.replace_chunks(
             id,
             new_chunks
@@ -416,15 +876,208 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
                 .enumerate()
                 .map(|(i, value)| (i + first_chunk_id, value)),
         )?;
-        let dirent = self.query_file(id)?;
-        self.inner
-            .resize_file(id, max(dirent.stat.size, (offset + data.len()) as u64))?;
+        self.inner.resize_file(id, max(size, end))?;
+
+        self.update_time(id, timestamp, false, true, true)?;
+
+        Ok(())
+    }
+
+    /// Zeroes the given range of the file if `zero` is set, turning the chunks within it into
+    /// holes, and extends the file to cover the range unless `keep_size` is set.
+    #[instrument(level = "trace", skip(self))]
+    pub fn fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        offset: u64,
+        length: u64,
+        zero: bool,
+        keep_size: bool,
+    ) -> OperationResult<()> {
+        let size = self.query_file(id)?.stat.size;
+        let end = offset + length;
+
+        if zero {
+            self.zero_range(id, offset, min(end, size))?;
+        }
+        if !keep_size && end > size {
+            self.resize_file(id, end)?;
+            self.inner.resize_file(id, end)?;
+        }
+
+        self.update_time(id, timestamp, false, true, true)?;
+
+        Ok(())
+    }
+
+    /// Copies a range of one file into another, returning the number of bytes copied. Whenever
+    /// the offsets allow, whole chunks are copied just by referencing the same blobs.
+    #[instrument(level = "trace", skip(self))]
+    pub fn copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        source_id: &str,
+        source_offset: u64,
+        offset: u64,
+        length: u64,
+    ) -> OperationResult<u64> {
+        let source_size = self.query_file(source_id)?.stat.size;
+        let length = min(length, source_size.saturating_sub(source_offset));
+        if length == 0 {
+            return Ok(0);
+        }
+
+        const UBLOB_SIZE: u64 = BLOB_SIZE as u64;
+        let chunk_offset = offset % UBLOB_SIZE;
+        if source_offset % UBLOB_SIZE != chunk_offset {
+            self.copy_data(id, timestamp, source_id, source_offset, offset, length)?;
+            return Ok(length);
+        }
+
+        // Only the partial chunks at the ends of the range have to be actually copied
+        let head = min(length, (UBLOB_SIZE - chunk_offset) % UBLOB_SIZE);
+        let whole_chunks = ((length - head) / UBLOB_SIZE) as usize;
+        let tail = length - head - whole_chunks as u64 * UBLOB_SIZE;
+
+        self.copy_data(id, timestamp, source_id, source_offset, offset, head)?;
 
+        if whole_chunks > 0 {
+            let first_chunk_id = ((offset + head) / UBLOB_SIZE) as usize;
+            let first_source_chunk_id = ((source_offset + head) / UBLOB_SIZE) as usize;
+            let size = self.query_file(id)?.stat.size;
+            let end = offset + head + whole_chunks as u64 * UBLOB_SIZE;
+            if end > size {
+                self.clear_past_end(id, size)?;
+            }
+
+            let source_chunks = self.inner.get_chunks(source_id)?;
+            let chunks = self.inner.get_chunks(id)?;
+
+            if first_chunk_id > chunks.len() {
+                let holes = iter::repeat(HOLE_BLOB_ID)
+                    .enumerate()
+                    .skip(chunks.len())
+                    .take(first_chunk_id - chunks.len());
+                self.inner.replace_chunks(id, holes)?;
+            }
+
+            let new_chunks = source_chunks
+                .iter()
+                .skip(first_source_chunk_id)
+                .take(whole_chunks)
+                .enumerate()
+                .map(|(i, blob_id)| (first_chunk_id + i, blob_id));
+            self.inner.replace_chunks(id, new_chunks)?;
+            self.inner.resize_file(id, max(size, end))?;
+        }
+
+        let copied = length - tail;
+        self.copy_data(
+            id,
+            timestamp,
+            source_id,
+            source_offset + copied,
+            offset + copied,
+            tail,
+        )?;
         self.update_time(id, timestamp, false, true, true)?;
 
+        Ok(length)
+    }
+
+    fn copy_data(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        source_id: &str,
+        source_offset: u64,
+        offset: u64,
+        length: u64,
+    ) -> OperationResult<()> {
+        let mut copied = 0;
+
+        while copied < length {
+            let size = min(length - copied, BLOB_SIZE as u64) as usize;
+            let mut data = self
+                .read(source_id, (source_offset + copied) as i64, size as u32)?
+                .to_vec();
+            data.resize(size, 0);
+
+            self.write(id, timestamp, (offset + copied) as usize, &data)?;
+            copied += size as u64;
+        }
+
+        Ok(())
+    }
+
+    fn zero_range(&mut self, id: &str, start: u64, end: u64) -> OperationResult<()> {
+        if start >= end {
+            return Ok(());
+        }
+
+        let chunks = self.inner.get_chunks(id)?;
+        let first_chunk_id = (start / BLOB_SIZE as u64) as usize;
+        let last_chunk_id = ((end - 1) / BLOB_SIZE as u64) as usize;
+        let mut new_chunks = Vec::new();
+
+        for (index, chunk) in chunks
+            .iter()
+            .enumerate()
+            .take(last_chunk_id + 1)
+            .skip(first_chunk_id)
+        {
+            let chunk_start = (index * BLOB_SIZE) as u64;
+            let from = (max(start, chunk_start) - chunk_start) as usize;
+            let to = (min(end, chunk_start + BLOB_SIZE as u64) - chunk_start) as usize;
+
+            let blob_id = if chunk == HOLE_BLOB_ID || (from == 0 && to == BLOB_SIZE) {
+                HOLE_BLOB_ID.to_owned()
+            } else {
+                // The part of the chunk past the end of the blob already reads as zeros
+                let mut data = self.inner.get_blob(chunk)?.to_vec();
+                let length = data.len();
+                data[min(from, length)..min(to, length)].fill(0);
+
+                self.add_chunk_blob(&data)?
+            };
+            new_chunks.push((index, blob_id));
+        }
+
+        self.inner.replace_chunks(id, new_chunks)?;
+
         Ok(())
     }
 
+    /// Stores the content of a chunk, returning the ID of its blob. Chunks consisting of zeros
+    /// only are stored as holes.
+    fn add_chunk_blob(&self, data: &[u8]) -> OperationResult<String> {
+        Ok(self.add_chunk_blobs(&[data])?.remove(0))
+    }
+
+    /// Stores the contents of the chunks at once, returning the IDs of their blobs in order.
+    fn add_chunk_blobs(&self, chunks: &[impl AsRef<[u8]> + Sync]) -> OperationResult<Vec<String>> {
+        let is_hole = |data: &[u8]| data.iter().all(|&x| x == 0);
+        let blobs = chunks
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|x| !is_hole(x))
+            .collect::<Vec<_>>();
+        let mut blob_ids = self.inner.add_blobs(blobs)?.into_iter();
+
+        Ok(chunks
+            .iter()
+            .map(|x| {
+                if is_hole(x.as_ref()) {
+                    HOLE_BLOB_ID.to_owned()
+                } else {
+                    blob_ids.next().unwrap()
+                }
+            })
+            .collect())
+    }
+
     pub fn add_blob(&self, data: &[u8]) -> OperationResult<String> {
         Ok(self.inner.add_blob(data)?)
     }
@@ -437,20 +1090,147 @@ impl<IdT: IdGenerator> StoreWrapper<IdT> {
         Ok(self.inner.replace_chunks(id, chunks)?)
     }
 
+    pub fn replace_chunks_batch<S: AsRef<str>, T: AsRef<str>, C>(
+        &self,
+        files: impl IntoIterator<Item = (S, C)>,
+    ) -> OperationResult<()>
+    where
+        C: IntoIterator<Item = (usize, T)>,
+    {
+        Ok(self.inner.replace_chunks_batch(files)?)
+    }
+
+    // Sidecars
+    pub fn get_sidecars(&self, id: &str) -> OperationResult<Vec<Sidecar>> {
+        Ok(self.inner.get_sidecars(id)?)
+    }
+
+    pub fn get_sidecar(&self, id: &str, name: &str) -> OperationResult<Option<Sidecar>> {
+        Ok(self.inner.get_sidecar(id, name)?)
+    }
+
+    pub fn set_sidecar(&self, id: &str, sidecar: &Sidecar) -> OperationResult<()> {
+        Ok(self.inner.set_sidecar(id, sidecar)?)
+    }
+
+    pub fn remove_sidecar(&self, id: &str, name: &str) -> OperationResult<bool> {
+        Ok(self.inner.remove_sidecar(id, name)?)
+    }
+
+    pub fn replace_sidecars(&self, id: &str, sidecars: &[Sidecar]) -> OperationResult<()> {
+        Ok(self.inner.replace_sidecars(id, sidecars)?)
+    }
+
     // Misc
     pub fn transaction(&self) -> Transaction {
         self.inner.transaction()
     }
+
+    pub fn try_transaction(&self) -> OperationResult<Transaction> {
+        Ok(self.inner.try_transaction()?)
+    }
+
+    pub fn read_transaction(&self) -> Transaction {
+        self.inner.read_transaction()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.inner.in_transaction()
+    }
+
+    pub fn session(&self) -> Self {
+        Self {
+            inner: self.inner.session(),
+            blob_cache: self.blob_cache.clone(),
+            dir_cache: self.dir_cache.clone(),
+        }
+    }
+
+    pub fn get_db_size(&self) -> OperationResult<u64> {
+        Ok(self.inner.get_db_size()?)
+    }
 }
 
 impl StoreWrapper<RandomHexIdGenerator> {
+    // Read
+    pub fn get_dir_usage(&self, id: &str) -> OperationResult<DirUsage> {
+        Ok(self.inner.get_dir_usage(id)?)
+    }
+
+    pub fn get_quota(&self, id: &str) -> OperationResult<Quota> {
+        Ok(self.inner.get_quota(id)?)
+    }
+
+    pub fn get_quotas(&self) -> OperationResult<Vec<(String, Quota)>> {
+        Ok(self.inner.get_quotas()?)
+    }
+
+    pub fn check_quota(
+        &self,
+        dir_id: &str,
+        size: u64,
+        files: u64,
+        source_dir: Option<&str>,
+    ) -> OperationResult<()> {
+        Ok(self.inner.check_quota(dir_id, size, files, source_dir)?)
+    }
+
     // Modify
+    pub fn set_quota(&self, id: &str, quota: Quota) -> OperationResult<()> {
+        Ok(self.inner.set_quota(id, quota)?)
+    }
+
     pub fn increment_dirent_version(&mut self, id: &str) -> OperationResult<()> {
-        Ok(self.inner.increment_dirent_version(id)?)
+        self.inner.increment_dirent_version(id)?;
+        self.invalidate_listing(id);
+
+        Ok(())
     }
 
     pub fn increment_content_version(&mut self, id: &str) -> OperationResult<()> {
-        Ok(self.inner.increment_content_version(id)?)
+        self.inner.increment_content_version(id)?;
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    pub fn increment_content_vector(&mut self, id: &str, replica: &str) -> OperationResult<()> {
+        self.inner.increment_content_vector(id, replica)?;
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    pub fn increment_dirent_vector(&mut self, id: &str, replica: &str) -> OperationResult<()> {
+        self.inner.increment_dirent_vector(id, replica)?;
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    // Applied operations
+    pub fn get_applied_operation(
+        &self,
+        tenant: &str,
+        op_id: &str,
+    ) -> OperationResult<Option<String>> {
+        Ok(self.inner.get_applied_operation(tenant, op_id)?)
+    }
+
+    pub fn add_applied_operation(
+        &self,
+        tenant: &str,
+        op_id: &str,
+        result: &str,
+        timestamp: i64,
+    ) -> OperationResult<()> {
+        Ok(self
+            .inner
+            .add_applied_operation(tenant, op_id, result, timestamp)?)
+    }
+
+    pub fn remove_applied_operations_before(&self, timestamp: i64) -> OperationResult<()> {
+        Ok(self.inner.remove_applied_operations_before(timestamp)?)
     }
 }
 
@@ -464,9 +1244,35 @@ impl StoreWrapper<LocalTempIdGenerator> {
         self.inner.get_temp_file_ids()
     }
 
+    pub fn get_replica_id(&self) -> OperationResult<String> {
+        Ok(self.inner.get_replica_id()?)
+    }
+
     // Modify
+    pub fn reset_id_generator(&mut self) {
+        self.inner.reset_id_generator();
+    }
+
     pub fn update_retrieved_version(&self, id: &str) -> OperationResult<()> {
-        Ok(self.inner.update_retrieved_version(id)?)
+        self.inner.update_retrieved_version(id)?;
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    pub fn increment_directory_version(&self, id: &str) -> OperationResult<()> {
+        self.inner.increment_directory_version(id)?;
+        self.invalidate_listing(id);
+
+        Ok(())
+    }
+
+    /// Removes the file from the cache, without updating its parent directory.
+    pub fn forget_file(&self, id: &str) -> OperationResult<()> {
+        self.inner.remove_file(id)?;
+        self.invalidate_listing(id);
+
+        Ok(())
     }
 
     pub fn remove_remaining_files<T: IntoIterator>(
@@ -478,11 +1284,49 @@ impl StoreWrapper<LocalTempIdGenerator> {
         T::Item: AsRef<str>,
         T::IntoIter: ExactSizeIterator,
     {
-        Ok(self.inner.remove_remaining_files(parent_id, to_keep)?)
+        self.inner.remove_remaining_files(parent_id, to_keep)?;
+        self.invalidate_listing(parent_id);
+
+        Ok(())
+    }
+
+    pub fn remove_remaining_files_in_page<T: IntoIterator>(
+        &self,
+        parent_id: &str,
+        start_after: &str,
+        end: Option<&str>,
+        to_keep: T,
+    ) -> OperationResult<()>
+    where
+        T::Item: AsRef<str>,
+        T::IntoIter: ExactSizeIterator,
+    {
+        self.inner
+            .remove_remaining_files_in_page(parent_id, start_after, end, to_keep)?;
+        self.invalidate_listing(parent_id);
+
+        Ok(())
     }
 
     pub fn assign_temp_id(&mut self, id: &str) -> OperationResult<String> {
-        Ok(self.inner.assign_temp_id(id)?)
+        let temp_id = self.inner.assign_temp_id(id)?;
+        self.invalidate_listing(id);
+
+        Ok(temp_id)
+    }
+
+    pub fn replace_temp_id(&mut self, temp_id: &str, new_id: &str) -> OperationResult<()> {
+        self.inner.replace_temp_id(temp_id, new_id)?;
+        self.invalidate_listing(temp_id);
+
+        Ok(())
+    }
+
+    pub fn make_local_only(&mut self, id: &str) -> OperationResult<String> {
+        let new_id = self.inner.make_local_only(id)?;
+        self.invalidate_listing(id);
+
+        Ok(new_id)
     }
 
     // Journal
@@ -490,10 +1334,18 @@ impl StoreWrapper<LocalTempIdGenerator> {
         Ok(self.inner.add_journal_entry(id, operation)?)
     }
 
-    pub fn get_journal(&self) -> OperationResult<Vec<Vec<u8>>> {
+    pub fn get_journal(&self) -> OperationResult<Vec<(i64, Vec<u8>)>> {
         Ok(self.inner.get_journal()?)
     }
 
+    pub fn get_journal_size(&self) -> OperationResult<u64> {
+        Ok(self.inner.get_journal_size()?)
+    }
+
+    pub fn is_file_in_journal(&self, id: &str) -> OperationResult<bool> {
+        Ok(self.inner.is_file_in_journal(id)?)
+    }
+
     pub fn clear_journal(&mut self) -> OperationResult<()> {
         Ok(self.inner.clear_journal()?)
     }
@@ -505,4 +1357,98 @@ impl StoreWrapper<LocalTempIdGenerator> {
     pub fn remove_journal_item(&self, id: i64) -> OperationResult<()> {
         Ok(self.inner.remove_journal_item(id)?)
     }
+
+    pub fn quarantine_journal_item(&self, id: i64, reason: &str) -> OperationResult<()> {
+        Ok(self.inner.quarantine_journal_item(id, reason)?)
+    }
+
+    pub fn get_quarantined_journal_size(&self) -> OperationResult<u64> {
+        Ok(self.inner.get_quarantined_journal_size()?)
+    }
+
+    pub fn get_quarantined_journal(&self) -> OperationResult<Vec<QuarantinedJournalEntry>> {
+        Ok(self.inner.get_quarantined_journal()?)
+    }
+
+    // Write intents
+    pub fn add_write_intent(
+        &self,
+        handle: u64,
+        file: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> OperationResult<()> {
+        Ok(self.inner.add_write_intent(handle, file, offset, data)?)
+    }
+
+    pub fn get_write_intents(&self) -> OperationResult<Vec<WriteIntent>> {
+        Ok(self.inner.get_write_intents()?)
+    }
+
+    pub fn remove_write_intents(&self, handle: u64) -> OperationResult<()> {
+        Ok(self.inner.remove_write_intents(handle)?)
+    }
+
+    // Dehydration
+    pub fn dehydrate(&self, id: &str, keep: &HashSet<String>) -> OperationResult<Dehydration> {
+        let dehydration = self.inner.dehydrate(id, keep)?;
+        self.clear_listings();
+
+        Ok(dehydration)
+    }
+
+    // Selective sync
+    pub fn exclude_from_sync(&self, id: &str) -> OperationResult<()> {
+        self.inner.exclude_from_sync(id)?;
+        // The subtree is retrieved anew
+        self.clear_listings();
+
+        Ok(())
+    }
+
+    pub fn include_in_sync(&self, id: &str) -> OperationResult<()> {
+        self.inner.include_in_sync(id)?;
+        // The subtree is retrieved anew
+        self.clear_listings();
+
+        Ok(())
+    }
+
+    pub fn is_excluded_from_sync(&self, id: &str) -> OperationResult<bool> {
+        Ok(self.inner.is_excluded_from_sync(id)?)
+    }
+
+    pub fn get_excluded_from_sync(&self) -> OperationResult<Vec<String>> {
+        Ok(self.inner.get_excluded_from_sync()?)
+    }
+
+    // Sync states
+    pub fn get_sync_states(&self) -> OperationResult<HashMap<String, SyncState>> {
+        Ok(self.inner.get_sync_states()?)
+    }
+
+    // Files removed while opened
+    pub fn detach_file(&self, id: &str) -> OperationResult<String> {
+        let new_id = self.inner.detach_file(id)?;
+        self.invalidate_listing(id);
+
+        Ok(new_id)
+    }
+
+    pub fn move_open_file(&self, from_id: &str, to_id: &str) -> OperationResult<()> {
+        self.inner.move_open_file(from_id, to_id)?;
+        self.invalidate_listing(from_id);
+        self.invalidate_listing(to_id);
+
+        Ok(())
+    }
+
+    // Inodes
+    pub fn get_inode(&self, id: &str) -> OperationResult<Inode> {
+        Ok(self.inner.get_inode(id)?)
+    }
+
+    pub fn get_id_by_inode(&self, inode: u64) -> OperationResult<String> {
+        Ok(self.inner.get_id_by_inode(inode)?)
+    }
 }
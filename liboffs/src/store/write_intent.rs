@@ -0,0 +1,74 @@
+use rusqlite::params;
+
+use crate::errors::OperationResult;
+use crate::store::encryption::WRITE_INTENT_AAD;
+use crate::store::id_generator::LocalTempIdGenerator;
+use crate::store::Store;
+
+/// Data written to an opened file that has not reached the journal yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteIntent {
+    /// Handle of the opened file the data has been written through.
+    pub handle: u64,
+    pub file: String,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl Store<LocalTempIdGenerator> {
+    /// Records the data written through the file handle, until it is written to the file with
+    /// the rest of the data buffered for the handle.
+    pub fn add_write_intent(
+        &self,
+        handle: u64,
+        file: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> OperationResult<()> {
+        self.pool.writer().execute(
+            r#"INSERT INTO write_intent (handle, file, "offset", data) VALUES (?, ?, ?, ?)"#,
+            params![
+                handle as i64,
+                file,
+                offset as i64,
+                self.encrypt(data, WRITE_INTENT_AAD)
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns all the recorded writes, in the order they have been made.
+    pub fn get_write_intents(&self) -> OperationResult<Vec<WriteIntent>> {
+        let connection = self.pool.reader();
+        let mut stmt = connection
+            .prepare(r#"SELECT handle, file, "offset", data FROM write_intent ORDER BY id"#)?;
+        let iter = stmt.query_map([], |row| {
+            Ok(WriteIntent {
+                handle: row.get::<_, i64>(0)? as u64,
+                file: row.get(1)?,
+                offset: row.get::<_, i64>(2)? as u64,
+                data: row.get(3)?,
+            })
+        })?;
+
+        iter.map(|intent| {
+            let intent = intent?;
+            Ok(WriteIntent {
+                data: self.decrypt(intent.data, WRITE_INTENT_AAD)?,
+                ..intent
+            })
+        })
+        .collect()
+    }
+
+    /// Forgets the writes made through the file handle, once their data has been written.
+    pub fn remove_write_intents(&self, handle: u64) -> OperationResult<()> {
+        self.pool.writer().execute(
+            "DELETE FROM write_intent WHERE handle = ?",
+            params![handle as i64],
+        )?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::error::Error;
+use std::sync::OnceLock;
+
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::{global, Context, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tonic::codegen::http::HeaderMap;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Registry};
+
+/// Filter of the logs set up by [`init_tracing`], along with the modules it lets through.
+static LOG_FILTER: OnceLock<(reload::Handle<Targets, Registry>, Vec<String>)> = OnceLock::new();
+
+/// Sets up the global tracing subscriber, which logs to stderr and optionally exports the spans
+/// to an OpenTelemetry collector listening at `otlp_endpoint`.
+///
+/// The verbosity is only applied to the `modules` specified; everything else is silenced.
+/// When the OTLP exporter is enabled, this has to be called from within a Tokio runtime.
+pub fn init_tracing(
+    service_name: &str,
+    modules: &[&str],
+    verbosity: usize,
+    quiet: bool,
+    otlp_endpoint: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let modules: Vec<String> = modules.iter().map(|x| (*x).to_owned()).collect();
+    let (filter, filter_handle) =
+        reload::Layer::new(module_filter(&modules, verbosity_level(verbosity, quiet)));
+
+    let otlp_layer = match otlp_endpoint {
+        Some(endpoint) => {
+            global::set_text_map_propagator(TraceContextPropagator::new());
+
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", service_name.to_owned()),
+                ])))
+                .install_batch(opentelemetry::runtime::Tokio)?;
+
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(otlp_layer)
+        .try_init()?;
+    let _ = LOG_FILTER.set((filter_handle, modules));
+
+    Ok(())
+}
+
+/// Returns the log level set by the `-v` and `-q` options.
+pub fn verbosity_level(verbosity: usize, quiet: bool) -> LevelFilter {
+    if quiet {
+        LevelFilter::OFF
+    } else {
+        match verbosity {
+            0 => LevelFilter::ERROR,
+            1 => LevelFilter::WARN,
+            2 => LevelFilter::INFO,
+            3 => LevelFilter::DEBUG,
+            _ => LevelFilter::TRACE,
+        }
+    }
+}
+
+fn module_filter(modules: &[String], level: LevelFilter) -> Targets {
+    Targets::new().with_targets(modules.iter().map(|module| (module.clone(), level)))
+}
+
+/// Changes the log level of the modules given to [`init_tracing`].
+pub fn set_log_level(level: LevelFilter) -> Result<(), Box<dyn Error>> {
+    let (handle, modules) = LOG_FILTER
+        .get()
+        .ok_or("The tracing has not been initialized")?;
+    handle.reload(module_filter(modules, level))?;
+
+    Ok(())
+}
+
+/// Flushes the spans that have not been exported yet
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl<'a> Injector for MetadataInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::from_str(&value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|x| x.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|x| x.as_str()).collect()
+    }
+}
+
+/// Attaches the trace context of the current span to outgoing gRPC request metadata
+pub fn inject_trace_context(metadata: &mut MetadataMap) {
+    let context = Span::current().context();
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Makes the span a child of the trace context sent by the other side of a gRPC connection
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context: Context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+
+    span.set_parent(context);
+}
@@ -20,3 +20,18 @@ pub fn check_is_dir(path_string: String) -> Result<(), String> {
         Ok(())
     }
 }
+
+pub fn check_number(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", value, e))
+}
+
+pub fn check_positive_number(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err(format!("{}: has to be positive", value)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", value, e)),
+    }
+}
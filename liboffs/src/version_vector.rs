@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// Number of modifications of a file made by each replica (client), which makes it possible to
+/// tell concurrent modifications apart from the ones that only happened later.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VersionVector {
+    versions: BTreeMap<String, i64>,
+}
+
+impl VersionVector {
+    pub fn new(versions: BTreeMap<String, i64>) -> Self {
+        Self { versions }
+    }
+
+    pub fn versions(&self) -> &BTreeMap<String, i64> {
+        &self.versions
+    }
+
+    pub fn into_versions(self) -> BTreeMap<String, i64> {
+        self.versions
+    }
+
+    pub fn get(&self, replica: &str) -> i64 {
+        self.versions.get(replica).copied().unwrap_or(0)
+    }
+
+    pub fn increment(&mut self, replica: &str) {
+        *self.versions.entry(replica.to_owned()).or_insert(0) += 1;
+    }
+
+    /// Returns whether all the modifications known by `other` are known by this vector as well.
+    pub fn includes(&self, other: &VersionVector) -> bool {
+        other
+            .versions
+            .iter()
+            .all(|(replica, &version)| self.get(replica) >= version)
+    }
+
+    /// Returns whether `base` includes all the modifications made by the replicas other than
+    /// `replica`, i.e. the modifications of `replica` made on top of `base` are not concurrent
+    /// with anyone else's.
+    pub fn is_descendant_for(&self, base: &VersionVector, replica: &str) -> bool {
+        self.versions
+            .iter()
+            .filter(|(other_replica, _)| *other_replica != replica)
+            .all(|(other_replica, &version)| base.get(other_replica) >= version)
+    }
+}
+
+impl fmt::Display for VersionVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (replica, version)) in self.versions.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", replica, version)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for VersionVector {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut versions = BTreeMap::new();
+
+        for entry in s.split(',').filter(|x| !x.is_empty()) {
+            let (replica, version) = entry.rsplit_once(':').unwrap_or((entry, ""));
+            versions.insert(replica.to_owned(), version.parse()?);
+        }
+
+        Ok(Self { versions })
+    }
+}
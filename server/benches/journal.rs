@@ -0,0 +1,125 @@
+//! Benchmarks of applying the journals sent by the clients, like the ones of a client that
+//! has been unpacking an archive while offline.
+
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use tempfile::TempDir;
+
+use offs::errors::JournalOperationStatus;
+use offs::modify_op::{
+    CreateDirectoryOperation, CreateFileOperation, ModifyOperation, ModifyOperationContent,
+    SetAttributesOperation,
+};
+use offs::store::id_generator::LocalTempIdGenerator;
+use offs::store::{FileType, Store};
+use offs::timespec::Timespec;
+use offs::version_vector::VersionVector;
+use offs::ROOT_ID;
+use offs_server::remote_fs::RemoteFs;
+
+const OPERATION_COUNT: usize = 10_000;
+const FILES_PER_DIRECTORY: usize = 100;
+
+fn operation(id: String, operation: ModifyOperationContent) -> ModifyOperation {
+    ModifyOperation {
+        id,
+        op_id: String::new(),
+        timestamp: Timespec::now(),
+        dirent_version: 1,
+        content_version: 1,
+        content_vector: VersionVector::default(),
+        dirent_vector: VersionVector::default(),
+        replica_id: String::new(),
+        operation,
+    }
+}
+
+/// Creates directories, files in them, and sets the times of the files, referring to the files
+/// created by the journal by their temporary IDs.
+fn make_journal() -> Vec<ModifyOperation> {
+    let mut journal = Vec::with_capacity(OPERATION_COUNT);
+    let mut created = 0;
+    let mut directory = String::new();
+
+    while journal.len() < OPERATION_COUNT {
+        if created % (FILES_PER_DIRECTORY + 1) == 0 {
+            journal.push(operation(
+                ROOT_ID.to_owned(),
+                ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                    name: format!("dir{}", created),
+                    perm: 0o755,
+                    owner: None,
+                }),
+            ));
+            directory = LocalTempIdGenerator::get_nth_id(created);
+            created += 1;
+            continue;
+        }
+
+        journal.push(operation(
+            directory.clone(),
+            ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+                name: format!("file{}", created),
+                file_type: FileType::RegularFile,
+                perm: 0o644,
+                dev: 0,
+                owner: None,
+            }),
+        ));
+        journal.push(operation(
+            LocalTempIdGenerator::get_nth_id(created),
+            ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+                perm: None,
+                uid: None,
+                gid: None,
+                size: None,
+                atim: Some(Timespec::new(0, 0)),
+                mtim: Some(Timespec::new(0, 0)),
+            }),
+        ));
+        created += 1;
+    }
+    journal.truncate(OPERATION_COUNT);
+
+    journal
+}
+
+fn bench_apply_journal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("apply_journal");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(30));
+    group.throughput(Throughput::Elements(OPERATION_COUNT as u64));
+
+    group.bench_function(format!("{} operations", OPERATION_COUNT), |b| {
+        b.iter_batched(
+            || {
+                let dir = TempDir::new().unwrap();
+                let mut fs = RemoteFs::new(
+                    Store::new_server(dir.path().join("store.db")).unwrap(),
+                    true,
+                )
+                .unwrap();
+                let share = fs.create_share("", "").unwrap();
+
+                (dir, fs, share, make_journal())
+            },
+            |(dir, mut fs, share, journal)| {
+                // Each operation is committed on its own, as on the server
+                let (_, _, statuses) = fs.apply_journal(&share, journal);
+                assert!(statuses
+                    .iter()
+                    .all(|x| *x == JournalOperationStatus::Applied));
+
+                // Removed once the time is measured
+                (dir, fs)
+            },
+            BatchSize::PerIteration,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_apply_journal);
+criterion_main!(benches);
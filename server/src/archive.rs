@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::iter;
+use std::path::Path;
+
+use prost::Message;
+use tar::{Archive, Builder, Header};
+use tracing::info;
+
+use offs::proto::filesystem::{ArchivedFile, ArchivedQuota};
+use offs::store::id_generator::RandomHexIdGenerator;
+use offs::store::wrapper::StoreWrapper;
+use offs::store::{BlobHash, Quota, Store};
+use offs::ROOT_ID;
+
+/// Version of the archive layout, bumped on incompatible changes
+const ARCHIVE_VERSION: &str = "2";
+/// Versions of the archive layout that can be imported. The version 1 archives have all the
+/// files in a single entry, without the sidecars and the quotas.
+const SUPPORTED_VERSIONS: &[&str] = &["1", ARCHIVE_VERSION];
+
+const VERSION_ENTRY: &str = "version";
+const FILES_ENTRY: &str = "files";
+const FILES_DIR: &str = "files/";
+const BLOBS_DIR: &str = "blobs/";
+
+const BLOB_EXPORT_BATCH_SIZE: usize = 64;
+/// Number of the files in a single entry, so that they are never all held in memory
+const FILE_EXPORT_BATCH_SIZE: usize = 1024;
+
+fn append_entry<W: Write>(
+    builder: &mut Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)?;
+
+    Ok(())
+}
+
+/// Writes the whole store, that is the directory trees of the tenants, along with the sidecars of
+/// the files and the quotas of the directories, and all the blobs, into a tar archive. The store
+/// is read within a single transaction, so the archive is consistent even if the server is
+/// running.
+///
+/// The blobs are stored first, one entry per blob, followed by the length-delimited
+/// `ArchivedFile` messages, ordered so that every directory comes before its children and split
+/// into the numbered entries of up to [`FILE_EXPORT_BATCH_SIZE`] files.
+pub fn export_store(
+    store: Store<RandomHexIdGenerator>,
+    archive_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let store = StoreWrapper::new(store);
+    let _transaction = store.read_transaction();
+    let mut builder = Builder::new(File::create(archive_path)?);
+
+    append_entry(&mut builder, VERSION_ENTRY, ARCHIVE_VERSION.as_bytes())?;
+
+    let blob_ids = store.get_blob_ids()?;
+    for batch in blob_ids.chunks(BLOB_EXPORT_BATCH_SIZE) {
+        for (id, content) in store.get_blobs(batch)? {
+            append_entry(&mut builder, &format!("{}{}", BLOBS_DIR, id), &content)?;
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut file_count = 0;
+    let mut queue = VecDeque::new();
+    for id in store.get_root_ids()? {
+        queue.push_back(store.query_file(&id)?);
+    }
+    while let Some(dirent) = queue.pop_front() {
+        let chunks = store.get_chunks(&dirent.id)?;
+        let tenant = store.get_tenant(&dirent.id)?;
+        let sidecars = store.get_sidecars(&dirent.id)?;
+        let quota = store.get_quota(&dirent.id)?;
+        queue.extend(store.list_files(&dirent.id)?);
+
+        ArchivedFile {
+            dirent: Some(dirent.into()),
+            chunks,
+            tenant,
+            sidecars: sidecars.into_iter().map(Into::into).collect(),
+            quota: Some(ArchivedQuota {
+                max_size: quota.max_size,
+                max_files: quota.max_files,
+            })
+            .filter(|_| !quota.is_unlimited()),
+        }
+        .encode_length_delimited(&mut files)?;
+        file_count += 1;
+
+        if file_count % FILE_EXPORT_BATCH_SIZE == 0 || queue.is_empty() {
+            let batch = (file_count - 1) / FILE_EXPORT_BATCH_SIZE;
+            append_entry(&mut builder, &format!("{}{:08}", FILES_DIR, batch), &files)?;
+            files.clear();
+        }
+    }
+
+    builder.into_inner()?.sync_all()?;
+    info!(
+        "Exported {} files and {} blobs to {}",
+        file_count,
+        blob_ids.len(),
+        archive_path.display()
+    );
+
+    Ok(())
+}
+
+/// Restores an archive created with [`export_store`] into an empty store.
+pub fn import_store(
+    store: Store<RandomHexIdGenerator>,
+    archive_path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let store = StoreWrapper::new(store);
+    let roots = store.get_root_ids()?;
+    if store.any_child_exists(ROOT_ID)? || roots.iter().any(|x| x != ROOT_ID) {
+        return Err("The store has to be empty to import an archive".into());
+    }
+
+    let mut archive = Archive::new(File::open(archive_path)?);
+    let transaction = store.transaction();
+    let mut file_count = 0;
+    let mut blob_count = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+
+        if path == VERSION_ENTRY {
+            let version = String::from_utf8_lossy(&data);
+            if !SUPPORTED_VERSIONS.contains(&version.as_ref()) {
+                return Err(format!("Unsupported archive version: {}", version).into());
+            }
+        } else if let Some(id) = path.strip_prefix(BLOBS_DIR) {
+            let blob_ids = store.add_blobs_batch(iter::once((BlobHash::of_id(id), &data)))?;
+            if blob_ids[0] != id {
+                return Err(format!("Blob {} does not match its content", id).into());
+            }
+            blob_count += 1;
+        } else if path == FILES_ENTRY || path.starts_with(FILES_DIR) {
+            let mut buf = data.as_slice();
+            while !buf.is_empty() {
+                let file = ArchivedFile::decode_length_delimited(&mut buf)?;
+                let dirent: offs::store::DirEntity = file.dirent.unwrap_or_default().try_into()?;
+
+                store.add_or_replace_dirent(&dirent)?;
+                if !file.tenant.is_empty() {
+                    store.set_tenant(&dirent.id, &file.tenant)?;
+                }
+                store.replace_chunks(&dirent.id, file.chunks.iter().enumerate())?;
+                for sidecar in file.sidecars {
+                    store.set_sidecar(&dirent.id, &sidecar.into())?;
+                }
+                if let Some(quota) = file.quota {
+                    let quota = Quota {
+                        max_size: quota.max_size,
+                        max_files: quota.max_files,
+                    };
+                    store.set_quota(&dirent.id, quota)?;
+                }
+                file_count += 1;
+            }
+        }
+    }
+
+    transaction.commit()?;
+    info!(
+        "Imported {} files and {} blobs from {}",
+        file_count,
+        blob_count,
+        archive_path.display()
+    );
+
+    Ok(())
+}
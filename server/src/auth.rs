@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use ring::rand::SystemRandom;
+use ring::{digest, hmac};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use offs::errors::{OperationError, OperationResult};
+use offs::AUTHORIZATION_HEADER_KEY;
+
+pub const BEARER_PREFIX: &str = "Bearer ";
+
+/// Name of the tenant that has made the request, stored in the request extensions.
+#[derive(Clone, Debug, Default)]
+pub struct Tenant(pub String);
+
+/// SHA-256 digest of the auth token the request has been made with, stored in the request
+/// extensions, so that the token can be told apart from the others without keeping a copy.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Credential(pub Vec<u8>);
+
+impl Credential {
+    pub fn of_token(token: &str) -> Self {
+        Self(
+            digest::digest(&digest::SHA256, token.as_bytes())
+                .as_ref()
+                .to_vec(),
+        )
+    }
+}
+
+/// Maps the auth tokens sent by the clients to the tenants. When no tokens are configured, the
+/// authentication is disabled and all the requests belong to the default tenant.
+///
+/// The clones share the tokens, so that all the services see them replaced on reload.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Authenticator {
+    /// Reads the tokens from a file containing `TENANT TOKEN` pairs, one per line.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut tokens = HashMap::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(tenant), Some(token), None) => {
+                    tokens.insert(token.to_owned(), tenant.to_owned());
+                }
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected a TENANT TOKEN pair",
+                        path.display(),
+                        number + 1
+                    )
+                    .into())
+                }
+            }
+        }
+
+        Ok(Self {
+            tokens: Arc::new(RwLock::new(tokens)),
+        })
+    }
+
+    /// Replaces the tokens with the ones of the other authenticator.
+    pub fn replace(&self, other: Self) {
+        let tokens = mem::take(&mut *other.tokens.write().unwrap());
+        *self.tokens.write().unwrap() = tokens;
+    }
+
+    /// Returns whether the requests are authenticated at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.read().unwrap().is_empty()
+    }
+
+    /// Returns the tenant the token belongs to.
+    pub fn authenticate(&self, token: Option<&str>) -> OperationResult<String> {
+        let tokens = self.tokens.read().unwrap();
+        if tokens.is_empty() {
+            return Ok(String::new());
+        }
+
+        let token = token.ok_or_else(|| OperationError::unauthenticated("Missing auth token"))?;
+        tokens
+            .get(token)
+            .cloned()
+            .ok_or_else(|| OperationError::unauthenticated("Invalid auth token"))
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix(BEARER_PREFIX));
+        let tenant = self.authenticate(token)?;
+        // The tokens are ignored when the authentication is disabled
+        let credential = token
+            .filter(|_| self.is_enabled())
+            .map(Credential::of_token);
+
+        request.extensions_mut().insert(Tenant(tenant));
+        if let Some(credential) = credential {
+            request.extensions_mut().insert(credential);
+        }
+        Ok(request)
+    }
+}
+
+/// Authorizes the admin RPCs with the token read from a file. When no token is configured, the
+/// admin RPCs are disabled.
+#[derive(Clone, Default)]
+pub struct AdminAuthenticator {
+    token: Arc<RwLock<Option<String>>>,
+}
+
+impl AdminAuthenticator {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let token = fs::read_to_string(path)?.trim().to_owned();
+        if token.is_empty() {
+            return Err(format!("{}: the admin token is empty", path.display()).into());
+        }
+
+        Ok(Self {
+            token: Arc::new(RwLock::new(Some(token))),
+        })
+    }
+
+    /// Replaces the token with the one of the other authenticator.
+    pub fn replace(&self, other: Self) {
+        let token = other.token.write().unwrap().take();
+        *self.token.write().unwrap() = token;
+    }
+
+    pub fn authenticate(&self, token: Option<&str>) -> OperationResult<()> {
+        let expected = self.token.read().unwrap();
+        let expected = expected
+            .as_deref()
+            .ok_or_else(|| OperationError::permission_denied("the admin RPCs are disabled"))?;
+
+        // Compared in constant time, so that the token cannot be guessed from the timing: the
+        // MACs of the tokens are compared, under a key of this call only
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .map_err(|_| OperationError::unauthenticated("Could not verify the admin token"))?;
+        let tag = hmac::sign(&key, expected.as_bytes());
+        hmac::verify(&key, token.unwrap_or_default().as_bytes(), tag.as_ref())
+            .map_err(|_| OperationError::unauthenticated("Invalid admin token"))
+    }
+}
+
+impl Interceptor for AdminAuthenticator {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER_KEY)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix(BEARER_PREFIX));
+        self.authenticate(token)?;
+
+        Ok(request)
+    }
+}
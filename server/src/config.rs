@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+
+use offs::store::BlobHash;
+
+use crate::limits::Limits;
+
+/// Size of the in-memory blob cache in MiB, unless set in the configuration
+const DEFAULT_BLOB_CACHE_SIZE: usize = 64;
+
+/// Server configuration, read from the TOML file given with `--config`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Maps the share names to the paths of their root directories in the store
+    pub shares: BTreeMap<String, String>,
+    /// File with the auth tokens of the tenants; authentication is disabled if not set
+    pub tokens_file: Option<PathBuf>,
+    /// File with the token authorizing the admin RPCs; they are disabled if not set
+    pub admin_token_file: Option<PathBuf>,
+    /// Private key identifying the server to the SFTP clients
+    pub sftp_host_key: Option<PathBuf>,
+    /// Log level ("off", "error", "warn", "info", "debug" or "trace"), instead of the one set
+    /// with `-v` and `-q`
+    pub log_level: Option<String>,
+    /// Size of the in-memory cache of the blob contents in MiB; 0 disables it
+    pub blob_cache_size: Option<usize>,
+    /// Hash the IDs of the new blobs are derived from ("sha256" or "blake3"); the BLAKE3 IDs
+    /// are only understood by the up-to-date clients
+    pub blob_hash: Option<String>,
+    pub limits: Limits,
+    pub maintenance: Maintenance,
+    pub share_links: ShareLinks,
+    /// The file the configuration has been read from, re-read on SIGHUP
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Periodic maintenance of the store database, read from the `[maintenance]` section.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Maintenance {
+    /// Seconds between the maintenance passes; 0 disables them
+    pub interval: u64,
+    /// Frees the unused pages of the database on every pass, instead of keeping them for reuse
+    pub incremental_vacuum: bool,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            interval: 300,
+            incremental_vacuum: false,
+        }
+    }
+}
+
+/// Links giving access to the files without authentication, read from the `[share_links]`
+/// section.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShareLinks {
+    /// File with the secret key the links are signed with; they are disabled if not set
+    pub key_file: Option<PathBuf>,
+    /// URL the links are served at, if not the one of the address given with
+    /// `--share-link-addr` (e.g. when behind a reverse proxy)
+    pub url: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&contents)?;
+        config.path = Some(path.to_owned());
+
+        if let Some(name) = config
+            .shares
+            .keys()
+            .find(|x| x.is_empty() || x.contains('/'))
+        {
+            return Err(format!("Invalid share name: {:?}", name).into());
+        }
+        config.limits.validate()?;
+        if let Some(log_level) = &config.log_level {
+            log_level
+                .parse::<LevelFilter>()
+                .map_err(|_| format!("Invalid log level: {:?}", log_level))?;
+        }
+        if let Some(blob_hash) = &config.blob_hash {
+            blob_hash.parse::<BlobHash>()?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        self.log_level.as_ref().map(|x| x.parse().unwrap())
+    }
+
+    /// Returns the size of the in-memory blob cache in bytes.
+    pub fn blob_cache_size(&self) -> usize {
+        self.blob_cache_size.unwrap_or(DEFAULT_BLOB_CACHE_SIZE) * 1024 * 1024
+    }
+
+    pub fn blob_hash(&self) -> BlobHash {
+        self.blob_hash
+            .as_ref()
+            .map_or_else(BlobHash::default, |x| x.parse().unwrap())
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use tonic_health::server::HealthReporter;
+use tracing::{error, info};
+
+use offs::proto::filesystem::admin_server::AdminServer;
+use offs::proto::filesystem::remote_fs_server::RemoteFsServer;
+use offs::store::id_generator::RandomHexIdGenerator;
+use offs::store::Store;
+
+use crate::remote_fs::{AdminServerImpl, RemoteFsServerImpl};
+
+/// How often the readiness probe writes to the store.
+const READINESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reports the health of the server over `grpc.health.v1`. The server as a whole (the empty
+/// service name) and the admin service are serving as long as the process is running, which
+/// makes them suitable for the liveness probes, while `filesystem.RemoteFS` is only serving
+/// while the store accepts writes, for the readiness probes.
+pub async fn run_health_reporter(store: Store<RandomHexIdGenerator>, mut reporter: HealthReporter) {
+    reporter
+        .set_service_status("", tonic_health::ServingStatus::Serving)
+        .await;
+    reporter.set_serving::<AdminServer<AdminServerImpl>>().await;
+
+    let mut interval = tokio::time::interval(READINESS_CHECK_INTERVAL);
+    let mut ready = None;
+    loop {
+        interval.tick().await;
+
+        let store = store.clone();
+        let result = tokio::task::spawn_blocking(move || store.check_writable())
+            .await
+            .unwrap();
+        match (&result, ready) {
+            (Ok(()), Some(false)) => info!("The store accepts writes again"),
+            (Err(e), Some(true) | None) => error!("The store does not accept writes: {}", e),
+            _ => {}
+        }
+
+        if result.is_ok() {
+            reporter
+                .set_serving::<RemoteFsServer<RemoteFsServerImpl>>()
+                .await;
+        } else {
+            reporter
+                .set_not_serving::<RemoteFsServer<RemoteFsServerImpl>>()
+                .await;
+        }
+        ready = Some(result.is_ok());
+    }
+}
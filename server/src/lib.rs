@@ -0,0 +1,13 @@
+//! Server side of offs: the store of the files shared by the clients, along with the frontends
+//! serving it. The binary is a thin wrapper around [`server::run_server`]; the library exists
+//! mostly so that the fuzz targets can drive [`remote_fs::RemoteFs`] directly.
+
+pub mod archive;
+mod auth;
+pub mod config;
+mod health;
+mod limits;
+mod metrics;
+pub mod remote_fs;
+pub mod server;
+mod sessions;
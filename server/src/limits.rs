@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+
+use hyper::body::{Bytes, HttpBody};
+use serde::Deserialize;
+use tokio_stream::Stream;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response};
+use tonic::codegen::BoxFuture;
+use tonic::transport::server::TcpConnectInfo;
+use tonic::transport::Body;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use offs::errors::{OperationError, OperationResult};
+
+/// Limits protecting the server from abusive or buggy clients, read from the `[limits]`
+/// section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum number of blobs requested at once by `GetBlobs` or `GetMissingBlobs`
+    pub max_blobs_per_request: usize,
+    /// Maximum number of files requested at once by `GetDirEntities`
+    pub max_files_per_request: usize,
+    /// Maximum number of operations in a single journal
+    pub max_journal_operations: usize,
+    /// Maximum size of the data of a single write operation, in bytes
+    pub max_write_size: usize,
+    /// Maximum size of a single gRPC message sent by a client, in bytes
+    pub max_message_size: usize,
+    /// Maximum total size of the blobs uploaded with a single journal, in bytes
+    pub max_journal_blobs_size: usize,
+    /// Maximum total size of the journals a client has staged and not committed yet, in bytes
+    pub max_staged_journals_size: usize,
+    /// Number of requests per second each IP address is allowed to make; unlimited if not set
+    pub requests_per_second: Option<u32>,
+    /// Number of requests an IP address can make at once after being idle; defaults to
+    /// `requests_per_second`
+    pub request_burst: Option<u32>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_blobs_per_request: 4096,
+            max_files_per_request: 4096,
+            max_journal_operations: 100_000,
+            max_write_size: 16 * 1024 * 1024,
+            max_message_size: 64 * 1024 * 1024,
+            max_journal_blobs_size: 1024 * 1024 * 1024,
+            max_staged_journals_size: 256 * 1024 * 1024,
+            requests_per_second: None,
+            request_burst: None,
+        }
+    }
+}
+
+impl Limits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.requests_per_second == Some(0) {
+            return Err("requests_per_second has to be positive".to_owned());
+        }
+        if self.request_burst == Some(0) {
+            return Err("request_burst has to be positive".to_owned());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the rate and the burst size of the requests, if limited.
+    fn rate_limit(&self) -> Option<(f64, f64)> {
+        self.requests_per_second.map(|rate| {
+            let burst = self.request_burst.unwrap_or(rate);
+            (rate as f64, burst as f64)
+        })
+    }
+}
+
+/// The limits shared by the services, which can be replaced when the configuration is
+/// reloaded.
+#[derive(Clone, Default)]
+pub struct SharedLimits(Arc<RwLock<Limits>>);
+
+impl SharedLimits {
+    pub fn new(limits: Limits) -> Self {
+        Self(Arc::new(RwLock::new(limits)))
+    }
+
+    pub fn get(&self) -> Limits {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, limits: Limits) {
+        *self.0.write().unwrap() = limits;
+    }
+
+    pub fn rate_limit_layer(&self) -> RateLimitLayer {
+        RateLimitLayer {
+            limits: self.clone(),
+            buckets: Default::default(),
+        }
+    }
+
+    pub fn message_size_limit_layer(&self) -> MessageSizeLimitLayer {
+        MessageSizeLimitLayer {
+            limits: self.clone(),
+        }
+    }
+}
+
+pub fn check_limit(what: &str, count: usize, max: usize) -> OperationResult<()> {
+    if count > max {
+        return Err(OperationError::request_too_large(&format!(
+            "{} exceeds the limit of {} ({})",
+            what, max, count
+        )));
+    }
+
+    Ok(())
+}
+
+/// Number of the rate limiting buckets kept before the unused ones are discarded.
+const MIN_PRUNED_BUCKETS: usize = 1024;
+/// Size of the header preceding each gRPC message: the compression flag and the length.
+const GRPC_HEADER_SIZE: usize = 5;
+
+/// Layer limiting the rate of the gRPC requests of each peer; it lets everything through if no
+/// rate is configured.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limits: SharedLimits,
+    buckets: Arc<Mutex<TokenBuckets>>,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limits: self.limits.clone(),
+            buckets: self.buckets.clone(),
+        }
+    }
+}
+
+/// Rejects the requests exceeding the rate with `RESOURCE_EXHAUSTED`.
+///
+/// The server clones the service for every connection it accepts, but the clones share the
+/// buckets, which are kept per IP address of the peer, so that a client cannot get around the
+/// limit by opening more connections. When the rate changes, all the buckets start over with
+/// the new one.
+#[derive(Clone)]
+pub struct RateLimit<S> {
+    inner: S,
+    limits: SharedLimits,
+    buckets: Arc<Mutex<TokenBuckets>>,
+}
+
+impl<S> Service<Request<Body>> for RateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let limit = self.limits.get().rate_limit();
+        let peer = request
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|x| x.remote_addr())
+            .map(|x| x.ip());
+
+        if self.buckets.lock().unwrap().try_acquire(limit, peer) {
+            Box::pin(self.inner.call(request))
+        } else {
+            let response = Status::from(OperationError::rate_limited()).to_http();
+            Box::pin(async move { Ok(response) })
+        }
+    }
+}
+
+#[derive(Default)]
+struct TokenBuckets {
+    /// The rate and the burst size the buckets have been created with
+    limit: Option<(f64, f64)>,
+    buckets: HashMap<Option<IpAddr>, TokenBucket>,
+    /// Number of the buckets above which the full ones are discarded
+    prune_at: usize,
+}
+
+impl TokenBuckets {
+    fn try_acquire(&mut self, limit: Option<(f64, f64)>, peer: Option<IpAddr>) -> bool {
+        if limit != self.limit {
+            self.limit = limit;
+            self.buckets.clear();
+        }
+        let limit = match limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        if !self.buckets.contains_key(&peer) && self.buckets.len() >= self.prune_at {
+            // A full bucket behaves the same as a new one, so nothing is lost by discarding it
+            self.buckets.retain(|_, x| !x.is_full());
+            self.prune_at = (self.buckets.len() * 2).max(MIN_PRUNED_BUCKETS);
+        }
+
+        self.buckets
+            .entry(peer)
+            .or_insert_with(|| TokenBucket::new(limit))
+            .try_acquire()
+    }
+}
+
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new((rate, burst): (f64, f64)) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+
+        self.tokens >= self.burst
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        self.refill();
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Layer limiting the size of the gRPC messages sent by the clients, which tonic does not limit
+/// by itself.
+#[derive(Clone)]
+pub struct MessageSizeLimitLayer {
+    limits: SharedLimits,
+}
+
+impl<S> Layer<S> for MessageSizeLimitLayer {
+    type Service = MessageSizeLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MessageSizeLimit {
+            inner,
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+/// Fails the requests with `RESOURCE_EXHAUSTED` as soon as a message declares a length greater
+/// than the limit, before it is buffered for decoding.
+#[derive(Clone)]
+pub struct MessageSizeLimit<S> {
+    inner: S,
+    limits: SharedLimits,
+}
+
+impl<S> Service<Request<Body>> for MessageSizeLimit<S>
+where
+    S: Service<Request<Body>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let max_size = self.limits.get().max_message_size;
+        let request = request.map(|body| {
+            Body::wrap_stream(LimitedBody {
+                inner: body,
+                max_size,
+                header: Vec::with_capacity(GRPC_HEADER_SIZE),
+                remaining: 0,
+            })
+        });
+
+        self.inner.call(request)
+    }
+}
+
+/// Body of a request checking the headers of the gRPC messages as it is read.
+struct LimitedBody {
+    inner: Body,
+    max_size: usize,
+    /// The bytes of the header of the next message read so far
+    header: Vec<u8>,
+    /// Number of the bytes of the current message which have not been read yet
+    remaining: usize,
+}
+
+impl LimitedBody {
+    fn check(&mut self, mut chunk: &[u8]) -> OperationResult<()> {
+        while !chunk.is_empty() {
+            if self.remaining > 0 {
+                let skipped = self.remaining.min(chunk.len());
+                self.remaining -= skipped;
+                chunk = &chunk[skipped..];
+                continue;
+            }
+
+            let read = (GRPC_HEADER_SIZE - self.header.len()).min(chunk.len());
+            self.header.extend_from_slice(&chunk[..read]);
+            chunk = &chunk[read..];
+            if self.header.len() == GRPC_HEADER_SIZE {
+                let size = u32::from_be_bytes(self.header[1..].try_into().unwrap()) as usize;
+                check_limit("Message size", size, self.max_size)?;
+                self.header.clear();
+                self.remaining = size;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Stream for LimitedBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let chunk = match ready!(Pin::new(&mut this.inner).poll_data(cx)) {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+            None => return Poll::Ready(None),
+        };
+
+        // Returned as a `Status`, so that tonic passes it on to the client as is
+        Poll::Ready(Some(match this.check(&chunk) {
+            Ok(()) => Ok(chunk),
+            Err(e) => Err(Status::from(e).into()),
+        }))
+    }
+}
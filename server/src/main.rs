@@ -1,11 +1,13 @@
 use std::net::ToSocketAddrs;
+use std::path::Path;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
-use offs::store::Store;
+use offs::store::{Durability, Store};
 
-mod remote_fs;
-mod server;
+use offs_server::config::Config;
+use offs_server::server::Frontends;
+use offs_server::{archive, remote_fs, server};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -21,6 +23,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Sets a custom store database path")
                 .default_value("store.db"),
         )
+        .arg(
+            Arg::with_name("durability")
+                .long("durability")
+                .value_name("LEVEL")
+                .help(
+                    "Sets how hard the store tries to keep the committed changes on a power \
+                     loss: full syncs every commit, normal may lose the last commits and off \
+                     may corrupt the store",
+                )
+                .possible_values(&["full", "normal", "off"])
+                .default_value("full"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Reads the server configuration (such as the shares) from a TOML file"),
+        )
+        .arg(
+            Arg::with_name("metrics-addr")
+                .long("metrics-addr")
+                .value_name("ADDRESS")
+                .help("Exposes Prometheus metrics over HTTP on the given address")
+                .validator(offs::validators::check_address),
+        )
+        .arg(
+            Arg::with_name("webdav-addr")
+                .long("webdav-addr")
+                .value_name("ADDRESS")
+                .help("Serves the files over WebDAV on the given address")
+                .validator(offs::validators::check_address),
+        )
+        .arg(
+            Arg::with_name("sftp-addr")
+                .long("sftp-addr")
+                .value_name("ADDRESS")
+                .help("Serves the files over SFTP on the given address")
+                .validator(offs::validators::check_address),
+        )
+        .arg(
+            Arg::with_name("share-link-addr")
+                .long("share-link-addr")
+                .value_name("ADDRESS")
+                .help("Serves the files the share links point to over HTTP on the given address")
+                .validator(offs::validators::check_address),
+        )
+        .arg(
+            Arg::with_name("otlp-endpoint")
+                .long("otlp-endpoint")
+                .value_name("URL")
+                .help("Exports the traces to an OpenTelemetry collector"),
+        )
+        .arg(
+            Arg::with_name("no-normalize-names")
+                .long("no-normalize-names")
+                .help(
+                    "Keeps the file names in the Unicode form sent by the clients instead of NFC",
+                ),
+        )
+        .arg(
+            Arg::with_name("verbosity")
+                .short("v")
+                .multiple(true)
+                .help("Increase message verbosity"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .help("Silence all output"),
+        )
         .arg(
             Arg::with_name("ADDRESS")
                 .help("The address to listen on")
@@ -28,14 +100,122 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .default_value("0.0.0.0:10031")
                 .index(1),
         )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes the store contents into an archive")
+                .arg(
+                    Arg::with_name("ARCHIVE")
+                        .help("The path of the archive to create")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Restores the store contents from an archive")
+                .arg(
+                    Arg::with_name("ARCHIVE")
+                        .help("The path of the archive to restore")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import-dir")
+                .about("Copies an existing directory tree into the store")
+                .arg(
+                    Arg::with_name("tenant")
+                        .long("tenant")
+                        .value_name("NAME")
+                        .help("Imports the files into the tree of the tenant")
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .long("path")
+                        .value_name("PATH")
+                        .help("Imports the files into the directory, creating it if needed")
+                        .default_value("/"),
+                )
+                .arg(
+                    Arg::with_name("progress")
+                        .long("progress")
+                        .help("Shows the number of the files and bytes imported so far"),
+                )
+                .arg(
+                    Arg::with_name("SOURCE")
+                        .help("The directory to import")
+                        .required(true),
+                ),
+        )
         .get_matches();
 
-    let store = Store::new_server(matches.value_of("store").unwrap()).unwrap();
+    let verbose = matches.occurrences_of("verbosity") as usize;
+    let quiet = matches.is_present("quiet");
+    offs::telemetry::init_tracing(
+        "offs-server",
+        &[module_path!(), "offs"],
+        verbose,
+        quiet,
+        matches.value_of("otlp-endpoint"),
+    )?;
+
+    let config = match matches.value_of("config") {
+        Some(path) => Config::load(Path::new(path))?,
+        None => Config::default(),
+    };
+    if let Some(log_level) = config.log_level() {
+        offs::telemetry::set_log_level(log_level)?;
+    }
+    let mut store = Store::new_server(matches.value_of("store").unwrap()).unwrap();
+    let durability: Durability = matches.value_of("durability").unwrap().parse()?;
+    store.set_durability(durability)?;
 
     let address_str = matches.value_of("ADDRESS").unwrap();
     let address = address_str.to_socket_addrs().unwrap().next().unwrap();
 
-    server::run_server(store, address).await?;
+    let metrics_address = matches
+        .value_of("metrics-addr")
+        .map(|x| x.to_socket_addrs().unwrap().next().unwrap());
+    let frontends = Frontends {
+        webdav_address: matches
+            .value_of("webdav-addr")
+            .map(|x| x.to_socket_addrs().unwrap().next().unwrap()),
+        sftp_address: matches
+            .value_of("sftp-addr")
+            .map(|x| x.to_socket_addrs().unwrap().next().unwrap()),
+        share_link_address: matches
+            .value_of("share-link-addr")
+            .map(|x| x.to_socket_addrs().unwrap().next().unwrap()),
+    };
+    let normalize_names = !matches.is_present("no-normalize-names");
+
+    let result = match matches.subcommand() {
+        ("export", Some(sub_m)) => {
+            archive::export_store(store, Path::new(sub_m.value_of("ARCHIVE").unwrap()))
+        }
+        ("import", Some(sub_m)) => {
+            archive::import_store(store, Path::new(sub_m.value_of("ARCHIVE").unwrap()))
+        }
+        ("import-dir", Some(sub_m)) => remote_fs::import_dir(
+            store,
+            normalize_names,
+            Path::new(sub_m.value_of("SOURCE").unwrap()),
+            sub_m.value_of("tenant").unwrap(),
+            sub_m.value_of("path").unwrap(),
+            sub_m.is_present("progress"),
+        ),
+        _ => {
+            server::run_server(
+                store,
+                config,
+                address,
+                metrics_address,
+                frontends,
+                normalize_names,
+            )
+            .await
+        }
+    };
+    offs::telemetry::shutdown_tracing();
 
-    Ok(())
+    result
 }
@@ -0,0 +1,210 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use tracing::info;
+
+use offs::store::blob_cache::BlobCache;
+use offs::store::id_generator::RandomHexIdGenerator;
+use offs::store::Store;
+
+const METRICS_PATH: &str = "/metrics";
+
+pub struct Metrics {
+    registry: Registry,
+
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    blob_bytes_served: IntCounter,
+    journal_apply_failures: IntCounterVec,
+    store_size: IntGauge,
+    blob_cache_size: IntGauge,
+    blob_cache_hits: IntCounter,
+    blob_cache_misses: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("offs".to_owned()), None).unwrap();
+
+        let rpc_requests = IntCounterVec::new(
+            Opts::new("rpc_requests_total", "Number of RPC requests received"),
+            &["method"],
+        )
+        .unwrap();
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rpc_request_duration_seconds",
+                "Time spent handling RPC requests",
+            ),
+            &["method"],
+        )
+        .unwrap();
+        let blob_bytes_served = IntCounter::new(
+            "blob_bytes_served_total",
+            "Number of blob content bytes sent to the clients",
+        )
+        .unwrap();
+        let journal_apply_failures = IntCounterVec::new(
+            Opts::new(
+                "journal_apply_failures_total",
+                "Number of journals that could not be fully applied",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let store_size = IntGauge::new("store_size_bytes", "Size of the store database").unwrap();
+        let blob_cache_size = IntGauge::new(
+            "blob_cache_size_bytes",
+            "Size of the blobs held in the in-memory blob cache",
+        )
+        .unwrap();
+        let blob_cache_hits = IntCounter::new(
+            "blob_cache_hits_total",
+            "Number of blobs served from the in-memory blob cache",
+        )
+        .unwrap();
+        let blob_cache_misses = IntCounter::new(
+            "blob_cache_misses_total",
+            "Number of blobs read from the store database, as they were not in the blob cache",
+        )
+        .unwrap();
+
+        registry.register(Box::new(rpc_requests.clone())).unwrap();
+        registry.register(Box::new(rpc_duration.clone())).unwrap();
+        registry
+            .register(Box::new(blob_bytes_served.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(journal_apply_failures.clone()))
+            .unwrap();
+        registry.register(Box::new(store_size.clone())).unwrap();
+        registry
+            .register(Box::new(blob_cache_size.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blob_cache_hits.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(blob_cache_misses.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+
+            rpc_requests,
+            rpc_duration,
+            blob_bytes_served,
+            journal_apply_failures,
+            store_size,
+            blob_cache_size,
+            blob_cache_hits,
+            blob_cache_misses,
+        }
+    }
+
+    /// Registers an RPC request; the returned timer records its latency when dropped
+    pub fn start_rpc(&self, method: &str) -> HistogramTimer {
+        self.rpc_requests.with_label_values(&[method]).inc();
+        self.rpc_duration.with_label_values(&[method]).start_timer()
+    }
+
+    pub fn add_blob_bytes_served(&self, bytes: u64) {
+        self.blob_bytes_served.inc_by(bytes);
+    }
+
+    pub fn inc_journal_apply_failures(&self, reason: &str) {
+        self.journal_apply_failures
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn set_store_size(&self, size: u64) {
+        self.store_size.set(size as i64);
+    }
+
+    /// Updates the blob cache metrics from the totals counted by the cache itself
+    pub fn set_blob_cache_stats(&self, blob_cache: &BlobCache) {
+        self.blob_cache_size.set(blob_cache.size() as i64);
+        self.blob_cache_hits
+            .inc_by(blob_cache.hits().saturating_sub(self.blob_cache_hits.get()));
+        self.blob_cache_misses.inc_by(
+            blob_cache
+                .misses()
+                .saturating_sub(self.blob_cache_misses.get()),
+        );
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+
+        buffer
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+async fn handle_request(
+    request: Request<Body>,
+    metrics: Arc<Metrics>,
+    store: Arc<Store<RandomHexIdGenerator>>,
+    blob_cache: Arc<BlobCache>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET || request.uri().path() != METRICS_PATH {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+
+    if let Ok(size) = store.get_db_size() {
+        metrics.set_store_size(size);
+    }
+    metrics.set_blob_cache_stats(&blob_cache);
+
+    let response = Response::builder()
+        .header(
+            hyper::header::CONTENT_TYPE,
+            TextEncoder::new().format_type(),
+        )
+        .body(Body::from(metrics.encode()))
+        .unwrap();
+
+    Ok(response)
+}
+
+pub async fn run_metrics_server(
+    metrics: Arc<Metrics>,
+    store: Store<RandomHexIdGenerator>,
+    blob_cache: Arc<BlobCache>,
+    address: SocketAddr,
+) -> Result<(), hyper::Error> {
+    info!("Metrics available at http://{}{}", address, METRICS_PATH);
+
+    let store = Arc::new(store);
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        let store = store.clone();
+        let blob_cache = blob_cache.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(request, metrics.clone(), store.clone(), blob_cache.clone())
+            }))
+        }
+    });
+
+    hyper::Server::bind(&address).serve(make_service).await
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use offs::proto::filesystem::admin_server::Admin;
+use offs::proto::filesystem::{
+    ClientInfo, DedupStats, DirUsage, FileDedupStats, GetDedupStatsRequest, GetQuotaRequest,
+    ListClientsRequest, ListClientsResponse, ListQuotasRequest, ListQuotasResponse, QuotaInfo,
+    RevokeClientRequest, SetQuotaRequest,
+};
+use offs::store;
+
+use crate::metrics::Metrics;
+use crate::sessions::{ClientSession, SessionRegistry};
+
+/// Serves the RPCs administering the server, such as setting the quotas of the tenants.
+pub struct AdminServerImpl {
+    fs: super::RemoteFs,
+    metrics: Arc<Metrics>,
+    sessions: SessionRegistry,
+}
+
+impl AdminServerImpl {
+    pub(crate) fn new(
+        fs: super::RemoteFs,
+        metrics: Arc<Metrics>,
+        sessions: SessionRegistry,
+    ) -> Self {
+        Self {
+            fs,
+            metrics,
+            sessions,
+        }
+    }
+}
+
+fn make_quota_info(
+    tenant: String,
+    path: String,
+    quota: store::Quota,
+    usage: store::DirUsage,
+) -> QuotaInfo {
+    QuotaInfo {
+        tenant,
+        path,
+        max_size: quota.max_size,
+        max_files: quota.max_files,
+        usage: Some(DirUsage {
+            size: usage.size,
+            files: usage.files,
+            directories: usage.directories,
+        }),
+    }
+}
+
+fn make_client_info(session: ClientSession) -> ClientInfo {
+    ClientInfo {
+        client_id: session.client_id,
+        tenant: session.tenant,
+        address: session.address.map(|x| x.to_string()).unwrap_or_default(),
+        first_seen: session.first_seen,
+        last_seen: session.last_seen,
+        ops_per_minute: session.ops_per_minute,
+        revoked: session.revoked,
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminServerImpl {
+    async fn set_quota(
+        &self,
+        request: Request<SetQuotaRequest>,
+    ) -> Result<Response<QuotaInfo>, Status> {
+        let _timer = self.metrics.start_rpc("set_quota");
+        let req = request.into_inner();
+        let quota = store::Quota {
+            max_size: req.max_size,
+            max_files: req.max_files,
+        };
+
+        let mut fs = self.fs.session();
+        fs.set_quota(&req.tenant, &req.path, quota)?;
+        let (quota, usage) = fs.get_quota(&req.tenant, &req.path)?;
+
+        Ok(Response::new(make_quota_info(
+            req.tenant, req.path, quota, usage,
+        )))
+    }
+
+    async fn get_quota(
+        &self,
+        request: Request<GetQuotaRequest>,
+    ) -> Result<Response<QuotaInfo>, Status> {
+        let _timer = self.metrics.start_rpc("get_quota");
+        let req = request.into_inner();
+
+        let (quota, usage) = self.fs.session().get_quota(&req.tenant, &req.path)?;
+
+        Ok(Response::new(make_quota_info(
+            req.tenant, req.path, quota, usage,
+        )))
+    }
+
+    async fn list_quotas(
+        &self,
+        _request: Request<ListQuotasRequest>,
+    ) -> Result<Response<ListQuotasResponse>, Status> {
+        let _timer = self.metrics.start_rpc("list_quotas");
+
+        let quotas = self
+            .fs
+            .list_quotas()?
+            .into_iter()
+            .map(|(tenant, path, quota, usage)| make_quota_info(tenant, path, quota, usage))
+            .collect();
+
+        Ok(Response::new(ListQuotasResponse { quotas }))
+    }
+
+    async fn get_dedup_stats(
+        &self,
+        request: Request<GetDedupStatsRequest>,
+    ) -> Result<Response<DedupStats>, Status> {
+        let _timer = self.metrics.start_rpc("get_dedup_stats");
+        let req = request.into_inner();
+
+        let stats = self.fs.store.dedup_stats(req.limit)?;
+
+        Ok(Response::new(DedupStats {
+            logical_size: stats.logical_size,
+            physical_size: stats.physical_size,
+            blobs: stats.blobs,
+            files: stats
+                .files
+                .into_iter()
+                .map(|x| FileDedupStats {
+                    tenant: x.tenant,
+                    path: x.path,
+                    logical_size: x.logical_size,
+                    physical_size: x.physical_size,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn list_clients(
+        &self,
+        _request: Request<ListClientsRequest>,
+    ) -> Result<Response<ListClientsResponse>, Status> {
+        let _timer = self.metrics.start_rpc("list_clients");
+
+        let clients = self
+            .sessions
+            .list()
+            .into_iter()
+            .map(make_client_info)
+            .collect();
+
+        Ok(Response::new(ListClientsResponse { clients }))
+    }
+
+    async fn revoke_client(
+        &self,
+        request: Request<RevokeClientRequest>,
+    ) -> Result<Response<ClientInfo>, Status> {
+        let _timer = self.metrics.start_rpc("revoke_client");
+        let req = request.into_inner();
+
+        let session = self.sessions.revoke(&req.tenant, &req.client_id)?;
+        info!(
+            "Revoked client {} of tenant \"{}\"",
+            session.client_id, session.tenant
+        );
+
+        Ok(Response::new(make_client_info(session)))
+    }
+}
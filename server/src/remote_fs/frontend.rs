@@ -0,0 +1,183 @@
+//! Helpers shared by the frontends that expose the files by their paths instead of the IDs
+//! (WebDAV and SFTP).
+
+use std::iter;
+
+use hyper::Body;
+use itertools::Itertools;
+use tracing::error;
+
+use offs::errors::OperationResult;
+use offs::modify_op::{generate_op_id, ModifyOperation, ModifyOperationContent};
+use offs::names::normalize_name;
+use offs::now;
+use offs::store::{DirEntity, FileType};
+
+use super::locks::FileLocksGuard;
+use super::{RemoteFs, Share};
+
+/// Size of the pieces the files are read in
+const READ_SIZE: u32 = 1024 * 1024;
+
+/// Creates an operation on top of the current version of the file, attributed to the given
+/// replica in the version vectors, so that the clients notice it as concurrent with their own.
+pub fn make_operation(
+    dirent: &DirEntity,
+    replica_id: &str,
+    content: ModifyOperationContent,
+) -> ModifyOperation {
+    ModifyOperation {
+        id: dirent.id.clone(),
+        op_id: generate_op_id(),
+        timestamp: now(),
+        dirent_version: dirent.dirent_version,
+        content_version: dirent.content_version,
+        content_vector: dirent.content_vector.clone(),
+        dirent_vector: dirent.dirent_vector.clone(),
+        replica_id: replica_id.to_owned(),
+        operation: content,
+    }
+}
+
+impl RemoteFs {
+    pub fn lookup(&self, parent_id: &str, name: &str) -> OperationResult<Option<DirEntity>> {
+        let name = if self.normalize_names {
+            normalize_name(name)
+        } else {
+            name.into()
+        };
+
+        self.store.try_query_file_by_name(parent_id, &name)
+    }
+
+    /// Applies an operation once the files it modifies are locked, so that it is not
+    /// interleaved with the journals modifying the same files.
+    pub async fn apply_locked(
+        &self,
+        share: &Share,
+        operation: ModifyOperation,
+    ) -> OperationResult<DirEntity> {
+        let _locks = self.lock_operations(share, iter::once(&operation)).await;
+        let share = share.clone();
+
+        self.with_session(move |fs| fs.apply_operation(&share, operation))
+            .await
+    }
+
+    /// Locks the files the frontend modifies: the whole subtrees of `trees` (along with their
+    /// parent directories), e.g. to remove or move them, and the `files`, e.g. the
+    /// directories the files are created in.
+    pub async fn lock_trees(
+        &self,
+        trees: &[&DirEntity],
+        files: &[&DirEntity],
+    ) -> OperationResult<FileLocksGuard> {
+        let mut ids = files.iter().map(|x| x.id.clone()).collect_vec();
+        for dirent in trees {
+            ids.push(dirent.id.clone());
+            if dirent.stat.file_type == FileType::Directory {
+                ids.extend(self.list_tree(&dirent.id)?.into_iter().map(|x| x.id));
+            }
+        }
+        let children = trees.iter().map(|x| x.id.clone()).collect();
+
+        Ok(self.lock_files(ids, children).await)
+    }
+
+    /// Returns the share of the whole tree of the tenant, only writing to the store if the
+    /// tenant has not been seen before.
+    pub async fn get_tenant_share(&self, tenant: &str) -> OperationResult<Share> {
+        if let Some(share) = self.find_share(tenant, "")? {
+            return Ok(share);
+        }
+
+        let tenant = tenant.to_owned();
+        self.with_session(move |fs| fs.create_share(&tenant, ""))
+            .await
+    }
+
+    /// Returns the file at the given path within the share, if it exists.
+    pub fn resolve_path(
+        &self,
+        share: &Share,
+        path: &[String],
+    ) -> OperationResult<Option<DirEntity>> {
+        let mut dirent = self.store.query_file(share.root_id())?;
+        for name in path {
+            dirent = match self.lookup(&dirent.id, name)? {
+                Some(dirent) => dirent,
+                None => return Ok(None),
+            };
+        }
+
+        Ok(Some(dirent))
+    }
+
+    pub fn read_file(
+        &self,
+        dirent: &DirEntity,
+        offset: u64,
+        size: u64,
+    ) -> OperationResult<Vec<u8>> {
+        let mut store = self.store.clone();
+        let end = dirent.stat.size.min(offset.saturating_add(size));
+        let mut data =
+            Vec::with_capacity(end.saturating_sub(offset).min(READ_SIZE as u64) as usize);
+
+        while offset + (data.len() as u64) < end {
+            let position = offset + data.len() as u64;
+            let chunk = store.read(
+                &dirent.id,
+                position as i64,
+                (end - position).min(READ_SIZE as u64) as u32,
+            )?;
+            if chunk.is_empty() {
+                break;
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(data)
+    }
+
+    /// Returns a body streaming the file, read piece by piece as the client receives it, so
+    /// that a huge (e.g. sparse) file is never held in memory. The body is aborted if the file
+    /// cannot be read, as its length has already been sent by then.
+    pub fn stream_file(&self, dirent: &DirEntity) -> Body {
+        let (mut sender, body) = Body::channel();
+        let store = self.store.clone();
+        let id = dirent.id.clone();
+        let size = dirent.stat.size;
+
+        tokio::spawn(async move {
+            let mut position = 0;
+            while position < size {
+                let mut store = store.clone();
+                let file_id = id.clone();
+                let length = (size - position).min(READ_SIZE as u64) as u32;
+                let chunk = tokio::task::spawn_blocking(move || {
+                    store.read(&file_id, position as i64, length)
+                })
+                .await;
+
+                let chunk = match chunk {
+                    Ok(Ok(chunk)) if !chunk.is_empty() => chunk,
+                    // Also when the file has been truncated in the meantime
+                    result => {
+                        let error = result.map(|x| x.map(|_| "no data"));
+                        error!("Could not stream file {}: {:?}", id, error);
+                        sender.abort();
+                        return;
+                    }
+                };
+                position += chunk.len() as u64;
+                if sender.send_data(chunk).await.is_err() {
+                    // The client is gone
+                    return;
+                }
+            }
+        });
+
+        body
+    }
+}
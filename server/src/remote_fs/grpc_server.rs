@@ -1,32 +1,387 @@
-use std::ops::DerefMut;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::iter;
+use std::mem;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
+use hyper::body::Bytes;
 use itertools::Itertools;
-use tokio::sync::{mpsc, RwLock};
+use prost::Message;
+use ring::digest;
+use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 
+use offs::errors::{
+    JournalApplyData, JournalApplyError, JournalApplyResult, JournalOperationStatus,
+    OperationError, OperationErrorType, OperationResult,
+};
 use offs::modify_op;
 use offs::modify_op::ModifyOperationContent;
-use offs::modify_op_handler::OperationApplier;
+use offs::proto::converters::ProtoConversionError;
 use offs::proto::filesystem::remote_fs_server::RemoteFs;
 use offs::proto::filesystem::{
-    ApplyJournalRequest, ApplyJournalResponse, Blob, DirEntity, GetBlobsRequest,
-    GetMissingBlobsRequest, GetMissingBlobsResult, ListChunksRequest, ListChunksResult,
-    ListRequest, ModifyOperation,
+    begin_journal_apply_response, ApplyJournalRequest, ApplyJournalResponse,
+    BeginJournalApplyResponse, Blob, CommitJournalApplyRequest, CreateShareLinkRequest, DirEntity,
+    DirUsage, GetBlobsRequest, GetDirEntitiesRequest, GetDirEntitiesResponse, GetDirUsageRequest,
+    GetMissingBlobsRequest, GetMissingBlobsResult, GetServerInfoRequest, ListChunksRequest,
+    ListChunksResult, ListRequest, ListSidecarsRequest, ListSidecarsResponse, MissingBlobsError,
+    ModifyOperation, ServerInfo, ServerLimits, SetSidecarRequest, ShareLink,
+};
+use offs::protocol::{
+    FEATURE_BLAKE3_BLOB_IDS, FEATURE_DIR_USAGE, FEATURE_GET_DIR_ENTITIES, FEATURE_LIST_PAGES,
+    FEATURE_LIST_TREE, FEATURE_SHARE_LINKS, FEATURE_SIDECARS, FEATURE_STAGED_JOURNAL_APPLY,
+    FEATURE_STREAMED_JOURNAL_APPLY, FEATURE_VALIDATE_JOURNAL, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
 };
+use offs::store::id_generator::{IdGenerator, RandomHexIdGenerator};
+use offs::store::{trim_trailing_zeros, BlobHash, Sidecar};
+use offs::{now, BLOB_SIZE, CLIENT_ID_HEADER_KEY, PROJ_VERSION};
+
+use super::validation::{validate_name, MAX_NAME_LENGTH, MAX_PATH_DEPTH, MAX_SYMLINK_LENGTH};
+use super::{journal_blob_hashes, journal_blob_ids, Share, ShareLinkSigner};
+use crate::auth::Tenant;
+use crate::limits::{check_limit, SharedLimits};
+use crate::metrics::Metrics;
+
+/// How long a staged journal waits for being committed before it is discarded.
+const STAGED_JOURNAL_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// How often the staged journals are checked for having timed out.
+const STAGED_JOURNAL_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of the files of a directory read from the store at once when listing it.
+const LIST_PAGE_SIZE: u32 = 1000;
+/// Number of the blobs read from the store at once when streaming them, so that only a few
+/// batches are held in memory, however many blobs are asked for.
+const GET_BLOBS_BATCH_SIZE: usize = 16;
+/// Total size of the blobs of a streamed journal stored at once, so that only a single batch
+/// is held in memory, however large the journal is.
+const STORE_BLOBS_BATCH_SIZE: usize = 16 * 1024 * 1024;
 
 pub struct RemoteFsServerImpl {
-    fs: RwLock<super::RemoteFs>,
+    fs: super::RemoteFs,
+    metrics: Arc<Metrics>,
+    share_paths: BTreeMap<String, String>,
+    /// The shares already resolved, keyed by the tenant and share name
+    shares: Mutex<HashMap<(String, String), Share>>,
+    /// The journals waiting to be committed, keyed by their staging IDs
+    staged_journals: Arc<StagedJournals>,
+    limits: SharedLimits,
+    share_links: Option<ShareLinkSigner>,
 }
 
 impl RemoteFsServerImpl {
-    pub fn new(fs: super::RemoteFs) -> Self {
+    pub fn new(
+        fs: super::RemoteFs,
+        metrics: Arc<Metrics>,
+        share_paths: BTreeMap<String, String>,
+        limits: SharedLimits,
+        share_links: Option<ShareLinkSigner>,
+    ) -> Self {
+        let staged_journals: Arc<StagedJournals> = Default::default();
+        tokio::spawn(prune_staged_journals(Arc::downgrade(&staged_journals)));
+
         Self {
-            fs: RwLock::new(fs),
+            fs,
+            metrics,
+            share_paths,
+            shares: Default::default(),
+            staged_journals,
+            limits,
+            share_links,
+        }
+    }
+
+    /// Creates the service with the default limits, without any shares or share links, e.g. to
+    /// serve a store kept in memory to the clients under test.
+    pub fn with_defaults(fs: super::RemoteFs) -> Self {
+        Self::new(
+            fs,
+            Arc::new(Metrics::new()),
+            BTreeMap::new(),
+            SharedLimits::default(),
+            None,
+        )
+    }
+
+    /// Returns the share of the tenant with the given name (or the tenant's whole tree if the
+    /// name is empty), creating its root directory on first use.
+    async fn get_share(&self, tenant: String, name: &str) -> OperationResult<Share> {
+        let key = (tenant, name.to_owned());
+        let cached = self.shares.lock().unwrap().get(&key).cloned();
+        if let Some(share) = cached {
+            return Ok(share);
+        }
+
+        let path = if name.is_empty() {
+            ""
+        } else {
+            self.share_paths
+                .get(name)
+                .ok_or_else(|| OperationError::share_does_not_exist(name))?
+        };
+        let share = self.fs.session().create_share(&key.0, path)?;
+        self.shares.lock().unwrap().insert(key, share.clone());
+
+        Ok(share)
+    }
+
+    fn check_operation(&self, operation: &modify_op::ModifyOperation) -> OperationResult<()> {
+        match &operation.operation {
+            ModifyOperationContent::WriteOperation(op) => check_limit(
+                "Write size",
+                op.data.len(),
+                self.limits.get().max_write_size,
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_journal(&self, operations: &[modify_op::ModifyOperation]) -> OperationResult<()> {
+        check_limit(
+            "Number of journal operations",
+            operations.len(),
+            self.limits.get().max_journal_operations,
+        )?;
+
+        operations.iter().try_for_each(|x| self.check_operation(x))
+    }
+
+    /// Checks the blobs uploaded with a journal: there is no use for more of them than the
+    /// chunks reference.
+    fn check_journal_blobs(
+        &self,
+        referenced: usize,
+        count: usize,
+        size: usize,
+    ) -> OperationResult<()> {
+        check_limit("Number of journal blobs", count, referenced)?;
+        check_limit(
+            "Size of journal blobs",
+            size,
+            self.limits.get().max_journal_blobs_size,
+        )
+    }
+
+    /// Stores a batch of the blobs uploaded with a journal, hashed with each of the functions,
+    /// and returns their IDs.
+    async fn store_journal_blobs(
+        &self,
+        hashes: &HashSet<BlobHash>,
+        blobs: Vec<Bytes>,
+    ) -> OperationResult<HashSet<String>> {
+        if blobs.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let hashes = hashes.clone();
+        self.fs
+            .with_session(move |fs| fs.store_journal_blobs(&hashes, blobs))
+            .await
+    }
+
+    /// Stores the blobs of the journal and keeps the journal until it is committed by
+    /// `commit_journal_apply`. `uploaded` are the IDs of the blobs already stored while the
+    /// journal was being received.
+    async fn stage_journal(
+        &self,
+        tenant: String,
+        client_id: String,
+        req: ApplyJournalRequest,
+        mut uploaded: HashSet<String>,
+    ) -> Result<BeginJournalApplyResponse, Status> {
+        if req.validate_only {
+            return Err(Status::invalid_argument(
+                "A journal cannot be validated in two phases",
+            ));
+        }
+        let size = req
+            .operations
+            .iter()
+            .map(Message::encoded_len)
+            .sum::<usize>()
+            + req.chunks.iter().map(Message::encoded_len).sum::<usize>();
+        let replica_id = derive_replica_id(&tenant, &client_id);
+        let share = self.get_share(tenant.clone(), &req.share).await?;
+        let operations = convert_operations(req.operations, &replica_id)?;
+        self.check_journal(&operations)?;
+        let chunks: Vec<Vec<String>> = req.chunks.into_iter().map(|x| x.into()).collect_vec();
+        self.check_journal_blobs(
+            journal_blob_ids(&chunks).len(),
+            req.blobs.len(),
+            req.blobs.iter().map(Bytes::len).sum(),
+        )?;
+        let (chunks, result) = {
+            let tenant = share.tenant().to_owned();
+            let blobs = req.blobs;
+            self.fs
+                .with_session(move |fs| -> OperationResult<_> {
+                    let transaction = fs.transaction()?;
+
+                    let hashes = journal_blob_hashes(&chunks);
+                    uploaded.extend(fs.store_journal_blobs(&hashes, blobs)?);
+                    let result = fs.find_missing_blobs(&tenant, &chunks, &uploaded)?;
+                    if result.is_ok() {
+                        transaction.commit()?;
+                    }
+
+                    Ok((chunks, result))
+                })
+                .await?
+        };
+
+        let response = match result {
+            Ok(()) => {
+                let staging_id = RandomHexIdGenerator::new().generate_id();
+                let mut staged_journals = self.staged_journals.lock().unwrap();
+                let staged_size: usize = staged_journals
+                    .values()
+                    .filter(|x| x.tenant == tenant && x.client_id == client_id)
+                    .map(|x| x.size)
+                    .sum();
+                check_limit(
+                    "Size of the staged journals",
+                    staged_size + size,
+                    self.limits.get().max_staged_journals_size,
+                )?;
+
+                let staged_journal = StagedJournal {
+                    tenant,
+                    client_id,
+                    share: req.share,
+                    operations,
+                    chunks,
+                    size,
+                    staged_at: Instant::now(),
+                };
+                staged_journals.insert(staging_id.clone(), staged_journal);
+
+                BeginJournalApplyResponse {
+                    staging_id,
+                    error: None,
+                }
+            }
+            Err(JournalApplyError::MissingBlobs(ids)) => {
+                self.metrics.inc_journal_apply_failures("missing_blobs");
+
+                BeginJournalApplyResponse {
+                    staging_id: String::new(),
+                    error: Some(begin_journal_apply_response::Error::MissingBlobs(
+                        MissingBlobsError { ids },
+                    )),
+                }
+            }
+        };
+
+        Ok(response)
+    }
+
+    fn record_journal_result(&self, result: &JournalApplyResult) {
+        match result {
+            Ok(data) => {
+                let statuses = &data.statuses;
+                if statuses.contains(&JournalOperationStatus::Conflicted) {
+                    self.metrics.inc_journal_apply_failures("conflicting_files");
+                }
+                if statuses
+                    .iter()
+                    .any(|x| matches!(x, JournalOperationStatus::Rejected(_)))
+                {
+                    self.metrics.inc_journal_apply_failures("invalid_journal");
+                }
+            }
+            Err(JournalApplyError::MissingBlobs(_)) => {
+                self.metrics.inc_journal_apply_failures("missing_blobs");
+            }
         }
     }
 }
 
+/// Journal whose blobs have been uploaded by `begin_journal_apply`, waiting to be committed.
+struct StagedJournal {
+    tenant: String,
+    /// ID of the client which has staged the journal, empty if it has not identified itself
+    client_id: String,
+    share: String,
+    operations: Vec<modify_op::ModifyOperation>,
+    chunks: Vec<Vec<String>>,
+    /// Encoded size of the operations and chunks, counted towards the limit of the client
+    size: usize,
+    staged_at: Instant,
+}
+
+type StagedJournals = Mutex<HashMap<String, StagedJournal>>;
+
+/// Discards the staged journals which have not been committed in time, until the server
+/// owning them is dropped.
+async fn prune_staged_journals(staged_journals: Weak<StagedJournals>) {
+    let mut interval = tokio::time::interval(STAGED_JOURNAL_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match staged_journals.upgrade() {
+            Some(staged_journals) => staged_journals
+                .lock()
+                .unwrap()
+                .retain(|_, x| x.staged_at.elapsed() < STAGED_JOURNAL_TIMEOUT),
+            None => return,
+        }
+    }
+}
+
+fn get_tenant<T>(request: &Request<T>) -> String {
+    request
+        .extensions()
+        .get::<Tenant>()
+        .cloned()
+        .unwrap_or_default()
+        .0
+}
+
+fn get_client_id<T>(request: &Request<T>) -> String {
+    request
+        .metadata()
+        .get(CLIENT_ID_HEADER_KEY)
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or_default()
+        .to_owned()
+}
+
+/// Returns the ID the modifications made by the client are attributed to in the version vectors.
+/// It is derived from the tenant and the client ID of the session instead of being taken from the
+/// operations, so that a client can pose neither as another tenant's client nor as the frontends
+/// of the server; empty if the client has not identified itself, in which case the content
+/// versions are compared instead.
+fn derive_replica_id(tenant: &str, client_id: &str) -> String {
+    if client_id.is_empty() {
+        return String::new();
+    }
+
+    let digest = digest::digest(
+        &digest::SHA256,
+        format!("{}\0{}", tenant, client_id).as_bytes(),
+    );
+    digest.as_ref()[..16]
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .join("")
+}
+
+/// Converts the operations sent by the client, attributing them to its replica.
+fn convert_operations(
+    operations: Vec<ModifyOperation>,
+    replica_id: &str,
+) -> Result<Vec<modify_op::ModifyOperation>, ProtoConversionError> {
+    operations
+        .into_iter()
+        .map(|x| {
+            Ok(modify_op::ModifyOperation {
+                replica_id: replica_id.to_owned(),
+                ..x.try_into()?
+            })
+        })
+        .try_collect()
+}
+
 #[tonic::async_trait]
 impl RemoteFs for RemoteFsServerImpl {
     type ListStream = ReceiverStream<Result<DirEntity, Status>>;
@@ -35,19 +390,79 @@ impl RemoteFs for RemoteFsServerImpl {
         &self,
         request: Request<ListRequest>,
     ) -> Result<Response<Self::ListStream>, Status> {
+        let _timer = self.metrics.start_rpc("list");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
         let (tx, rx) = mpsc::channel(4);
-        let files = self
-            .fs
-            .read()
-            .await
-            .store
-            .list_files(&request.into_inner().id)?
-            .into_iter()
-            .map(|x| DirEntity::from(x));
+        let fs = self.fs.clone();
+        let id = share.to_store_id(&req.id).to_owned();
+        fs.check_in_share(&share, &id)?;
+
+        // The directory is read in pages, so that the huge ones are never held in memory as a
+        // whole
+        tokio::spawn(async move {
+            let mut start_after = req.start_after;
+            let mut remaining = if req.limit == 0 { u32::MAX } else { req.limit };
+
+            while remaining > 0 {
+                let page_size = remaining.min(LIST_PAGE_SIZE);
+                let files = match fs.store.list_files_page(&id, &start_after, page_size) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        let _ = tx.send(Err(e.into())).await;
+                        break;
+                    }
+                };
+                let is_last = files.len() < page_size as usize;
+                remaining -= files.len() as u32;
+                if let Some(last) = files.last() {
+                    start_after = last.name.clone();
+                }
+
+                for file in files {
+                    let file = DirEntity::from(share.to_client_dirent(file));
+                    if tx.send(Ok(file)).await.is_err() {
+                        // The client has gone away
+                        return;
+                    }
+                }
+                if is_last {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ListTreeStream = ReceiverStream<Result<DirEntity, Status>>;
+
+    async fn list_tree(
+        &self,
+        request: Request<ListRequest>,
+    ) -> Result<Response<Self::ListTreeStream>, Status> {
+        let _timer = self.metrics.start_rpc("list_tree");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let (tx, rx) = mpsc::channel(4);
+        let files = {
+            let fs = &self.fs;
+            let id = share.to_store_id(&req.id);
+            fs.check_in_share(&share, id)?;
+
+            fs.list_tree(id)?
+        }
+        .into_iter()
+        .map(move |x| DirEntity::from(share.to_client_dirent(x)));
 
         tokio::spawn(async move {
             for file in files {
-                tx.send(Ok(file)).await.unwrap();
+                if tx.send(Ok(file)).await.is_err() {
+                    // The client has gone away
+                    break;
+                }
             }
         });
 
@@ -58,12 +473,17 @@ impl RemoteFs for RemoteFsServerImpl {
         &self,
         request: Request<ListChunksRequest>,
     ) -> Result<Response<ListChunksResult>, Status> {
-        let chunks = self
-            .fs
-            .read()
-            .await
-            .store
-            .get_chunks(&request.into_inner().id)?;
+        let _timer = self.metrics.start_rpc("list_chunks");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let chunks = {
+            let fs = &self.fs;
+            let id = share.to_store_id(&req.id);
+            fs.check_in_share(&share, id)?;
+
+            fs.store.get_chunks(id)?
+        };
 
         let resp = ListChunksResult {
             blob_id: chunks.into(),
@@ -72,25 +492,204 @@ impl RemoteFs for RemoteFsServerImpl {
         Ok(Response::new(resp))
     }
 
+    async fn get_dir_entities(
+        &self,
+        request: Request<GetDirEntitiesRequest>,
+    ) -> Result<Response<GetDirEntitiesResponse>, Status> {
+        let _timer = self.metrics.start_rpc("get_dir_entities");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        check_limit(
+            "Number of files",
+            req.ids.len(),
+            self.limits.get().max_files_per_request,
+        )?;
+        let share = self.get_share(tenant, &req.share).await?;
+
+        let mut resp = GetDirEntitiesResponse::default();
+        let fs = &self.fs;
+        for id in req.ids {
+            let store_id = share.to_store_id(&id);
+            match fs.check_in_share(&share, store_id) {
+                Ok(()) => {
+                    let dirent = fs.store.query_file(store_id)?;
+                    resp.dir_entities
+                        .push(DirEntity::from(share.to_client_dirent(dirent)));
+                }
+                Err(e) if matches!(e.error_type, OperationErrorType::FileDoesNotExist) => {
+                    resp.missing_ids.push(id);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Response::new(resp))
+    }
+
+    async fn get_dir_usage(
+        &self,
+        request: Request<GetDirUsageRequest>,
+    ) -> Result<Response<DirUsage>, Status> {
+        let _timer = self.metrics.start_rpc("get_dir_usage");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let usage = {
+            let fs = &self.fs;
+            let id = share.to_store_id(&req.id);
+            fs.check_in_share(&share, id)?;
+
+            fs.store.get_dir_usage(id)?
+        };
+
+        let resp = DirUsage {
+            size: usage.size,
+            files: usage.files,
+            directories: usage.directories,
+        };
+
+        Ok(Response::new(resp))
+    }
+
+    async fn create_share_link(
+        &self,
+        request: Request<CreateShareLinkRequest>,
+    ) -> Result<Response<ShareLink>, Status> {
+        let _timer = self.metrics.start_rpc("create_share_link");
+        let signer = self.share_links.as_ref().ok_or_else(|| {
+            OperationError::unsupported_protocol("the share links are disabled on the server")
+        })?;
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let dirent = {
+            let fs = &self.fs;
+            let id = share.to_store_id(&req.id);
+            fs.check_in_share(&share, id)?;
+
+            fs.store.query_file(id)?
+        };
+
+        let expires_at = now()
+            .sec
+            .saturating_add(req.expires_in.try_into().unwrap_or(i64::MAX));
+        let resp = ShareLink {
+            url: signer.create_link(&dirent, expires_at),
+            expires_at,
+        };
+
+        Ok(Response::new(resp))
+    }
+
+    async fn list_sidecars(
+        &self,
+        request: Request<ListSidecarsRequest>,
+    ) -> Result<Response<ListSidecarsResponse>, Status> {
+        let _timer = self.metrics.start_rpc("list_sidecars");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let sidecars = {
+            let fs = &self.fs;
+            let id = share.to_store_id(&req.id);
+            fs.check_in_share(&share, id)?;
+
+            fs.store.get_sidecars(id)?
+        };
+
+        let resp = ListSidecarsResponse {
+            sidecars: sidecars.into_iter().map(Into::into).collect(),
+        };
+
+        Ok(Response::new(resp))
+    }
+
+    async fn set_sidecar(
+        &self,
+        request: Request<SetSidecarRequest>,
+    ) -> Result<Response<ListSidecarsResponse>, Status> {
+        let _timer = self.metrics.start_rpc("set_sidecar");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        validate_name(&req.name)?;
+        check_limit("Sidecar size", req.data.len(), BLOB_SIZE)?;
+        let share = self.get_share(tenant, &req.share).await?;
+        let sidecars = self
+            .fs
+            .with_session(move |fs| -> OperationResult<_> {
+                let id = share.to_store_id(&req.id);
+                fs.check_in_share(&share, id)?;
+                let transaction = fs.transaction()?;
+
+                if req.data.is_empty() {
+                    fs.store.remove_sidecar(id, &req.name)?;
+                } else {
+                    let sidecar = Sidecar {
+                        name: req.name,
+                        blob: fs.store.add_blob(&req.data)?,
+                        size: req.data.len() as u64,
+                        content_version: fs.store.query_file(id)?.content_version,
+                    };
+                    fs.store.set_sidecar(id, &sidecar)?;
+                }
+                let sidecars = fs.store.get_sidecars(id)?;
+                transaction.commit()?;
+
+                Ok(sidecars)
+            })
+            .await?;
+
+        let resp = ListSidecarsResponse {
+            sidecars: sidecars.into_iter().map(Into::into).collect(),
+        };
+
+        Ok(Response::new(resp))
+    }
+
     type GetBlobsStream = ReceiverStream<Result<Blob, Status>>;
 
     async fn get_blobs(
         &self,
         request: Request<GetBlobsRequest>,
     ) -> Result<Response<Self::GetBlobsStream>, Status> {
+        let _timer = self.metrics.start_rpc("get_blobs");
+        let tenant = get_tenant(&request);
+        let ids = request.into_inner().id;
+        check_limit(
+            "Number of blobs",
+            ids.len(),
+            self.limits.get().max_blobs_per_request,
+        )?;
         let (tx, rx) = mpsc::channel(4);
-        let blobs = self
-            .fs
-            .read()
-            .await
-            .store
-            .get_blobs(request.into_inner().id)?
-            .into_iter()
-            .map(|(k, v)| Blob { id: k, content: v });
+        let fs = self.fs.clone();
+        let metrics = self.metrics.clone();
+        let owned = fs.store.get_tenant_blobs(&tenant, &ids)?;
+        let ids = ids.into_iter().filter(|x| owned.contains(x)).collect_vec();
 
+        // The blobs are read in batches as the client receives them, rather than loaded into
+        // memory all at once
         tokio::spawn(async move {
-            for blob in blobs {
-                tx.send(Ok(blob)).await.unwrap();
+            for batch in ids.chunks(GET_BLOBS_BATCH_SIZE) {
+                let blobs = match fs.store.get_blobs(batch) {
+                    Ok(blobs) => blobs,
+                    Err(e) => {
+                        let _ = tx.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+
+                for (id, content) in blobs {
+                    let blob = Blob {
+                        id,
+                        length: content.len() as u64,
+                        content: content.slice(..trim_trailing_zeros(&content).len()),
+                    };
+                    metrics.add_blob_bytes_served(blob.content.len() as u64);
+                    if tx.send(Ok(blob)).await.is_err() {
+                        // The client has gone away
+                        return;
+                    }
+                }
             }
         });
 
@@ -101,53 +700,198 @@ impl RemoteFs for RemoteFsServerImpl {
         &self,
         request: Request<ModifyOperation>,
     ) -> Result<Response<DirEntity>, Status> {
+        let _timer = self.metrics.start_rpc("apply_operation");
+        let tenant = get_tenant(&request);
+        let replica_id = derive_replica_id(&tenant, &get_client_id(&request));
+        let req = request.into_inner();
+        let share = self.get_share(tenant, &req.share).await?;
+        let operation = modify_op::ModifyOperation {
+            replica_id,
+            ..req.try_into()?
+        };
+        self.check_operation(&operation)?;
+        let _locks = self
+            .fs
+            .lock_operations(&share, iter::once(&operation))
+            .await;
         let dir_entity = {
-            let mut fs = self.fs.write().await;
-            let transaction = fs.store.transaction();
-
-            let operation: offs::modify_op::ModifyOperation = request.into_inner().into();
-            let dir_entity = fs.store.try_query_file(&operation.id)?;
-
-            let new_id = OperationApplier::apply_operation(fs.deref_mut(), &operation)?;
-
-            let dir_entity = match operation.operation {
-                ModifyOperationContent::RemoveFileOperation(_)
-                | ModifyOperationContent::RemoveDirectoryOperation(_) => dir_entity.unwrap(),
-                _ => fs.store.query_file(&new_id)?,
-            };
-
-            transaction.commit().unwrap();
-
-            dir_entity
+            let share = share.clone();
+            self.fs
+                .with_session(move |fs| fs.apply_operation(&share, operation))
+                .await?
         };
 
-        Ok(Response::new(dir_entity.into()))
+        Ok(Response::new(share.to_client_dirent(dir_entity).into()))
     }
 
     async fn apply_journal(
         &self,
         request: Request<ApplyJournalRequest>,
     ) -> Result<Response<ApplyJournalResponse>, Status> {
+        let _timer = self.metrics.start_rpc("apply_journal");
+        let tenant = get_tenant(&request);
+        let replica_id = derive_replica_id(&tenant, &get_client_id(&request));
         let req = request.into_inner();
-        let converted_operations: Vec<modify_op::ModifyOperation> =
-            req.operations.into_iter().map(|x| x.into()).collect_vec();
+        let share = self.get_share(tenant, &req.share).await?;
+        let converted_operations = convert_operations(req.operations, &replica_id)?;
+        self.check_journal(&converted_operations)?;
         let converted_chunks: Vec<Vec<String>> =
             req.chunks.into_iter().map(|x| x.into()).collect_vec();
-        let converted_blobs: Vec<Vec<u8>> = req.blobs.into();
+        let blobs = req.blobs;
+        self.check_journal_blobs(
+            journal_blob_ids(&converted_chunks).len(),
+            blobs.len(),
+            blobs.iter().map(Bytes::len).sum(),
+        )?;
+        let _locks = self.fs.lock_operations(&share, &converted_operations).await;
+        if req.validate_only {
+            let statuses = self
+                .fs
+                .with_session(move |fs| -> OperationResult<_> {
+                    // Rolled back when dropped, so nothing is stored
+                    let _transaction = fs.transaction()?;
+
+                    Ok(fs
+                        .commit_journal(&share, converted_operations, converted_chunks)?
+                        .statuses)
+                })
+                .await?;
+            let result: JournalApplyResult = Ok(JournalApplyData {
+                assigned_ids: Vec::new(),
+                dir_entities: Vec::new(),
+                statuses,
+            });
+
+            return Ok(Response::new(result.into()));
+        }
+        let result = self
+            .fs
+            .with_session(move |fs| {
+                fs.apply_full_journal(&share, converted_operations, converted_chunks, blobs)
+                    .map(|result| {
+                        result.map(|mut data| {
+                            data.dir_entities = data
+                                .dir_entities
+                                .into_iter()
+                                .map(|x| share.to_client_dirent(x))
+                                .collect();
+                            data
+                        })
+                    })
+            })
+            .await?;
+
+        self.record_journal_result(&result);
+
+        Ok(Response::new(result.into()))
+    }
+
+    async fn begin_journal_apply(
+        &self,
+        request: Request<ApplyJournalRequest>,
+    ) -> Result<Response<BeginJournalApplyResponse>, Status> {
+        let _timer = self.metrics.start_rpc("begin_journal_apply");
+        let tenant = get_tenant(&request);
+        let client_id = get_client_id(&request);
+
+        Ok(Response::new(
+            self.stage_journal(tenant, client_id, request.into_inner(), HashSet::new())
+                .await?,
+        ))
+    }
+
+    async fn begin_streamed_journal_apply(
+        &self,
+        request: Request<Streaming<ApplyJournalRequest>>,
+    ) -> Result<Response<BeginJournalApplyResponse>, Status> {
+        let _timer = self.metrics.start_rpc("begin_streamed_journal_apply");
+        let tenant = get_tenant(&request);
+        let client_id = get_client_id(&request);
+        let mut stream = request.into_inner();
 
-        let result = {
-            let mut fs = self.fs.write().await;
-            let transaction = fs.store.transaction();
+        let mut req = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("The journal has not been sent"))?;
+        // The blobs are stored as they arrive, a batch at a time, instead of being held in
+        // memory until the whole journal has been received
+        let chunks: Vec<Vec<String>> = req.chunks.iter().map(|x| x.clone().into()).collect_vec();
+        let referenced = journal_blob_ids(&chunks).len();
+        let hashes = journal_blob_hashes(&chunks);
+        let mut uploaded = HashSet::new();
+        let (mut count, mut size) = (0, 0);
+        let mut batch = Vec::new();
+        let mut batch_size = 0;
+        let mut received = mem::take(&mut req.blobs);
+        loop {
+            for blob in received {
+                count += 1;
+                size += blob.len();
+                self.check_journal_blobs(referenced, count, size)?;
 
-            let result =
-                fs.apply_full_journal(converted_operations, converted_chunks, converted_blobs);
-            if result.is_ok() {
-                transaction.commit().unwrap();
+                batch_size += blob.len();
+                batch.push(blob);
+                if batch_size >= STORE_BLOBS_BATCH_SIZE {
+                    uploaded.extend(
+                        self.store_journal_blobs(&hashes, mem::take(&mut batch))
+                            .await?,
+                    );
+                    batch_size = 0;
+                }
             }
 
-            result
+            match stream.message().await? {
+                Some(message) => received = message.blobs,
+                None => break,
+            }
+        }
+        uploaded.extend(self.store_journal_blobs(&hashes, batch).await?);
+
+        Ok(Response::new(
+            self.stage_journal(tenant, client_id, req, uploaded).await?,
+        ))
+    }
+
+    async fn commit_journal_apply(
+        &self,
+        request: Request<CommitJournalApplyRequest>,
+    ) -> Result<Response<ApplyJournalResponse>, Status> {
+        let _timer = self.metrics.start_rpc("commit_journal_apply");
+        let tenant = get_tenant(&request);
+        let req = request.into_inner();
+        let share = self.get_share(tenant.clone(), &req.share).await?;
+        let staged_journal = {
+            let mut staged_journals = self.staged_journals.lock().unwrap();
+            match staged_journals.get(&req.staging_id) {
+                Some(x) if x.tenant == tenant && x.share == req.share => {
+                    staged_journals.remove(&req.staging_id).unwrap()
+                }
+                _ => return Err(Status::not_found("The journal is not staged")),
+            }
         };
 
+        let _locks = self
+            .fs
+            .lock_operations(&share, &staged_journal.operations)
+            .await;
+        let data = self
+            .fs
+            .with_session(move |fs| -> OperationResult<_> {
+                let mut data =
+                    fs.commit_journal(&share, staged_journal.operations, staged_journal.chunks)?;
+                data.dir_entities = data
+                    .dir_entities
+                    .into_iter()
+                    .map(|x| share.to_client_dirent(x))
+                    .collect();
+
+                Ok(data)
+            })
+            .await?;
+
+        let result = Ok(data);
+        self.record_journal_result(&result);
+
         Ok(Response::new(result.into()))
     }
 
@@ -155,12 +899,18 @@ impl RemoteFs for RemoteFsServerImpl {
         &self,
         request: Request<GetMissingBlobsRequest>,
     ) -> Result<Response<GetMissingBlobsResult>, Status> {
-        let chunks = self
-            .fs
-            .read()
-            .await
-            .store
-            .get_missing_blobs(request.into_inner().id)?;
+        let _timer = self.metrics.start_rpc("get_missing_blobs");
+        let tenant = get_tenant(&request);
+        let ids = request.into_inner().id;
+        check_limit(
+            "Number of blobs",
+            ids.len(),
+            self.limits.get().max_blobs_per_request,
+        )?;
+        // Blobs that only belong to other tenants are reported as missing, so that the client
+        // proves it actually has their contents by uploading them
+        let owned = self.fs.store.get_tenant_blobs(&tenant, &ids)?;
+        let chunks = ids.into_iter().filter(|x| !owned.contains(x)).collect_vec();
 
         let resp = GetMissingBlobsResult {
             blob_id: chunks.into(),
@@ -168,4 +918,51 @@ impl RemoteFs for RemoteFsServerImpl {
 
         Ok(Response::new(resp))
     }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<ServerInfo>, Status> {
+        let _timer = self.metrics.start_rpc("get_server_info");
+
+        let limits = self.limits.get();
+        let resp = ServerInfo {
+            server_version: PROJ_VERSION.to_owned(),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: vec![
+                FEATURE_STAGED_JOURNAL_APPLY.to_owned(),
+                FEATURE_STREAMED_JOURNAL_APPLY.to_owned(),
+                FEATURE_LIST_TREE.to_owned(),
+                FEATURE_GET_DIR_ENTITIES.to_owned(),
+                FEATURE_DIR_USAGE.to_owned(),
+                FEATURE_LIST_PAGES.to_owned(),
+                FEATURE_SIDECARS.to_owned(),
+                FEATURE_VALIDATE_JOURNAL.to_owned(),
+            ]
+            .into_iter()
+            .chain(
+                self.share_links
+                    .as_ref()
+                    .map(|_| FEATURE_SHARE_LINKS.to_owned()),
+            )
+            .chain(
+                (self.fs.store.blob_hash() == BlobHash::Blake3)
+                    .then(|| FEATURE_BLAKE3_BLOB_IDS.to_owned()),
+            )
+            .collect(),
+            limits: Some(ServerLimits {
+                blob_size: BLOB_SIZE as u64,
+                max_name_length: MAX_NAME_LENGTH as u32,
+                max_symlink_length: MAX_SYMLINK_LENGTH as u32,
+                max_path_depth: MAX_PATH_DEPTH,
+                max_blobs_per_request: limits.max_blobs_per_request as u64,
+                max_journal_operations: limits.max_journal_operations as u64,
+                max_write_size: limits.max_write_size as u64,
+                max_files_per_request: limits.max_files_per_request as u64,
+            }),
+        };
+
+        Ok(Response::new(resp))
+    }
 }
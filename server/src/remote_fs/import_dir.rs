@@ -0,0 +1,235 @@
+use std::error::Error;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use tracing::info;
+
+use offs::errors::OperationResult;
+use offs::modify_op::{
+    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation, ModifyOperationContent,
+    SetAttributesOperation, WriteOperation,
+};
+use offs::store::id_generator::RandomHexIdGenerator;
+use offs::store::{DirEntity, FileMode, FileOwner, FileType, Store};
+use offs::timespec::Timespec;
+
+use super::frontend::make_operation;
+use super::{RemoteFs, Share};
+
+const IMPORT_REPLICA_ID: &str = "import";
+/// Size of the writes the files are split into
+const WRITE_SIZE: usize = 4 * 1024 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Importer {
+    fs: RemoteFs,
+    share: Share,
+    progress: bool,
+    file_count: u64,
+    byte_count: u64,
+    last_progress: Instant,
+}
+
+/// Copies the directory tree into the directory at the path within the tree of the tenant,
+/// creating the missing directories, and keeping the modes and the times of the files. The
+/// hard links are imported as separate files.
+///
+/// The files are written to the store directly, so the server must not be running.
+pub fn import_dir(
+    store: Store<RandomHexIdGenerator>,
+    normalize_names: bool,
+    source: &Path,
+    tenant: &str,
+    path: &str,
+    progress: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !fs::metadata(source)?.is_dir() {
+        return Err(format!("{} is not a directory", source.display()).into());
+    }
+
+    let mut fs = RemoteFs::new(store, normalize_names)?;
+    let share = fs.create_share(tenant, path)?;
+    let mut importer = Importer {
+        fs,
+        share,
+        progress,
+        file_count: 0,
+        byte_count: 0,
+        last_progress: Instant::now(),
+    };
+
+    let root = importer.fs.store.query_file(importer.share.root_id())?;
+    importer.import_children(&root, source)?;
+    importer.set_attributes(&root, &fs::metadata(source)?)?;
+
+    if progress {
+        eprintln!();
+    }
+    info!(
+        "Imported {} files ({} bytes) from {}",
+        importer.file_count,
+        importer.byte_count,
+        source.display()
+    );
+
+    Ok(())
+}
+
+impl Importer {
+    fn import_children(&mut self, parent: &DirEntity, dir: &Path) -> Result<(), Box<dyn Error>> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|x| x.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| format!("{}: the name is not valid UTF-8", path.display()))?;
+
+            self.import_file(parent, name, &path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+
+        Ok(())
+    }
+
+    fn import_file(
+        &mut self,
+        parent: &DirEntity,
+        name: String,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let metadata = fs::symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+        let perm = (metadata.mode() & 0o7777) as FileMode;
+        let owner = Some(FileOwner {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        });
+
+        let content = if file_type.is_dir() {
+            ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                name,
+                perm,
+                owner,
+            })
+        } else if file_type.is_symlink() {
+            let link = fs::read_link(path)?
+                .into_os_string()
+                .into_string()
+                .map_err(|_| "the symlink target is not valid UTF-8")?;
+            ModifyOperationContent::CreateSymlinkOperation(CreateSymlinkOperation {
+                name,
+                link,
+                owner,
+            })
+        } else {
+            ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+                name,
+                file_type: get_file_type(&metadata),
+                perm,
+                dev: metadata.rdev() as u32,
+                owner,
+            })
+        };
+        let dirent = self.apply(parent, content)?;
+
+        if file_type.is_dir() {
+            self.import_children(&dirent, path)?;
+        } else if file_type.is_file() {
+            self.write_content(&dirent, path)?;
+        }
+        // Importing the content changes the times, so they are set at the end
+        self.set_attributes(&dirent, &metadata)?;
+
+        self.file_count += 1;
+        self.report_progress();
+
+        Ok(())
+    }
+
+    fn write_content(&mut self, dirent: &DirEntity, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        let mut dirent = dirent.clone();
+        let mut offset = 0;
+
+        loop {
+            let mut data = Vec::with_capacity(WRITE_SIZE);
+            (&mut file).take(WRITE_SIZE as u64).read_to_end(&mut data)?;
+            if data.is_empty() {
+                break;
+            }
+
+            let size = data.len();
+            dirent = self.apply(
+                &dirent,
+                ModifyOperationContent::WriteOperation(WriteOperation { offset, data }),
+            )?;
+            offset += size as i64;
+            self.byte_count += size as u64;
+            self.report_progress();
+        }
+
+        Ok(())
+    }
+
+    fn set_attributes(&mut self, dirent: &DirEntity, metadata: &Metadata) -> OperationResult<()> {
+        let dirent = self.fs.store.query_file(&dirent.id)?;
+        let content = ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+            perm: Some((metadata.mode() & 0o7777) as FileMode),
+            uid: None,
+            gid: None,
+            size: None,
+            atim: Some(Timespec::new(
+                metadata.atime(),
+                metadata.atime_nsec() as u32,
+            )),
+            mtim: Some(Timespec::new(
+                metadata.mtime(),
+                metadata.mtime_nsec() as u32,
+            )),
+        });
+        self.apply(&dirent, content)?;
+
+        Ok(())
+    }
+
+    fn apply(
+        &mut self,
+        dirent: &DirEntity,
+        content: ModifyOperationContent,
+    ) -> OperationResult<DirEntity> {
+        let operation = make_operation(dirent, IMPORT_REPLICA_ID, content);
+        self.fs.apply_operation(&self.share, operation)
+    }
+
+    fn report_progress(&mut self) {
+        if self.progress && self.last_progress.elapsed() >= PROGRESS_INTERVAL {
+            eprint!(
+                "\rImported {} files ({} bytes)",
+                self.file_count, self.byte_count
+            );
+            self.last_progress = Instant::now();
+        }
+    }
+}
+
+fn get_file_type(metadata: &Metadata) -> FileType {
+    let file_type = metadata.file_type();
+
+    if file_type.is_fifo() {
+        FileType::NamedPipe
+    } else if file_type.is_char_device() {
+        FileType::CharDevice
+    } else if file_type.is_block_device() {
+        FileType::BlockDevice
+    } else if file_type.is_socket() {
+        FileType::Socket
+    } else {
+        FileType::RegularFile
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OwnedMutexGuard;
+
+type LockMap = Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>;
+
+/// Locks on the files being modified, so that the modifications of a file are applied one at
+/// a time, while the ones of unrelated files proceed in parallel.
+#[derive(Clone, Default)]
+pub struct FileLocks {
+    locks: LockMap,
+}
+
+impl FileLocks {
+    /// Locks the files with the given IDs, waiting for the other operations to release them.
+    /// The IDs are locked in order, so that two operations never wait for each other.
+    pub async fn lock(&self, ids: impl IntoIterator<Item = String>) -> FileLocksGuard {
+        let ids: BTreeSet<String> = ids.into_iter().collect();
+        let mut guard = FileLocksGuard {
+            locks: self.locks.clone(),
+            guards: Vec::with_capacity(ids.len()),
+        };
+
+        for id in ids {
+            let lock = self
+                .locks
+                .lock()
+                .unwrap()
+                .entry(id.clone())
+                .or_default()
+                .clone();
+            guard.guards.push((id, lock.lock_owned().await));
+        }
+
+        guard
+    }
+}
+
+pub struct FileLocksGuard {
+    locks: LockMap,
+    guards: Vec<(String, OwnedMutexGuard<()>)>,
+}
+
+impl Drop for FileLocksGuard {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+
+        for (id, guard) in self.guards.drain(..) {
+            drop(guard);
+            // Forget the lock, unless another operation is waiting for it
+            if locks.get(&id).is_some_and(|x| Arc::strong_count(x) == 1) {
+                locks.remove(&id);
+            }
+        }
+    }
+}
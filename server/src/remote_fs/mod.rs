@@ -1,125 +1,819 @@
+use std::borrow::Cow;
+use std::cmp::min;
+use std::collections::HashSet;
 use std::path::Path;
+use std::sync::Arc;
+
+use itertools::Itertools;
 
 use offs::errors::{
-    JournalApplyData, JournalApplyError, JournalApplyResult, OperationError, OperationErrorType,
-    OperationResult,
+    JournalApplyData, JournalApplyError, JournalApplyResult, JournalOperationStatus,
+    OperationError, OperationErrorType, OperationResult,
 };
 
 use offs::modify_op::{
-    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation, ModifyOperation,
-    ModifyOperationContent, RemoveDirectoryOperation, RemoveFileOperation, RenameOperation,
-    SetAttributesOperation, WriteOperation,
+    CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
+    FallocateOperation, ModifyOperation, ModifyOperationContent, RemoveDirectoryOperation,
+    RemoveFileOperation, RenameOperation, SetAclOperation, SetAttributesOperation, WriteOperation,
 };
 use offs::modify_op_handler::{OperationApplier, OperationHandler};
-use offs::now;
+use offs::names::normalize_name;
+use offs::store::blob_cache::BlobCache;
 use offs::store::id_generator::{LocalTempIdGenerator, RandomHexIdGenerator};
 use offs::store::wrapper::StoreWrapper;
-use offs::store::{FileDev, FileMode, FileType, Store};
+use offs::store::{
+    BlobHash, DirEntity, DirUsage, FileDev, FileMode, FileOwner, FileType, Quota, Store,
+    Transaction, HOLE_BLOB_ID,
+};
+use offs::version_vector::VersionVector;
+use offs::{now, ROOT_ID};
 
+mod admin;
+mod frontend;
 mod grpc_server;
+mod import_dir;
+mod locks;
+mod sftp;
+mod share;
+mod share_link;
+mod validation;
+mod webdav;
+pub use admin::AdminServerImpl;
 use chrono::{TimeZone, Utc};
 pub use grpc_server::RemoteFsServerImpl;
+pub use import_dir::import_dir;
+use locks::{FileLocks, FileLocksGuard};
 use offs::timespec::Timespec;
+pub use sftp::{load_host_key, run_sftp_server};
+pub use share::Share;
+pub use share_link::{run_share_link_server, ShareLinkSigner};
+pub use webdav::run_webdav_server;
 
-macro_rules! check_content_version {
-    ($id:ident, $dirent:ident, $content_version:ident) => {{
-        if $dirent.content_version < $content_version {
-            return Err(OperationError::invalid_content_version());
-        } else if $dirent.content_version > $content_version {
-            return Err(OperationError::conflicted_file($id.to_owned()));
-        }
-    }};
-}
+/// How long the IDs of the applied journal operations are kept for deduplication.
+const APPLIED_OPERATION_RETENTION_SECS: i64 = 30 * 24 * 60 * 60;
 
 #[derive(Clone)]
 pub struct RemoteFs {
     store: StoreWrapper<RandomHexIdGenerator>,
+    locks: FileLocks,
+    normalize_names: bool,
 }
 
 impl RemoteFs {
-    pub fn new(mut store: Store<RandomHexIdGenerator>) -> OperationResult<Self> {
+    pub fn new(
+        mut store: Store<RandomHexIdGenerator>,
+        normalize_names: bool,
+    ) -> OperationResult<Self> {
         store.create_root_directory(0o755, now())?;
 
-        Ok(Self {
-            store: StoreWrapper::new(store),
-        })
+        Ok(Self::with_store_wrapper(
+            StoreWrapper::new(store),
+            normalize_names,
+        ))
+    }
+
+    /// Creates the filesystem serving the recently read blobs from memory, rather than querying
+    /// the database for each of them.
+    pub fn with_blob_cache(
+        mut store: Store<RandomHexIdGenerator>,
+        normalize_names: bool,
+        blob_cache: Arc<BlobCache>,
+    ) -> OperationResult<Self> {
+        store.create_root_directory(0o755, now())?;
+
+        Ok(Self::with_store_wrapper(
+            StoreWrapper::with_blob_cache(store, blob_cache),
+            normalize_names,
+        ))
+    }
+
+    fn with_store_wrapper(
+        store: StoreWrapper<RandomHexIdGenerator>,
+        normalize_names: bool,
+    ) -> Self {
+        Self {
+            store,
+            locks: Default::default(),
+            normalize_names,
+        }
+    }
+
+    /// Returns a handle to the filesystem writing through a database connection of its own,
+    /// so that it can modify the files in parallel with the other handles. The handles share
+    /// the file locks.
+    pub fn session(&self) -> Self {
+        Self {
+            store: self.store.session(),
+            locks: self.locks.clone(),
+            normalize_names: self.normalize_names,
+        }
+    }
+
+    /// Runs `f` with a session of its own on the blocking thread pool, as the store might wait
+    /// for up to its busy timeout for the transactions of the other sessions.
+    pub async fn with_session<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Self) -> R + Send + 'static,
+    ) -> R {
+        let mut fs = self.session();
+        tokio::task::spawn_blocking(move || f(&mut fs))
+            .await
+            .unwrap()
+    }
+
+    /// Starts a transaction, failing if the store stays locked by the other sessions for
+    /// longer than the busy timeout.
+    pub fn transaction(&self) -> OperationResult<Transaction> {
+        self.store.try_transaction()
+    }
+
+    /// Locks the files the operations modify, including the directories whose entries they
+    /// change. The files created by the journal do not need to be locked, as no one else
+    /// knows their IDs yet.
+    pub async fn lock_operations<'a>(
+        &self,
+        share: &Share,
+        operations: impl IntoIterator<Item = &'a ModifyOperation>,
+    ) -> FileLocksGuard {
+        let mut ids = Vec::new();
+
+        for operation in operations {
+            // The ID of a created file is the ID of its parent directory
+            let changes_parent = matches!(
+                operation.operation,
+                ModifyOperationContent::RemoveFileOperation(_)
+                    | ModifyOperationContent::RemoveDirectoryOperation(_)
+                    | ModifyOperationContent::RenameOperation(_)
+            );
+            ids.push((operation.id.as_str(), changes_parent));
+
+            match &operation.operation {
+                ModifyOperationContent::RenameOperation(op) => ids.push((&op.new_parent, false)),
+                ModifyOperationContent::CopyRangeOperation(op) => ids.push((&op.source_id, false)),
+                _ => {}
+            }
+        }
+
+        let mut store_ids = Vec::new();
+        let mut children = Vec::new();
+        for (id, changes_parent) in ids {
+            if LocalTempIdGenerator::is_local_id(id) {
+                continue;
+            }
+            let id = share.to_store_id(id);
+            store_ids.push(id.to_owned());
+            if changes_parent {
+                children.push(id.to_owned());
+            }
+        }
+
+        self.lock_files(store_ids, children).await
+    }
+
+    /// Locks the files, along with the parent directories of `children`. The parents are read
+    /// again once locked, as the files might have been moved to other directories while
+    /// waiting for the locks.
+    pub async fn lock_files(&self, ids: Vec<String>, children: Vec<String>) -> FileLocksGuard {
+        loop {
+            let parents = self.query_parents(&children);
+            let guard = self
+                .locks
+                .lock(ids.iter().chain(parents.iter().flatten()).cloned())
+                .await;
+
+            if self.query_parents(&children) == parents {
+                return guard;
+            }
+        }
+    }
+
+    fn query_parents(&self, ids: &[String]) -> Vec<Option<String>> {
+        ids.iter()
+            .map(|id| match self.store.try_query_file(id) {
+                Ok(Some(dirent)) if !dirent.parent.is_empty() => Some(dirent.parent),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the root directory of the tenant, creating it if the tenant has not been seen
+    /// before.
+    fn get_tenant_root(&mut self, tenant: &str) -> OperationResult<String> {
+        if tenant.is_empty() {
+            return Ok(ROOT_ID.to_owned());
+        }
+
+        match self.store.get_tenant_root(tenant)? {
+            Some(id) => Ok(id),
+            None => Ok(self.store.create_tenant_root(tenant, 0o755, now())?),
+        }
+    }
+
+    /// Returns the share of the tenant rooted at the given path, creating the missing
+    /// directories.
+    pub fn create_share(&mut self, tenant: &str, path: &str) -> OperationResult<Share> {
+        let transaction = self.transaction()?;
+        let tenant_root = self.get_tenant_root(tenant)?;
+        let mut id = tenant_root.clone();
+
+        for name in path.split('/').filter(|x| !x.is_empty()) {
+            id = match self.store.try_query_file_by_name(&id, name)? {
+                Some(dirent) if dirent.stat.file_type == FileType::Directory => dirent.id,
+                Some(_) => return Err(OperationError::not_a_directory(path)),
+                None => self.create_directory(&id, now(), name, 0o755, None)?,
+            };
+        }
+
+        transaction.commit()?;
+
+        let is_tenant_root = id == tenant_root;
+        Ok(Share::new(tenant.to_owned(), id, is_tenant_root))
+    }
+
+    /// Returns the share of the tenant rooted at the given path without writing anything, or
+    /// `None` if it has not been created yet.
+    pub fn find_share(&self, tenant: &str, path: &str) -> OperationResult<Option<Share>> {
+        let tenant_root = if tenant.is_empty() {
+            ROOT_ID.to_owned()
+        } else {
+            match self.store.get_tenant_root(tenant)? {
+                Some(id) => id,
+                None => return Ok(None),
+            }
+        };
+        let mut id = tenant_root.clone();
+
+        for name in path.split('/').filter(|x| !x.is_empty()) {
+            id = match self.store.try_query_file_by_name(&id, name)? {
+                Some(dirent) if dirent.stat.file_type == FileType::Directory => dirent.id,
+                Some(_) => return Err(OperationError::not_a_directory(path)),
+                None => return Ok(None),
+            };
+        }
+
+        let is_tenant_root = id == tenant_root;
+        Ok(Some(Share::new(tenant.to_owned(), id, is_tenant_root)))
+    }
+
+    /// Returns the directory at the path within the root directory of the tenant.
+    fn resolve_tenant_dir(&mut self, tenant: &str, path: &str) -> OperationResult<String> {
+        let mut id = self.get_tenant_root(tenant)?;
+
+        for name in path.split('/').filter(|x| !x.is_empty()) {
+            id = match self.store.try_query_file_by_name(&id, name)? {
+                Some(dirent) if dirent.stat.file_type == FileType::Directory => dirent.id,
+                Some(_) => return Err(OperationError::not_a_directory(path)),
+                None => return Err(OperationError::file_does_not_exist(path)),
+            };
+        }
+
+        Ok(id)
+    }
+
+    /// Returns the path of the file within the root directory of its tenant.
+    fn get_tenant_path(&self, id: &str) -> OperationResult<String> {
+        let mut names = Vec::new();
+        let mut dirent = self.store.query_file(id)?;
+        while !dirent.parent.is_empty() {
+            names.push(dirent.name);
+            dirent = self.store.query_file(&dirent.parent)?;
+        }
+        names.reverse();
+
+        Ok(format!("/{}", names.join("/")))
+    }
+
+    /// Sets the quota of the directory at the path within the root directory of the tenant (or
+    /// of the whole tenant, if the path is empty).
+    pub fn set_quota(&mut self, tenant: &str, path: &str, quota: Quota) -> OperationResult<()> {
+        let transaction = self.transaction()?;
+        let id = self.resolve_tenant_dir(tenant, path)?;
+        self.store.set_quota(&id, quota)?;
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    pub fn get_quota(&mut self, tenant: &str, path: &str) -> OperationResult<(Quota, DirUsage)> {
+        let id = self.resolve_tenant_dir(tenant, path)?;
+
+        Ok((self.store.get_quota(&id)?, self.store.get_dir_usage(&id)?))
+    }
+
+    /// Returns the tenant and the path of every directory with a quota, along with the quota
+    /// and the current usage.
+    pub fn list_quotas(&self) -> OperationResult<Vec<(String, String, Quota, DirUsage)>> {
+        self.store
+            .get_quotas()?
+            .into_iter()
+            .map(|(id, quota)| {
+                Ok((
+                    self.store.get_tenant(&id)?,
+                    self.get_tenant_path(&id)?,
+                    quota,
+                    self.store.get_dir_usage(&id)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns an error if the file is not located within the share.
+    pub fn check_in_share(&self, share: &Share, id: &str) -> OperationResult<()> {
+        if self.store.get_tenant(id)? != share.tenant() {
+            return Err(OperationError::file_does_not_exist(id));
+        }
+        if share.is_tenant_root() {
+            return Ok(());
+        }
+
+        let mut current = id.to_owned();
+        while current != share.root_id() {
+            let dirent = self.store.query_file(&current)?;
+            if dirent.parent.is_empty() {
+                return Err(OperationError::file_does_not_exist(id));
+            }
+            current = dirent.parent;
+        }
+
+        Ok(())
+    }
+
+    /// Returns all the files in the subtree of the directory, breadth-first, so that every
+    /// directory comes before its contents.
+    pub fn list_tree(&self, id: &str) -> OperationResult<Vec<DirEntity>> {
+        let mut files = self.store.list_files(id)?;
+
+        let mut i = 0;
+        while i < files.len() {
+            if files[i].stat.file_type == FileType::Directory {
+                let children = self.store.list_files(&files[i].id)?;
+                files.extend(children);
+            }
+            i += 1;
+        }
+
+        Ok(files)
+    }
+
+    /// Translates the file IDs referenced by the operation to the store IDs, making sure the
+    /// operation does not reach outside of the share.
+    pub fn scope_operation(
+        &self,
+        share: &Share,
+        mut operation: ModifyOperation,
+    ) -> OperationResult<ModifyOperation> {
+        if !LocalTempIdGenerator::is_local_id(&operation.id) {
+            operation.id = share.to_store_id(&operation.id).to_owned();
+            self.check_in_share(share, &operation.id)?;
+        }
+
+        if let ModifyOperationContent::RenameOperation(op) = &mut operation.operation {
+            if !LocalTempIdGenerator::is_local_id(&op.new_parent) {
+                op.new_parent = share.to_store_id(&op.new_parent).to_owned();
+                self.check_in_share(share, &op.new_parent)?;
+            }
+        }
+        if let ModifyOperationContent::CopyRangeOperation(op) = &mut operation.operation {
+            if !LocalTempIdGenerator::is_local_id(&op.source_id) {
+                op.source_id = share.to_store_id(&op.source_id).to_owned();
+                self.check_in_share(share, &op.source_id)?;
+            }
+        }
+
+        Ok(operation)
+    }
+
+    /// Applies a single operation sent by a client that is online, returning the state of the
+    /// file after the operation (or before it, if the file has been removed).
+    pub fn apply_operation(
+        &mut self,
+        share: &Share,
+        operation: ModifyOperation,
+    ) -> OperationResult<DirEntity> {
+        let transaction = self.transaction()?;
+
+        let mut operation = self.scope_operation(share, operation)?;
+        self.normalize_names(&mut operation);
+        self.validate_operation(&operation)?;
+        let dir_entity = self.store.try_query_file(&operation.id)?;
+
+        let new_id = OperationApplier::apply_operation(self, &operation)?;
+        self.increment_version_vectors(&operation)?;
+
+        let dir_entity = match operation.operation {
+            ModifyOperationContent::RemoveFileOperation(_)
+            | ModifyOperationContent::RemoveDirectoryOperation(_) => {
+                dir_entity.ok_or_else(|| OperationError::file_does_not_exist(&operation.id))?
+            }
+            _ => self.store.query_file(&new_id)?,
+        };
+
+        transaction.commit()?;
+
+        Ok(dir_entity)
     }
 
     pub fn apply_full_journal(
         &mut self,
+        share: &Share,
+        op_list: impl IntoIterator<Item = ModifyOperation>,
+        chunks: Vec<Vec<String>>,
+        blobs: impl IntoIterator<Item = impl AsRef<[u8]> + Sync>,
+    ) -> OperationResult<JournalApplyResult> {
+        if let Err(e) = self.add_journal_blobs(share.tenant(), &chunks, blobs)? {
+            return Ok(Err(e));
+        }
+
+        Ok(Ok(self.commit_journal(share, op_list, chunks)?))
+    }
+
+    /// Applies the operations of the journal, whose blobs have already been added.
+    pub fn commit_journal(
+        &mut self,
+        share: &Share,
         op_list: impl IntoIterator<Item = ModifyOperation>,
-        chunks: impl IntoIterator<Item = impl IntoIterator<Item = impl AsRef<str>>>,
-        blobs: impl IntoIterator<Item = impl AsRef<[u8]>>,
-    ) -> JournalApplyResult {
-        let (assigned_ids, processed_ids) = self.apply_journal(op_list)?;
+        chunks: Vec<Vec<String>>,
+    ) -> OperationResult<JournalApplyData> {
+        self.store
+            .remove_applied_operations_before(now().sec - APPLIED_OPERATION_RETENTION_SECS)?;
+
+        let (assigned_ids, processed_ids, statuses) = self.apply_journal(share, op_list);
         let dir_entities = processed_ids
             .iter()
-            .filter_map(|id| self.store.try_query_file(id).unwrap())
-            .collect();
+            .map(|id| self.store.try_query_file(id))
+            .filter_map(Result::transpose)
+            .collect::<OperationResult<_>>()?;
 
-        self.store.add_blobs(blobs).unwrap();
-        for (id, file_chunks) in assigned_ids.iter().zip(chunks.into_iter()) {
-            self.store
-                .replace_chunks(id, file_chunks.into_iter().enumerate())
-                .unwrap();
-        }
+        let file_chunks = assigned_ids
+            .iter()
+            .zip(chunks.into_iter())
+            .filter(|(id, _)| !id.is_empty())
+            .map(|(id, file_chunks)| (id, file_chunks.into_iter().enumerate()));
+        self.store.replace_chunks_batch(file_chunks)?;
 
         Ok(JournalApplyData {
             assigned_ids,
             dir_entities,
+            statuses,
         })
     }
 
+    /// Stores the uploaded blobs and makes sure the tenant does not reference any blobs it has
+    /// neither uploaded nor already owned, as that would let it read the files of others.
+    pub fn add_journal_blobs(
+        &mut self,
+        tenant: &str,
+        chunks: &[Vec<String>],
+        blobs: impl IntoIterator<Item = impl AsRef<[u8]> + Sync>,
+    ) -> OperationResult<Result<(), JournalApplyError>> {
+        let uploaded = self.store_journal_blobs(&journal_blob_hashes(chunks), blobs)?;
+
+        self.find_missing_blobs(tenant, chunks, &uploaded)
+    }
+
+    /// Stores the blobs uploaded with a journal, hashed with each of the functions, and returns
+    /// their IDs.
+    pub fn store_journal_blobs(
+        &mut self,
+        hashes: &HashSet<BlobHash>,
+        blobs: impl IntoIterator<Item = impl AsRef<[u8]> + Sync>,
+    ) -> OperationResult<HashSet<String>> {
+        let blobs = blobs.into_iter().collect_vec();
+        let uploaded = self.store.add_blobs_batch(
+            hashes
+                .iter()
+                .flat_map(|hash| blobs.iter().map(move |data| (*hash, data.as_ref()))),
+        )?;
+
+        Ok(uploaded.into_iter().collect())
+    }
+
+    /// Makes sure every blob referenced by the chunks has either been uploaded or is already
+    /// owned by the tenant.
+    pub fn find_missing_blobs(
+        &self,
+        tenant: &str,
+        chunks: &[Vec<String>],
+        uploaded: &HashSet<String>,
+    ) -> OperationResult<Result<(), JournalApplyError>> {
+        let referenced = journal_blob_ids(chunks);
+        let owned = self.store.get_tenant_blobs(tenant, referenced.iter())?;
+        let missing = referenced
+            .into_iter()
+            .filter(|x| !uploaded.contains(*x) && !owned.contains(*x))
+            .cloned()
+            .collect_vec();
+
+        if missing.is_empty() {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(JournalApplyError::MissingBlobs(missing)))
+        }
+    }
+
+    /// Applies every operation of the journal that can be applied. Returns the IDs assigned to
+    /// the created files (empty if the creation failed), the IDs of the files modified, and the
+    /// status of each operation.
     pub fn apply_journal(
         &mut self,
+        share: &Share,
         op_list: impl IntoIterator<Item = ModifyOperation>,
-    ) -> Result<(Vec<String>, Vec<String>), JournalApplyError> {
+    ) -> (Vec<String>, Vec<String>, Vec<JournalOperationStatus>) {
         let mut assigned_ids: Vec<String> = Vec::new();
         let mut processed_ids = Vec::new();
-        let mut conflicted_files = Vec::new();
+        let mut statuses = Vec::new();
 
-        for mut operation in op_list {
-            let id = &operation.id;
-
-            let add_assigned_id = match operation.operation {
+        for operation in op_list {
+            let add_assigned_id = matches!(
+                operation.operation,
                 ModifyOperationContent::CreateFileOperation(_)
-                | ModifyOperationContent::CreateSymlinkOperation(_)
-                | ModifyOperationContent::CreateDirectoryOperation(_) => true,
-                _ => false,
-            };
+                    | ModifyOperationContent::CreateSymlinkOperation(_)
+                    | ModifyOperationContent::CreateDirectoryOperation(_)
+            );
 
-            let result = if LocalTempIdGenerator::is_local_id(id) {
-                operation.id = assigned_ids[LocalTempIdGenerator::get_n(id)].clone();
-                OperationApplier::apply_operation_deferred(self, &operation)
-            } else {
-                OperationApplier::apply_operation_deferred(self, &operation)
-            };
+            let result = self.apply_journal_operation(share, &assigned_ids, operation);
 
-            if result.is_err() {
-                let err = result.err().unwrap();
-                match err.error_type {
-                    OperationErrorType::ConflictedFile => {
-                        conflicted_files.push(String::from_utf8_lossy(&err.details).to_string())
+            let status = match result {
+                Ok(new_id) => {
+                    if add_assigned_id {
+                        assigned_ids.push(new_id.clone());
                     }
-                    _ => return Err(JournalApplyError::InvalidJournal),
-                };
+                    processed_ids.push(new_id);
 
-                continue;
+                    JournalOperationStatus::Applied
+                }
+                Err(err) => {
+                    if add_assigned_id {
+                        assigned_ids.push(String::new());
+                    }
+
+                    match err.error_type {
+                        OperationErrorType::ConflictedFile => JournalOperationStatus::Conflicted,
+                        _ => JournalOperationStatus::Rejected(err.message),
+                    }
+                }
+            };
+            statuses.push(status);
+        }
+
+        (assigned_ids, processed_ids, statuses)
+    }
+
+    fn apply_journal_operation(
+        &mut self,
+        share: &Share,
+        assigned_ids: &[String],
+        operation: ModifyOperation,
+    ) -> OperationResult<String> {
+        // Each operation is applied in a transaction of its own, so that the store is not
+        // locked for the whole journal and a rejected operation leaves nothing behind; the
+        // files are kept from being modified in between by `lock_operations`. The journals
+        // that are only validated are rolled back in a single transaction instead.
+        let transaction = if self.store.in_transaction() {
+            None
+        } else {
+            Some(self.transaction()?)
+        };
+
+        // The journal might be sent again if the connection was lost before the client received
+        // the response, so the operations already applied are skipped
+        let op_id = operation.op_id.clone();
+        if !op_id.is_empty() {
+            if let Some(result) = self.store.get_applied_operation(share.tenant(), &op_id)? {
+                return Ok(result);
             }
+        }
+
+        let mut operation = self.scope_operation(share, operation)?;
+        Self::assign_journal_ids(assigned_ids, &mut operation)?;
+        self.normalize_names(&mut operation);
+        self.validate_operation(&operation)?;
+        self.check_versions(&operation)?;
+        let result = OperationApplier::apply_operation_deferred(self, &operation)?;
+        self.increment_version_vectors(&operation)?;
+
+        if !op_id.is_empty() {
+            self.store
+                .add_applied_operation(share.tenant(), &op_id, &result, now().sec)?;
+        }
+        if let Some(transaction) = transaction {
+            transaction.commit()?;
+        }
+
+        Ok(result)
+    }
+
+    /// Converts the names given to the files by the operation to NFC, so that the clients
+    /// using different Unicode forms do not create distinct files with the same name.
+    pub fn normalize_names(&self, operation: &mut ModifyOperation) {
+        if !self.normalize_names {
+            return;
+        }
+
+        let name = match &mut operation.operation {
+            ModifyOperationContent::CreateFileOperation(op) => &mut op.name,
+            ModifyOperationContent::CreateSymlinkOperation(op) => &mut op.name,
+            ModifyOperationContent::CreateDirectoryOperation(op) => &mut op.name,
+            ModifyOperationContent::RenameOperation(op) => &mut op.new_name,
+            _ => return,
+        };
+        if let Cow::Owned(normalized) = normalize_name(name) {
+            *name = normalized;
+        }
+    }
+
+    /// Rejects the operations with invalid names, as well as the ones that would nest the files
+    /// too deeply, instead of trusting the client to have checked them.
+    pub fn validate_operation(&self, operation: &ModifyOperation) -> OperationResult<()> {
+        validation::validate_operation(operation)?;
+
+        let depth = match &operation.operation {
+            ModifyOperationContent::CreateFileOperation(_)
+            | ModifyOperationContent::CreateSymlinkOperation(_)
+            | ModifyOperationContent::CreateDirectoryOperation(_) => {
+                self.store.get_depth(&operation.id)? + 1
+            }
+            ModifyOperationContent::RenameOperation(op) => {
+                self.store.get_depth(&op.new_parent)?
+                    + 1
+                    + self.store.get_subtree_height(&operation.id)?
+            }
+            _ => 0,
+        };
+        if depth > validation::MAX_PATH_DEPTH {
+            return Err(OperationError::invalid_argument(&format!(
+                "path deeper than {} directories",
+                validation::MAX_PATH_DEPTH
+            )));
+        }
+
+        self.check_file_types(operation)
+    }
+
+    /// Makes sure the operation is not made on a directory if it needs a file, and the other
+    /// way round.
+    fn check_file_types(&self, operation: &ModifyOperation) -> OperationResult<()> {
+        let (id, is_directory) = match &operation.operation {
+            ModifyOperationContent::CreateFileOperation(_)
+            | ModifyOperationContent::CreateSymlinkOperation(_)
+            | ModifyOperationContent::CreateDirectoryOperation(_)
+            | ModifyOperationContent::RemoveDirectoryOperation(_) => (&operation.id, true),
+            ModifyOperationContent::RenameOperation(op) => (&op.new_parent, true),
+            ModifyOperationContent::CopyRangeOperation(op) => {
+                self.check_file_type(&op.source_id, false)?;
+                (&operation.id, false)
+            }
+            ModifyOperationContent::RemoveFileOperation(_)
+            | ModifyOperationContent::WriteOperation(_)
+            | ModifyOperationContent::FallocateOperation(_) => (&operation.id, false),
+            ModifyOperationContent::SetAttributesOperation(op) if op.size.is_some() => {
+                (&operation.id, false)
+            }
+            _ => return Ok(()),
+        };
+
+        self.check_file_type(id, is_directory)
+    }
+
+    fn check_file_type(&self, id: &str, is_directory: bool) -> OperationResult<()> {
+        // The missing files are reported by the operations themselves
+        let dirent = match self.store.try_query_file(id)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        match (dirent.stat.file_type == FileType::Directory, is_directory) {
+            (false, true) => Err(OperationError::not_a_directory(id)),
+            (true, false) => Err(OperationError::is_a_directory(id)),
+            _ => Ok(()),
+        }
+    }
 
-            let new_id = result.ok().unwrap();
-            if add_assigned_id {
-                assigned_ids.push(new_id.clone());
+    fn is_content_operation(operation: &ModifyOperation) -> bool {
+        match &operation.operation {
+            ModifyOperationContent::WriteOperation(_)
+            | ModifyOperationContent::FallocateOperation(_)
+            | ModifyOperationContent::CopyRangeOperation(_) => true,
+            ModifyOperationContent::SetAttributesOperation(op) => op.size.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the operation modifies the attributes or ACLs of the file, other than its
+    /// size, which is a part of the content.
+    fn is_dirent_operation(operation: &ModifyOperation) -> bool {
+        match &operation.operation {
+            ModifyOperationContent::SetAclOperation(_) => true,
+            ModifyOperationContent::SetAttributesOperation(op) => {
+                op.perm.is_some()
+                    || op.uid.is_some()
+                    || op.gid.is_some()
+                    || op.atim.is_some()
+                    || op.mtim.is_some()
             }
+            _ => false,
+        }
+    }
 
-            processed_ids.push(new_id);
+    /// Makes sure the content and the attributes of the file have not been modified since the
+    /// versions the operation was made on top of. With the version vectors, only the
+    /// modifications made by the other replicas are taken into account, so that a client can
+    /// modify the file several times while offline without conflicting with itself.
+    fn check_versions(&self, operation: &ModifyOperation) -> OperationResult<()> {
+        let is_content_operation = Self::is_content_operation(operation);
+        let is_dirent_operation = Self::is_dirent_operation(operation);
+        if !is_content_operation && !is_dirent_operation {
+            return Ok(());
         }
+        let dirent = self.store.query_file(&operation.id)?;
+
+        if is_content_operation && dirent.stat.has_size() {
+            if operation.replica_id.is_empty() {
+                if dirent.content_version < operation.content_version {
+                    return Err(OperationError::invalid_content_version());
+                } else if dirent.content_version > operation.content_version {
+                    return Err(OperationError::conflicted_file(operation.id.clone()));
+                }
+            } else {
+                Self::check_version_vector(
+                    operation,
+                    &dirent.content_vector,
+                    &operation.content_vector,
+                )?;
+            }
+        }
+        // The older clients do not check the dirent versions, so neither do the operations
+        // without a replica
+        if is_dirent_operation && !operation.replica_id.is_empty() {
+            Self::check_version_vector(operation, &dirent.dirent_vector, &operation.dirent_vector)?;
+        }
+
+        Ok(())
+    }
 
-        if conflicted_files.is_empty() {
-            Ok((assigned_ids, processed_ids))
+    fn check_version_vector(
+        operation: &ModifyOperation,
+        current: &VersionVector,
+        base: &VersionVector,
+    ) -> OperationResult<()> {
+        if !current.includes(base) {
+            Err(OperationError::invalid_content_version())
+        } else if !current.is_descendant_for(base, &operation.replica_id) {
+            Err(OperationError::conflicted_file(operation.id.clone()))
         } else {
-            Err(JournalApplyError::ConflictingFiles(conflicted_files))
+            Ok(())
+        }
+    }
+
+    /// Records the modification of the file content and attributes in its version vectors.
+    pub fn increment_version_vectors(
+        &mut self,
+        operation: &ModifyOperation,
+    ) -> OperationResult<()> {
+        if operation.replica_id.is_empty() {
+            return Ok(());
+        }
+
+        if Self::is_content_operation(operation) {
+            self.store
+                .increment_content_vector(&operation.id, &operation.replica_id)?;
+        }
+        if Self::is_dirent_operation(operation) {
+            self.store
+                .increment_dirent_vector(&operation.id, &operation.replica_id)?;
         }
+
+        Ok(())
+    }
+
+    /// Replaces the temporary IDs the operation refers to with the IDs assigned to the files
+    /// created earlier in the journal.
+    fn assign_journal_ids(
+        assigned_ids: &[String],
+        operation: &mut ModifyOperation,
+    ) -> OperationResult<()> {
+        let assign = |id: &mut String| -> OperationResult<()> {
+            if !LocalTempIdGenerator::is_local_id(id) {
+                return Ok(());
+            }
+
+            match assigned_ids.get(LocalTempIdGenerator::get_n(id)) {
+                Some(assigned_id) if !assigned_id.is_empty() => {
+                    *id = assigned_id.clone();
+                    Ok(())
+                }
+                _ => Err(OperationError::file_does_not_exist(id)),
+            }
+        };
+
+        assign(&mut operation.id)?;
+        match &mut operation.operation {
+            ModifyOperationContent::RenameOperation(op) => assign(&mut op.new_parent)?,
+            ModifyOperationContent::CopyRangeOperation(op) => assign(&mut op.source_id)?,
+            _ => {}
+        }
+
+        Ok(())
     }
 
     fn get_name_if_conflicts_by_id(
@@ -160,7 +854,11 @@ impl RemoteFs {
             .extension()
             .map_or("".to_owned(), |x| format!(".{}", x.to_str().unwrap()));
 
-        let datetime = Utc.timestamp(timestamp.sec, timestamp.nsec);
+        // The timestamps come from the clients, so they may be out of the range of chrono
+        let datetime = Utc
+            .timestamp_opt(timestamp.sec, timestamp.nsec)
+            .single()
+            .unwrap_or_else(Utc::now);
         let date_str = datetime.format("%Y-%m-%d").to_string();
 
         let new_name = format!("{} (Conflicted copy {}){}", name, date_str, ext);
@@ -192,6 +890,7 @@ impl RemoteFs {
         unreachable!();
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn create_file(
         &mut self,
         parent_id: &str,
@@ -200,12 +899,14 @@ impl RemoteFs {
         file_type: FileType,
         mode: FileMode,
         dev: FileDev,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
+        self.store.check_quota(parent_id, 0, 1, None)?;
         self.store.increment_content_version(parent_id)?;
 
         Ok(self
             .store
-            .create_file(parent_id, timestamp, name, file_type, mode, dev)?)
+            .create_file(parent_id, timestamp, name, file_type, mode, dev, owner)?)
     }
 
     fn create_symlink(
@@ -214,12 +915,15 @@ impl RemoteFs {
         timestamp: Timespec,
         name: &str,
         link: &str,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
+        self.store
+            .check_quota(parent_id, link.len() as u64, 1, None)?;
         self.store.increment_content_version(parent_id)?;
 
         Ok(self
             .store
-            .create_symlink(parent_id, timestamp, name, link)?)
+            .create_symlink(parent_id, timestamp, name, link, owner)?)
     }
 
     fn create_directory(
@@ -228,12 +932,14 @@ impl RemoteFs {
         timestamp: Timespec,
         name: &str,
         mode: FileMode,
+        owner: Option<FileOwner>,
     ) -> OperationResult<String> {
+        self.store.check_quota(parent_id, 0, 1, None)?;
         self.store.increment_content_version(parent_id)?;
 
         Ok(self
             .store
-            .create_directory(parent_id, timestamp, name, mode)?)
+            .create_directory(parent_id, timestamp, name, mode, owner)?)
     }
 
     fn remove_file(&mut self, id: &str, timestamp: Timespec) -> OperationResult<()> {
@@ -246,12 +952,13 @@ impl RemoteFs {
     }
 
     fn remove_directory(&mut self, id: &str, timestamp: Timespec) -> OperationResult<()> {
-        let dirent = self.store.query_file(id)?;
-        self.store.increment_content_version(&dirent.parent)?;
-
         if self.store.any_child_exists(id)? {
             return Err(OperationError::directory_not_empty());
         }
+
+        let dirent = self.store.query_file(id)?;
+        self.store.increment_content_version(&dirent.parent)?;
+
         self.store.remove_directory(id, timestamp)?;
 
         Ok(())
@@ -264,9 +971,25 @@ impl RemoteFs {
         new_parent: &str,
         new_name: &str,
     ) -> OperationResult<()> {
+        // Otherwise the directory would end up in a cycle, unreachable from the root
+        if self.store.is_descendant(new_parent, id)? {
+            return Err(OperationError::rename_into_descendant(id));
+        }
+
         let dirent = self.store.query_file(id)?;
+        let (size, files) = if dirent.stat.file_type == FileType::Directory {
+            let usage = self.store.get_dir_usage(id)?;
+            (usage.size, usage.files + usage.directories + 1)
+        } else {
+            (dirent.stat.size, 1)
+        };
+        self.store
+            .check_quota(new_parent, size, files, Some(&dirent.parent))?;
+
         self.store.increment_content_version(&dirent.parent)?;
-        self.store.increment_content_version(&new_parent)?;
+        if new_parent != dirent.parent {
+            self.store.increment_content_version(&new_parent)?;
+        }
         self.store.increment_dirent_version(id)?;
 
         self.store.rename(id, timestamp, new_parent, new_name)?;
@@ -285,7 +1008,8 @@ impl RemoteFs {
         atim: Option<Timespec>,
         mtim: Option<Timespec>,
     ) -> OperationResult<()> {
-        if size.is_some() {
+        if let Some(size) = size {
+            self.check_quota_for_size(id, size)?;
             self.store.increment_content_version(id)?;
         } else {
             self.store.increment_dirent_version(id)?;
@@ -304,12 +1028,109 @@ impl RemoteFs {
         offset: usize,
         data: &[u8],
     ) -> OperationResult<()> {
+        self.check_quota_for_size(id, (offset + data.len()) as u64)?;
         self.store.increment_content_version(id)?;
 
         self.store.write(id, timestamp, offset, data)?;
 
         Ok(())
     }
+
+    fn fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        if !operation.keep_size {
+            self.check_quota_for_size(id, (operation.offset + operation.length) as u64)?;
+        }
+        self.store.increment_content_version(id)?;
+
+        self.store.fallocate(
+            id,
+            timestamp,
+            operation.offset as u64,
+            operation.length as u64,
+            operation.zero,
+            operation.keep_size,
+        )?;
+
+        Ok(())
+    }
+
+    fn copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        // Only the existing part of the source is copied
+        let source_size = self.store.query_file(&operation.source_id)?.stat.size;
+        let length = min(
+            operation.length as u64,
+            source_size.saturating_sub(operation.source_offset as u64),
+        );
+        self.check_quota_for_size(id, operation.offset as u64 + length)?;
+        self.store.increment_content_version(id)?;
+
+        self.store.copy_range(
+            id,
+            timestamp,
+            &operation.source_id,
+            operation.source_offset as u64,
+            operation.offset as u64,
+            operation.length as u64,
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns an error if growing the file to `new_size` would exceed a quota. Shrinking the
+    /// files is always allowed.
+    fn check_quota_for_size(&self, id: &str, new_size: u64) -> OperationResult<()> {
+        let dirent = self.store.query_file(id)?;
+
+        self.store.check_quota(
+            &dirent.parent,
+            new_size.saturating_sub(dirent.stat.size),
+            0,
+            None,
+        )
+    }
+
+    fn set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        self.store.increment_dirent_version(id)?;
+
+        self.store
+            .set_acl(id, timestamp, operation.acl_type, &operation.acl)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the IDs of the blobs referenced by the chunks of a journal.
+fn journal_blob_ids(chunks: &[Vec<String>]) -> HashSet<&String> {
+    chunks
+        .iter()
+        .flatten()
+        .filter(|x| *x != HOLE_BLOB_ID)
+        .collect()
+}
+
+/// Returns the hash functions the blobs referenced by the chunks of a journal are identified
+/// with. The blobs are uploaded without their IDs, so they are hashed with each of them; that is
+/// a single one unless the journal spans a change of the hash.
+fn journal_blob_hashes(chunks: &[Vec<String>]) -> HashSet<BlobHash> {
+    journal_blob_ids(chunks)
+        .into_iter()
+        .map(|x| BlobHash::of_id(x))
+        .collect()
 }
 
 impl OperationHandler for RemoteFs {
@@ -326,6 +1147,7 @@ impl OperationHandler for RemoteFs {
             operation.file_type,
             operation.perm,
             operation.dev,
+            operation.owner,
         )?)
     }
 
@@ -335,7 +1157,13 @@ impl OperationHandler for RemoteFs {
         timestamp: Timespec,
         operation: &CreateSymlinkOperation,
     ) -> OperationResult<String> {
-        Ok(self.create_symlink(parent_id, timestamp, &operation.name, &operation.link)?)
+        Ok(self.create_symlink(
+            parent_id,
+            timestamp,
+            &operation.name,
+            &operation.link,
+            operation.owner,
+        )?)
     }
 
     fn perform_create_directory(
@@ -344,7 +1172,13 @@ impl OperationHandler for RemoteFs {
         timestamp: Timespec,
         operation: &CreateDirectoryOperation,
     ) -> OperationResult<String> {
-        Ok(self.create_directory(parent_id, timestamp, &operation.name, operation.perm)?)
+        Ok(self.create_directory(
+            parent_id,
+            timestamp,
+            &operation.name,
+            operation.perm,
+            operation.owner,
+        )?)
     }
 
     fn perform_remove_file(
@@ -408,6 +1242,39 @@ impl OperationHandler for RemoteFs {
         Ok(())
     }
 
+    fn perform_set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        self.set_acl(id, timestamp, operation)?;
+
+        Ok(())
+    }
+
+    fn perform_fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        self.fallocate(id, timestamp, operation)?;
+
+        Ok(())
+    }
+
+    fn perform_copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        self.copy_range(id, timestamp, operation)?;
+
+        Ok(())
+    }
+
     fn deferred_create_file(
         &mut self,
         parent_id: &str,
@@ -425,6 +1292,7 @@ impl OperationHandler for RemoteFs {
             operation.file_type,
             operation.perm,
             operation.dev,
+            operation.owner,
         )?)
     }
 
@@ -438,7 +1306,13 @@ impl OperationHandler for RemoteFs {
     ) -> OperationResult<String> {
         let new_name = self.get_name_if_conflicts(parent_id, &operation.name, timestamp)?;
 
-        Ok(self.create_symlink(parent_id, timestamp, &new_name, &operation.link)?)
+        Ok(self.create_symlink(
+            parent_id,
+            timestamp,
+            &new_name,
+            &operation.link,
+            operation.owner,
+        )?)
     }
 
     fn deferred_create_directory(
@@ -451,7 +1325,13 @@ impl OperationHandler for RemoteFs {
     ) -> OperationResult<String> {
         let new_name = self.get_name_if_conflicts(parent_id, &operation.name, timestamp)?;
 
-        Ok(self.create_directory(parent_id, timestamp, &new_name, operation.perm)?)
+        Ok(self.create_directory(
+            parent_id,
+            timestamp,
+            &new_name,
+            operation.perm,
+            operation.owner,
+        )?)
     }
 
     fn deferred_remove_file(
@@ -500,19 +1380,13 @@ impl OperationHandler for RemoteFs {
         id: &str,
         timestamp: Timespec,
         _dirent_version: i64,
-        content_version: i64,
+        _content_version: i64,
         operation: &SetAttributesOperation,
     ) -> OperationResult<()> {
         let mut size = operation.size;
 
-        if size.is_some() {
-            let dirent = self.store.query_file(id)?;
-
-            if dirent.stat.has_size() {
-                check_content_version!(id, dirent, content_version);
-            } else {
-                size = None;
-            }
+        if size.is_some() && !self.store.query_file(id)?.stat.has_size() {
+            size = None;
         }
 
         self.set_attributes(
@@ -534,16 +1408,50 @@ impl OperationHandler for RemoteFs {
         id: &str,
         timestamp: Timespec,
         _dirent_version: i64,
-        content_version: i64,
+        _content_version: i64,
         operation: &WriteOperation,
     ) -> OperationResult<()> {
-        {
-            let dirent = self.store.query_file(id)?;
-            check_content_version!(id, dirent, content_version);
-        }
-
         self.write(id, timestamp, operation.offset as usize, &operation.data)?;
 
         Ok(())
     }
+
+    fn deferred_set_acl(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        operation: &SetAclOperation,
+    ) -> OperationResult<()> {
+        self.set_acl(id, timestamp, operation)?;
+
+        Ok(())
+    }
+
+    fn deferred_fallocate(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        operation: &FallocateOperation,
+    ) -> OperationResult<()> {
+        self.fallocate(id, timestamp, operation)?;
+
+        Ok(())
+    }
+
+    fn deferred_copy_range(
+        &mut self,
+        id: &str,
+        timestamp: Timespec,
+        _dirent_version: i64,
+        _content_version: i64,
+        operation: &CopyRangeOperation,
+    ) -> OperationResult<()> {
+        self.copy_range(id, timestamp, operation)?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,640 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use russh::keys::PrivateKey;
+use russh::server::{Auth, ChannelOpenHandle, Msg, Server, Session};
+use russh::{Channel, ChannelId};
+use russh_sftp::protocol::{
+    Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode,
+};
+use russh_sftp::server::StatusReply;
+use tracing::{debug, info};
+
+use offs::errors::{OperationError, OperationErrorType};
+use offs::modify_op::{
+    CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation, ModifyOperationContent,
+    RemoveDirectoryOperation, RemoveFileOperation, RenameOperation, SetAttributesOperation,
+    WriteOperation,
+};
+use offs::store::{DirEntity, FileMode, FileType};
+use offs::timespec::Timespec;
+
+use super::frontend::make_operation;
+use super::{RemoteFs, Share};
+use crate::auth::Authenticator;
+
+/// Replica the modifications made over SFTP are attributed to in the version vectors.
+const SFTP_REPLICA_ID: &str = "sftp";
+const SFTP_SUBSYSTEM: &str = "sftp";
+const DEFAULT_FILE_MODE: FileMode = 0o644;
+const DEFAULT_DIRECTORY_MODE: FileMode = 0o755;
+const PERMISSIONS_MASK: u32 = 0o7777;
+/// Maximum number of bytes returned by a single read, whatever the client asks for; the
+/// clients read the rest of the file with the following requests.
+const MAX_READ_LENGTH: u32 = 256 * 1024;
+
+struct SftpError(StatusReply);
+
+impl From<OperationError> for SftpError {
+    fn from(error: OperationError) -> Self {
+        debug!("SFTP request failed: {:?}", error);
+
+        let status_code = match error.error_type {
+            OperationErrorType::FileDoesNotExist | OperationErrorType::ShareDoesNotExist => {
+                StatusCode::NoSuchFile
+            }
+            OperationErrorType::Unauthenticated | OperationErrorType::PermissionDenied => {
+                StatusCode::PermissionDenied
+            }
+            OperationErrorType::InvalidUnicode
+            | OperationErrorType::InvalidAcl
+            | OperationErrorType::InvalidArgument
+            | OperationErrorType::NameTooLong => StatusCode::BadMessage,
+            _ => StatusCode::Failure,
+        };
+
+        SftpError(status_code.with_message(error.message))
+    }
+}
+
+impl From<StatusCode> for SftpError {
+    fn from(status_code: StatusCode) -> Self {
+        SftpError(status_code.into())
+    }
+}
+
+impl From<SftpError> for StatusReply {
+    fn from(error: SftpError) -> Self {
+        error.0
+    }
+}
+
+type SftpResult<T> = Result<T, SftpError>;
+
+fn file_type_bits(file_type: FileType) -> u32 {
+    match file_type {
+        FileType::NamedPipe => 0o010000,
+        FileType::CharDevice => 0o020000,
+        FileType::Directory => 0o040000,
+        FileType::BlockDevice => 0o060000,
+        FileType::RegularFile => 0o100000,
+        FileType::Symlink => 0o120000,
+        FileType::Socket => 0o140000,
+    }
+}
+
+fn to_file_attributes(dirent: &DirEntity) -> FileAttributes {
+    FileAttributes {
+        size: Some(dirent.stat.size),
+        uid: Some(dirent.stat.uid),
+        user: None,
+        gid: Some(dirent.stat.gid),
+        group: None,
+        permissions: Some(file_type_bits(dirent.stat.file_type) | dirent.stat.mode as u32),
+        atime: Some(dirent.stat.atim.sec as u32),
+        mtime: Some(dirent.stat.mtim.sec as u32),
+    }
+}
+
+/// Splits the path into the names of the files, resolving `.` and `..`. The paths are relative
+/// to the root of the tenant's tree, which also acts as the home directory.
+fn parse_path(path: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name.to_owned()),
+        }
+    }
+
+    names
+}
+
+fn ok_status(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".to_owned(),
+        language_tag: "en-US".to_owned(),
+    }
+}
+
+enum OpenHandle {
+    File(String),
+    /// The directory listing, taken when the directory has been opened and returned by the
+    /// first `readdir`.
+    Directory(Option<Vec<DirEntity>>),
+}
+
+/// Translates the SFTP requests of a single session to the operations applied to the tenant's
+/// tree.
+struct SftpSession {
+    fs: Arc<RemoteFs>,
+    share: Share,
+    handles: HashMap<String, OpenHandle>,
+    next_handle: u64,
+}
+
+impl SftpSession {
+    fn add_handle(&mut self, handle: OpenHandle) -> String {
+        let name = self.next_handle.to_string();
+        self.next_handle += 1;
+        self.handles.insert(name.clone(), handle);
+
+        name
+    }
+
+    fn get_file_handle(&self, handle: &str) -> SftpResult<String> {
+        match self.handles.get(handle) {
+            Some(OpenHandle::File(id)) => Ok(id.clone()),
+            _ => Err(StatusCode::Failure.into()),
+        }
+    }
+
+    async fn resolve(&self, path: &str) -> SftpResult<DirEntity> {
+        self.fs
+            .resolve_path(&self.share, &parse_path(path))?
+            .ok_or_else(|| StatusCode::NoSuchFile.into())
+    }
+
+    /// Returns the directory the file is to be created in, along with the name of the file.
+    fn resolve_parent(&self, fs: &RemoteFs, path: &str) -> SftpResult<(DirEntity, String)> {
+        let mut names = parse_path(path);
+        let name = names.pop().ok_or(StatusCode::Failure)?;
+
+        match fs.resolve_path(&self.share, &names)? {
+            Some(parent) if parent.stat.file_type == FileType::Directory => Ok((parent, name)),
+            _ => Err(StatusCode::NoSuchFile.into()),
+        }
+    }
+
+    async fn set_attributes(&self, id: &str, attrs: FileAttributes) -> SftpResult<()> {
+        let fs = &self.fs;
+        let dirent = fs.store.query_file(id)?;
+        let content = ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+            perm: attrs
+                .permissions
+                .map(|x| (x & PERMISSIONS_MASK) as FileMode),
+            uid: attrs.uid,
+            gid: attrs.gid,
+            size: attrs.size,
+            atim: attrs.atime.map(|x| Timespec::new(x as i64, 0)),
+            mtim: attrs.mtime.map(|x| Timespec::new(x as i64, 0)),
+        });
+        fs.apply_locked(
+            &self.share,
+            make_operation(&dirent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn apply_to_path(
+        &self,
+        id: u32,
+        path: &str,
+        file_type: fn(FileType) -> bool,
+        content: ModifyOperationContent,
+    ) -> SftpResult<Status> {
+        let dirent = self.resolve(path).await?;
+        if !file_type(dirent.stat.file_type) {
+            return Err(StatusCode::Failure.into());
+        }
+
+        let fs = &self.fs;
+        fs.apply_locked(
+            &self.share,
+            make_operation(&dirent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(ok_status(id))
+    }
+}
+
+impl russh_sftp::server::Handler for SftpSession {
+    type Error = SftpError;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported.into()
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        pflags: OpenFlags,
+        attrs: FileAttributes,
+    ) -> SftpResult<Handle> {
+        debug!("SFTP open: {:?}, flags={:?}", filename, pflags);
+
+        let fs = &self.fs;
+        let (parent, name) = self.resolve_parent(fs, &filename)?;
+        let dirent = match fs.lookup(&parent.id, &name)? {
+            Some(_) if pflags.contains(OpenFlags::CREATE | OpenFlags::EXCLUDE) => {
+                return Err(StatusCode::Failure.into())
+            }
+            Some(dirent) if dirent.stat.file_type == FileType::Directory => {
+                return Err(StatusCode::Failure.into())
+            }
+            Some(dirent) => dirent,
+            None if pflags.contains(OpenFlags::CREATE) => {
+                let content = ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+                    name,
+                    file_type: FileType::RegularFile,
+                    perm: attrs
+                        .permissions
+                        .map_or(DEFAULT_FILE_MODE, |x| (x & PERMISSIONS_MASK) as FileMode),
+                    dev: 0,
+                    owner: None,
+                });
+                fs.apply_locked(
+                    &self.share,
+                    make_operation(&parent, SFTP_REPLICA_ID, content),
+                )
+                .await?
+            }
+            None => return Err(StatusCode::NoSuchFile.into()),
+        };
+
+        if pflags.contains(OpenFlags::TRUNCATE) && dirent.stat.size > 0 {
+            let content = ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+                perm: None,
+                uid: None,
+                gid: None,
+                size: Some(0),
+                atim: None,
+                mtim: None,
+            });
+            fs.apply_locked(
+                &self.share,
+                make_operation(&dirent, SFTP_REPLICA_ID, content),
+            )
+            .await?;
+        }
+
+        Ok(Handle {
+            id,
+            handle: self.add_handle(OpenHandle::File(dirent.id)),
+        })
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> SftpResult<Status> {
+        self.handles.remove(&handle);
+
+        Ok(ok_status(id))
+    }
+
+    async fn read(&mut self, id: u32, handle: String, offset: u64, len: u32) -> SftpResult<Data> {
+        let file_id = self.get_file_handle(&handle)?;
+        let fs = &self.fs;
+        let dirent = fs.store.query_file(&file_id)?;
+        if offset >= dirent.stat.size {
+            return Err(StatusCode::Eof.into());
+        }
+
+        Ok(Data {
+            id,
+            data: fs.read_file(&dirent, offset, len.min(MAX_READ_LENGTH) as u64)?,
+        })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> SftpResult<Status> {
+        let file_id = self.get_file_handle(&handle)?;
+        let fs = &self.fs;
+        let dirent = fs.store.query_file(&file_id)?;
+        let content = ModifyOperationContent::WriteOperation(WriteOperation {
+            offset: offset as i64,
+            data,
+        });
+        fs.apply_locked(
+            &self.share,
+            make_operation(&dirent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn lstat(&mut self, id: u32, path: String) -> SftpResult<Attrs> {
+        let dirent = self.resolve(&path).await?;
+
+        Ok(Attrs {
+            id,
+            attrs: to_file_attributes(&dirent),
+        })
+    }
+
+    async fn stat(&mut self, id: u32, path: String) -> SftpResult<Attrs> {
+        // The symlinks may point outside of the tree, so they are not followed
+        self.lstat(id, path).await
+    }
+
+    async fn fstat(&mut self, id: u32, handle: String) -> SftpResult<Attrs> {
+        let file_id = self.get_file_handle(&handle)?;
+        let dirent = self.fs.store.query_file(&file_id)?;
+
+        Ok(Attrs {
+            id,
+            attrs: to_file_attributes(&dirent),
+        })
+    }
+
+    async fn setstat(
+        &mut self,
+        id: u32,
+        path: String,
+        attrs: FileAttributes,
+    ) -> SftpResult<Status> {
+        let dirent = self.resolve(&path).await?;
+        self.set_attributes(&dirent.id, attrs).await?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn fsetstat(
+        &mut self,
+        id: u32,
+        handle: String,
+        attrs: FileAttributes,
+    ) -> SftpResult<Status> {
+        let file_id = self.get_file_handle(&handle)?;
+        self.set_attributes(&file_id, attrs).await?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn opendir(&mut self, id: u32, path: String) -> SftpResult<Handle> {
+        let dirent = self.resolve(&path).await?;
+        if dirent.stat.file_type != FileType::Directory {
+            return Err(StatusCode::Failure.into());
+        }
+        let files = self.fs.store.list_files(&dirent.id)?;
+
+        Ok(Handle {
+            id,
+            handle: self.add_handle(OpenHandle::Directory(Some(files))),
+        })
+    }
+
+    async fn readdir(&mut self, id: u32, handle: String) -> SftpResult<Name> {
+        let files = match self.handles.get_mut(&handle) {
+            Some(OpenHandle::Directory(files)) => files.take().ok_or(StatusCode::Eof)?,
+            _ => return Err(StatusCode::Failure.into()),
+        };
+
+        Ok(Name {
+            id,
+            files: files
+                .iter()
+                .map(|x| File::new(x.name.clone(), to_file_attributes(x)))
+                .collect(),
+        })
+    }
+
+    async fn remove(&mut self, id: u32, filename: String) -> SftpResult<Status> {
+        let content = ModifyOperationContent::RemoveFileOperation(RemoveFileOperation {});
+        self.apply_to_path(id, &filename, |x| x != FileType::Directory, content)
+            .await
+    }
+
+    async fn mkdir(&mut self, id: u32, path: String, attrs: FileAttributes) -> SftpResult<Status> {
+        let fs = &self.fs;
+        let (parent, name) = self.resolve_parent(fs, &path)?;
+        if fs.lookup(&parent.id, &name)?.is_some() {
+            return Err(StatusCode::Failure.into());
+        }
+
+        let content = ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+            name,
+            perm: attrs.permissions.map_or(DEFAULT_DIRECTORY_MODE, |x| {
+                (x & PERMISSIONS_MASK) as FileMode
+            }),
+            owner: None,
+        });
+        fs.apply_locked(
+            &self.share,
+            make_operation(&parent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn rmdir(&mut self, id: u32, path: String) -> SftpResult<Status> {
+        let content = ModifyOperationContent::RemoveDirectoryOperation(RemoveDirectoryOperation {});
+        self.apply_to_path(id, &path, |x| x == FileType::Directory, content)
+            .await
+    }
+
+    async fn realpath(&mut self, id: u32, path: String) -> SftpResult<Name> {
+        Ok(Name {
+            id,
+            files: vec![File::dummy(format!("/{}", parse_path(&path).join("/")))],
+        })
+    }
+
+    async fn rename(&mut self, id: u32, oldpath: String, newpath: String) -> SftpResult<Status> {
+        let dirent = self.resolve(&oldpath).await?;
+
+        let fs = &self.fs;
+        let (parent, name) = self.resolve_parent(fs, &newpath)?;
+        // SFTP version 3 does not allow replacing the existing files
+        if fs.lookup(&parent.id, &name)?.is_some() {
+            return Err(StatusCode::Failure.into());
+        }
+
+        let content = ModifyOperationContent::RenameOperation(RenameOperation {
+            new_parent: parent.id,
+            new_name: name,
+        });
+        fs.apply_locked(
+            &self.share,
+            make_operation(&dirent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(ok_status(id))
+    }
+
+    async fn readlink(&mut self, id: u32, path: String) -> SftpResult<Name> {
+        let dirent = self.resolve(&path).await?;
+        if dirent.stat.file_type != FileType::Symlink {
+            return Err(StatusCode::Failure.into());
+        }
+
+        let data = self.fs.read_file(&dirent, 0, dirent.stat.size)?;
+        let link = String::from_utf8(data).map_err(|_| StatusCode::Failure)?;
+
+        Ok(Name {
+            id,
+            files: vec![File::dummy(link)],
+        })
+    }
+
+    async fn symlink(
+        &mut self,
+        id: u32,
+        linkpath: String,
+        targetpath: String,
+    ) -> SftpResult<Status> {
+        // OpenSSH sends the arguments in the reverse order than the draft specifies, and the
+        // other clients follow it
+        let (link, path) = (linkpath, targetpath);
+
+        let fs = &self.fs;
+        let (parent, name) = self.resolve_parent(fs, &path)?;
+        let content = ModifyOperationContent::CreateSymlinkOperation(CreateSymlinkOperation {
+            name,
+            link,
+            owner: None,
+        });
+        fs.apply_locked(
+            &self.share,
+            make_operation(&parent, SFTP_REPLICA_ID, content),
+        )
+        .await?;
+
+        Ok(ok_status(id))
+    }
+}
+
+/// SSH connection that only serves the SFTP subsystem, authenticated with the tenant's token as
+/// the password.
+struct SshSession {
+    fs: Arc<RemoteFs>,
+    authenticator: Authenticator,
+    tenant: String,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl SshSession {
+    fn authenticate(&mut self, token: Option<&str>) -> Auth {
+        match self.authenticator.authenticate(token) {
+            Ok(tenant) => {
+                self.tenant = tenant;
+                Auth::Accept
+            }
+            Err(_) => Auth::reject(),
+        }
+    }
+}
+
+impl russh::server::Handler for SshSession {
+    type Error = russh::Error;
+
+    async fn auth_none(&mut self, _user: &str) -> Result<Auth, Self::Error> {
+        Ok(self.authenticate(None))
+    }
+
+    async fn auth_password(&mut self, _user: &str, password: &str) -> Result<Auth, Self::Error> {
+        Ok(self.authenticate(Some(password)))
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        reply.accept().await;
+
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.close(channel)
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel_id: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let channel = match self.channels.remove(&channel_id) {
+            Some(channel) if name == SFTP_SUBSYSTEM => channel,
+            _ => return session.channel_failure(channel_id),
+        };
+        let share = match self.fs.get_tenant_share(&self.tenant).await {
+            Ok(share) => share,
+            Err(_) => return session.channel_failure(channel_id),
+        };
+
+        session.channel_success(channel_id)?;
+        let sftp = SftpSession {
+            fs: self.fs.clone(),
+            share,
+            handles: HashMap::new(),
+            next_handle: 0,
+        };
+        russh_sftp::server::run(channel.into_stream(), sftp).await;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct SftpServer {
+    fs: Arc<RemoteFs>,
+    authenticator: Authenticator,
+}
+
+impl Server for SftpServer {
+    type Handler = SshSession;
+
+    fn new_client(&mut self, peer_addr: Option<SocketAddr>) -> Self::Handler {
+        debug!("SFTP connection from {:?}", peer_addr);
+
+        SshSession {
+            fs: self.fs.clone(),
+            authenticator: self.authenticator.clone(),
+            tenant: String::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+pub fn load_host_key(path: &Path) -> Result<PrivateKey, Box<dyn Error>> {
+    russh::keys::load_secret_key(path, None)
+        .map_err(|e| format!("Could not load the SSH host key {}: {}", path.display(), e).into())
+}
+
+pub async fn run_sftp_server(
+    fs: Arc<RemoteFs>,
+    authenticator: Authenticator,
+    host_key: PrivateKey,
+    address: SocketAddr,
+) -> std::io::Result<()> {
+    info!("SFTP available at {}", address);
+
+    let config = russh::server::Config {
+        keys: vec![host_key],
+        auth_rejection_time: Duration::from_secs(1),
+        auth_rejection_time_initial: Some(Duration::from_secs(0)),
+        ..Default::default()
+    };
+
+    SftpServer { fs, authenticator }
+        .run_on_address(Arc::new(config), address)
+        .await
+}
@@ -0,0 +1,54 @@
+use offs::store::DirEntity;
+use offs::ROOT_ID;
+
+/// A directory subtree of a tenant, exposed to the clients either as a named share or as the
+/// tenant's whole tree. The clients see the root of the share as [`ROOT_ID`], so the IDs are
+/// translated when crossing the RPC boundary.
+#[derive(Clone, Debug)]
+pub struct Share {
+    tenant: String,
+    root_id: String,
+    is_tenant_root: bool,
+}
+
+impl Share {
+    pub fn new(tenant: String, root_id: String, is_tenant_root: bool) -> Self {
+        Self {
+            tenant,
+            root_id,
+            is_tenant_root,
+        }
+    }
+
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    pub fn root_id(&self) -> &str {
+        &self.root_id
+    }
+
+    pub fn is_tenant_root(&self) -> bool {
+        self.is_tenant_root
+    }
+
+    pub fn to_store_id<'a>(&'a self, id: &'a str) -> &'a str {
+        if id == ROOT_ID {
+            &self.root_id
+        } else {
+            id
+        }
+    }
+
+    pub fn to_client_dirent(&self, mut dirent: DirEntity) -> DirEntity {
+        if dirent.id == self.root_id {
+            dirent.id = ROOT_ID.to_owned();
+            dirent.parent = String::new();
+            dirent.name = String::new();
+        } else if dirent.parent == self.root_id {
+            dirent.parent = ROOT_ID.to_owned();
+        }
+
+        dirent
+    }
+}
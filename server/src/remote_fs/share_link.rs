@@ -0,0 +1,204 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use hyper::header::{
+    CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use percent_encoding::utf8_percent_encode;
+use ring::hmac;
+use tracing::{debug, info};
+
+use offs::now;
+use offs::store::{DirEntity, FileType};
+
+use super::webdav::{
+    empty_response, format_http_date, make_etag, make_listing, parse_path, WebDavError,
+    PATH_SEGMENT,
+};
+use super::RemoteFs;
+
+/// The keys shorter than this are rejected, as they could be guessed.
+const MIN_KEY_LENGTH: usize = 32;
+
+/// Signs the tokens of the share links, which give read-only access to a single file or
+/// directory tree until they expire. A token consists of the ID of the file and the time it
+/// expires at, followed by their HMAC-SHA256, so the server does not have to store the links.
+#[derive(Clone)]
+pub struct ShareLinkSigner {
+    key: hmac::Key,
+    url: String,
+}
+
+impl ShareLinkSigner {
+    /// Reads the key from the file. The links are created with the given URL as their base.
+    pub fn load(key_file: &Path, url: &str) -> Result<Self, Box<dyn Error>> {
+        let key = fs::read(key_file)
+            .map_err(|e| format!("Could not read {}: {}", key_file.display(), e))?;
+        let key = key.trim_ascii();
+        if key.len() < MIN_KEY_LENGTH {
+            return Err(format!(
+                "{}: the share link key has to be at least {} bytes long",
+                key_file.display(),
+                MIN_KEY_LENGTH
+            )
+            .into());
+        }
+
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            url: url.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// Returns the URL of the link to the file. The links to the directories end with a slash,
+    /// so that the relative links of their listings work.
+    pub fn create_link(&self, dirent: &DirEntity, expires_at: i64) -> String {
+        let payload = format!("{}.{}", dirent.id, expires_at);
+        let signature = hmac::sign(&self.key, payload.as_bytes());
+        let suffix = if dirent.stat.file_type == FileType::Directory {
+            "/"
+        } else {
+            ""
+        };
+
+        format!(
+            "{}/{}.{}{}",
+            self.url,
+            payload,
+            base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD),
+            suffix
+        )
+    }
+
+    /// Returns the ID of the file the token gives access to, unless it is forged or expired.
+    fn verify(&self, token: &str) -> Option<String> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &signature).ok()?;
+
+        let (id, expires_at) = payload.split_once('.')?;
+        if expires_at.parse::<i64>().ok()? <= now().sec {
+            return None;
+        }
+
+        Some(id.to_owned())
+    }
+}
+
+/// Serves the files the share links point to over plain HTTP (`GET` and `HEAD` only). The
+/// first segment of the path is the token; the rest is the path within the shared directory.
+struct ShareLinkServer {
+    fs: Arc<RemoteFs>,
+    signer: ShareLinkSigner,
+}
+
+impl ShareLinkServer {
+    async fn handle_request(&self, request: Request<Body>) -> Response<Body> {
+        debug!("Share link request: {} {}", request.method(), request.uri());
+
+        let with_body = match *request.method() {
+            Method::GET => true,
+            Method::HEAD => false,
+            _ => return empty_response(StatusCode::METHOD_NOT_ALLOWED),
+        };
+
+        self.get(request.uri().path(), with_body)
+            .await
+            .unwrap_or_else(|WebDavError(status)| empty_response(status))
+    }
+
+    async fn get(&self, uri_path: &str, with_body: bool) -> Result<Response<Body>, WebDavError> {
+        let path = parse_path(uri_path)?;
+        let (token, path) = path
+            .split_first()
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+        // Forged and expired links are not told apart from the ones to removed files
+        let id = self
+            .signer
+            .verify(token)
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+
+        let fs = &self.fs;
+        let mut dirent = fs.store.query_file(&id)?;
+        for name in path {
+            dirent = fs
+                .lookup(&dirent.id, name)?
+                .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+        }
+
+        let (content_type, length, body) = match dirent.stat.file_type {
+            FileType::Directory if !uri_path.ends_with('/') => {
+                return Ok(Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(LOCATION, format!("{}/", uri_path))
+                    .body(Body::empty())
+                    .unwrap());
+            }
+            FileType::Directory => {
+                let listing = make_listing(path, &fs.store.list_files(&dirent.id)?);
+                (
+                    "text/html; charset=utf-8",
+                    listing.len() as u64,
+                    Body::from(listing),
+                )
+            }
+            FileType::RegularFile => {
+                let body = if with_body {
+                    fs.stream_file(&dirent)
+                } else {
+                    Body::empty()
+                };
+                ("application/octet-stream", dirent.stat.size, body)
+            }
+            _ => return Err(WebDavError(StatusCode::NOT_FOUND)),
+        };
+
+        let mut response = Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, length)
+            .header(LAST_MODIFIED, format_http_date(dirent.stat.mtim))
+            .header(ETAG, make_etag(&dirent));
+        if dirent.stat.file_type == FileType::RegularFile {
+            // The token is not a meaningful name to save the file as
+            response = response.header(
+                CONTENT_DISPOSITION,
+                format!(
+                    "inline; filename*=UTF-8''{}",
+                    utf8_percent_encode(&dirent.name, PATH_SEGMENT)
+                ),
+            );
+        }
+
+        Ok(response
+            .body(if with_body { body } else { Body::empty() })
+            .unwrap())
+    }
+}
+
+pub async fn run_share_link_server(
+    fs: Arc<RemoteFs>,
+    signer: ShareLinkSigner,
+    address: SocketAddr,
+) -> Result<(), hyper::Error> {
+    info!("Share links available at http://{}/", address);
+
+    let server = Arc::new(ShareLinkServer { fs, signer });
+    let make_service = make_service_fn(move |_| {
+        let server = server.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(server.handle_request(request).await) }
+            }))
+        }
+    });
+
+    hyper::Server::bind(&address).serve(make_service).await
+}
@@ -0,0 +1,92 @@
+use offs::errors::{OperationError, OperationResult};
+use offs::modify_op::{ModifyOperation, ModifyOperationContent};
+
+/// Maximum length of a file name, in bytes (`NAME_MAX` on Linux).
+pub const MAX_NAME_LENGTH: usize = 255;
+/// Maximum length of a symlink target, in bytes (`PATH_MAX` on Linux).
+pub const MAX_SYMLINK_LENGTH: usize = 4096;
+/// Maximum number of directories a file can be nested in; a deeper path could not be expressed
+/// within `PATH_MAX` anyway.
+pub const MAX_PATH_DEPTH: u64 = 2048;
+
+const RESERVED_NAMES: &[&str] = &[".", ".."];
+
+pub fn validate_name(name: &str) -> OperationResult<()> {
+    if name.is_empty() {
+        Err(OperationError::invalid_argument("empty file name"))
+    } else if name.len() > MAX_NAME_LENGTH {
+        Err(OperationError::name_too_long(&format!(
+            "longer than {} bytes",
+            MAX_NAME_LENGTH
+        )))
+    } else if name.contains(&['/', '\0'][..]) {
+        Err(OperationError::invalid_argument(&format!(
+            "file name {:?} contains a slash or a null character",
+            name
+        )))
+    } else if RESERVED_NAMES.contains(&name) {
+        Err(OperationError::invalid_argument(&format!(
+            "reserved file name {:?}",
+            name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_symlink(link: &str) -> OperationResult<()> {
+    if link.is_empty() {
+        Err(OperationError::invalid_argument("empty symlink target"))
+    } else if link.len() > MAX_SYMLINK_LENGTH {
+        Err(OperationError::name_too_long(&format!(
+            "symlink target longer than {} bytes",
+            MAX_SYMLINK_LENGTH
+        )))
+    } else if link.contains('\0') {
+        Err(OperationError::invalid_argument(
+            "symlink target contains a null character",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks the arguments of the operation that do not depend on the state of the store.
+pub fn validate_operation(operation: &ModifyOperation) -> OperationResult<()> {
+    match &operation.operation {
+        ModifyOperationContent::CreateFileOperation(op) => validate_name(&op.name),
+        ModifyOperationContent::CreateSymlinkOperation(op) => {
+            validate_name(&op.name)?;
+            validate_symlink(&op.link)
+        }
+        ModifyOperationContent::CreateDirectoryOperation(op) => validate_name(&op.name),
+        ModifyOperationContent::RenameOperation(op) => validate_name(&op.new_name),
+        ModifyOperationContent::WriteOperation(op) => {
+            validate_range(op.offset, op.data.len() as i64)
+        }
+        ModifyOperationContent::FallocateOperation(op) => {
+            if op.length <= 0 {
+                return Err(OperationError::invalid_argument("non-positive length"));
+            }
+            validate_range(op.offset, op.length)
+        }
+        ModifyOperationContent::CopyRangeOperation(op) => {
+            validate_range(op.source_offset, op.length)?;
+            validate_range(op.offset, op.length)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Rejects the negative offsets and lengths, as well as the ranges ending past `i64::MAX`.
+fn validate_range(offset: i64, length: i64) -> OperationResult<()> {
+    if offset < 0 || length < 0 {
+        Err(OperationError::invalid_argument(
+            "negative offset or length",
+        ))
+    } else if offset.checked_add(length).is_none() {
+        Err(OperationError::invalid_argument("range out of bounds"))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,675 @@
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Request, Response, StatusCode, Uri};
+use itertools::Itertools;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use tracing::{debug, info};
+
+use offs::errors::{OperationError, OperationErrorType};
+use offs::modify_op::{
+    CopyRangeOperation, CreateDirectoryOperation, CreateFileOperation, CreateSymlinkOperation,
+    ModifyOperationContent, RemoveDirectoryOperation, RemoveFileOperation, RenameOperation,
+    SetAttributesOperation, WriteOperation,
+};
+use offs::store::{DirEntity, FileMode, FileType};
+use offs::timespec::Timespec;
+
+use super::frontend::make_operation;
+use super::{RemoteFs, Share};
+use crate::auth::{Authenticator, BEARER_PREFIX};
+use crate::limits::SharedLimits;
+
+const BASIC_PREFIX: &str = "Basic ";
+/// Replica the modifications made over WebDAV are attributed to in the version vectors.
+const WEBDAV_REPLICA_ID: &str = "webdav";
+const DEFAULT_FILE_MODE: FileMode = 0o644;
+const DEFAULT_DIRECTORY_MODE: FileMode = 0o755;
+const ALLOWED_METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, MKCOL, COPY, MOVE, PROPFIND";
+
+/// Characters that are left unescaped in the path segments of the URLs (RFC 3986 `unreserved`).
+pub(super) const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+pub(super) struct WebDavError(pub(super) StatusCode);
+
+impl From<OperationError> for WebDavError {
+    fn from(error: OperationError) -> Self {
+        debug!("WebDAV request failed: {:?}", error);
+
+        WebDavError(match error.error_type {
+            OperationErrorType::FileDoesNotExist | OperationErrorType::ShareDoesNotExist => {
+                StatusCode::NOT_FOUND
+            }
+            OperationErrorType::DirectoryNotEmpty
+            | OperationErrorType::ConflictedFile
+            | OperationErrorType::InvalidContentVersion
+            | OperationErrorType::RenameIntoDescendant => StatusCode::CONFLICT,
+            OperationErrorType::InvalidUnicode
+            | OperationErrorType::InvalidAcl
+            | OperationErrorType::InvalidArgument
+            | OperationErrorType::NameTooLong => StatusCode::BAD_REQUEST,
+            OperationErrorType::NotADirectory | OperationErrorType::IsADirectory => {
+                StatusCode::CONFLICT
+            }
+            OperationErrorType::Unauthenticated => StatusCode::UNAUTHORIZED,
+            OperationErrorType::PermissionDenied => StatusCode::FORBIDDEN,
+            OperationErrorType::NoSpace | OperationErrorType::QuotaExceeded => {
+                StatusCode::INSUFFICIENT_STORAGE
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    }
+}
+
+type WebDavResult<T> = Result<T, WebDavError>;
+
+/// Serves the files of the tenants over WebDAV (class 1, without locking), so that they can be
+/// accessed from a browser or a phone without installing the client. The modifications are
+/// applied as the same operations the clients send.
+struct WebDavServer {
+    fs: Arc<RemoteFs>,
+    authenticator: Authenticator,
+    limits: SharedLimits,
+}
+
+impl WebDavServer {
+    async fn handle_request(&self, request: Request<Body>) -> Response<Body> {
+        debug!("WebDAV request: {} {}", request.method(), request.uri());
+
+        let tenant = match self
+            .authenticator
+            .authenticate(get_token(&request).as_deref())
+        {
+            Ok(tenant) => tenant,
+            Err(_) => {
+                return Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header("WWW-Authenticate", "Basic realm=\"offs\"")
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        };
+
+        let result = match parse_path(request.uri().path()) {
+            Ok(path) => self.dispatch(&tenant, path, request).await,
+            Err(e) => Err(e),
+        };
+
+        result.unwrap_or_else(|WebDavError(status)| empty_response(status))
+    }
+
+    async fn dispatch(
+        &self,
+        tenant: &str,
+        path: Vec<String>,
+        request: Request<Body>,
+    ) -> WebDavResult<Response<Body>> {
+        let share = self.fs.get_tenant_share(tenant).await?;
+
+        match request.method().as_str() {
+            "OPTIONS" => Ok(Response::builder()
+                .header("DAV", "1")
+                .header("Allow", ALLOWED_METHODS)
+                .header("MS-Author-Via", "DAV")
+                .body(Body::empty())
+                .unwrap()),
+            "PROPFIND" => self.propfind(&share, &path, request.headers()).await,
+            "GET" => self.get(&share, &path, true).await,
+            "HEAD" => self.get(&share, &path, false).await,
+            "PUT" => self.put(&share, &path, request.into_body()).await,
+            "DELETE" => self.delete(&share, &path).await,
+            "MKCOL" => self.mkcol(&share, &path).await,
+            "MOVE" => {
+                self.move_or_copy(&share, &path, request.headers(), true)
+                    .await
+            }
+            "COPY" => {
+                self.move_or_copy(&share, &path, request.headers(), false)
+                    .await
+            }
+            _ => Err(WebDavError(StatusCode::METHOD_NOT_ALLOWED)),
+        }
+    }
+
+    async fn propfind(
+        &self,
+        share: &Share,
+        path: &[String],
+        headers: &HeaderMap,
+    ) -> WebDavResult<Response<Body>> {
+        let fs = &self.fs;
+        let dirent = fs
+            .resolve_path(share, path)?
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+
+        let mut entries = vec![(make_href(path, &dirent), dirent)];
+        let depth = headers.get("Depth").and_then(|x| x.to_str().ok());
+        if depth != Some("0") && entries[0].1.stat.file_type == FileType::Directory {
+            for child in fs.store.list_files(&entries[0].1.id)? {
+                let mut child_path = path.to_vec();
+                child_path.push(child.name.clone());
+                entries.push((make_href(&child_path, &child), child));
+            }
+        }
+
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+        for (href, dirent) in &entries {
+            write_propfind_entry(&mut body, href, dirent);
+        }
+        body.push_str("</D:multistatus>\n");
+
+        Ok(Response::builder()
+            .status(StatusCode::MULTI_STATUS)
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap())
+    }
+
+    async fn get(
+        &self,
+        share: &Share,
+        path: &[String],
+        with_body: bool,
+    ) -> WebDavResult<Response<Body>> {
+        let fs = &self.fs;
+        let dirent = fs
+            .resolve_path(share, path)?
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+
+        let (content_type, length, body) = if dirent.stat.file_type == FileType::Directory {
+            let listing = make_listing(path, &fs.store.list_files(&dirent.id)?);
+            (
+                "text/html; charset=utf-8",
+                listing.len() as u64,
+                Body::from(listing),
+            )
+        } else {
+            let body = if with_body {
+                fs.stream_file(&dirent)
+            } else {
+                Body::empty()
+            };
+            ("application/octet-stream", dirent.stat.size, body)
+        };
+
+        let response = Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_LENGTH, length)
+            .header(LAST_MODIFIED, format_http_date(dirent.stat.mtim))
+            .header(ETAG, make_etag(&dirent));
+
+        Ok(response
+            .body(if with_body { body } else { Body::empty() })
+            .unwrap())
+    }
+
+    /// Replaces the content of the file with the body, written in pieces of at most
+    /// `max_write_size` bytes as it arrives, so that the size of the files uploaded is not
+    /// limited by the memory of the server.
+    async fn put(
+        &self,
+        share: &Share,
+        path: &[String],
+        mut body: Body,
+    ) -> WebDavResult<Response<Body>> {
+        let (name, parent_path) = path
+            .split_last()
+            .ok_or(WebDavError(StatusCode::METHOD_NOT_ALLOWED))?;
+
+        let fs = &self.fs;
+        let parent = resolve_directory(fs, share, parent_path)?;
+        let (mut dirent, status) = match fs.lookup(&parent.id, name)? {
+            Some(dirent) if dirent.stat.file_type == FileType::Directory => {
+                return Err(WebDavError(StatusCode::METHOD_NOT_ALLOWED))
+            }
+            Some(dirent) => (dirent, StatusCode::NO_CONTENT),
+            None => {
+                let operation = make_operation(
+                    &parent,
+                    WEBDAV_REPLICA_ID,
+                    ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+                        name: name.clone(),
+                        file_type: FileType::RegularFile,
+                        perm: DEFAULT_FILE_MODE,
+                        dev: 0,
+                        owner: None,
+                    }),
+                );
+                (
+                    fs.apply_locked(share, operation).await?,
+                    StatusCode::CREATED,
+                )
+            }
+        };
+
+        if dirent.stat.size != 0 {
+            let operation = make_operation(
+                &dirent,
+                WEBDAV_REPLICA_ID,
+                ModifyOperationContent::SetAttributesOperation(SetAttributesOperation {
+                    perm: None,
+                    uid: None,
+                    gid: None,
+                    size: Some(0),
+                    atim: None,
+                    mtim: None,
+                }),
+            );
+            dirent = fs.apply_locked(share, operation).await?;
+        }
+
+        let max_write_size = self.limits.get().max_write_size;
+        let mut offset = 0;
+        let mut data = Vec::new();
+        loop {
+            let chunk = body.data().await;
+            let is_last = chunk.is_none();
+            if let Some(chunk) = chunk {
+                let chunk = chunk.map_err(|_| WebDavError(StatusCode::BAD_REQUEST))?;
+                data.extend_from_slice(&chunk);
+            }
+
+            while data.len() >= max_write_size || (is_last && !data.is_empty()) {
+                let rest = data.split_off(data.len().min(max_write_size));
+                let length = data.len();
+                let operation = make_operation(
+                    &dirent,
+                    WEBDAV_REPLICA_ID,
+                    ModifyOperationContent::WriteOperation(WriteOperation {
+                        offset,
+                        data: std::mem::replace(&mut data, rest),
+                    }),
+                );
+                dirent = fs.apply_locked(share, operation).await?;
+                offset += length as i64;
+            }
+            if is_last {
+                break;
+            }
+        }
+
+        Ok(empty_response(status))
+    }
+
+    async fn delete(&self, share: &Share, path: &[String]) -> WebDavResult<Response<Body>> {
+        if path.is_empty() {
+            return Err(WebDavError(StatusCode::FORBIDDEN));
+        }
+
+        let dirent = self
+            .fs
+            .resolve_path(share, path)?
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+        let _locks = self.fs.lock_trees(&[&dirent], &[]).await?;
+        let share = share.clone();
+        self.fs
+            .with_session(move |fs| remove_tree(fs, &share, &dirent))
+            .await?;
+
+        Ok(empty_response(StatusCode::NO_CONTENT))
+    }
+
+    async fn mkcol(&self, share: &Share, path: &[String]) -> WebDavResult<Response<Body>> {
+        let (name, parent_path) = path
+            .split_last()
+            .ok_or(WebDavError(StatusCode::METHOD_NOT_ALLOWED))?;
+
+        let fs = &self.fs;
+        let parent = resolve_directory(fs, share, parent_path)?;
+        if fs.lookup(&parent.id, name)?.is_some() {
+            return Err(WebDavError(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let operation = make_operation(
+            &parent,
+            WEBDAV_REPLICA_ID,
+            ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                name: name.clone(),
+                perm: DEFAULT_DIRECTORY_MODE,
+                owner: None,
+            }),
+        );
+        fs.apply_locked(share, operation).await?;
+
+        Ok(empty_response(StatusCode::CREATED))
+    }
+
+    async fn move_or_copy(
+        &self,
+        share: &Share,
+        path: &[String],
+        headers: &HeaderMap,
+        is_move: bool,
+    ) -> WebDavResult<Response<Body>> {
+        let destination = headers
+            .get("Destination")
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<Uri>().ok())
+            .ok_or(WebDavError(StatusCode::BAD_REQUEST))?;
+        let destination = parse_path(destination.path())?;
+        let overwrite = headers.get("Overwrite") != Some(&HeaderValue::from_static("F"));
+
+        let (name, parent_path) = destination
+            .split_last()
+            .ok_or(WebDavError(StatusCode::FORBIDDEN))?;
+        if path.is_empty() || destination.starts_with(path) {
+            return Err(WebDavError(StatusCode::FORBIDDEN));
+        }
+
+        let fs = &self.fs;
+        let source = fs
+            .resolve_path(share, path)?
+            .ok_or(WebDavError(StatusCode::NOT_FOUND))?;
+        let parent = resolve_directory(fs, share, parent_path)?;
+        let existing = fs.lookup(&parent.id, name)?;
+        let status = match existing {
+            Some(_) if !overwrite => return Err(WebDavError(StatusCode::PRECONDITION_FAILED)),
+            Some(_) => StatusCode::NO_CONTENT,
+            None => StatusCode::CREATED,
+        };
+
+        let mut trees = existing.iter().collect_vec();
+        if is_move {
+            trees.push(&source);
+        }
+        let _locks = fs.lock_trees(&trees, &[&parent]).await?;
+        let share = share.clone();
+        let name = name.clone();
+        fs.with_session(move |fs| -> WebDavResult<()> {
+            if let Some(existing) = &existing {
+                remove_tree(fs, &share, existing)?;
+            }
+
+            if is_move {
+                let operation = make_operation(
+                    &source,
+                    WEBDAV_REPLICA_ID,
+                    ModifyOperationContent::RenameOperation(RenameOperation {
+                        new_parent: parent.id.clone(),
+                        new_name: name,
+                    }),
+                );
+                fs.apply_operation(&share, operation)?;
+            } else {
+                copy_tree(fs, &share, &source, &parent, &name)?;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        Ok(empty_response(status))
+    }
+}
+
+fn get_token(request: &Request<Body>) -> Option<String> {
+    let header = request
+        .headers()
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+
+    if let Some(token) = header.strip_prefix(BEARER_PREFIX) {
+        return Some(token.to_owned());
+    }
+
+    // The user name is ignored, as the token alone identifies the tenant
+    let credentials = base64::decode(header.strip_prefix(BASIC_PREFIX)?).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_, token) = credentials.split_once(':')?;
+
+    Some(token.to_owned())
+}
+
+pub(super) fn parse_path(path: &str) -> WebDavResult<Vec<String>> {
+    path.split('/')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            percent_decode_str(x)
+                .decode_utf8()
+                .map(|x| x.into_owned())
+                .map_err(|_| WebDavError(StatusCode::BAD_REQUEST))
+        })
+        .collect()
+}
+
+fn make_href(path: &[String], dirent: &DirEntity) -> String {
+    let mut href = String::new();
+    for name in path {
+        write!(href, "/{}", utf8_percent_encode(name, PATH_SEGMENT)).unwrap();
+    }
+    if href.is_empty() || dirent.stat.file_type == FileType::Directory {
+        href.push('/');
+    }
+
+    href
+}
+
+pub(super) fn make_etag(dirent: &DirEntity) -> String {
+    format!("\"{}-{}\"", dirent.id, dirent.content_version)
+}
+
+/// Converts the time to a date with a whole number of seconds; the times chrono cannot
+/// represent are shown as the Unix epoch.
+fn to_date(time: Timespec) -> DateTime<Utc> {
+    Utc.timestamp_opt(time.sec, 0)
+        .single()
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+pub(super) fn format_http_date(time: Timespec) -> String {
+    to_date(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Formats the time as RFC 3339, like WebDAV expects the creation dates.
+fn format_creation_date(time: Timespec) -> String {
+    to_date(time).format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_propfind_entry(body: &mut String, href: &str, dirent: &DirEntity) {
+    let is_directory = dirent.stat.file_type == FileType::Directory;
+
+    write!(
+        body,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>",
+        href,
+        escape_xml(&dirent.name)
+    )
+    .unwrap();
+    if is_directory {
+        body.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        write!(
+            body,
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>application/octet-stream</D:getcontenttype>",
+            dirent.stat.size
+        )
+        .unwrap();
+    }
+    writeln!(
+        body,
+        "<D:creationdate>{}</D:creationdate><D:getlastmodified>{}</D:getlastmodified>\
+         <D:getetag>{}</D:getetag></D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat>\
+         </D:response>",
+        format_creation_date(dirent.stat.crtim),
+        format_http_date(dirent.stat.mtim),
+        escape_xml(&make_etag(dirent))
+    )
+    .unwrap();
+}
+
+pub(super) fn make_listing(path: &[String], files: &[DirEntity]) -> String {
+    let title = escape_xml(&format!("/{}", path.join("/")));
+    let mut listing = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\
+         <body><h1>{0}</h1><ul>\n",
+        title
+    );
+    if !path.is_empty() {
+        listing.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+
+    let mut files = files.iter().collect::<Vec<_>>();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    for file in files {
+        let suffix = if file.stat.file_type == FileType::Directory {
+            "/"
+        } else {
+            ""
+        };
+        writeln!(
+            listing,
+            "<li><a href=\"{}{}\">{}{}</a></li>",
+            utf8_percent_encode(&file.name, PATH_SEGMENT),
+            suffix,
+            escape_xml(&file.name),
+            suffix
+        )
+        .unwrap();
+    }
+    listing.push_str("</ul></body></html>\n");
+
+    listing
+}
+
+pub(super) fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn resolve_directory(fs: &RemoteFs, share: &Share, path: &[String]) -> WebDavResult<DirEntity> {
+    match fs.resolve_path(share, path)? {
+        Some(dirent) if dirent.stat.file_type == FileType::Directory => Ok(dirent),
+        _ => Err(WebDavError(StatusCode::CONFLICT)),
+    }
+}
+
+/// Removes the file, or the directory with all its contents, as WebDAV's `DELETE` does.
+fn remove_tree(fs: &mut RemoteFs, share: &Share, dirent: &DirEntity) -> WebDavResult<()> {
+    let content = if dirent.stat.file_type == FileType::Directory {
+        for child in fs.store.list_files(&dirent.id)? {
+            remove_tree(fs, share, &child)?;
+        }
+        ModifyOperationContent::RemoveDirectoryOperation(RemoveDirectoryOperation {})
+    } else {
+        ModifyOperationContent::RemoveFileOperation(RemoveFileOperation {})
+    };
+
+    // The versions of the directory have changed when removing its children
+    let dirent = fs.store.query_file(&dirent.id)?;
+    fs.apply_operation(share, make_operation(&dirent, WEBDAV_REPLICA_ID, content))?;
+
+    Ok(())
+}
+
+fn copy_tree(
+    fs: &mut RemoteFs,
+    share: &Share,
+    source: &DirEntity,
+    parent: &DirEntity,
+    name: &str,
+) -> WebDavResult<()> {
+    let stat = &source.stat;
+    let content = match stat.file_type {
+        FileType::Directory => {
+            ModifyOperationContent::CreateDirectoryOperation(CreateDirectoryOperation {
+                name: name.to_owned(),
+                perm: stat.mode,
+                owner: Some(source.owner()),
+            })
+        }
+        FileType::Symlink => {
+            let link = String::from_utf8(fs.read_file(source, 0, stat.size)?)
+                .map_err(|_| WebDavError(StatusCode::INTERNAL_SERVER_ERROR))?;
+            ModifyOperationContent::CreateSymlinkOperation(CreateSymlinkOperation {
+                name: name.to_owned(),
+                link,
+                owner: Some(source.owner()),
+            })
+        }
+        file_type => ModifyOperationContent::CreateFileOperation(CreateFileOperation {
+            name: name.to_owned(),
+            file_type,
+            perm: stat.mode,
+            dev: stat.dev,
+            owner: Some(source.owner()),
+        }),
+    };
+    let dirent = fs.apply_operation(share, make_operation(parent, WEBDAV_REPLICA_ID, content))?;
+
+    match stat.file_type {
+        FileType::Directory => {
+            for child in fs.store.list_files(&source.id)? {
+                copy_tree(fs, share, &child, &dirent, &child.name)?;
+            }
+        }
+        FileType::RegularFile if stat.size > 0 => {
+            let operation = make_operation(
+                &dirent,
+                WEBDAV_REPLICA_ID,
+                ModifyOperationContent::CopyRangeOperation(CopyRangeOperation {
+                    source_id: source.id.clone(),
+                    source_offset: 0,
+                    offset: 0,
+                    length: stat.size as i64,
+                }),
+            );
+            fs.apply_operation(share, operation)?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+pub async fn run_webdav_server(
+    fs: Arc<RemoteFs>,
+    authenticator: Authenticator,
+    limits: SharedLimits,
+    address: SocketAddr,
+) -> Result<(), hyper::Error> {
+    info!("WebDAV available at http://{}/", address);
+
+    let server = Arc::new(WebDavServer {
+        fs,
+        authenticator,
+        limits,
+    });
+    let make_service = make_service_fn(move |_| {
+        let server = server.clone();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(server.handle_request(request).await) }
+            }))
+        }
+    });
+
+    hyper::Server::bind(&address).serve(make_service).await
+}
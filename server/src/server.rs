@@ -1,25 +1,309 @@
+use std::io;
 use std::net::SocketAddr;
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use sd_notify::NotifyState;
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_stream::wrappers::TcpListenerStream;
 use tonic::transport::Server;
+use tower::ServiceBuilder;
+use tracing::{debug, error, info, info_span};
 
+use offs::proto::filesystem::admin_server::AdminServer;
 use offs::proto::filesystem::remote_fs_server::RemoteFsServer;
+use offs::store::blob_cache::BlobCache;
 use offs::store::id_generator::RandomHexIdGenerator;
 use offs::store::Store;
+use offs::REQUEST_ID_HEADER_KEY;
 
-use crate::remote_fs::{RemoteFs, RemoteFsServerImpl};
+use crate::auth::{AdminAuthenticator, Authenticator};
+use crate::config::{Config, Maintenance};
+use crate::health::run_health_reporter;
+use crate::limits::SharedLimits;
+use crate::metrics::{run_metrics_server, Metrics};
+use crate::remote_fs::{
+    load_host_key, run_sftp_server, run_share_link_server, run_webdav_server, AdminServerImpl,
+    RemoteFs, RemoteFsServerImpl, ShareLinkSigner,
+};
+use crate::sessions::{SessionRegistry, SessionTracker};
+
+/// Addresses of the frontends serving the files over other protocols than gRPC, if enabled.
+pub struct Frontends {
+    pub webdav_address: Option<SocketAddr>,
+    pub sftp_address: Option<SocketAddr>,
+    pub share_link_address: Option<SocketAddr>,
+}
 
 pub async fn run_server(
-    store: Store<RandomHexIdGenerator>,
+    mut store: Store<RandomHexIdGenerator>,
+    config: Config,
     address: SocketAddr,
+    metrics_address: Option<SocketAddr>,
+    frontends: Frontends,
+    normalize_names: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Server listening on {}", address);
+    store.set_blob_hash(config.blob_hash());
+    info!("Hashing the new blobs with {}", config.blob_hash());
+    info!("Store durability: {}", store.durability());
+
+    let metrics = Arc::new(Metrics::new());
+    let blob_cache = Arc::new(BlobCache::new(config.blob_cache_size()));
+
+    if let Some(metrics_address) = metrics_address {
+        let metrics = metrics.clone();
+        let store = store.clone();
+        let blob_cache = blob_cache.clone();
+
+        tokio::spawn(async move {
+            run_metrics_server(metrics, store, blob_cache, metrics_address)
+                .await
+                .expect("Could not run metrics server");
+        });
+    }
+
+    if config.maintenance.incremental_vacuum {
+        info!("Enabling the incremental vacuum of the store");
+        store.enable_incremental_vacuum()?;
+    }
+    if config.maintenance.interval > 0 {
+        tokio::spawn(run_maintenance(store.clone(), config.maintenance.clone()));
+    }
+
+    let authenticator = match &config.tokens_file {
+        Some(path) => Authenticator::load(path)?,
+        None => Authenticator::default(),
+    };
+    let admin_authenticator = match &config.admin_token_file {
+        Some(path) => AdminAuthenticator::load(path)?,
+        None => AdminAuthenticator::default(),
+    };
+    for (name, path) in &config.shares {
+        info!("Exposing share {} at {}", name, path);
+    }
+    let share_links = load_share_link_signer(&config, frontends.share_link_address)?;
+    let limits = SharedLimits::new(config.limits);
+    if let Some(path) = config.path {
+        tokio::spawn(reload_on_sighup(
+            path,
+            authenticator.clone(),
+            admin_authenticator.clone(),
+            limits.clone(),
+            blob_cache.clone(),
+        ));
+    }
+
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(run_health_reporter(store.clone(), health_reporter));
+
+    let fs = RemoteFs::with_blob_cache(store, normalize_names, blob_cache)?;
+    // The frontends share the file locks with the gRPC service, so that the requests are only
+    // serialized when they modify the same files
+    let frontend_fs = Arc::new(fs.session());
+
+    if let Some(webdav_address) = frontends.webdav_address {
+        let fs = frontend_fs.clone();
+        let authenticator = authenticator.clone();
+        let limits = limits.clone();
 
-    Server::builder()
-        .add_service(RemoteFsServer::new(RemoteFsServerImpl::new(RemoteFs::new(
-            store,
-        )?)))
-        .serve(address)
-        .await?;
+        tokio::spawn(async move {
+            run_webdav_server(fs, authenticator, limits, webdav_address)
+                .await
+                .expect("Could not run WebDAV server");
+        });
+    }
+
+    if let Some(sftp_address) = frontends.sftp_address {
+        let host_key = config
+            .sftp_host_key
+            .as_deref()
+            .ok_or("sftp_host_key has to be configured to serve SFTP")?;
+        let host_key = load_host_key(host_key)?;
+        let fs = frontend_fs.clone();
+        let authenticator = authenticator.clone();
+
+        tokio::spawn(async move {
+            run_sftp_server(fs, authenticator, host_key, sftp_address)
+                .await
+                .expect("Could not run SFTP server");
+        });
+    }
+
+    if let Some(share_link_address) = frontends.share_link_address {
+        let fs = frontend_fs.clone();
+        let signer = share_links
+            .clone()
+            .ok_or("share_links.key_file has to be configured to serve the share links")?;
+
+        tokio::spawn(async move {
+            run_share_link_server(fs, signer, share_link_address)
+                .await
+                .expect("Could not run share link server");
+        });
+    }
+
+    let sessions = SessionRegistry::default();
+    let listener = bind_listener(address)?;
+    println!("Server listening on {}", listener.local_addr()?);
+
+    let server = Server::builder()
+        .layer(
+            ServiceBuilder::new()
+                .layer(limits.rate_limit_layer())
+                .layer(limits.message_size_limit_layer())
+                .into_inner(),
+        )
+        .trace_fn(|request| {
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER_KEY)
+                .and_then(|x| x.to_str().ok())
+                .unwrap_or_default();
+            let span = info_span!("grpc_request", path = request.uri().path(), request_id);
+            offs::telemetry::set_remote_parent(&span, request.headers());
+
+            span
+        })
+        .add_service(health_service)
+        .add_service(AdminServer::with_interceptor(
+            AdminServerImpl::new(fs.session(), metrics.clone(), sessions.clone()),
+            admin_authenticator,
+        ))
+        .add_service(RemoteFsServer::with_interceptor(
+            RemoteFsServerImpl::new(fs, metrics, config.shares, limits, share_links),
+            SessionTracker::new(authenticator, sessions),
+        ))
+        .serve_with_incoming(TcpListenerStream::new(listener));
+
+    sd_notify::notify(false, &[NotifyState::Ready])?;
+    server.await?;
 
     Ok(())
 }
+
+/// Checkpoints the WAL of the store periodically, so that it does not grow indefinitely on a
+/// busy server, freeing the unused pages of the database as well if configured to.
+async fn run_maintenance(store: Store<RandomHexIdGenerator>, maintenance: Maintenance) {
+    let mut interval = tokio::time::interval(Duration::from_secs(maintenance.interval));
+    // The first tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let store = store.clone();
+        let incremental_vacuum = maintenance.incremental_vacuum;
+        let result = tokio::task::spawn_blocking(move || store.run_maintenance(incremental_vacuum))
+            .await
+            .unwrap();
+        match result {
+            Ok(result) => debug!(
+                "Store maintenance: checkpointed: {}, freed pages: {}",
+                result.checkpointed, result.freed_pages
+            ),
+            Err(e) => error!("Store maintenance failed: {}", e),
+        }
+    }
+}
+
+/// Loads the key the share links are signed with, if configured. The links point to the share
+/// link server of this instance, unless another URL is configured.
+fn load_share_link_signer(
+    config: &Config,
+    share_link_address: Option<SocketAddr>,
+) -> Result<Option<ShareLinkSigner>, Box<dyn std::error::Error>> {
+    let key_file = match &config.share_links.key_file {
+        Some(key_file) => key_file,
+        None => return Ok(None),
+    };
+    let url = match (&config.share_links.url, share_link_address) {
+        (Some(url), _) => url.clone(),
+        (None, Some(address)) => format!("http://{}", address),
+        (None, None) => {
+            return Err(
+                "share_links.url or --share-link-addr has to be given to create the \
+                        share links"
+                    .into(),
+            )
+        }
+    };
+
+    Ok(Some(ShareLinkSigner::load(key_file, &url)?))
+}
+
+/// Re-reads the configuration file on SIGHUP, replacing the tokens, the limits and the log
+/// level without dropping the connections. The other settings only take effect on restart.
+async fn reload_on_sighup(
+    path: PathBuf,
+    authenticator: Authenticator,
+    admin_authenticator: AdminAuthenticator,
+    limits: SharedLimits,
+    blob_cache: Arc<BlobCache>,
+) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            error!("Could not handle SIGHUP: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("Reloading the configuration from {}", path.display());
+        match reload_config(
+            &path,
+            &authenticator,
+            &admin_authenticator,
+            &limits,
+            &blob_cache,
+        ) {
+            Ok(()) => info!("Configuration reloaded"),
+            Err(e) => error!("Could not reload the configuration: {}", e),
+        }
+    }
+}
+
+/// Reads the whole configuration before applying any of it, so that an invalid file leaves
+/// the current one in place.
+fn reload_config(
+    path: &Path,
+    authenticator: &Authenticator,
+    admin_authenticator: &AdminAuthenticator,
+    limits: &SharedLimits,
+    blob_cache: &BlobCache,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load(path)?;
+    let new_authenticator = match &config.tokens_file {
+        Some(path) => Authenticator::load(path)?,
+        None => Authenticator::default(),
+    };
+    let new_admin_authenticator = match &config.admin_token_file {
+        Some(path) => AdminAuthenticator::load(path)?,
+        None => AdminAuthenticator::default(),
+    };
+
+    authenticator.replace(new_authenticator);
+    admin_authenticator.replace(new_admin_authenticator);
+    if let Some(log_level) = config.log_level() {
+        offs::telemetry::set_log_level(log_level)?;
+    }
+    blob_cache.set_capacity(config.blob_cache_size());
+    limits.set(config.limits);
+
+    Ok(())
+}
+
+/// Uses the socket passed by systemd if the server was socket-activated, and binds to `address`
+/// otherwise.
+fn bind_listener(address: SocketAddr) -> io::Result<TcpListener> {
+    let listener = match sd_notify::listen_fds()?.next() {
+        Some(fd) => unsafe { std::net::TcpListener::from_raw_fd(fd) },
+        None => std::net::TcpListener::bind(address)?,
+    };
+    listener.set_nonblocking(true)?;
+
+    TcpListener::from_std(listener)
+}
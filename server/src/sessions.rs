@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use offs::errors::{OperationError, OperationResult};
+use offs::{now, CLIENT_ID_HEADER_KEY};
+
+use crate::auth::{Authenticator, Credential, Tenant};
+
+/// How long a client can stay idle before its session is forgotten. The revocations are kept
+/// regardless.
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// Length of the window the requests per minute are counted over, in seconds.
+const OPS_WINDOW: usize = 60;
+/// Maximum number of the sessions kept for a single tenant. The IDs are chosen by the clients,
+/// so the least recently active sessions are forgotten to make room for the new ones.
+const MAX_SESSIONS_PER_TENANT: usize = 1000;
+
+/// Session of a client, as reported to the admins.
+#[derive(Clone, Debug)]
+pub struct ClientSession {
+    pub tenant: String,
+    pub client_id: String,
+    pub address: Option<SocketAddr>,
+    /// Unix times of the first and the last request
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub ops_per_minute: u32,
+    pub revoked: bool,
+}
+
+/// Numbers of the requests made within each of the last seconds.
+struct OpsCounter {
+    counts: [u32; OPS_WINDOW],
+    second: u64,
+}
+
+impl OpsCounter {
+    fn new(second: u64) -> Self {
+        Self {
+            counts: [0; OPS_WINDOW],
+            second,
+        }
+    }
+
+    /// Clears the counts of the seconds that have passed since the last update.
+    fn advance(&mut self, second: u64) {
+        let elapsed = second.saturating_sub(self.second).min(OPS_WINDOW as u64);
+        for i in 1..=elapsed {
+            self.counts[((self.second + i) % OPS_WINDOW as u64) as usize] = 0;
+        }
+        self.second = self.second.max(second);
+    }
+
+    fn record(&mut self, second: u64) {
+        self.advance(second);
+        self.counts[(self.second % OPS_WINDOW as u64) as usize] += 1;
+    }
+
+    fn per_minute(&mut self, second: u64) -> u32 {
+        self.advance(second);
+        self.counts.iter().sum()
+    }
+}
+
+struct Session {
+    /// The credential of the last request, unless the authentication is disabled
+    credential: Option<Credential>,
+    address: Option<SocketAddr>,
+    first_seen: i64,
+    last_seen: i64,
+    last_active: Instant,
+    ops: OpsCounter,
+}
+
+struct Sessions {
+    started: Instant,
+    /// Keyed by the tenant and then by the client ID, as the IDs are chosen by the clients
+    clients: HashMap<String, HashMap<String, Session>>,
+    /// The credentials of the revoked clients, rejected whatever client ID they are sent with
+    revoked_credentials: HashSet<Credential>,
+    /// The revoked clients, only used when the authentication is disabled, as there are no
+    /// credentials then
+    revoked_clients: HashSet<(String, String)>,
+    last_pruned: Instant,
+}
+
+impl Sessions {
+    fn second(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    fn prune(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.last_pruned) < Duration::from_secs(OPS_WINDOW as u64) {
+            return;
+        }
+
+        for tenant_clients in self.clients.values_mut() {
+            tenant_clients.retain(|_, session| {
+                now.duration_since(session.last_active) < SESSION_IDLE_TIMEOUT
+            });
+        }
+        self.clients
+            .retain(|_, tenant_clients| !tenant_clients.is_empty());
+        self.last_pruned = now;
+    }
+
+    fn is_revoked(&self, tenant: &str, client_id: &str, credential: Option<&Credential>) -> bool {
+        match credential {
+            Some(credential) => self.revoked_credentials.contains(credential),
+            None => self
+                .revoked_clients
+                .contains(&(tenant.to_owned(), client_id.to_owned())),
+        }
+    }
+
+    fn describe(&mut self, tenant: &str, client_id: &str) -> ClientSession {
+        let second = self.second();
+        let session = &self.clients[tenant][client_id];
+        let revoked = self.is_revoked(tenant, client_id, session.credential.as_ref());
+        let session = self
+            .clients
+            .get_mut(tenant)
+            .and_then(|x| x.get_mut(client_id))
+            .unwrap();
+
+        ClientSession {
+            address: session.address,
+            first_seen: session.first_seen,
+            last_seen: session.last_seen,
+            ops_per_minute: session.ops.per_minute(second),
+            revoked,
+            tenant: tenant.to_owned(),
+            client_id: client_id.to_owned(),
+        }
+    }
+}
+
+/// Keeps track of the clients that have made requests recently: where they connect from, when
+/// they were last active and how many requests they make.
+#[derive(Clone)]
+pub struct SessionRegistry {
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+impl Default for SessionRegistry {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        Self {
+            sessions: Arc::new(Mutex::new(Sessions {
+                started: now,
+                clients: HashMap::new(),
+                revoked_credentials: HashSet::new(),
+                revoked_clients: HashSet::new(),
+                last_pruned: now,
+            })),
+        }
+    }
+}
+
+impl SessionRegistry {
+    /// Fails if the credential the request has been made with has been revoked.
+    pub fn check(&self, credential: Option<&Credential>) -> OperationResult<()> {
+        let sessions = self.sessions.lock().unwrap();
+        match credential {
+            Some(credential) if sessions.revoked_credentials.contains(credential) => {
+                Err(OperationError::permission_denied(
+                    "the token has been revoked by the administrator",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a request of the client, failing if the client has been revoked.
+    pub fn record(
+        &self,
+        tenant: &str,
+        client_id: &str,
+        credential: Option<&Credential>,
+        address: Option<SocketAddr>,
+    ) -> OperationResult<()> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.prune();
+        let second = sessions.second();
+        let timestamp = now().sec;
+
+        let tenant_clients = sessions.clients.entry(tenant.to_owned()).or_default();
+        if !tenant_clients.contains_key(client_id)
+            && tenant_clients.len() >= MAX_SESSIONS_PER_TENANT
+        {
+            let least_recent = tenant_clients
+                .iter()
+                .min_by_key(|(_, session)| session.last_active)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = least_recent {
+                tenant_clients.remove(&id);
+            }
+        }
+        let session = tenant_clients
+            .entry(client_id.to_owned())
+            .or_insert_with(|| Session {
+                credential: None,
+                address,
+                first_seen: timestamp,
+                last_seen: timestamp,
+                last_active: Instant::now(),
+                ops: OpsCounter::new(second),
+            });
+        session.credential = credential.cloned();
+        session.address = address.or(session.address);
+        session.last_seen = timestamp;
+        session.last_active = Instant::now();
+        session.ops.record(second);
+
+        if sessions.is_revoked(tenant, client_id, credential) {
+            return Err(OperationError::permission_denied(
+                "the client has been revoked by the administrator",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the sessions of the clients, sorted by the tenant and the client ID.
+    pub fn list(&self) -> Vec<ClientSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.prune();
+
+        let keys: Vec<_> = sessions
+            .clients
+            .iter()
+            .flat_map(|(tenant, clients)| {
+                clients.keys().map(move |id| (tenant.clone(), id.clone()))
+            })
+            .collect();
+        let mut result: Vec<_> = keys
+            .into_iter()
+            .map(|(tenant, client_id)| sessions.describe(&tenant, &client_id))
+            .collect();
+        result.sort_by(|a, b| (&a.tenant, &a.client_id).cmp(&(&b.tenant, &b.client_id)));
+
+        result
+    }
+
+    /// Rejects all the further requests made with the auth token of the client, whatever client
+    /// ID they are sent with, until the server is restarted; when the authentication is
+    /// disabled, only the requests of the client ID are rejected. Only the clients with a
+    /// session can be revoked, so that mistyped IDs are reported.
+    pub fn revoke(&self, tenant: &str, client_id: &str) -> OperationResult<ClientSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let credential = match sessions.clients.get(tenant).and_then(|x| x.get(client_id)) {
+            Some(session) => session.credential.clone(),
+            None => {
+                return Err(OperationError::invalid_argument(&format!(
+                    "client {} of tenant \"{}\" has not made any requests recently",
+                    client_id, tenant
+                )))
+            }
+        };
+
+        match credential {
+            Some(credential) => sessions.revoked_credentials.insert(credential),
+            None => sessions
+                .revoked_clients
+                .insert((tenant.to_owned(), client_id.to_owned())),
+        };
+        Ok(sessions.describe(tenant, client_id))
+    }
+}
+
+/// Authenticates the requests of the clients and records them in their sessions. The requests
+/// of the clients that do not identify themselves are only checked for having been made with a
+/// revoked token.
+#[derive(Clone)]
+pub struct SessionTracker {
+    authenticator: Authenticator,
+    sessions: SessionRegistry,
+}
+
+impl SessionTracker {
+    pub fn new(authenticator: Authenticator, sessions: SessionRegistry) -> Self {
+        Self {
+            authenticator,
+            sessions,
+        }
+    }
+}
+
+impl Interceptor for SessionTracker {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let request = self.authenticator.call(request)?;
+        let credential = request.extensions().get::<Credential>();
+
+        let client_id = request
+            .metadata()
+            .get(CLIENT_ID_HEADER_KEY)
+            .and_then(|x| x.to_str().ok());
+        match client_id {
+            Some(client_id) => {
+                let tenant = request.extensions().get::<Tenant>().cloned();
+                let tenant = tenant.unwrap_or_default().0;
+                self.sessions
+                    .record(&tenant, client_id, credential, request.remote_addr())?;
+            }
+            None => self.sessions.check(credential)?,
+        }
+
+        Ok(request)
+    }
+}